@@ -15,7 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use ccore::AccountProvider;
-use ckeys::KeyPair;
+use ckeys::{DerivationPath, ExtendedPrivate, KeyPair, Mnemonic};
 use ckeystore::accounts_dir::RootDiskDirectory;
 use ckeystore::KeyStore;
 use clap::ArgMatches;
@@ -49,6 +49,25 @@ pub fn run_account_command(matches: ArgMatches) -> Result<(), String> {
             }
             Ok(())
         }
+        "mnemonic" => {
+            let words = subcommand.matches.value_of("words").unwrap();
+            let word_count = words.parse().map_err(|_| format!("Invalid number of words: {}", words))?;
+            let mnemonic = Mnemonic::generate(word_count).map_err(|e| e.to_string())?;
+            println!("{}", mnemonic);
+            Ok(())
+        }
+        "import-mnemonic" => {
+            let mnemonic =
+                subcommand.matches.value_of("mnemonic").unwrap().parse::<Mnemonic>().map_err(|e| e.to_string())?;
+            let path =
+                subcommand.matches.value_of("path").unwrap().parse::<DerivationPath>().map_err(|e| e.to_string())?;
+            let seed = mnemonic.seed(subcommand.matches.value_of("mnemonic-passphrase").unwrap_or(""));
+            let master = ExtendedPrivate::from_seed(&seed).map_err(|e| e.to_string())?;
+            let password = subcommand.matches.value_of("passphrase").unwrap();
+            let address = ap.insert_derived_account(&master, &path, password).map_err(|e| e.to_string())?;
+            info!("Address {} is derived along {}", address, path);
+            Ok(())
+        }
         _ => Err("Invalid subcommand".to_string()),
     }
 }
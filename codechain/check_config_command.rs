@@ -0,0 +1,166 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::net::{TcpListener, UdpSocket};
+use std::path::Path;
+
+use ccore::check_database;
+use ckeys::KeyPair;
+use ckeystore::accounts_dir::RootDiskDirectory;
+use ckeystore::KeyStore;
+use clap::ArgMatches;
+use crpc::TlsCertificates;
+
+use super::{client_config, config};
+
+// FIXME : Share the keystore path with the node when it becomes an option.
+const KEYSTORE_PATH: &'static str = "keystoreData";
+
+/// Checks everything the node checks while starting, but doesn't start it.
+/// All the problems are reported at once, and the command fails if there is any.
+pub fn run_check_config_command(matches: &ArgMatches, subcommand: &ArgMatches) -> Result<(), String> {
+    let config_path = subcommand.value_of("config-path").expect("config-path is required");
+    let problems = check(config_path, matches);
+    if problems.is_empty() {
+        println!("{} is valid", config_path);
+        return Ok(())
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    Err(format!("{} problem(s) in {}", problems.len(), config_path))
+}
+
+fn check(config_path: &str, matches: &ArgMatches) -> Vec<String> {
+    let mut problems = Vec::new();
+    {
+        let mut report = |result: Result<(), String>| {
+            if let Err(problem) = result {
+                problems.push(problem);
+            }
+        };
+
+        report(check_node(config_path, matches));
+        report(check_keystore());
+        report(config::parse_discovery_config(matches).map(|_| ()));
+        report(config::parse_history_policy(matches).map(|_| ()));
+        report(config::parse_warp_signers(matches).map(|_| ()));
+
+        let network_port = match config::parse_network_config(matches) {
+            Ok(Some(network)) => {
+                report(check_node_key(&network.node_key_path));
+                report(check_tcp_port("127.0.0.1", network.port));
+                report(check_udp_port("127.0.0.1", network.port));
+                if let Some(port) = network.websocket_port {
+                    report(check_tcp_port("127.0.0.1", port));
+                }
+                Some(network.port)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                report(Err(err));
+                None
+            }
+        };
+        match config::parse_rpc_config(matches) {
+            Ok(Some(rpc)) => {
+                if Some(rpc.port) == network_port {
+                    report(Err(format!("The JSON RPC and the network use the same port {}", rpc.port)));
+                } else {
+                    report(check_tcp_port(&rpc.interface, rpc.port));
+                }
+            }
+            Ok(None) => {}
+            Err(err) => report(Err(err)),
+        }
+        match config::parse_tls_config(matches) {
+            Ok(Some(tls)) => report(TlsCertificates::load(&tls.certificate_path, &tls.key_path).map(|_| ())),
+            Ok(None) => {}
+            Err(err) => report(Err(err)),
+        }
+    }
+    problems
+}
+
+// Checks the config file, the chain spec and the database, which depend on each other.
+fn check_node(config_path: &str, matches: &ArgMatches) -> Result<(), String> {
+    let mut config = config::load(config_path)?;
+    config.overwrite_with(matches)?;
+    KeyPair::from_private(config.secret_key.into()).map_err(|e| format!("Invalid secret key: {}", e))?;
+    let client_config = client_config(&config)?;
+    let spec = config.chain_type.spec()?;
+    check_database(&client_config, &spec, Path::new(&config.db_path))
+        .map_err(|e| format!("Cannot use the database at {}: {}", config.db_path, e))
+}
+
+fn check_keystore() -> Result<(), String> {
+    // The node creates the directory if it doesn't exist.
+    if !Path::new(KEYSTORE_PATH).exists() {
+        return Ok(())
+    }
+    let dir = RootDiskDirectory::create(KEYSTORE_PATH).map_err(|e| format!("Cannot read {}: {}", KEYSTORE_PATH, e))?;
+    KeyStore::open(Box::new(dir)).map_err(|e| format!("Cannot open the keystore at {}: {}", KEYSTORE_PATH, e))?;
+    Ok(())
+}
+
+fn check_node_key(node_key_path: &str) -> Result<(), String> {
+    // The node generates a new key if it doesn't exist.
+    if !Path::new(node_key_path).exists() {
+        return Ok(())
+    }
+    ::cnetwork::load_or_generate_node_key(Path::new(node_key_path)).map(|_| ())
+}
+
+fn check_tcp_port(interface: &str, port: u16) -> Result<(), String> {
+    TcpListener::bind((interface, port))
+        .map(|_| ())
+        .map_err(|e| format!("Cannot listen on {}:{}: {}", interface, port, e))
+}
+
+fn check_udp_port(interface: &str, port: u16) -> Result<(), String> {
+    UdpSocket::bind((interface, port))
+        .map(|_| ())
+        .map_err(|e| format!("Cannot bind UDP {}:{}: {}", interface, port, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_in_use_is_a_problem() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(check_tcp_port("127.0.0.1", port).is_err());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        assert!(check_udp_port("127.0.0.1", port).is_err());
+    }
+
+    #[test]
+    fn free_port_is_not_a_problem() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert!(check_tcp_port("127.0.0.1", port).is_ok());
+    }
+
+    #[test]
+    fn missing_node_key_is_generated_by_the_node() {
+        let path = "this node key does not exist";
+        assert!(check_node_key(path).is_ok());
+        assert!(!Path::new(path).exists());
+    }
+}
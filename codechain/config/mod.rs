@@ -14,15 +14,26 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fmt, fs};
+use std::time::Duration;
+use std::{env, fmt, fs};
 
-use ccore::Spec;
+use ccore::{Spec, DEFAULT_SNAPSHOT_PERIOD};
 use cdiscovery::{KademliaConfig, UnstructuredConfig};
+pub use cnode::Discovery;
+use ckeys::Private;
 use clap;
-use cnetwork::{NetworkConfig, SocketAddr};
-use ctypes::{Address, Secret};
-use rpc::HttpConfiguration as RpcHttpConfig;
+use cnode::SnapshotConfig;
+use cnetwork::{DnsSeed, NetworkConfig, RateLimit, SocketAddr, SocketOptions};
+use crpc::{RateLimit as RpcRateLimit, RateLimits as RpcRateLimits};
+use csync::HistoryPolicy;
+use ctypes::{Address, Public, Secret};
+use rpc::{
+    HttpConfiguration as RpcHttpConfig, IpcConfiguration as RpcIpcConfig, TlsConfiguration as RpcTlsConfig,
+    WsConfiguration as RpcWsConfig,
+};
+use rpc_apis::ApiSet;
 use toml;
 
 #[derive(Debug, PartialEq, Deserialize)]
@@ -86,14 +97,27 @@ pub struct Config {
     pub quiet: bool,
     pub instance_id: Option<usize>,
     pub db_path: String,
+    pub db_backend: Option<String>,
+    pub invoice_retention: Option<String>,
+    pub pruning: Option<String>,
+    pub pruning_history: Option<u64>,
     pub chain_type: ChainType,
     pub enable_block_sync: bool,
+    #[serde(default)]
+    pub light: bool,
     pub enable_parcel_relay: bool,
+    #[serde(default = "enabled")]
+    pub enable_parcel_diffusion: bool,
     pub secret_key: Secret,
     pub author: Option<Address>,
     pub engine_signer: Option<Address>,
 }
 
+// The options added later are enabled when an older config file doesn't have them.
+fn enabled() -> bool {
+    true
+}
+
 pub fn load(config_path: &str) -> Result<Config, String> {
     let toml_string = fs::read_to_string(config_path).map_err(|e| format!("Fail to read file: {:?}", e))?;
     toml::from_str(toml_string.as_ref()).map_err(|e| format!("Error while parse TOML: {:?}", e))
@@ -110,15 +134,33 @@ impl Config {
         if let Some(db_path) = matches.value_of("db-path") {
             self.db_path = db_path.to_string();
         }
+        if let Some(db_backend) = matches.value_of("db-backend") {
+            self.db_backend = Some(db_backend.to_string());
+        }
+        if let Some(invoice_retention) = matches.value_of("invoice-retention") {
+            self.invoice_retention = Some(invoice_retention.to_string());
+        }
+        if let Some(pruning) = matches.value_of("pruning") {
+            self.pruning = Some(pruning.to_string());
+        }
+        if let Some(pruning_history) = matches.value_of("pruning-history") {
+            self.pruning_history = Some(pruning_history.parse().map_err(|_| "Invalid pruning-history")?);
+        }
         if let Some(chain) = matches.value_of("chain") {
             self.chain_type = chain.parse()?;
         }
         if matches.is_present("no-sync") {
             self.enable_block_sync = false;
         }
+        if matches.is_present("light") {
+            self.light = true;
+        }
         if matches.is_present("no-parcel-relay") {
             self.enable_parcel_relay = false;
         }
+        if matches.is_present("no-parcel-diffusion") {
+            self.enable_parcel_diffusion = false;
+        }
         if let Some(secret) = matches.value_of("secret-key") {
             self.secret_key = Secret::from_str(secret).map_err(|_| "Invalid secret key")?;
         }
@@ -137,15 +179,41 @@ pub fn parse_network_config(matches: &clap::ArgMatches) -> Result<Option<Network
         return Ok(None)
     }
 
-    let bootstrap_addresses = {
-        if let Some(addresses) = matches.values_of("bootstrap-addresses") {
-            addresses.map(|s| SocketAddr::from_str(s).unwrap()).collect::<Vec<_>>()
+    let bootnodes = {
+        if let Some(addresses) = matches.values_of("bootnodes") {
+            addresses
+                .map(|s| SocketAddr::from_str(s).map_err(|_| format!("Invalid bootnode: {}", s)))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        }
+    };
+
+    let dns_seeds = {
+        if let Some(seeds) = matches.values_of("dns-seeds") {
+            seeds
+                .map(|s| DnsSeed::from_str(s).map_err(|err| format!("Invalid DNS seed {}: {}", s, err)))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        }
+    };
+
+    let static_peers = {
+        if let Some(addresses) = matches.values_of("static-peers") {
+            addresses
+                .map(|s| SocketAddr::from_str(s).map_err(|_| format!("Invalid static peer: {}", s)))
+                .collect::<Result<Vec<_>, _>>()?
         } else {
             vec![]
         }
     };
 
     let port = value_t_or_exit!(matches, "port", u16);
+    let websocket_port = parse_optional(matches, "websocket-port")?;
+    if websocket_port == Some(port) {
+        return Err("The WebSocket and the network use the same port".to_owned())
+    }
 
 
     let min_peers = value_t_or_exit!(matches, "min-peers", usize);
@@ -155,17 +223,76 @@ pub fn parse_network_config(matches: &clap::ArgMatches) -> Result<Option<Network
         return Err("Invalid min/max peers".to_owned())
     }
 
+    let node_key_path = value_t_or_exit!(matches, "node-key-path", String);
+    let relay = matches.is_present("relay");
+    let peer_store_path = matches.value_of("peer-store-path").map(|path| path.to_string());
+    let otlp_endpoint = matches.value_of("otlp-endpoint").map(|endpoint| endpoint.to_string());
+    let otlp_sample_rate = value_t_or_exit!(matches, "otlp-sample-rate", f64);
+    let capture_path = matches.value_of("capture-path").map(|path| path.to_string());
+    let idle_timeout = match matches.value_of("idle-timeout") {
+        Some(timeout) => Some(timeout.parse().map_err(|_| "Invalid idle-timeout")?),
+        None => None,
+    };
+    let static_peer_idle_timeout = parse_optional(matches, "static-peer-idle-timeout")?;
+    let evictable_idle_timeout = value_t_or_exit!(matches, "evictable-idle-timeout", u64);
+    let socket_options = SocketOptions {
+        nodelay: !matches.is_present("no-tcp-nodelay"),
+        recv_buffer_size: parse_optional(matches, "socket-recv-buffer")?,
+        send_buffer_size: parse_optional(matches, "socket-send-buffer")?,
+        linger: parse_optional(matches, "socket-linger")?.map(Duration::from_secs),
+    };
+    // The static peers are always sent the votes at once.
+    let static_peer_socket_options = SocketOptions {
+        nodelay: true,
+        recv_buffer_size: parse_optional(matches, "static-peer-recv-buffer")?.or(socket_options.recv_buffer_size),
+        send_buffer_size: parse_optional(matches, "static-peer-send-buffer")?.or(socket_options.send_buffer_size),
+        ..socket_options
+    };
+
+    let default_rate_limit = RateLimit::default();
+    let rate_limit = RateLimit {
+        messages_per_second: parse_optional(matches, "max-messages-per-second")?
+            .unwrap_or(default_rate_limit.messages_per_second),
+        bytes_per_second: parse_optional(matches, "max-bytes-per-second")?
+            .unwrap_or(default_rate_limit.bytes_per_second),
+    };
+    let extension_workers = value_t_or_exit!(matches, "extension-workers", usize);
+    let mdns = matches.is_present("mdns");
+    let allow_legacy_cipher = matches.is_present("allow-legacy-cipher");
+    let allowlist_path = matches.value_of("allowlist").map(|path| path.to_string());
+
     Ok(Some(NetworkConfig {
         port,
-        bootstrap_addresses,
+        websocket_port,
+        bootnodes,
         min_peers,
         max_peers,
+        node_key_path,
+        relay,
+        static_peers,
+        peer_store_path,
+        dns_seeds,
+        otlp_endpoint,
+        otlp_sample_rate,
+        capture_path,
+        idle_timeout,
+        static_peer_idle_timeout,
+        evictable_idle_timeout,
+        socket_options,
+        static_peer_socket_options,
+        rate_limit,
+        extension_workers,
+        mdns,
+        allow_legacy_cipher,
+        allowlist_path,
     }))
 }
 
-pub enum Discovery {
-    Kademlia(KademliaConfig),
-    Unstructured(UnstructuredConfig),
+fn parse_optional<T: FromStr>(matches: &clap::ArgMatches, name: &str) -> Result<Option<T>, String> {
+    match matches.value_of(name) {
+        Some(value) => Ok(Some(value.parse().map_err(|_| format!("Invalid {}", name))?)),
+        None => Ok(None),
+    }
 }
 
 pub fn parse_discovery_config(matches: &clap::ArgMatches) -> Result<Option<Discovery>, String> {
@@ -189,6 +316,51 @@ pub fn parse_discovery_config(matches: &clap::ArgMatches) -> Result<Option<Disco
     }
 }
 
+pub fn parse_history_policy(matches: &clap::ArgMatches) -> Result<HistoryPolicy, String> {
+    let max_depth = match matches.value_of("history-depth") {
+        Some(depth) => Some(depth.parse().map_err(|_| "Invalid history-depth")?),
+        None => None,
+    };
+    let allowed_peers = {
+        if let Some(peers) = matches.values_of("history-peers") {
+            peers
+                .map(|s| Public::from_str(s).map_err(|_| format!("Invalid history peer: {}", s)))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        }
+    };
+    Ok(HistoryPolicy::new(max_depth, allowed_peers))
+}
+
+pub fn parse_snapshot_config(matches: &clap::ArgMatches, secret: Secret) -> Result<Option<SnapshotConfig>, String> {
+    let path = match matches.value_of("snapshot-path") {
+        Some(path) => path.into(),
+        None => return Ok(None),
+    };
+    let period = match matches.value_of("snapshot-period") {
+        Some(period) => period.parse().map_err(|_| "Invalid snapshot-period")?,
+        None => DEFAULT_SNAPSHOT_PERIOD,
+    };
+    if period == 0 {
+        return Err("snapshot-period must be greater than 0".to_string())
+    }
+    Ok(Some(SnapshotConfig {
+        path,
+        period,
+        private: Private::from(secret),
+    }))
+}
+
+pub fn parse_warp_signers(matches: &clap::ArgMatches) -> Result<Vec<Address>, String> {
+    match matches.values_of("warp-signers") {
+        Some(signers) => signers
+            .map(|s| Address::from_str(s).map_err(|_| format!("Invalid warp signer: {}", s)))
+            .collect::<Result<Vec<_>, _>>(),
+        None => Ok(vec![]),
+    }
+}
+
 pub fn parse_rpc_config(matches: &clap::ArgMatches) -> Result<Option<RpcHttpConfig>, String> {
     if matches.is_present("no-jsonrpc") {
         return Ok(None)
@@ -201,11 +373,160 @@ pub fn parse_rpc_config(matches: &clap::ArgMatches) -> Result<Option<RpcHttpConf
     if let Some(interface) = matches.value_of("jsonrpc-interface") {
         config.interface = interface.to_owned();
     }
-    if let Some(cors) = matches.value_of("jsonrpc-cors") {
-        config.cors = Some(vec![cors.parse().map_err(|_| "Invalid JSON RPC CORS".to_owned())?]);
+    if let Some(origins) = matches.values_of("jsonrpc-cors") {
+        let origins: Vec<String> = origins.map(|origin| origin.to_owned()).collect();
+        config.cors = if origins == ["none"] {
+            None
+        } else {
+            Some(origins)
+        };
+    }
+    if let Some(hosts) = matches.values_of("jsonrpc-hosts") {
+        let hosts: Vec<String> = hosts.map(|host| host.to_owned()).collect();
+        config.hosts = if hosts == ["all"] {
+            None
+        } else {
+            Some(hosts)
+        };
+    }
+    config.secrets_path = parse_secrets_path(matches);
+    config.rate_limits = parse_rate_limits(matches)?;
+    if let Some(scopes) = matches.values_of("jsonrpc-apis") {
+        config.allowed_methods = Some(scopes.map(|scope| scope.to_owned()).collect());
+    }
+    if matches.is_present("jsonrpc-max-batch-size") {
+        config.max_batch_size = value_t_or_exit!(matches, "jsonrpc-max-batch-size", usize);
+    }
+    if matches.is_present("jsonrpc-all-apis") {
+        if config.secrets_path.is_none() {
+            return Err("--jsonrpc-all-apis cannot be used with --jsonrpc-no-auth".to_string())
+        }
+        config.apis = ApiSet::All;
+    }
+
+    Ok(Some(config))
+}
+
+// The calls need the API tokens unless the authorization is turned off explicitly
+fn parse_secrets_path(matches: &clap::ArgMatches) -> Option<String> {
+    if matches.is_present("jsonrpc-no-auth") {
+        return None
+    }
+    Some(value_t_or_exit!(matches, "jsonrpc-secrets", String))
+}
+
+// The same limits apply to HTTP and WebSocket
+fn parse_rate_limits(matches: &clap::ArgMatches) -> Result<RpcRateLimits, String> {
+    let mut rate_limits = RpcRateLimits::default();
+    if matches.is_present("jsonrpc-rate-limit") {
+        rate_limits.per_client = Some(RpcRateLimit {
+            calls_per_second: value_t_or_exit!(matches, "jsonrpc-rate-limit", u32),
+        });
+    }
+    if let Some(limits) = matches.values_of("jsonrpc-method-rate-limit") {
+        for limit in limits {
+            let (method, calls_per_second) = parse_method_rate_limit(limit)?;
+            rate_limits.per_method.insert(
+                method,
+                RpcRateLimit {
+                    calls_per_second,
+                },
+            );
+        }
+    }
+    Ok(rate_limits)
+}
+
+fn parse_method_rate_limit(limit: &str) -> Result<(String, u32), String> {
+    let mut parts = limit.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(method), Some(calls_per_second)) if !method.is_empty() => {
+            let calls_per_second =
+                calls_per_second.parse().map_err(|_| format!("Invalid rate limit of {}: {}", method, limit))?;
+            Ok((method.to_string(), calls_per_second))
+        }
+        _ => Err(format!("The rate limit must be METHOD=CALLS: {}", limit)),
+    }
+}
+
+pub fn parse_ipc_config(matches: &clap::ArgMatches, data_dir: &str) -> Result<Option<RpcIpcConfig>, String> {
+    if matches.is_present("no-ipc") {
+        return Ok(None)
+    }
+
+    let mut config = RpcIpcConfig::in_dir(&absolute_path(data_dir)?);
+
+    if let Some(path) = matches.value_of("ipc-path") {
+        config.socket_addr = absolute_path(path)?.to_string_lossy().into_owned();
+    }
+    if let Some(scopes) = matches.values_of("ipc-apis") {
+        config.allowed_methods = Some(scopes.map(|scope| scope.to_owned()).collect());
+    }
+    if matches.is_present("jsonrpc-max-batch-size") {
+        config.max_batch_size = value_t_or_exit!(matches, "jsonrpc-max-batch-size", usize);
+    }
+
+    Ok(Some(config))
+}
+
+fn absolute_path(path: &str) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Ok(path.to_path_buf())
+    }
+    let current_dir = env::current_dir().map_err(|e| format!("Cannot read the current directory: {}", e))?;
+    Ok(current_dir.join(path))
+}
+
+pub fn parse_tls_config(matches: &clap::ArgMatches) -> Result<Option<RpcTlsConfig>, String> {
+    match (matches.value_of("rpc-tls-certificate"), matches.value_of("rpc-tls-key")) {
+        (Some(certificate_path), Some(key_path)) => Ok(Some(RpcTlsConfig {
+            certificate_path: certificate_path.to_owned(),
+            key_path: key_path.to_owned(),
+        })),
+        (None, None) => Ok(None),
+        _ => Err("Both --rpc-tls-certificate and --rpc-tls-key are needed to serve TLS".to_string()),
+    }
+}
+
+pub fn parse_ws_config(matches: &clap::ArgMatches) -> Result<Option<RpcWsConfig>, String> {
+    if matches.is_present("no-ws") {
+        return Ok(None)
+    }
+
+    let port = value_t_or_exit!(matches, "ws-port", u16);
+
+    let mut config = RpcWsConfig::with_port(port);
+
+    if let Some(interface) = matches.value_of("ws-interface") {
+        config.interface = interface.to_owned();
+    }
+    if let Some(origins) = matches.values_of("ws-origins") {
+        let origins: Vec<String> = origins.map(|origin| origin.to_owned()).collect();
+        config.origins = if origins == ["all"] {
+            None
+        } else {
+            Some(origins)
+        };
+    }
+    if let Some(hosts) = matches.values_of("ws-hosts") {
+        let hosts: Vec<String> = hosts.map(|host| host.to_owned()).collect();
+        config.hosts = if hosts == ["all"] {
+            None
+        } else {
+            Some(hosts)
+        };
+    }
+    config.secrets_path = parse_secrets_path(matches);
+    config.rate_limits = parse_rate_limits(matches)?;
+    if matches.is_present("ws-max-connections") {
+        config.max_connections = value_t_or_exit!(matches, "ws-max-connections", usize);
+    }
+    if let Some(scopes) = matches.values_of("ws-apis") {
+        config.allowed_methods = Some(scopes.map(|scope| scope.to_owned()).collect());
     }
-    if let Some(hosts) = matches.value_of("jsonrpc-hosts") {
-        config.hosts = Some(vec![hosts.parse().map_err(|_| "Invalid JSON RPC hosts".to_owned())?]);
+    if matches.is_present("jsonrpc-max-batch-size") {
+        config.max_batch_size = value_t_or_exit!(matches, "jsonrpc-max-batch-size", usize);
     }
 
     Ok(Some(config))
@@ -32,6 +32,7 @@ extern crate codechain_keys as ckeys;
 extern crate codechain_keystore as ckeystore;
 extern crate codechain_logger as clogger;
 extern crate codechain_network as cnetwork;
+extern crate codechain_node as cnode;
 extern crate codechain_reactor as creactor;
 extern crate codechain_rpc as crpc;
 extern crate codechain_sync as csync;
@@ -44,30 +45,34 @@ extern crate parking_lot;
 extern crate toml;
 
 mod account_command;
+mod check_config_command;
 mod config;
 mod rpc;
 mod rpc_apis;
+mod service_command;
 
-use std::path::Path;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use account_command::run_account_command;
 use app_dirs::AppInfo;
-use ccore::{AccountProvider, ClientService, Miner, MinerOptions, MinerService, Spec};
-use cdiscovery::{KademliaExtension, UnstructuredExtension};
+use check_config_command::run_check_config_command;
+use ccore::{AccountProvider, ClientConfig};
 use ckeystore::accounts_dir::RootDiskDirectory;
 use ckeystore::KeyStore;
 use clap::ArgMatches;
 use clogger::LoggerConfig;
-use cnetwork::{NetworkConfig, NetworkService, SocketAddr};
+use cnode::NodeBuilder;
 use creactor::EventLoop;
-use crpc::Server as RpcServer;
-use csync::{BlockSyncExtension, ParcelSyncExtension};
+use crpc::{IpcServer as RpcIpcServer, Server as RpcServer, TlsCertificates, TlsServer, WsServer as RpcWsServer};
 use ctrlc::CtrlC;
 use fdlimit::raise_fd_limit;
 use parking_lot::{Condvar, Mutex};
-use rpc::HttpConfiguration as RpcHttpConfig;
+use rpc::{
+    HttpConfiguration as RpcHttpConfig, IpcConfiguration as RpcIpcConfig, TlsConfiguration as RpcTlsConfig,
+    WsConfiguration as RpcWsConfig,
+};
+use service_command::run_service_command;
 
 #[cfg(feature = "stratum")]
 extern crate stratum;
@@ -79,28 +84,56 @@ pub const APP_INFO: AppInfo = AppInfo {
     author: "Kodebox",
 };
 
-pub fn rpc_start(cfg: RpcHttpConfig, deps: Arc<rpc_apis::ApiDependencies>) -> Result<RpcServer, String> {
+pub fn rpc_start(
+    cfg: RpcHttpConfig,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    tls: Option<Arc<TlsCertificates>>,
+) -> Result<(RpcServer, Option<TlsServer>), String> {
     info!("RPC Listening on {}", cfg.port);
-    rpc::new_http(cfg, deps)
+    rpc::new_http(cfg, deps, tls)
 }
 
-pub fn network_start(cfg: &NetworkConfig) -> Result<NetworkService, String> {
-    info!("Handshake Listening on {}", cfg.port);
-    let address = SocketAddr::v4(127, 0, 0, 1, cfg.port);
-    let service = NetworkService::start(address, cfg.min_peers, cfg.max_peers)
-        .map_err(|e| format!("Network service error: {:?}", e))?;
+pub fn ipc_start(cfg: RpcIpcConfig, deps: Arc<rpc_apis::ApiDependencies>) -> Result<RpcIpcServer, String> {
+    info!("IPC Listening on {}", cfg.socket_addr);
+    rpc::new_ipc(cfg, deps)
+}
 
-    Ok(service)
+pub fn ws_start(
+    cfg: RpcWsConfig,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    tls: Option<Arc<TlsCertificates>>,
+) -> Result<(RpcWsServer, Option<TlsServer>), String> {
+    info!("WebSocket Listening on {}", cfg.port);
+    rpc::new_ws(cfg, deps, tls)
 }
 
-pub fn client_start(cfg: &config::Config, spec: &Spec, miner: Arc<Miner>) -> Result<ClientService, String> {
-    info!("Starting client");
-    let client_path = Path::new(&cfg.db_path);
-    let client_config = Default::default();
-    let service = ClientService::start(client_config, &spec, &client_path, miner)
-        .map_err(|e| format!("Client service error: {:?}", e))?;
+pub fn tls_start(cfg: RpcTlsConfig) -> Result<Arc<TlsCertificates>, String> {
+    info!("RPC over TLS with the certificate {}", cfg.certificate_path);
+    let certificates = Arc::new(TlsCertificates::load(&cfg.certificate_path, &cfg.key_path)?);
+    TlsCertificates::reload_on_sighup(Arc::clone(&certificates))?;
+    Ok(certificates)
+}
 
-    Ok(service)
+pub fn client_config(cfg: &config::Config) -> Result<ClientConfig, String> {
+    let invoice_retention = match cfg.invoice_retention {
+        Some(ref invoice_retention) => invoice_retention.parse()?,
+        None => Default::default(),
+    };
+    let pruning = match cfg.pruning {
+        Some(ref pruning) => pruning.parse()?,
+        None => Default::default(),
+    };
+    let db_backend = match cfg.db_backend {
+        Some(ref db_backend) => db_backend.parse()?,
+        None => Default::default(),
+    };
+    Ok(ClientConfig {
+        db_backend,
+        invoice_retention,
+        pruning,
+        history: cfg.pruning_history.unwrap_or(ClientConfig::default().history),
+        ..Default::default()
+    })
 }
 
 #[cfg(all(unix, target_arch = "x86_64"))]
@@ -124,9 +157,15 @@ fn run() -> Result<(), String> {
 }
 
 fn run_subcommand(matches: ArgMatches) -> Result<(), String> {
+    if let Some(check_config) = matches.subcommand_matches("check-config") {
+        // The node options are given before the subcommand, so both are needed.
+        return run_check_config_command(&matches, check_config)
+    }
     let subcommand = matches.subcommand.unwrap();
     if subcommand.name == "account" {
         run_account_command(subcommand.matches)
+    } else if subcommand.name == "service" {
+        run_service_command(subcommand.matches)
     } else {
         Err("Invalid subcommand".to_string())
     }
@@ -160,72 +199,76 @@ fn run_node(matches: ArgMatches) -> Result<(), String> {
         ap.insert_account(config.secret_key.into()).map_err(|e| format!("Invalid secret key: {:?}", e))?
     };
 
-    let miner = Miner::new(MinerOptions::default(), &spec, Some(ap.clone()));
     let author = config.author.unwrap_or(address);
-    miner.set_author(author);
-    let enginer_signer = config.engine_signer.unwrap_or(address);
-    miner.set_engine_signer(enginer_signer).map_err(|err| format!("{:?}", err))?;
-
-    let client = client_start(&config, &spec, miner.clone())?;
+    let engine_signer = config.engine_signer.unwrap_or(address);
+    let mut builder = NodeBuilder::new(spec, &config.db_path)
+        .client_config(client_config(&config)?)
+        .account_provider(ap.clone())
+        .author(author)
+        .engine_signer(engine_signer)
+        .light(config.light)
+        .parcel_relay(config.enable_parcel_relay)
+        .parcel_diffusion(config.enable_parcel_diffusion);
+    if config.enable_block_sync {
+        builder = builder.block_sync(Some(config::parse_history_policy(&matches)?));
+    } else {
+        builder = builder.block_sync(None);
+    }
+    if let Some(snapshot_config) = config::parse_snapshot_config(&matches, config.secret_key)? {
+        builder = builder.snapshot(snapshot_config);
+    }
+    builder = builder.warp(config::parse_warp_signers(&matches)?);
+    if let Some(network_config) = config::parse_network_config(&matches)? {
+        builder = builder.network(network_config);
+    }
+    if let Some(discovery) = config::parse_discovery_config(&matches)? {
+        builder = builder.discovery(discovery);
+    }
+    // The spec is dropped in the builder to free up genesis state.
+    let node = builder.start()?;
 
     let rpc_apis_deps = Arc::new(rpc_apis::ApiDependencies {
-        client: client.client(),
-        miner: miner.clone(),
+        client: node.client(),
+        miner: node.miner(),
+        network_service: node.network(),
+        block_sync: node.block_sync(),
+        light_sync: if config.light {
+            node.light_sync()
+        } else {
+            None
+        },
+        kademlia: node.kademlia(),
+        account_provider: ap,
     });
 
+    let tls_certificates = match config::parse_tls_config(&matches)? {
+        Some(tls_config) => Some(tls_start(tls_config)?),
+        None => None,
+    };
+
     let _rpc_server = {
         if let Some(rpc_config) = config::parse_rpc_config(&matches)? {
-            Some(rpc_start(rpc_config, rpc_apis_deps.clone())?)
+            Some(rpc_start(rpc_config, rpc_apis_deps.clone(), tls_certificates.clone())?)
         } else {
             None
         }
     };
 
-    let _network_service = {
-        if let Some(network_config) = config::parse_network_config(&matches)? {
-            let service = network_start(&network_config)?;
-
-            match config::parse_discovery_config(&matches)? {
-                Some(config::Discovery::Unstructured(config)) => {
-                    let unstructured = UnstructuredExtension::new(config);
-                    service.set_routing_table(&*unstructured);
-                    service.register_extension(unstructured)?;
-                    info!(target: "discovery", "Node runs with unstructured discovery");
-                }
-                Some(config::Discovery::Kademlia(config)) => {
-                    let kademlia = Arc::new(KademliaExtension::new(config));
-                    service.set_routing_table(&*kademlia);
-                    service.register_extension(kademlia)?;
-                    info!(target: "discovery", "Node runs with kademlia discovery");
-                }
-                None => {
-                    warn!(target: "discovery", "Node runs without discovery extension");
-                }
-            }
-
-            if config.enable_block_sync {
-                let sync = BlockSyncExtension::new(client.client());
-                service.register_extension(sync.clone())?;
-                client.client().add_notify(sync.clone());
-            }
-            if config.enable_parcel_relay {
-                service.register_extension(ParcelSyncExtension::new(client.client()))?;
-            }
-            if let Some(consensus_extension) = spec.engine.network_extension() {
-                service.register_extension(consensus_extension)?;
-            }
-
-            for address in network_config.bootstrap_addresses {
-                service.connect_to(address)?;
-            }
-            Some(service)
+    let _ipc_server = {
+        if let Some(ipc_config) = config::parse_ipc_config(&matches, &config.db_path)? {
+            Some(ipc_start(ipc_config, rpc_apis_deps.clone())?)
         } else {
             None
         }
     };
 
-    // drop the spec to free up genesis state.
-    drop(spec);
+    let _ws_server = {
+        if let Some(ws_config) = config::parse_ws_config(&matches)? {
+            Some(ws_start(ws_config, rpc_apis_deps.clone(), tls_certificates.clone())?)
+        } else {
+            None
+        }
+    };
 
     info!(target: "test_script", "Initialization complete");
 
@@ -0,0 +1,409 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ccore::{
+    AccountProvider, Client, ClientConfig, ClientService, Miner, MinerOptions, MinerService, SnapshotService, Spec,
+};
+use cdiscovery::{KademliaConfig, KademliaExtension, UnstructuredConfig, UnstructuredExtension};
+use cnetwork::{
+    load_allowlist, load_or_generate_node_key, start_capture, start_trace_exporter, NetworkConfig, NetworkExtension,
+    NetworkService, P2pConfig, SocketAddr,
+};
+use ckeys::Private;
+use csync::{BlockSyncExtension, HistoryPolicy, LightSyncExtension, ParcelSyncExtension, SnapshotSyncExtension};
+use ctypes::Address;
+
+pub enum Discovery {
+    Kademlia(KademliaConfig),
+    Unstructured(UnstructuredConfig),
+}
+
+/// The node takes a snapshot of the state every `period` blocks into `path`, and signs its manifest with `private`.
+pub struct SnapshotConfig {
+    pub path: PathBuf,
+    pub period: u64,
+    pub private: Private,
+}
+
+/// Collects the options of a node. Nothing starts until `start` is called.
+///
+/// Without `network`, the node runs alone and the options of the network extensions are ignored.
+pub struct NodeBuilder {
+    spec: Spec,
+    db_path: PathBuf,
+    client_config: ClientConfig,
+    miner_options: MinerOptions,
+    account_provider: Option<Arc<AccountProvider>>,
+    author: Option<Address>,
+    engine_signer: Option<Address>,
+    network: Option<NetworkConfig>,
+    discovery: Option<Discovery>,
+    block_sync: Option<HistoryPolicy>,
+    light: bool,
+    snapshot: Option<SnapshotConfig>,
+    warp_signers: Vec<Address>,
+    parcel_relay: bool,
+    parcel_diffusion: bool,
+    extensions: Vec<Arc<NetworkExtension>>,
+}
+
+impl NodeBuilder {
+    pub fn new<P: AsRef<Path>>(spec: Spec, db_path: P) -> Self {
+        Self {
+            spec,
+            db_path: db_path.as_ref().to_path_buf(),
+            client_config: Default::default(),
+            miner_options: Default::default(),
+            account_provider: None,
+            author: None,
+            engine_signer: None,
+            network: None,
+            discovery: None,
+            block_sync: Some(HistoryPolicy::new(None, vec![])),
+            light: false,
+            snapshot: None,
+            warp_signers: Vec::new(),
+            parcel_relay: true,
+            parcel_diffusion: true,
+            extensions: Vec::new(),
+        }
+    }
+
+    pub fn client_config(mut self, client_config: ClientConfig) -> Self {
+        self.client_config = client_config;
+        self
+    }
+
+    pub fn miner_options(mut self, miner_options: MinerOptions) -> Self {
+        self.miner_options = miner_options;
+        self
+    }
+
+    /// The accounts which sign the blocks and the parcels of this node.
+    pub fn account_provider(mut self, account_provider: Arc<AccountProvider>) -> Self {
+        self.account_provider = Some(account_provider);
+        self
+    }
+
+    pub fn author(mut self, author: Address) -> Self {
+        self.author = Some(author);
+        self
+    }
+
+    /// The account must be in the account provider.
+    pub fn engine_signer(mut self, engine_signer: Address) -> Self {
+        self.engine_signer = Some(engine_signer);
+        self
+    }
+
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    pub fn discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// `None` disables the block sync.
+    pub fn block_sync(mut self, history_policy: Option<HistoryPolicy>) -> Self {
+        self.block_sync = history_policy;
+        self
+    }
+
+    /// A light node syncs only the headers, and fetches the bodies and the accounts from the full nodes on demand.
+    pub fn light(mut self, enabled: bool) -> Self {
+        self.light = enabled;
+        self
+    }
+
+    pub fn snapshot(mut self, snapshot: SnapshotConfig) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// A new node restores the latest snapshot signed by one of `warp_signers` instead of replaying every block.
+    pub fn warp(mut self, warp_signers: Vec<Address>) -> Self {
+        self.warp_signers = warp_signers;
+        self
+    }
+
+    pub fn parcel_relay(mut self, enabled: bool) -> Self {
+        self.parcel_relay = enabled;
+        self
+    }
+
+    pub fn parcel_diffusion(mut self, enabled: bool) -> Self {
+        self.parcel_diffusion = enabled;
+        self
+    }
+
+    /// Registers an extension of the embedding project to the network.
+    pub fn extension(mut self, extension: Arc<NetworkExtension>) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    pub fn start(self) -> Result<Node, String> {
+        let miner = Miner::new(self.miner_options, &self.spec, self.account_provider);
+        if let Some(author) = self.author {
+            miner.set_author(author);
+        }
+        if let Some(engine_signer) = self.engine_signer {
+            miner.set_engine_signer(engine_signer).map_err(|err| format!("{:?}", err))?;
+        }
+
+        info!("Starting client");
+        let client_service = ClientService::start(self.client_config, &self.spec, &self.db_path, miner.clone())
+            .map_err(|e| format!("Client service error: {:?}", e))?;
+        let client = client_service.client();
+
+        let snapshot = self.snapshot.map(|config| {
+            let snapshot = SnapshotService::new(client.clone(), config.path, config.period, config.private);
+            client.add_notify(snapshot.clone());
+            snapshot
+        });
+
+        let mut block_sync = None;
+        let mut light_sync = None;
+        let mut kademlia_extension = None;
+        let network_service = match self.network {
+            Some(network_config) => {
+                let service = network_start(&network_config, self.spec.params().network_id)?;
+
+                match self.discovery {
+                    Some(Discovery::Unstructured(config)) => {
+                        let unstructured = UnstructuredExtension::new(config);
+                        service.set_routing_table(&*unstructured);
+                        service.register_extension(unstructured)?;
+                        info!(target: "discovery", "Node runs with unstructured discovery");
+                    }
+                    Some(Discovery::Kademlia(config)) => {
+                        let kademlia = Arc::new(KademliaExtension::new(config));
+                        service.set_routing_table(&*kademlia);
+                        service.register_extension(kademlia.clone())?;
+                        kademlia_extension = Some(kademlia);
+                        info!(target: "discovery", "Node runs with kademlia discovery");
+                    }
+                    None => {
+                        warn!(target: "discovery", "Node runs without discovery extension");
+                    }
+                }
+
+                if let Some(history_policy) = self.block_sync {
+                    let sync = BlockSyncExtension::new(client.clone(), history_policy);
+                    sync.set_headers_only(self.light);
+                    service.register_extension(sync.clone())?;
+                    client.add_notify(sync.clone());
+                    block_sync = Some(sync);
+                }
+                if snapshot.is_some() || !self.warp_signers.is_empty() {
+                    let extension = SnapshotSyncExtension::new(
+                        client.clone(),
+                        snapshot.clone(),
+                        block_sync.clone(),
+                        self.warp_signers,
+                    );
+                    service.register_extension(extension)?;
+                }
+                // The full nodes serve the light nodes.
+                let light = LightSyncExtension::new(client.clone(), client.clone());
+                service.register_extension(light.clone())?;
+                light_sync = Some(light);
+                if self.parcel_relay {
+                    let parcel_sync = ParcelSyncExtension::new(client.clone(), client.clone(), self.parcel_diffusion);
+                    service.register_extension(parcel_sync)?;
+                }
+                if let Some(consensus_extension) = self.spec.engine.network_extension() {
+                    service.register_extension(consensus_extension)?;
+                }
+                for extension in self.extensions {
+                    service.register_extension(extension)?;
+                }
+
+                Some(Arc::new(service))
+            }
+            None => None,
+        };
+
+        Ok(Node {
+            client_service,
+            miner,
+            network_service,
+            block_sync,
+            light_sync,
+            snapshot,
+            kademlia: kademlia_extension,
+        })
+    }
+}
+
+// Lets the peers track the software versions in the network
+fn user_agent() -> String {
+    format!("CodeChain/v{}/{}", env!("CARGO_PKG_VERSION"), ::std::env::consts::OS)
+}
+
+fn network_start(cfg: &NetworkConfig, network_id: u64) -> Result<NetworkService, String> {
+    info!("Handshake Listening on {}", cfg.port);
+    let address = SocketAddr::v4(127, 0, 0, 1, cfg.port);
+    let websocket_address = cfg.websocket_port.map(|port| SocketAddr::v4(127, 0, 0, 1, port));
+    let key_pair = load_or_generate_node_key(Path::new(&cfg.node_key_path))?;
+    info!("Node identity is {:?}", key_pair.public());
+    if let Some(endpoint) = &cfg.otlp_endpoint {
+        start_trace_exporter(endpoint, cfg.otlp_sample_rate)?;
+        info!("Exporting {} of the network traces to {}", cfg.otlp_sample_rate, endpoint);
+    }
+    if let Some(path) = &cfg.capture_path {
+        start_capture(path)?;
+        warn!("Capturing the decrypted network messages to {}", path);
+    }
+    if cfg.mdns {
+        info!("Finding the local nodes of the network {} with mDNS", network_id);
+    }
+    if cfg.allow_legacy_cipher {
+        warn!("Accepting the peers which use the unauthenticated legacy cipher");
+    }
+    let allowlist = match &cfg.allowlist_path {
+        Some(path) => {
+            let allowlist = load_allowlist(Path::new(path))?;
+            info!("Only the {} nodes in the allowlist {} can connect", allowlist.len(), path);
+            Some(allowlist)
+        }
+        None => None,
+    };
+    let p2p_config = P2pConfig {
+        address,
+        websocket_address,
+        key_pair,
+        network_id,
+        relay: cfg.relay,
+        static_peers: cfg.static_peers.clone(),
+        bootnodes: cfg.bootnodes.clone(),
+        peer_store_path: cfg.peer_store_path.as_ref().map(PathBuf::from),
+        min_peers: cfg.min_peers,
+        max_peers: cfg.max_peers,
+        idle_timeout: cfg.idle_timeout.map(Duration::from_secs),
+        static_peer_idle_timeout: cfg.static_peer_idle_timeout.map(Duration::from_secs),
+        evictable_idle_timeout: Duration::from_secs(cfg.evictable_idle_timeout),
+        socket_options: cfg.socket_options,
+        static_peer_socket_options: cfg.static_peer_socket_options,
+        rate_limit: cfg.rate_limit,
+        user_agent: user_agent(),
+        allow_legacy_cipher: cfg.allow_legacy_cipher,
+        allowlist,
+    };
+    let service = NetworkService::start(p2p_config, cfg.dns_seeds.clone(), cfg.extension_workers, cfg.mdns)
+        .map_err(|e| format!("Network service error: {:?}", e))?;
+
+    Ok(service)
+}
+
+/// A running node. The services stop when it is dropped.
+pub struct Node {
+    client_service: ClientService,
+    miner: Arc<Miner>,
+    network_service: Option<Arc<NetworkService>>,
+    block_sync: Option<Arc<BlockSyncExtension>>,
+    light_sync: Option<Arc<LightSyncExtension>>,
+    snapshot: Option<Arc<SnapshotService>>,
+    // Only the kademlia discovery has the routing table to inspect
+    kademlia: Option<Arc<KademliaExtension>>,
+}
+
+impl Node {
+    pub fn client(&self) -> Arc<Client> {
+        self.client_service.client()
+    }
+
+    /// The miner, which also keeps the pending parcels.
+    pub fn miner(&self) -> Arc<Miner> {
+        Arc::clone(&self.miner)
+    }
+
+    pub fn network(&self) -> Option<Arc<NetworkService>> {
+        self.network_service.clone()
+    }
+
+    pub fn block_sync(&self) -> Option<Arc<BlockSyncExtension>> {
+        self.block_sync.clone()
+    }
+
+    /// Fetches the bodies and the accounts on demand.
+    pub fn light_sync(&self) -> Option<Arc<LightSyncExtension>> {
+        self.light_sync.clone()
+    }
+
+    pub fn snapshot(&self) -> Option<Arc<SnapshotService>> {
+        self.snapshot.clone()
+    }
+
+    pub fn kademlia(&self) -> Option<Arc<KademliaExtension>> {
+        self.kademlia.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use ccore::ChainInfo;
+    use ctypes::H160;
+
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn node_without_network_runs_alone() {
+        let tempdir = TempDir::new("node").unwrap();
+        let spec = Spec::new_test();
+        let genesis_hash = spec.genesis_header().hash();
+
+        let node = NodeBuilder::new(spec, tempdir.path()).start().unwrap();
+        assert!(node.network().is_none());
+        assert!(node.block_sync().is_none());
+        assert!(node.light_sync().is_none());
+        assert!(node.snapshot().is_none());
+        assert!(node.kademlia().is_none());
+
+        let chain_info = node.client().chain_info();
+        assert_eq!(0, chain_info.best_block_number);
+        assert_eq!(genesis_hash, chain_info.genesis_hash);
+    }
+
+    #[test]
+    fn node_restarts_on_its_database() {
+        let tempdir = TempDir::new("node").unwrap();
+        let best_block_hash = {
+            let node = NodeBuilder::new(Spec::new_test(), tempdir.path()).start().unwrap();
+            node.client().chain_info().best_block_hash
+        };
+
+        let node = NodeBuilder::new(Spec::new_test(), tempdir.path()).start().unwrap();
+        assert_eq!(best_block_hash, node.client().chain_info().best_block_hash);
+    }
+
+    #[test]
+    fn engine_signer_needs_the_account_provider() {
+        let tempdir = TempDir::new("node").unwrap();
+        let builder = NodeBuilder::new(Spec::new_solo(), tempdir.path()).engine_signer(H160::random());
+        assert!(builder.start().is_err());
+    }
+}
@@ -16,17 +16,35 @@
 
 use std::io;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 
-use crpc::{start_http, Compatibility, MetaIoHandler, Server};
-use rpc_apis;
+use crpc::{
+    start_http, start_ipc, start_tls, start_ws, Authorization, Compatibility, DEFAULT_MAX_BATCH_SIZE, HealthCheck,
+    IpcServer, MetaIoHandler, Metadata, RateLimits, RequestMiddleware, Scopes, Secrets, Server, TlsCertificates,
+    TlsServer, WsServer,
+};
+use rpc_apis::{self, ApiSet};
+
+const DEFAULT_MAX_WS_CONNECTIONS: usize = 100;
 
 #[derive(Debug, PartialEq)]
 pub struct HttpConfiguration {
     pub interface: String,
     pub port: u16,
+    // The origins which get the CORS headers. None sends no CORS header.
     pub cors: Option<Vec<String>>,
+    // The accepted Host headers. None accepts any host.
     pub hosts: Option<Vec<String>>,
+    // The calls need the API tokens in this file. None accepts every call, which only --jsonrpc-no-auth sets.
+    pub secrets_path: Option<String>,
+    // The calls which a client can make, counted by its IP address
+    pub rate_limits: RateLimits,
+    // The scopes of the methods which are served. None serves every method.
+    pub allowed_methods: Option<Vec<String>>,
+    pub max_batch_size: usize,
+    // The account, the miner and the admin methods are served only if it's set explicitly
+    pub apis: ApiSet,
 }
 
 impl HttpConfiguration {
@@ -34,27 +52,132 @@ impl HttpConfiguration {
         HttpConfiguration {
             interface: "127.0.0.1".into(),
             port,
-            cors: None,
-            hosts: Some(Vec::new()),
+            // Only the pages served from this machine can read the responses
+            cors: Some(vec!["http://localhost:*".into(), "http://127.0.0.1:*".into()]),
+            hosts: Some(vec![format!("localhost:{}", port), format!("127.0.0.1:{}", port)]),
+            secrets_path: None,
+            rate_limits: RateLimits::default(),
+            allowed_methods: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            apis: ApiSet::Restricted,
+        }
+    }
+}
+
+// The PEM files which the HTTP and the WebSocket servers use to serve TLS
+#[derive(Debug, PartialEq)]
+pub struct TlsConfiguration {
+    pub certificate_path: String,
+    pub key_path: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IpcConfiguration {
+    pub socket_addr: String,
+    pub allowed_methods: Option<Vec<String>>,
+    pub max_batch_size: usize,
+}
+
+impl IpcConfiguration {
+    // The socket is in the given data directory, which should be an absolute path
+    pub fn in_dir(dir: &Path) -> Self {
+        IpcConfiguration {
+            socket_addr: if cfg!(windows) {
+                r"\\.\pipe\codechain.ipc".into()
+            } else {
+                dir.join("codechain.ipc").to_string_lossy().into_owned()
+            },
+            allowed_methods: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WsConfiguration {
+    pub interface: String,
+    pub port: u16,
+    // The accepted Origin headers of the browsers. None accepts any origin.
+    pub origins: Option<Vec<String>>,
+    // The accepted Host headers. None accepts any host.
+    pub hosts: Option<Vec<String>>,
+    // The calls need the API tokens in this file. None accepts every call, which only --jsonrpc-no-auth sets.
+    pub secrets_path: Option<String>,
+    // The calls which a connection can make
+    pub rate_limits: RateLimits,
+    pub max_connections: usize,
+    pub allowed_methods: Option<Vec<String>>,
+    pub max_batch_size: usize,
+}
+
+impl WsConfiguration {
+    pub fn with_port(port: u16) -> Self {
+        WsConfiguration {
+            interface: "127.0.0.1".into(),
+            port,
+            // Only the pages served from this machine can connect
+            origins: Some(vec!["http://localhost:*".into(), "http://127.0.0.1:*".into()]),
+            hosts: Some(vec![format!("localhost:{}", port), format!("127.0.0.1:{}", port)]),
+            secrets_path: None,
+            rate_limits: RateLimits::default(),
+            max_connections: DEFAULT_MAX_WS_CONNECTIONS,
+            allowed_methods: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+}
+
+// The rpc server listens on the loopback behind the TLS server, if the certificates are given.
+fn listen_address(addr: SocketAddr, tls: &Option<Arc<TlsCertificates>>) -> SocketAddr {
+    if tls.is_some() {
+        "127.0.0.1:0".parse().expect("The loopback address is valid")
+    } else {
+        addr
+    }
+}
+
+fn serve_tls(
+    addr: &SocketAddr,
+    backend: &SocketAddr,
+    tls: Option<Arc<TlsCertificates>>,
+) -> Result<Option<TlsServer>, String> {
+    match tls {
+        Some(certificates) => {
+            start_tls(addr, *backend, certificates).map(Some).map_err(|e| format!("TLS error on {}: {:?}", addr, e))
         }
+        None => Ok(None),
     }
 }
 
-pub fn new_http(cfg: HttpConfiguration, deps: Arc<rpc_apis::ApiDependencies>) -> Result<Server, String> {
+pub fn new_http(
+    cfg: HttpConfiguration,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    tls: Option<Arc<TlsCertificates>>,
+) -> Result<(Server, Option<TlsServer>), String> {
     let url = format!("{}:{}", cfg.interface, cfg.port);
     let addr = url.parse().map_err(|_| format!("Invalid JSONRPC listen host/port given: {}", url))?;
-    let server = setup_http_rpc_server(&addr, cfg.cors, cfg.hosts, deps)?;
-    Ok(server)
+    let secrets = load_secrets(cfg.secrets_path, "HTTP")?;
+    let middleware = allow_methods(
+        RequestMiddleware::new(Authorization::new(secrets), cfg.rate_limits, cfg.max_batch_size),
+        cfg.allowed_methods,
+    );
+    let server =
+        setup_http_rpc_server(&listen_address(addr, &tls), cfg.cors, cfg.hosts, middleware, cfg.apis, deps)?;
+    let tls_server = serve_tls(&addr, server.address(), tls)?;
+    Ok((server, tls_server))
 }
 
 pub fn setup_http_rpc_server(
     url: &SocketAddr,
     cors_domains: Option<Vec<String>>,
     allowed_hosts: Option<Vec<String>>,
+    middleware: RequestMiddleware,
+    apis: ApiSet,
     deps: Arc<rpc_apis::ApiDependencies>,
 ) -> Result<Server, String> {
-    let server = setup_rpc_server(deps);
-    let start_result = start_http(url, cors_domains, allowed_hosts, server);
+    let health = HealthCheck::new(deps.client.clone(), deps.network_service.clone(), deps.block_sync.clone());
+    let server = setup_rpc_server(deps, apis, middleware);
+    let start_result = start_http(url, cors_domains, allowed_hosts, health, server);
     match start_result {
         Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => {
             Err(format!("RPC address {} is already in use, make sure that another instance of a Bitcoin node is not running or change the address using the --jsonrpc-port and --jsonrpc-interface options.", url))
@@ -64,8 +187,78 @@ pub fn setup_http_rpc_server(
     }
 }
 
-fn setup_rpc_server(deps: Arc<rpc_apis::ApiDependencies>) -> MetaIoHandler<()> {
-    let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
-    deps.extend_api(&mut handler);
+pub fn new_ipc(cfg: IpcConfiguration, deps: Arc<rpc_apis::ApiDependencies>) -> Result<IpcServer, String> {
+    let middleware = allow_methods(
+        RequestMiddleware::new(Authorization::new(None), RateLimits::default(), cfg.max_batch_size),
+        cfg.allowed_methods,
+    );
+    let server = setup_rpc_server(deps, ApiSet::All, middleware);
+    let server = start_ipc(&cfg.socket_addr, server).map_err(|e| format!("IPC error: {:?}", e))?;
+    restrict_to_owner(&cfg.socket_addr).map_err(|e| format!("IPC error: {:?}", e))?;
+    Ok(server)
+}
+
+// Only the user who runs the node can connect to the socket, since the IPC serves every method.
+#[cfg(unix)]
+fn restrict_to_owner(path: &str) -> io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_to_owner(_path: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn new_ws(
+    cfg: WsConfiguration,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    tls: Option<Arc<TlsCertificates>>,
+) -> Result<(WsServer, Option<TlsServer>), String> {
+    let url = format!("{}:{}", cfg.interface, cfg.port);
+    let addr = url.parse().map_err(|_| format!("Invalid WebSocket listen host/port given: {}", url))?;
+    let secrets = load_secrets(cfg.secrets_path, "WebSocket")?;
+    let middleware = allow_methods(
+        RequestMiddleware::new(Authorization::new(secrets), cfg.rate_limits, cfg.max_batch_size),
+        cfg.allowed_methods,
+    );
+    let mut server = setup_rpc_server(deps.clone(), ApiSet::Restricted, middleware);
+    deps.extend_pubsub_api(&mut server);
+    let server = start_ws(&listen_address(addr, &tls), cfg.origins, cfg.hosts, cfg.max_connections, server)
+        .map_err(|e| format!("WebSocket error: {:?}", e))?;
+    let tls_server = serve_tls(&addr, server.addr(), tls)?;
+    Ok((server, tls_server))
+}
+
+fn load_secrets(path: Option<String>, transport: &str) -> Result<Option<Secrets>, String> {
+    match path {
+        Some(path) => {
+            let secrets = Secrets::load_or_generate(Path::new(&path))?;
+            info!("RPC calls over {} need the API tokens in {}", transport, path);
+            Ok(Some(secrets))
+        }
+        None => {
+            warn!("RPC calls over {} are accepted without the API tokens", transport);
+            Ok(None)
+        }
+    }
+}
+
+fn allow_methods(middleware: RequestMiddleware, allowed_methods: Option<Vec<String>>) -> RequestMiddleware {
+    match allowed_methods {
+        Some(scopes) => middleware.with_allowed_methods(Scopes::new(scopes)),
+        None => middleware,
+    }
+}
+
+fn setup_rpc_server(
+    deps: Arc<rpc_apis::ApiDependencies>,
+    apis: ApiSet,
+    middleware: RequestMiddleware,
+) -> MetaIoHandler<Metadata, RequestMiddleware> {
+    let mut handler = MetaIoHandler::new(Compatibility::Both, middleware);
+    deps.extend_api(&mut handler, apis);
     rpc_apis::setup_rpc(handler)
 }
@@ -16,23 +16,74 @@
 
 use std::sync::Arc;
 
-use ccore::{Client, Miner};
-use crpc::{MetaIoHandler, Params, Value};
+use ccore::{AccountProvider, Client, Miner};
+use cdiscovery::KademliaExtension;
+use cnetwork::NetworkService;
+use crpc::{MetaIoHandler, Metadata, Params, RequestMiddleware, Value};
+use csync::{BlockSyncExtension, LightSyncExtension};
+
+/// The methods which a transport serves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiSet {
+    /// Every method, for the local tools on the IPC
+    All,
+    /// The methods which are safe to expose over the network
+    Restricted,
+}
 
 pub struct ApiDependencies {
     pub client: Arc<Client>,
     pub miner: Arc<Miner>,
+    pub network_service: Option<Arc<NetworkService>>,
+    pub block_sync: Option<Arc<BlockSyncExtension>>,
+    /// Set only on a light node, which fetches the state and the bodies from the peers
+    pub light_sync: Option<Arc<LightSyncExtension>>,
+    pub kademlia: Option<Arc<KademliaExtension>>,
+    pub account_provider: Arc<AccountProvider>,
 }
 
 impl ApiDependencies {
-    pub fn extend_api(&self, handler: &mut MetaIoHandler<()>) {
+    pub fn extend_api(&self, handler: &mut MetaIoHandler<Metadata, RequestMiddleware>, apis: ApiSet) {
         use crpc::v1::*;
-        handler.extend_with(ChainClient::new(&self.client, &self.miner).to_delegate());
+        let chain =
+            ChainClient::new(self.client.clone(), self.client.clone(), self.client.clone(), self.light_sync.clone());
+        handler.extend_with(chain.to_delegate());
+        if let Some(network_service) = &self.network_service {
+            handler.extend_with(Net::to_delegate(NetClient::new(network_service, &self.block_sync)));
+        }
+        if let Some(block_sync) = &self.block_sync {
+            handler.extend_with(BlockSyncClient::new(block_sync).to_delegate());
+        }
+        if apis == ApiSet::Restricted {
+            return
+        }
         handler.extend_with(DevelClient::new(&self.client).to_delegate());
+        handler.extend_with(MinerClient::new(&self.client, &self.miner).to_delegate());
+        handler.extend_with(AccountClient::new(&self.account_provider).to_delegate());
+        if let Some(network_service) = &self.network_service {
+            handler.extend_with(NetAdmin::to_delegate(NetClient::new(network_service, &self.block_sync)));
+            handler.extend_with(DebugClient::new(network_service).to_delegate());
+        }
+        if let Some(kademlia) = &self.kademlia {
+            handler.extend_with(DiscoveryClient::new(kademlia).to_delegate());
+        }
+    }
+}
+
+impl ApiDependencies {
+    /// The subscriptions, which need the sessions of the WebSocket
+    pub fn extend_pubsub_api(&self, handler: &mut MetaIoHandler<Metadata, RequestMiddleware>) {
+        use crpc::v1::*;
+        handler.extend_with(ChainPubSubClient::new(&self.client).to_delegate());
+        if let Some(network_service) = &self.network_service {
+            handler.extend_with(NetPubSubClient::new(network_service).to_delegate());
+        }
     }
 }
 
-pub fn setup_rpc(mut handler: MetaIoHandler<()>) -> MetaIoHandler<()> {
+pub fn setup_rpc(
+    mut handler: MetaIoHandler<Metadata, RequestMiddleware>,
+) -> MetaIoHandler<Metadata, RequestMiddleware> {
     handler.add_method("ping", |_params: Params| Ok(Value::String("pong".to_string())));
     handler
 }
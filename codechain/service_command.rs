@@ -0,0 +1,193 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::env;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+const DEFAULT_NAME: &'static str = "codechain";
+// The node flushes the database when it stops, so give it some time before it is killed.
+const STOP_TIMEOUT_SECS: u64 = 30;
+
+struct ServiceConfig {
+    name: String,
+    exe: PathBuf,
+    working_directory: PathBuf,
+    config_path: Option<PathBuf>,
+    user: Option<String>,
+}
+
+impl ServiceConfig {
+    fn from_matches(matches: &ArgMatches) -> Result<Self, String> {
+        let cwd = env::current_dir().map_err(|e| format!("Cannot get the current directory: {}", e))?;
+        let absolute = |path: &str| cwd.join(path);
+
+        let exe = env::current_exe().map_err(|e| format!("Cannot get the executable path: {}", e))?;
+        let working_directory = matches.value_of("working-directory").map(&absolute).unwrap_or_else(|| cwd.clone());
+        let config_path = matches.value_of("config-path").map(&absolute);
+        Ok(ServiceConfig {
+            name: matches.value_of("name").unwrap_or(DEFAULT_NAME).to_string(),
+            exe,
+            working_directory,
+            config_path,
+            user: matches.value_of("user").map(|user| user.to_string()),
+        })
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![self.exe.display().to_string()];
+        if let Some(ref config_path) = self.config_path {
+            args.push("--config-path".to_string());
+            args.push(config_path.display().to_string());
+        }
+        args
+    }
+}
+
+pub fn run_service_command(matches: ArgMatches) -> Result<(), String> {
+    let subcommand = matches.subcommand.unwrap();
+    let config = ServiceConfig::from_matches(&subcommand.matches)?;
+
+    match subcommand.name.as_ref() {
+        "systemd" => {
+            print!("{}", systemd_unit(&config));
+            Ok(())
+        }
+        "launchd" => {
+            print!("{}", launchd_plist(&config));
+            Ok(())
+        }
+        _ => Err("Invalid subcommand".to_string()),
+    }
+}
+
+/// The unit stops the node with SIGINT, which is what the node waits for before shutting down.
+fn systemd_unit(config: &ServiceConfig) -> String {
+    let mut unit = String::new();
+    unit.push_str("[Unit]\n");
+    unit.push_str("Description=CodeChain node\n");
+    unit.push_str("After=network-online.target\n");
+    unit.push_str("Wants=network-online.target\n");
+    unit.push_str("\n");
+    unit.push_str("[Service]\n");
+    unit.push_str("Type=simple\n");
+    if let Some(ref user) = config.user {
+        unit.push_str(&format!("User={}\n", user));
+    }
+    unit.push_str(&format!("WorkingDirectory={}\n", config.working_directory.display()));
+    let exec_start: Vec<_> = config.args().iter().map(|arg| systemd_quote(arg)).collect();
+    unit.push_str(&format!("ExecStart={}\n", exec_start.join(" ")));
+    unit.push_str("KillSignal=SIGINT\n");
+    unit.push_str(&format!("TimeoutStopSec={}\n", STOP_TIMEOUT_SECS));
+    unit.push_str("Restart=on-failure\n");
+    unit.push_str("LimitNOFILE=65536\n");
+    unit.push_str("\n");
+    unit.push_str("[Install]\n");
+    unit.push_str("WantedBy=multi-user.target\n");
+    unit
+}
+
+fn systemd_quote(arg: &str) -> String {
+    if arg.chars().any(|c| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// launchd sends SIGTERM on stop and waits ExitTimeOut seconds before it sends SIGKILL.
+fn launchd_plist(config: &ServiceConfig) -> String {
+    let mut plist = String::new();
+    plist.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    plist.push_str(
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+    );
+    plist.push_str("<plist version=\"1.0\">\n");
+    plist.push_str("<dict>\n");
+    plist.push_str(&format!("    <key>Label</key>\n    <string>io.kodebox.{}</string>\n", xml_escape(&config.name)));
+    plist.push_str("    <key>ProgramArguments</key>\n    <array>\n");
+    for arg in config.args() {
+        plist.push_str(&format!("        <string>{}</string>\n", xml_escape(&arg)));
+    }
+    plist.push_str("    </array>\n");
+    plist.push_str(&format!(
+        "    <key>WorkingDirectory</key>\n    <string>{}</string>\n",
+        xml_escape(&config.working_directory.display().to_string())
+    ));
+    if let Some(ref user) = config.user {
+        plist.push_str(&format!("    <key>UserName</key>\n    <string>{}</string>\n", xml_escape(user)));
+    }
+    plist.push_str("    <key>RunAtLoad</key>\n    <true/>\n");
+    plist.push_str("    <key>KeepAlive</key>\n    <dict>\n");
+    plist.push_str("        <key>SuccessfulExit</key>\n        <false/>\n");
+    plist.push_str("    </dict>\n");
+    plist.push_str(&format!("    <key>ExitTimeOut</key>\n    <integer>{}</integer>\n", STOP_TIMEOUT_SECS));
+    plist.push_str("</dict>\n");
+    plist.push_str("</plist>\n");
+    plist
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(user: Option<&str>) -> ServiceConfig {
+        ServiceConfig {
+            name: "node <1>".to_string(),
+            exe: PathBuf::from("/opt/code chain/codechain"),
+            working_directory: PathBuf::from("/var/lib/codechain"),
+            config_path: Some(PathBuf::from("/etc/codechain/config.toml")),
+            user: user.map(|user| user.to_string()),
+        }
+    }
+
+    #[test]
+    fn systemd_unit_stops_the_node_with_sigint() {
+        let unit = systemd_unit(&config(Some("codechain")));
+        assert!(unit.contains("\nExecStart=\"/opt/code chain/codechain\" --config-path /etc/codechain/config.toml\n"));
+        assert!(unit.contains("\nWorkingDirectory=/var/lib/codechain\n"));
+        assert!(unit.contains("\nUser=codechain\n"));
+        assert!(unit.contains("\nKillSignal=SIGINT\n"));
+        assert!(unit.contains(&format!("\nTimeoutStopSec={}\n", STOP_TIMEOUT_SECS)));
+    }
+
+    #[test]
+    fn systemd_unit_without_user_runs_as_root() {
+        assert!(!systemd_unit(&config(None)).contains("User="));
+    }
+
+    #[test]
+    fn systemd_quote_escapes_the_quotes_and_the_backslashes() {
+        assert_eq!("plain", systemd_quote("plain"));
+        assert_eq!("\"a b\"", systemd_quote("a b"));
+        assert_eq!("\"a\\\"b\\\\c\"", systemd_quote("a\"b\\c"));
+    }
+
+    #[test]
+    fn launchd_plist_escapes_the_strings() {
+        let plist = launchd_plist(&config(Some("a&b")));
+        assert!(plist.contains("<string>io.kodebox.node &lt;1&gt;</string>"));
+        assert!(plist.contains("<string>/opt/code chain/codechain</string>"));
+        assert!(plist.contains("<string>--config-path</string>"));
+        assert!(plist.contains("<key>UserName</key>\n    <string>a&amp;b</string>"));
+        assert!(plist.contains(&format!("<key>ExitTimeOut</key>\n    <integer>{}</integer>", STOP_TIMEOUT_SECS)));
+    }
+}
@@ -14,15 +14,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use ccrypto::blake256;
 use ckeys::{
-    public_to_address, ECDSASignature, Error as KeysError, Generator, KeyPair, Message, Private, Public, Random,
+    public_to_address, DerivationPath, ECDSASignature, Error as KeysError, ExtendedPrivate, Generator, KeyPair, Message,
+    Private, Public, Random,
 };
 use ckeystore::accounts_dir::MemoryDirectory;
-use ckeystore::{Error as KeystoreError, KeyStore, SimpleSecretStore};
-use ctypes::Address;
+use ckeystore::{Error as KeystoreError, KeyStore, SecretStore, SimpleSecretStore};
+use ctypes::{Address, H256};
 use parking_lot::RwLock;
 
 /// Signing error
@@ -36,6 +40,12 @@ pub enum SignError {
     KeystoreError(KeystoreError),
     /// Inappropriate chain
     InappropriateChain,
+    /// The account is locked and the password isn't given.
+    NotUnlocked,
+    /// The password is empty or the one of the node accounts.
+    WeakPassword,
+    /// The account is the one which the node signs with, e.g. the engine signer.
+    NodeAccount,
 }
 
 impl From<KeysError> for SignError {
@@ -57,26 +67,54 @@ impl fmt::Display for SignError {
             SignError::KeysError(e) => write!(f, "{}", e),
             SignError::KeystoreError(e) => write!(f, "{}", e),
             SignError::InappropriateChain => write!(f, "Inappropriate chain"),
+            SignError::NotUnlocked => write!(f, "Account is locked"),
+            SignError::WeakPassword => write!(f, "Password is empty or reserved"),
+            SignError::NodeAccount => write!(f, "Account is used by the node"),
         }
     }
 }
 
+/// The password of the accounts which the node signs with, such as the engine signer.
+const DEFAULT_PASSWORD: &str = "password";
+
+/// The user messages are signed with this prefix, so their signatures can't be the ones of parcels or blocks.
+const USER_MESSAGE_PREFIX: &[u8] = b"\x19CodeChain Signed Message:\n";
+
+/// The hash which is signed for the message of a user.
+pub fn user_message_hash(message: &H256) -> Message {
+    let mut prefixed = USER_MESSAGE_PREFIX.to_vec();
+    prefixed.extend_from_slice(message);
+    blake256(&prefixed)
+}
+
+/// An account which signs without its password, until the deadline if there is one.
+struct Unlocked {
+    password: String,
+    until: Option<Instant>,
+}
+
+impl Unlocked {
+    fn is_expired(&self) -> bool {
+        self.until.map_or(false, |until| until <= Instant::now())
+    }
+}
+
 pub struct AccountProvider {
     keystore: RwLock<KeyStore>,
+    unlocked: RwLock<HashMap<Address, Unlocked>>,
 }
 
 impl AccountProvider {
     pub fn new(keystore: KeyStore) -> Arc<Self> {
         Arc::new(Self {
             keystore: RwLock::new(keystore),
+            unlocked: RwLock::new(HashMap::new()),
         })
     }
 
     /// Creates not disk backed provider.
     pub fn transient_provider() -> Arc<Self> {
-        Arc::new(Self {
-            keystore: RwLock::new(KeyStore::open(Box::new(MemoryDirectory::default())).unwrap()),
-        })
+        Self::new(KeyStore::open(Box::new(MemoryDirectory::default())).unwrap())
     }
 
     pub fn new_account_and_public(&self) -> Result<(Address, Public), SignError> {
@@ -84,24 +122,101 @@ impl AccountProvider {
         let private = acc.private().clone();
         let public = acc.public().clone();
         let address = public_to_address(&public);
-        self.keystore.write().insert_account(*private, "password")?;
+        self.keystore.write().insert_account(*private, DEFAULT_PASSWORD)?;
         Ok((address, public))
     }
 
     pub fn insert_account(&self, private: Private) -> Result<Address, SignError> {
+        self.insert_account_with_password(private, DEFAULT_PASSWORD)
+    }
+
+    /// Creates a new account of a user encrypted with the password.
+    pub fn new_account(&self, password: &str) -> Result<Address, SignError> {
+        let acc = Random.generate().expect("secp context has generation capabilities; qed");
+        self.insert_account_with_password(acc.private().clone(), password)
+    }
+
+    /// Imports the private key of a user encrypted with the password.
+    pub fn insert_account_with_password(&self, private: Private, password: &str) -> Result<Address, SignError> {
+        check_password(password)?;
         let acc = KeyPair::from_private(private)?;
         let private = acc.private().clone();
         let public = acc.public().clone();
         let address = public_to_address(&public);
-        self.keystore.write().insert_account(*private, "password")?;
+        self.keystore.write().insert_account(*private, password)?;
+        Ok(address)
+    }
+
+    /// Derives the account along the path from the HD master key and imports it.
+    pub fn insert_derived_account(
+        &self,
+        master: &ExtendedPrivate,
+        path: &DerivationPath,
+        password: &str,
+    ) -> Result<Address, SignError> {
+        check_password(password)?;
+        let address = self.keystore.write().insert_derived(master, path, password)?;
         Ok(address)
     }
 
     pub fn sign(&self, address: Address, message: Message) -> Result<ECDSASignature, SignError> {
-        let signature = self.keystore.read().sign(&address, "password", &message)?;
+        let signature = self.keystore.read().sign(&address, DEFAULT_PASSWORD, &message)?;
         Ok(signature)
     }
 
+    /// Lets the account sign without its password for the duration, or until the node exits.
+    pub fn unlock(&self, address: Address, password: &str, duration: Option<Duration>) -> Result<(), SignError> {
+        self.check_user_account(&address)?;
+        if !self.keystore.read().test_password(&address, password)? {
+            return Err(KeystoreError::InvalidPassword.into())
+        }
+        self.unlocked.write().insert(
+            address,
+            Unlocked {
+                password: password.to_string(),
+                until: duration.map(|duration| Instant::now() + duration),
+            },
+        );
+        Ok(())
+    }
+
+    /// Signs the prefixed message of a user with the password, or with the unlocked account if the password isn't
+    /// given. The accounts of the node never sign the messages of the users.
+    pub fn sign_user_message(
+        &self,
+        address: Address,
+        password: Option<&str>,
+        message: &H256,
+    ) -> Result<ECDSASignature, SignError> {
+        self.check_user_account(&address)?;
+        let password = match password {
+            Some(password) => password.to_string(),
+            None => self.unlocked_password(&address)?,
+        };
+        let signature = self.keystore.read().sign(&address, &password, &user_message_hash(message))?;
+        Ok(signature)
+    }
+
+    fn check_user_account(&self, address: &Address) -> Result<(), SignError> {
+        if self.keystore.read().test_password(address, DEFAULT_PASSWORD)? {
+            return Err(SignError::NodeAccount)
+        }
+        Ok(())
+    }
+
+    fn unlocked_password(&self, address: &Address) -> Result<String, SignError> {
+        let mut unlocked = self.unlocked.write();
+        let is_expired = match unlocked.get(address) {
+            Some(account) => account.is_expired(),
+            None => return Err(SignError::NotUnlocked),
+        };
+        if is_expired {
+            unlocked.remove(address);
+            return Err(SignError::NotUnlocked)
+        }
+        Ok(unlocked[address].password.clone())
+    }
+
     pub fn has_account(&self, address: Address) -> Result<bool, SignError> {
         let has = self.keystore.read().has_account(&address)?;
         Ok(has)
@@ -112,3 +227,92 @@ impl AccountProvider {
         Ok(addresses)
     }
 }
+
+fn check_password(password: &str) -> Result<(), SignError> {
+    if password.is_empty() || password == DEFAULT_PASSWORD {
+        return Err(SignError::WeakPassword)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ckeys::recover_ecdsa;
+
+    use super::*;
+
+    #[test]
+    fn locked_account_signs_only_with_password() {
+        let ap = AccountProvider::transient_provider();
+        let address = ap.new_account("secret").unwrap();
+        let message = H256::random();
+
+        assert!(ap.sign_user_message(address, None, &message).is_err());
+        assert!(ap.sign_user_message(address, Some("wrong"), &message).is_err());
+        assert!(ap.sign_user_message(address, Some("secret"), &message).is_ok());
+    }
+
+    #[test]
+    fn unlock_requires_the_password() {
+        let ap = AccountProvider::transient_provider();
+        let address = ap.new_account("secret").unwrap();
+
+        assert!(ap.unlock(address, "wrong", None).is_err());
+        assert!(ap.unlock(address, "secret", None).is_ok());
+        assert!(ap.sign_user_message(address, None, &H256::random()).is_ok());
+    }
+
+    #[test]
+    fn unlocked_account_is_locked_again_after_the_duration() {
+        let ap = AccountProvider::transient_provider();
+        let address = ap.new_account("secret").unwrap();
+
+        ap.unlock(address, "secret", Some(Duration::from_secs(0))).unwrap();
+        match ap.sign_user_message(address, None, &H256::random()) {
+            Err(SignError::NotUnlocked) => {}
+            _ => panic!("The account must be locked"),
+        }
+    }
+
+    #[test]
+    fn user_message_is_signed_with_the_prefix() {
+        let ap = AccountProvider::transient_provider();
+        let address = ap.new_account("secret").unwrap();
+        let message = H256::random();
+
+        let signature = ap.sign_user_message(address, Some("secret"), &message).unwrap();
+        let signer = recover_ecdsa(&signature, &user_message_hash(&message)).unwrap();
+        assert_eq!(address, public_to_address(&signer));
+        let raw_signer = recover_ecdsa(&signature, &message).unwrap();
+        assert_ne!(address, public_to_address(&raw_signer));
+    }
+
+    #[test]
+    fn node_account_does_not_sign_user_messages() {
+        let ap = AccountProvider::transient_provider();
+        let (address, _) = ap.new_account_and_public().unwrap();
+
+        match ap.sign_user_message(address, Some(DEFAULT_PASSWORD), &H256::random()) {
+            Err(SignError::NodeAccount) => {}
+            _ => panic!("The node account must not sign"),
+        }
+        match ap.unlock(address, DEFAULT_PASSWORD, None) {
+            Err(SignError::NodeAccount) => {}
+            _ => panic!("The node account must not be unlocked"),
+        }
+    }
+
+    #[test]
+    fn user_account_requires_a_password() {
+        let ap = AccountProvider::transient_provider();
+
+        match ap.new_account("") {
+            Err(SignError::WeakPassword) => {}
+            _ => panic!("The empty password must be refused"),
+        }
+        match ap.new_account(DEFAULT_PASSWORD) {
+            Err(SignError::WeakPassword) => {}
+            _ => panic!("The password of the node accounts must be refused"),
+        }
+    }
+}
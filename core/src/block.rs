@@ -18,7 +18,7 @@ use std::collections::HashSet;
 
 use ccrypto::BLAKE_NULL_RLP;
 use cmerkle::skewed_merkle_root;
-use ctypes::{Address, Bytes, H256};
+use ctypes::{Address, Bloom, Bytes, H256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use trie::TrieFactory;
 use unexpected::Mismatch;
@@ -52,6 +52,14 @@ impl Block {
     }
 }
 
+impl Encodable for Block {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        self.header.stream_rlp(s, Seal::With);
+        s.append_list(&self.parcels);
+    }
+}
+
 impl Decodable for Block {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
         if rlp.as_raw().len() != rlp.payload_info()?.total() {
@@ -92,6 +100,14 @@ impl ExecutedBlock {
     pub fn state_mut(&mut self) -> &mut State<StateDB> {
         &mut self.state
     }
+
+    /// The bloom of the logs in all the invoices.
+    fn log_bloom(&self) -> Bloom {
+        self.invoices.iter().flat_map(|invoices| invoices.iter()).fold(Bloom::default(), |mut bloom, invoice| {
+            bloom.accrue_bloom(&invoice.log_bloom());
+            bloom
+        })
+    }
 }
 
 impl Parcels for ExecutedBlock {
@@ -155,6 +171,7 @@ impl<'x> OpenBlock<'x> {
         if self.block.parcels_set.contains(&parcel.hash()) {
             return Err(ParcelError::AlreadyImported.into())
         }
+        parcel.check_timelock(self.block.header.number(), self.block.header.timestamp())?;
 
         let outcomes = self.block.state.apply(&parcel)?;
 
@@ -197,10 +214,16 @@ impl<'x> OpenBlock<'x> {
             self.block.parcels.iter().map(|e| e.rlp_bytes()),
         ));
         self.block.header.set_state_root(self.block.state.root().clone());
+        match self.block.state.shard_roots() {
+            Ok(shard_roots) => self.block.header.set_shard_roots(shard_roots),
+            Err(e) => warn!("Encountered error on reading the shard roots: {}", e),
+        }
         self.block.header.set_invoices_root(skewed_merkle_root(
             parent_invoices_root,
             self.block.invoices.iter().flat_map(|invoices| invoices.iter().map(|invoice| invoice.rlp_bytes())),
         ));
+        let log_bloom = self.block.log_bloom();
+        self.block.header.set_log_bloom(log_bloom);
 
         ClosedBlock {
             block: self.block,
@@ -229,7 +252,13 @@ impl<'x> OpenBlock<'x> {
                 self.block.invoices.iter().flat_map(|invoices| invoices.iter().map(|invoice| invoice.rlp_bytes())),
             ));
         }
+        let log_bloom = self.block.log_bloom();
+        self.block.header.set_log_bloom(log_bloom);
         self.block.header.set_state_root(self.block.state.root().clone());
+        match self.block.state.shard_roots() {
+            Ok(shard_roots) => self.block.header.set_shard_roots(shard_roots),
+            Err(e) => warn!("Encountered error on reading the shard roots: {}", e),
+        }
 
         LockedBlock {
             block: self.block,
@@ -434,11 +463,38 @@ pub fn enact(
 
 #[cfg(test)]
 mod tests {
-    use ctypes::Address;
-
+    use ccrypto::Blake;
+    use ckeys::ECDSASignature;
+    use ctypes::{Address, BloomInput, Secret, H256};
+    use cvm::{encode, Instruction};
+    use rlp;
+
+    use super::super::header::{Header, Seal};
+    use super::super::invoice::EventKind;
+    use super::super::parcel::{AssetOutPoint, AssetTransferInput, AssetTransferOutput, Parcel, SignedParcel};
     use super::super::spec::Spec;
+    use super::super::state::{AssetAddress, AssetSchemeAddress, MessageAddress};
     use super::super::tests::helpers::get_temp_state_db;
-    use super::OpenBlock;
+    use super::super::transaction::Transaction;
+    use super::{Block, Drain, IsBlock, OpenBlock};
+
+    #[test]
+    fn encode_and_decode_block() {
+        let mut header = Header::new();
+        header.set_number(3);
+        header.set_seal(vec![rlp::encode(&17u64).into_vec()]);
+        // The decoded header memoizes its hash
+        header.hash();
+        let signature = ECDSASignature::from_rsv(&H256::from([0x01; 32]), &H256::from([0x02; 32]), 1);
+        let parcel = Parcel::default().with_signature(signature);
+        let block = Block {
+            header,
+            parcels: vec![parcel],
+        };
+        let encoded = rlp::encode(&block).into_vec();
+        assert_eq!(block.rlp_bytes(Seal::With), encoded);
+        assert_eq!(block, rlp::decode(&encoded));
+    }
 
     #[test]
     fn open_block() {
@@ -452,4 +508,100 @@ mod tests {
         let b = b.close_and_lock(parent_parcels_root, parent_invoices_root);
         let _ = b.seal(&*spec.engine, vec![]);
     }
+
+    fn parcel(nonce: u64, transaction: Transaction) -> SignedParcel {
+        Parcel {
+            nonce: nonce.into(),
+            fee: 1.into(),
+            transactions: vec![transaction],
+            ..Parcel::default()
+        }.sign(&Secret::blake("").into())
+    }
+
+    #[test]
+    fn asset_is_relayed_from_the_block_after_the_one_which_sent_it() {
+        let spec = Spec::new_test();
+        let genesis_header = spec.genesis_header();
+        let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+        let mut b1 =
+            OpenBlock::new(&*spec.engine, Default::default(), db, &genesis_header, Address::zero(), vec![], false)
+                .unwrap();
+        let lock_script = encode(&[Instruction::PushI(1)]);
+        let lock_script_hash = Blake::blake(&lock_script);
+        let mint = Transaction::AssetMint {
+            shard_id: 0,
+            metadata: "metadata".to_string(),
+            lock_script_hash,
+            parameters: vec![],
+            amount: Some(100),
+            registrar: None,
+            nonce: 0,
+        };
+        let asset_type: H256 = AssetSchemeAddress::new(mint.hash(), 0).into();
+        let transfer = Transaction::AssetTransfer {
+            network_id: 0,
+            shard_id: 0,
+            inputs: vec![AssetTransferInput {
+                prev_out: AssetOutPoint {
+                    transaction_hash: mint.hash(),
+                    index: 0,
+                    asset_type,
+                    amount: 100,
+                },
+                lock_script,
+                unlock_script: vec![],
+                timelock: None,
+            }],
+            outputs: vec![AssetTransferOutput {
+                lock_script_hash,
+                parameters: vec![],
+                asset_type,
+                amount: 100,
+                shard_id: Some(1),
+            }],
+            nonce: 0,
+        };
+        let sender = parcel(0, mint.clone()).sender();
+        b1.block.state.add_balance(&sender, &100.into()).unwrap();
+        b1.block.state.create_shard(0).unwrap();
+        b1.block.state.create_shard(1).unwrap();
+        b1.push_parcel(parcel(0, mint), None).unwrap();
+        b1.push_parcel(parcel(1, transfer.clone()), None).unwrap();
+
+        let message_address = MessageAddress::new(transfer.hash(), 0, 0);
+        let message = b1.state().message(&message_address).unwrap().unwrap();
+        let proof = b1.state().message_proof(&message_address).unwrap().unwrap();
+        let relay = |nonce| Transaction::AssetRelay {
+            network_id: 0,
+            shard_id: 1,
+            source_shard_id: 0,
+            transaction_hash: transfer.hash(),
+            index: 0,
+            message: message.clone(),
+            proof: proof.clone(),
+            nonce,
+        };
+        let relayed_asset = AssetAddress::new(transfer.hash(), 0, 1);
+
+        // The shard roots of the parent block don't have the message yet.
+        b1.push_parcel(parcel(2, relay(0)), None).unwrap();
+        assert_eq!(None, b1.state().asset(&relayed_asset).unwrap());
+
+        let parcels_root = genesis_header.parcels_root().clone();
+        let invoices_root = genesis_header.invoices_root().clone();
+        let b1 = b1.close_and_lock(parcels_root, invoices_root);
+        let b1_header = b1.header().clone();
+        let db = b1.drain();
+
+        // The mint and the transfer emitted their logs, and the failed relay didn't.
+        let log_bloom = b1_header.log_bloom();
+        assert!(log_bloom.contains_input(BloomInput::Raw(&asset_type)));
+        assert!(log_bloom.contains_input(BloomInput::Raw(&EventKind::AssetTransfer.topic())));
+        assert!(!log_bloom.contains_input(BloomInput::Raw(&EventKind::AssetRelay.topic())));
+
+        let mut b2 =
+            OpenBlock::new(&*spec.engine, Default::default(), db, &b1_header, Address::zero(), vec![], false).unwrap();
+        b2.push_parcel(parcel(3, relay(1)), None).unwrap();
+        assert!(b2.state().asset(&relayed_asset).unwrap().is_some());
+    }
 }
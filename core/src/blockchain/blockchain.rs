@@ -123,6 +123,17 @@ impl BlockChain {
         ImportRoute::new(&hash, &location)
     }
 
+    /// Marks the canonical block as final. The blocks before it are never retracted.
+    pub fn finalize_block(&self, batch: &mut DBTransaction, hash: &H256) {
+        self.headerchain.finalize(batch, hash);
+    }
+
+    /// Removes the invoices of the block with given hash.
+    /// It is used to prune the invoices that are out of the retention period.
+    pub fn remove_invoices(&self, batch: &mut DBTransaction, hash: &H256) {
+        self.invoice_db.remove_invoice(batch, hash);
+    }
+
     /// Apply pending insertion updates
     pub fn commit(&self) {
         self.headerchain.commit();
@@ -147,6 +158,9 @@ impl BlockChain {
             let best_hash = self.best_block_hash();
             let route = tree_route(self, best_hash, parent_hash)
                 .expect("blocks being imported always within recent history; qed");
+            if self.headerchain.retracts_finalized(&route) {
+                return BlockLocation::Branch
+            }
 
             match route.retracted.len() {
                 0 => BlockLocation::CanonChain,
@@ -161,6 +175,7 @@ impl BlockChain {
     pub fn chain_info(&self) -> BlockChainInfo {
         let best_block_header = self.best_block_header();
         let best_block_detail = self.best_block_detail();
+        let finalized_block_detail = self.headerchain.finalized_header_detail();
 
         BlockChainInfo {
             total_score: best_block_detail.total_score.clone(),
@@ -169,6 +184,8 @@ impl BlockChain {
             best_block_hash: best_block_header.hash(),
             best_block_number: best_block_detail.number,
             best_block_timestamp: best_block_header.timestamp(),
+            finalized_block_hash: self.headerchain.finalized_header_hash(),
+            finalized_block_number: finalized_block_detail.number,
         }
     }
 
@@ -177,6 +194,11 @@ impl BlockChain {
         self.best_block_hash.read().clone()
     }
 
+    /// Get the hash of the latest finalized block, which is the genesis block if the engine doesn't finalize blocks.
+    pub fn finalized_block_hash(&self) -> H256 {
+        self.headerchain.finalized_header_hash()
+    }
+
     /// Get best block detail
     pub fn best_block_detail(&self) -> BlockDetails {
         self.block_details(&self.best_block_hash()).expect("Best block always exists")
@@ -434,3 +456,86 @@ impl InvoiceProvider for BlockChain {
 }
 
 impl BlockProvider for BlockChain {}
+
+#[cfg(test)]
+mod tests {
+    use kvdb_memorydb;
+    use rlp;
+
+    use super::super::super::block::Block;
+    use super::super::super::header::Header;
+    use super::*;
+
+    fn block_bytes(header: Header) -> Vec<u8> {
+        rlp::encode(&Block {
+            header,
+            parcels: vec![],
+        }).into_vec()
+    }
+
+    #[test]
+    fn best_block_is_committed_and_persisted() {
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(db::NUM_COLUMNS.unwrap_or(0)));
+        let genesis = Header::new();
+        let mut child = Header::new();
+        child.set_parent_hash(genesis.hash());
+        child.set_number(1);
+        child.set_score(1u64.into());
+        let child_hash = child.hash();
+
+        let chain = BlockChain::new(&block_bytes(genesis.clone()), db.clone());
+        assert_eq!(genesis.hash(), chain.best_block_hash());
+
+        let mut batch = DBTransaction::new();
+        chain.insert_block(&mut batch, &block_bytes(child), vec![]);
+        db.write(batch).unwrap();
+        // The best block changes only after the batch is committed
+        assert_eq!(genesis.hash(), chain.best_block_hash());
+        chain.commit();
+        assert_eq!(child_hash, chain.best_block_hash());
+        assert_eq!(Some(child_hash), chain.block_hash(1));
+        assert!(chain.is_known(&child_hash));
+
+        let reopened = BlockChain::new(&block_bytes(genesis), db);
+        assert_eq!(child_hash, reopened.best_block_hash());
+        assert_eq!(1, reopened.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn branch_retracting_finalized_block_does_not_become_canonical() {
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(db::NUM_COLUMNS.unwrap_or(0)));
+        let genesis = Header::new();
+        let child = |score: u64| {
+            let mut child = Header::new();
+            child.set_parent_hash(genesis.hash());
+            child.set_number(1);
+            child.set_score(score.into());
+            child
+        };
+        let finalized = child(1);
+        let heavier = child(5);
+
+        let chain = BlockChain::new(&block_bytes(genesis.clone()), db.clone());
+        assert_eq!(genesis.hash(), chain.finalized_block_hash());
+
+        let mut batch = DBTransaction::new();
+        chain.insert_block(&mut batch, &block_bytes(finalized.clone()), vec![]);
+        chain.finalize_block(&mut batch, &finalized.hash());
+        db.write(batch).unwrap();
+        chain.commit();
+        assert_eq!(finalized.hash(), chain.finalized_block_hash());
+
+        let mut batch = DBTransaction::new();
+        let route = chain.insert_block(&mut batch, &block_bytes(heavier.clone()), vec![]);
+        db.write(batch).unwrap();
+        chain.commit();
+        assert!(route.enacted.is_empty());
+        assert!(chain.is_known(&heavier.hash()));
+        assert_eq!(finalized.hash(), chain.best_block_hash());
+        assert_eq!(Some(finalized.hash()), chain.block_hash(1));
+
+        let reopened = BlockChain::new(&block_bytes(genesis), db);
+        assert_eq!(finalized.hash(), reopened.finalized_block_hash());
+        assert_eq!(1, reopened.chain_info().finalized_block_number);
+    }
+}
@@ -284,21 +284,33 @@ mod tests {
         let invoices = vec![
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Failed,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
         ];
         let parcel_invoices = ParcelInvoices {
@@ -315,9 +327,13 @@ mod tests {
         let invoices = vec![
             Invoice {
                 outcome: TransactionOutcome::Success,
+                fee: 0.into(),
+                logs: vec![],
             },
             Invoice {
                 outcome: TransactionOutcome::Failed,
+                fee: 0.into(),
+                logs: vec![],
             },
         ];
         let parcel_invoices = ParcelInvoices {
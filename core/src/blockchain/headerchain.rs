@@ -30,9 +30,10 @@ use super::super::types::BlockNumber;
 use super::super::views::HeaderView;
 use super::block_info::BlockLocation;
 use super::extras::BlockDetails;
-use super::route::tree_route;
+use super::route::{tree_route, TreeRoute};
 
 const BEST_HEADER_KEY: &[u8] = b"best-header";
+const FINALIZED_HEADER_KEY: &[u8] = b"finalized-header";
 
 /// Structure providing fast access to blockchain data.
 ///
@@ -40,6 +41,7 @@ const BEST_HEADER_KEY: &[u8] = b"best-header";
 pub struct HeaderChain {
     // All locks must be captured in the order declared here.
     best_header_hash: RwLock<H256>,
+    finalized_header_hash: RwLock<H256>,
 
     // cache
     header_cache: RwLock<HashMap<H256, Bytes>>,
@@ -49,6 +51,7 @@ pub struct HeaderChain {
     db: Arc<KeyValueDB>,
 
     pending_best_header_hash: RwLock<Option<H256>>,
+    pending_finalized_header_hash: RwLock<Option<H256>>,
     pending_hashes: RwLock<HashMap<BlockNumber, H256>>,
     pending_details: RwLock<HashMap<H256, BlockDetails>>,
 }
@@ -83,8 +86,15 @@ impl HeaderChain {
             }
         };
 
+        // The genesis block is final
+        let finalized_header_hash = match db.get(db::COL_EXTRA, FINALIZED_HEADER_KEY).unwrap() {
+            Some(hash) => H256::from_slice(&hash),
+            None => genesis.hash(),
+        };
+
         Self {
             best_header_hash: RwLock::new(best_header_hash),
+            finalized_header_hash: RwLock::new(finalized_header_hash),
 
             header_cache: RwLock::new(HashMap::new()),
             detail_cache: RwLock::new(HashMap::new()),
@@ -93,6 +103,7 @@ impl HeaderChain {
             db,
 
             pending_best_header_hash: RwLock::new(None),
+            pending_finalized_header_hash: RwLock::new(None),
             pending_hashes: RwLock::new(HashMap::new()),
             pending_details: RwLock::new(HashMap::new()),
         }
@@ -135,19 +146,31 @@ impl HeaderChain {
         Some(location)
     }
 
+    /// Marks the canonical header as final, so that the branches which retract it never become canonical.
+    /// The header must be a descendant of the current finalized header.
+    pub fn finalize(&self, batch: &mut DBTransaction, hash: &H256) {
+        batch.put(db::COL_EXTRA, FINALIZED_HEADER_KEY, hash);
+        *self.pending_finalized_header_hash.write() = Some(*hash);
+    }
+
     /// Apply pending insertion updates
     pub fn commit(&self) {
         let mut pending_best_header_hash = self.pending_best_header_hash.write();
+        let mut pending_finalized_header_hash = self.pending_finalized_header_hash.write();
         let mut pending_write_hashes = self.pending_hashes.write();
         let mut pending_block_details = self.pending_details.write();
 
         let mut best_header_hash = self.best_header_hash.write();
+        let mut finalized_header_hash = self.finalized_header_hash.write();
         let mut write_block_details = self.detail_cache.write();
         let mut write_hashes = self.hash_cache.write();
         // update best block
         if let Some(hash) = pending_best_header_hash.take() {
             *best_header_hash = hash;
         }
+        if let Some(hash) = pending_finalized_header_hash.take() {
+            *finalized_header_hash = hash;
+        }
 
         write_hashes.extend(mem::replace(&mut *pending_write_hashes, HashMap::new()));
         write_block_details.extend(mem::replace(&mut *pending_block_details, HashMap::new()));
@@ -214,6 +237,10 @@ impl HeaderChain {
             let best_hash = self.best_header_hash();
             let route = tree_route(self, best_hash, parent_hash)
                 .expect("blocks being imported always within recent history; qed");
+            if self.retracts_finalized(&route) {
+                warn!(target: "blockchain", "The branch of {} retracts the finalized block", header.hash());
+                return BlockLocation::Branch
+            }
 
             match route.retracted.len() {
                 0 => BlockLocation::CanonChain,
@@ -224,6 +251,12 @@ impl HeaderChain {
         }
     }
 
+    /// Whether the route from the best header retracts the finalized header.
+    pub fn retracts_finalized(&self, route: &TreeRoute) -> bool {
+        let ancestor_number = self.block_number(&route.ancestor).expect("Ancestor always exist in DB");
+        ancestor_number < self.finalized_header_detail().number
+    }
+
     /// Get best block hash.
     pub fn best_header_hash(&self) -> H256 {
         self.best_header_hash.read().clone()
@@ -237,6 +270,14 @@ impl HeaderChain {
     pub fn best_header_detail(&self) -> BlockDetails {
         self.block_details(&self.best_header_hash()).expect("Best header always exists")
     }
+
+    pub fn finalized_header_hash(&self) -> H256 {
+        self.finalized_header_hash.read().clone()
+    }
+
+    pub fn finalized_header_detail(&self) -> BlockDetails {
+        self.block_details(&self.finalized_header_hash()).expect("Finalized header always exists")
+    }
 }
 
 /// Interface for querying blocks by hash and by number.
@@ -21,7 +21,7 @@ use ctypes::H256;
 use kvdb::{DBTransaction, KeyValueDB};
 use parking_lot::RwLock;
 
-use super::super::db::{self, CacheUpdatePolicy, Readable, Writable};
+use super::super::db::{self, CacheUpdatePolicy, Key, Readable, Writable};
 use super::extras::{BlockInvoices, ParcelAddress, ParcelInvoices};
 
 /// Structure providing fast access to blockchain data.
@@ -57,6 +57,13 @@ impl InvoiceDB {
         let mut invoice_cache = self.invoice_cache.write();
         batch.extend_with_cache(db::COL_EXTRA, &mut *invoice_cache, invoice_map, CacheUpdatePolicy::Remove);
     }
+
+    /// Removes the invoices of the block with given hash.
+    pub fn remove_invoice(&self, batch: &mut DBTransaction, hash: &H256) {
+        let mut invoice_cache = self.invoice_cache.write();
+        batch.delete(db::COL_EXTRA, &Key::<BlockInvoices>::key(hash));
+        invoice_cache.remove(hash);
+    }
 }
 
 /// Interface for querying invoices.
@@ -33,4 +33,8 @@ pub struct BlockChainInfo {
     pub best_block_number: BlockNumber,
     /// Best blockchain block timestamp.
     pub best_block_timestamp: u64,
+    /// The latest block which can never be retracted.
+    pub finalized_block_hash: H256,
+    /// The number of the latest finalized block.
+    pub finalized_block_number: BlockNumber,
 }
@@ -17,6 +17,8 @@
 use cnetwork::NodeId;
 use ctypes::H256;
 
+use super::super::types::BlockNumber;
+
 /// Represents what has to be handled by actor listening to chain events
 pub trait ChainNotify: Send + Sync {
     /// fires when chain has new headers.
@@ -45,6 +47,11 @@ pub trait ChainNotify: Send + Sync {
         // does nothing by default
     }
 
+    /// fires when the finalized block advances.
+    fn finalized(&self, _hash: H256, _number: BlockNumber) {
+        // does nothing by default
+    }
+
     /// fires when new parcels are received from a peer
     fn parcels_received(&self, _hashes: Vec<H256>, _peer_id: NodeId) {
         // does nothing by default
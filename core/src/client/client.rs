@@ -14,11 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::{Arc, Weak};
 use std::time::Instant;
 
+use ccrypto::blake256;
 use cio::IoChannel;
 use cnetwork::NodeId;
 use ctypes::{Address, Bytes, H256, Public, U256};
@@ -27,6 +29,8 @@ use kvdb::{DBTransaction, KeyValueDB};
 use parking_lot::{Mutex, RwLock};
 use rlp::{Encodable, UntrustedRlp};
 use trie::{TrieFactory, TrieSpec};
+use unexpected::Mismatch;
+use util_error::UtilError;
 
 use super::super::block::{enact, ClosedBlock, Drain, IsBlock, LockedBlock, OpenBlock, SealedBlock};
 use super::super::blockchain::{
@@ -38,14 +42,19 @@ use super::super::consensus::CodeChainEngine;
 use super::super::encoded;
 use super::super::error::{BlockImportError, Error, ImportError};
 use super::super::header::Header;
-use super::super::miner::{Miner, MinerService};
+use super::super::invoice::LocalizedLogEntry;
+use super::super::miner::{Miner, MinerService, ParcelImportResult};
 use super::super::parcel::{LocalizedParcel, SignedParcel, UnverifiedParcel};
 use super::super::service::ClientIoMessage;
+use super::super::snapshot::{
+    self, Error as SnapshotError, ManifestData, SnapshotWriter, StateRebuilder, PREFERRED_CHUNK_SIZE, SNAPSHOT_VERSION,
+};
 use super::super::spec::Spec;
-use super::super::state::State;
+use super::super::state::{State, StateInfo};
 use super::super::state_db::StateDB;
+use super::super::transaction::Transaction;
 use super::super::types::{
-    BlockId, BlockNumber, BlockStatus, ParcelId, TransactionId, VerificationQueueInfo as BlockQueueInfo,
+    BlockId, BlockNumber, BlockStatus, Filter, ParcelId, TransactionId, VerificationQueueInfo as BlockQueueInfo,
 };
 use super::super::verification::queue::{BlockQueue, HeaderQueue};
 use super::super::verification::{self, PreverifiedBlock, Verifier};
@@ -53,8 +62,9 @@ use super::super::views::{BlockView, HeaderView};
 use super::{
     AccountData, Balance, BlockChain as BlockChainTrait, BlockChainClient, BlockChainInfo, BlockInfo, BlockProducer,
     ChainInfo, ChainNotify, ClientConfig, EngineClient, Error as ClientError, ImportBlock, ImportResult,
-    ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock, RegularKey, ReopenBlock,
-    StateOrBlock,
+    ImportSealedBlock, Invoice, InvoiceRetention, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock,
+    RecentBlocks, RegularKey, ReopenBlock, SeenBlocks, SnapshotClient, StateClient, StateOrBlock,
+    TransactionQueueClient,
 };
 
 const MAX_PARCEL_QUEUE_SIZE: usize = 4096;
@@ -79,6 +89,8 @@ pub struct Client {
     trie_factory: TrieFactory,
 
     importer: Importer,
+
+    recent_blocks: Mutex<RecentBlocks>,
 }
 
 impl Client {
@@ -96,7 +108,18 @@ impl Client {
 
         let trie_factory = TrieFactory::new(trie_spec);
 
-        let journal_db = journaldb::new(db.clone(), journaldb::Algorithm::Archive, ::db::COL_STATE);
+        // The pruning algorithm can't be changed, since the journal of an algorithm is unknown to the others.
+        match ::db::pruning(&*db).map_err(ClientError::Database)? {
+            Some(pruning) if pruning != config.pruning => {
+                return Err(ClientError::PruningMismatch(Mismatch {
+                    expected: pruning,
+                    found: config.pruning,
+                }).into())
+            }
+            Some(_) => {}
+            None => ::db::write_pruning(&*db, config.pruning).map_err(ClientError::Database)?,
+        }
+        let journal_db = journaldb::new(db.clone(), config.pruning, ::db::COL_STATE);
         let mut state_db = StateDB::new(journal_db, config.state_cache_size);
         if state_db.journal_db().is_empty() {
             // Sets the correct state root.
@@ -123,6 +146,7 @@ impl Client {
             queue_parcels: AtomicUsize::new(0),
             trie_factory,
             importer,
+            recent_blocks: Mutex::new(RecentBlocks::default()),
         });
 
         // ensure buffered changes are flushed.
@@ -238,11 +262,45 @@ impl Client {
         self.block_header(id).and_then(|header| {
             let db = self.state_db.read().boxed_clone();
 
+            // the state of the blocks older than the journal is already pruned.
+            if db.is_pruned() && db.journal_db().earliest_era().map_or(false, |era| header.number() < era) {
+                return None
+            }
+
             let root = header.state_root();
             State::from_existing(db, root, self.engine.machine().account_start_nonce(), self.trie_factory.clone()).ok()
         })
     }
 
+    /// Writes the chunks of the state and the block at `number`.
+    pub fn take_snapshot(
+        &self,
+        number: BlockNumber,
+        writer: &mut SnapshotWriter,
+    ) -> Result<ManifestData, SnapshotError> {
+        let block = self.block(BlockId::Number(number)).ok_or(SnapshotError::UnknownBlock(number))?;
+        let state_root = block.state_root();
+        let block_hash = block.hash();
+
+        let db = self.state_db.read().boxed_clone();
+        let state_chunks = snapshot::chunk_state(db.as_hashdb(), &state_root, PREFERRED_CHUNK_SIZE, |hash, chunk| {
+            writer.write_chunk(hash, chunk)
+        })?;
+
+        let block = block.into_inner();
+        let block_chunk = blake256(&block);
+        writer.write_chunk(&block_chunk, &block)?;
+
+        Ok(ManifestData {
+            version: SNAPSHOT_VERSION,
+            state_chunks,
+            block_chunk,
+            state_root,
+            block_number: number,
+            block_hash,
+        })
+    }
+
     pub fn database(&self) -> Arc<KeyValueDB> {
         Arc::clone(&self.db.read())
     }
@@ -299,11 +357,15 @@ impl ImportBlock for Client {
         use super::super::verification::queue::kind::blocks::Unverified;
         use super::super::verification::queue::kind::BlockLike;
 
-        let unverified = Unverified::new(bytes);
+        let mut unverified = Unverified::new(bytes);
         {
-            if self.chain.read().is_known(&unverified.hash()) {
+            let chain = self.chain.read();
+            if chain.is_known(&unverified.hash()) {
                 return Err(BlockImportError::Import(ImportError::AlreadyInChain))
             }
+            if verification::is_checkpoint_ancestor(unverified.header(), &*self.engine, &**chain) {
+                unverified = unverified.checkpointed();
+            }
         }
         Ok(self.importer.block_queue.import(unverified)?)
     }
@@ -319,13 +381,23 @@ impl ImportBlock for Client {
     }
 }
 
-impl BlockChainTrait for Client {}
+impl SeenBlocks for Client {
+    fn mark_block_seen(&self, hash: H256) -> bool {
+        self.recent_blocks.lock().mark(hash)
+    }
 
-impl BlockChainClient for Client {
-    fn queue_info(&self) -> BlockQueueInfo {
-        self.importer.block_queue.queue_info()
+    fn is_block_seen(&self, hash: &H256) -> bool {
+        self.recent_blocks.lock().contains(hash)
+    }
+
+    fn unmark_block_seen(&self, hash: &H256) {
+        self.recent_blocks.lock().remove(hash);
     }
+}
+
+impl BlockChainTrait for Client {}
 
+impl TransactionQueueClient for Client {
     fn queue_parcels(&self, parcels: Vec<Bytes>, peer_id: NodeId) {
         let queue_size = self.queue_parcels.load(AtomicOrdering::Relaxed);
         trace!(target: "external_parcel", "Queue size: {}", queue_size);
@@ -344,9 +416,19 @@ impl BlockChainClient for Client {
         }
     }
 
+    fn queue_own_parcel(&self, parcel: SignedParcel) -> Result<ParcelImportResult, Error> {
+        self.importer.miner.import_own_parcel(self, parcel)
+    }
+
     fn ready_parcels(&self) -> Vec<SignedParcel> {
         self.importer.miner.ready_parcels()
     }
+}
+
+impl BlockChainClient for Client {
+    fn queue_info(&self) -> BlockQueueInfo {
+        self.importer.block_queue.queue_info()
+    }
 
     fn block_number(&self, id: BlockId) -> Option<BlockNumber> {
         self.block_number_ref(&id)
@@ -397,6 +479,75 @@ impl BlockChainClient for Client {
                 .and_then(|invoices| invoices.invoices.get(transaction_address.index).cloned())
         })
     }
+
+    fn block_invoices(&self, id: BlockId) -> Option<Vec<Invoice>> {
+        let chain = self.chain.read();
+        Self::block_hash(&chain, id).and_then(|hash| chain.block_invoices(&hash)).map(|block_invoices| {
+            block_invoices.invoices.into_iter().flat_map(|parcel_invoices| parcel_invoices.invoices).collect()
+        })
+    }
+
+    fn transaction(&self, id: TransactionId) -> Option<Transaction> {
+        self.transaction_address(id).and_then(|transaction_address| {
+            let parcel_id = transaction_address.parcel_address.into();
+            self.parcel(parcel_id).and_then(|parcel| parcel.transactions.get(transaction_address.index).cloned())
+        })
+    }
+
+    fn is_invoice_retained(&self, id: BlockId) -> bool {
+        let best_block_number = self.chain.read().best_block_detail().number;
+        match self.block_number_ref(&id) {
+            Some(number) => self.importer.invoice_retention.is_retained(number, best_block_number),
+            None => true,
+        }
+    }
+
+    fn is_transaction_invoice_retained(&self, id: TransactionId) -> bool {
+        match self.transaction_address(id) {
+            Some(address) => self.is_invoice_retained(BlockId::Hash(address.parcel_address.block_hash)),
+            None => true,
+        }
+    }
+
+    fn logs(&self, filter: Filter) -> Vec<LocalizedLogEntry> {
+        let (from, to) = match (self.block_number_ref(&filter.from_block), self.block_number_ref(&filter.to_block)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Vec::new(),
+        };
+        let chain = self.chain.read();
+        let mut logs = Vec::new();
+        for number in from..=to {
+            let block_hash = match chain.block_hash(number) {
+                Some(block_hash) => block_hash,
+                None => break,
+            };
+            match chain.block_header_data(&block_hash) {
+                Some(header) if filter.matches_bloom(&header.log_bloom()) => {}
+                _ => continue,
+            }
+            // The invoices of the old blocks may be pruned by the retention policy.
+            let (body, block_invoices) = match (chain.block_body(&block_hash), chain.block_invoices(&block_hash)) {
+                (Some(body), Some(block_invoices)) => (body, block_invoices),
+                _ => continue,
+            };
+            for (parcel, parcel_invoices) in body.parcels().into_iter().zip(block_invoices.invoices) {
+                let parcel_hash = parcel.hash();
+                for (transaction, invoice) in parcel.as_unsigned().transactions.iter().zip(parcel_invoices.invoices) {
+                    let transaction_hash = transaction.hash();
+                    for entry in invoice.logs.into_iter().filter(|entry| filter.matches(entry)) {
+                        logs.push(LocalizedLogEntry {
+                            entry,
+                            block_hash,
+                            block_number: number,
+                            parcel_hash,
+                            transaction_hash,
+                        });
+                    }
+                }
+            }
+        }
+        logs
+    }
 }
 
 pub struct Importer {
@@ -417,6 +568,15 @@ pub struct Importer {
 
     /// CodeChain engine to be used during import
     pub engine: Arc<CodeChainEngine>,
+
+    /// Decides how long the invoices are kept
+    pub invoice_retention: InvoiceRetention,
+
+    /// The highest canonical block whose invoices are pruned
+    last_pruned_invoices: Mutex<Option<BlockNumber>>,
+
+    /// Number of recent blocks whose state is kept when the state db is pruned
+    pub history: u64,
 }
 
 impl Importer {
@@ -447,13 +607,16 @@ impl Importer {
             header_queue,
             miner,
             engine,
+            invoice_retention: config.invoice_retention,
+            last_pruned_invoices: Mutex::new(None),
+            history: config.history,
         })
     }
 
     /// This is triggered by a message coming from a block queue when the block is ready for insertion
     pub fn import_verified_blocks(&self, client: &Client) -> usize {
         let max_blocks_to_import = 4;
-        let (imported_blocks, import_results, invalid_blocks, imported, duration, is_empty) = {
+        let (imported_blocks, import_results, invalid_blocks, imported, duration, is_empty, finalized) = {
             let mut imported_blocks = Vec::with_capacity(max_blocks_to_import);
             let mut invalid_blocks = HashSet::new();
             let mut import_results = Vec::with_capacity(max_blocks_to_import);
@@ -464,6 +627,7 @@ impl Importer {
                 return 0
             }
             let start = Instant::now();
+            let finalized_before = client.chain.read().finalized_block_hash();
 
             for block in blocks {
                 let header = &block.header;
@@ -491,13 +655,24 @@ impl Importer {
 
             if !invalid_blocks.is_empty() {
                 self.block_queue.mark_as_bad(&invalid_blocks);
+                // The blocks can be fetched again, e.g. from the other peers.
+                let mut recent_blocks = client.recent_blocks.lock();
+                for hash in &invalid_blocks {
+                    recent_blocks.remove(hash);
+                }
             }
             let is_empty = self.block_queue.mark_as_good(&imported_blocks);
             let duration_ns = {
                 let elapsed = start.elapsed();
                 elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
             };
-            (imported_blocks, import_results, invalid_blocks, imported, duration_ns, is_empty)
+            let chain_info = client.chain.read().chain_info();
+            let finalized = if chain_info.finalized_block_hash != finalized_before {
+                Some((chain_info.finalized_block_hash, chain_info.finalized_block_number))
+            } else {
+                None
+            };
+            (imported_blocks, import_results, invalid_blocks, imported, duration_ns, is_empty, finalized)
         };
 
         {
@@ -519,6 +694,9 @@ impl Importer {
                     );
                 });
             }
+            if let Some((hash, number)) = finalized {
+                client.notify(|notify| notify.finalized(hash, number));
+            }
         }
 
         client.db.read().flush().expect("DB flush failed.");
@@ -577,10 +755,25 @@ impl Importer {
         // check epoch end signal
         self.check_epoch_end_signal(&header, &chain, &mut batch);
 
+        // the canonical block which falls out of the history is looked up before the new block changes the route.
+        let ancient = if number >= self.history {
+            let ancient = number - self.history;
+            chain.block_hash(ancient).map(|hash| (ancient, hash))
+        } else {
+            None
+        };
+
         state.journal_under(&mut batch, number, hash).expect("DB commit failed");
+        if let Some((ancient, ancient_hash)) = ancient {
+            state.mark_canonical(&mut batch, ancient, &ancient_hash).expect("DB commit failed");
+        }
         let route = chain.insert_block(&mut batch, block_data, invoices.clone());
+        self.prune_invoices(&chain, &mut batch, header, &route);
 
         let is_canon = route.enacted.last().map_or(false, |h| h == hash);
+        if is_canon && self.engine.is_final(header) {
+            chain.finalize_block(&mut batch, hash);
+        }
         state.sync_cache(&route.enacted, &route.retracted, is_canon);
         // Final commit to the DB
         client.db.read().write_buffered(batch);
@@ -591,6 +784,57 @@ impl Importer {
         route
     }
 
+    // remove the invoices which are out of the retention period.
+    fn prune_invoices(&self, chain: &BlockChain, batch: &mut DBTransaction, header: &Header, route: &ImportRoute) {
+        let n = match self.invoice_retention {
+            InvoiceRetention::KeepAll => return,
+            InvoiceRetention::KeepLast(n) => n,
+            InvoiceRetention::KeepNone => {
+                chain.remove_invoices(batch, &header.hash());
+                return
+            }
+        };
+        let number = header.number();
+        // The blocks off the canonical chain keep their invoices until they become canonical.
+        if number < n || !route.enacted.contains(&header.hash()) {
+            return
+        }
+        let until = number - n;
+        // The enacted blocks are above the fork point, and the canonical blocks below it are unchanged.
+        let fork = number - route.enacted.len() as BlockNumber;
+
+        let mut last_pruned = self.last_pruned_invoices.lock();
+        let from = match *last_pruned {
+            Some(last) => cmp::min(last + 1, fork + 1),
+            None => until,
+        };
+        *last_pruned = Some(until);
+        if from > until {
+            return
+        }
+
+        for height in from..=cmp::min(until, fork) {
+            if let Some(pruned) = chain.block_hash(height) {
+                chain.remove_invoices(batch, &pruned);
+            }
+        }
+        // The canonical hashes above the fork point are not committed yet, so they are found through the parents.
+        let mut hash = header.hash();
+        let mut parent_hash = *header.parent_hash();
+        let mut height = number;
+        while height > fork && height >= from {
+            if height <= until {
+                chain.remove_invoices(batch, &hash);
+            }
+            hash = parent_hash;
+            height -= 1;
+            parent_hash = match chain.block_header(&hash) {
+                Some(parent) => *parent.parent_hash(),
+                None => break,
+            };
+        }
+    }
+
     // check for ending of epoch and write transition if it occurs.
     fn check_epoch_end<'a>(&self, header: &'a Header, chain: &BlockChain, client: &Client) {
         let is_epoch_end = self.engine.is_epoch_end(
@@ -681,7 +925,11 @@ impl Importer {
             return Err(())
         };
 
-        let verify_external_result = self.verifier.verify_block_external(header, engine);
+        let verify_external_result = if verification::is_checkpoint_ancestor(header, engine, &**chain) {
+            Ok(())
+        } else {
+            self.verifier.verify_block_external(header, engine)
+        };
         if let Err(e) = verify_external_result {
             warn!(target: "client", "Stage 4 block verification failed for #{} ({})\nError: {:?}", header.number(), header.hash(), e);
             return Err(())
@@ -823,6 +1071,12 @@ impl RegularKey for Client {
     }
 }
 
+impl StateClient for Client {
+    fn state_info(&self, id: BlockId) -> Option<Box<StateInfo>> {
+        self.state_at(id).map(|state| Box::new(state) as Box<StateInfo>)
+    }
+}
+
 impl ReopenBlock for Client {
     fn reopen_block(&self, block: ClosedBlock) -> OpenBlock {
         let engine = &*self.engine;
@@ -883,3 +1137,100 @@ impl ImportSealedBlock for Client {
 }
 
 impl MiningBlockChainClient for Client {}
+
+impl SnapshotClient for Client {
+    fn state_rebuilder(&self) -> StateRebuilder {
+        StateRebuilder::new(self.db.read().clone(), ::db::COL_STATE)
+    }
+
+    fn restore_snapshot(
+        &self,
+        manifest: &ManifestData,
+        rebuilder: StateRebuilder,
+        block: &[u8],
+    ) -> Result<(), SnapshotError> {
+        let header = UntrustedRlp::new(block).val_at::<Header>(0)?;
+        if header.hash() != manifest.block_hash {
+            return Err(SnapshotError::BlockMismatch(Mismatch {
+                expected: manifest.block_hash,
+                found: header.hash(),
+            }))
+        }
+        if *header.state_root() != manifest.state_root {
+            return Err(SnapshotError::StateRootMismatch(Mismatch {
+                expected: manifest.state_root,
+                found: *header.state_root(),
+            }))
+        }
+
+        let route = {
+            let _import_lock = self.importer.import_lock.lock();
+            let chain = self.chain.read();
+            if chain.best_block_detail().number != 0 {
+                return Err(SnapshotError::ChainNotEmpty)
+            }
+            if chain.block_header(header.parent_hash()).is_none() {
+                return Err(SnapshotError::UnknownBlock(header.number().saturating_sub(1)))
+            }
+
+            let mut batch = DBTransaction::new();
+            rebuilder.finalize(&mut batch, manifest)?;
+            let mut state_db = self.state_db.write();
+            state_db.journal_under(&mut batch, header.number(), &manifest.block_hash)?;
+            let route = chain.insert_block(&mut batch, block, Vec::new());
+            state_db.sync_cache(&route.enacted, &route.retracted, true);
+            self.db.read().write_buffered(batch);
+            chain.commit();
+            route
+        };
+        self.db.read().flush().map_err(UtilError::from)?;
+        info!(target: "client", "Restored the snapshot at #{} ({})", header.number(), manifest.block_hash);
+
+        let hash = manifest.block_hash;
+        self.notify(|notify| {
+            notify.new_blocks(vec![hash], vec![], route.enacted.clone(), route.retracted.clone(), vec![], 0);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cio::IoChannel;
+    use journaldb::Algorithm;
+    use kvdb::KeyValueDB;
+    use kvdb_memorydb;
+
+    use super::super::super::error::Error;
+    use super::super::super::miner::Miner;
+    use super::super::super::spec::Spec;
+    use super::super::{ClientConfig, Error as ClientError};
+    use super::Client;
+
+    fn open(db: &Arc<KeyValueDB>, pruning: Algorithm) -> Result<Arc<Client>, Error> {
+        let spec = Spec::new_test();
+        let miner = Arc::new(Miner::with_spec(&spec));
+        let config = ClientConfig {
+            pruning,
+            ..Default::default()
+        };
+        Client::new(config, &spec, db.clone(), miner, IoChannel::disconnected())
+    }
+
+    #[test]
+    fn database_is_reopened_only_with_its_pruning_algorithm() {
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(::db::NUM_COLUMNS.unwrap_or(0)));
+        assert!(open(&db, Algorithm::OverlayRecent).is_ok());
+        assert!(open(&db, Algorithm::OverlayRecent).is_ok());
+        match open(&db, Algorithm::Archive) {
+            Err(Error::Client(ClientError::PruningMismatch(mismatch))) => {
+                assert_eq!(Algorithm::OverlayRecent, mismatch.expected);
+                assert_eq!(Algorithm::Archive, mismatch.found);
+            }
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => panic!("The database is opened with another pruning algorithm"),
+        }
+    }
+}
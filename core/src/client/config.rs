@@ -14,11 +14,15 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "kvdb-rocksdb")]
 use std::path::Path;
 use std::str::FromStr;
 
+use journaldb;
+#[cfg(feature = "kvdb-rocksdb")]
 use kvdb_rocksdb::CompactionProfile;
 
+use super::super::types::BlockNumber;
 use super::super::verification::{QueueConfig, VerifierType};
 
 /// Client state db compaction profile
@@ -38,6 +42,7 @@ impl Default for DatabaseCompactionProfile {
     }
 }
 
+#[cfg(feature = "kvdb-rocksdb")]
 impl DatabaseCompactionProfile {
     /// Returns corresponding compaction profile.
     pub fn compaction_profile(&self, db_path: &Path) -> CompactionProfile {
@@ -62,6 +67,78 @@ impl FromStr for DatabaseCompactionProfile {
     }
 }
 
+/// The key-value database engine which stores the chain
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DatabaseBackend {
+    /// RocksDB at the client path
+    RocksDB,
+    /// Keeps everything in memory. The chain is lost when the client stops.
+    Memory,
+}
+
+impl Default for DatabaseBackend {
+    fn default() -> Self {
+        DatabaseBackend::RocksDB
+    }
+}
+
+impl FromStr for DatabaseBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rocksdb" => Ok(DatabaseBackend::RocksDB),
+            "memory" => Ok(DatabaseBackend::Memory),
+            _ => Err("Invalid database backend given. Expected rocksdb/memory.".into()),
+        }
+    }
+}
+
+/// How long the invoices of imported blocks are kept in the database
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InvoiceRetention {
+    /// Keep the invoices of every block
+    KeepAll,
+    /// Keep the invoices of the last N canonical blocks only
+    KeepLast(u64),
+    /// Do not keep any invoice
+    KeepNone,
+}
+
+impl Default for InvoiceRetention {
+    fn default() -> Self {
+        InvoiceRetention::KeepAll
+    }
+}
+
+impl InvoiceRetention {
+    /// Returns true if the invoices of the block `number` are kept when the best block is `best_number`.
+    pub fn is_retained(&self, number: BlockNumber, best_number: BlockNumber) -> bool {
+        match self {
+            InvoiceRetention::KeepAll => true,
+            InvoiceRetention::KeepLast(n) => number + n > best_number,
+            InvoiceRetention::KeepNone => false,
+        }
+    }
+}
+
+impl FromStr for InvoiceRetention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(InvoiceRetention::KeepAll),
+            "none" => Ok(InvoiceRetention::KeepNone),
+            n => match n.parse() {
+                Ok(0) | Err(_) => {
+                    Err("Invalid invoice retention given. Expected all/none or a positive number of blocks.".into())
+                }
+                Ok(n) => Ok(InvoiceRetention::KeepLast(n)),
+            },
+        }
+    }
+}
+
 /// Client configuration. Includes configs for all sub-systems.
 #[derive(Debug, PartialEq)]
 pub struct ClientConfig {
@@ -69,6 +146,8 @@ pub struct ClientConfig {
     pub queue: QueueConfig,
     /// Fat DB enabled?
     pub fat_db: bool,
+    /// Database engine
+    pub db_backend: DatabaseBackend,
     /// RocksDB column cache-size if not default
     pub db_cache_size: Option<usize>,
     /// State db compaction profile
@@ -79,6 +158,12 @@ pub struct ClientConfig {
     pub state_cache_size: usize,
     /// Type of block verifier used by client.
     pub verifier_type: VerifierType,
+    /// Invoice retention policy.
+    pub invoice_retention: InvoiceRetention,
+    /// State db pruning algorithm; archive keeps the state of every block.
+    pub pruning: journaldb::Algorithm,
+    /// Number of recent blocks whose state is kept when the state db is pruned.
+    pub history: u64,
 }
 
 impl Default for ClientConfig {
@@ -88,11 +173,44 @@ impl Default for ClientConfig {
         Self {
             queue: Default::default(),
             fat_db: false,
+            db_backend: Default::default(),
             db_cache_size: Default::default(),
             db_compaction: Default::default(),
             db_wal: true,
             state_cache_size: DEFAULT_STATE_CACHE_SIZE as usize * mb,
             verifier_type: Default::default(),
+            invoice_retention: Default::default(),
+            pruning: Default::default(),
+            history: 64,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_database_backend() {
+        assert_eq!(Ok(DatabaseBackend::RocksDB), "rocksdb".parse());
+        assert_eq!(Ok(DatabaseBackend::Memory), "memory".parse());
+        assert!("leveldb".parse::<DatabaseBackend>().is_err());
+    }
+
+    #[test]
+    fn parse_invoice_retention() {
+        assert_eq!(Ok(InvoiceRetention::KeepAll), "all".parse());
+        assert_eq!(Ok(InvoiceRetention::KeepNone), "none".parse());
+        assert_eq!(Ok(InvoiceRetention::KeepLast(1000)), "1000".parse());
+        assert!("recent".parse::<InvoiceRetention>().is_err());
+        assert!("0".parse::<InvoiceRetention>().is_err());
+    }
+
+    #[test]
+    fn keep_last_retains_only_recent_blocks() {
+        let retention = InvoiceRetention::KeepLast(10);
+        assert!(retention.is_retained(100, 100));
+        assert!(retention.is_retained(91, 100));
+        assert!(!retention.is_retained(90, 100));
+    }
+}
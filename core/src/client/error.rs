@@ -14,8 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use ctypes::H256;
+use journaldb::Algorithm;
 use kvdb;
 use std::fmt::{Display, Error as FmtError, Formatter};
+use unexpected::Mismatch;
 use util_error::UtilError;
 
 /// Client configuration errors.
@@ -23,6 +26,12 @@ use util_error::UtilError;
 pub enum Error {
     /// Database error
     Database(kvdb::Error),
+    /// The database is written by a build with another layout
+    DatabaseVersion(Mismatch<u32>),
+    /// The database belongs to another chain
+    GenesisMismatch(Mismatch<H256>),
+    /// The state in the database is pruned with another algorithm
+    PruningMismatch(Mismatch<Algorithm>),
     /// Util error
     Util(UtilError),
 }
@@ -47,6 +56,9 @@ impl Display for Error {
         match self {
             Error::Util(err) => write!(f, "{}", err),
             Error::Database(s) => write!(f, "Database error: {}", s),
+            Error::DatabaseVersion(mis) => write!(f, "Incompatible database version: {}", mis),
+            Error::GenesisMismatch(mis) => write!(f, "The database is for another genesis block: {}", mis),
+            Error::PruningMismatch(mis) => write!(f, "The database is pruned with another algorithm: {}", mis),
         }
     }
 }
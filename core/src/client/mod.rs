@@ -18,13 +18,15 @@ mod chain_notify;
 mod client;
 mod config;
 mod error;
+mod recent_blocks;
 mod test_client;
 
 pub use self::chain_notify::ChainNotify;
 
 pub use self::client::Client;
-pub use self::config::ClientConfig;
+pub use self::config::{ClientConfig, DatabaseBackend, InvoiceRetention};
 pub use self::error::Error;
+pub use self::recent_blocks::RecentBlocks;
 pub use self::test_client::TestBlockChainClient;
 
 use cnetwork::NodeId;
@@ -34,13 +36,16 @@ use super::block::{ClosedBlock, OpenBlock, SealedBlock};
 use super::blockchain::ParcelInvoices;
 use super::blockchain_info::BlockChainInfo;
 use super::encoded;
-use super::error::BlockImportError;
+use super::error::{BlockImportError, Error as CoreError};
+use super::miner::ParcelImportResult;
 use super::parcel::{LocalizedParcel, SignedParcel};
+use super::snapshot::{Error as SnapshotError, ManifestData, StateRebuilder};
 use super::state::StateInfo;
+use super::transaction::Transaction;
 use super::types::{
-    BlockId, BlockNumber, BlockStatus, ParcelId, TransactionId, VerificationQueueInfo as BlockQueueInfo,
+    BlockId, BlockNumber, BlockStatus, Filter, ParcelId, TransactionId, VerificationQueueInfo as BlockQueueInfo,
 };
-use super::Invoice;
+use super::{Invoice, LocalizedLogEntry};
 
 /// Provides `chain_info` method
 pub trait ChainInfo {
@@ -66,8 +71,20 @@ pub trait ParcelInfo {
     fn parcel_block(&self, id: ParcelId) -> Option<H256>;
 }
 
+/// Keeps the blocks announced recently.
+/// The network extensions share it so that a block is not downloaded twice.
+pub trait SeenBlocks {
+    /// Marks the block as seen. Returns false if it was seen already.
+    fn mark_block_seen(&self, hash: H256) -> bool;
+
+    fn is_block_seen(&self, hash: &H256) -> bool;
+
+    /// Forgets the block, e.g. because its body was rejected and has to be downloaded again.
+    fn unmark_block_seen(&self, hash: &H256);
+}
+
 /// Client facilities used by internally sealing Engines.
-pub trait EngineClient: Sync + Send + ChainInfo + ImportBlock {
+pub trait EngineClient: Sync + Send + ChainInfo + ImportBlock + SeenBlocks {
     /// Make a new block and seal it.
     fn update_sealing(&self);
 
@@ -141,6 +158,26 @@ pub trait RegularKey {
 /// Provides methods to access account info
 pub trait AccountData: Nonce + Balance {}
 
+/// Provides the state of the chain.
+/// The RPC modules query the accounts and the assets through it.
+pub trait StateClient: Sync + Send + AccountData + RegularKey {
+    /// Get the state at the given block.
+    /// Returns None if the block is unknown or its state is pruned.
+    fn state_info(&self, id: BlockId) -> Option<Box<StateInfo>>;
+}
+
+/// Provides the parcels which wait for the next block.
+pub trait TransactionQueueClient: Sync + Send {
+    /// Queue parcels for importing.
+    fn queue_parcels(&self, parcels: Vec<Bytes>, peer_id: NodeId);
+
+    /// Import the parcel signed by the owner of this node.
+    fn queue_own_parcel(&self, parcel: SignedParcel) -> Result<ParcelImportResult, CoreError>;
+
+    /// List all parcels that are allowed into the next block.
+    fn ready_parcels(&self) -> Vec<SignedParcel>;
+}
+
 /// Provides methods to import block into blockchain
 pub trait ImportBlock {
     /// Import a block into the blockchain.
@@ -154,16 +191,12 @@ pub trait ImportBlock {
 pub trait BlockChain: ChainInfo + BlockInfo + ParcelInfo {}
 
 /// Blockchain database client. Owns and manages a blockchain and a block queue.
-pub trait BlockChainClient: Sync + Send + AccountData + BlockChain + ImportBlock {
+/// The extensions and the RPC modules depend on it, `StateClient` and `TransactionQueueClient` instead of `Client`,
+/// so that they can be tested with `TestBlockChainClient`.
+pub trait BlockChainClient: Sync + Send + BlockChain + ImportBlock + SeenBlocks {
     /// Get block queue information.
     fn queue_info(&self) -> BlockQueueInfo;
 
-    /// Queue parcels for importing.
-    fn queue_parcels(&self, parcels: Vec<Bytes>, peer_id: NodeId);
-
-    /// List all parcels that are allowed into the next block.
-    fn ready_parcels(&self) -> Vec<SignedParcel>;
-
     /// Look up the block number for the given block ID.
     fn block_number(&self, id: BlockId) -> Option<BlockNumber>;
 
@@ -187,6 +220,22 @@ pub trait BlockChainClient: Sync + Send + AccountData + BlockChain + ImportBlock
     fn parcel_invoices(&self, id: ParcelId) -> Option<ParcelInvoices>;
 
     fn transaction_invoice(&self, id: TransactionId) -> Option<Invoice>;
+
+    /// Get the invoices of all the transactions in the block, in the order of the invoices root.
+    fn block_invoices(&self, id: BlockId) -> Option<Vec<Invoice>>;
+
+    /// Get transaction with given hash.
+    fn transaction(&self, id: TransactionId) -> Option<Transaction>;
+
+    /// Returns false if the invoices of the given block are pruned by the retention policy.
+    fn is_invoice_retained(&self, id: BlockId) -> bool;
+
+    /// Returns false if the invoice of the given transaction is pruned by the retention policy.
+    fn is_transaction_invoice_retained(&self, id: TransactionId) -> bool;
+
+    /// Get the logs matching the filter, in the order of the blocks. The blocks whose invoices are pruned are
+    /// skipped.
+    fn logs(&self, filter: Filter) -> Vec<LocalizedLogEntry>;
 }
 
 /// Result of import block operation.
@@ -214,4 +263,19 @@ pub trait PrepareOpenBlock {
 pub trait BlockProducer: PrepareOpenBlock + ReopenBlock {}
 
 /// Extended client interface used for mining
-pub trait MiningBlockChainClient: BlockChainClient + BlockProducer + ImportSealedBlock {}
+pub trait MiningBlockChainClient: BlockChainClient + AccountData + BlockProducer + ImportSealedBlock {}
+
+/// Provides methods to restore the chain from a state snapshot
+pub trait SnapshotClient: BlockChainClient {
+    /// Returns the rebuilder which restores the state into the state db.
+    fn state_rebuilder(&self) -> StateRebuilder;
+
+    /// Puts the block of the snapshot on the genesis block with the rebuilt state.
+    /// The headers up to the block must be imported already.
+    fn restore_snapshot(
+        &self,
+        manifest: &ManifestData,
+        rebuilder: StateRebuilder,
+        block: &[u8],
+    ) -> Result<(), SnapshotError>;
+}
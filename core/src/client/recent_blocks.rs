@@ -0,0 +1,115 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::time::{Duration, Instant};
+
+use ctypes::H256;
+use lru_cache::LruCache;
+
+const DEFAULT_CAPACITY: usize = 1024;
+const DEFAULT_LIFETIME_SECONDS: u64 = 30;
+
+/// The blocks which were announced or requested recently.
+/// An entry expires after a while, so a block whose import failed can be fetched again.
+pub struct RecentBlocks {
+    seen: LruCache<H256, Instant>,
+    lifetime: Duration,
+}
+
+impl Default for RecentBlocks {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, Duration::from_secs(DEFAULT_LIFETIME_SECONDS))
+    }
+}
+
+impl RecentBlocks {
+    pub fn new(capacity: usize, lifetime: Duration) -> Self {
+        Self {
+            seen: LruCache::new(capacity),
+            lifetime,
+        }
+    }
+
+    /// Returns false if the block was seen already.
+    pub fn mark(&mut self, hash: H256) -> bool {
+        if self.contains(&hash) {
+            return false
+        }
+        self.seen.insert(hash, Instant::now());
+        true
+    }
+
+    pub fn contains(&mut self, hash: &H256) -> bool {
+        let lifetime = self.lifetime;
+        let is_expired = match self.seen.get_mut(hash) {
+            Some(seen_at) => seen_at.elapsed() >= lifetime,
+            None => return false,
+        };
+        if is_expired {
+            self.seen.remove(hash);
+        }
+        !is_expired
+    }
+
+    pub fn remove(&mut self, hash: &H256) {
+        self.seen.remove(hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_is_marked_only_once() {
+        let mut recent = RecentBlocks::default();
+        let hash = H256::random();
+        assert!(!recent.contains(&hash));
+        assert!(recent.mark(hash));
+        assert!(recent.contains(&hash));
+        assert!(!recent.mark(hash));
+    }
+
+    #[test]
+    fn expired_block_can_be_marked_again() {
+        let mut recent = RecentBlocks::new(DEFAULT_CAPACITY, Duration::from_secs(0));
+        let hash = H256::random();
+        assert!(recent.mark(hash));
+        assert!(!recent.contains(&hash));
+        assert!(recent.mark(hash));
+    }
+
+    #[test]
+    fn removed_block_can_be_marked_again() {
+        let mut recent = RecentBlocks::default();
+        let hash = H256::random();
+        assert!(recent.mark(hash));
+        recent.remove(&hash);
+        assert!(!recent.contains(&hash));
+        assert!(recent.mark(hash));
+    }
+
+    #[test]
+    fn the_least_recent_block_is_forgotten() {
+        let mut recent = RecentBlocks::new(2, Duration::from_secs(DEFAULT_LIFETIME_SECONDS));
+        let first = H256::random();
+        recent.mark(first);
+        recent.mark(H256::random());
+        recent.mark(H256::random());
+        assert!(!recent.contains(&first));
+    }
+}
@@ -41,7 +41,7 @@ use cnetwork::NodeId;
 use ctypes::{Address, Bytes, H256, U256};
 use journaldb;
 use kvdb_memorydb;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use rlp::*;
 use trie;
 
@@ -51,18 +51,23 @@ use super::super::blockchain_info::BlockChainInfo;
 use super::super::client::ImportResult;
 use super::super::client::{
     AccountData, Balance, BlockChain, BlockChainClient, BlockInfo, BlockProducer, BlockStatus, ChainInfo, ImportBlock,
-    ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock, ReopenBlock, StateOrBlock,
+    ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock, RecentBlocks, RegularKey,
+    ReopenBlock, SeenBlocks, StateClient, StateOrBlock, TransactionQueueClient,
 };
 use super::super::db::{COL_STATE, NUM_COLUMNS};
 use super::super::encoded;
-use super::super::error::BlockImportError;
+use super::super::error::{BlockImportError, Error};
 use super::super::header::Header as BlockHeader;
+use super::super::invoice::LocalizedLogEntry;
 use super::super::miner::{Miner, MinerService, ParcelImportResult};
 use super::super::parcel::{LocalizedParcel, Parcel, SignedParcel};
 use super::super::spec::Spec;
-use super::super::state::{Asset, AssetAddress, AssetScheme, AssetSchemeAddress, StateInfo};
+use super::super::state::{
+    Asset, AssetAddress, AssetScheme, AssetSchemeAddress, CrossShardMessage, MessageAddress, StateInfo,
+};
 use super::super::state_db::StateDB;
-use super::super::types::{BlockId, BlockNumber, ParcelId, TransactionId, VerificationQueueInfo as QueueInfo};
+use super::super::transaction::Transaction;
+use super::super::types::{BlockId, BlockNumber, Filter, ParcelId, TransactionId, VerificationQueueInfo as QueueInfo};
 
 /// Test client.
 pub struct TestBlockChainClient {
@@ -96,6 +101,8 @@ pub struct TestBlockChainClient {
     pub latest_block_timestamp: RwLock<u64>,
     /// Pruning history size to report.
     pub history: RwLock<Option<u64>>,
+    /// Blocks announced recently.
+    pub recent_blocks: Mutex<RecentBlocks>,
 }
 
 impl Default for TestBlockChainClient {
@@ -145,6 +152,7 @@ impl TestBlockChainClient {
             spec,
             latest_block_timestamp: RwLock::new(10_000_000),
             history: RwLock::new(None),
+            recent_blocks: Mutex::new(RecentBlocks::default()),
         };
 
         // insert genesis hash.
@@ -345,6 +353,12 @@ impl Balance for TestBlockChainClient {
 
 impl AccountData for TestBlockChainClient {}
 
+impl RegularKey for TestBlockChainClient {
+    fn regular_key(&self, _address: &Address, _state: StateOrBlock) -> Option<Public> {
+        None
+    }
+}
+
 impl ChainInfo for TestBlockChainClient {
     fn chain_info(&self) -> BlockChainInfo {
         let number = self.blocks.read().len() as BlockNumber - 1;
@@ -355,6 +369,8 @@ impl ChainInfo for TestBlockChainClient {
             best_block_hash: self.last_hash.read().clone(),
             best_block_number: number,
             best_block_timestamp: number,
+            finalized_block_hash: self.genesis_hash.clone(),
+            finalized_block_number: 0,
         }
     }
 }
@@ -442,12 +458,35 @@ impl StateInfo for () {
     fn regular_key(&self, _address: &Address) -> trie::Result<Option<Public>> {
         unimplemented!()
     }
+    fn account_proof(&self, _address: &Address) -> trie::Result<Vec<Bytes>> {
+        unimplemented!()
+    }
     fn asset_scheme(&self, _a: &AssetSchemeAddress) -> trie::Result<Option<AssetScheme>> {
         unimplemented!()
     }
     fn asset(&self, _a: &AssetAddress) -> trie::Result<Option<Asset>> {
         unimplemented!()
     }
+    fn message(&self, _a: &MessageAddress) -> trie::Result<Option<CrossShardMessage>> {
+        unimplemented!()
+    }
+    fn message_proof(&self, _a: &MessageAddress) -> trie::Result<Option<Vec<Bytes>>> {
+        unimplemented!()
+    }
+}
+
+impl SeenBlocks for TestBlockChainClient {
+    fn mark_block_seen(&self, hash: H256) -> bool {
+        self.recent_blocks.lock().mark(hash)
+    }
+
+    fn is_block_seen(&self, hash: &H256) -> bool {
+        self.recent_blocks.lock().contains(hash)
+    }
+
+    fn unmark_block_seen(&self, hash: &H256) {
+        self.recent_blocks.lock().remove(hash);
+    }
 }
 
 impl BlockChainClient for TestBlockChainClient {
@@ -493,16 +532,6 @@ impl BlockChainClient for TestBlockChainClient {
         }
     }
 
-    fn queue_parcels(&self, parcels: Vec<Bytes>, _peer_id: NodeId) {
-        // import right here
-        let parcels = parcels.into_iter().filter_map(|bytes| UntrustedRlp::new(&bytes).as_val().ok()).collect();
-        self.miner.import_external_parcels(self, parcels);
-    }
-
-    fn ready_parcels(&self) -> Vec<SignedParcel> {
-        self.miner.ready_parcels()
-    }
-
     fn parcel(&self, _id: ParcelId) -> Option<LocalizedParcel> {
         unimplemented!();
     }
@@ -514,6 +543,48 @@ impl BlockChainClient for TestBlockChainClient {
     fn transaction_invoice(&self, _id: TransactionId) -> Option<Invoice> {
         unimplemented!()
     }
+
+    fn block_invoices(&self, _id: BlockId) -> Option<Vec<Invoice>> {
+        unimplemented!()
+    }
+
+    fn transaction(&self, _id: TransactionId) -> Option<Transaction> {
+        unimplemented!()
+    }
+
+    fn is_invoice_retained(&self, _id: BlockId) -> bool {
+        true
+    }
+
+    fn is_transaction_invoice_retained(&self, _id: TransactionId) -> bool {
+        true
+    }
+
+    fn logs(&self, _filter: Filter) -> Vec<LocalizedLogEntry> {
+        unimplemented!()
+    }
+}
+
+impl StateClient for TestBlockChainClient {
+    fn state_info(&self, _id: BlockId) -> Option<Box<StateInfo>> {
+        None
+    }
+}
+
+impl TransactionQueueClient for TestBlockChainClient {
+    fn queue_parcels(&self, parcels: Vec<Bytes>, _peer_id: NodeId) {
+        // import right here
+        let parcels = parcels.into_iter().filter_map(|bytes| UntrustedRlp::new(&bytes).as_val().ok()).collect();
+        self.miner.import_external_parcels(self, parcels);
+    }
+
+    fn queue_own_parcel(&self, parcel: SignedParcel) -> Result<ParcelImportResult, Error> {
+        self.miner.import_own_parcel(self, parcel)
+    }
+
+    fn ready_parcels(&self) -> Vec<SignedParcel> {
+        self.miner.ready_parcels()
+    }
 }
 
 impl super::EngineClient for TestBlockChainClient {
@@ -196,6 +196,12 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
         false
     }
 
+    /// Whether the block is committed so that it and its ancestors can never be retracted.
+    /// Only the BFT engines finalize blocks. Takes a header of a fully verified block.
+    fn is_final(&self, _verified_header: &M::Header) -> bool {
+        false
+    }
+
     /// Broadcast a block proposal.
     fn broadcast_proposal_block(&self, _block: SealedBlock) {}
 
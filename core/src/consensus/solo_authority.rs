@@ -92,7 +92,7 @@ fn verify_external(header: &Header, validators: &ValidatorSet) -> Result<(), Err
         return Err(EngineError::NotAuthorized(header.author().clone()).into())
     }
 
-    match validators.contains(header.parent_hash(), &signer) {
+    match validators.contains(header.parent_hash(), header.number() - 1, &signer) {
         false => Err(BlockError::InvalidSeal.into()),
         true => Ok(()),
     }
@@ -120,7 +120,7 @@ impl ConsensusEngine<CodeChainMachine> for SoloAuthority {
     fn generate_seal(&self, block: &ExecutedBlock, _parent: &Header) -> Seal {
         let header = block.header();
         let author = header.author();
-        if self.validators.contains(header.parent_hash(), author) {
+        if self.validators.contains(header.parent_hash(), header.number() - 1, author) {
             // account should be permanently unlocked, otherwise sealing will fail
             if let Ok(signature) = self.sign(header.bare_hash()) {
                 return Seal::Regular(vec![::rlp::encode(&(&H520::from(signature) as &[u8])).into_vec()])
@@ -37,7 +37,7 @@ use self::message::*;
 pub use self::params::{TendermintParams, TendermintTimeouts};
 use super::super::account_provider::AccountProvider;
 use super::super::block::*;
-use super::super::client::EngineClient;
+use super::super::client::{EngineClient, SeenBlocks};
 use super::super::codechain_machine::CodeChainMachine;
 use super::super::error::{BlockError, Error};
 use super::super::header::Header;
@@ -166,7 +166,7 @@ impl Tendermint {
     fn view_proposer(&self, bh: &H256, height: Height, view: View) -> Address {
         let proposer_nonce = height + view;
         trace!(target: "engine", "Proposer nonce: {}", proposer_nonce);
-        self.validators.get(bh, proposer_nonce)
+        self.validators.get(bh, (height - 1) as BlockNumber, proposer_nonce)
     }
 
     /// Check if address is a proposer for given view.
@@ -197,12 +197,19 @@ impl Tendermint {
         message.vote_step.is_view(self.height.load(AtomicOrdering::SeqCst), self.view.load(AtomicOrdering::SeqCst))
     }
 
-    fn is_authority(&self, address: &Address) -> bool {
-        self.validators.contains(&*self.proposal_parent.read(), address)
+    /// Check if address is a validator at the given height.
+    fn is_authority(&self, height: Height, address: &Address) -> bool {
+        height > 0 && self.validators.contains(&*self.proposal_parent.read(), (height - 1) as BlockNumber, address)
     }
 
     fn check_above_threshold(&self, n: usize) -> Result<(), EngineError> {
-        let threshold = self.validators.count(&*self.proposal_parent.read()) * 2 / 3;
+        let parent_number = (self.height.load(AtomicOrdering::SeqCst) - 1) as BlockNumber;
+        self.check_above_threshold_of(&*self.proposal_parent.read(), parent_number, n)
+    }
+
+    // The number of the validators changes at the transitions of the validator set.
+    fn check_above_threshold_of(&self, parent: &H256, parent_number: BlockNumber, n: usize) -> Result<(), EngineError> {
+        let threshold = self.validators.count(parent, parent_number) * 2 / 3;
         if n > threshold {
             Ok(())
         } else {
@@ -493,7 +500,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
     fn verify_block_external(&self, header: &Header) -> Result<(), Error> {
         if let Ok(proposal) = ConsensusMessage::new_proposal(header) {
             let proposer = proposal.verify()?;
-            if !self.is_authority(&proposer) {
+            if !self.validators.contains(header.parent_hash(), header.number() - 1, &proposer) {
                 return Err(EngineError::NotAuthorized(proposer).into())
             }
             self.check_view_proposer(
@@ -520,7 +527,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
                     Some(a) => a,
                     None => public_to_address(&recover_ecdsa(&precommit.signature.into(), &precommit_hash)?),
                 };
-                if !self.validators.contains(header.parent_hash(), &address) {
+                if !self.validators.contains(header.parent_hash(), header.number() - 1, &address) {
                     return Err(EngineError::NotAuthorized(address.to_owned()).into())
                 }
 
@@ -530,7 +537,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
                 }
             }
 
-            self.check_above_threshold(origins.len()).map_err(Into::into)
+            self.check_above_threshold_of(header.parent_hash(), header.number() - 1, origins.len()).map_err(Into::into)
         }
     }
 
@@ -562,7 +569,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
             let msg_hash = blake256(rlp.at(1).map_err(fmt_err)?.as_raw());
             let sender = public_to_address(&recover_ecdsa(&message.signature.into(), &msg_hash).map_err(fmt_err)?);
 
-            if !self.is_authority(&sender) {
+            if !self.is_authority(message.vote_step.height, &sender) {
                 return Err(EngineError::NotAuthorized(sender))
             }
             self.broadcast_message(rlp.as_raw().to_vec());
@@ -709,6 +716,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
             // New Commit received, skip to next height.
             trace!(target: "engine", "Received a commit: {:?}.", header.number());
             self.to_next_height(header.number() as usize);
+            *self.proposal_parent.write() = header.hash();
             self.to_step(Step::Commit);
             return false
         }
@@ -724,6 +732,11 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
         true
     }
 
+    fn is_final(&self, header: &Header) -> bool {
+        // The precommits in the seal are verified to be over the threshold.
+        UntrustedRlp::new(&header.seal()[2]).item_count().map_or(false, |count| count > 0)
+    }
+
     fn broadcast_proposal_block(&self, block: SealedBlock) {
         self.extension.broadcast_proposal_block(block.rlp_bytes());
     }
@@ -753,7 +766,7 @@ where
             let signature: H520 = rlp.as_val()?;
             let address = (self.recover)(&signature.into(), &message)?;
 
-            if !self.subchain_validators.contains(header.parent_hash(), &address) {
+            if !self.subchain_validators.contains(header.parent_hash(), header.number() - 1, &address) {
                 return Err(EngineError::NotAuthorized(address.to_owned()).into())
             }
             addresses.insert(address);
@@ -909,7 +922,19 @@ impl NetworkExtension for TendermintExtension {
             Ok(TendermintMessage::ProposalBlock(bytes)) => {
                 if let Some(ref weak) = *self.client.read() {
                     if let Some(c) = weak.upgrade() {
+                        let hash = match UntrustedRlp::new(&bytes).at(0) {
+                            Ok(header) => blake256(header.as_raw()),
+                            Err(e) => {
+                                info!(target: "engine", "Invalid proposal block from peer {}: {:?}", token, e);
+                                return
+                            }
+                        };
+                        if !c.mark_block_seen(hash) {
+                            trace!(target: "engine", "Proposal block {} is already being imported", hash);
+                            return
+                        }
                         if let Err(e) = c.import_block(bytes) {
+                            c.unmark_block_seen(&hash);
                             info!(target: "engine", "Failed to import proposal block {:?}", e);
                         }
                     }
@@ -18,7 +18,7 @@ use cjson;
 use ctypes::U256;
 use time::Duration;
 
-use super::super::validator_set::{new_validator_set, ValidatorSet};
+use super::super::validator_set::{new_multi_validator_set, new_validator_set, ValidatorSet};
 use super::{Step, Timeouts};
 
 /// `Tendermint` params.
@@ -34,8 +34,19 @@ pub struct TendermintParams {
 impl From<cjson::spec::TendermintParams> for TendermintParams {
     fn from(p: cjson::spec::TendermintParams) -> Self {
         let dt = TendermintTimeouts::default();
+        let validators = p.validators.into_iter().map(Into::into).collect();
+        let validators = match p.validator_transitions {
+            Some(transitions) => {
+                let transitions = transitions
+                    .into_iter()
+                    .map(|t| (t.block.into(), t.validators.into_iter().map(Into::into).collect()))
+                    .collect();
+                new_multi_validator_set(validators, transitions)
+            }
+            None => new_validator_set(validators),
+        };
         TendermintParams {
-            validators: new_validator_set(p.validators.into_iter().map(Into::into).collect()),
+            validators,
             timeouts: TendermintTimeouts {
                 propose: p.timeout_propose.map_or(dt.propose, to_duration),
                 prevote: p.timeout_prevote.map_or(dt.prevote, to_duration),
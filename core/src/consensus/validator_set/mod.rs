@@ -14,10 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
 use std::sync::Weak;
 
 use ctypes::{Address, Bytes, H256};
 
+use self::multi::Multi;
 use self::validator_list::ValidatorList;
 use super::super::client::EngineClient;
 use super::super::codechain_machine::CodeChainMachine;
@@ -26,6 +28,7 @@ use super::super::header::Header;
 use super::super::types::BlockNumber;
 use super::EpochChange;
 
+pub mod multi;
 pub mod validator_list;
 
 /// Creates a validator set from validator addresses.
@@ -33,17 +36,33 @@ pub fn new_validator_set(validators: Vec<Address>) -> Box<ValidatorSet> {
     Box::new(ValidatorList::new(validators))
 }
 
+/// Creates a validator set which changes to the given sets at the given blocks.
+pub fn new_multi_validator_set(
+    validators: Vec<Address>,
+    transitions: Vec<(BlockNumber, Vec<Address>)>,
+) -> Box<ValidatorSet> {
+    let mut sets = BTreeMap::new();
+    sets.insert(0, ValidatorList::new(validators));
+    for (number, validators) in transitions {
+        sets.insert(number, ValidatorList::new(validators));
+    }
+    Box::new(Multi::new(sets))
+}
+
 /// A validator set.
 pub trait ValidatorSet: Send + Sync {
     /// Checks if a given address is a validator,
     /// using underlying, default call mechanism.
-    fn contains(&self, parent: &H256, address: &Address) -> bool;
+    ///
+    /// The set validates the child of the given parent, whose number is passed explicitly
+    /// because the parent may not be imported yet.
+    fn contains(&self, parent: &H256, parent_number: BlockNumber, address: &Address) -> bool;
 
     /// Draws an validator nonce modulo number of validators.
-    fn get(&self, parent: &H256, nonce: usize) -> Address;
+    fn get(&self, parent: &H256, parent_number: BlockNumber, nonce: usize) -> Address;
 
     /// Returns the current number of validators.
-    fn count(&self, parent: &H256) -> usize;
+    fn count(&self, parent: &H256, parent_number: BlockNumber) -> usize;
 
     /// Signalling that a new epoch has begun.
     ///
@@ -0,0 +1,149 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::collections::BTreeMap;
+
+use ctypes::{Address, H256};
+
+use super::super::super::codechain_machine::CodeChainMachine;
+use super::super::super::error::Error;
+use super::super::super::header::Header;
+use super::super::super::types::BlockNumber;
+use super::super::EpochChange;
+use super::validator_list::ValidatorList;
+use super::ValidatorSet;
+
+/// Validator sets which are activated at the configured blocks.
+///
+/// The set activated at block `n` validates the blocks from `n` until the next set is activated.
+pub struct Multi {
+    sets: BTreeMap<BlockNumber, ValidatorList>,
+}
+
+impl Multi {
+    /// The set activated at block 0 must exist.
+    pub fn new(sets: BTreeMap<BlockNumber, ValidatorList>) -> Self {
+        assert!(sets.contains_key(&0), "The validator set of the genesis block is required");
+        Multi {
+            sets,
+        }
+    }
+
+    /// The set which validates the block of the given number.
+    fn set_at(&self, number: BlockNumber) -> &ValidatorList {
+        self.sets.range(..=number).next_back().map(|(_, set)| set).expect("The set of the genesis block exists")
+    }
+
+    /// The set which validates the child of the block of the given number.
+    fn correct_set(&self, parent_number: BlockNumber) -> &ValidatorList {
+        self.set_at(parent_number + 1)
+    }
+}
+
+impl ValidatorSet for Multi {
+    fn contains(&self, parent: &H256, parent_number: BlockNumber, address: &Address) -> bool {
+        self.correct_set(parent_number).contains(parent, parent_number, address)
+    }
+
+    fn get(&self, parent: &H256, parent_number: BlockNumber, nonce: usize) -> Address {
+        self.correct_set(parent_number).get(parent, parent_number, nonce)
+    }
+
+    fn count(&self, parent: &H256, parent_number: BlockNumber) -> usize {
+        self.correct_set(parent_number).count(parent, parent_number)
+    }
+
+    fn is_epoch_end(&self, first: bool, chain_head: &Header) -> Option<Vec<u8>> {
+        if first || self.sets.contains_key(&(chain_head.number() + 1)) {
+            Some(Vec::new())
+        } else {
+            None
+        }
+    }
+
+    fn signals_epoch_end(&self, _: bool, _: &Header) -> EpochChange {
+        EpochChange::No
+    }
+
+    fn epoch_set(
+        &self,
+        _first: bool,
+        _: &CodeChainMachine,
+        number: BlockNumber,
+        _: &[u8],
+    ) -> Result<(ValidatorList, Option<H256>), Error> {
+        // The epoch ends at the block of `number`.
+        Ok((self.set_at(number + 1).clone(), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ctypes::Address;
+
+    use super::super::super::super::header::Header;
+    use super::super::validator_list::ValidatorList;
+    use super::super::ValidatorSet;
+    use super::Multi;
+
+    fn multi() -> (Multi, Address, Address) {
+        let a1 = Address::random();
+        let a2 = Address::random();
+        let mut sets = BTreeMap::new();
+        sets.insert(0, ValidatorList::new(vec![a1]));
+        sets.insert(10, ValidatorList::new(vec![a2]));
+        (Multi::new(sets), a1, a2)
+    }
+
+    #[test]
+    fn set_is_activated_at_the_configured_block() {
+        let (multi, a1, a2) = multi();
+        assert_eq!(vec![a1], multi.set_at(0).to_vec());
+        assert_eq!(vec![a1], multi.set_at(9).to_vec());
+        assert_eq!(vec![a2], multi.set_at(10).to_vec());
+        assert_eq!(vec![a2], multi.set_at(100).to_vec());
+    }
+
+    #[test]
+    fn epoch_ends_before_the_activation() {
+        let (multi, _, _) = multi();
+        let header = |number| {
+            let mut header = Header::new();
+            header.set_number(number);
+            header
+        };
+        assert!(multi.is_epoch_end(true, &header(0)).is_some());
+        assert!(multi.is_epoch_end(false, &header(8)).is_none());
+        assert!(multi.is_epoch_end(false, &header(9)).is_some());
+        assert!(multi.is_epoch_end(false, &header(10)).is_none());
+    }
+
+    #[test]
+    fn uses_the_set_of_the_child_of_the_given_parent() {
+        let (multi, a1, a2) = multi();
+        let parent = Default::default();
+        assert!(multi.contains(&parent, 8, &a1));
+        assert!(!multi.contains(&parent, 8, &a2));
+        assert!(!multi.contains(&parent, 9, &a1));
+        assert!(multi.contains(&parent, 9, &a2));
+        assert_eq!(a1, multi.get(&parent, 8, 0));
+        assert_eq!(a2, multi.get(&parent, 9, 0));
+        assert_eq!(1, multi.count(&parent, 9));
+    }
+}
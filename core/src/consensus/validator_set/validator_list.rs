@@ -68,11 +68,11 @@ impl HeapSizeOf for ValidatorList {
 }
 
 impl ValidatorSet for ValidatorList {
-    fn contains(&self, _bh: &H256, address: &Address) -> bool {
+    fn contains(&self, _bh: &H256, _number: BlockNumber, address: &Address) -> bool {
         self.validators.contains(address)
     }
 
-    fn get(&self, _bh: &H256, nonce: usize) -> Address {
+    fn get(&self, _bh: &H256, _number: BlockNumber, nonce: usize) -> Address {
         let validator_n = self.validators.len();
 
         if validator_n == 0 {
@@ -82,7 +82,7 @@ impl ValidatorSet for ValidatorList {
         self.validators.get(nonce % validator_n).expect("There are validator_n authorities; taking number modulo validator_n gives number in validator_n range; qed").clone()
     }
 
-    fn count(&self, _bh: &H256) -> usize {
+    fn count(&self, _bh: &H256, _number: BlockNumber) -> usize {
         self.validators.len()
     }
 
@@ -121,9 +121,9 @@ mod tests {
         let a1 = Address::from_str("cd1722f3947def4cf144679da39c4c32bdc35681").unwrap();
         let a2 = Address::from_str("0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6").unwrap();
         let set = ValidatorList::new(vec![a1.clone(), a2.clone()]);
-        assert!(set.contains(&Default::default(), &a1));
-        assert_eq!(set.get(&Default::default(), 0), a1);
-        assert_eq!(set.get(&Default::default(), 1), a2);
-        assert_eq!(set.get(&Default::default(), 2), a1);
+        assert!(set.contains(&Default::default(), 0, &a1));
+        assert_eq!(set.get(&Default::default(), 0, 0), a1);
+        assert_eq!(set.get(&Default::default(), 0, 1), a2);
+        assert_eq!(set.get(&Default::default(), 0, 2), a1);
     }
 }
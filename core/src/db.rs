@@ -17,7 +17,9 @@
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops::Deref;
+use std::str;
 
+use journaldb::Algorithm;
 use kvdb::{DBTransaction, KeyValueDB};
 use parking_lot::RwLock;
 use rlp;
@@ -34,6 +36,38 @@ pub const COL_EXTRA: Option<u32> = Some(3);
 /// Number of columns in DB
 pub const NUM_COLUMNS: Option<u32> = Some(4);
 
+/// The version of the database layout. Increase it when the layout changes incompatibly.
+/// 2: The headers have the shard roots.
+/// 3: The headers have the log bloom, and the invoices have the fee and the logs.
+pub const DB_VERSION: u32 = 3;
+const DB_VERSION_KEY: &'static [u8] = b"db-version";
+
+/// Returns the layout version of the database, or None if no version is written yet.
+pub fn version(db: &KeyValueDB) -> Result<Option<u32>, ::kvdb::Error> {
+    Ok(db.get(COL_EXTRA, DB_VERSION_KEY)?.map(|version| rlp::decode(&version)))
+}
+
+/// Writes the version of this build to the database.
+pub fn write_version(db: &KeyValueDB) -> Result<(), ::kvdb::Error> {
+    let mut batch = DBTransaction::new();
+    batch.put(COL_EXTRA, DB_VERSION_KEY, &rlp::encode(&DB_VERSION));
+    db.write(batch)
+}
+
+const PRUNING_KEY: &'static [u8] = b"pruning";
+
+/// Returns the pruning algorithm of the state in the database, or None if no algorithm is written yet.
+pub fn pruning(db: &KeyValueDB) -> Result<Option<Algorithm>, ::kvdb::Error> {
+    Ok(db.get(COL_EXTRA, PRUNING_KEY)?.and_then(|name| str::from_utf8(&name).ok().and_then(|name| name.parse().ok())))
+}
+
+/// Writes the pruning algorithm of the state to the database.
+pub fn write_pruning(db: &KeyValueDB, algorithm: Algorithm) -> Result<(), ::kvdb::Error> {
+    let mut batch = DBTransaction::new();
+    batch.put(COL_EXTRA, PRUNING_KEY, algorithm.as_str().as_bytes());
+    db.write(batch)
+}
+
 /// Modes for updating caches.
 #[derive(Clone, Copy)]
 pub enum CacheUpdatePolicy {
@@ -24,7 +24,7 @@
 //! decoded object where parts like the hash can be saved.
 
 use ccrypto::blake256;
-use ctypes::{Address, H256, U256};
+use ctypes::{Address, Bloom, H256, U256};
 use heapsize::HeapSizeOf;
 use rlp::Rlp;
 
@@ -107,6 +107,16 @@ impl Header {
         self.view().invoices_root()
     }
 
+    /// Returns the roots of the state tries of the shards.
+    pub fn shard_roots(&self) -> Vec<H256> {
+        self.view().shard_roots()
+    }
+
+    /// Returns the bloom of the addresses and the topics of the logs.
+    pub fn log_bloom(&self) -> Bloom {
+        self.view().log_bloom()
+    }
+
     /// Score of this block
     pub fn score(&self) -> U256 {
         self.view().score()
@@ -283,6 +293,16 @@ impl Block {
         self.header_view().invoices_root()
     }
 
+    /// Returns the roots of the state tries of the shards.
+    pub fn shard_roots(&self) -> Vec<H256> {
+        self.header_view().shard_roots()
+    }
+
+    /// Returns the bloom of the addresses and the topics of the logs.
+    pub fn log_bloom(&self) -> Bloom {
+        self.header_view().log_bloom()
+    }
+
     /// Score of this block
     pub fn score(&self) -> U256 {
         self.header_view().score()
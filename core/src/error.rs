@@ -18,7 +18,7 @@ use std::fmt;
 
 use cio::IoError;
 use ckeys::Error as KeyError;
-use ctypes::{Address, H256, U256};
+use ctypes::{Address, Bloom, H256, U256};
 use trie::TrieError;
 use unexpected::{Mismatch, OutOfBounds};
 use util_error::UtilError;
@@ -98,6 +98,12 @@ pub enum BlockError {
     InvalidSeal,
     /// Invoices trie root header field is invalid.
     InvalidInvoicesRoot(Mismatch<H256>),
+    /// Number of the shard roots in the header is invalid.
+    InvalidShardCount(Mismatch<usize>),
+    /// Shard root header field is invalid.
+    InvalidShardRoot(Mismatch<H256>),
+    /// Log bloom header field is invalid.
+    InvalidLogBloom(Box<Mismatch<Bloom>>),
     /// Timestamp header field is invalid.
     InvalidTimestamp(OutOfBounds<u64>),
     /// Timestamp header field is too far in future.
@@ -113,6 +119,8 @@ pub enum BlockError {
     TooManyParcels(Address),
     /// Parent given is unknown.
     UnknownParent(H256),
+    /// The block at a checkpoint has a hash other than the trusted one.
+    InvalidCheckpoint(Mismatch<H256>),
 }
 
 impl fmt::Display for BlockError {
@@ -129,6 +137,9 @@ impl fmt::Display for BlockError {
             MismatchedH256SealElement(mis) => format!("Seal element out of bounds: {}", mis),
             InvalidSeal => "Block has invalid seal.".into(),
             InvalidInvoicesRoot(mis) => format!("Invalid invoices trie root in header: {}", mis),
+            InvalidShardCount(mis) => format!("Invalid number of shard roots in header: {}", mis),
+            InvalidShardRoot(mis) => format!("Invalid shard root in header: {}", mis),
+            InvalidLogBloom(mis) => format!("Invalid log bloom in header: {}", mis),
             InvalidTimestamp(oob) => format!("Invalid timestamp in header: {}", oob),
             TemporarilyInvalid(oob) => format!("Future timestamp in header: {}", oob),
             InvalidParentHash(mis) => format!("Invalid parent hash: {}", mis),
@@ -136,6 +147,7 @@ impl fmt::Display for BlockError {
             RidiculousNumber(oob) => format!("Implausible block number. {}", oob),
             UnknownParent(hash) => format!("Unknown parent: {}", hash),
             TooManyParcels(address) => format!("Too many parcels from: {}", address),
+            InvalidCheckpoint(mis) => format!("Block contradicts the checkpoint: {}", mis),
         };
 
         f.write_fmt(format_args!("Block error ({})", msg))
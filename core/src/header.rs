@@ -19,7 +19,7 @@ use std::cmp;
 use time::get_time;
 
 use ccrypto::{blake256, BLAKE_NULL_RLP};
-use ctypes::{Address, Bytes, H256, U256};
+use ctypes::{Address, Bloom, Bytes, H256, U256};
 use heapsize::HeapSizeOf;
 use rlp::*;
 
@@ -54,6 +54,10 @@ pub struct Header {
     state_root: H256,
     /// Block invoices root.
     invoices_root: H256,
+    /// Roots of the state tries of the shards, in the order of the shard id.
+    shard_roots: Vec<H256>,
+    /// Bloom of the addresses and the topics of the logs which the transactions emitted.
+    log_bloom: Bloom,
 
     /// Block score.
     score: U256,
@@ -79,6 +83,8 @@ impl Default for Header {
             parcels_root: BLAKE_NULL_RLP,
             state_root: BLAKE_NULL_RLP,
             invoices_root: BLAKE_NULL_RLP,
+            shard_roots: vec![],
+            log_bloom: Bloom::default(),
 
             score: U256::default(),
             seal: vec![],
@@ -133,6 +139,15 @@ impl Header {
     pub fn parcels_root(&self) -> &H256 {
         &self.parcels_root
     }
+    /// Get the shard roots field of the header.
+    pub fn shard_roots(&self) -> &[H256] {
+        &self.shard_roots
+    }
+
+    /// Get the log bloom field of the header.
+    pub fn log_bloom(&self) -> &Bloom {
+        &self.log_bloom
+    }
 
     /// Get the score field of the header.
     pub fn score(&self) -> &U256 {
@@ -193,6 +208,16 @@ impl Header {
         self.invoices_root = a;
         self.note_dirty()
     }
+    /// Set the shard roots field of the header.
+    pub fn set_shard_roots(&mut self, a: Vec<H256>) {
+        self.shard_roots = a;
+        self.note_dirty()
+    }
+    /// Set the log bloom field of the header.
+    pub fn set_log_bloom(&mut self, a: Bloom) {
+        self.log_bloom = a;
+        self.note_dirty()
+    }
 
     /// Set the score field of the header.
     pub fn set_score(&mut self, a: U256) {
@@ -234,7 +259,7 @@ impl Header {
     /// Place this header into an RLP stream `s`, optionally `with_seal`.
     pub fn stream_rlp(&self, s: &mut RlpStream, with_seal: Seal) {
         s.begin_list(
-            9 + match with_seal {
+            11 + match with_seal {
                 Seal::With => self.seal.len(),
                 _ => 0,
             },
@@ -248,6 +273,8 @@ impl Header {
         s.append(&self.number);
         s.append(&self.timestamp);
         s.append(&self.extra_data);
+        s.append_list(&self.shard_roots);
+        s.append(&self.log_bloom);
         if let Seal::With = with_seal {
             for b in &self.seal {
                 s.append_raw(b, 1);
@@ -276,7 +303,9 @@ impl Header {
 
 impl HeapSizeOf for Header {
     fn heap_size_of_children(&self) -> usize {
-        self.extra_data.heap_size_of_children() + self.seal.heap_size_of_children()
+        self.extra_data.heap_size_of_children()
+            + self.shard_roots.heap_size_of_children()
+            + self.seal.heap_size_of_children()
     }
 }
 
@@ -292,12 +321,14 @@ impl Decodable for Header {
             number: r.val_at(6)?,
             timestamp: cmp::min(r.val_at::<U256>(7)?, u64::max_value().into()).as_u64(),
             extra_data: r.val_at(8)?,
+            shard_roots: r.list_at(9)?,
+            log_bloom: r.val_at(10)?,
             seal: vec![],
             hash: RefCell::new(Some(blake256(r.as_raw()))),
             bare_hash: RefCell::new(None),
         };
 
-        for i in 9..r.item_count()? {
+        for i in 11..r.item_count()? {
             blockheader.seal.push(r.at(i)?.as_raw().to_vec())
         }
 
@@ -332,3 +363,37 @@ impl ::machine::Header for Header {
         Header::number(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ctypes::{Bloom, H256};
+    use rlp;
+
+    use super::{Header, Seal};
+
+    #[test]
+    fn encode_and_decode_header() {
+        let mut header = Header::new();
+        header.set_number(10);
+        header.set_timestamp(1_500_000_000);
+        header.set_extra_data(b"codechain".to_vec());
+        header.set_shard_roots(vec![H256::from([0x11; 32]), H256::from([0x22; 32])]);
+        header.set_log_bloom(Bloom::from([0x33; 256]));
+        header.set_seal(vec![rlp::encode(&1u64).into_vec(), rlp::encode(&2u64).into_vec()]);
+        // The decoded header memoizes its hash
+        let hash = header.hash();
+
+        let decoded: Header = rlp::decode(&header.rlp(Seal::With));
+        assert_eq!(header, decoded);
+        assert_eq!(hash, decoded.hash());
+        assert_ne!(hash, header.bare_hash());
+    }
+
+    #[test]
+    fn bare_hash_ignores_the_seal() {
+        let mut header = Header::new();
+        let bare_hash = header.bare_hash();
+        header.set_seal(vec![rlp::encode(&1u64).into_vec()]);
+        assert_eq!(bare_hash, header.bare_hash());
+    }
+}
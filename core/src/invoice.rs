@@ -14,13 +14,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use ccrypto::blake256;
+use ctypes::{Bloom, BloomInput, H256, U256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::types::BlockNumber;
+
 /// Information describing execution of a parcel.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Invoice {
     /// Transaction outcome.
     pub outcome: TransactionOutcome,
+    /// The fee which the parcel paid. Only the invoice of the first transaction in the parcel has it, so that the
+    /// fees of the invoices add up to the fees paid in the block.
+    pub fee: U256,
+    /// The events which the transaction emitted. A failed transaction emits nothing.
+    pub logs: Vec<LogEntry>,
 }
 
 /// Transaction outcome store in the invoice.
@@ -32,31 +41,140 @@ pub enum TransactionOutcome {
 
 impl Invoice {
     /// Create a new invocie.
-    pub fn new(outcome: TransactionOutcome) -> Self {
+    pub fn new(outcome: TransactionOutcome, fee: U256, logs: Vec<LogEntry>) -> Self {
         Self {
             outcome,
+            fee,
+            logs,
         }
     }
+
+    /// The bloom of the addresses and the topics of the logs.
+    pub fn log_bloom(&self) -> Bloom {
+        self.logs.iter().fold(Bloom::default(), |mut bloom, log| {
+            bloom.accrue_bloom(&log.bloom());
+            bloom
+        })
+    }
 }
 
 impl Encodable for Invoice {
     fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
         match self.outcome {
             TransactionOutcome::Success => s.append(&1u8),
             TransactionOutcome::Failed => s.append(&0u8),
         };
+        s.append(&self.fee);
+        s.append_list(&self.logs);
     }
 }
 
 impl Decodable for Invoice {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        let outcome = match rlp.as_val::<u8>()? {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let outcome = match rlp.val_at::<u8>(0)? {
             1 => TransactionOutcome::Success,
             0 => TransactionOutcome::Failed,
             _ => return Err(DecoderError::Custom("Invalid parcel outcome")),
         };
         Ok(Self {
             outcome,
+            fee: rlp.val_at(1)?,
+            logs: rlp.list_at(2)?,
+        })
+    }
+}
+
+/// The kind of an event. The blake256 hash of its name is the first topic of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Payment,
+    SetRegularKey,
+    AssetMint,
+    AssetTransfer,
+    AssetRelay,
+}
+
+impl EventKind {
+    pub fn topic(&self) -> H256 {
+        blake256(match self {
+            EventKind::Payment => "Payment",
+            EventKind::SetRegularKey => "SetRegularKey",
+            EventKind::AssetMint => "AssetMint",
+            EventKind::AssetTransfer => "AssetTransfer",
+            EventKind::AssetRelay => "AssetRelay",
         })
     }
 }
+
+/// An event emitted by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+pub struct LogEntry {
+    /// The account or the asset type which the event is about. The account address is padded to 32 bytes.
+    pub address: H256,
+    /// The kind of the event, followed by the values it is indexed by.
+    pub topics: Vec<H256>,
+}
+
+impl LogEntry {
+    pub fn new(kind: EventKind, address: H256, mut topics: Vec<H256>) -> Self {
+        topics.insert(0, kind.topic());
+        Self {
+            address,
+            topics,
+        }
+    }
+
+    /// The bloom of the address and the topics.
+    pub fn bloom(&self) -> Bloom {
+        let mut bloom = Bloom::default();
+        bloom.accrue(BloomInput::Raw(&self.address));
+        for topic in &self.topics {
+            bloom.accrue(BloomInput::Raw(topic));
+        }
+        bloom
+    }
+}
+
+/// A log with the position of the transaction which emitted it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalizedLogEntry {
+    pub entry: LogEntry,
+    pub block_hash: H256,
+    pub block_number: BlockNumber,
+    pub parcel_hash: H256,
+    pub transaction_hash: H256,
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes::{BloomInput, H256};
+    use rlp;
+
+    use super::{EventKind, Invoice, LogEntry, TransactionOutcome};
+
+    #[test]
+    fn encode_and_decode_invoice() {
+        let log = LogEntry::new(EventKind::AssetMint, H256::from([0x11; 32]), vec![H256::from([0x22; 32])]);
+        let invoice = Invoice::new(TransactionOutcome::Success, 10.into(), vec![log]);
+        assert_eq!(invoice, rlp::decode(&rlp::encode(&invoice)));
+    }
+
+    #[test]
+    fn log_bloom_has_the_addresses_and_the_topics() {
+        let address = H256::from([0x11; 32]);
+        let lock_script_hash = H256::from([0x22; 32]);
+        let log = LogEntry::new(EventKind::AssetMint, address, vec![lock_script_hash]);
+        let bloom = Invoice::new(TransactionOutcome::Success, 0.into(), vec![log]).log_bloom();
+
+        assert!(bloom.contains_input(BloomInput::Raw(&address)));
+        assert!(bloom.contains_input(BloomInput::Raw(&lock_script_hash)));
+        assert!(bloom.contains_input(BloomInput::Raw(&EventKind::AssetMint.topic())));
+        assert!(!bloom.contains_input(BloomInput::Raw(&EventKind::Payment.topic())));
+        assert!(Invoice::new(TransactionOutcome::Failed, 0.into(), vec![]).log_bloom().is_empty());
+    }
+}
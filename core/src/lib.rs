@@ -28,6 +28,7 @@ extern crate heapsize;
 extern crate journaldb;
 extern crate kvdb;
 extern crate kvdb_memorydb;
+#[cfg(feature = "kvdb-rocksdb")]
 extern crate kvdb_rocksdb;
 extern crate linked_hash_map;
 extern crate lru_cache;
@@ -71,6 +72,7 @@ mod parcel;
 mod pod_account;
 mod pod_state;
 mod service;
+mod snapshot;
 mod spec;
 mod state;
 mod state_db;
@@ -82,23 +84,31 @@ mod views;
 #[cfg(test)]
 mod tests;
 
-pub use account_provider::AccountProvider;
+pub use account_provider::{AccountProvider, SignError};
 pub use block::Block;
 pub use client::{
-    Balance, BlockChainClient, BlockInfo, ChainInfo, ChainNotify, Client, ImportBlock, Nonce, RegularKey,
-    TestBlockChainClient,
+    Balance, BlockChainClient, BlockInfo, ChainInfo, ChainNotify, Client, ClientConfig, DatabaseBackend, EngineClient,
+    ImportBlock, InvoiceRetention, Nonce, ParcelInfo, RegularKey, SeenBlocks, SnapshotClient, StateClient,
+    TestBlockChainClient, TransactionQueueClient,
 };
-pub use db::COL_STATE;
-pub use error::{BlockImportError, Error, ImportError};
+pub use db::{version as database_version, COL_STATE};
+pub use error::{BlockError, BlockImportError, Error, ImportError};
 pub use header::{Header, Seal};
-pub use invoice::Invoice;
+pub use invoice::{EventKind, Invoice, LocalizedLogEntry, LogEntry};
 pub use miner::{Miner, MinerOptions, MinerService};
 pub use parcel::{
     parcel_error_message, AssetOutPoint, AssetTransferInput, AssetTransferOutput, LocalizedParcel, Parcel,
-    SignedParcel, UnverifiedParcel,
+    ParcelError, SignedParcel, Timelock, UnverifiedParcel,
+};
+pub use service::{check_database, ClientService};
+pub use snapshot::{
+    Error as SnapshotError, ManifestData, SignedManifest, SnapshotReader, SnapshotService, StateRebuilder,
+    DEFAULT_SNAPSHOT_PERIOD, SNAPSHOT_VERSION,
 };
-pub use service::ClientService;
 pub use spec::Spec;
-pub use state::{Asset, AssetAddress, AssetScheme, AssetSchemeAddress};
+pub use state::{
+    verify_account_proof, Account, Asset, AssetAddress, AssetScheme, AssetSchemeAddress, CrossShardMessage,
+    MessageAddress, Shard, ShardAddress,
+};
 pub use transaction::{Error as TransactionError, Transaction};
-pub use types::{BlockId, BlockNumber, ParcelId};
+pub use types::{BlockId, BlockNumber, Filter, ParcelId, ShardId};
@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -48,6 +49,10 @@ pub struct MinerOptions {
     pub parcel_queue_size: usize,
     /// Maximum memory usage of parcels in the queue (current / future).
     pub parcel_queue_memory_limit: Option<usize>,
+    /// Maximum number of the external parcels of a sender in the queue.
+    pub parcel_queue_per_sender_limit: usize,
+    /// How many blocks an external parcel may wait for the parcels of the lower nonces.
+    pub parcel_queue_future_period: BlockNumber,
     /// How many historical work packages can we store before running out?
     pub work_queue_size: usize,
 }
@@ -60,6 +65,8 @@ impl Default for MinerOptions {
             reseal_min_period: Duration::from_secs(2),
             parcel_queue_size: 8192,
             parcel_queue_memory_limit: Some(2 * 1024 * 1024),
+            parcel_queue_per_sender_limit: 1024,
+            parcel_queue_future_period: 32,
             work_queue_size: 20,
         }
     }
@@ -71,6 +78,7 @@ pub struct Miner {
     next_allowed_reseal: Mutex<Instant>,
     author: RwLock<Address>,
     extra_data: RwLock<Bytes>,
+    is_authoring: AtomicBool,
     sealing_queue: Mutex<SealingQueue>,
     engine: Arc<CodeChainEngine>,
     options: MinerOptions,
@@ -88,13 +96,17 @@ impl Miner {
 
     fn new_raw(options: MinerOptions, spec: &Spec, accounts: Option<Arc<AccountProvider>>) -> Self {
         let mem_limit = options.parcel_queue_memory_limit.unwrap_or_else(usize::max_value);
-        let parcel_queue = Arc::new(RwLock::new(ParcelQueue::with_limits(options.parcel_queue_size, mem_limit)));
+        let mut parcel_queue = ParcelQueue::with_limits(options.parcel_queue_size, mem_limit);
+        parcel_queue.set_per_sender_limit(options.parcel_queue_per_sender_limit);
+        parcel_queue.set_future_period(options.parcel_queue_future_period);
+        let parcel_queue = Arc::new(RwLock::new(parcel_queue));
         Self {
             parcel_queue,
             parcel_listener: RwLock::new(vec![]),
             next_allowed_reseal: Mutex::new(Instant::now()),
             author: RwLock::new(Address::default()),
             extra_data: RwLock::new(Vec::new()),
+            is_authoring: AtomicBool::new(true),
             sealing_queue: Mutex::new(SealingQueue::new(options.work_queue_size)),
             engine: spec.engine.clone(),
             options,
@@ -124,6 +136,10 @@ impl Miner {
 
     /// Check is reseal is allowed and necessary.
     fn requires_reseal(&self) -> bool {
+        if !self.is_authoring() {
+            trace!(target: "miner", "requires_reseal: authoring is stopped");
+            return false
+        }
         let has_local_parcels = self.parcel_queue.read().has_local_pending_parcels();
         let should_disable_sealing = !has_local_parcels && self.engine.seals_internally().is_none();
 
@@ -149,6 +165,7 @@ impl Miner {
         let best_block_header = client.best_block_header().decode();
         let insertion_time = client.chain_info().best_block_number;
         let mut inserted = Vec::with_capacity(parcels.len());
+        parcel_queue.set_best_block(best_block_header.number(), best_block_header.timestamp());
 
         let results = parcels
             .into_iter()
@@ -233,6 +250,10 @@ impl Miner {
             match result {
                 // already have parcel - ignore
                 Err(Error::Parcel(ParcelError::AlreadyImported)) => {}
+                // The parcel stays in the queue until it becomes spendable
+                Err(Error::Parcel(ParcelError::Timelocked(_))) => {
+                    debug!(target: "miner", "Skipping timelocked parcel {:?}", hash);
+                }
                 Err(Error::Parcel(ParcelError::NotAllowed)) => {
                     non_allowed_parcels.insert(hash);
                     debug!(target: "miner",
@@ -371,6 +392,15 @@ impl MinerService for Miner {
         *self.extra_data.write() = extra_data;
     }
 
+    fn is_authoring(&self) -> bool {
+        self.is_authoring.load(Ordering::SeqCst)
+    }
+
+    fn set_authoring(&self, is_authoring: bool) {
+        trace!(target: "miner", "Set authoring to {}", is_authoring);
+        self.is_authoring.store(is_authoring, Ordering::SeqCst);
+    }
+
     fn set_engine_signer(&self, address: Address) -> Result<(), SignError> {
         if self.engine.seals_internally().is_some() {
             if let Some(ref ap) = self.accounts {
@@ -433,7 +463,9 @@ impl MinerService for Miner {
                 balance: chain.latest_balance(a),
             };
             let time = chain.chain_info().best_block_number;
+            let best_block_timestamp = chain.best_block_header().timestamp();
             let mut parcel_queue = self.parcel_queue.write();
+            parcel_queue.set_best_block(time, best_block_timestamp);
             parcel_queue.remove_old(&fetch_account, time);
         }
     }
@@ -48,6 +48,12 @@ pub trait MinerService: Send + Sync {
     /// Set the extra_data that we will seal blocks with.
     fn set_extra_data(&self, extra_data: Bytes);
 
+    /// Whether the blocks are authored when there are parcels to seal.
+    fn is_authoring(&self) -> bool;
+
+    /// Starts or stops authoring the blocks. The blocks from the network are still imported.
+    fn set_authoring(&self, is_authoring: bool);
+
     /// Set info necessary to sign consensus messages.
     fn set_engine_signer(&self, address: Address) -> Result<(), SignError>;
 
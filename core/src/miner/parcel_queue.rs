@@ -37,6 +37,8 @@ const FEE_BUMP_SHIFT: usize = 3; // 2 = 25%, 3 = 12.5%, 4 = 6.25%
 /// Point in time when parcel was inserted.
 pub type QueuingInstant = BlockNumber;
 const DEFAULT_QUEUING_PERIOD: BlockNumber = 128;
+/// The external parcels waiting for a nonce gap to be filled expire earlier than the others.
+const DEFAULT_FUTURE_QUEUING_PERIOD: BlockNumber = 32;
 
 /// Parcel origin
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -336,6 +338,8 @@ pub struct ParcelQueue {
     /// When we reach `max_time_in_queue / 2^3` we re-validate
     /// account balance.
     max_time_in_queue: QueuingInstant,
+    /// Maximal time an external parcel may stay in `future`.
+    max_time_in_future: QueuingInstant,
     /// Priority queue for parcels that can go to block
     current: ParcelSet,
     /// Priority queue for parcels that has been received but are not yet valid to go to block
@@ -348,6 +352,12 @@ pub struct ParcelQueue {
     local_parcels: LocalParcelsList,
     /// Next id that should be assigned to a parcel imported to the queue.
     next_parcel_id: u64,
+    /// The maximum number of the external parcels of a sender in the queue
+    per_sender_limit: usize,
+    /// The number of the best block, which decides whether the timelocks are expired.
+    best_block_number: BlockNumber,
+    /// The timestamp of the best block, which decides whether the timelocks are expired.
+    best_block_timestamp: u64,
 }
 
 impl Default for ParcelQueue {
@@ -383,15 +393,47 @@ impl ParcelQueue {
         ParcelQueue {
             minimal_fee: U256::zero(),
             max_time_in_queue: DEFAULT_QUEUING_PERIOD,
+            max_time_in_future: DEFAULT_FUTURE_QUEUING_PERIOD,
             current,
             future,
             by_hash: HashMap::new(),
             last_nonces: HashMap::new(),
             local_parcels: LocalParcelsList::default(),
             next_parcel_id: 0,
+            per_sender_limit: usize::max_value(),
+            best_block_number: 0,
+            best_block_timestamp: 0,
         }
     }
 
+    /// Sets the maximum number of the parcels of a sender, which is not applied to the local parcels.
+    /// The parcels already in the queue are not removed.
+    pub fn set_per_sender_limit(&mut self, limit: usize) {
+        self.per_sender_limit = limit;
+    }
+
+    /// Sets how many blocks an external parcel may wait in `future` for the nonce gap to be filled.
+    pub fn set_future_period(&mut self, period: QueuingInstant) {
+        self.max_time_in_future = period;
+    }
+
+    /// Sets the best block against which the timelocks of the parcels are checked.
+    /// The parcels which become spendable are promoted on the next `remove_old`.
+    pub fn set_best_block(&mut self, number: BlockNumber, timestamp: u64) {
+        self.best_block_number = number;
+        self.best_block_timestamp = timestamp;
+    }
+
+    // The number of the parcels of the sender in both `current` and `future`
+    fn count_of_sender(&self, sender: &Address) -> usize {
+        let count = |set: &ParcelSet| set.by_address.row(sender).map_or(0, |by_nonce| by_nonce.len());
+        count(&self.current) + count(&self.future)
+    }
+
+    fn has_nonce(&self, sender: &Address, nonce: &U256) -> bool {
+        self.current.by_address.get(sender, nonce).is_some() || self.future.by_address.get(sender, nonce).is_some()
+    }
+
     /// Set the new limit for `current` and `future` queue.
     pub fn set_limit(&mut self, limit: usize) {
         self.current.set_limit(limit);
@@ -483,10 +525,16 @@ impl ParcelQueue {
 
         for (sender, details) in senders.iter() {
             self.cull(*sender, details.nonce);
+            // The timelocks expired since the last block might have made a batch of `future` spendable
+            let next_nonce = self.last_nonces.get(sender).map_or(details.nonce, |nonce| *nonce + U256::one());
+            self.move_matching_future_to_current(*sender, next_nonce, details.nonce);
         }
 
         let max_time = self.max_time_in_queue;
+        let max_time_in_future = self.max_time_in_future;
         let balance_check = max_time >> 3;
+        let future = &self.future;
+        let (number, timestamp) = self.next_block();
         // Clear parcels occupying the queue too long
         let invalid = self.by_hash
             .iter()
@@ -497,6 +545,13 @@ impl ParcelQueue {
                     return Some(*hash)
                 }
 
+                // The gap is not filled for a long time
+                let is_future = future.by_address.get(&parcel.sender(), &parcel.nonce()).is_some();
+                let is_timelocked = parcel.parcel.check_timelock(number, timestamp).is_err();
+                if is_future && !is_timelocked && time_diff > max_time_in_future {
+                    return Some(*hash)
+                }
+
                 if time_diff > balance_check {
                     return match senders.get(&parcel.sender()) {
                         Some(details) if parcel.cost() > details.balance => Some(*hash),
@@ -702,6 +757,19 @@ impl ParcelQueue {
                 balance: client_account.balance,
             })
         }
+        // A parcel replacing another of the same nonce doesn't increase the count
+        if origin != ParcelOrigin::Local
+            && self.count_of_sender(&parcel.sender()) >= self.per_sender_limit
+            && !self.has_nonce(&parcel.sender(), &parcel.nonce)
+        {
+            trace!(target: "parcel_queue",
+                   "Dropping parcel over the limit of the sender: {:?} ({})",
+                   parcel.hash(),
+                   parcel.sender()
+            );
+
+            return Err(ParcelError::LimitReached)
+        }
         parcel.check_low_s()?;
         // No invalid parcels beyond this point.
         let id = self.next_parcel_id;
@@ -754,8 +822,15 @@ impl ParcelQueue {
         }
 
         // Future parcel
-        if nonce > next_nonce {
-            // We have a gap - put to future.
+        let (number, timestamp) = self.next_block();
+        let is_timelocked = match parcel.parcel.check_timelock(number, timestamp) {
+            // A timelocked parcel can't replace the one in `current`
+            Err(err) if nonce < next_nonce => return Err(err),
+            Err(_) => true,
+            Ok(()) => false,
+        };
+        if nonce > next_nonce || is_timelocked {
+            // We have a gap or the parcel is timelocked - put to future.
             // Insert parcel (or replace old one with lower fee)
             check_too_cheap(Self::replace_parcel(
                 parcel,
@@ -853,13 +928,27 @@ impl ParcelQueue {
     /// (because nonce matches).
     fn move_matching_future_to_current(&mut self, address: Address, mut current_nonce: U256, first_nonce: U256) {
         let mut update_last_nonce_to = None;
+        let (number, timestamp) = self.next_block();
         {
             let by_nonce = self.future.by_address.row_mut(&address);
             if by_nonce.is_none() {
                 return
             }
             let by_nonce = by_nonce.expect("None is tested in early-exit condition above; qed");
-            while let Some(order) = by_nonce.remove(&current_nonce) {
+            loop {
+                let hash = match by_nonce.get(&current_nonce) {
+                    Some(order) => order.hash,
+                    None => break,
+                };
+                // The parcels after the timelocked one wait in `future` with it
+                let is_timelocked = {
+                    let parcel = self.by_hash.get(&hash).expect("All parcels in `future` are also in `by_hash`");
+                    parcel.parcel.check_timelock(number, timestamp).is_err()
+                };
+                if is_timelocked {
+                    break
+                }
+                let order = by_nonce.remove(&current_nonce).expect("The order is found above; qed");
                 // remove also from priority and fee
                 self.future.by_priority.remove(&order);
                 self.future.by_fee.remove(&order.fee, &order.hash);
@@ -890,6 +979,11 @@ impl ParcelQueue {
         }
     }
 
+    /// The lowest number and timestamp that the next block can have.
+    fn next_block(&self) -> (BlockNumber, u64) {
+        (self.best_block_number + 1, self.best_block_timestamp + 1)
+    }
+
     /// Drop all parcels from given sender from `current`.
     /// Either moves them to `future` or removes them from queue completely.
     fn move_all_to_future(&mut self, sender: &Address, current_nonce: U256) {
@@ -1093,6 +1187,7 @@ pub mod test {
 
     use ckeys::{Generator, Random};
 
+    use super::super::super::parcel::{AssetOutPoint, AssetTransferInput, Timelock};
     use super::super::super::Parcel;
     use super::*;
 
@@ -1127,6 +1222,7 @@ pub mod test {
     fn mint_transaction_does_not_increase_cost() {
         let fee = U256::from(100);
         let transactions = vec![Transaction::AssetMint {
+            shard_id: 0,
             metadata: "Metadata".to_string(),
             lock_script_hash: H256::zero(),
             parameters: vec![],
@@ -1152,6 +1248,7 @@ pub mod test {
         let fee = U256::from(100);
         let transactions = vec![
             Transaction::AssetMint {
+                shard_id: 0,
                 metadata: "Metadata".to_string(),
                 lock_script_hash: H256::zero(),
                 parameters: vec![],
@@ -1161,6 +1258,7 @@ pub mod test {
             },
             Transaction::AssetTransfer {
                 network_id: 0,
+                shard_id: 0,
                 inputs: vec![],
                 outputs: vec![],
                 nonce: 0,
@@ -1186,6 +1284,7 @@ pub mod test {
         let keypair = Random.generate().unwrap();
         let transactions = vec![
             Transaction::AssetMint {
+                shard_id: 0,
                 metadata: "Metadata".to_string(),
                 lock_script_hash: H256::zero(),
                 parameters: vec![],
@@ -1195,6 +1294,7 @@ pub mod test {
             },
             Transaction::AssetTransfer {
                 network_id: 0,
+                shard_id: 0,
                 inputs: vec![],
                 outputs: vec![],
                 nonce: 0,
@@ -1233,6 +1333,7 @@ pub mod test {
                 value: pay_value0,
             },
             Transaction::AssetMint {
+                shard_id: 0,
                 metadata: "Metadata".to_string(),
                 lock_script_hash: H256::zero(),
                 parameters: vec![],
@@ -1248,6 +1349,7 @@ pub mod test {
             },
             Transaction::AssetTransfer {
                 network_id: 0,
+                shard_id: 0,
                 inputs: vec![],
                 outputs: vec![],
                 nonce: 0,
@@ -1270,4 +1372,169 @@ pub mod test {
 
         assert_eq!(fee + pay_value0 + pay_value1 + pay_value2, queued.cost());
     }
+
+    #[test]
+    fn external_parcels_over_the_per_sender_limit_are_rejected() {
+        let mut queue = ParcelQueue::new();
+        queue.set_per_sender_limit(2);
+        let keypair = Random.generate().unwrap();
+        let parcel = |nonce: u64, fee: u64| {
+            Parcel {
+                nonce: nonce.into(),
+                fee: fee.into(),
+                transactions: vec![],
+                network_id: 200,
+            }.sign(keypair.private())
+        };
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: 1_000_000.into(),
+        };
+
+        assert!(queue.add(parcel(0, 100), ParcelOrigin::External, 0, &fetch_account).is_ok());
+        assert!(queue.add(parcel(1, 100), ParcelOrigin::External, 0, &fetch_account).is_ok());
+        assert_eq!(
+            Err(ParcelError::LimitReached),
+            queue.add(parcel(2, 100), ParcelOrigin::External, 0, &fetch_account)
+        );
+        // Replacing the parcel of the same nonce with a higher fee
+        assert!(queue.add(parcel(1, 200), ParcelOrigin::External, 0, &fetch_account).is_ok());
+        // The local parcels are not limited
+        assert!(queue.add(parcel(2, 100), ParcelOrigin::Local, 0, &fetch_account).is_ok());
+    }
+
+    #[test]
+    fn stale_future_parcels_are_removed() {
+        let mut queue = ParcelQueue::new();
+        queue.set_future_period(10);
+        let keypair = Random.generate().unwrap();
+        let parcel = |nonce: u64| {
+            Parcel {
+                nonce: nonce.into(),
+                fee: 100.into(),
+                transactions: vec![],
+                network_id: 200,
+            }.sign(keypair.private())
+        };
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: 1_000_000.into(),
+        };
+
+        assert_eq!(Ok(ParcelImportResult::Current), queue.add(parcel(0), ParcelOrigin::External, 0, &fetch_account));
+        assert_eq!(Ok(ParcelImportResult::Future), queue.add(parcel(2), ParcelOrigin::External, 0, &fetch_account));
+        let local = parcel(3);
+        assert_eq!(Ok(ParcelImportResult::Future), queue.add(local.clone(), ParcelOrigin::Local, 0, &fetch_account));
+
+        queue.remove_old(&fetch_account, 10);
+        assert_eq!(1, queue.status().pending);
+        assert_eq!(2, queue.status().future);
+
+        queue.remove_old(&fetch_account, 11);
+        assert_eq!(1, queue.status().pending);
+        assert_eq!(1, queue.status().future);
+        assert_eq!(vec![local], queue.future_parcels());
+    }
+
+    #[test]
+    fn future_parcels_are_promoted_when_the_gap_is_filled() {
+        let mut queue = ParcelQueue::new();
+        let keypair = Random.generate().unwrap();
+        let parcel = |nonce: u64| {
+            Parcel {
+                nonce: nonce.into(),
+                fee: 100.into(),
+                transactions: vec![],
+                network_id: 200,
+            }.sign(keypair.private())
+        };
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: 1_000_000.into(),
+        };
+
+        let parcels = vec![parcel(0), parcel(1), parcel(2)];
+
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            queue.add(parcels[1].clone(), ParcelOrigin::External, 0, &fetch_account)
+        );
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            queue.add(parcels[2].clone(), ParcelOrigin::External, 0, &fetch_account)
+        );
+        assert!(queue.top_parcels().is_empty());
+
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            queue.add(parcels[0].clone(), ParcelOrigin::External, 0, &fetch_account)
+        );
+        assert_eq!(3, queue.status().pending);
+        assert_eq!(0, queue.status().future);
+        assert_eq!(parcels, queue.top_parcels());
+    }
+
+    #[test]
+    fn timelocked_parcels_wait_in_future_until_they_mature() {
+        let mut queue = ParcelQueue::new();
+        queue.set_best_block(1, 1_000);
+        let keypair = Random.generate().unwrap();
+        let parcel = |nonce: u64, timelock: Option<Timelock>| {
+            let transactions = vec![Transaction::AssetTransfer {
+                network_id: 200,
+                shard_id: 0,
+                inputs: vec![AssetTransferInput {
+                    prev_out: AssetOutPoint {
+                        transaction_hash: H256::zero(),
+                        index: 0,
+                        asset_type: H256::zero(),
+                        amount: 0,
+                    },
+                    lock_script: vec![],
+                    unlock_script: vec![],
+                    timelock,
+                }],
+                outputs: vec![],
+                nonce: 0,
+            }];
+            Parcel {
+                nonce: nonce.into(),
+                fee: 100.into(),
+                transactions,
+                network_id: 200,
+            }.sign(keypair.private())
+        };
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: 1_000_000.into(),
+        };
+
+        let parcels = vec![
+            parcel(0, Some(Timelock::Block(4))),
+            parcel(1, None),
+            parcel(2, Some(Timelock::Time(1_004))),
+        ];
+        for parcel in &parcels {
+            assert_eq!(
+                Ok(ParcelImportResult::Future),
+                queue.add(parcel.clone(), ParcelOrigin::External, 0, &fetch_account)
+            );
+        }
+
+        // The next block is the 3rd one
+        queue.set_best_block(2, 1_001);
+        queue.remove_old(&fetch_account, 2);
+        assert!(queue.top_parcels().is_empty());
+        assert_eq!(3, queue.status().future);
+
+        queue.set_best_block(3, 1_002);
+        queue.remove_old(&fetch_account, 3);
+        assert_eq!(parcels[..2].to_vec(), queue.top_parcels());
+        assert_eq!(1, queue.status().future);
+
+        queue.set_best_block(4, 1_003);
+        queue.remove_old(&fetch_account, 4);
+        assert_eq!(parcels, queue.top_parcels());
+        assert_eq!(0, queue.status().future);
+    }
 }
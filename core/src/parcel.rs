@@ -23,10 +23,11 @@ use ctypes::{Address, Bytes, H160, H256, U256};
 use heapsize::HeapSizeOf;
 use rlp::{self, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
-use super::types::BlockNumber;
+use super::types::{BlockNumber, ShardId};
 use super::Transaction;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "type", content = "content")]
 /// Errors concerning parcel processing.
 pub enum ParcelError {
     /// Parcel is already imported to the queue
@@ -66,6 +67,8 @@ pub enum ParcelError {
     NotAllowed,
     /// Signature error
     InvalidSignature(String),
+    /// The parcel spends an asset whose timelock is not expired yet.
+    Timelocked(Timelock),
 }
 
 pub fn parcel_error_message(error: &ParcelError) -> String {
@@ -91,6 +94,8 @@ pub fn parcel_error_message(error: &ParcelError) -> String {
         } => format!("Invalid parcel nonce: expected {}, found {}", expected, got),
         NotAllowed => "Sender does not have permissions to execute this type of transction".into(),
         InvalidSignature(err) => format!("Parcel has invalid signature: {}.", err),
+        Timelocked(Timelock::Block(number)) => format!("Timelocked until the block {}", number),
+        Timelocked(Timelock::Time(timestamp)) => format!("Timelocked until the timestamp {}", timestamp),
     }
 }
 
@@ -146,6 +151,24 @@ impl Parcel {
         blake256(stream.as_raw())
     }
 
+    /// Checks whether every input of the parcel can be spent in the block of the number and the timestamp.
+    pub fn check_timelock(&self, number: BlockNumber, timestamp: u64) -> Result<(), ParcelError> {
+        for transaction in &self.transactions {
+            if let Transaction::AssetTransfer {
+                inputs,
+                ..
+            } = transaction
+            {
+                for timelock in inputs.iter().filter_map(|input| input.timelock) {
+                    if !timelock.is_expired(number, timestamp) {
+                        return Err(ParcelError::Timelocked(timelock))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Signs the parcel as coming from `sender`.
     pub fn sign(self, private: &Private) -> SignedParcel {
         let sig = sign_ecdsa(&private, &self.hash()).expect("data is valid and context has signing capabilities; qed");
@@ -404,29 +427,145 @@ pub struct AssetOutPoint {
     pub amount: u64,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AssetTransferInput {
     pub prev_out: AssetOutPoint,
     pub lock_script: Bytes,
     pub unlock_script: Bytes,
+    pub timelock: Option<Timelock>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable, Serialize)]
+// The timelock is appended only when it exists, so the inputs without it keep their encoding.
+impl Encodable for AssetTransferInput {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self.timelock {
+            Some(timelock) => {
+                s.begin_list(4);
+                s.append(&self.prev_out).append(&self.lock_script).append(&self.unlock_script).append(&timelock);
+            }
+            None => {
+                s.begin_list(3);
+                s.append(&self.prev_out).append(&self.lock_script).append(&self.unlock_script);
+            }
+        }
+    }
+}
+
+impl rlp::Decodable for AssetTransferInput {
+    fn decode(d: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let timelock = match d.item_count()? {
+            3 => None,
+            4 => Some(d.val_at(3)?),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        Ok(AssetTransferInput {
+            prev_out: d.val_at(0)?,
+            lock_script: d.val_at(1)?,
+            unlock_script: d.val_at(2)?,
+            timelock,
+        })
+    }
+}
+
+/// The input can't be spent until the timelock expires.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Timelock {
+    /// Spendable from the block of the number.
+    Block(BlockNumber),
+    /// Spendable from the block whose timestamp is not less than it.
+    Time(u64),
+}
+
+impl Timelock {
+    pub fn is_expired(&self, number: BlockNumber, timestamp: u64) -> bool {
+        match self {
+            Timelock::Block(block) => *block <= number,
+            Timelock::Time(time) => *time <= timestamp,
+        }
+    }
+}
+
+const TIMELOCK_BLOCK_ID: u8 = 0x01;
+const TIMELOCK_TIME_ID: u8 = 0x02;
+
+impl Encodable for Timelock {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            Timelock::Block(number) => s.begin_list(2).append(&TIMELOCK_BLOCK_ID).append(number),
+            Timelock::Time(timestamp) => s.begin_list(2).append(&TIMELOCK_TIME_ID).append(timestamp),
+        };
+    }
+}
+
+impl rlp::Decodable for Timelock {
+    fn decode(d: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if d.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        match d.val_at(0)? {
+            TIMELOCK_BLOCK_ID => Ok(Timelock::Block(d.val_at(1)?)),
+            TIMELOCK_TIME_ID => Ok(Timelock::Time(d.val_at(1)?)),
+            _ => Err(DecoderError::Custom("Unexpected timelock")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AssetTransferOutput {
     pub lock_script_hash: H256,
     pub parameters: Vec<Bytes>,
     pub asset_type: H256,
     pub amount: u64,
+    /// The shard to which the output is relayed. `None` means the shard of the transfer.
+    pub shard_id: Option<ShardId>,
+}
+
+// The shard id is appended only when it exists, so the outputs without it keep their encoding.
+impl Encodable for AssetTransferOutput {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self.shard_id {
+            Some(shard_id) => {
+                s.begin_list(5);
+                s.append(&self.lock_script_hash).append(&self.parameters).append(&self.asset_type).append(&self.amount);
+                s.append(&shard_id);
+            }
+            None => {
+                s.begin_list(4);
+                s.append(&self.lock_script_hash).append(&self.parameters).append(&self.asset_type).append(&self.amount);
+            }
+        }
+    }
+}
+
+impl rlp::Decodable for AssetTransferOutput {
+    fn decode(d: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let shard_id = match d.item_count()? {
+            4 => None,
+            5 => Some(d.val_at(4)?),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        Ok(AssetTransferOutput {
+            lock_script_hash: d.val_at(0)?,
+            parameters: d.val_at(1)?,
+            asset_type: d.val_at(2)?,
+            amount: d.val_at(3)?,
+            shard_id,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use ctypes::{Address, H256, Public, U256};
-    use rlp::Encodable;
+    use rlp::{Encodable, UntrustedRlp};
 
-    use super::{Parcel, Transaction, UnverifiedParcel};
+    use super::{
+        AssetOutPoint, AssetTransferInput, AssetTransferOutput, Parcel, ParcelError, Timelock, Transaction,
+        UnverifiedParcel,
+    };
 
     #[test]
     fn test_unverified_parcel_rlp() {
@@ -469,6 +608,7 @@ mod tests {
     #[test]
     fn encode_and_decode_asset_mint() {
         let transaction = Transaction::AssetMint {
+            shard_id: 0,
             metadata: "mint test".to_string(),
             lock_script_hash: H256::random(),
             parameters: vec![],
@@ -483,6 +623,7 @@ mod tests {
     #[test]
     fn encode_and_decode_asset_mint_with_parameters() {
         let transaction = Transaction::AssetMint {
+            shard_id: 0,
             metadata: "mint test".to_string(),
             lock_script_hash: H256::random(),
             parameters: vec![vec![1, 2, 3], vec![4, 5, 6], vec![0, 7]],
@@ -501,6 +642,7 @@ mod tests {
         let network_id = 0;
         let transaction = Transaction::AssetTransfer {
             network_id,
+            shard_id: 0,
             inputs,
             outputs,
             nonce: 0,
@@ -508,4 +650,73 @@ mod tests {
 
         assert_eq!(transaction, ::rlp::decode(transaction.rlp_bytes().as_ref()))
     }
+
+    #[test]
+    fn encode_and_decode_output_to_another_shard() {
+        let output = |shard_id| AssetTransferOutput {
+            lock_script_hash: H256::random(),
+            parameters: vec![vec![1]],
+            asset_type: H256::random(),
+            amount: 30,
+            shard_id,
+        };
+        let to_another_shard = output(Some(3));
+        assert_eq!(to_another_shard, ::rlp::decode(&::rlp::encode(&to_another_shard)));
+        assert_eq!(5, UntrustedRlp::new(&::rlp::encode(&to_another_shard)).item_count().unwrap());
+
+        let to_same_shard = output(None);
+        assert_eq!(to_same_shard, ::rlp::decode(&::rlp::encode(&to_same_shard)));
+        assert_eq!(4, UntrustedRlp::new(&::rlp::encode(&to_same_shard)).item_count().unwrap());
+    }
+
+    fn input(timelock: Option<Timelock>) -> AssetTransferInput {
+        AssetTransferInput {
+            prev_out: AssetOutPoint {
+                transaction_hash: H256::random(),
+                index: 0,
+                asset_type: H256::random(),
+                amount: 30,
+            },
+            lock_script: vec![0x30, 0x01],
+            unlock_script: vec![],
+            timelock,
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_timelocked_asset_transfer() {
+        let transaction = Transaction::AssetTransfer {
+            network_id: 0,
+            shard_id: 0,
+            inputs: vec![input(None), input(Some(Timelock::Block(10))), input(Some(Timelock::Time(1_530_000_000)))],
+            outputs: vec![],
+            nonce: 0,
+        };
+
+        assert_eq!(transaction, ::rlp::decode(transaction.rlp_bytes().as_ref()))
+    }
+
+    #[test]
+    fn input_without_timelock_keeps_its_encoding() {
+        assert_eq!(3, ::rlp::Rlp::new(&input(None).rlp_bytes()).item_count());
+        assert_eq!(4, ::rlp::Rlp::new(&input(Some(Timelock::Block(10))).rlp_bytes()).item_count());
+    }
+
+    #[test]
+    fn check_timelock() {
+        let parcel = Parcel {
+            transactions: vec![Transaction::AssetTransfer {
+                network_id: 0,
+                shard_id: 0,
+                inputs: vec![input(Some(Timelock::Block(10))), input(Some(Timelock::Time(1_000)))],
+                outputs: vec![],
+                nonce: 0,
+            }],
+            ..Parcel::default()
+        };
+
+        assert_eq!(Err(ParcelError::Timelocked(Timelock::Block(10))), parcel.check_timelock(9, 1_000));
+        assert_eq!(Err(ParcelError::Timelocked(Timelock::Time(1_000))), parcel.check_timelock(10, 999));
+        assert_eq!(Ok(()), parcel.check_timelock(10, 1_000));
+    }
 }
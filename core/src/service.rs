@@ -19,13 +19,19 @@ use std::sync::Arc;
 
 use cio::{IoContext, IoHandler, IoHandlerResult, IoService};
 use cnetwork::NodeId;
-use ctypes::Bytes;
+use ctypes::{Bytes, H256};
+use kvdb::KeyValueDB;
+use kvdb_memorydb;
+#[cfg(feature = "kvdb-rocksdb")]
 use kvdb_rocksdb::{Database, DatabaseConfig};
+use unexpected::Mismatch;
 
-use super::client::{Client, ClientConfig};
+use super::client::{Client, ClientConfig, DatabaseBackend, Error as ClientError};
+use super::db::{self, Readable};
 use super::error::Error;
 use super::miner::Miner;
 use super::spec::Spec;
+use super::types::BlockNumber;
 
 /// Client service setup.
 pub struct ClientService {
@@ -42,16 +48,11 @@ impl ClientService {
     ) -> Result<ClientService, Error> {
         let io_service = IoService::<ClientIoMessage>::start()?;
 
-        let mut db_config = DatabaseConfig::with_columns(super::db::NUM_COLUMNS);
-
-        db_config.memory_budget = config.db_cache_size;
-        db_config.compaction = config.db_compaction.compaction_profile(client_path);
-        db_config.wal = config.db_wal;
-
-        let db = Arc::new(Database::open(
-            &db_config,
-            &client_path.to_str().expect("DB path could not be converted to string."),
-        ).map_err(::client::Error::Database)?);
+        let db = open_database(&config, client_path)?;
+        match db::version(&*db).map_err(ClientError::Database)? {
+            Some(version) => check_version(version)?,
+            None => db::write_version(&*db).map_err(ClientError::Database)?,
+        }
 
         let client = Client::new(config, &spec, db, miner, io_service.channel())?;
 
@@ -73,6 +74,80 @@ impl ClientService {
     }
 }
 
+/// Checks that the node can run on the database at `client_path` without starting the client.
+/// Nothing is written, so a missing database is left to be created when the node starts.
+pub fn check_database(config: &ClientConfig, spec: &Spec, client_path: &Path) -> Result<(), Error> {
+    if config.db_backend == DatabaseBackend::Memory || !client_path.exists() {
+        return Ok(())
+    }
+    let db = open_database(config, client_path)?;
+    check_written_database(config, &*db, spec)
+}
+
+// The database which the node didn't write yet has neither the version, the pruning nor the genesis block.
+fn check_written_database(config: &ClientConfig, db: &KeyValueDB, spec: &Spec) -> Result<(), Error> {
+    if let Some(version) = db::version(db).map_err(ClientError::Database)? {
+        check_version(version)?;
+    }
+    if let Some(pruning) = db::pruning(db).map_err(ClientError::Database)? {
+        if pruning != config.pruning {
+            return Err(ClientError::PruningMismatch(Mismatch {
+                expected: pruning,
+                found: config.pruning,
+            }).into())
+        }
+    }
+    let genesis_hash: Option<H256> = db.read(db::COL_EXTRA, &(0 as BlockNumber));
+    if let Some(genesis_hash) = genesis_hash {
+        let expected = spec.genesis_header().hash();
+        if genesis_hash != expected {
+            return Err(ClientError::GenesisMismatch(Mismatch {
+                expected,
+                found: genesis_hash,
+            }).into())
+        }
+    }
+    Ok(())
+}
+
+fn check_version(version: u32) -> Result<(), Error> {
+    if version != db::DB_VERSION {
+        return Err(ClientError::DatabaseVersion(Mismatch {
+            expected: db::DB_VERSION,
+            found: version,
+        }).into())
+    }
+    Ok(())
+}
+
+fn open_database(config: &ClientConfig, client_path: &Path) -> Result<Arc<KeyValueDB>, Error> {
+    match config.db_backend {
+        DatabaseBackend::RocksDB => open_rocksdb(config, client_path),
+        DatabaseBackend::Memory => {
+            warn!(target: "client", "The chain is kept in memory and will be lost when the client stops");
+            Ok(Arc::new(kvdb_memorydb::create(super::db::NUM_COLUMNS.unwrap_or(0))))
+        }
+    }
+}
+
+#[cfg(feature = "kvdb-rocksdb")]
+fn open_rocksdb(config: &ClientConfig, client_path: &Path) -> Result<Arc<KeyValueDB>, Error> {
+    let mut db_config = DatabaseConfig::with_columns(super::db::NUM_COLUMNS);
+
+    db_config.memory_budget = config.db_cache_size;
+    db_config.compaction = config.db_compaction.compaction_profile(client_path);
+    db_config.wal = config.db_wal;
+
+    let db = Database::open(&db_config, &client_path.to_str().expect("DB path could not be converted to string."))
+        .map_err(::client::Error::Database)?;
+    Ok(Arc::new(db))
+}
+
+#[cfg(not(feature = "kvdb-rocksdb"))]
+fn open_rocksdb(_config: &ClientConfig, _client_path: &Path) -> Result<Arc<KeyValueDB>, Error> {
+    Err(::client::Error::Database("This build does not support RocksDB".into()).into())
+}
+
 /// Message type for external and internal events
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum ClientIoMessage {
@@ -105,3 +180,76 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use journaldb::Algorithm;
+    use kvdb::DBTransaction;
+    use rlp;
+
+    use super::super::db::Writable;
+    use super::*;
+
+    fn memory_db() -> Arc<KeyValueDB> {
+        Arc::new(kvdb_memorydb::create(db::NUM_COLUMNS.unwrap_or(0)))
+    }
+
+    fn write_genesis_hash(db: &KeyValueDB, genesis_hash: H256) {
+        let mut batch = DBTransaction::new();
+        batch.write(db::COL_EXTRA, &(0 as BlockNumber), &genesis_hash);
+        db.write(batch).unwrap();
+    }
+
+    #[test]
+    fn empty_database_is_usable() {
+        assert!(check_written_database(&ClientConfig::default(), &*memory_db(), &Spec::new_test()).is_ok());
+    }
+
+    #[test]
+    fn database_of_the_same_genesis_is_usable() {
+        let spec = Spec::new_test();
+        let db = memory_db();
+        db::write_version(&*db).unwrap();
+        write_genesis_hash(&*db, spec.genesis_header().hash());
+        assert!(check_written_database(&ClientConfig::default(), &*db, &spec).is_ok());
+    }
+
+    #[test]
+    fn database_of_another_version_is_rejected() {
+        let db = memory_db();
+        let mut batch = DBTransaction::new();
+        batch.put(db::COL_EXTRA, b"db-version", &rlp::encode(&(db::DB_VERSION + 1)));
+        db.write(batch).unwrap();
+        match check_written_database(&ClientConfig::default(), &*db, &Spec::new_test()) {
+            Err(Error::Client(ClientError::DatabaseVersion(_))) => {}
+            _ => panic!("DatabaseVersion expected"),
+        }
+    }
+
+    #[test]
+    fn database_of_another_genesis_is_rejected() {
+        let db = memory_db();
+        write_genesis_hash(&*db, H256::random());
+        match check_written_database(&ClientConfig::default(), &*db, &Spec::new_test()) {
+            Err(Error::Client(ClientError::GenesisMismatch(_))) => {}
+            _ => panic!("GenesisMismatch expected"),
+        }
+    }
+
+    #[test]
+    fn database_of_another_pruning_is_rejected() {
+        let db = memory_db();
+        db::write_pruning(&*db, Algorithm::OverlayRecent).unwrap();
+        match check_written_database(&ClientConfig::default(), &*db, &Spec::new_test()) {
+            Err(Error::Client(ClientError::PruningMismatch(_))) => {}
+            _ => panic!("PruningMismatch expected"),
+        }
+    }
+
+    #[test]
+    fn missing_database_is_left_to_the_node() {
+        let path = Path::new("this database does not exist");
+        assert!(check_database(&ClientConfig::default(), &Spec::new_test(), path).is_ok());
+        assert!(!path.exists());
+    }
+}
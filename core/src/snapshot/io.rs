@@ -0,0 +1,87 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ctypes::{Bytes, H256};
+use rlp;
+
+use super::{Error, SignedManifest};
+
+const MANIFEST_FILE_NAME: &'static str = "MANIFEST";
+
+fn chunk_path(dir: &Path, hash: &H256) -> PathBuf {
+    dir.join(format!("{:x}", hash))
+}
+
+/// Writes a snapshot into a directory. Every chunk is a file named after its hash.
+pub struct SnapshotWriter {
+    dir: PathBuf,
+}
+
+impl SnapshotWriter {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn write_chunk(&mut self, hash: &H256, chunk: &[u8]) -> Result<(), Error> {
+        File::create(chunk_path(&self.dir, hash))?.write_all(chunk)?;
+        Ok(())
+    }
+
+    /// The manifest is written last, so a directory without it is an incomplete snapshot.
+    pub fn finish(self, manifest: &SignedManifest) -> Result<(), Error> {
+        File::create(self.dir.join(MANIFEST_FILE_NAME))?.write_all(&rlp::encode(manifest))?;
+        Ok(())
+    }
+}
+
+/// Reads a snapshot written by `SnapshotWriter`.
+pub struct SnapshotReader {
+    dir: PathBuf,
+    manifest: SignedManifest,
+}
+
+impl SnapshotReader {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        File::open(dir.as_ref().join(MANIFEST_FILE_NAME))?.read_to_end(&mut bytes)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            manifest: rlp::UntrustedRlp::new(&bytes).as_val()?,
+        })
+    }
+
+    pub fn manifest(&self) -> &SignedManifest {
+        &self.manifest
+    }
+
+    /// Returns `None` if the chunk is not in this snapshot.
+    pub fn chunk(&self, hash: &H256) -> Result<Option<Bytes>, Error> {
+        if !self.manifest.manifest.chunks().contains(hash) {
+            return Ok(None)
+        }
+        let mut bytes = Vec::new();
+        File::open(chunk_path(&self.dir, hash))?.read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+}
@@ -0,0 +1,572 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! State snapshots.
+//!
+//! A snapshot is the state of a block split into chunks. Every chunk is a list of the key-value pairs of the state
+//! trie and is addressed by its hash, so that a node can fetch the chunks from any peer and check each of them on
+//! arrival. The pairs of the state tries of the shards follow the pairs of the world trie, tagged with their shard
+//! ids. The manifest lists the chunks and is signed by the node which took the snapshot.
+
+mod io;
+mod service;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use ccrypto::{blake256, BLAKE_NULL_RLP};
+use ckeys::{public_to_address, recover_ecdsa, sign_ecdsa, Address, Error as KeyError, Private};
+use ctypes::{Bytes, H256, H520};
+use hashdb::{DBValue, HashDB};
+use kvdb::{DBTransaction, KeyValueDB};
+use memorydb::MemoryDB;
+use rlp::{self, DecoderError, RlpStream, UntrustedRlp};
+use trie::{Trie, TrieDB, TrieDBMut, TrieError, TrieMut};
+use unexpected::Mismatch;
+use util_error::UtilError;
+
+use super::state::{Shard, ShardAddress};
+use super::types::{BlockNumber, ShardId};
+
+pub use self::io::{SnapshotReader, SnapshotWriter};
+pub use self::service::Service as SnapshotService;
+
+/// The version of the snapshot format.
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+/// The chunks are cut when they grow over this size. A chunk can be a bit larger since a key-value pair is not split.
+pub const PREFERRED_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The number of blocks between the snapshots.
+pub const DEFAULT_SNAPSHOT_PERIOD: u64 = 1 << 14;
+
+/// The rebuilder writes the nodes to the database whenever they grow over this size in memory.
+const MAX_OVERLAY_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The block is not in the chain.
+    UnknownBlock(BlockNumber),
+    /// The chain already has blocks other than the genesis block.
+    ChainNotEmpty,
+    /// The restored state doesn't match the state root in the manifest.
+    StateRootMismatch(Mismatch<H256>),
+    /// The block doesn't match the manifest.
+    BlockMismatch(Mismatch<H256>),
+    Trie(TrieError),
+    Decoder(DecoderError),
+    Key(KeyError),
+    Util(UtilError),
+    Io(::std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownBlock(number) => write!(f, "Block #{} is not in the chain", number),
+            Error::ChainNotEmpty => write!(f, "A snapshot can be restored only on the genesis block"),
+            Error::StateRootMismatch(mismatch) => write!(f, "Restored state root mismatch: {}", mismatch),
+            Error::BlockMismatch(mismatch) => write!(f, "Snapshot block mismatch: {}", mismatch),
+            Error::Trie(err) => err.fmt(f),
+            Error::Decoder(err) => err.fmt(f),
+            Error::Key(err) => err.fmt(f),
+            Error::Util(err) => err.fmt(f),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<Box<TrieError>> for Error {
+    fn from(err: Box<TrieError>) -> Self {
+        Error::Trie(*err)
+    }
+}
+
+impl From<DecoderError> for Error {
+    fn from(err: DecoderError) -> Self {
+        Error::Decoder(err)
+    }
+}
+
+impl From<KeyError> for Error {
+    fn from(err: KeyError) -> Self {
+        Error::Key(err)
+    }
+}
+
+impl From<UtilError> for Error {
+    fn from(err: UtilError) -> Self {
+        Error::Util(err)
+    }
+}
+
+impl From<::std::io::Error> for Error {
+    fn from(err: ::std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Describes the chunks of a snapshot.
+#[derive(Debug, Clone, PartialEq, RlpEncodable, RlpDecodable)]
+pub struct ManifestData {
+    pub version: u64,
+    /// Hashes of the state chunks.
+    pub state_chunks: Vec<H256>,
+    /// Hash of the chunk which has the block of the snapshot.
+    pub block_chunk: H256,
+    pub state_root: H256,
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+}
+
+impl ManifestData {
+    pub fn hash(&self) -> H256 {
+        blake256(&rlp::encode(self))
+    }
+
+    /// Every chunk including the block chunk.
+    pub fn chunks(&self) -> Vec<H256> {
+        let mut chunks = self.state_chunks.clone();
+        chunks.push(self.block_chunk);
+        chunks
+    }
+}
+
+/// The manifest with the signature of the node which took the snapshot.
+#[derive(Debug, Clone, PartialEq, RlpEncodable, RlpDecodable)]
+pub struct SignedManifest {
+    pub manifest: ManifestData,
+    signature: H520,
+}
+
+impl SignedManifest {
+    pub fn new(manifest: ManifestData, private: &Private) -> Result<Self, Error> {
+        let signature = sign_ecdsa(private, &manifest.hash())?;
+        Ok(Self {
+            manifest,
+            signature: signature.into(),
+        })
+    }
+
+    /// The address of the node which signed the manifest.
+    pub fn signer(&self) -> Result<Address, Error> {
+        let public = recover_ecdsa(&self.signature.into(), &self.manifest.hash())?;
+        Ok(public_to_address(&public))
+    }
+}
+
+/// Splits the state trie into chunks. `write_chunk` is called with the hash and the bytes of every chunk.
+pub fn chunk_state<F>(db: &HashDB, root: &H256, chunk_size: usize, mut write_chunk: F) -> Result<Vec<H256>, Error>
+where
+    F: FnMut(&H256, &[u8]) -> Result<(), Error>, {
+    let trie = TrieDB::new(db, root)?;
+
+    let mut hashes = Vec::new();
+    {
+        let mut flush = |pairs: &mut Vec<(Bytes, Bytes, Option<ShardId>)>| -> Result<(), Error> {
+            let mut stream = RlpStream::new_list(pairs.len());
+            for (key, value, shard_id) in pairs.drain(..) {
+                match shard_id {
+                    Some(shard_id) => stream.begin_list(3).append(&key).append(&value).append(&shard_id),
+                    None => stream.begin_list(2).append(&key).append(&value),
+                };
+            }
+            let chunk = stream.out();
+            let hash = blake256(&chunk);
+            write_chunk(&hash, &chunk)?;
+            hashes.push(hash);
+            Ok(())
+        };
+
+        let mut pairs = Vec::new();
+        let mut size = 0;
+        let mut shards = Vec::new();
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            if let Some(address) = shard_address(&key) {
+                let shard: Shard = UntrustedRlp::new(&value).as_val()?;
+                shards.push((address.shard_id(), *shard.root()));
+            }
+            size += key.len() + value.len();
+            pairs.push((key, value.into_vec(), None));
+            if size >= chunk_size {
+                flush(&mut pairs)?;
+                size = 0;
+            }
+        }
+        for (shard_id, shard_root) in shards {
+            let shard_trie = TrieDB::new(db, &shard_root)?;
+            for item in shard_trie.iter()? {
+                let (key, value) = item?;
+                size += key.len() + value.len();
+                pairs.push((key, value.into_vec(), Some(shard_id)));
+                if size >= chunk_size {
+                    flush(&mut pairs)?;
+                    size = 0;
+                }
+            }
+        }
+        if !pairs.is_empty() {
+            flush(&mut pairs)?;
+        }
+    }
+    Ok(hashes)
+}
+
+fn shard_address(key: &[u8]) -> Option<ShardAddress> {
+    if key.len() != 32 {
+        return None
+    }
+    ShardAddress::from_hash(H256::from_slice(key))
+}
+
+/// The nodes of the state being rebuilt, on top of the nodes in the state column of the database.
+struct RebuildDB {
+    overlay: MemoryDB,
+    backing: Arc<KeyValueDB>,
+    column: Option<u32>,
+}
+
+impl RebuildDB {
+    /// Moves the inserted nodes to the batch. The removed nodes are left in the database, because the other states,
+    /// e.g. the genesis state, may have them too.
+    fn drain_into(&mut self, batch: &mut DBTransaction) {
+        for (key, (value, rc)) in self.overlay.drain() {
+            if rc > 0 {
+                batch.put(self.column, &key, &value);
+            }
+        }
+    }
+}
+
+impl HashDB for RebuildDB {
+    fn keys(&self) -> HashMap<H256, i32> {
+        self.overlay.keys()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        if let Some(value) = self.overlay.get(key) {
+            return Some(value)
+        }
+        self.backing.get(self.column, key).expect("Low-level database error. Some issue with your hard disk?")
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, value: &[u8]) -> H256 {
+        self.overlay.insert(value)
+    }
+
+    fn emplace(&mut self, key: H256, value: DBValue) {
+        self.overlay.emplace(key, value);
+    }
+
+    fn remove(&mut self, key: &H256) {
+        self.overlay.remove(key);
+    }
+}
+
+/// Rebuilds the state trie from the chunks. The chunks can be fed in any order.
+///
+/// The nodes are written to the state column of the database in batches, so that a large state doesn't stay in
+/// memory. The client journals the rebuilt state under the block of the snapshot once it's finalized.
+pub struct StateRebuilder {
+    db: RebuildDB,
+    state_root: H256,
+    shard_roots: HashMap<ShardId, H256>,
+    /// The roots of the shards which the world trie has.
+    expected_shard_roots: HashMap<ShardId, H256>,
+}
+
+impl StateRebuilder {
+    pub fn new(backing: Arc<KeyValueDB>, column: Option<u32>) -> Self {
+        Self {
+            db: RebuildDB {
+                overlay: MemoryDB::new(),
+                backing,
+                column,
+            },
+            state_root: BLAKE_NULL_RLP,
+            shard_roots: HashMap::new(),
+            expected_shard_roots: HashMap::new(),
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        let mut world_pairs = Vec::new();
+        let mut shard_pairs: HashMap<ShardId, Vec<(Bytes, Bytes)>> = HashMap::new();
+        for pair in UntrustedRlp::new(chunk).iter() {
+            let key: Bytes = pair.val_at(0)?;
+            let value: Bytes = pair.val_at(1)?;
+            match pair.item_count()? {
+                2 => {
+                    if let Some(address) = shard_address(&key) {
+                        let shard: Shard = UntrustedRlp::new(&value).as_val()?;
+                        self.expected_shard_roots.insert(address.shard_id(), *shard.root());
+                    }
+                    world_pairs.push((key, value));
+                }
+                3 => shard_pairs.entry(pair.val_at(2)?).or_insert_with(Vec::new).push((key, value)),
+                _ => return Err(DecoderError::RlpIncorrectListLen.into()),
+            }
+        }
+
+        insert_pairs(&mut self.db, &mut self.state_root, world_pairs)?;
+        for (shard_id, pairs) in shard_pairs {
+            let shard_root = self.shard_roots.entry(shard_id).or_insert(BLAKE_NULL_RLP);
+            insert_pairs(&mut self.db, shard_root, pairs)?;
+        }
+
+        if self.db.overlay.mem_used() > MAX_OVERLAY_SIZE {
+            let mut batch = DBTransaction::new();
+            self.db.drain_into(&mut batch);
+            self.db.backing.write(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the state roots and puts the rest of the nodes in the batch.
+    pub fn finalize(mut self, batch: &mut DBTransaction, manifest: &ManifestData) -> Result<(), Error> {
+        if self.state_root != manifest.state_root {
+            return Err(Error::StateRootMismatch(Mismatch {
+                expected: manifest.state_root,
+                found: self.state_root,
+            }))
+        }
+        for (shard_id, expected) in &self.expected_shard_roots {
+            let found = self.shard_roots.get(shard_id).cloned().unwrap_or(BLAKE_NULL_RLP);
+            if *expected != found {
+                return Err(Error::StateRootMismatch(Mismatch {
+                    expected: *expected,
+                    found,
+                }))
+            }
+        }
+        for (shard_id, found) in &self.shard_roots {
+            if !self.expected_shard_roots.contains_key(shard_id) {
+                return Err(Error::StateRootMismatch(Mismatch {
+                    expected: BLAKE_NULL_RLP,
+                    found: *found,
+                }))
+            }
+        }
+        self.db.drain_into(batch);
+        Ok(())
+    }
+}
+
+fn insert_pairs(db: &mut HashDB, root: &mut H256, pairs: Vec<(Bytes, Bytes)>) -> Result<(), Error> {
+    if pairs.is_empty() {
+        return Ok(())
+    }
+    let mut trie = if *root == BLAKE_NULL_RLP {
+        TrieDBMut::new(db, root)
+    } else {
+        TrieDBMut::from_existing(db, root)?
+    };
+    for (key, value) in pairs {
+        trie.insert(&key, &value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ckeys::{Generator, Random};
+    use journaldb::{self, Algorithm};
+    use kvdb_memorydb;
+    use memorydb::MemoryDB;
+
+    use super::*;
+
+    fn new_rebuilder() -> StateRebuilder {
+        StateRebuilder::new(Arc::new(kvdb_memorydb::create(0)), None)
+    }
+
+    fn build_trie(db: &mut HashDB, count: u32) -> H256 {
+        let mut root = H256::new();
+        {
+            let mut trie = TrieDBMut::new(db, &mut root);
+            for i in 0..count {
+                let key = blake256(&rlp::encode(&i));
+                trie.insert(&key, &rlp::encode(&(i * 7)).into_vec()).unwrap();
+            }
+        }
+        root
+    }
+
+    fn take_chunks(db: &HashDB, root: &H256, chunk_size: usize) -> (Vec<H256>, HashMap<H256, Bytes>) {
+        let mut chunks = HashMap::new();
+        let hashes = chunk_state(db, root, chunk_size, |hash, chunk| {
+            chunks.insert(*hash, chunk.to_vec());
+            Ok(())
+        }).unwrap();
+        (hashes, chunks)
+    }
+
+    fn manifest(state_root: H256, state_chunks: Vec<H256>) -> ManifestData {
+        ManifestData {
+            version: SNAPSHOT_VERSION,
+            state_chunks,
+            block_chunk: H256::random(),
+            state_root,
+            block_number: 10,
+            block_hash: H256::random(),
+        }
+    }
+
+    #[test]
+    fn chunks_are_addressed_by_their_hashes() {
+        let mut db = MemoryDB::new();
+        let root = build_trie(&mut db, 100);
+        let (hashes, chunks) = take_chunks(&db, &root, 256);
+        assert!(hashes.len() > 1);
+        for hash in hashes {
+            assert_eq!(hash, blake256(&chunks[&hash]));
+        }
+    }
+
+    #[test]
+    fn state_is_rebuilt_from_chunks_in_any_order() {
+        let mut db = MemoryDB::new();
+        let root = build_trie(&mut db, 100);
+        let (mut hashes, chunks) = take_chunks(&db, &root, 256);
+        let manifest = manifest(root, hashes.clone());
+
+        hashes.reverse();
+        let mut rebuilder = new_rebuilder();
+        for hash in hashes {
+            rebuilder.feed(&chunks[&hash]).unwrap();
+        }
+        let mut batch = DBTransaction::new();
+        rebuilder.finalize(&mut batch, &manifest).unwrap();
+    }
+
+    #[test]
+    fn missing_chunk_is_detected() {
+        let mut db = MemoryDB::new();
+        let root = build_trie(&mut db, 100);
+        let (hashes, chunks) = take_chunks(&db, &root, 256);
+        let manifest = manifest(root, hashes.clone());
+
+        let mut rebuilder = new_rebuilder();
+        for hash in hashes.iter().skip(1) {
+            rebuilder.feed(&chunks[hash]).unwrap();
+        }
+        let mut batch = DBTransaction::new();
+        match rebuilder.finalize(&mut batch, &manifest) {
+            Err(Error::StateRootMismatch(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    fn build_sharded_state(db: &mut HashDB) -> H256 {
+        let shard_root = build_trie(db, 50);
+        let mut root = build_trie(db, 50);
+        {
+            let mut trie = TrieDBMut::from_existing(db, &mut root).unwrap();
+            trie.insert(ShardAddress::new(3).as_ref(), &rlp::encode(&Shard::new(shard_root))).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn sharded_state_is_rebuilt_from_chunks() {
+        let mut db = MemoryDB::new();
+        let root = build_sharded_state(&mut db);
+        let (hashes, chunks) = take_chunks(&db, &root, 256);
+        let manifest = manifest(root, hashes.clone());
+
+        let mut rebuilder = new_rebuilder();
+        for hash in hashes.iter().rev() {
+            rebuilder.feed(&chunks[hash]).unwrap();
+        }
+        let mut batch = DBTransaction::new();
+        rebuilder.finalize(&mut batch, &manifest).unwrap();
+    }
+
+    #[test]
+    fn missing_chunk_of_shard_is_detected() {
+        let mut db = MemoryDB::new();
+        let root = build_sharded_state(&mut db);
+        let (hashes, chunks) = take_chunks(&db, &root, 256);
+        let manifest = manifest(root, hashes.clone());
+
+        // The last chunk only has the pairs of the shard.
+        let mut rebuilder = new_rebuilder();
+        for hash in hashes.iter().take(hashes.len() - 1) {
+            rebuilder.feed(&chunks[hash]).unwrap();
+        }
+        let mut batch = DBTransaction::new();
+        match rebuilder.finalize(&mut batch, &manifest) {
+            Err(Error::StateRootMismatch(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rebuilt_state_is_written_on_the_existing_state() {
+        let mut db = MemoryDB::new();
+        let root = build_trie(&mut db, 100);
+        let (hashes, chunks) = take_chunks(&db, &root, 256);
+        let manifest = manifest(root, hashes.clone());
+
+        // Some nodes of the existing state are the same as the nodes of the rebuilt state.
+        let backing: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(0));
+        let mut existing = MemoryDB::new();
+        build_trie(&mut existing, 50);
+        let mut batch = DBTransaction::new();
+        for (key, (value, _)) in existing.drain() {
+            batch.put(None, &key, &value);
+        }
+        backing.write(batch).unwrap();
+
+        let mut rebuilder = StateRebuilder::new(backing.clone(), None);
+        for hash in &hashes {
+            rebuilder.feed(&chunks[hash]).unwrap();
+        }
+        let mut batch = DBTransaction::new();
+        rebuilder.finalize(&mut batch, &manifest).unwrap();
+        backing.write(batch).unwrap();
+
+        let journal_db = journaldb::new(backing, Algorithm::Archive, None);
+        let trie = TrieDB::new(journal_db.as_hashdb(), &root).unwrap();
+        assert_eq!(100, trie.iter().unwrap().count());
+    }
+
+    #[test]
+    fn empty_state_has_no_chunks() {
+        let db = MemoryDB::new();
+        let (hashes, _) = take_chunks(&db, &BLAKE_NULL_RLP, 256);
+        assert!(hashes.is_empty());
+    }
+
+    #[test]
+    fn signer_of_manifest() {
+        let key_pair = Random.generate().unwrap();
+        let signed = SignedManifest::new(manifest(H256::random(), vec![H256::random()]), key_pair.private()).unwrap();
+        assert_eq!(key_pair.address(), signed.signer().unwrap());
+
+        let decoded: SignedManifest = rlp::decode(&rlp::encode(&signed));
+        assert_eq!(signed, decoded);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use ckeys::Private;
+use ctypes::H256;
+use parking_lot::RwLock;
+
+use super::super::client::{BlockChainClient, ChainNotify, Client};
+use super::super::types::{BlockId, BlockNumber};
+use super::{Error, SignedManifest, SnapshotReader, SnapshotWriter};
+
+const LATEST_SNAPSHOT_DIR_NAME: &'static str = "latest";
+
+/// Takes a snapshot every `period` canonical blocks and keeps only the latest one.
+pub struct Service {
+    client: Arc<Client>,
+    dir: PathBuf,
+    period: u64,
+    private: Private,
+    latest: Arc<RwLock<Option<SnapshotReader>>>,
+    is_taking: Arc<AtomicBool>,
+}
+
+impl Service {
+    /// The manifests are signed with `private`.
+    pub fn new<P: AsRef<Path>>(client: Arc<Client>, dir: P, period: u64, private: Private) -> Arc<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let latest = SnapshotReader::open(dir.join(LATEST_SNAPSHOT_DIR_NAME)).ok();
+        Arc::new(Self {
+            client,
+            dir,
+            period,
+            private,
+            latest: Arc::new(RwLock::new(latest)),
+            is_taking: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Calls `f` with the latest snapshot if there is one.
+    pub fn with_latest<F, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&SnapshotReader) -> T, {
+        self.latest.read().as_ref().map(f)
+    }
+
+    fn spawn(&self, number: BlockNumber) {
+        if self.is_taking.swap(true, Ordering::SeqCst) {
+            debug!(target: "snapshot", "Skip the snapshot at #{} since the previous one is not finished", number);
+            return
+        }
+        let client = Arc::clone(&self.client);
+        let dir = self.dir.clone();
+        let private = self.private.clone();
+        let latest = Arc::clone(&self.latest);
+        let is_taking = Arc::clone(&self.is_taking);
+        let spawned = thread::Builder::new().name("snapshot".to_string()).spawn(move || {
+            match take(&client, &dir, number, &private, &latest) {
+                Ok(()) => info!(target: "snapshot", "Took the snapshot at #{}", number),
+                Err(err) => warn!(target: "snapshot", "Cannot take the snapshot at #{}: {}", number, err),
+            }
+            is_taking.store(false, Ordering::SeqCst);
+        });
+        if let Err(err) = spawned {
+            warn!(target: "snapshot", "Cannot spawn the snapshot thread: {}", err);
+            self.is_taking.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// The snapshot is written aside and replaces the latest one only when it is complete.
+fn take(
+    client: &Client,
+    dir: &Path,
+    number: BlockNumber,
+    private: &Private,
+    latest: &RwLock<Option<SnapshotReader>>,
+) -> Result<(), Error> {
+    let temp_dir = dir.join(format!("{}.tmp", number));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    let mut writer = SnapshotWriter::new(&temp_dir)?;
+    let manifest = client.take_snapshot(number, &mut writer)?;
+    writer.finish(&SignedManifest::new(manifest, private)?)?;
+
+    let latest_dir = dir.join(LATEST_SNAPSHOT_DIR_NAME);
+    let mut latest = latest.write();
+    *latest = None;
+    if latest_dir.exists() {
+        fs::remove_dir_all(&latest_dir)?;
+    }
+    fs::rename(&temp_dir, &latest_dir)?;
+    *latest = Some(SnapshotReader::open(&latest_dir)?);
+    Ok(())
+}
+
+impl ChainNotify for Service {
+    fn new_blocks(
+        &self,
+        _imported: Vec<H256>,
+        _invalid: Vec<H256>,
+        enacted: Vec<H256>,
+        _retracted: Vec<H256>,
+        _sealed: Vec<H256>,
+        _duration: u64,
+    ) {
+        let number = enacted
+            .into_iter()
+            .filter_map(|hash| self.client.block_number(BlockId::Hash(hash)))
+            .filter(|number| *number != 0 && *number % self.period == 0)
+            .max();
+        if let Some(number) = number {
+            self.spawn(number);
+        }
+    }
+}
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
 use std::io::Read;
 use std::sync::Arc;
 
@@ -23,7 +24,7 @@ use ctypes::{Address, Bytes, H256, U256};
 use memorydb::MemoryDB;
 use parking_lot::RwLock;
 use rlp::{Rlp, RlpStream};
-use state::Backend;
+use state::{Backend, CacheableItem, Shard, ShardAddress};
 use trie::TrieFactory;
 
 use super::super::codechain_machine::CodeChainMachine;
@@ -31,6 +32,7 @@ use super::super::consensus::{CodeChainEngine, NullEngine, Solo, SoloAuthority,
 use super::super::error::Error;
 use super::super::header::Header;
 use super::super::pod_state::PodState;
+use super::super::types::{BlockNumber, ShardId};
 use super::super::state::backend::Basic as BasicBackend;
 use super::seal::Generic as GenericSeal;
 use super::Genesis;
@@ -45,6 +47,17 @@ pub struct CommonParams {
     pub network_id: u64,
     /// Minimum parcel cost.
     pub min_parcel_cost: U256,
+    /// Number of shards in the genesis state.
+    pub shard_count: ShardId,
+    /// Trusted block hashes by their numbers.
+    pub checkpoints: BTreeMap<BlockNumber, H256>,
+}
+
+impl CommonParams {
+    /// The number and the hash of the highest checkpoint.
+    pub fn last_checkpoint(&self) -> Option<(BlockNumber, H256)> {
+        self.checkpoints.iter().next_back().map(|(number, hash)| (*number, *hash))
+    }
 }
 
 impl From<cjson::spec::Params> for CommonParams {
@@ -54,6 +67,11 @@ impl From<cjson::spec::Params> for CommonParams {
             maximum_extra_data_size: p.maximum_extra_data_size.into(),
             network_id: p.network_id.into(),
             min_parcel_cost: p.min_parcel_cost.into(),
+            shard_count: p.shard_count.map_or(1, Into::into),
+            checkpoints: p
+                .checkpoints
+                .map(|checkpoints| checkpoints.into_iter().map(|(number, hash)| (number.into(), hash.into())).collect())
+                .unwrap_or_default(),
         }
     }
 }
@@ -142,6 +160,11 @@ impl Spec {
             for (address, account) in self.genesis_state.get().iter() {
                 t.insert(&**address, &account.rlp())?;
             }
+
+            let empty_shard = Shard::new(BLAKE_NULL_RLP).rlp();
+            for shard_id in 0..self.params().shard_count {
+                t.insert(ShardAddress::new(shard_id).as_ref(), &empty_shard)?;
+            }
         }
 
         *self.state_root_memo.write() = root;
@@ -209,6 +232,7 @@ impl Spec {
         header.set_parcels_root(self.parcels_root.clone());
         header.set_extra_data(self.extra_data.clone());
         header.set_state_root(self.state_root());
+        header.set_shard_roots(vec![BLAKE_NULL_RLP; self.params().shard_count as usize]);
         header.set_invoices_root(self.invoices_root.clone());
         header.set_score(self.score.clone());
         header.set_seal({
@@ -263,3 +287,78 @@ fn load_from(s: cjson::spec::Spec) -> Result<Spec, Error> {
 
     Ok(s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::state::State;
+    use super::super::super::tests::helpers::get_temp_state_db;
+    use super::super::super::views::BlockView;
+    use super::*;
+
+    const CUSTOM_SPEC: &str = r#"{
+        "name": "Custom",
+        "engine": {
+            "solo": {
+                "params": {}
+            }
+        },
+        "params": {
+            "maximumExtraDataSize": "0x20",
+            "networkID": "0x2a",
+            "minParcelCost": "5"
+        },
+        "genesis": {
+            "seal": {
+                "generic": "0x0"
+            },
+            "score": "0x400",
+            "author": "0x0000000000000000000000000000000000000001",
+            "timestamp": "0x5b000000",
+            "parentHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "extraData": "0x"
+        },
+        "accounts": {
+            "0000000000000000000000000000000000000001": { "balance": "100", "nonce": "0" }
+        }
+    }"#;
+
+    #[test]
+    fn custom_spec_builds_the_genesis_and_the_engine() {
+        let spec = Spec::load(CUSTOM_SPEC.as_bytes()).unwrap();
+        assert_eq!("Custom", spec.name);
+        assert_eq!("Solo", spec.engine.name());
+        assert_eq!(0x2a, spec.params().network_id);
+        assert_eq!(U256::from(5), spec.params().min_parcel_cost);
+        assert_eq!(1, spec.params().shard_count);
+
+        let header = spec.genesis_header();
+        assert_eq!(0, header.number());
+        assert_eq!(0x5b00_0000, header.timestamp());
+        assert_eq!(&U256::from(0x400), header.score());
+        assert_eq!(&Address::from(1), header.author());
+        assert_ne!(BLAKE_NULL_RLP, *header.state_root());
+
+        let genesis = spec.genesis_block();
+        assert_eq!(header.hash(), BlockView::new(&genesis).header_view().hash());
+    }
+
+    #[test]
+    fn genesis_state_has_the_empty_shards() {
+        let custom = CUSTOM_SPEC.replace(r#""minParcelCost": "5""#, r#""minParcelCost": "5", "shardCount": "0x2""#);
+        let spec = Spec::load(custom.as_bytes()).unwrap();
+        assert_eq!(2, spec.params().shard_count);
+
+        let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+        let state = State::from_existing(db, spec.state_root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(Some(BLAKE_NULL_RLP), state.shard_root(0).unwrap());
+        assert_eq!(Some(BLAKE_NULL_RLP), state.shard_root(1).unwrap());
+        assert_eq!(None, state.shard_root(2).unwrap());
+        assert_eq!(vec![BLAKE_NULL_RLP; 2], state.shard_roots().unwrap());
+        assert_eq!(&[BLAKE_NULL_RLP; 2][..], spec.genesis_header().shard_roots());
+    }
+
+    #[test]
+    fn invalid_spec_is_rejected() {
+        assert!(Spec::load(&b"{}"[..]).is_err());
+    }
+}
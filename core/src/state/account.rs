@@ -18,8 +18,11 @@
 
 use std::fmt;
 
-use ctypes::{self, Bytes, Public, U256};
+use ctypes::{self, Address, Bytes, Public, H256, U256};
+use hashdb::HashDB;
+use memorydb::MemoryDB;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use trie::{SecTrieDB, Trie};
 
 use super::CacheableItem;
 
@@ -148,6 +151,22 @@ impl fmt::Debug for Account {
     }
 }
 
+/// Reads the account from the nodes of the world trie on the path to it.
+/// Returns `None` if the nodes don't prove either that the trie of `root` has the account or that it doesn't.
+/// The keys of the world trie are hashed.
+pub fn verify_account_proof(root: &H256, address: &Address, proof: &[Bytes]) -> Option<Option<Account>> {
+    let mut db = MemoryDB::new();
+    for node in proof {
+        db.insert(node);
+    }
+    let trie = SecTrieDB::new(&db, root).ok()?;
+    let value = trie.get(address.as_ref()).ok()?;
+    match value {
+        Some(value) => Some(Some(::rlp::decode(&value))),
+        None => Some(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,6 +232,38 @@ mod tests {
         assert_eq!(a.regular_key(), Some(Public::default()));
     }
 
+    #[test]
+    fn proof_of_account() {
+        use trie::{Recorder, SecTrieDBMut, TrieMut};
+
+        let account = Account::new(69u8.into(), 1u8.into());
+        let address = Address::random();
+        let mut db = MemoryDB::new();
+        let mut root = H256::new();
+        {
+            let mut trie = SecTrieDBMut::new(&mut db, &mut root);
+            for i in 0..20u8 {
+                trie.insert(&[i; 20], &[i]).unwrap();
+            }
+            trie.insert(address.as_ref(), &account.rlp()).unwrap();
+        }
+        let proof_of = |address: &Address| {
+            let mut recorder = Recorder::new();
+            SecTrieDB::new(&db, &root).unwrap().get_with(address.as_ref(), &mut recorder).unwrap();
+            recorder.drain().into_iter().map(|record| record.data).collect::<Vec<Bytes>>()
+        };
+
+        let proof = proof_of(&address);
+        let proven = verify_account_proof(&root, &address, &proof).unwrap().unwrap();
+        assert_eq!(account.balance(), proven.balance());
+        assert_eq!(account.nonce(), proven.nonce());
+        assert!(verify_account_proof(&root, &address, &proof[1..]).is_none());
+        assert!(verify_account_proof(&H256::random(), &address, &proof).is_none());
+
+        let missing = Address::random();
+        assert!(verify_account_proof(&root, &missing, &proof_of(&missing)).unwrap().is_none());
+    }
+
     #[test]
     fn is_null() {
         let mut a = Account::new(69u8.into(), 0u8.into());
@@ -17,13 +17,22 @@
 macro_rules! impl_address {
     ($name:ident, $prefix:expr) => {
         impl $name {
-            fn from_transaction_hash(transaction_hash: ::ctypes::H256, index: u64) -> Self {
+            fn from_transaction_hash(
+                transaction_hash: ::ctypes::H256,
+                index: u64,
+                shard_id: ::types::ShardId,
+            ) -> Self {
                 let mut hash: ::ctypes::H256 =
                     ::ccrypto::Blake::blake_with_key(&transaction_hash, &::ctypes::H128::from(index));
-                hash[0..8].clone_from_slice(&[$prefix, 0, 0, 0, 0, 0, 0, 0]);
+                hash[0..8].clone_from_slice(&[$prefix, 0, (shard_id >> 8) as u8, shard_id as u8, 0, 0, 0, 0]);
                 $name(hash)
             }
 
+            /// The shard whose state trie has the item of the address.
+            pub fn shard_id(&self) -> ::types::ShardId {
+                ((self.0[2] as ::types::ShardId) << 8) | (self.0[3] as ::types::ShardId)
+            }
+
             pub fn from_hash(hash: ::ctypes::H256) -> Option<Self> {
                 if Self::is_valid_format(&hash) {
                     Some($name(hash))
@@ -33,7 +42,7 @@ macro_rules! impl_address {
             }
 
             pub fn is_valid_format(hash: &::ctypes::H256) -> bool {
-                if hash[0..4] != [$prefix, 0, 0, 0] {
+                if hash[0..2] != [$prefix, 0] {
                     return false // prefix
                 }
                 hash[4..8] == [0, 0, 0, 0] // world id
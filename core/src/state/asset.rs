@@ -17,6 +17,7 @@
 use ctypes::{Bytes, H256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::super::types::ShardId;
 use super::CacheableItem;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -112,11 +113,10 @@ pub struct AssetAddress(H256);
 impl_address!(AssetAddress, PREFIX);
 
 impl AssetAddress {
-    pub fn new(transaction_hash: H256, index: usize) -> Self {
-        debug_assert_eq!(::std::mem::size_of::<u64>(), ::std::mem::size_of::<usize>());
+    pub fn new(transaction_hash: H256, index: usize, shard_id: ShardId) -> Self {
         let index = index as u64;
 
-        Self::from_transaction_hash(transaction_hash, index)
+        Self::from_transaction_hash(transaction_hash, index, shard_id)
     }
 }
 
@@ -142,13 +142,23 @@ mod tests {
             }
             address
         };
-        let address1 = AssetAddress::new(parcel_id, 0);
-        let address2 = AssetAddress::new(parcel_id, 1);
+        let address1 = AssetAddress::new(parcel_id, 0, 0);
+        let address2 = AssetAddress::new(parcel_id, 1, 0);
         assert_ne!(address1, address2);
         assert_eq!(address1[0..8], [PREFIX, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(address2[0..8], [PREFIX, 0, 0, 0, 0, 0, 0, 0]);
     }
 
+    #[test]
+    fn shard_id_is_in_the_address() {
+        let parcel_id = H256::random();
+        let address = AssetAddress::new(parcel_id, 0, 0x0102);
+        assert_ne!(AssetAddress::new(parcel_id, 0, 0), address);
+        assert_eq!(address[0..8], [PREFIX, 0, 0x01, 0x02, 0, 0, 0, 0]);
+        assert_eq!(0x0102, address.shard_id());
+        assert_eq!(Some(address.clone()), AssetAddress::from_hash(address.into()));
+    }
+
     #[test]
     fn parse_fail_return_none() {
         let hash = {
@@ -17,6 +17,7 @@
 use ctypes::{Address, Bytes, H256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::super::types::ShardId;
 use super::CacheableItem;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -81,10 +82,10 @@ pub struct AssetSchemeAddress(H256);
 impl_address!(AssetSchemeAddress, PREFIX);
 
 impl AssetSchemeAddress {
-    pub fn new(transaction_hash: H256) -> Self {
+    pub fn new(transaction_hash: H256, shard_id: ShardId) -> Self {
         let index = ::std::u64::MAX;
 
-        Self::from_transaction_hash(transaction_hash, index)
+        Self::from_transaction_hash(transaction_hash, index, shard_id)
     }
 }
 
@@ -131,7 +132,7 @@ mod tests {
             }
             address
         };
-        let asset_address = AssetSchemeAddress::new(origin);
+        let asset_address = AssetSchemeAddress::new(origin, 0);
         let hash: H256 = asset_address.into();
         assert_ne!(origin, hash);
         assert_eq!(hash[0..4], [PREFIX, 0, 0, 0]);
@@ -219,8 +219,15 @@ where
     }
 
     pub fn commit<'db>(&mut self, trie: &mut Box<TrieMut + 'db>) -> Result<(), Error> {
+        self.commit_matching(trie, |_| true)
+    }
+
+    /// Commits the dirty items whose addresses satisfy `f`.
+    pub fn commit_matching<'db, F>(&mut self, trie: &mut Box<TrieMut + 'db>, f: F) -> Result<(), Error>
+    where
+        F: Fn(&Item::Address) -> bool, {
         let mut cache = self.cache.borrow_mut();
-        for (address, ref mut a) in cache.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
+        for (address, ref mut a) in cache.iter_mut().filter(|&(address, ref a)| a.is_dirty() && f(address)) {
             a.state = EntryState::Committed;
             match &a.item {
                 Some(item) => {
@@ -234,6 +241,10 @@ where
         Ok(())
     }
 
+    pub fn dirty_addresses(&self) -> Vec<Item::Address> {
+        self.cache.borrow().iter().filter(|&(_, ref a)| a.is_dirty()).map(|(address, _)| address.clone()).collect()
+    }
+
     pub fn propagate_to_global_cache<F>(&self, mut f: F)
     where
         F: FnMut(Item::Address, Option<Item>, bool), {
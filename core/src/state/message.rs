@@ -0,0 +1,216 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ctypes::{Bytes, H256};
+use hashdb::HashDB;
+use memorydb::MemoryDB;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use trie::{SecTrieDB, Trie};
+
+use super::super::types::ShardId;
+use super::CacheableItem;
+
+/// An asset on the way from a shard to another.
+///
+/// The transfer that sends an output to another shard leaves the message in the trie of its shard. The relay puts the
+/// message in the trie of the destination shard as a receipt, so that the message is applied only once.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrossShardMessage {
+    destination: ShardId,
+    asset_type: H256,
+    lock_script_hash: H256,
+    parameters: Vec<Bytes>,
+    amount: u64,
+}
+
+impl CrossShardMessage {
+    pub fn new(
+        destination: ShardId,
+        asset_type: H256,
+        lock_script_hash: H256,
+        parameters: Vec<Bytes>,
+        amount: u64,
+    ) -> Self {
+        Self {
+            destination,
+            asset_type,
+            lock_script_hash,
+            parameters,
+            amount,
+        }
+    }
+
+    pub fn destination(&self) -> ShardId {
+        self.destination
+    }
+
+    pub fn asset_type(&self) -> &H256 {
+        &self.asset_type
+    }
+
+    pub fn lock_script_hash(&self) -> &H256 {
+        &self.lock_script_hash
+    }
+
+    pub fn parameters(&self) -> &Vec<Bytes> {
+        &self.parameters
+    }
+
+    pub fn amount(&self) -> &u64 {
+        &self.amount
+    }
+}
+
+impl CacheableItem for CrossShardMessage {
+    type Address = MessageAddress;
+
+    fn overwrite_with(&mut self, other: Self) {
+        *self = other;
+    }
+
+    // The messages are never removed.
+    fn is_null(&self) -> bool {
+        false
+    }
+
+    fn from_rlp(rlp: &[u8]) -> Self {
+        ::rlp::decode(rlp)
+    }
+
+    fn rlp(&self) -> Bytes {
+        ::rlp::encode(self).into_vec()
+    }
+}
+
+const PREFIX: u8 = 'M' as u8;
+
+impl Encodable for CrossShardMessage {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(6)
+            .append(&PREFIX)
+            .append(&self.destination)
+            .append(&self.asset_type)
+            .append(&self.lock_script_hash)
+            .append(&self.parameters)
+            .append(&self.amount);
+    }
+}
+
+impl Decodable for CrossShardMessage {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 6 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let prefix = rlp.val_at::<u8>(0)?;
+        if PREFIX != prefix {
+            debug!(target: "state", "{} is not an expected prefix for cross-shard message", prefix);
+            return Err(DecoderError::Custom("Unexpected prefix"))
+        }
+        Ok(Self {
+            destination: rlp.val_at(1)?,
+            asset_type: rlp.val_at(2)?,
+            lock_script_hash: rlp.val_at(3)?,
+            parameters: rlp.val_at(4)?,
+            amount: rlp.val_at(5)?,
+        })
+    }
+}
+
+/// The key of a message in the trie of a shard. The shard id in the address is the shard whose trie has the message.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct MessageAddress(H256);
+
+impl_address!(MessageAddress, PREFIX);
+
+impl MessageAddress {
+    pub fn new(transaction_hash: H256, index: usize, shard_id: ShardId) -> Self {
+        Self::from_transaction_hash(transaction_hash, index as u64, shard_id)
+    }
+}
+
+/// Checks that the trie of the root has the message on the address with the proof, the nodes on the path to it.
+/// The keys of the state tries are hashed.
+pub fn verify_message_proof(
+    root: &H256,
+    address: &MessageAddress,
+    message: &CrossShardMessage,
+    proof: &[Bytes],
+) -> bool {
+    let mut db = MemoryDB::new();
+    for node in proof {
+        db.insert(node);
+    }
+    let trie = match SecTrieDB::new(&db, root) {
+        Ok(trie) => trie,
+        Err(_) => return false,
+    };
+    match trie.get(address.as_ref()) {
+        Ok(Some(value)) => *value == *message.rlp(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trie::{Recorder, SecTrieDBMut, TrieMut};
+
+    use super::*;
+
+    fn message() -> CrossShardMessage {
+        CrossShardMessage::new(2, H256::random(), H256::random(), vec![vec![1]], 100)
+    }
+
+    #[test]
+    fn rlpio() {
+        let message = message();
+        assert_eq!(message, ::rlp::decode(&::rlp::encode(&message)));
+    }
+
+    #[test]
+    fn address_has_the_shard() {
+        let transaction_hash = H256::random();
+        let address = MessageAddress::new(transaction_hash, 1, 3);
+        assert_eq!(3, address.shard_id());
+        assert_ne!(MessageAddress::new(transaction_hash, 1, 2), address);
+    }
+
+    #[test]
+    fn proof_of_message() {
+        let message = message();
+        let address = MessageAddress::new(H256::random(), 0, 1);
+        let mut db = MemoryDB::new();
+        let mut root = H256::new();
+        {
+            let mut trie = SecTrieDBMut::new(&mut db, &mut root);
+            for i in 0..20u8 {
+                trie.insert(&[i; 32], &[i]).unwrap();
+            }
+            trie.insert(address.as_ref(), &message.rlp()).unwrap();
+        }
+
+        let mut recorder = Recorder::new();
+        SecTrieDB::new(&db, &root).unwrap().get_with(address.as_ref(), &mut recorder).unwrap().unwrap();
+        let proof: Vec<Bytes> = recorder.drain().into_iter().map(|record| record.data).collect();
+
+        assert!(verify_message_proof(&root, &address, &message, &proof));
+        let other_message = CrossShardMessage::new(3, H256::zero(), H256::zero(), vec![], 1);
+        assert!(!verify_message_proof(&root, &address, &other_message, &proof));
+        assert!(!verify_message_proof(&root, &MessageAddress::new(H256::random(), 0, 1), &message, &proof));
+        assert!(!verify_message_proof(&root, &address, &message, &proof[1..]));
+        assert!(!verify_message_proof(&H256::random(), &address, &message, &proof));
+    }
+}
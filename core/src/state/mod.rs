@@ -20,21 +20,22 @@
 //! or rolled back.
 
 use std::cell::RefMut;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 
-use ccrypto::Blake;
+use ccrypto::{Blake, BLAKE_NULL_RLP};
 use ctypes::{Address, Bytes, H256, Public, U128, U256};
 use cvm::{decode, execute, ScriptResult, VMConfig};
 use error::Error;
 use parcel::{AssetTransferInput, AssetTransferOutput, SignedParcel};
-use trie::{self, Trie, TrieError, TrieFactory};
+use trie::{self, Recorder, Trie, TrieError, TrieFactory};
 use unexpected::Mismatch;
 
 use self::cache::Cache;
-use super::invoice::{Invoice, TransactionOutcome};
+use super::invoice::{EventKind, Invoice, LogEntry, TransactionOutcome};
 use super::parcel::ParcelError;
 use super::state_db::StateDB;
+use super::types::ShardId;
 use super::{Transaction, TransactionError};
 
 #[macro_use]
@@ -44,14 +45,18 @@ mod account;
 mod asset;
 mod asset_scheme;
 mod cache;
+mod message;
+mod shard;
 
 pub mod backend;
 
-pub use self::account::Account;
+pub use self::account::{verify_account_proof, Account};
 pub use self::asset::{Asset, AssetAddress};
 pub use self::asset_scheme::{AssetScheme, AssetSchemeAddress};
 pub use self::backend::Backend;
 pub use self::cache::CacheableItem;
+pub use self::message::{verify_message_proof, CrossShardMessage, MessageAddress};
+pub use self::shard::{Shard, ShardAddress};
 
 /// Used to return information about an `State::apply` operation.
 pub struct ApplyOutcome {
@@ -111,9 +116,13 @@ type CheckpointId = usize;
 pub struct State<B: Backend> {
     db: B,
     root: H256,
+    /// The root when the state is opened, i.e. the state root of the parent block.
+    parent_root: H256,
     account: Cache<Account>,
     asset_scheme: Cache<AssetScheme>,
     asset: Cache<Asset>,
+    shard: Cache<Shard>,
+    message: Cache<CrossShardMessage>,
     id_of_checkpoints: Vec<CheckpointId>,
     account_start_nonce: U256,
     trie_factory: TrieFactory,
@@ -130,9 +139,16 @@ pub trait StateInfo {
     /// Get the regular key of account `a`.
     fn regular_key(&self, a: &Address) -> trie::Result<Option<Public>>;
 
+    /// Get the proof of account `a`, or of its absence.
+    fn account_proof(&self, a: &Address) -> trie::Result<Vec<Bytes>>;
+
     /// Get the asset.
     fn asset_scheme(&self, a: &AssetSchemeAddress) -> trie::Result<Option<AssetScheme>>;
     fn asset(&self, a: &AssetAddress) -> trie::Result<Option<Asset>>;
+
+    /// Get the cross-shard message and the proof of it.
+    fn message(&self, a: &MessageAddress) -> trie::Result<Option<CrossShardMessage>>;
+    fn message_proof(&self, a: &MessageAddress) -> trie::Result<Option<Vec<Bytes>>>;
 }
 
 impl<B: Backend> StateInfo for State<B> {
@@ -145,6 +161,9 @@ impl<B: Backend> StateInfo for State<B> {
     fn regular_key(&self, a: &Address) -> trie::Result<Option<Public>> {
         State::regular_key(self, a)
     }
+    fn account_proof(&self, a: &Address) -> trie::Result<Vec<Bytes>> {
+        State::account_proof(self, a)
+    }
 
     fn asset_scheme(&self, a: &AssetSchemeAddress) -> trie::Result<Option<AssetScheme>> {
         State::asset_scheme(self, a)
@@ -153,6 +172,14 @@ impl<B: Backend> StateInfo for State<B> {
     fn asset(&self, a: &AssetAddress) -> trie::Result<Option<Asset>> {
         State::asset(self, a)
     }
+
+    fn message(&self, a: &MessageAddress) -> trie::Result<Option<CrossShardMessage>> {
+        State::message(self, a)
+    }
+
+    fn message_proof(&self, a: &MessageAddress) -> trie::Result<Option<Vec<Bytes>>> {
+        State::message_proof(self, a)
+    }
 }
 
 const PARCEL_CHECKPOINT: CheckpointId = 123;
@@ -173,9 +200,12 @@ impl<B: Backend> State<B> {
         State {
             db,
             root,
+            parent_root: root,
             account: Cache::new(),
             asset_scheme: Cache::new(),
             asset: Cache::new(),
+            shard: Cache::new(),
+            message: Cache::new(),
             id_of_checkpoints: Default::default(),
             account_start_nonce,
             trie_factory,
@@ -196,9 +226,12 @@ impl<B: Backend> State<B> {
         let state = State {
             db,
             root,
+            parent_root: root,
             account: Cache::new(),
             asset_scheme: Cache::new(),
             asset: Cache::new(),
+            shard: Cache::new(),
+            message: Cache::new(),
             id_of_checkpoints: Default::default(),
             account_start_nonce,
             trie_factory,
@@ -213,6 +246,8 @@ impl<B: Backend> State<B> {
         self.account.checkpoint();
         self.asset_scheme.checkpoint();
         self.asset.checkpoint();
+        self.shard.checkpoint();
+        self.message.checkpoint();
     }
 
     /// Merge last checkpoint with previous.
@@ -223,6 +258,8 @@ impl<B: Backend> State<B> {
         self.account.discard_checkpoint();
         self.asset_scheme.discard_checkpoint();
         self.asset.discard_checkpoint();
+        self.shard.discard_checkpoint();
+        self.message.discard_checkpoint();
     }
 
     /// Revert to the last checkpoint and discard it.
@@ -233,6 +270,8 @@ impl<B: Backend> State<B> {
         self.account.revert_to_checkpoint();
         self.asset_scheme.revert_to_checkpoint();
         self.asset.revert_to_checkpoint();
+        self.shard.revert_to_checkpoint();
+        self.message.revert_to_checkpoint();
     }
 
     /// Destroy the current object and return root and database.
@@ -293,8 +332,12 @@ impl<B: Backend> State<B> {
             return Ok(cached_asset)
         }
 
+        let shard_root = match self.shard_root(a.shard_id())? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
         // because of lexical borrow of self.db
-        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
         if let Some(r) = db.get_with(a.as_ref(), AssetScheme::from_rlp)? {
             Ok(Some(r))
         } else {
@@ -308,8 +351,12 @@ impl<B: Backend> State<B> {
             return Ok(cached_asset)
         }
 
+        let shard_root = match self.shard_root(a.shard_id())? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
         // because of lexical borrow of self.db
-        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
         if let Some(r) = db.get_with(a.as_ref(), Asset::from_rlp)? {
             Ok(Some(r))
         } else {
@@ -317,6 +364,72 @@ impl<B: Backend> State<B> {
         }
     }
 
+    /// Get the root of the state trie of the shard. `None` if the shard doesn't exist.
+    pub fn shard_root(&self, shard_id: ShardId) -> trie::Result<Option<H256>> {
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let f = |shard: Option<&Shard>| shard.map(|shard| *shard.root());
+        self.shard.ensure_cached(&ShardAddress::new(shard_id), &f, db, |_| None)
+    }
+
+    /// Get the root of the state trie of the shard in the parent block. `None` if the shard didn't exist.
+    fn parent_shard_root(&self, shard_id: ShardId) -> trie::Result<Option<H256>> {
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.parent_root)?;
+        Ok(db.get_with(ShardAddress::new(shard_id).as_ref(), Shard::from_rlp)?.map(|shard| *shard.root()))
+    }
+
+    /// Get the cross-shard message in the trie of the shard of the address.
+    pub fn message(&self, a: &MessageAddress) -> trie::Result<Option<CrossShardMessage>> {
+        let shard_root = match self.shard_root(a.shard_id())? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
+        self.message.ensure_cached(a, &|message: Option<&CrossShardMessage>| message.cloned(), db, |_| None)
+    }
+
+    /// Get the nodes of the committed trie of the shard on the path to the message.
+    /// `None` if the trie doesn't have the message.
+    pub fn message_proof(&self, a: &MessageAddress) -> trie::Result<Option<Vec<Bytes>>> {
+        let shard_root = match self.shard_root(a.shard_id())? {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let trie = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
+        let mut recorder = Recorder::new();
+        if trie.get_with(a.as_ref(), &mut recorder)?.is_none() {
+            return Ok(None)
+        }
+        Ok(Some(recorder.drain().into_iter().map(|record| record.data).collect()))
+    }
+
+    /// Get the nodes of the committed world trie on the path to account `a`.
+    /// The nodes prove that the trie doesn't have the account as well.
+    pub fn account_proof(&self, a: &Address) -> trie::Result<Vec<Bytes>> {
+        let trie = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let mut recorder = Recorder::new();
+        trie.get_with(a.as_ref(), &mut recorder)?;
+        Ok(recorder.drain().into_iter().map(|record| record.data).collect())
+    }
+
+    /// Get the roots of the state tries of the shards in the order of the shard id.
+    /// The shards are numbered from 0 without a gap.
+    pub fn shard_roots(&self) -> trie::Result<Vec<H256>> {
+        let mut shard_roots = Vec::new();
+        for shard_id in 0..=ShardId::max_value() {
+            match self.shard_root(shard_id)? {
+                Some(root) => shard_roots.push(root),
+                None => break,
+            }
+        }
+        Ok(shard_roots)
+    }
+
+    /// Creates the shard of the empty state trie if it doesn't exist.
+    pub fn create_shard(&mut self, shard_id: ShardId) -> trie::Result<()> {
+        self.require_shard(shard_id)?;
+        Ok(())
+    }
+
     /// Add `incr` to the balance of account `a`.
     pub fn add_balance(&mut self, a: &Address, incr: &U256) -> trie::Result<()> {
         trace!(target: "state", "add_balance({}, {}): {}", a, incr, self.balance(a)?);
@@ -365,20 +478,25 @@ impl<B: Backend> State<B> {
     fn mint_asset(
         &mut self,
         transaction_hash: H256,
+        shard_id: ShardId,
         metadata: &String,
         lock_script_hash: &H256,
         parameters: &Vec<Bytes>,
         amount: &Option<u64>,
         registrar: &Option<Address>,
     ) -> Result<(), Error> {
-        let asset_scheme_address = AssetSchemeAddress::new(transaction_hash);
+        if self.shard_root(shard_id)?.is_none() {
+            return Err(TransactionError::InvalidShardId(shard_id).into())
+        }
+
+        let asset_scheme_address = AssetSchemeAddress::new(transaction_hash, shard_id);
         let amount = amount.unwrap_or(::std::u64::MAX);
         let asset_scheme = self.require_asset_scheme(&asset_scheme_address, || {
             AssetScheme::new(metadata.clone(), amount, registrar.clone())
         })?;
         trace!(target: "tx", "{:?} is minted on {:?}", asset_scheme, asset_scheme_address);
 
-        let asset_address = AssetAddress::new(transaction_hash, 0);
+        let asset_address = AssetAddress::new(transaction_hash, 0, shard_id);
         let asset = self.require_asset(&asset_address, || {
             Asset::new(asset_scheme_address.into(), *lock_script_hash, parameters.clone(), amount)
         });
@@ -389,15 +507,20 @@ impl<B: Backend> State<B> {
     fn transfer_asset(
         &mut self,
         transaction: &Transaction,
+        shard_id: ShardId,
         inputs: &[AssetTransferInput],
         outputs: &[AssetTransferOutput],
     ) -> Result<(), Error> {
         debug_assert!(is_input_and_output_consistent(inputs, outputs));
 
+        if self.shard_root(shard_id)?.is_none() {
+            return Err(TransactionError::InvalidShardId(shard_id).into())
+        }
+
         for input in inputs {
             let (address_hash, lock_script_hash) = {
                 let index = input.prev_out.index;
-                let address = AssetAddress::new(input.prev_out.transaction_hash, index);
+                let address = AssetAddress::new(input.prev_out.transaction_hash, index, shard_id);
                 match self.asset(&address)? {
                     Some(asset) => (address.into(), *asset.lock_script_hash()),
                     None => return Err(TransactionError::AssetNotFound(address.into()).into()),
@@ -414,11 +537,8 @@ impl<B: Backend> State<B> {
 
             let script_result = match (decode(&input.lock_script), decode(&input.unlock_script)) {
                 (Ok(lock_script), Ok(unlock_script)) => {
-                    let mut script = Vec::new();
-                    script.extend(lock_script);
-                    script.extend(unlock_script);
                     // FIXME : apply parameters to vm
-                    execute(script.as_slice(), transaction.hash_without_script(), VMConfig::default())
+                    execute(&unlock_script, &lock_script, transaction.hash_without_script(), VMConfig::default())
                 }
                 // FIXME : Deliver full decode error
                 _ => return Err(TransactionError::InvalidScript.into()),
@@ -438,7 +558,7 @@ impl<B: Backend> State<B> {
         for input in inputs {
             let index = input.prev_out.index;
             let amount = input.prev_out.amount;
-            let address = AssetAddress::new(input.prev_out.transaction_hash, index);
+            let address = AssetAddress::new(input.prev_out.transaction_hash, index, shard_id);
 
             let asset_type = input.prev_out.asset_type.clone();
             let asset_scheme_address = AssetSchemeAddress::from_hash(asset_type)
@@ -467,15 +587,88 @@ impl<B: Backend> State<B> {
             deleted_asset.push((hash, amount));
         }
         let mut created_asset = Vec::with_capacity(outputs.len());
+        let mut sent_messages = Vec::new();
         for (index, output) in outputs.iter().enumerate() {
-            let asset_address = AssetAddress::new(transaction.hash(), index);
-            let asset =
-                Asset::new(output.asset_type, output.lock_script_hash, output.parameters.clone(), output.amount);
-            self.require_asset(&asset_address, || asset)?;
-            created_asset.push((asset_address, output.amount));
+            match output.shard_id {
+                Some(destination) if destination != shard_id => {
+                    if self.shard_root(destination)?.is_none() {
+                        return Err(TransactionError::InvalidShardId(destination).into())
+                    }
+                    let message_address = MessageAddress::new(transaction.hash(), index, shard_id);
+                    let message = CrossShardMessage::new(
+                        destination,
+                        output.asset_type,
+                        output.lock_script_hash,
+                        output.parameters.clone(),
+                        output.amount,
+                    );
+                    self.require_message(&message_address, || message)?;
+                    sent_messages.push((message_address, destination));
+                }
+                _ => {
+                    let asset_address = AssetAddress::new(transaction.hash(), index, shard_id);
+                    let asset = Asset::new(
+                        output.asset_type,
+                        output.lock_script_hash,
+                        output.parameters.clone(),
+                        output.amount,
+                    );
+                    self.require_asset(&asset_address, || asset)?;
+                    created_asset.push((asset_address, output.amount));
+                }
+            }
         }
         trace!(target: "tx", "Deleted assets {:?}", deleted_asset);
         trace!(target: "tx", "Created assets {:?}", created_asset);
+        trace!(target: "tx", "Sent messages {:?}", sent_messages);
+        Ok(())
+    }
+
+    /// Applies the message from the source shard if the proof shows that the source shard has it.
+    /// The proof is checked against the root of the source shard in the parent block, so the message can be relayed
+    /// from the block after the one which sent it.
+    fn relay_asset(
+        &mut self,
+        shard_id: ShardId,
+        source_shard_id: ShardId,
+        transaction_hash: H256,
+        index: usize,
+        message: &CrossShardMessage,
+        proof: &[Bytes],
+    ) -> Result<(), Error> {
+        if message.destination() != shard_id {
+            return Err(TransactionError::InvalidShardId(message.destination()).into())
+        }
+        if source_shard_id == shard_id {
+            return Err(TransactionError::InvalidShardId(source_shard_id).into())
+        }
+        if self.shard_root(shard_id)?.is_none() {
+            return Err(TransactionError::InvalidShardId(shard_id).into())
+        }
+        // The messages which the earlier parcels of this block sent are not committed in the parent block yet.
+        let source_root = self
+            .parent_shard_root(source_shard_id)?
+            .ok_or_else(|| TransactionError::InvalidShardId(source_shard_id))?;
+
+        let receipt_address = MessageAddress::new(transaction_hash, index, shard_id);
+        if self.message(&receipt_address)?.is_some() {
+            return Err(TransactionError::MessageAlreadyRelayed(receipt_address.into()).into())
+        }
+        let source_address = MessageAddress::new(transaction_hash, index, source_shard_id);
+        if !verify_message_proof(&source_root, &source_address, message, proof) {
+            return Err(TransactionError::InvalidMessageProof.into())
+        }
+
+        self.require_message(&receipt_address, || message.clone())?;
+        let asset_address = AssetAddress::new(transaction_hash, index, shard_id);
+        let asset = Asset::new(
+            *message.asset_type(),
+            *message.lock_script_hash(),
+            message.parameters().clone(),
+            *message.amount(),
+        );
+        self.require_asset(&asset_address, || asset)?;
+        trace!(target: "tx", "{:?} is relayed to {:?}", source_address, asset_address);
         Ok(())
     }
 
@@ -527,12 +720,18 @@ impl<B: Backend> State<B> {
 
         let mut results = Vec::with_capacity(parcel.transactions.len());
         for t in &parcel.transactions {
+            // The fee is paid once for the parcel.
+            let paid_fee = if results.is_empty() {
+                fee
+            } else {
+                0.into()
+            };
             self.checkpoint(TRANSACTION_CHECKPOINT);
             results.push(match self.execute_transaction(t, &fee_payer, &parcel.network_id) {
                 Ok(_) => {
                     info!(target: "tx", "Tx({}) is applied", t.hash());
                     self.discard_checkpoint(TRANSACTION_CHECKPOINT);
-                    let invoice = Invoice::new(TransactionOutcome::Success);
+                    let invoice = Invoice::new(TransactionOutcome::Success, paid_fee, emitted_logs(t));
                     let error = None;
                     ApplyOutcome {
                         invoice,
@@ -542,7 +741,7 @@ impl<B: Backend> State<B> {
                 Err(Error::Transaction(err)) => {
                     info!(target: "tx", "Cannot apply Tx({}): {:?}", t.hash(), err);
                     self.revert_to_checkpoint(TRANSACTION_CHECKPOINT);
-                    let invoice = Invoice::new(TransactionOutcome::Failed);
+                    let invoice = Invoice::new(TransactionOutcome::Failed, paid_fee, vec![]);
                     let error = Some(err);
                     ApplyOutcome {
                         invoice,
@@ -622,14 +821,24 @@ impl<B: Backend> State<B> {
                 Ok(())
             }
             Transaction::AssetMint {
+                shard_id,
                 ref metadata,
                 ref lock_script_hash,
                 ref amount,
                 ref parameters,
                 ref registrar,
                 ..
-            } => Ok(self.mint_asset(transaction.hash(), metadata, lock_script_hash, parameters, amount, registrar)?),
+            } => Ok(self.mint_asset(
+                transaction.hash(),
+                *shard_id,
+                metadata,
+                lock_script_hash,
+                parameters,
+                amount,
+                registrar,
+            )?),
             Transaction::AssetTransfer {
+                shard_id,
                 ref inputs,
                 ref outputs,
                 network_id,
@@ -641,17 +850,53 @@ impl<B: Backend> State<B> {
                         found: *network_id,
                     }).into())
                 }
-                self.transfer_asset(&transaction, inputs, outputs)
+                self.transfer_asset(&transaction, *shard_id, inputs, outputs)
+            }
+            Transaction::AssetRelay {
+                network_id,
+                shard_id,
+                source_shard_id,
+                transaction_hash,
+                index,
+                message,
+                proof,
+                ..
+            } => {
+                if parcel_network_id != network_id {
+                    return Err(TransactionError::InvalidNetworkId(Mismatch {
+                        expected: *parcel_network_id,
+                        found: *network_id,
+                    }).into())
+                }
+                self.relay_asset(*shard_id, *source_shard_id, *transaction_hash, *index, message, proof)
             }
         }
     }
 
-    /// Commits our cached account changes into the trie.
+    /// Commits our cached changes into the tries.
+    /// The assets and the asset schemes go to the tries of their shards, and then the new roots of the shards go
+    /// to the world trie.
     pub fn commit(&mut self) -> Result<(), Error> {
+        let mut shard_ids: BTreeSet<ShardId> =
+            self.asset_scheme.dirty_addresses().iter().map(AssetSchemeAddress::shard_id).collect();
+        shard_ids.extend(self.asset.dirty_addresses().iter().map(AssetAddress::shard_id));
+        shard_ids.extend(self.message.dirty_addresses().iter().map(MessageAddress::shard_id));
+
+        for shard_id in shard_ids {
+            let mut shard_root =
+                self.shard_root(shard_id)?.ok_or_else(|| TransactionError::InvalidShardId(shard_id))?;
+            {
+                let mut trie = self.trie_factory.from_existing(self.db.as_hashdb_mut(), &mut shard_root)?;
+                self.asset_scheme.commit_matching(&mut trie, |address| address.shard_id() == shard_id)?;
+                self.asset.commit_matching(&mut trie, |address| address.shard_id() == shard_id)?;
+                self.message.commit_matching(&mut trie, |address| address.shard_id() == shard_id)?;
+            }
+            self.require_shard(shard_id)?.set_root(shard_root);
+        }
+
         let mut trie = self.trie_factory.from_existing(self.db.as_hashdb_mut(), &mut self.root)?;
         self.account.commit(&mut trie)?;
-        self.asset_scheme.commit(&mut trie)?;
-        self.asset.commit(&mut trie)?;
+        self.shard.commit(&mut trie)?;
         Ok(())
     }
 
@@ -674,6 +919,8 @@ impl<B: Backend> State<B> {
         self.account.clear();
         self.asset_scheme.clear();
         self.asset.clear();
+        self.shard.clear();
+        self.message.clear();
     }
 
     /// Check caches for required data
@@ -701,7 +948,8 @@ impl<B: Backend> State<B> {
     ) -> trie::Result<RefMut<'a, AssetScheme>>
     where
         F: FnOnce() -> AssetScheme, {
-        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let shard_root = self.shard_root(a.shard_id())?.unwrap_or(BLAKE_NULL_RLP);
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
         let from_db = || self.db.get_cached_asset_scheme(a);
         self.asset_scheme.require_item_or_from(a, default, db, from_db)
     }
@@ -709,15 +957,36 @@ impl<B: Backend> State<B> {
     fn require_asset<'a, F>(&'a self, a: &AssetAddress, default: F) -> trie::Result<RefMut<'a, Asset>>
     where
         F: FnOnce() -> Asset, {
-        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        let shard_root = self.shard_root(a.shard_id())?.unwrap_or(BLAKE_NULL_RLP);
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
         let from_db = || self.db.get_cached_asset(a);
         self.asset.require_item_or_from(a, default, db, from_db)
     }
+
+    // The messages are not in the global cache.
+    fn require_message<'a, F>(&'a self, a: &MessageAddress, default: F) -> trie::Result<RefMut<'a, CrossShardMessage>>
+    where
+        F: FnOnce() -> CrossShardMessage, {
+        let shard_root = self.shard_root(a.shard_id())?.unwrap_or(BLAKE_NULL_RLP);
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &shard_root)?;
+        self.message.require_item_or_from(a, default, db, || None)
+    }
+
+    // The shards are not in the global cache.
+    fn require_shard<'a>(&'a self, shard_id: ShardId) -> trie::Result<RefMut<'a, Shard>> {
+        let default = || Shard::new(BLAKE_NULL_RLP);
+        let db = self.trie_factory.readonly(self.db.as_hashdb(), &self.root)?;
+        self.shard.require_item_or_from(&ShardAddress::new(shard_id), default, db, || None)
+    }
 }
 
 impl<B: Backend> fmt::Debug for State<B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "account: {:?} asset_scheme: {:?} asset: {:?}", self.account, self.asset_scheme, self.asset)
+        write!(
+            f,
+            "account: {:?} asset_scheme: {:?} asset: {:?} shard: {:?} message: {:?}",
+            self.account, self.asset_scheme, self.asset, self.shard, self.message
+        )
     }
 }
 
@@ -728,10 +997,13 @@ impl Clone for State<StateDB> {
         State {
             db: self.db.boxed_clone(),
             root: self.root.clone(),
+            parent_root: self.parent_root.clone(),
             id_of_checkpoints: self.id_of_checkpoints.clone(),
             account: self.account.clone(),
             asset_scheme: self.asset_scheme.clone(),
             asset: self.asset.clone(),
+            shard: self.shard.clone(),
+            message: self.message.clone(),
             account_start_nonce: self.account_start_nonce.clone(),
             trie_factory: self.trie_factory.clone(),
         }
@@ -765,11 +1037,46 @@ fn is_input_and_output_consistent(inputs: &[AssetTransferInput], outputs: &[Asse
     sum.iter().all(|(_, sum)| sum.is_zero())
 }
 
+// The accounts and the asset types which the applied transaction touched, and whom they went to.
+fn emitted_logs(transaction: &Transaction) -> Vec<LogEntry> {
+    match transaction {
+        Transaction::Payment {
+            sender,
+            receiver,
+            ..
+        } => vec![LogEntry::new(EventKind::Payment, H256::from(*sender), vec![H256::from(*receiver)])],
+        Transaction::SetRegularKey {
+            address,
+            ..
+        } => vec![LogEntry::new(EventKind::SetRegularKey, H256::from(*address), vec![])],
+        Transaction::AssetMint {
+            shard_id,
+            lock_script_hash,
+            ..
+        } => {
+            let asset_type = AssetSchemeAddress::new(transaction.hash(), *shard_id).into();
+            vec![LogEntry::new(EventKind::AssetMint, asset_type, vec![*lock_script_hash])]
+        }
+        Transaction::AssetTransfer {
+            outputs,
+            ..
+        } => outputs
+            .iter()
+            .map(|output| LogEntry::new(EventKind::AssetTransfer, output.asset_type, vec![output.lock_script_hash]))
+            .collect(),
+        Transaction::AssetRelay {
+            message,
+            ..
+        } => vec![LogEntry::new(EventKind::AssetRelay, *message.asset_type(), vec![*message.lock_script_hash()])],
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ccrypto::Blake;
     use ckeys::{Generator, Random};
     use ctypes::{Address, Secret, U256};
+    use cvm::{encode, Instruction};
 
     use super::super::parcel::{AssetOutPoint, Parcel};
     use super::super::tests::helpers::{get_temp_state, get_temp_state_db};
@@ -883,6 +1190,11 @@ mod tests {
         assert_eq!(1, res.len());
         let res = &res[0];
         assert_eq!(res.invoice.outcome, TransactionOutcome::Success);
+        assert_eq!(res.invoice.fee, 5.into());
+        assert_eq!(
+            res.invoice.logs,
+            vec![LogEntry::new(EventKind::Payment, H256::from(sender), vec![H256::from(receiver)])]
+        );
         assert!(res.error.is_none());
         assert_eq!(state.balance(&receiver).unwrap(), 10.into());
         assert_eq!(state.balance(&sender).unwrap(), 5.into());
@@ -945,6 +1257,8 @@ mod tests {
         assert_eq!(1, res.len());
         let res = &res[0];
         assert_eq!(res.invoice.outcome, TransactionOutcome::Failed);
+        assert_eq!(res.invoice.fee, 5.into());
+        assert!(res.invoice.logs.is_empty());
         assert_eq!(
             res.error.as_ref().unwrap(),
             &TransactionError::InsufficientBalance {
@@ -1071,6 +1385,19 @@ mod tests {
         assert_eq!(state.balance(&b).unwrap(), U256::from(18u64));
     }
 
+    #[test]
+    fn proof_of_committed_account() {
+        let mut state = get_temp_state();
+        let a = Address::random();
+        state.add_balance(&a, &U256::from(69u64)).unwrap();
+        state.commit().unwrap();
+
+        let account = verify_account_proof(state.root(), &a, &state.account_proof(&a).unwrap()).unwrap().unwrap();
+        assert_eq!(&U256::from(69u64), account.balance());
+        let b = Address::random();
+        assert!(verify_account_proof(state.root(), &b, &state.account_proof(&b).unwrap()).unwrap().is_none());
+    }
+
     #[test]
     fn alter_nonce() {
         let mut state = get_temp_state();
@@ -1172,7 +1499,9 @@ mod tests {
             let root_parent = H256::random();
 
             let state_db = state_db.boxed_clone_canon(&root_parent);
-            State::new(state_db, U256::from(0), Default::default())
+            let mut state = State::new(state_db, U256::from(0), Default::default());
+            state.create_shard(0).unwrap();
+            state
         };
 
         let metadata = "metadata".to_string();
@@ -1181,6 +1510,7 @@ mod tests {
         let amount = 100;
         let registrar = Some(Address::random());
         let transactions = vec![Transaction::AssetMint {
+            shard_id: 0,
             metadata: metadata.clone(),
             lock_script_hash,
             parameters,
@@ -1200,13 +1530,13 @@ mod tests {
         assert!(added_result.is_ok());
 
         let minted_result =
-            state.mint_asset(parcel_hash.clone(), &metadata, &lock_script_hash, &vec![], &Some(amount), &registrar);
+            state.mint_asset(parcel_hash.clone(), 0, &metadata, &lock_script_hash, &vec![], &Some(amount), &registrar);
         assert!(minted_result.is_ok());
 
         let commit = state.commit();
         assert!(commit.is_ok());
 
-        let asset_scheme_address = AssetSchemeAddress::new(parcel_hash.clone());
+        let asset_scheme_address = AssetSchemeAddress::new(parcel_hash.clone(), 0);
         let asset_scheme = state.asset_scheme(&asset_scheme_address);
         assert!(asset_scheme.is_ok());
         let asset_scheme = asset_scheme.unwrap();
@@ -1227,7 +1557,9 @@ mod tests {
             let root_parent = H256::random();
 
             let state_db = state_db.boxed_clone_canon(&root_parent);
-            State::new(state_db, U256::from(0), Default::default())
+            let mut state = State::new(state_db, U256::from(0), Default::default());
+            state.create_shard(0).unwrap();
+            state
         };
 
         let metadata = "metadata".to_string();
@@ -1235,6 +1567,7 @@ mod tests {
         let parameters = vec![];
         let registrar = Some(Address::random());
         let transactions = vec![Transaction::AssetMint {
+            shard_id: 0,
             metadata: metadata.clone(),
             lock_script_hash,
             parameters: vec![],
@@ -1254,13 +1587,13 @@ mod tests {
         assert!(added_result.is_ok());
 
         let minted_result =
-            state.mint_asset(parcel_hash.clone(), &metadata, &lock_script_hash, &parameters, &None, &registrar);
+            state.mint_asset(parcel_hash.clone(), 0, &metadata, &lock_script_hash, &parameters, &None, &registrar);
         assert!(minted_result.is_ok());
 
         let commit = state.commit();
         assert!(commit.is_ok());
 
-        let asset_scheme_address = AssetSchemeAddress::new(parcel_hash.clone());
+        let asset_scheme_address = AssetSchemeAddress::new(parcel_hash.clone(), 0);
         let asset_scheme = state.asset_scheme(&asset_scheme_address);
         assert!(asset_scheme.is_ok());
         let asset_scheme = asset_scheme.unwrap();
@@ -1273,6 +1606,116 @@ mod tests {
         assert!(asset_scheme.is_permissioned());
     }
 
+    #[test]
+    fn cannot_mint_asset_on_nonexistent_shard() {
+        let mut state = get_temp_state();
+        state.create_shard(0).unwrap();
+
+        let metadata = "metadata".to_string();
+        match state.mint_asset(H256::random(), 1, &metadata, &H256::random(), &vec![], &None, &None) {
+            Err(Error::Transaction(TransactionError::InvalidShardId(1))) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn assets_are_committed_to_the_trie_of_their_shard() {
+        let mut state = get_temp_state();
+        state.create_shard(0).unwrap();
+        state.create_shard(1).unwrap();
+        state.commit().unwrap();
+        assert_eq!(Some(BLAKE_NULL_RLP), state.shard_root(0).unwrap());
+        assert_eq!(Some(BLAKE_NULL_RLP), state.shard_root(1).unwrap());
+        assert_eq!(None, state.shard_root(2).unwrap());
+
+        let transaction_hash = H256::random();
+        state.mint_asset(transaction_hash, 1, &"metadata".to_string(), &H256::random(), &vec![], &None, &None).unwrap();
+        state.commit().unwrap();
+        assert_eq!(Some(BLAKE_NULL_RLP), state.shard_root(0).unwrap());
+        assert_ne!(Some(BLAKE_NULL_RLP), state.shard_root(1).unwrap());
+
+        let (root, db) = state.drop();
+        let state = State::from_existing(db, root, U256::from(0), Default::default()).unwrap();
+        assert!(state.asset_scheme(&AssetSchemeAddress::new(transaction_hash, 1)).unwrap().is_some());
+        assert!(state.asset(&AssetAddress::new(transaction_hash, 0, 1)).unwrap().is_some());
+        assert!(state.asset(&AssetAddress::new(transaction_hash, 0, 0)).unwrap().is_none());
+    }
+
+    #[test]
+    fn relay_asset_to_another_shard() {
+        let mut state = get_temp_state();
+        state.create_shard(0).unwrap();
+        state.create_shard(1).unwrap();
+
+        let lock_script = encode(&[Instruction::PushI(1)]);
+        let lock_script_hash = Blake::blake(&lock_script);
+        let mint_hash = H256::random();
+        let metadata = "metadata".to_string();
+        state.mint_asset(mint_hash, 0, &metadata, &lock_script_hash, &vec![], &Some(100), &None).unwrap();
+        state.commit().unwrap();
+
+        let asset_type: H256 = AssetSchemeAddress::new(mint_hash, 0).into();
+        let inputs = vec![AssetTransferInput {
+            prev_out: AssetOutPoint {
+                transaction_hash: mint_hash,
+                index: 0,
+                asset_type,
+                amount: 100,
+            },
+            lock_script,
+            unlock_script: vec![],
+            timelock: None,
+        }];
+        let outputs = vec![AssetTransferOutput {
+            lock_script_hash,
+            parameters: vec![],
+            asset_type,
+            amount: 100,
+            shard_id: Some(1),
+        }];
+        let transfer = Transaction::AssetTransfer {
+            network_id: 0,
+            shard_id: 0,
+            inputs: inputs.clone(),
+            outputs: outputs.clone(),
+            nonce: 0,
+        };
+        state.transfer_asset(&transfer, 0, &inputs, &outputs).unwrap();
+        assert!(state.asset(&AssetAddress::new(transfer.hash(), 0, 0)).unwrap().is_none());
+
+        // The message can't be relayed until the block which sent it is committed.
+        let message_address = MessageAddress::new(transfer.hash(), 0, 0);
+        assert_eq!(None, state.message_proof(&message_address).unwrap());
+        state.commit().unwrap();
+
+        let message = state.message(&message_address).unwrap().unwrap();
+        assert_eq!(1, message.destination());
+        let proof = state.message_proof(&message_address).unwrap().unwrap();
+
+        // The message is relayed from the next block.
+        match state.relay_asset(1, 0, transfer.hash(), 0, &message, &proof) {
+            Err(Error::Transaction(TransactionError::InvalidShardId(0))) => {}
+            result => panic!("{:?}", result),
+        }
+        let (root, db) = state.drop();
+        let mut state = State::from_existing(db, root, U256::from(0), Default::default()).unwrap();
+
+        match state.relay_asset(1, 0, transfer.hash(), 0, &message, &proof[1..]) {
+            Err(Error::Transaction(TransactionError::InvalidMessageProof)) => {}
+            result => panic!("{:?}", result),
+        }
+        state.relay_asset(1, 0, transfer.hash(), 0, &message, &proof).unwrap();
+        match state.relay_asset(1, 0, transfer.hash(), 0, &message, &proof) {
+            Err(Error::Transaction(TransactionError::MessageAlreadyRelayed(_))) => {}
+            result => panic!("{:?}", result),
+        }
+        state.commit().unwrap();
+
+        let asset = state.asset(&AssetAddress::new(transfer.hash(), 0, 1)).unwrap().unwrap();
+        assert_eq!(&asset_type, asset.asset_type());
+        assert_eq!(&100, asset.amount());
+    }
+
     #[test]
     fn test_is_input_and_output_consistent() {
         let asset_type = H256::random();
@@ -1288,12 +1731,14 @@ mod tests {
                 },
                 lock_script: vec![],
                 unlock_script: vec![],
+                timelock: None,
             }],
             &[AssetTransferOutput {
                 lock_script_hash: H256::random(),
                 parameters: vec![],
                 asset_type,
                 amount,
+                shard_id: None,
             }]
         ));
     }
@@ -1322,6 +1767,7 @@ mod tests {
                     },
                     lock_script: vec![],
                     unlock_script: vec![],
+                    timelock: None,
                 },
                 AssetTransferInput {
                     prev_out: AssetOutPoint {
@@ -1332,6 +1778,7 @@ mod tests {
                     },
                     lock_script: vec![],
                     unlock_script: vec![],
+                    timelock: None,
                 },
             ],
             &[
@@ -1340,12 +1787,14 @@ mod tests {
                     parameters: vec![],
                     asset_type: asset_type1,
                     amount: amount1,
+                    shard_id: None,
                 },
                 AssetTransferOutput {
                     lock_script_hash: H256::random(),
                     parameters: vec![],
                     asset_type: asset_type2,
                     amount: amount2,
+                    shard_id: None,
                 },
             ]
         ));
@@ -1375,6 +1824,7 @@ mod tests {
                     },
                     lock_script: vec![],
                     unlock_script: vec![],
+                    timelock: None,
                 },
                 AssetTransferInput {
                     prev_out: AssetOutPoint {
@@ -1385,6 +1835,7 @@ mod tests {
                     },
                     lock_script: vec![],
                     unlock_script: vec![],
+                    timelock: None,
                 },
             ],
             &[
@@ -1393,12 +1844,14 @@ mod tests {
                     parameters: vec![],
                     asset_type: asset_type2,
                     amount: amount2,
+                    shard_id: None,
                 },
                 AssetTransferOutput {
                     lock_script_hash: H256::random(),
                     parameters: vec![],
                     asset_type: asset_type1,
                     amount: amount1,
+                    shard_id: None,
                 },
             ]
         ));
@@ -1420,6 +1873,7 @@ mod tests {
                 parameters: vec![],
                 asset_type,
                 amount: output_amount,
+                shard_id: None,
             }]
         ));
     }
@@ -1439,6 +1893,7 @@ mod tests {
                 },
                 lock_script: vec![],
                 unlock_script: vec![],
+                timelock: None,
             }],
             &[]
         ));
@@ -1460,12 +1915,14 @@ mod tests {
                 },
                 lock_script: vec![],
                 unlock_script: vec![],
+                timelock: None,
             }],
             &[AssetTransferOutput {
                 lock_script_hash: H256::random(),
                 parameters: vec![],
                 asset_type,
                 amount: output_amount,
+                shard_id: None,
             }]
         ));
     }
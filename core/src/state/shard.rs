@@ -0,0 +1,152 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use ctypes::{Bytes, H256};
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::super::types::ShardId;
+use super::CacheableItem;
+
+/// The root of the state trie of a shard, which has the assets and the asset schemes of the shard.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shard {
+    root: H256,
+}
+
+impl Shard {
+    pub fn new(root: H256) -> Self {
+        Self {
+            root,
+        }
+    }
+
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    pub fn set_root(&mut self, root: H256) {
+        self.root = root;
+    }
+}
+
+impl CacheableItem for Shard {
+    type Address = ShardAddress;
+
+    fn overwrite_with(&mut self, other: Self) {
+        self.root = other.root;
+    }
+
+    // The shard of the empty state trie still exists.
+    fn is_null(&self) -> bool {
+        false
+    }
+
+    fn from_rlp(rlp: &[u8]) -> Self {
+        ::rlp::decode(rlp)
+    }
+
+    fn rlp(&self) -> Bytes {
+        ::rlp::encode(self).into_vec()
+    }
+}
+
+const PREFIX: u8 = 'H' as u8;
+
+impl Encodable for Shard {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2).append(&PREFIX).append(&self.root);
+    }
+}
+
+impl Decodable for Shard {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let prefix = rlp.val_at::<u8>(0)?;
+        if PREFIX != prefix {
+            debug!(target: "state", "{} is not an expected prefix for shard", prefix);
+            return Err(DecoderError::Custom("Unexpected prefix"))
+        }
+        Ok(Self {
+            root: rlp.val_at(1)?,
+        })
+    }
+}
+
+/// The key of a shard in the world state trie.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ShardAddress(H256);
+
+impl ShardAddress {
+    pub fn new(shard_id: ShardId) -> Self {
+        let mut hash = H256::zero();
+        hash[0..4].clone_from_slice(&[PREFIX, 0, (shard_id >> 8) as u8, shard_id as u8]);
+        ShardAddress(hash)
+    }
+
+    pub fn from_hash(hash: H256) -> Option<Self> {
+        let address = Self::new(((hash[2] as ShardId) << 8) | (hash[3] as ShardId));
+        if address.0 == hash {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    pub fn shard_id(&self) -> ShardId {
+        ((self.0[2] as ShardId) << 8) | (self.0[3] as ShardId)
+    }
+}
+
+impl Into<H256> for ShardAddress {
+    fn into(self) -> H256 {
+        self.0
+    }
+}
+
+impl fmt::Debug for ShardAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl AsRef<[u8]> for ShardAddress {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rlpio() {
+        let shard = Shard::new(H256::random());
+        assert_eq!(shard, ::rlp::decode(&::rlp::encode(&shard)));
+    }
+
+    #[test]
+    fn address_of_shard() {
+        let address = ShardAddress::new(0x0102);
+        assert_eq!(0x0102, address.shard_id());
+        assert_ne!(ShardAddress::new(0), address);
+
+        let hash: H256 = address.clone().into();
+        assert_eq!(Some(address), ShardAddress::from_hash(hash));
+        assert_eq!(None, ShardAddress::from_hash(H256::random()));
+    }
+}
@@ -615,7 +615,7 @@ mod tests {
         let amount = 1234;
         let registrar = Some(Address::random());
         let asset_scheme = AssetScheme::new("A metadata for test asset_scheme".to_string(), amount, registrar);
-        let asset_scheme_address = AssetSchemeAddress::new(h0);
+        let asset_scheme_address = AssetSchemeAddress::new(h0, 0);
 
         let mut s = state_db.boxed_clone_canon(&root_parent);
 
@@ -661,7 +661,7 @@ mod tests {
         let parameters = vec![];
         let amount = 1000;
         let asset = Asset::new(asset_scheme_address, lock_script_hash, parameters, amount);
-        let asset_address = AssetAddress::new(parcel_hash, 0);
+        let asset_address = AssetAddress::new(parcel_hash, 0, 0);
 
         let mut s = state_db.boxed_clone_canon(&root_parent);
 
@@ -0,0 +1,285 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pins the digests of the consensus-critical structures.
+//! Every platform must produce the same bytes, otherwise the nodes on the other architectures fork.
+//! `scripts/cross_test.sh` runs these tests on the 32-bit and the big-endian targets.
+
+use ckeys::ECDSASignature;
+use ctypes::{Address, Bloom, H256, Public, U256};
+use rlp::{self, UntrustedRlp};
+use rustc_hex::ToHex;
+
+use block::Block;
+use header::{Header, Seal};
+use invoice::{Invoice, LogEntry, TransactionOutcome};
+use parcel::{AssetOutPoint, AssetTransferInput, AssetTransferOutput, Parcel, UnverifiedParcel};
+use state::{Account, Asset, AssetAddress, AssetScheme, AssetSchemeAddress, CrossShardMessage, Shard, ShardAddress};
+use transaction::Transaction;
+
+fn header() -> Header {
+    let mut header = Header::new();
+    header.set_parent_hash(H256::from([0x11; 32]));
+    header.set_author(Address::from([0x22; 20]));
+    header.set_state_root(H256::from([0x33; 32]));
+    header.set_parcels_root(H256::from([0x44; 32]));
+    header.set_invoices_root(H256::from([0x55; 32]));
+    header.set_score(U256::from(0x01_0203_0405u64));
+    header.set_number(1_000_000);
+    header.set_timestamp(1_530_000_000);
+    header.set_extra_data(b"conformance".to_vec());
+    header.set_shard_roots(vec![H256::from([0x66; 32]), H256::from([0x77; 32])]);
+    header.set_log_bloom(Bloom::from([0x88; 256]));
+    header.set_seal(vec![rlp::encode(&0x1234_5678u64).into_vec()]);
+    header
+}
+
+fn payment() -> Transaction {
+    Transaction::Payment {
+        nonce: 7.into(),
+        sender: Address::from([0x66; 20]),
+        receiver: Address::from([0x77; 20]),
+        value: U256::from(1_000_000_000_000u64),
+    }
+}
+
+fn set_regular_key() -> Transaction {
+    Transaction::SetRegularKey {
+        address: Address::from([0x88; 20]),
+        nonce: 3.into(),
+        key: Public::from([0x99; 64]),
+    }
+}
+
+// The amounts and the nonces do not fit in 32 bits.
+fn asset_mint() -> Transaction {
+    Transaction::AssetMint {
+        shard_id: 0x0102,
+        metadata: "conformance".to_string(),
+        lock_script_hash: H256::from([0xaa; 32]),
+        parameters: vec![vec![0x01, 0x02], vec![]],
+        amount: Some(0x1_0000_0000),
+        registrar: Some(Address::from([0xbb; 20])),
+        nonce: 0xffff_ffff_ffff,
+    }
+}
+
+fn asset_transfer() -> Transaction {
+    Transaction::AssetTransfer {
+        network_id: 17,
+        shard_id: 0x0102,
+        inputs: vec![AssetTransferInput {
+            prev_out: AssetOutPoint {
+                transaction_hash: H256::from([0xcc; 32]),
+                index: 3,
+                asset_type: H256::from([0xdd; 32]),
+                amount: 0x1_0000_0001,
+            },
+            lock_script: vec![0x30, 0x01],
+            unlock_script: vec![0x02],
+            timelock: None,
+        }],
+        outputs: vec![AssetTransferOutput {
+            lock_script_hash: H256::from([0xee; 32]),
+            parameters: vec![vec![0xff; 3]],
+            asset_type: H256::from([0xdd; 32]),
+            amount: 0x1_0000_0001,
+            shard_id: None,
+        }],
+        nonce: 5,
+    }
+}
+
+fn asset_relay() -> Transaction {
+    Transaction::AssetRelay {
+        network_id: 17,
+        shard_id: 0x0102,
+        source_shard_id: 0x0304,
+        transaction_hash: H256::from([0xcc; 32]),
+        index: 3,
+        message: CrossShardMessage::new(
+            0x0102,
+            H256::from([0xdd; 32]),
+            H256::from([0xee; 32]),
+            vec![vec![0xff; 3]],
+            0x1_0000_0001,
+        ),
+        proof: vec![vec![0x01, 0x02, 0x03], vec![0x80]],
+        nonce: 9,
+    }
+}
+
+fn parcel() -> Parcel {
+    Parcel {
+        nonce: 0x2a.into(),
+        fee: U256::from(1_000_000_000_000_000_000u64),
+        transactions: vec![payment(), set_regular_key(), asset_mint(), asset_transfer()],
+        network_id: 17,
+    }
+}
+
+// The signature is not valid, but the digest doesn't depend on whether it is.
+fn unverified_parcel() -> UnverifiedParcel {
+    parcel().with_signature(ECDSASignature::from_rsv(&H256::from([0x01; 32]), &H256::from([0x02; 32]), 1))
+}
+
+#[test]
+fn header_digest() {
+    let header = header();
+    assert_eq!(header.bare_hash(), H256::from("753676483b23c601e0b2a6dab054207667526d51c2674b1554209d0c734effcd"));
+    assert_eq!(header.hash(), H256::from("5b2fcd768311cf16391e414b1ad3d23b76ea81bd2f2b4251bea5463c9d19f1f2"));
+
+    let decoded: Header = rlp::decode(&header.rlp(Seal::With));
+    assert_eq!(decoded.hash(), header.hash());
+}
+
+#[test]
+fn transaction_encoding() {
+    assert_eq!(
+        rlp::encode(&payment()).to_hex(),
+        "f2010794666666666666666666666666666666666666666694777777777777777777777777777777777777777785e8d4a51000"
+    );
+    assert_eq!(
+        rlp::encode(&asset_mint()).to_hex(),
+        "f85a038201028b636f6e666f726d616e6365a0aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\
+         c482010280c6850100000000d594bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb86ffffffffffff"
+    );
+}
+
+#[test]
+fn transaction_digests() {
+    assert_eq!(payment().hash(), H256::from("b1fae314fc89defc024e0ac24c9f018ffcd595730edbd30ab72027ea467e353e"));
+    assert_eq!(
+        set_regular_key().hash(),
+        H256::from("b08bfaaa80cd7c97dee66814cdbf7bb004ac6e790f5e38fe6189a660cb5faba3")
+    );
+    assert_eq!(asset_mint().hash(), H256::from("6224eca6b7480dcf14daa9159c97aaf490bd07bddb1f5f00de3226c148e14c92"));
+    assert_eq!(asset_transfer().hash(), H256::from("471cb9f1942203dac5e2c3139d8e1c451ccfd8487c0325568e361025ef6110a1"));
+    assert_eq!(
+        asset_transfer().hash_without_script(),
+        H256::from("2d00f78c862b8f16b5aae42c25f80d13d813d21f491391fd01dd7a5bcbe8d0ae")
+    );
+    assert_eq!(asset_relay().hash(), H256::from("bd7a489868c9d4863ef74e00e6df022fc0556c139bc7f5c7f436d5e396cb3a1f"));
+
+    for transaction in vec![payment(), set_regular_key(), asset_mint(), asset_transfer(), asset_relay()] {
+        assert_eq!(transaction, rlp::decode(&rlp::encode(&transaction)));
+    }
+}
+
+#[test]
+fn parcel_digests() {
+    assert_eq!(parcel().hash(), H256::from("8e192afd72fbc2ed450fb0193d7a8e8c989fc2dac84f605d13a5f7a0286eb708"));
+
+    let parcel = unverified_parcel();
+    assert_eq!(parcel.hash(), H256::from("b24c1e83e28f3d3fa7eaaa1ce4bd2607356a392d774e3a938f1639881bce825d"));
+    let decoded: UnverifiedParcel = rlp::decode(&rlp::encode(&parcel));
+    assert_eq!(decoded.hash(), parcel.hash());
+}
+
+#[test]
+fn block_digest() {
+    let block = Block {
+        header: header(),
+        parcels: vec![unverified_parcel()],
+    };
+    let bytes = block.rlp_bytes(Seal::With);
+    assert_eq!(
+        ::ccrypto::blake256(&bytes),
+        H256::from("56d8bed49c5e90ddc1f5348e2ff106f643df7412f751461929d773373b1b15da")
+    );
+    let decoded: Block = rlp::decode(&bytes);
+    assert_eq!(decoded.header.hash(), block.header.hash());
+}
+
+#[test]
+fn invoice_encoding() {
+    let log = LogEntry {
+        address: H256::from([0x66; 32]),
+        topics: vec![H256::from([0x77; 32]), H256::from([0x88; 32])],
+    };
+    let success = Invoice {
+        outcome: TransactionOutcome::Success,
+        fee: 5.into(),
+        logs: vec![log],
+    };
+    let failed = Invoice {
+        outcome: TransactionOutcome::Failed,
+        fee: 0.into(),
+        logs: vec![],
+    };
+    assert_eq!(
+        rlp::encode(&success).to_hex(),
+        "f86b0105f867f865a06666666666666666666666666666666666666666666666666666666666666666\
+         f842a07777777777777777777777777777777777777777777777777777777777777777\
+         a08888888888888888888888888888888888888888888888888888888888888888"
+    );
+    assert_eq!(rlp::encode(&failed).to_hex(), "c38080c0");
+}
+
+#[test]
+fn state_encoding() {
+    let mut account = Account::new(U256::from(1_000_000_000_000_000_000u64) * U256::from(100), 2.into());
+    account.set_regular_key(&Public::from([0x99; 64]));
+    assert_eq!(
+        rlp::encode(&account).to_hex(),
+        "f8504389056bc75e2d6310000002f842b840\
+         9999999999999999999999999999999999999999999999999999999999999999\
+         9999999999999999999999999999999999999999999999999999999999999999"
+    );
+
+    let asset_scheme = AssetScheme::new("conformance".to_string(), 0x1_0000_0000, Some(Address::from([0xbb; 20])));
+    assert_eq!(
+        rlp::encode(&asset_scheme).to_hex(),
+        "e9538b636f6e666f726d616e6365850100000000d594bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+    );
+
+    let asset = Asset::new(H256::from([0xdd; 32]), H256::from([0xee; 32]), vec![vec![0x01]], 0x1_0000_0001);
+    assert_eq!(
+        rlp::encode(&asset).to_hex(),
+        "f84b41a0dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd\
+         a0eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeec101850100000001"
+    );
+
+    let shard = Shard::new(H256::from([0x77; 32]));
+    assert_eq!(rlp::encode(&shard).to_hex(), "e248a07777777777777777777777777777777777777777777777777777777777777777");
+}
+
+#[test]
+fn state_addresses() {
+    let transaction_hash = H256::from([0xcc; 32]);
+    let asset_address: H256 = AssetAddress::new(transaction_hash, 3, 0).into();
+    assert_eq!(asset_address, H256::from("4100000000000000d1c5660255fee81cad12d29f8d1b8c8a4875a6299e9de2a9"));
+    let asset_scheme_address: H256 = AssetSchemeAddress::new(transaction_hash, 0).into();
+    assert_eq!(asset_scheme_address, H256::from("53000000000000006d7a664eb2821d398b2a03a5be7a77489f2250994875b764"));
+
+    let asset_address: H256 = AssetAddress::new(transaction_hash, 3, 0x0102).into();
+    assert_eq!(asset_address, H256::from("4100010200000000d1c5660255fee81cad12d29f8d1b8c8a4875a6299e9de2a9"));
+    let shard_address: H256 = ShardAddress::new(0x0102).into();
+    assert_eq!(shard_address, H256::from("4800010200000000000000000000000000000000000000000000000000000000"));
+}
+
+#[test]
+fn index_larger_than_32_bits_is_rejected_on_32_bit_platforms() {
+    let mut outpoint = rlp::RlpStream::new_list(4);
+    outpoint.append(&H256::from([0xcc; 32])).append(&0x1_0000_0000u64).append(&H256::from([0xdd; 32])).append(&1u64);
+    let bytes = outpoint.out();
+    let decoded = UntrustedRlp::new(&bytes).as_val::<AssetOutPoint>();
+    if cfg!(target_pointer_width = "32") {
+        assert!(decoded.is_err());
+    } else {
+        assert_eq!(decoded.unwrap().index as u64, 0x1_0000_0000u64);
+    }
+}
@@ -22,6 +22,8 @@ use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use unexpected::Mismatch;
 
 use super::parcel::{AssetTransferInput, AssetTransferOutput};
+use super::state::CrossShardMessage;
+use super::types::ShardId;
 
 /// Parcel transaction type.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -41,6 +43,7 @@ pub enum Transaction {
     },
     #[serde(rename_all = "camelCase")]
     AssetMint {
+        shard_id: ShardId,
         metadata: String,
         lock_script_hash: H256,
         parameters: Vec<Bytes>,
@@ -51,10 +54,24 @@ pub enum Transaction {
     #[serde(rename_all = "camelCase")]
     AssetTransfer {
         network_id: u64,
+        shard_id: ShardId,
         inputs: Vec<AssetTransferInput>,
         outputs: Vec<AssetTransferOutput>,
         nonce: u64,
     },
+    /// Applies the message which the transfer in the source shard sent to `shard_id`.
+    /// The proof is the nodes of the source shard's trie on the path to the message.
+    #[serde(rename_all = "camelCase")]
+    AssetRelay {
+        network_id: u64,
+        shard_id: ShardId,
+        source_shard_id: ShardId,
+        transaction_hash: H256,
+        index: usize,
+        message: CrossShardMessage,
+        proof: Vec<Bytes>,
+        nonce: u64,
+    },
 }
 
 impl Transaction {
@@ -62,6 +79,7 @@ impl Transaction {
         match self {
             Transaction::AssetTransfer {
                 network_id,
+                shard_id,
                 inputs,
                 outputs,
                 nonce,
@@ -72,10 +90,12 @@ impl Transaction {
                         prev_out: input.prev_out.clone(),
                         lock_script: Vec::new(),
                         unlock_script: Vec::new(),
+                        timelock: input.timelock,
                     })
                     .collect();
                 Transaction::AssetTransfer {
                     network_id: *network_id,
+                    shard_id: *shard_id,
                     inputs: new_inputs,
                     outputs: outputs.clone(),
                     nonce: *nonce,
@@ -99,6 +119,7 @@ const PAYMENT_ID: TransactionId = 0x01;
 const SET_REGULAR_KEY_ID: TransactionId = 0x02;
 const ASSET_MINT_ID: TransactionId = 0x03;
 const ASSET_TRANSFER_ID: TransactionId = 0x04;
+const ASSET_RELAY_ID: TransactionId = 0x05;
 
 impl Decodable for Transaction {
     fn decode(d: &UntrustedRlp) -> Result<Self, DecoderError> {
@@ -125,27 +146,44 @@ impl Decodable for Transaction {
                 })
             }
             ASSET_MINT_ID => {
-                if d.item_count()? != 7 {
+                if d.item_count()? != 8 {
                     return Err(DecoderError::RlpIncorrectListLen)
                 }
                 Ok(Transaction::AssetMint {
-                    metadata: d.val_at(1)?,
-                    lock_script_hash: d.val_at(2)?,
-                    parameters: d.val_at(3)?,
-                    amount: d.val_at(4)?,
-                    registrar: d.val_at(5)?,
-                    nonce: d.val_at(6)?,
+                    shard_id: d.val_at(1)?,
+                    metadata: d.val_at(2)?,
+                    lock_script_hash: d.val_at(3)?,
+                    parameters: d.val_at(4)?,
+                    amount: d.val_at(5)?,
+                    registrar: d.val_at(6)?,
+                    nonce: d.val_at(7)?,
                 })
             }
             ASSET_TRANSFER_ID => {
-                if d.item_count()? != 5 {
+                if d.item_count()? != 6 {
                     return Err(DecoderError::RlpIncorrectListLen)
                 }
                 Ok(Transaction::AssetTransfer {
                     network_id: d.val_at(1)?,
-                    inputs: d.list_at(2)?,
-                    outputs: d.list_at(3)?,
-                    nonce: d.val_at(4)?,
+                    shard_id: d.val_at(2)?,
+                    inputs: d.list_at(3)?,
+                    outputs: d.list_at(4)?,
+                    nonce: d.val_at(5)?,
+                })
+            }
+            ASSET_RELAY_ID => {
+                if d.item_count()? != 9 {
+                    return Err(DecoderError::RlpIncorrectListLen)
+                }
+                Ok(Transaction::AssetRelay {
+                    network_id: d.val_at(1)?,
+                    shard_id: d.val_at(2)?,
+                    source_shard_id: d.val_at(3)?,
+                    transaction_hash: d.val_at(4)?,
+                    index: d.val_at(5)?,
+                    message: d.val_at(6)?,
+                    proof: d.val_at(7)?,
+                    nonce: d.val_at(8)?,
                 })
             }
             _ => Err(DecoderError::Custom("Unexpected transaction")),
@@ -168,14 +206,16 @@ impl Encodable for Transaction {
                 key,
             } => s.begin_list(4).append(&SET_REGULAR_KEY_ID).append(address).append(nonce).append(key),
             Transaction::AssetMint {
+                shard_id,
                 metadata,
                 lock_script_hash,
                 parameters,
                 amount,
                 registrar,
                 nonce,
-            } => s.begin_list(7)
+            } => s.begin_list(8)
                 .append(&ASSET_MINT_ID)
+                .append(shard_id)
                 .append(metadata)
                 .append(lock_script_hash)
                 .append(parameters)
@@ -184,15 +224,36 @@ impl Encodable for Transaction {
                 .append(nonce),
             Transaction::AssetTransfer {
                 network_id,
+                shard_id,
                 inputs,
                 outputs,
                 nonce,
-            } => s.begin_list(5)
+            } => s.begin_list(6)
                 .append(&ASSET_TRANSFER_ID)
                 .append(network_id)
+                .append(shard_id)
                 .append_list(inputs)
                 .append_list(outputs)
                 .append(nonce),
+            Transaction::AssetRelay {
+                network_id,
+                shard_id,
+                source_shard_id,
+                transaction_hash,
+                index,
+                message,
+                proof,
+                nonce,
+            } => s.begin_list(9)
+                .append(&ASSET_RELAY_ID)
+                .append(network_id)
+                .append(shard_id)
+                .append(source_shard_id)
+                .append(transaction_hash)
+                .append(index)
+                .append(message)
+                .append(proof)
+                .append(nonce),
         };
     }
 }
@@ -223,6 +284,12 @@ pub enum Error {
     /// Script execution result is `Fail`
     FailedToUnlock(H256),
     InvalidNetworkId(Mismatch<u64>),
+    /// The shard doesn't exist or the asset belongs to another shard
+    InvalidShardId(ShardId),
+    /// The proof doesn't show that the source shard has the message
+    InvalidMessageProof,
+    /// The destination shard already has applied the message
+    MessageAlreadyRelayed(H256),
 }
 
 impl fmt::Display for Error {
@@ -253,6 +320,9 @@ impl fmt::Display for Error {
             Error::InvalidScript => write!(f, "Failed to decode script"),
             Error::FailedToUnlock(hash) => write!(f, "Failed to unlock asset {}", hash),
             Error::InvalidNetworkId(mismatch) => write!(f, "Invalid network id. {}", mismatch),
+            Error::InvalidShardId(shard_id) => write!(f, "Invalid shard id: {}", shard_id),
+            Error::InvalidMessageProof => write!(f, "The proof of the cross-shard message is invalid"),
+            Error::MessageAlreadyRelayed(hash) => write!(f, "The cross-shard message {} is already relayed", hash),
         }
     }
 }
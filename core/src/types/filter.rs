@@ -0,0 +1,100 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ctypes::{Bloom, BloomInput, H256};
+
+use super::super::invoice::LogEntry;
+use super::BlockId;
+
+/// Selects the logs in the range of the blocks by their addresses and topics.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Filter {
+    /// The first block of the range.
+    pub from_block: BlockId,
+    /// The last block of the range.
+    pub to_block: BlockId,
+    /// The log matches if its address is any of them. Empty matches every address.
+    pub addresses: Vec<H256>,
+    /// The topics of the log must match them in order. `None` matches any topic.
+    pub topics: Vec<Option<H256>>,
+}
+
+impl Filter {
+    /// Returns false if no log in the block with the bloom can match.
+    pub fn matches_bloom(&self, bloom: &Bloom) -> bool {
+        let address_matches = self.addresses.is_empty()
+            || self.addresses.iter().any(|address| bloom.contains_input(BloomInput::Raw(address)));
+        address_matches && self.topics.iter().all(|topic| match topic {
+            Some(topic) => bloom.contains_input(BloomInput::Raw(topic)),
+            None => true,
+        })
+    }
+
+    pub fn matches(&self, log: &LogEntry) -> bool {
+        let address_matches = self.addresses.is_empty() || self.addresses.contains(&log.address);
+        address_matches && self.topics.iter().enumerate().all(|(i, topic)| match topic {
+            Some(topic) => log.topics.get(i) == Some(topic),
+            None => true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes::H256;
+
+    use super::super::super::invoice::{EventKind, LogEntry};
+    use super::super::BlockId;
+    use super::Filter;
+
+    fn filter(addresses: Vec<H256>, topics: Vec<Option<H256>>) -> Filter {
+        Filter {
+            from_block: BlockId::Earliest,
+            to_block: BlockId::Latest,
+            addresses,
+            topics,
+        }
+    }
+
+    #[test]
+    fn matches_the_address_and_the_topics_in_order() {
+        let asset_type = H256::from([0x11; 32]);
+        let lock_script_hash = H256::from([0x22; 32]);
+        let log = LogEntry::new(EventKind::AssetTransfer, asset_type, vec![lock_script_hash]);
+        let bloom = log.bloom();
+
+        let matching = vec![
+            filter(vec![], vec![]),
+            filter(vec![H256::from([0x33; 32]), asset_type], vec![]),
+            filter(vec![], vec![Some(EventKind::AssetTransfer.topic())]),
+            filter(vec![asset_type], vec![None, Some(lock_script_hash)]),
+        ];
+        for filter in matching {
+            assert!(filter.matches_bloom(&bloom), "{:?}", filter);
+            assert!(filter.matches(&log), "{:?}", filter);
+        }
+
+        let other = vec![
+            filter(vec![lock_script_hash], vec![]),
+            filter(vec![], vec![Some(EventKind::AssetMint.topic())]),
+            filter(vec![], vec![Some(lock_script_hash)]),
+            filter(vec![], vec![None, None, Some(lock_script_hash)]),
+        ];
+        for filter in other {
+            assert!(!filter.matches(&log), "{:?}", filter);
+        }
+    }
+}
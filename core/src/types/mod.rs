@@ -15,11 +15,14 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod block_status;
+mod filter;
 mod ids;
 mod verification_queue_info;
 
 pub use self::block_status::BlockStatus;
+pub use self::filter::Filter;
 pub use self::ids::{BlockId, ParcelId, TransactionId};
 pub use self::verification_queue_info::VerificationQueueInfo;
 
 pub type BlockNumber = u64;
+pub type ShardId = u16;
@@ -22,7 +22,7 @@ use heapsize::HeapSizeOf;
 use rlp::UntrustedRlp;
 use unexpected::{Mismatch, OutOfBounds};
 
-use super::super::blockchain::BlockProvider;
+use super::super::blockchain::{BlockProvider, HeaderProvider};
 use super::super::client::BlockInfo;
 use super::super::consensus::CodeChainEngine;
 use super::super::error::{BlockError, Error};
@@ -75,6 +75,14 @@ pub fn verify_header_params(header: &Header, engine: &CodeChainEngine) -> Result
             found: header.number(),
         })))
     }
+    if let Some(checkpoint) = engine.params().checkpoints.get(&header.number()) {
+        if *checkpoint != header.hash() {
+            return Err(From::from(BlockError::InvalidCheckpoint(Mismatch {
+                expected: *checkpoint,
+                found: header.hash(),
+            })))
+        }
+    }
     let maximum_extra_data_size = engine.maximum_extra_data_size();
     if header.number() != 0 && header.extra_data().len() > maximum_extra_data_size {
         return Err(From::from(BlockError::ExtraDataOutOfBounds(OutOfBounds {
@@ -151,6 +159,23 @@ pub fn verify_block_unordered(
     })
 }
 
+/// Whether the header is proven to be an ancestor of the last checkpoint by the imported headers.
+/// It is if both the checkpoint and the header are on the canonical chain of the headers, which is linked by the
+/// parent hashes. The seals of such headers aren't verified.
+pub fn is_checkpoint_ancestor<C>(header: &Header, engine: &CodeChainEngine, chain: &C) -> bool
+where
+    C: HeaderProvider + ?Sized, {
+    let (checkpoint_number, checkpoint_hash) = match engine.params().last_checkpoint() {
+        Some(checkpoint) => checkpoint,
+        None => return false,
+    };
+    if header.number() > checkpoint_number {
+        return false
+    }
+    chain.block_hash(checkpoint_number) == Some(checkpoint_hash)
+        && chain.block_hash(header.number()) == Some(header.hash())
+}
+
 /// Parameters for full verification of block family
 pub struct FullFamilyParams<'a, C: BlockInfo + 'a> {
     /// Serialized block bytes
@@ -232,11 +257,168 @@ pub fn verify_block_final(expected: &Header, got: &Header) -> Result<(), Error>
             found: got.state_root().clone(),
         })))
     }
+    if expected.shard_roots().len() != got.shard_roots().len() {
+        return Err(From::from(BlockError::InvalidShardCount(Mismatch {
+            expected: expected.shard_roots().len(),
+            found: got.shard_roots().len(),
+        })))
+    }
+    for (expected, got) in expected.shard_roots().iter().zip(got.shard_roots()) {
+        if expected != got {
+            return Err(From::from(BlockError::InvalidShardRoot(Mismatch {
+                expected: *expected,
+                found: *got,
+            })))
+        }
+    }
     if expected.invoices_root() != got.invoices_root() {
         return Err(From::from(BlockError::InvalidInvoicesRoot(Mismatch {
             expected: expected.invoices_root().clone(),
             found: got.invoices_root().clone(),
         })))
     }
+    if expected.log_bloom() != got.log_bloom() {
+        return Err(From::from(BlockError::InvalidLogBloom(Box::new(Mismatch {
+            expected: *expected.log_bloom(),
+            found: *got.log_bloom(),
+        }))))
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ctypes::Bloom;
+
+    use super::super::super::blockchain::BlockDetails;
+    use super::super::super::codechain_machine::CodeChainMachine;
+    use super::super::super::consensus::NullEngine;
+    use super::super::super::encoded;
+    use super::super::super::spec::CommonParams;
+    use super::*;
+
+    struct TestChain {
+        canonical: HashMap<BlockNumber, H256>,
+    }
+
+    impl HeaderProvider for TestChain {
+        fn is_known_header(&self, hash: &H256) -> bool {
+            self.canonical.values().any(|canonical| canonical == hash)
+        }
+
+        fn block_details(&self, _hash: &H256) -> Option<BlockDetails> {
+            None
+        }
+
+        fn block_hash(&self, index: BlockNumber) -> Option<H256> {
+            self.canonical.get(&index).cloned()
+        }
+
+        fn block_header_data(&self, _hash: &H256) -> Option<encoded::Header> {
+            None
+        }
+    }
+
+    fn headers(count: BlockNumber) -> Vec<Header> {
+        let mut headers: Vec<Header> = Vec::new();
+        for number in 0..count {
+            let mut header = Header::new();
+            header.set_number(number);
+            if let Some(parent) = headers.last() {
+                header.set_parent_hash(parent.hash());
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn engine_with_checkpoint(checkpoint: &Header) -> Box<CodeChainEngine> {
+        let mut params = CommonParams::default();
+        params.checkpoints.insert(checkpoint.number(), checkpoint.hash());
+        Box::new(NullEngine::new(Default::default(), CodeChainMachine::new(params)))
+    }
+
+    fn chain_of(headers: &[Header]) -> TestChain {
+        TestChain {
+            canonical: headers.iter().map(|header| (header.number(), header.hash())).collect(),
+        }
+    }
+
+    #[test]
+    fn ancestor_of_the_imported_checkpoint_is_trusted() {
+        let headers = headers(5);
+        let engine = engine_with_checkpoint(&headers[3]);
+        let chain = chain_of(&headers);
+
+        assert!(is_checkpoint_ancestor(&headers[1], &*engine, &chain));
+        assert!(is_checkpoint_ancestor(&headers[3], &*engine, &chain));
+        assert!(!is_checkpoint_ancestor(&headers[4], &*engine, &chain));
+    }
+
+    #[test]
+    fn forged_header_below_the_checkpoint_is_not_trusted() {
+        let headers = headers(5);
+        let engine = engine_with_checkpoint(&headers[3]);
+        let chain = chain_of(&headers);
+
+        let mut forged = headers[1].clone();
+        forged.set_extra_data(b"forged".to_vec());
+        assert!(!is_checkpoint_ancestor(&forged, &*engine, &chain));
+    }
+
+    #[test]
+    fn header_is_not_trusted_before_the_checkpoint_is_imported() {
+        let headers = headers(5);
+        let engine = engine_with_checkpoint(&headers[3]);
+        let chain = chain_of(&headers[..3]);
+
+        assert!(!is_checkpoint_ancestor(&headers[1], &*engine, &chain));
+    }
+
+    #[test]
+    fn header_is_not_trusted_on_a_fork_of_the_checkpoint() {
+        let headers = headers(5);
+        let engine = engine_with_checkpoint(&headers[3]);
+        let mut fork = headers.clone();
+        fork[3].set_extra_data(b"fork".to_vec());
+        let chain = chain_of(&fork);
+
+        assert!(!is_checkpoint_ancestor(&fork[1], &*engine, &chain));
+    }
+
+    #[test]
+    fn executed_shard_roots_must_match_the_header() {
+        let mut expected = Header::new();
+        expected.set_shard_roots(vec![H256::from([0x11; 32]), H256::from([0x22; 32])]);
+        let mut got = expected.clone();
+        assert!(verify_block_final(&expected, &got).is_ok());
+
+        got.set_shard_roots(vec![H256::from([0x11; 32]), H256::from([0x33; 32])]);
+        match verify_block_final(&expected, &got) {
+            Err(Error::Block(BlockError::InvalidShardRoot(_))) => {}
+            _ => panic!("InvalidShardRoot expected"),
+        }
+
+        got.set_shard_roots(vec![H256::from([0x11; 32])]);
+        match verify_block_final(&expected, &got) {
+            Err(Error::Block(BlockError::InvalidShardCount(_))) => {}
+            _ => panic!("InvalidShardCount expected"),
+        }
+    }
+
+    #[test]
+    fn executed_log_bloom_must_match_the_header() {
+        let mut expected = Header::new();
+        expected.set_log_bloom(Bloom::from([0x11; 256]));
+        let mut got = expected.clone();
+        assert!(verify_block_final(&expected, &got).is_ok());
+
+        got.set_log_bloom(Bloom::default());
+        match verify_block_final(&expected, &got) {
+            Err(Error::Block(BlockError::InvalidLogBloom(_))) => {}
+            _ => panic!("InvalidLogBloom expected"),
+        }
+    }
+}
@@ -15,7 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use ccrypto::blake256;
-use ctypes::{Address, Bytes, H256, U256};
+use ctypes::{Address, Bloom, Bytes, H256, U256};
 use rlp::{self, Rlp};
 
 use super::super::types::BlockNumber;
@@ -95,10 +95,20 @@ impl<'a> HeaderView<'a> {
         self.rlp.val_at(8)
     }
 
+    /// Returns the roots of the state tries of the shards.
+    pub fn shard_roots(&self) -> Vec<H256> {
+        self.rlp.list_at(9)
+    }
+
+    /// Returns the bloom of the addresses and the topics of the logs.
+    pub fn log_bloom(&self) -> Bloom {
+        self.rlp.val_at(10)
+    }
+
     /// Returns a vector of post-RLP-encoded seal fields.
     pub fn seal(&self) -> Vec<Bytes> {
         let mut seal = vec![];
-        for i in 9..self.rlp.item_count() {
+        for i in 11..self.rlp.item_count() {
             seal.push(self.rlp.at(i).as_raw().to_vec());
         }
         seal
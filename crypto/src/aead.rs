@@ -0,0 +1,141 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ctypes::H256;
+use rcrypto::aead::{AeadDecryptor, AeadEncryptor};
+use rcrypto::aes::KeySize::KeySize256;
+use rcrypto::aes_gcm::AesGcm;
+use rcrypto::chacha20poly1305::ChaCha20Poly1305;
+
+pub const TAG_LENGTH: usize = 16;
+pub const AES_256_GCM_NONCE_LENGTH: usize = 12;
+pub const CHACHA20_POLY1305_NONCE_LENGTH: usize = 8;
+
+fn seal<E: AeadEncryptor>(mut encryptor: E, plain: &[u8]) -> Vec<u8> {
+    let mut sealed = vec![0u8; plain.len() + TAG_LENGTH];
+    {
+        let (cipher_text, tag) = sealed.split_at_mut(plain.len());
+        encryptor.encrypt(plain, cipher_text, tag);
+    }
+    sealed
+}
+
+fn open<D: AeadDecryptor>(mut decryptor: D, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LENGTH {
+        return None
+    }
+    let (cipher_text, tag) = sealed.split_at(sealed.len() - TAG_LENGTH);
+    let mut plain = vec![0u8; cipher_text.len()];
+    if decryptor.decrypt(cipher_text, &mut plain, tag) {
+        Some(plain)
+    } else {
+        None
+    }
+}
+
+/// AES-256/GCM encryption. The authentication tag is appended to the cipher text.
+///
+/// The nonce must not be reused with the same key.
+pub fn aes_256_gcm_seal(key: &H256, nonce: &[u8; AES_256_GCM_NONCE_LENGTH], aad: &[u8], plain: &[u8]) -> Vec<u8> {
+    seal(AesGcm::new(KeySize256, key, nonce, aad), plain)
+}
+
+/// AES-256/GCM decryption. Returns None if the data or the associated data is forged.
+pub fn aes_256_gcm_open(
+    key: &H256,
+    nonce: &[u8; AES_256_GCM_NONCE_LENGTH],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    open(AesGcm::new(KeySize256, key, nonce, aad), sealed)
+}
+
+/// ChaCha20-Poly1305 encryption. The authentication tag is appended to the cipher text.
+///
+/// The nonce must not be reused with the same key.
+pub fn chacha20_poly1305_seal(
+    key: &H256,
+    nonce: &[u8; CHACHA20_POLY1305_NONCE_LENGTH],
+    aad: &[u8],
+    plain: &[u8],
+) -> Vec<u8> {
+    seal(ChaCha20Poly1305::new(key, nonce, aad), plain)
+}
+
+/// ChaCha20-Poly1305 decryption. Returns None if the data or the associated data is forged.
+pub fn chacha20_poly1305_open(
+    key: &H256,
+    nonce: &[u8; CHACHA20_POLY1305_NONCE_LENGTH],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    open(ChaCha20Poly1305::new(key, nonce, aad), sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use ctypes::H256;
+
+    use self::rand::{OsRng, Rng};
+    use super::*;
+
+    fn key() -> H256 {
+        let mut key = H256([0; 32]);
+        OsRng::new().unwrap().fill_bytes(&mut key);
+        key
+    }
+
+    #[test]
+    fn aes_256_gcm_opens_the_sealed_data() {
+        let key = key();
+        let nonce = [7u8; AES_256_GCM_NONCE_LENGTH];
+        let sealed = aes_256_gcm_seal(&key, &nonce, b"aad", b"some data");
+        assert_eq!(b"some data".len() + TAG_LENGTH, sealed.len());
+        assert_eq!(Some(b"some data".to_vec()), aes_256_gcm_open(&key, &nonce, b"aad", &sealed));
+    }
+
+    #[test]
+    fn aes_256_gcm_rejects_the_forged_data() {
+        let key = key();
+        let nonce = [7u8; AES_256_GCM_NONCE_LENGTH];
+        let mut sealed = aes_256_gcm_seal(&key, &nonce, b"aad", b"some data");
+        assert_eq!(None, aes_256_gcm_open(&key, &nonce, b"another aad", &sealed));
+        sealed[0] ^= 1;
+        assert_eq!(None, aes_256_gcm_open(&key, &nonce, b"aad", &sealed));
+        assert_eq!(None, aes_256_gcm_open(&key, &nonce, b"aad", &sealed[..TAG_LENGTH - 1]));
+    }
+
+    #[test]
+    fn chacha20_poly1305_opens_the_sealed_data() {
+        let key = key();
+        let nonce = [7u8; CHACHA20_POLY1305_NONCE_LENGTH];
+        let sealed = chacha20_poly1305_seal(&key, &nonce, b"aad", b"some data");
+        assert_eq!(Some(b"some data".to_vec()), chacha20_poly1305_open(&key, &nonce, b"aad", &sealed));
+    }
+
+    #[test]
+    fn chacha20_poly1305_rejects_the_forged_data() {
+        let key = key();
+        let nonce = [7u8; CHACHA20_POLY1305_NONCE_LENGTH];
+        let mut sealed = chacha20_poly1305_seal(&key, &nonce, b"aad", b"some data");
+        assert_eq!(None, chacha20_poly1305_open(&key, &[8u8; CHACHA20_POLY1305_NONCE_LENGTH], b"aad", &sealed));
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert_eq!(None, chacha20_poly1305_open(&key, &nonce, b"aad", &sealed));
+    }
+}
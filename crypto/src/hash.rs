@@ -14,10 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ctypes::H160;
+use ctypes::{H160, H256, H512};
 use rcrypto::digest::Digest;
+use rcrypto::hmac::Hmac;
+use rcrypto::mac::Mac;
 use rcrypto::ripemd160::Ripemd160;
 use rcrypto::sha1::Sha1;
+use rcrypto::sha2::{Sha256, Sha512};
 
 /// RIPEMD160
 #[inline]
@@ -41,9 +44,29 @@ pub fn sha1<T: AsRef<[u8]>>(s: T) -> H160 {
     result
 }
 
+/// SHA-256
+#[inline]
+pub fn sha256<T: AsRef<[u8]>>(s: T) -> H256 {
+    let input = s.as_ref();
+    let mut result = H256::default();
+    let mut hasher = Sha256::new();
+    hasher.input(input);
+    hasher.result(&mut *result);
+    result
+}
+
+/// HMAC with SHA-512
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> H512 {
+    let mut result = H512::default();
+    let mut hmac = Hmac::new(Sha512::new(), key);
+    hmac.input(data);
+    hmac.raw_result(&mut *result);
+    result
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ripemd160, sha1};
+    use super::{hmac_sha512, ripemd160, sha1, sha256};
 
     #[test]
     fn test_ripemd160() {
@@ -58,4 +81,20 @@ mod tests {
         let result = sha1(b"hello");
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_sha256() {
+        let expected = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".into();
+        let result = sha256(b"hello");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_hmac_sha512() {
+        let expected = "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                        9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+            .into();
+        let result = hmac_sha512(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(result, expected);
+    }
 }
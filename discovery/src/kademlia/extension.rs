@@ -28,6 +28,7 @@ use super::config::Config;
 use super::event::Event;
 use super::kademlia::Kademlia;
 use super::message::Message;
+use super::status::{Lookup, Table};
 
 
 pub struct Extension {
@@ -56,6 +57,20 @@ impl Extension {
         }
     }
 
+    /// The routing tables for the node ids which the peers know this node by.
+    pub fn routing_tables(&self) -> Vec<Table> {
+        let kademlias = self.kademlias.read();
+        kademlias.values().map(|kademlia| kademlia.table()).collect()
+    }
+
+    /// The answers of the peers which were asked for the nodes, from the oldest.
+    pub fn recent_lookups(&self) -> Vec<Lookup> {
+        let kademlias = self.kademlias.read();
+        let mut lookups: Vec<_> = kademlias.values().flat_map(|kademlia| kademlia.recent_lookups()).collect();
+        lookups.sort_by_key(|lookup| lookup.timestamp);
+        lookups
+    }
+
     fn on_receive(&self, node: &NodeId, message: &[u8]) -> ::std::result::Result<(), DecoderError> {
         if let Some(sender) = self.get_address(&node) {
             let rlp = UntrustedRlp::new(message);
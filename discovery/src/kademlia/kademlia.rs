@@ -18,6 +18,7 @@ extern crate rand;
 
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
 use cnetwork::SocketAddr;
@@ -27,14 +28,19 @@ use super::contact::Contact;
 use super::message::{self, Message};
 use super::node_id::log2_distance_between_nodes;
 use super::routing_table::RoutingTable;
+use super::status::{Lookup, Table};
 use super::NodeId;
 
+// The number of the lookups which are kept for the diagnosis
+const RECENT_LOOKUPS: usize = 32;
+
 pub struct Kademlia {
     k: u8,
     pub t_refresh: u32,
     table: RoutingTable,
     to_be_verified: VecDeque<Contact>,
     seq: AtomicUsize,
+    lookups: VecDeque<Lookup>,
 }
 
 impl Kademlia {
@@ -45,6 +51,7 @@ impl Kademlia {
             table: RoutingTable::new(local_id, k),
             to_be_verified: VecDeque::new(),
             seq: AtomicUsize::new(0),
+            lookups: VecDeque::new(),
         }
     }
 
@@ -100,10 +107,20 @@ impl Kademlia {
         })
     }
 
-    fn handle_nodes_message(&mut self, sender: NodeId, contacts: &Vec<Contact>) -> Option<Command> {
+    fn handle_nodes_message(
+        &mut self,
+        sender: NodeId,
+        contacts: &Vec<Contact>,
+        sender_address: &SocketAddr,
+    ) -> Option<Command> {
         let local_id = self.local_id();
         let distance_to_target = log2_distance_between_nodes(&local_id, &sender);
         let add_aggressive = self.table.len() < self.k as usize;
+        let new_contacts = contacts
+            .iter()
+            .filter(|contact| contact.id() != local_id && contact.id() != sender && !self.table.contains(contact))
+            .count();
+        self.record_lookup(sender_address, contacts.len(), new_contacts);
         contacts
             .into_iter()
             .take(self.k as usize)
@@ -114,6 +131,20 @@ impl Kademlia {
             .and(Some(Command::Verify))
     }
 
+    fn record_lookup(&mut self, peer: &SocketAddr, received: usize, new_contacts: usize) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        self.lookups.push_back(Lookup {
+            local_id: self.local_id(),
+            peer: peer.clone(),
+            received,
+            new_contacts,
+            timestamp,
+        });
+        if RECENT_LOOKUPS < self.lookups.len() {
+            self.lookups.pop_front();
+        }
+    }
+
     pub fn handle_message(&mut self, message: &Message, sender_address: &SocketAddr) -> Option<Command> {
         // FIXME : Check validity of response first.
 
@@ -136,7 +167,7 @@ impl Kademlia {
                 contacts,
                 sender,
                 ..
-            } => self.handle_nodes_message(*sender, contacts),
+            } => self.handle_nodes_message(*sender, contacts, sender_address),
         }
     }
 
@@ -180,6 +211,18 @@ impl Kademlia {
         Some(Command::Verify)
     }
 
+    pub fn table(&self) -> Table {
+        Table {
+            local_id: self.local_id(),
+            buckets: self.table.buckets(),
+        }
+    }
+
+    /// The lookups from the oldest.
+    pub fn recent_lookups(&self) -> Vec<Lookup> {
+        self.lookups.iter().cloned().collect()
+    }
+
     pub fn remove(&mut self, address: &SocketAddr) {
         let _ = self.table.remove_address(&address);
         let _ = self.to_be_verified.retain(|contact| contact.addr() != address);
@@ -190,8 +233,10 @@ impl Kademlia {
 #[cfg(test)]
 mod tests {
     use super::super::contact::Contact;
+    use super::super::message::Message;
     use super::Kademlia;
     use super::NodeId;
+    use super::RECENT_LOOKUPS;
 
     use super::super::{K, T_REFRESH};
 
@@ -304,6 +349,44 @@ mod tests {
         assert_eq!(1, kademlia.to_be_verified.len());
     }
 
+    #[test]
+    fn test_nodes_message_is_recorded_as_lookup() {
+        let id = Contact::from_hash(ID0).id();
+        let mut kademlia = default_kademlia(id);
+
+        let sender = Contact::from_hash_with_addr(ID1, 127, 0, 0, 1, 3485);
+        let message = Message::Nodes {
+            id: 0,
+            sender: sender.id(),
+            contacts: vec![Contact::from_hash_with_addr(ID4, 127, 0, 0, 1, 3486)],
+        };
+        kademlia.handle_message(&message, sender.addr());
+
+        let lookups = kademlia.recent_lookups();
+        assert_eq!(1, lookups.len());
+        assert_eq!(sender.addr(), &lookups[0].peer);
+        assert_eq!(1, lookups[0].received);
+        assert_eq!(1, lookups[0].new_contacts);
+    }
+
+    #[test]
+    fn test_only_recent_lookups_are_kept() {
+        let id = Contact::from_hash(ID0).id();
+        let mut kademlia = default_kademlia(id);
+
+        let sender = Contact::from_hash_with_addr(ID1, 127, 0, 0, 1, 3485);
+        let message = Message::Nodes {
+            id: 0,
+            sender: sender.id(),
+            contacts: vec![],
+        };
+        for _ in 0..(RECENT_LOOKUPS + 1) {
+            kademlia.handle_message(&message, sender.addr());
+        }
+        assert_eq!(RECENT_LOOKUPS, kademlia.recent_lookups().len());
+        assert!(kademlia.recent_lookups().iter().all(|lookup| lookup.new_contacts == 0));
+    }
+
     #[test]
     fn handle_refresh_command_must_not_crash() {
         let mut kademlia = Kademlia::new(0xDEADBEEF.into(), 8, 60_000);
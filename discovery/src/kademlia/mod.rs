@@ -23,10 +23,12 @@ mod kademlia;
 mod message;
 mod node_id;
 mod routing_table;
+mod status;
 
 
 pub use self::config::Config;
 pub use self::extension::Extension;
+pub use self::status::{Bucket, Lookup, Table};
 use self::node_id::NodeId;
 
 
@@ -4,6 +4,7 @@ use std::collections::{BTreeSet, HashMap, VecDeque};
 use cnetwork::SocketAddr;
 
 use super::contact::Contact;
+use super::status;
 use super::NodeId;
 
 pub struct RoutingTable {
@@ -141,6 +142,22 @@ impl RoutingTable {
     pub fn len(&self) -> usize {
         self.buckets.values().map(|bucket| bucket.contacts.len()).sum()
     }
+
+    /// The non-empty buckets by their distances.
+    pub fn buckets(&self) -> Vec<status::Bucket> {
+        let mut buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .filter(|(_, bucket)| !bucket.is_empty())
+            .map(|(distance, bucket)| status::Bucket {
+                distance: *distance,
+                capacity: bucket.bucket_size,
+                contacts: bucket.contacts.iter().map(|contact| (contact.id(), contact.addr().clone())).collect(),
+            })
+            .collect();
+        buckets.sort_by_key(|bucket| bucket.distance);
+        buckets
+    }
 }
 
 
@@ -263,6 +280,18 @@ mod tests {
         routing_table
     }
 
+    #[test]
+    fn test_buckets_are_ordered_by_distance() {
+        const BUCKET_SIZE: u8 = 5;
+        let routing_table = init_routing_table(BUCKET_SIZE, 0);
+
+        let buckets = routing_table.buckets();
+        assert_eq!(vec![1, 2, 3, 4, 5], buckets.iter().map(|bucket| bucket.distance).collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 4, 8, 2], buckets.iter().map(|bucket| bucket.contacts.len()).collect::<Vec<_>>());
+        assert!(buckets.iter().all(|bucket| bucket.capacity == BUCKET_SIZE));
+        assert_eq!((get_contact(1).id(), get_contact(1).addr().clone()), buckets[0].contacts[0]);
+    }
+
     #[test]
     fn test_size_of_closest_contacts_is_not_larger_than_bucket_size() {
         const BUCKET_SIZE: u8 = 5;
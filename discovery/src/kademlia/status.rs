@@ -0,0 +1,47 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use cnetwork::SocketAddr;
+
+use super::NodeId;
+
+/// The contacts at the same distance from this node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    pub distance: usize,
+    pub capacity: u8,
+    pub contacts: Vec<(NodeId, SocketAddr)>,
+}
+
+/// The routing table for one of the node ids which the peers know this node by.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub local_id: NodeId,
+    pub buckets: Vec<Bucket>,
+}
+
+/// The answer of a peer which was asked for the nodes close to this node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lookup {
+    pub local_id: NodeId,
+    pub peer: SocketAddr,
+    /// The number of the contacts in the answer
+    pub received: usize,
+    /// The number of the received contacts which were not in the routing table
+    pub new_contacts: usize,
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+}
@@ -35,5 +35,8 @@ extern crate codechain_types as ctypes;
 mod kademlia;
 mod unstructured;
 
-pub use kademlia::{Config as KademliaConfig, Extension as KademliaExtension};
+pub use kademlia::{
+    Bucket as KademliaBucket, Config as KademliaConfig, Extension as KademliaExtension, Lookup as KademliaLookup,
+    Table as KademliaTable,
+};
 pub use unstructured::{Config as UnstructuredConfig, Extension as UnstructuredExtension};
@@ -98,23 +98,32 @@ impl NetworkExtension for Extension {
                 let api = self.api.lock();
                 match (&*api, &*routing_table) {
                     (Some(api), Some(routing_table)) => {
-                        let mut addresses = routing_table.all_addresses().into_iter().collect::<Vec<_>>();
-                        thread_rng().shuffle(&mut addresses);
-                        let addresses =
-                            addresses.into_iter().take(::std::cmp::min(self.config.t_refresh as usize, len)).collect();
-                        let response = Message::Response(addresses).rlp_bytes();
+                        let mut records = routing_table.records();
+                        thread_rng().shuffle(&mut records);
+                        // The record of this node has its external address, which the peers behind the same NAT
+                        // can't learn otherwise
+                        if let Some(local_record) = routing_table.local_record() {
+                            records.insert(0, local_record);
+                        }
+                        let records =
+                            records.into_iter().take(::std::cmp::min(self.config.t_refresh as usize, len)).collect();
+                        let response = Message::Response(records).rlp_bytes();
                         api.send(&node, &response);
                     }
                     _ => {}
                 }
             }
-            Message::Response(addresses) => {
+            Message::Response(records) => {
                 let routing_table = self.routing_table.read();
                 match routing_table.as_ref() {
                     None => warn!(target: "discovery", "No routing table"),
                     Some(routing_table) => {
-                        for address in addresses.into_iter() {
-                            routing_table.add_candidate(address);
+                        let forged = records.iter().filter(|record| !record.verify()).count();
+                        if forged != 0 {
+                            warn!(target: "discovery", "{} sent {} records with the invalid signatures", node, forged);
+                        }
+                        for record in records.into_iter() {
+                            routing_table.add_record(record);
                         }
                     }
                 }
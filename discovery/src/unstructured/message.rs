@@ -14,13 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use cnetwork::SocketAddr;
+use cnetwork::NodeRecord;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 #[derive(Debug, PartialEq)]
 pub enum Message {
     Request(usize),
-    Response(Vec<SocketAddr>),
+    Response(Vec<NodeRecord>),
 }
 
 impl Encodable for Message {
@@ -29,8 +29,8 @@ impl Encodable for Message {
             Message::Request(len) => {
                 s.append(len);
             }
-            Message::Response(addresses) => {
-                s.append_list(addresses);
+            Message::Response(records) => {
+                s.append_list(records);
             }
         }
     }
@@ -48,8 +48,15 @@ impl Decodable for Message {
 
 #[cfg(test)]
 mod tests {
+    use ckeys::{Generator, Random};
+    use cnetwork::SocketAddr;
+
     use super::*;
 
+    fn record(port: u16) -> NodeRecord {
+        NodeRecord::new(&Random.generate().unwrap(), 0, vec![SocketAddr::v4(127, 0, 0, 1, port)], 0x11, vec![])
+    }
+
     #[test]
     fn encode_and_decode_request_0() {
         let request = Message::Request(0);
@@ -97,7 +104,7 @@ mod tests {
 
     #[test]
     fn encode_and_decode_one_response() {
-        let request = Message::Response(vec![SocketAddr::v4(127, 0, 0, 1, 3480)]);
+        let request = Message::Response(vec![record(3480)]);
         let encoded = request.rlp_bytes();
         let rlp = UntrustedRlp::new(&encoded);
         let decoded: Message = Decodable::decode(&rlp).unwrap();
@@ -106,7 +113,7 @@ mod tests {
 
     #[test]
     fn encode_and_decode_two_response() {
-        let request = Message::Response(vec![SocketAddr::v4(127, 0, 0, 1, 3480), SocketAddr::v4(127, 0, 0, 1, 3481)]);
+        let request = Message::Response(vec![record(3480), record(3481)]);
         let encoded = request.rlp_bytes();
         let rlp = UntrustedRlp::new(&encoded);
         let decoded: Message = Decodable::decode(&rlp).unwrap();
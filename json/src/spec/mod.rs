@@ -36,4 +36,4 @@ pub use self::solo::{Solo, SoloParams};
 pub use self::solo_authority::{SoloAuthority, SoloAuthorityParams};
 pub use self::spec::Spec;
 pub use self::state::State;
-pub use self::tendermint::{Tendermint, TendermintParams};
+pub use self::tendermint::{Tendermint, TendermintParams, ValidatorTransition};
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::super::uint::Uint;
+use std::collections::BTreeMap;
+
+use super::super::hash::H256;
+use super::super::uint::{validate_optional_u16, Uint};
 
 /// Spec params.
 #[derive(Debug, PartialEq, Deserialize)]
@@ -31,6 +34,11 @@ pub struct Params {
     /// Minimum parcel cost.
     #[serde(rename = "minParcelCost")]
     pub min_parcel_cost: Uint,
+    /// Number of shards in the genesis state, defaults to 1.
+    #[serde(rename = "shardCount", default, deserialize_with = "validate_optional_u16")]
+    pub shard_count: Option<Uint>,
+    /// Trusted block hashes by their numbers.
+    pub checkpoints: Option<BTreeMap<Uint, H256>>,
 }
 
 #[cfg(test)]
@@ -38,6 +46,7 @@ mod tests {
     use ctypes::U256;
     use serde_json;
 
+    use super::super::super::hash::H256;
     use super::super::super::uint::Uint;
     use super::Params;
 
@@ -47,7 +56,11 @@ mod tests {
 			"accountStartNonce": "0x01",
 			"maximumExtraDataSize": "0x20",
 			"networkID" : "0x1",
-			"minParcelCost" : "10"
+			"minParcelCost" : "10",
+			"shardCount" : "0x3",
+			"checkpoints": {
+				"1000": "0x0000000000000000000000000000000000000000000000000000000000000001"
+			}
 		}"#;
 
         let deserialized: Params = serde_json::from_str(s).unwrap();
@@ -55,5 +68,33 @@ mod tests {
         assert_eq!(deserialized.maximum_extra_data_size, Uint(U256::from(0x20)));
         assert_eq!(deserialized.network_id, Uint(U256::from(0x1)));
         assert_eq!(deserialized.min_parcel_cost, Uint(U256::from(10)));
+        assert_eq!(deserialized.shard_count, Some(Uint(U256::from(3))));
+        let checkpoints = deserialized.checkpoints.unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[&Uint(U256::from(1000))], H256(1.into()));
+    }
+
+    #[test]
+    fn shard_count_is_optional() {
+        let s = r#"{
+			"maximumExtraDataSize": "0x20",
+			"networkID" : "0x1",
+			"minParcelCost" : "10"
+		}"#;
+
+        let deserialized: Params = serde_json::from_str(s).unwrap();
+        assert_eq!(deserialized.shard_count, None);
+    }
+
+    #[test]
+    fn shard_count_beyond_u16_is_rejected() {
+        let s = r#"{
+			"maximumExtraDataSize": "0x20",
+			"networkID" : "0x1",
+			"minParcelCost" : "10",
+			"shardCount" : "0x10000"
+		}"#;
+
+        assert!(serde_json::from_str::<Params>(s).is_err());
     }
 }
@@ -22,6 +22,9 @@ use super::super::uint::Uint;
 pub struct TendermintParams {
     /// Valid validators.
     pub validators: Vec<Address>,
+    /// The validators which replace the previous ones from the given blocks.
+    #[serde(rename = "validatorTransitions")]
+    pub validator_transitions: Option<Vec<ValidatorTransition>>,
     /// Propose step timeout in milliseconds.
     #[serde(rename = "timeoutPropose")]
     pub timeout_propose: Option<Uint>,
@@ -39,6 +42,14 @@ pub struct TendermintParams {
     pub block_reward: Option<Uint>,
 }
 
+/// The validator set activated at a block.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct ValidatorTransition {
+    /// The first block validated by the validators.
+    pub block: Uint,
+    pub validators: Vec<Address>,
+}
+
 /// Tendermint engine deserialization.
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct Tendermint {
@@ -51,6 +62,7 @@ mod tests {
     use serde_json;
 
     use super::super::super::hash::Address;
+    use super::super::super::uint::Uint;
     use super::Tendermint;
 
     #[test]
@@ -64,5 +76,25 @@ mod tests {
         let deserialized: Tendermint = serde_json::from_str(s).unwrap();
         let vs = vec![Address(H160::from("0xc6d9d2cd449a754c494264e1809c50e34d64562b"))];
         assert_eq!(deserialized.params.validators, vs);
+        assert_eq!(deserialized.params.validator_transitions, None);
+    }
+
+    #[test]
+    fn tendermint_with_transitions_deserialization() {
+        let s = r#"{
+			"params": {
+				"validators": ["0xc6d9d2cd449a754c494264e1809c50e34d64562b"],
+				"validatorTransitions": [{
+					"block": 100,
+					"validators": ["0x0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6"]
+				}]
+			}
+		}"#;
+
+        let deserialized: Tendermint = serde_json::from_str(s).unwrap();
+        let transitions = deserialized.params.validator_transitions.unwrap();
+        assert_eq!(1, transitions.len());
+        assert_eq!(Uint(100.into()), transitions[0].block);
+        assert_eq!(vec![Address(H160::from("0x0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6"))], transitions[0].validators);
     }
 }
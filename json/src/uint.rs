@@ -43,6 +43,14 @@ impl Into<usize> for Uint {
         u64::from(self.0) as usize
     }
 }
+impl Into<u16> for Uint {
+    fn into(self) -> u16 {
+        let value = u64::from(self.0);
+        assert!(value <= u64::from(u16::max_value()), "Integer overflow when casting {} to u16", value);
+        value as u16
+    }
+}
+
 impl Into<u8> for Uint {
     fn into(self) -> u8 {
         u64::from(self.0) as u8
@@ -120,6 +128,20 @@ where
     Ok(value)
 }
 
+pub fn validate_optional_u16<'de, D>(d: D) -> Result<Option<Uint>, D::Error>
+where
+    D: Deserializer<'de>, {
+    let value: Option<Uint> = Option::deserialize(d)?;
+
+    if let Some(value) = value {
+        if value > Uint(U256::from(u16::max_value())) {
+            return Err(Error::invalid_value(Unexpected::Str(&value.0.to_string()), &"a value which fits in u16"))
+        }
+    }
+
+    Ok(value)
+}
+
 #[cfg(test)]
 mod test {
     use ctypes::U256;
@@ -28,6 +28,8 @@ pub enum Error {
     InvalidChecksum,
     InvalidPrivate,
     InvalidAddress,
+    InvalidMnemonic,
+    InvalidDerivationPath,
     FailedKeyGeneration,
     Bech32MissingSeparator,
     Bech32InvalidChecksum,
@@ -50,6 +52,8 @@ impl fmt::Display for Error {
             Error::InvalidChecksum => "Invalid Checksum".into(),
             Error::InvalidPrivate => "Invalid Private".into(),
             Error::InvalidAddress => "Invalid Address".into(),
+            Error::InvalidMnemonic => "Invalid Mnemonic".into(),
+            Error::InvalidDerivationPath => "Invalid Derivation Path".into(),
             Error::FailedKeyGeneration => "Key generation failed".into(),
             Error::Bech32MissingSeparator => "Missing human-readable separator".into(),
             Error::Bech32InvalidChecksum => "Invalid checksum".into(),
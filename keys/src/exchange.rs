@@ -18,9 +18,9 @@ use std::result;
 
 use secp256k1::{ecdh, key};
 
-use super::{Error, Private, Public, SECP256K1, Secret};
+use super::{Error, Private, Public, SECP256K1, SharedSecret};
 
-pub fn exchange(public: &Public, private: &Private) -> result::Result<Secret, Error> {
+pub fn exchange(public: &Public, private: &Private) -> result::Result<SharedSecret, Error> {
     let public = {
         let mut public_buffer = [4u8; 65];
         (&mut public_buffer[1..65]).copy_from_slice(&public[0..64]);
@@ -31,7 +31,7 @@ pub fn exchange(public: &Public, private: &Private) -> result::Result<Secret, Er
     let private = key::SecretKey::from_slice(&SECP256K1, &private)?;
     let shared = ecdh::SharedSecret::new_raw(&SECP256K1, &public, &private);
 
-    Ok(Secret::from(&shared[0..32]))
+    Ok(SharedSecret::from(&shared[0..32]))
 }
 
 #[cfg(test)]
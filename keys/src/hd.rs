@@ -0,0 +1,212 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::str::FromStr;
+
+use codechain_types::H256;
+use crypto::hmac_sha512;
+use secp256k1::key;
+
+use super::{Error, KeyPair, Private, SECP256K1};
+
+/// The indices from this one are hardened.
+pub const HARDENED: u32 = 0x8000_0000;
+const MASTER_SECRET: &[u8] = b"Bitcoin seed";
+
+/// A BIP-32 extended private key, which derives its children with the chain code.
+#[derive(Clone, PartialEq)]
+pub struct ExtendedPrivate {
+    private: Private,
+    chain_code: H256,
+}
+
+impl ExtendedPrivate {
+    /// The master key of the seed, such as the seed of a mnemonic phrase.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, Error> {
+        let i = hmac_sha512(MASTER_SECRET, seed);
+        let private = key::SecretKey::from_slice(&SECP256K1, &i[..32])?;
+        Ok(ExtendedPrivate {
+            private: Private::from(private),
+            chain_code: H256::from_slice(&i[32..]),
+        })
+    }
+
+    /// Fails for the indices which don't make a valid key, whose probability is lower than 1 in 2^127.
+    pub fn derive_child(&self, index: u32) -> Result<Self, Error> {
+        let context = &SECP256K1;
+        let parent = key::SecretKey::from_slice(context, &self.private[..])?;
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0);
+            data.extend_from_slice(&self.private[..]);
+        } else {
+            let public = key::PublicKey::from_secret_key(context, &parent)?;
+            data.extend_from_slice(&public.serialize_vec(context, true));
+        }
+        data.extend_from_slice(&[(index >> 24) as u8, (index >> 16) as u8, (index >> 8) as u8, index as u8]);
+
+        let i = hmac_sha512(&self.chain_code, &data);
+        let mut child = key::SecretKey::from_slice(context, &i[..32])?;
+        child.add_assign(context, &parent)?;
+        Ok(ExtendedPrivate {
+            private: Private::from(child),
+            chain_code: H256::from_slice(&i[32..]),
+        })
+    }
+
+    pub fn derive(&self, path: &DerivationPath) -> Result<Self, Error> {
+        let mut key = self.clone();
+        for index in &path.indices {
+            key = key.derive_child(*index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn private(&self) -> &Private {
+        &self.private
+    }
+
+    pub fn chain_code(&self) -> &H256 {
+        &self.chain_code
+    }
+
+    pub fn key_pair(&self) -> Result<KeyPair, Error> {
+        KeyPair::from_private(self.private.clone())
+    }
+}
+
+impl fmt::Debug for ExtendedPrivate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExtendedPrivate {{ private: {:?}, chain_code: {:?} }}", self.private, self.chain_code)
+    }
+}
+
+/// The child indices from the master key, written as m/44'/0'/0'/0/0 where ' marks the hardened ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DerivationPath {
+    indices: Vec<u32>,
+}
+
+impl DerivationPath {
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// The path to the child of the last key.
+    pub fn child(&self, index: u32) -> Self {
+        let mut indices = self.indices.clone();
+        indices.push(index);
+        DerivationPath {
+            indices,
+        }
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(Error::InvalidDerivationPath)
+        }
+        let indices = parts
+            .map(|part| {
+                let (number, offset) = if part.ends_with('\'') || part.ends_with('h') {
+                    (&part[..part.len() - 1], HARDENED)
+                } else {
+                    (part, 0)
+                };
+                match number.parse::<u32>() {
+                    Ok(index) if index < HARDENED => Ok(index + offset),
+                    _ => Err(Error::InvalidDerivationPath),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(DerivationPath {
+            indices,
+        })
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.indices {
+            if *index >= HARDENED {
+                write!(f, "/{}'", index - HARDENED)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+
+    use super::*;
+
+    fn assert_key(key: &ExtendedPrivate, private: &'static str, chain_code: &'static str) {
+        assert_eq!(H256::from(private), **key.private());
+        assert_eq!(H256::from(chain_code), *key.chain_code());
+    }
+
+    #[test]
+    fn derive_along_path() {
+        // The first test vector of BIP-32
+        let master = ExtendedPrivate::from_seed(&"000102030405060708090a0b0c0d0e0f".from_hex().unwrap()).unwrap();
+        assert_key(
+            &master,
+            "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508",
+        );
+
+        let hardened = master.derive(&"m/0'".parse().unwrap()).unwrap();
+        assert_key(
+            &hardened,
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141",
+        );
+
+        let key = master.derive(&"m/0'/1/2'/2/1000000000".parse().unwrap()).unwrap();
+        assert_key(
+            &key,
+            "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+            "c783e67b921d2beb8f6b389cc646d7263b4145701dadd2161548a8b078e65e9e",
+        );
+    }
+
+    #[test]
+    fn path_is_parsed() {
+        let path: DerivationPath = "m/44'/0h/0'/0/1".parse().unwrap();
+        assert_eq!(&[44 + HARDENED, HARDENED, HARDENED, 0, 1], path.indices());
+        assert_eq!("m/44'/0'/0'/0/1", path.to_string());
+        assert_eq!(DerivationPath::default(), "m".parse().unwrap());
+        assert_eq!("m/44'/0'/0'/0/1/2", path.child(2).to_string());
+    }
+
+    #[test]
+    fn invalid_path_is_rejected() {
+        assert_eq!(Err(Error::InvalidDerivationPath), "44'/0'".parse::<DerivationPath>());
+        assert_eq!(Err(Error::InvalidDerivationPath), "m/x".parse::<DerivationPath>());
+        assert_eq!(Err(Error::InvalidDerivationPath), "m/2147483648".parse::<DerivationPath>());
+        assert_eq!(Err(Error::InvalidDerivationPath), "m//0".parse::<DerivationPath>());
+    }
+}
@@ -29,19 +29,25 @@ extern crate secp256k1;
 mod address;
 mod error;
 mod exchange;
+mod hd;
 mod keypair;
+mod mnemonic;
 mod network;
 mod private;
 mod random;
+mod shared_secret;
 mod signature;
 
 pub use address::FullAddress;
 pub use error::Error;
 pub use exchange::exchange;
+pub use hd::{DerivationPath, ExtendedPrivate, HARDENED};
 pub use keypair::{public_to_address, KeyPair};
+pub use mnemonic::Mnemonic;
 pub use network::Network;
 pub use private::Private;
 pub use random::Random;
+pub use shared_secret::{zeroize, SharedSecret};
 pub use rustc_serialize::hex;
 pub use signature::{recover_ecdsa, sign_ecdsa, verify_ecdsa, verify_ecdsa_address, ECDSASignature};
 
@@ -0,0 +1,199 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::fmt;
+use std::str::FromStr;
+
+use codechain_types::H512;
+use crypto::{pbkdf2, sha256};
+use rand::os::OsRng;
+use rand::Rng;
+
+use super::Error;
+
+/// The BIP-39 English wordlist, sorted.
+const ENGLISH: &str = include_str!("../res/bip39/english.txt");
+const PBKDF2_ROUNDS: u32 = 2048;
+const BITS_PER_WORD: usize = 11;
+
+lazy_static! {
+    static ref WORDLIST: Vec<&'static str> = ENGLISH.lines().collect();
+}
+
+/// A BIP-39 mnemonic phrase of 12, 15, 18, 21 or 24 English words.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    words: Vec<&'static str>,
+}
+
+impl Mnemonic {
+    /// Creates a phrase of the word count from the random entropy.
+    pub fn generate(word_count: usize) -> Result<Self, Error> {
+        if !is_valid_word_count(word_count) {
+            return Err(Error::InvalidMnemonic)
+        }
+        let mut entropy = vec![0u8; word_count / 3 * 4];
+        OsRng::new().map_err(|_| Error::FailedKeyGeneration)?.fill_bytes(&mut entropy);
+        Self::from_entropy(&entropy)
+    }
+
+    /// Encodes 16, 20, 24, 28 or 32 bytes of entropy with the checksum.
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, Error> {
+        if entropy.len() % 4 != 0 || !is_valid_word_count(entropy.len() / 4 * 3) {
+            return Err(Error::InvalidMnemonic)
+        }
+        let entropy_bits = entropy.len() * 8;
+        let checksum = sha256(entropy)[0];
+        let bit = |i: usize| {
+            if i < entropy_bits {
+                (entropy[i / 8] >> (7 - i % 8)) & 1
+            } else {
+                (checksum >> (7 - (i - entropy_bits))) & 1
+            }
+        };
+        let word_count = entropy.len() / 4 * 3;
+        let words = (0..word_count)
+            .map(|word| {
+                let index = (0..BITS_PER_WORD)
+                    .fold(0usize, |index, i| (index << 1) | usize::from(bit(word * BITS_PER_WORD + i)));
+                WORDLIST[index]
+            })
+            .collect();
+        Ok(Mnemonic {
+            words,
+        })
+    }
+
+    /// The 64-byte seed of the phrase, salted with the passphrase.
+    /// Neither is normalized, so the passphrase should be ASCII to get the same seed as the other wallets.
+    pub fn seed(&self, passphrase: &str) -> H512 {
+        let mut seed = [0u8; 64];
+        let phrase = self.to_string();
+        let salt = format!("mnemonic{}", passphrase);
+        pbkdf2::sha512(PBKDF2_ROUNDS, pbkdf2::Salt(salt.as_bytes()), pbkdf2::Secret(phrase.as_bytes()), &mut seed);
+        H512::from(seed)
+    }
+}
+
+fn is_valid_word_count(word_count: usize) -> bool {
+    word_count % 3 == 0 && word_count >= 12 && word_count <= 24
+}
+
+impl FromStr for Mnemonic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let indices = s
+            .split_whitespace()
+            .map(|word| WORDLIST.binary_search_by(|probe| probe.cmp(&word)).map_err(|_| Error::InvalidMnemonic))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !is_valid_word_count(indices.len()) {
+            return Err(Error::InvalidMnemonic)
+        }
+
+        let mut entropy = vec![0u8; indices.len() / 3 * 4];
+        for i in 0..entropy.len() * 8 {
+            let index = indices[i / BITS_PER_WORD];
+            if (index >> (BITS_PER_WORD - 1 - i % BITS_PER_WORD)) & 1 == 1 {
+                entropy[i / 8] |= 1 << (7 - i % 8);
+            }
+        }
+        let mnemonic = Self::from_entropy(&entropy)?;
+        if mnemonic.words.iter().zip(indices).any(|(word, index)| *word != WORDLIST[index]) {
+            return Err(Error::InvalidChecksum)
+        }
+        Ok(mnemonic)
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.words.join(" "))
+    }
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mnemonic of {} words", self.words.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn wordlist_is_sorted() {
+        assert_eq!(2048, WORDLIST.len());
+        assert!(WORDLIST.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn entropy_is_encoded_with_checksum() {
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            ),
+            ("ffffffffffffffffffffffffffffffff", "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong"),
+            (
+                "7ac45cfe7722ee6c7ba84fbc2d5bd61b45cb2fe5eb65aa78",
+                "kiss carry display unusual confirm curtain upgrade antique rotate hello void custom frequent obey nut hole price segment",
+            ),
+            (
+                "f585c11aec520db57dd353c69554b21a89b20fb0650966fa0a9d6f74fd989d8f",
+                "void come effort suffer camp survey warrior heavy shoot primary clutch crush open amazing screen patrol group space point ten exist slush involve unfold",
+            ),
+        ];
+        for (entropy, phrase) in vectors.iter() {
+            let mnemonic = Mnemonic::from_entropy(&entropy.from_hex().unwrap()).unwrap();
+            assert_eq!(*phrase, mnemonic.to_string());
+            assert_eq!(mnemonic, phrase.parse::<Mnemonic>().unwrap());
+        }
+    }
+
+    #[test]
+    fn seed_is_salted_with_passphrase() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mnemonic: Mnemonic = phrase.parse().unwrap();
+        let expected = "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+                        1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04";
+        assert_eq!(H512::from(expected), mnemonic.seed("TREZOR"));
+    }
+
+    #[test]
+    fn invalid_phrases_are_rejected() {
+        let wrong_checksum = format!("{}abandon", "abandon ".repeat(11));
+        assert_eq!(Err(Error::InvalidChecksum), wrong_checksum.parse::<Mnemonic>());
+        let unknown_word = format!("{}zzz", "abandon ".repeat(11));
+        assert_eq!(Err(Error::InvalidMnemonic), unknown_word.parse::<Mnemonic>());
+        assert_eq!(Err(Error::InvalidMnemonic), "zoo zoo zoo".parse::<Mnemonic>());
+    }
+
+    #[test]
+    fn generated_phrase_is_valid() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic, mnemonic.to_string().parse::<Mnemonic>().unwrap());
+        assert!(Mnemonic::generate(13).is_err());
+    }
+}
@@ -22,7 +22,7 @@ use codechain_types::H256;
 use hex::ToHex;
 use secp256k1::key;
 
-use super::Error;
+use super::{zeroize, Error};
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct Private(H256);
@@ -74,6 +74,12 @@ impl From<key::SecretKey> for Private {
     }
 }
 
+impl Drop for Private {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
 impl Deref for Private {
     type Target = H256;
 
@@ -0,0 +1,112 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+use std::sync::Arc;
+
+use codechain_types::H256;
+
+/// Overwrites the bytes with zeros in a way the compiler does not optimize out.
+pub fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+struct Key(H256);
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+/// The secret agreed with ECDH.
+///
+/// Clones share a single copy of the key, which is zeroed when the last of them is dropped.
+#[derive(Clone)]
+pub struct SharedSecret(Arc<Key>);
+
+impl From<H256> for SharedSecret {
+    fn from(mut secret: H256) -> Self {
+        let shared = SharedSecret(Arc::new(Key(secret)));
+        zeroize(&mut secret);
+        shared
+    }
+}
+
+impl<'a> From<&'a [u8]> for SharedSecret {
+    fn from(secret: &'a [u8]) -> Self {
+        assert_eq!(32, secret.len(), "Caller should provide 32-byte length slice");
+
+        let mut key = Key(H256::zero());
+        key.0.copy_from_slice(secret);
+        SharedSecret(Arc::new(key))
+    }
+}
+
+impl Deref for SharedSecret {
+    type Target = H256;
+
+    fn deref(&self) -> &Self::Target {
+        &(self.0).0
+    }
+}
+
+impl PartialEq for SharedSecret {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for SharedSecret {}
+
+impl fmt::Debug for SharedSecret {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "SharedSecret")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zeroize_overwrites_all_bytes() {
+        let mut secret = H256::random();
+        zeroize(&mut secret);
+        assert_eq!(H256::zero(), secret);
+    }
+
+    #[test]
+    fn clones_share_the_key() {
+        let secret = SharedSecret::from(H256::random());
+        let cloned = secret.clone();
+        assert_eq!(&**secret as *const H256, &**cloned as *const H256);
+        assert_eq!(secret, cloned);
+    }
+
+    #[test]
+    fn debug_does_not_print_the_key() {
+        let key = H256::random();
+        let secret = SharedSecret::from(key);
+        assert!(!format!("{:?}", secret).contains(&format!("{:x}", key)));
+    }
+}
@@ -17,7 +17,7 @@
 use super::crypto::Crypto;
 use account::Version;
 use ccrypto;
-use ckeys::{sign_ecdsa, Address, ECDSASignature, KeyPair, Message, Public};
+use ckeys::{sign_ecdsa, Address, DerivationPath, ECDSASignature, KeyPair, Message, Public};
 use {json, Error};
 
 /// Account representation.
@@ -37,6 +37,8 @@ pub struct SafeAccount {
     pub name: String,
     /// Account metadata
     pub meta: String,
+    /// The path from the master key, if the account is derived from a seed
+    pub derivation: Option<DerivationPath>,
 }
 
 impl Into<json::KeyFile> for SafeAccount {
@@ -48,6 +50,7 @@ impl Into<json::KeyFile> for SafeAccount {
             crypto: self.crypto.into(),
             name: Some(self.name.into()),
             meta: Some(self.meta.into()),
+            derivation: self.derivation.map(|path| path.to_string()),
         }
     }
 }
@@ -70,6 +73,7 @@ impl SafeAccount {
             filename: None,
             name,
             meta,
+            derivation: None,
         })
     }
 
@@ -85,6 +89,7 @@ impl SafeAccount {
             filename,
             name: json.name.unwrap_or(String::new()),
             meta: json.meta.unwrap_or("{}".to_owned()),
+            derivation: json.derivation.and_then(|path| path.parse().ok()),
         }
     }
 
@@ -111,6 +116,7 @@ impl SafeAccount {
             filename: self.filename.clone(),
             name: self.name.clone(),
             meta: self.meta.clone(),
+            derivation: self.derivation.clone(),
         };
         Ok(result)
     }
@@ -24,6 +24,8 @@ use time;
 use {json, Error, SafeAccount};
 
 const IGNORED_FILES: &'static [&'static str] = &["thumbs.db"];
+/// Hidden, so it's never read as a key file.
+const LOCK_FILE_NAME: &'static str = ".lock";
 
 #[cfg(not(windows))]
 fn restrict_permissions_to_owner(file_path: &Path) -> Result<(), i32> {
@@ -42,6 +44,33 @@ fn restrict_permissions_to_owner(_file_path: &Path) -> Result<(), i32> {
     Ok(())
 }
 
+#[cfg(not(windows))]
+fn lock_file(file: &fs::File, exclusive: bool) -> io::Result<()> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(windows)]
+fn lock_file(_file: &fs::File, _exclusive: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// An advisory lock on the keys directory, shared with the other processes using the same directory.
+/// It's released when dropped.
+struct DirectoryLock {
+    _file: fs::File,
+}
+
 /// Root keys directory implementation
 pub type RootDiskDirectory = DiskDirectory<DiskKeyFileManager>;
 
@@ -97,6 +126,16 @@ where
         }
     }
 
+    /// Blocks until the other processes release their locks.
+    /// The readers share the lock, and a writer holds it exclusively.
+    fn lock(&self, exclusive: bool) -> Result<DirectoryLock, Error> {
+        let file = fs::OpenOptions::new().create(true).write(true).open(self.path.join(LOCK_FILE_NAME))?;
+        lock_file(&file, exclusive)?;
+        Ok(DirectoryLock {
+            _file: file,
+        })
+    }
+
     fn files(&self) -> Result<Vec<PathBuf>, Error> {
         Ok(fs::read_dir(&self.path)?
             .flat_map(Result::ok)
@@ -168,6 +207,8 @@ where
         mut filename: String,
         dedup: bool,
     ) -> Result<SafeAccount, Error> {
+        let _lock = self.lock(true)?;
+
         // path to keyfile
         let mut keyfile_path = self.path.join(filename.as_str());
 
@@ -213,6 +254,7 @@ where
     T: KeyFileManager,
 {
     fn load(&self) -> Result<Vec<SafeAccount>, Error> {
+        let _lock = self.lock(false)?;
         let accounts = self.files_content()?.into_iter().map(|(_, account)| account).collect();
         Ok(accounts)
     }
@@ -229,6 +271,8 @@ where
     }
 
     fn remove(&self, account: &SafeAccount) -> Result<(), Error> {
+        let _lock = self.lock(true)?;
+
         // enumerate all entries in keystore
         // and find entry with given address
         let to_remove = self.files_content()?
@@ -339,6 +383,19 @@ mod test {
         let _ = fs::remove_dir_all(dir);
     }
 
+    #[test]
+    fn lock_file_is_not_loaded_as_account() {
+        let temp_path = TempDir::new("").unwrap();
+        let directory = RootDiskDirectory::create(&temp_path).unwrap();
+
+        let keypair = Random.generate().unwrap();
+        let account = SafeAccount::create(&keypair, [0u8; 16], "test pass", 1024, "Test".to_owned(), "{}".to_owned());
+        directory.insert(account.unwrap()).expect("Account should be inserted ok");
+
+        assert!(temp_path.path().join(".lock").exists());
+        assert_eq!(directory.load().unwrap().len(), 1);
+    }
+
     #[test]
     fn hash_of_files() {
         let temp_path = TempDir::new("").unwrap();
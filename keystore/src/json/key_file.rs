@@ -54,6 +54,9 @@ pub struct KeyFile {
     pub address: H160,
     pub name: Option<String>,
     pub meta: Option<String>,
+    /// The BIP-32 path from the master key, for the HD accounts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<String>,
 }
 
 enum KeyFileField {
@@ -63,6 +66,7 @@ enum KeyFileField {
     Address,
     Name,
     Meta,
+    Derivation,
 }
 
 impl<'a> Deserialize<'a> for KeyFileField {
@@ -93,6 +97,7 @@ impl<'a> Visitor<'a> for KeyFileFieldVisitor {
             "address" => Ok(KeyFileField::Address),
             "name" => Ok(KeyFileField::Name),
             "meta" => Ok(KeyFileField::Meta),
+            "derivation" => Ok(KeyFileField::Derivation),
             _ => Err(Error::custom(format!("Unknown field: '{}'", value))),
         }
     }
@@ -137,6 +142,7 @@ impl<'a> Visitor<'a> for KeyFileVisitor {
         let mut address = None;
         let mut name = None;
         let mut meta = None;
+        let mut derivation = None;
 
         loop {
             match visitor.next_key()? {
@@ -154,6 +160,7 @@ impl<'a> Visitor<'a> for KeyFileVisitor {
                 }
                 Some(KeyFileField::Name) => name = none_if_empty(visitor.next_value().ok()),
                 Some(KeyFileField::Meta) => meta = none_if_empty(visitor.next_value().ok()),
+                Some(KeyFileField::Derivation) => derivation = none_if_empty(visitor.next_value().ok()),
                 None => break,
             }
         }
@@ -185,6 +192,7 @@ impl<'a> Visitor<'a> for KeyFileVisitor {
             address,
             name,
             meta,
+            derivation,
         };
 
         Ok(result)
@@ -258,6 +266,7 @@ mod tests {
             },
             name: Some("Test".to_owned()),
             meta: Some("{}".to_owned()),
+            derivation: None,
         };
 
         let keyfile: KeyFile = serde_json::from_str(json).unwrap();
@@ -309,6 +318,7 @@ mod tests {
             },
             name: None,
             meta: None,
+            derivation: None,
         };
 
         let keyfile: KeyFile = serde_json::from_str(json).unwrap();
@@ -337,6 +347,7 @@ mod tests {
             },
             name: Some("Test".to_owned()),
             meta: None,
+            derivation: Some("m/44'/0'/0'/0/0".to_owned()),
         };
 
         let serialized = serde_json::to_string(&file).unwrap();
@@ -23,7 +23,7 @@ use std::time::{Duration, Instant};
 use account::SafeAccount;
 use accounts_dir::KeyDirectory;
 use ccrypto::KEY_ITERATIONS;
-use ckeys::{Address, ECDSASignature, KeyPair, Message, Public, Secret};
+use ckeys::{Address, DerivationPath, ECDSASignature, ExtendedPrivate, KeyPair, Message, Public, Secret};
 use json::{self, OpaqueKeyFile, Uuid};
 use random::Random;
 use {Error, OpaqueSecret, SecretStore, SimpleSecretStore};
@@ -67,6 +67,15 @@ impl SimpleSecretStore for KeyStore {
         self.store.insert_account(secret, password)
     }
 
+    fn insert_derived(
+        &self,
+        master: &ExtendedPrivate,
+        path: &DerivationPath,
+        password: &str,
+    ) -> Result<Address, Error> {
+        self.store.insert_derived(master, path, password)
+    }
+
     fn accounts(&self) -> Result<Vec<Address>, Error> {
         self.store.accounts()
     }
@@ -149,6 +158,11 @@ impl SecretStore for KeyStore {
         Ok(account.meta.clone())
     }
 
+    fn derivation(&self, account: &Address) -> Result<Option<DerivationPath>, Error> {
+        let account = self.get(account)?;
+        Ok(account.derivation.clone())
+    }
+
     fn set_name(&self, account_ref: &Address, name: String) -> Result<(), Error> {
         let old = self.get(account_ref)?;
         let mut safe_account = old.clone();
@@ -334,6 +348,19 @@ impl SimpleSecretStore for KeyMultiStore {
         self.import(account)
     }
 
+    fn insert_derived(
+        &self,
+        master: &ExtendedPrivate,
+        path: &DerivationPath,
+        password: &str,
+    ) -> Result<Address, Error> {
+        let keypair = master.derive(path)?.key_pair()?;
+        let id: [u8; 16] = Random::random();
+        let mut account = SafeAccount::create(&keypair, id, password, self.iterations, "".to_owned(), "{}".to_owned())?;
+        account.derivation = Some(path.clone());
+        self.import(account)
+    }
+
     fn accounts(&self) -> Result<Vec<Address>, Error> {
         self.reload_if_changed()?;
         Ok(self.cache.read().keys().cloned().collect())
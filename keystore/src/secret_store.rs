@@ -30,7 +30,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ckeys::{Address, ECDSASignature, Message, Public, Secret};
+use ckeys::{Address, DerivationPath, ECDSASignature, ExtendedPrivate, Message, Public, Secret};
 use json::{OpaqueKeyFile, Uuid};
 use std::path::PathBuf;
 use Error;
@@ -41,6 +41,8 @@ use OpaqueSecret;
 pub trait SimpleSecretStore: Send + Sync {
     /// Inserts new accounts to the store with given password.
     fn insert_account(&self, secret: Secret, password: &str) -> Result<Address, Error>;
+    /// Derives the account along the path from the master key, and inserts it with the path.
+    fn insert_derived(&self, master: &ExtendedPrivate, path: &DerivationPath, password: &str) -> Result<Address, Error>;
     /// Changes accounts password.
     fn change_password(&self, account: &Address, old_password: &str, new_password: &str) -> Result<(), Error>;
     /// Exports key details for account.
@@ -87,6 +89,8 @@ pub trait SecretStore: SimpleSecretStore {
     fn name(&self, account: &Address) -> Result<String, Error>;
     /// Returns account's metadata.
     fn meta(&self, account: &Address) -> Result<String, Error>;
+    /// Returns the path from the master key, if the account is derived from a seed.
+    fn derivation(&self, account: &Address) -> Result<Option<DerivationPath>, Error>;
 
     /// Modifies account metadata.
     fn set_name(&self, account: &Address, name: String) -> Result<(), Error>;
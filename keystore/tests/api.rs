@@ -21,8 +21,8 @@ extern crate rand;
 mod util;
 
 use ckeystore::accounts_dir::RootDiskDirectory;
-use ckeystore::ckeys::{verify_ecdsa_address, Generator, KeyPair, Random, Secret};
-use ckeystore::{KeyStore, SimpleSecretStore};
+use ckeystore::ckeys::{verify_ecdsa_address, DerivationPath, ExtendedPrivate, Generator, KeyPair, Random, Secret};
+use ckeystore::{KeyStore, SecretStore, SimpleSecretStore};
 use util::TransientDir;
 
 #[test]
@@ -140,3 +140,17 @@ fn test_decrypting_files_with_short_ciphertext() {
     assert!(verify_ecdsa_address(&kp1.address(), &s1, &message).unwrap());
     assert!(verify_ecdsa_address(&kp2.address(), &s2, &message).unwrap());
 }
+
+#[test]
+fn secret_store_derive_account() {
+    let dir = TransientDir::create().unwrap();
+    let store = KeyStore::open(Box::new(dir)).unwrap();
+    let master = ExtendedPrivate::from_seed(&[0u8; 64]).unwrap();
+    let path: DerivationPath = "m/44'/0'/0'/0/0".parse().unwrap();
+    let address = store.insert_derived(&master, &path, "").unwrap();
+    assert_eq!(address, master.derive(&path).unwrap().key_pair().unwrap().address());
+    assert_eq!(Some(path), store.derivation(&address).unwrap());
+
+    assert!(store.insert_account(random_secret(), "").is_ok());
+    assert_eq!(store.accounts().unwrap().len(), 2);
+}
@@ -0,0 +1,66 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use ckeys::Public;
+
+/// Loads the public keys of the nodes which may connect to this node, one hex-encoded key per line.
+/// The empty lines and the lines which start with `#` are ignored.
+pub fn load(path: &Path) -> Result<Vec<Public>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read allowlist {:?}: {}", path, e))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // A typo must not lock out a node silently
+        .map(|line| Public::from_str(line).map_err(|_| format!("Invalid public key {} in allowlist {:?}", line, path)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use ckeys::hex::ToHex;
+    use ckeys::{Generator, Random};
+
+    use super::*;
+
+    #[test]
+    fn keys_are_loaded_without_the_comments() {
+        let path = env::temp_dir().join("codechain-network-allowlist-test");
+        let key1 = *Random.generate().unwrap().public();
+        let key2 = *Random.generate().unwrap().public();
+        let contents = format!("# validators\n{}\n\n  {}  \n", key1.to_hex(), key2.to_hex());
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(Ok(vec![key1, key2]), load(&path));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_key_is_an_error() {
+        let path = env::temp_dir().join("codechain-network-allowlist-invalid-test");
+        fs::write(&path, "not a key\n").unwrap();
+
+        assert!(load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! Records the decrypted extension messages of the established connections to a file.
+//! The captured messages can be fed back to the extensions with `TestNetworkClient::replay`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::NodeId;
+
+lazy_static! {
+    static ref RECORDER: Mutex<Option<Sender<Record>>> = Mutex::new(None);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+const INBOUND: u8 = 0;
+const OUTBOUND: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Nanoseconds since the unix epoch
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub node_id: NodeId,
+    pub extension_name: String,
+    pub data: Vec<u8>,
+}
+
+impl Encodable for Record {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        let direction = match self.direction {
+            Direction::Inbound => INBOUND,
+            Direction::Outbound => OUTBOUND,
+        };
+        s.begin_list(5)
+            .append(&self.timestamp)
+            .append(&direction)
+            .append(&self.node_id)
+            .append(&self.extension_name)
+            .append(&self.data);
+    }
+}
+
+impl Decodable for Record {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 5 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let direction = match rlp.val_at::<u8>(1)? {
+            INBOUND => Direction::Inbound,
+            OUTBOUND => Direction::Outbound,
+            _ => return Err(DecoderError::Custom("Invalid direction")),
+        };
+        Ok(Self {
+            timestamp: rlp.val_at(0)?,
+            direction,
+            node_id: rlp.val_at(2)?,
+            extension_name: rlp.val_at(3)?,
+            data: rlp.val_at(4)?,
+        })
+    }
+}
+
+/// Starts appending the records to the file at `path`.
+pub fn start(path: &str) -> Result<(), String> {
+    let file = open(Path::new(path)).map_err(|err| format!("Cannot open the capture file {}: {}", path, err))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("capture".to_string())
+        .spawn(move || run(file, receiver))
+        .map_err(|err| format!("Cannot start the capture: {}", err))?;
+    *RECORDER.lock() = Some(sender);
+    Ok(())
+}
+
+// The decrypted messages are as secret as the session keys, so a new file is readable only by the owner.
+#[cfg(unix)]
+fn open(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().create(true).append(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+pub fn is_enabled() -> bool {
+    RECORDER.lock().is_some()
+}
+
+pub fn record(direction: Direction, node_id: &NodeId, extension_name: &str, data: &[u8]) {
+    let recorder = RECORDER.lock();
+    if let Some(sender) = recorder.as_ref() {
+        let timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64);
+        let record = Record {
+            timestamp: timestamp.unwrap_or(0),
+            direction,
+            node_id: *node_id,
+            extension_name: extension_name.to_string(),
+            data: data.to_vec(),
+        };
+        // The receiver is gone only if the writer failed, which is already logged.
+        let _ = sender.send(record);
+    }
+}
+
+fn run(file: File, receiver: Receiver<Record>) {
+    let mut writer = BufWriter::new(file);
+    while let Ok(record) = receiver.recv() {
+        let mut result = writer.write_all(&record.rlp_bytes());
+        // Flushes when the burst ends, so that the file is complete while the node is idle
+        while result.is_ok() {
+            match receiver.try_recv() {
+                Ok(record) => result = writer.write_all(&record.rlp_bytes()),
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = result.and_then(|_| writer.flush()) {
+            cwarn!(NET, "Cannot write the capture: {}", err);
+            return
+        }
+    }
+}
+
+/// Reads all the records of the capture file.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, String> {
+    let bytes = fs::read(path).map_err(|err| format!("Cannot read the capture: {}", err))?;
+    decode(&bytes)
+}
+
+fn decode(mut bytes: &[u8]) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    while !bytes.is_empty() {
+        let rlp = UntrustedRlp::new(bytes);
+        let length = rlp.payload_info().map_err(|err| format!("Invalid capture: {:?}", err))?.total();
+        if bytes.len() < length {
+            return Err("The last record of the capture is truncated".to_string())
+        }
+        records.push(rlp.as_val().map_err(|err| format!("Invalid capture record: {:?}", err))?);
+        bytes = &bytes[length..];
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Arc;
+
+    use super::super::extension::{Api, Extension};
+    use super::super::test::TestClient;
+    use super::*;
+
+    struct TestExtension {
+        messages: Mutex<Vec<(NodeId, Vec<u8>)>>,
+    }
+
+    impl Extension for TestExtension {
+        fn name(&self) -> String {
+            "block-propagation".to_string()
+        }
+
+        fn need_encryption(&self) -> bool {
+            false
+        }
+
+        fn on_initialize(&self, _api: Arc<Api>) {}
+
+        fn on_message(&self, node: &NodeId, message: &[u8]) {
+            self.messages.lock().push((*node, message.to_vec()));
+        }
+    }
+
+    fn record(direction: Direction, data: Vec<u8>) -> Record {
+        Record {
+            timestamp: 1_000,
+            direction,
+            node_id: 3.into(),
+            extension_name: "block-propagation".to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn records_are_decoded_in_order() {
+        let records = vec![record(Direction::Outbound, vec![1, 2, 3]), record(Direction::Inbound, vec![0xc0; 100])];
+        let mut bytes = Vec::new();
+        for record in records.iter() {
+            bytes.extend_from_slice(&record.rlp_bytes());
+        }
+        assert_eq!(Ok(records), decode(&bytes));
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let bytes = record(Direction::Inbound, vec![7; 10]).rlp_bytes().into_vec();
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn empty_capture_has_no_record() {
+        assert_eq!(Ok(vec![]), decode(&[]));
+    }
+
+    #[test]
+    fn captured_messages_are_replayed() {
+        let path = env::temp_dir().join("codechain-network-capture-test");
+        let _ = fs::remove_file(&path);
+        let (sender, receiver) = mpsc::channel();
+        sender.send(record(Direction::Inbound, vec![1, 2, 3])).unwrap();
+        sender.send(record(Direction::Outbound, vec![4])).unwrap();
+        sender.send(record(Direction::Inbound, vec![5, 6])).unwrap();
+        drop(sender);
+        run(open(&path).unwrap(), receiver);
+
+        let extension = Arc::new(TestExtension {
+            messages: Mutex::new(vec![]),
+        });
+        let mut client = TestClient::new();
+        client.register_extension(Arc::clone(&extension) as Arc<Extension>);
+        // Only the inbound messages are fed to the extension
+        assert_eq!(2, client.replay(&read(&path).unwrap()));
+        let node_id = NodeId::from(3);
+        assert_eq!(vec![(node_id, vec![1, 2, 3]), (node_id, vec![5, 6])], *extension.messages.lock());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn capture_is_readable_only_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join("codechain-network-capture-mode-test");
+        let _ = fs::remove_file(&path);
+
+        open(&path).unwrap();
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -15,21 +15,35 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
-use std::sync::{Arc, Weak};
+use std::sync::{mpsc, Arc, Weak};
+use std::time::{self as std_time, Instant};
 
+use bytes::Bytes;
 use cio::IoChannel;
-use parking_lot::RwLock;
+use ckeys::Public;
+use parking_lot::{Mutex, RwLock};
 use rlp::Encodable;
 use time::Duration;
 
-use super::p2p::Message as P2pMessage;
+use super::p2p::{Message as P2pMessage, NodeStatus};
+use super::reputation::{PeerBehavior, Reputations};
 use super::timer::Message as TimerMessage;
-use super::{Api, NetworkExtension, NetworkExtensionError, NetworkExtensionResult, NodeId, TimerToken};
+use super::trace;
+use super::workers::{Callback, Workers};
+use super::{
+    Api, DisconnectReason, DropCounts, NetworkExtension, NetworkExtensionError, NetworkExtensionResult, NodeId,
+    TimerToken,
+};
+
+const MAX_QUEUED_PEER_NOTIFICATIONS: usize = 1024;
 
 struct ClientApi {
     extension: Weak<NetworkExtension>,
     p2p_channel: IoChannel<P2pMessage>,
     timer_channel: IoChannel<TimerMessage>,
+    identities: Arc<RwLock<HashMap<NodeId, Public>>>,
+    envelope_versions: Arc<RwLock<HashMap<NodeId, u64>>>,
+    reputations: Arc<RwLock<Reputations>>,
 }
 
 impl Api for ClientApi {
@@ -42,7 +56,8 @@ impl Api for ClientApi {
                 node_id,
                 extension_name,
                 need_encryption,
-                data: message.to_vec(),
+                data: Bytes::from(message),
+                trace: trace::current(),
             }) {
                 cwarn!(NETAPI, "Cannot send extension message to {:?} : {:?}", id, err);
             } else {
@@ -56,7 +71,7 @@ impl Api for ClientApi {
     fn negotiate(&self, id: &NodeId) {
         if let Some(extension) = self.extension.upgrade() {
             let extension_name = extension.name();
-            let version = 0;
+            let version = extension.version();
             let node_id = *id;
             if let Err(err) = self.p2p_channel.send(P2pMessage::RequestNegotiation {
                 node_id,
@@ -72,6 +87,15 @@ impl Api for ClientApi {
         }
     }
 
+    fn disconnect(&self, id: &NodeId) {
+        let node_id = *id;
+        if let Err(err) = self.p2p_channel.send(P2pMessage::RequestDisconnection(node_id)) {
+            cwarn!(NETAPI, "Cannot request disconnection from {:?} : {:?}", id, err);
+        } else {
+            ctrace!(NETAPI, "Request disconnection from {:?}", id);
+        }
+    }
+
     fn set_timer(&self, timer_id: usize, duration: Duration) -> NetworkExtensionResult<()> {
         if let Some(extension) = self.extension.upgrade() {
             let extension_name = extension.name();
@@ -124,44 +148,41 @@ impl Api for ClientApi {
             cdebug!(NETAPI, "The extension already dropped");
         }
     }
+
+    fn peer_identity(&self, id: &NodeId) -> Option<Public> {
+        self.identities.read().get(id).cloned()
+    }
+
+    fn envelope_version(&self, id: &NodeId) -> Option<u64> {
+        self.envelope_versions.read().get(id).cloned()
+    }
+
+    fn report(&self, id: &NodeId, behavior: PeerBehavior) {
+        ctrace!(NETAPI, "{:?} is reported as {:?}", id, behavior);
+        self.reputations.write().report(id, behavior);
+    }
+}
+
+/// A change of the established peers, which is sent to the subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub enum PeerNotification {
+    Connected(NodeId),
+    /// The reason is None if the connection is lost without the reason
+    Disconnected(NodeId, Option<DisconnectReason>),
 }
 
 pub struct Client {
     extensions: RwLock<HashMap<String, Arc<NetworkExtension>>>,
     p2p_channel: IoChannel<P2pMessage>,
     timer_channel: IoChannel<TimerMessage>,
-}
-
-macro_rules! define_broadcast_method {
-    ($method_name: ident) => {
-        pub fn $method_name (&self) {
-            let extensions = self.extensions.read();
-            for (_, ref extension) in extensions.iter() {
-                extension.$method_name();
-            }
-        }
-    };
-    ($method_name: ident; $($var: ident, $t: ty);*) => {
-        pub fn $method_name (&self, $($var: $t), *) {
-            let extensions = self.extensions.read();
-            for (_, ref extension) in extensions.iter() {
-                extension.$method_name($($var),*);
-            }
-        }
-    };
-}
-
-macro_rules! define_method {
-    ($method_name: ident; $($var: ident, $t: ty);*) => {
-        pub fn $method_name (&self, name: &String, $($var: $t), *) {
-            let extensions = self.extensions.read();
-            if let Some(ref extension) = extensions.get(name) {
-                extension.$method_name($($var),*);
-            } else {
-                cdebug!(NETAPI, "{} doesn't exist.", name);
-            }
-        }
-    };
+    identities: Arc<RwLock<HashMap<NodeId, Public>>>,
+    envelope_versions: Arc<RwLock<HashMap<NodeId, u64>>>,
+    reputations: Arc<RwLock<Reputations>>,
+    // Runs the callbacks of the peers, the messages and the timers off the io threads
+    workers: Workers,
+    peer_subscribers: Mutex<Vec<mpsc::SyncSender<PeerNotification>>>,
+    // Published by the p2p handler periodically, so that reading it never waits for the io threads
+    node_status: RwLock<Option<(NodeStatus, Instant)>>,
 }
 
 impl Client {
@@ -174,6 +195,33 @@ impl Client {
         }
     }
 
+    /// Removes the extension from the running node.
+    /// The messages and the negotiation requests to the extension are not routed anymore.
+    pub fn deregister_extension(&self, extension_name: &String) -> Option<Arc<NetworkExtension>> {
+        self.extensions.write().remove(extension_name)
+    }
+
+    /// The names of the registered extensions, which are announced in the record of this node.
+    pub fn extension_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.extensions.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn has_extension(&self, extension_name: &String) -> bool {
+        self.extensions.read().contains_key(extension_name)
+    }
+
+    /// The messages dropped because the extensions didn't keep up with them
+    pub fn worker_drops(&self) -> HashMap<String, DropCounts> {
+        self.workers.drops()
+    }
+
+    pub fn extension_version(&self, extension_name: &String) -> Option<u64> {
+        self.extensions.read().get(extension_name).map(|extension| extension.version())
+    }
+
+    /// The extension is notified of the peers which were connected before it was registered.
     pub fn initialize_extension(&self, extension_name: &String) {
         let extension = {
             let mut extensions = self.extensions.read();
@@ -182,35 +230,160 @@ impl Client {
         if let Some(extension) = extension {
             let p2p_channel = self.p2p_channel.clone();
             let timer_channel = self.timer_channel.clone();
+            let identities = Arc::clone(&self.identities);
+            let envelope_versions = Arc::clone(&self.envelope_versions);
+            let reputations = Arc::clone(&self.reputations);
             let api: Arc<Api> = Arc::new(ClientApi {
                 extension: Arc::downgrade(&extension),
                 p2p_channel,
                 timer_channel,
+                identities,
+                envelope_versions,
+                reputations,
             });
             extension.on_initialize(api);
+
+            let connected: Vec<NodeId> = self.identities.read().keys().cloned().collect();
+            for node_id in connected {
+                self.workers.dispatch(Arc::clone(&extension), Callback::NodeAdded(node_id));
+            }
         }
     }
 
-    pub fn new(p2p_channel: IoChannel<P2pMessage>, timer_channel: IoChannel<TimerMessage>) -> Arc<Self> {
+    /// The callbacks run on the io threads if `extension_workers` is 0.
+    pub fn new(
+        p2p_channel: IoChannel<P2pMessage>,
+        timer_channel: IoChannel<TimerMessage>,
+        extension_workers: usize,
+    ) -> Arc<Self> {
         Arc::new(Self {
             extensions: RwLock::new(HashMap::new()),
             p2p_channel,
             timer_channel,
+            identities: Arc::new(RwLock::new(HashMap::new())),
+            envelope_versions: Arc::new(RwLock::new(HashMap::new())),
+            reputations: Arc::new(RwLock::new(Reputations::new())),
+            workers: Workers::start(extension_workers),
+            peer_subscribers: Mutex::new(Vec::new()),
+            node_status: RwLock::new(None),
         })
     }
 
-    define_broadcast_method!(on_node_added; id, &NodeId);
-    define_broadcast_method!(on_node_removed; id, &NodeId);
+    pub fn set_node_status(&self, status: NodeStatus) {
+        *self.node_status.write() = Some((status, Instant::now()));
+    }
+
+    /// The last status which the p2p handler published, unless it's older than `max_age`.
+    pub fn node_status(&self, max_age: std_time::Duration) -> Option<NodeStatus> {
+        match &*self.node_status.read() {
+            Some((status, published_at)) if published_at.elapsed() <= max_age => Some(status.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set_peer_identity(&self, id: &NodeId, public: Public) {
+        self.identities.write().insert(*id, public);
+    }
+
+    pub fn set_peer_envelope_version(&self, id: &NodeId, version: u64) {
+        self.envelope_versions.write().insert(*id, version);
+    }
+
+    pub fn remove_peer_identity(&self, id: &NodeId) {
+        self.identities.write().remove(id);
+        self.envelope_versions.write().remove(id);
+        self.reputations.write().forget(id);
+    }
+
+    /// The peer with the lowest reputation among the candidates, if it has misbehaved.
+    pub fn worst_peer(&self, candidates: Vec<NodeId>) -> Option<NodeId> {
+        self.reputations.read().worst(candidates)
+    }
+
+    /// The peer with the lowest reputation among the candidates, even if it has never misbehaved.
+    pub fn least_reputable_peer(&self, candidates: Vec<NodeId>) -> Option<NodeId> {
+        self.reputations.read().lowest(candidates)
+    }
+
+    /// The receiver gets the changes of the peers until it's dropped.
+    /// The changes are dropped while the receiver has `MAX_QUEUED_PEER_NOTIFICATIONS` of them.
+    pub fn subscribe_peers(&self) -> mpsc::Receiver<PeerNotification> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_PEER_NOTIFICATIONS);
+        self.peer_subscribers.lock().push(sender);
+        receiver
+    }
+
+    pub fn on_node_added(&self, id: &NodeId) {
+        self.dispatch_to_all(|| Callback::NodeAdded(*id));
+        self.notify_peer_subscribers(PeerNotification::Connected(*id));
+    }
+
+    pub fn on_node_removed(&self, id: &NodeId, reason: Option<DisconnectReason>) {
+        self.dispatch_to_all(|| Callback::NodeRemoved(*id));
+        self.notify_peer_subscribers(PeerNotification::Disconnected(*id, reason));
+    }
+
+    // Forgets the subscribers whose receivers are dropped, and never waits for the slow ones
+    fn notify_peer_subscribers(&self, notification: PeerNotification) {
+        self.peer_subscribers.lock().retain(|subscriber| match subscriber.try_send(notification.clone()) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                cwarn!(NETAPI, "A peer subscriber is too slow to get {:?}", notification);
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    pub fn on_node_disconnected(&self, id: &NodeId, reason: DisconnectReason) {
+        self.dispatch_to_all(|| Callback::NodeDisconnected(*id, reason));
+    }
+
+    pub fn on_negotiated(&self, name: &String, id: &NodeId) {
+        self.dispatch(name, Callback::Negotiated(*id));
+    }
 
-    define_method!(on_negotiated; id, &NodeId);
-    define_method!(on_negotiation_allowed; id, &NodeId);
-    define_method!(on_negotiation_denied; id, &NodeId);
+    pub fn on_negotiation_allowed(&self, name: &String, id: &NodeId) {
+        self.dispatch(name, Callback::NegotiationAllowed(*id));
+    }
 
-    define_method!(on_message; id, &NodeId; data, &[u8]);
+    pub fn on_negotiation_denied(&self, name: &String, id: &NodeId) {
+        self.dispatch(name, Callback::NegotiationDenied(*id));
+    }
 
-    define_method!(on_timeout; timer_id, TimerToken);
+    pub fn on_negotiation_failed(&self, name: &String, id: &NodeId) {
+        self.dispatch(name, Callback::NegotiationFailed(*id));
+    }
+
+    pub fn on_message(&self, name: &String, id: &NodeId, data: &[u8]) {
+        self.dispatch(name, Callback::Message(*id, data.to_vec()));
+    }
+
+    pub fn on_timeout(&self, name: &String, timer_id: TimerToken) {
+        self.dispatch(name, Callback::Timeout(timer_id));
+    }
 
-    define_method!(on_local_message; message, &[u8]);
+    pub fn on_local_message(&self, name: &String, message: &[u8]) {
+        self.dispatch(name, Callback::LocalMessage(message.to_vec()));
+    }
+
+    fn dispatch(&self, name: &String, callback: Callback) {
+        let extensions = self.extensions.read();
+        if let Some(extension) = extensions.get(name) {
+            self.workers.dispatch(Arc::clone(extension), callback);
+        } else {
+            cdebug!(NETAPI, "{} doesn't exist.", name);
+        }
+    }
+
+    fn dispatch_to_all<F>(&self, callback: F)
+    where
+        F: Fn() -> Callback, {
+        let extensions = self.extensions.read();
+        for extension in extensions.values() {
+            self.workers.dispatch(Arc::clone(extension), callback());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -220,11 +393,14 @@ mod tests {
     use std::vec::Vec;
 
     use cio::IoService;
+    use ckeys::Public;
     use parking_lot::Mutex;
     use rlp::Encodable;
     use time::Duration;
 
-    use super::{Api, Client, NetworkExtension, NetworkExtensionResult, NodeId};
+    use super::{
+        Api, Client, DisconnectReason, NetworkExtension, NetworkExtensionResult, NodeId, PeerBehavior, PeerNotification,
+    };
 
     #[allow(dead_code)]
     struct TestApi;
@@ -238,6 +414,10 @@ mod tests {
             unimplemented!()
         }
 
+        fn disconnect(&self, _id: &NodeId) {
+            unimplemented!()
+        }
+
         fn set_timer(&self, _timer_id: usize, _duration: Duration) -> NetworkExtensionResult<()> {
             unimplemented!()
         }
@@ -253,6 +433,18 @@ mod tests {
         fn send_local_message(&self, _message: &Encodable) {
             unimplemented!()
         }
+
+        fn peer_identity(&self, _id: &NodeId) -> Option<Public> {
+            unimplemented!()
+        }
+
+        fn envelope_version(&self, _id: &NodeId) -> Option<u64> {
+            unimplemented!()
+        }
+
+        fn report(&self, _id: &NodeId, _behavior: PeerBehavior) {
+            unimplemented!()
+        }
     }
 
     #[derive(Debug, Eq, PartialEq)]
@@ -260,9 +452,11 @@ mod tests {
         Initialize,
         NodeAdded,
         NodeRemoved,
+        NodeDisconnected(DisconnectReason),
         Negotiated,
         NegotiationAllowed,
         NegotiationDenied,
+        NegotiationFailed,
         Message,
         Timeout,
     }
@@ -305,6 +499,11 @@ mod tests {
             callbacks.push(Callback::NodeRemoved);
         }
 
+        fn on_node_disconnected(&self, _id: &NodeId, reason: DisconnectReason) {
+            let mut callbacks = self.callbacks.lock();
+            callbacks.push(Callback::NodeDisconnected(reason));
+        }
+
         fn on_negotiated(&self, _id: &NodeId) {
             let mut callbacks = self.callbacks.lock();
             callbacks.push(Callback::Negotiated);
@@ -320,6 +519,11 @@ mod tests {
             callbacks.push(Callback::NegotiationDenied);
         }
 
+        fn on_negotiation_failed(&self, _id: &NodeId) {
+            let mut callbacks = self.callbacks.lock();
+            callbacks.push(Callback::NegotiationFailed);
+        }
+
         fn on_message(&self, _id: &NodeId, _message: &[u8]) {
             let mut callbacks = self.callbacks.lock();
             callbacks.push(Callback::Message);
@@ -336,7 +540,7 @@ mod tests {
         let p2p_service = IoService::start().unwrap();
         let timer_service = IoService::start().unwrap();
 
-        let client = Client::new(p2p_service.channel(), timer_service.channel());
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
 
         let e1 = Arc::new(TestExtension::new("e1".to_string()));
         client.register_extension(Arc::clone(&e1) as Arc<NetworkExtension>);
@@ -358,12 +562,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn subscribers_get_the_changes_of_the_peers() {
+        let p2p_service = IoService::start().unwrap();
+        let timer_service = IoService::start().unwrap();
+
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
+        let receiver = client.subscribe_peers();
+        let dropped = client.subscribe_peers();
+        drop(dropped);
+
+        client.on_node_added(&1.into());
+        client.on_node_removed(&1.into(), None);
+
+        assert_eq!(PeerNotification::Connected(1.into()), receiver.recv().unwrap());
+        assert_eq!(PeerNotification::Disconnected(1.into(), None), receiver.recv().unwrap());
+        assert_eq!(1, client.peer_subscribers.lock().len());
+    }
+
+    #[test]
+    fn broadcast_the_disconnect_reason_before_node_removed() {
+        let p2p_service = IoService::start().unwrap();
+        let timer_service = IoService::start().unwrap();
+
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
+
+        let e1 = Arc::new(TestExtension::new("e1".to_string()));
+        client.register_extension(Arc::clone(&e1) as Arc<NetworkExtension>);
+        client.initialize_extension(&"e1".to_string());
+
+        client.on_node_disconnected(&1.into(), DisconnectReason::TooManyPeers);
+        client.on_node_removed(&1.into(), Some(DisconnectReason::TooManyPeers));
+
+        let callbacks = e1.callbacks.lock();
+        assert_eq!(
+            callbacks.deref(),
+            &vec![
+                Callback::Initialize,
+                Callback::NodeDisconnected(DisconnectReason::TooManyPeers),
+                Callback::NodeRemoved,
+            ]
+        );
+    }
+
+    #[test]
+    fn an_extension_registered_later_knows_the_connected_peers() {
+        let p2p_service = IoService::start().unwrap();
+        let timer_service = IoService::start().unwrap();
+
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
+        client.set_peer_identity(&1.into(), Public::random());
+
+        let e1 = Arc::new(TestExtension::new("e1".to_string()));
+        client.register_extension(Arc::clone(&e1) as Arc<NetworkExtension>);
+        client.initialize_extension(&"e1".to_string());
+
+        let callbacks = e1.callbacks.lock();
+        assert_eq!(callbacks.deref(), &vec![Callback::Initialize, Callback::NodeAdded]);
+    }
+
+    #[test]
+    fn a_deregistered_extension_receives_nothing() {
+        let p2p_service = IoService::start().unwrap();
+        let timer_service = IoService::start().unwrap();
+
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
+
+        let e1 = Arc::new(TestExtension::new("e1".to_string()));
+        client.register_extension(Arc::clone(&e1) as Arc<NetworkExtension>);
+        client.initialize_extension(&"e1".to_string());
+        assert!(client.has_extension(&"e1".to_string()));
+
+        assert!(client.deregister_extension(&"e1".to_string()).is_some());
+        assert!(!client.has_extension(&"e1".to_string()));
+        assert!(client.deregister_extension(&"e1".to_string()).is_none());
+
+        client.on_node_added(&1.into());
+        client.on_message(&"e1".to_string(), &1.into(), &vec![]);
+        let callbacks = e1.callbacks.lock();
+        assert_eq!(callbacks.deref(), &vec![Callback::Initialize]);
+    }
+
     #[test]
     fn message_only_to_target() {
         let p2p_service = IoService::start().unwrap();
         let timer_service = IoService::start().unwrap();
 
-        let client = Client::new(p2p_service.channel(), timer_service.channel());
+        let client = Client::new(p2p_service.channel(), timer_service.channel(), 0);
 
         let e1 = Arc::new(TestExtension::new("e1".to_string()));
         client.register_extension(Arc::clone(&e1) as Arc<NetworkExtension>);
@@ -14,11 +14,44 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::SocketAddr;
+use super::{DnsSeed, RateLimit, SocketAddr, SocketOptions};
 
 pub struct Config {
     pub port: u16,
-    pub bootstrap_addresses: Vec<SocketAddr>,
+    /// The port for the light clients which connect over WebSocket
+    pub websocket_port: Option<u16>,
+    /// Dialed at startup until the node has the minimum peers
+    pub bootnodes: Vec<SocketAddr>,
     pub min_peers: usize,
     pub max_peers: usize,
+    pub node_key_path: String,
+    pub relay: bool,
+    pub static_peers: Vec<SocketAddr>,
+    /// The file which remembers the connected peers. They are dialed at startup if the bootnodes are not enough.
+    pub peer_store_path: Option<String>,
+    /// Resolved periodically into more bootstrap peers
+    pub dns_seeds: Vec<DnsSeed>,
+    pub otlp_endpoint: Option<String>,
+    /// The fraction of the traces which are exported to the OTLP endpoint
+    pub otlp_sample_rate: f64,
+    pub capture_path: Option<String>,
+    /// Seconds after which a peer that sent no extension message is disconnected
+    pub idle_timeout: Option<u64>,
+    /// Overrides the idle timeout for the static peers, which never time out if it's None
+    pub static_peer_idle_timeout: Option<u64>,
+    /// Seconds after which an idle peer is evicted first when the slots are full
+    pub evictable_idle_timeout: u64,
+    pub socket_options: SocketOptions,
+    /// The socket options for the static peers, which are usually the other validators
+    pub static_peer_socket_options: SocketOptions,
+    /// Limits the inbound extension messages of each peer
+    pub rate_limit: RateLimit,
+    /// The number of the threads which run the extension callbacks
+    pub extension_workers: usize,
+    /// Finds the nodes of the same network on the local network with mDNS
+    pub mdns: bool,
+    /// Accepts the older peers which know only the unauthenticated AES-CBC cipher
+    pub allow_legacy_cipher: bool,
+    /// The file of the public keys which may connect to this node. Any node may connect if it's None.
+    pub allowlist_path: Option<String>,
 }
@@ -0,0 +1,271 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reads and writes the DNS wire format (RFC 1035), as much as mDNS and the DNS seeds need.
+//! The names are written without the compression, but the compressed names are read.
+
+use std::str;
+
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+pub const CLASS_IN: u16 = 1;
+
+pub const FLAG_RESPONSE: u16 = 0x8000;
+pub const FLAG_AUTHORITATIVE: u16 = 0x0400;
+pub const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000F;
+
+// Stops following the compressed names which point to each other
+const MAX_NAME_POINTERS: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Truncated,
+    InvalidName,
+}
+
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+impl Header {
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    pub fn rcode(&self) -> u16 {
+        self.flags & RCODE_MASK
+    }
+
+    /// The number of the records in all sections.
+    pub fn records(&self) -> usize {
+        self.answers as usize + self.authorities as usize + self.additionals as usize
+    }
+
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        write_u16(bytes, self.id);
+        write_u16(bytes, self.flags);
+        write_u16(bytes, self.questions);
+        write_u16(bytes, self.answers);
+        write_u16(bytes, self.authorities);
+        write_u16(bytes, self.additionals);
+    }
+}
+
+pub struct Record<'a> {
+    pub name: String,
+    pub record_type: u16,
+    pub data: &'a [u8],
+}
+
+pub fn write_question(bytes: &mut Vec<u8>, name: &str, query_type: u16) {
+    write_name(bytes, name);
+    write_u16(bytes, query_type);
+    write_u16(bytes, CLASS_IN);
+}
+
+pub fn write_record(bytes: &mut Vec<u8>, name: &str, record_type: u16, class: u16, ttl: u32, data: &[u8]) {
+    write_name(bytes, name);
+    write_u16(bytes, record_type);
+    write_u16(bytes, class);
+    write_u16(bytes, (ttl >> 16) as u16);
+    write_u16(bytes, ttl as u16);
+    write_u16(bytes, data.len() as u16);
+    bytes.extend_from_slice(data);
+}
+
+pub fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.push(0);
+}
+
+pub fn write_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.push((value >> 8) as u8);
+    bytes.push(value as u8);
+}
+
+/// Splits the data of a TXT record into its strings.
+pub fn txt_strings(data: &[u8]) -> Vec<&[u8]> {
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let end = offset + 1 + data[offset] as usize;
+        match data.get(offset + 1..end) {
+            Some(string) => strings.push(string),
+            None => break,
+        }
+        offset = end;
+    }
+    strings
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            offset: 0,
+        }
+    }
+
+    pub fn read_header(&mut self) -> Result<Header, Error> {
+        Ok(Header {
+            id: self.read_u16()?,
+            flags: self.read_u16()?,
+            questions: self.read_u16()?,
+            answers: self.read_u16()?,
+            authorities: self.read_u16()?,
+            additionals: self.read_u16()?,
+        })
+    }
+
+    /// Returns the name and the type of the question.
+    pub fn read_question(&mut self) -> Result<(String, u16), Error> {
+        let name = self.read_name()?;
+        let query_type = self.read_u16()?;
+        let _class = self.read_u16()?;
+        Ok((name, query_type))
+    }
+
+    pub fn read_record(&mut self) -> Result<Record<'a>, Error> {
+        let name = self.read_name()?;
+        let record_type = self.read_u16()?;
+        let _class = self.read_u16()?;
+        let _ttl = self.read_u32()?;
+        let length = self.read_u16()? as usize;
+        let start = self.offset;
+        self.skip(length)?;
+        Ok(Record {
+            name,
+            record_type,
+            data: &self.bytes[start..self.offset],
+        })
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), Error> {
+        if self.offset + length > self.bytes.len() {
+            return Err(Error::Truncated)
+        }
+        self.offset += length;
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        let value = *self.bytes.get(self.offset).ok_or(Error::Truncated)?;
+        self.offset += 1;
+        Ok(value)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok((u16::from(self.read_u8()?) << 8) | u16::from(self.read_u8()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok((u32::from(self.read_u16()?) << 16) | u32::from(self.read_u16()?))
+    }
+
+    // The names are compared in the lower case
+    fn read_name(&mut self) -> Result<String, Error> {
+        let mut labels = Vec::new();
+        // The offset after the name, which is set when the first pointer is followed
+        let mut next = None;
+        let mut pointers = 0;
+        loop {
+            let length = self.read_u8()?;
+            if length == 0 {
+                break
+            }
+            if length & 0xC0 == 0xC0 {
+                let pointer = (usize::from(length & 0x3F) << 8) | usize::from(self.read_u8()?);
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    return Err(Error::InvalidName)
+                }
+                if next.is_none() {
+                    next = Some(self.offset);
+                }
+                self.offset = pointer;
+                continue
+            }
+            let start = self.offset;
+            self.skip(length as usize)?;
+            let label = str::from_utf8(&self.bytes[start..self.offset]).map_err(|_| Error::InvalidName)?;
+            labels.push(label.to_lowercase());
+        }
+        if let Some(next) = next {
+            self.offset = next;
+        }
+        Ok(labels.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_names_are_read() {
+        let mut bytes = Vec::new();
+        write_name(&mut bytes, "seed.codechain.io");
+        // "www" and the pointer to "codechain.io"
+        bytes.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 5]);
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(Ok("seed.codechain.io".to_string()), reader.read_name());
+        assert_eq!(Ok("www.codechain.io".to_string()), reader.read_name());
+        assert_eq!(Err(Error::Truncated), reader.read_u8());
+    }
+
+    #[test]
+    fn pointer_loops_are_rejected() {
+        let bytes = [0xC0, 0];
+        assert_eq!(Err(Error::InvalidName), Reader::new(&bytes).read_name());
+    }
+
+    #[test]
+    fn write_and_read_record() {
+        let mut bytes = Vec::new();
+        write_record(&mut bytes, "a.local", TYPE_TXT, CLASS_IN, 120, &[1, b'x']);
+
+        let mut reader = Reader::new(&bytes);
+        let record = reader.read_record().unwrap();
+        assert_eq!("a.local", record.name);
+        assert_eq!(TYPE_TXT, record.record_type);
+        assert_eq!(vec![&b"x"[..]], txt_strings(record.data));
+    }
+
+    #[test]
+    fn truncated_records_are_rejected() {
+        let mut bytes = Vec::new();
+        write_record(&mut bytes, "a.local", TYPE_TXT, CLASS_IN, 120, &[1, b'x']);
+        let bytes = &bytes[..bytes.len() - 1];
+        assert_eq!(Err(Error::Truncated), Reader::new(bytes).read_record().map(|_| ()));
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Resolves the DNS seeds into the addresses of the bootstrap peers, periodically.
+//!
+//! A seed publishes each peer as a TXT record "IP:PORT EXPIRATION SIGNATURE", which is signed by the key of the seed,
+//! so a compromised DNS server can neither inject arbitrary peers nor revive the expired records.
+//! The signature covers the domain too, so the records of one seed cannot be served for another seed of the same key.
+
+use std::fs;
+use std::io;
+use std::net::{self, IpAddr, UdpSocket};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ccrypto::blake256;
+use cio::IoChannel;
+use ckeys::{sign_ecdsa, verify_ecdsa, ECDSASignature, KeyPair, Public};
+use ctypes::H256;
+use rand;
+
+use super::dns::{self, Header, Reader, FLAG_RECURSION_DESIRED, TYPE_TXT};
+use super::p2p::Message as P2pMessage;
+use super::SocketAddr;
+
+const DNS_PORT: u16 = 53;
+const MAX_RESPONSE_SIZE: usize = 4096;
+const TIMEOUT_SECS: u64 = 5;
+const REFRESH_SECS: u64 = 30 * 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Seed {
+    pub domain: String,
+    /// Verifies the TXT records
+    pub public: Public,
+}
+
+impl FromStr for Seed {
+    type Err = String;
+
+    /// Parses PUBLIC_KEY@DOMAIN.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(2, '@');
+        let domain = match parts.next() {
+            Some(domain) if !domain.is_empty() => domain,
+            _ => return Err(format!("Invalid DNS seed {}", s)),
+        };
+        let public = match parts.next() {
+            Some(public) => Public::from_str(public).map_err(|_| format!("Invalid key of DNS seed {}", s))?,
+            None => return Err(format!("DNS seed {} has no key to verify its records", s)),
+        };
+        Ok(Self {
+            domain: domain.to_lowercase(),
+            public,
+        })
+    }
+}
+
+/// Makes the TXT record which the seed of `domain` with the key pair publishes for the peer.
+/// The record is rejected after `expiration`, which is in the seconds since the UNIX epoch.
+pub fn sign_record(key_pair: &KeyPair, domain: &str, address: &SocketAddr, expiration: u64) -> String {
+    let hash = signed_hash(domain, &address.to_string(), expiration);
+    let signature = sign_ecdsa(key_pair.private(), &hash).expect("The key pair is valid");
+    format!("{} {} {}", address, expiration, signature)
+}
+
+// The domain is signed but not published in the record
+fn signed_hash(domain: &str, address: &str, expiration: u64) -> H256 {
+    blake256(format!("{} {} {}", domain.to_lowercase(), address, expiration))
+}
+
+fn verify_record(seed: &Seed, record: &str, now: u64) -> Option<SocketAddr> {
+    let mut parts = record.split_whitespace();
+    let address = parts.next()?;
+    let expiration = parts.next()?.parse::<u64>().ok()?;
+    let signature = ECDSASignature::from_str(parts.next()?).ok()?;
+    if parts.next().is_some() || expiration < now {
+        return None
+    }
+    let hash = signed_hash(&seed.domain, address, expiration);
+    if !verify_ecdsa(&seed.public, &signature, &hash).unwrap_or(false) {
+        return None
+    }
+    SocketAddr::from_str(address).ok()
+}
+
+/// Resolves the seeds now and every 30 minutes, and feeds the peers into the bootstrapping.
+pub fn start(seeds: Vec<Seed>, p2p_channel: IoChannel<P2pMessage>) -> Result<(), String> {
+    thread::Builder::new()
+        .name("dns seeds".to_string())
+        .spawn(move || loop {
+            let addresses = resolve_all(&seeds);
+            if !addresses.is_empty() {
+                if let Err(err) = p2p_channel.send(P2pMessage::AddSeedPeers(addresses)) {
+                    cwarn!(NET, "Cannot feed the peers of the DNS seeds: {:?}", err);
+                    return
+                }
+            }
+            thread::sleep(Duration::from_secs(REFRESH_SECS));
+        })
+        .map_err(|err| format!("Cannot start DNS seeds: {}", err))?;
+    Ok(())
+}
+
+fn resolve_all(seeds: &[Seed]) -> Vec<SocketAddr> {
+    let nameserver = match nameserver() {
+        Ok(nameserver) => nameserver,
+        Err(err) => {
+            cwarn!(NET, "Cannot find the nameserver for the DNS seeds: {}", err);
+            return vec![]
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("The clock is after the epoch").as_secs();
+    let mut addresses = Vec::new();
+    for seed in seeds {
+        match resolve(&nameserver, seed, now) {
+            Ok(resolved) => {
+                cinfo!(NET, "DNS seed {} gave {} peers", seed.domain, resolved.len());
+                addresses.extend(resolved);
+            }
+            Err(err) => cwarn!(NET, "Cannot resolve DNS seed {}: {}", seed.domain, err),
+        }
+    }
+    addresses
+}
+
+fn resolve(nameserver: &net::SocketAddr, seed: &Seed, now: u64) -> io::Result<Vec<SocketAddr>> {
+    let mut addresses = Vec::new();
+    for data in query(nameserver, &seed.domain, TYPE_TXT)? {
+        // A record can be split into several strings
+        let record: Vec<u8> = dns::txt_strings(&data).concat();
+        let record = String::from_utf8_lossy(&record);
+        match verify_record(seed, &record, now) {
+            Some(address) => addresses.push(address),
+            None => cwarn!(NET, "DNS seed {} has an invalid or expired record: {}", seed.domain, record),
+        }
+    }
+    Ok(addresses)
+}
+
+// The first nameserver of the system
+fn nameserver() -> io::Result<net::SocketAddr> {
+    let contents = fs::read_to_string("/etc/resolv.conf")?;
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(ip)) => IpAddr::from_str(ip).ok(),
+                _ => None,
+            }
+        })
+        .map(|ip| net::SocketAddr::new(ip, DNS_PORT))
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No nameserver in /etc/resolv.conf"))
+}
+
+// Returns the data of the answers. The answers must fit in a UDP response.
+fn query(nameserver: &net::SocketAddr, domain: &str, query_type: u16) -> io::Result<Vec<Vec<u8>>> {
+    let id = rand::random::<u16>();
+    let mut request = Vec::new();
+    Header {
+        id,
+        flags: FLAG_RECURSION_DESIRED,
+        questions: 1,
+        answers: 0,
+        authorities: 0,
+        additionals: 0,
+    }.write(&mut request);
+    dns::write_question(&mut request, domain, query_type);
+
+    let local: net::SocketAddr = if nameserver.is_ipv4() {
+        "0.0.0.0:0".parse().expect("The address is valid")
+    } else {
+        "[::]:0".parse().expect("The address is valid")
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+    socket.send_to(&request, nameserver)?;
+
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+    loop {
+        let (size, from) = socket.recv_from(&mut buf)?;
+        if &from != nameserver {
+            continue
+        }
+        match parse_answers(&buf[..size], id, domain, query_type) {
+            Ok(Some(answers)) => return Ok(answers),
+            // The response of another query
+            Ok(None) => continue,
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))),
+        }
+    }
+}
+
+fn parse_answers(bytes: &[u8], id: u16, domain: &str, query_type: u16) -> Result<Option<Vec<Vec<u8>>>, String> {
+    let mut reader = Reader::new(bytes);
+    let header = reader.read_header().map_err(|err| format!("{:?}", err))?;
+    if header.id != id || !header.is_response() {
+        return Ok(None)
+    }
+    if header.rcode() != 0 {
+        return Err(format!("The nameserver returned the error code {}", header.rcode()))
+    }
+    for _ in 0..header.questions {
+        reader.read_question().map_err(|err| format!("{:?}", err))?;
+    }
+    let mut answers = Vec::new();
+    for _ in 0..header.answers {
+        let record = reader.read_record().map_err(|err| format!("{:?}", err))?;
+        // The CNAME records which lead to the answers are skipped
+        if record.record_type == query_type && record.name == domain {
+            answers.push(record.data.to_vec());
+        }
+    }
+    Ok(Some(answers))
+}
+
+#[cfg(test)]
+mod tests {
+    use ckeys::hex::ToHex;
+    use ckeys::{Generator, Random};
+
+    use super::super::dns::{CLASS_IN, FLAG_RESPONSE};
+    use super::*;
+
+    const DOMAIN: &str = "seed.codechain.io";
+
+    fn seed(key_pair: &KeyPair, domain: &str) -> Seed {
+        Seed {
+            domain: domain.to_string(),
+            public: *key_pair.public(),
+        }
+    }
+
+    #[test]
+    fn parse_seeds() {
+        let public = Public::random();
+        assert_eq!(
+            Ok(Seed {
+                domain: DOMAIN.to_string(),
+                public,
+            }),
+            format!("{}@Seed.CodeChain.io", public.to_hex()).parse()
+        );
+        assert!(DOMAIN.parse::<Seed>().is_err(), "The records of a seed must be signed");
+        assert!("invalid@seed.codechain.io".parse::<Seed>().is_err());
+        assert!("".parse::<Seed>().is_err());
+    }
+
+    #[test]
+    fn signed_record_is_verified() {
+        let key_pair = Random.generate().unwrap();
+        let address = SocketAddr::v4(1, 2, 3, 4, 3485);
+        let record = sign_record(&key_pair, DOMAIN, &address, 1000);
+        assert_eq!(Some(address), verify_record(&seed(&key_pair, DOMAIN), &record, 999));
+    }
+
+    #[test]
+    fn expired_record_is_rejected() {
+        let key_pair = Random.generate().unwrap();
+        let record = sign_record(&key_pair, DOMAIN, &SocketAddr::v4(1, 2, 3, 4, 3485), 1000);
+        assert_eq!(None, verify_record(&seed(&key_pair, DOMAIN), &record, 1001));
+    }
+
+    #[test]
+    fn record_of_another_key_is_rejected() {
+        let key_pair = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        let record = sign_record(&other, DOMAIN, &SocketAddr::v4(1, 2, 3, 4, 3485), 1000);
+        assert_eq!(None, verify_record(&seed(&key_pair, DOMAIN), &record, 0));
+    }
+
+    #[test]
+    fn record_of_another_domain_is_rejected() {
+        let key_pair = Random.generate().unwrap();
+        let record = sign_record(&key_pair, "other.codechain.io", &SocketAddr::v4(1, 2, 3, 4, 3485), 1000);
+        assert_eq!(None, verify_record(&seed(&key_pair, DOMAIN), &record, 0));
+    }
+
+    #[test]
+    fn injected_address_is_rejected() {
+        let key_pair = Random.generate().unwrap();
+        let record = sign_record(&key_pair, DOMAIN, &SocketAddr::v4(1, 2, 3, 4, 3485), 1000);
+        let injected = record.replacen("1.2.3.4", "5.6.7.8", 1);
+        assert_eq!(None, verify_record(&seed(&key_pair, DOMAIN), &injected, 0));
+    }
+
+    #[test]
+    fn answers_of_the_domain_are_parsed() {
+        let mut bytes = Vec::new();
+        Header {
+            id: 7,
+            flags: FLAG_RESPONSE,
+            questions: 1,
+            answers: 2,
+            authorities: 0,
+            additionals: 0,
+        }.write(&mut bytes);
+        dns::write_question(&mut bytes, "seed.codechain.io", TYPE_TXT);
+        dns::write_record(&mut bytes, "seed.codechain.io", TYPE_TXT, CLASS_IN, 60, b"\x08a record");
+        dns::write_record(&mut bytes, "other.codechain.io", TYPE_TXT, CLASS_IN, 60, b"\x07another");
+
+        assert_eq!(Ok(Some(vec![b"\x08a record".to_vec()])), parse_answers(&bytes, 7, "seed.codechain.io", TYPE_TXT));
+        assert_eq!(Ok(None), parse_answers(&bytes, 8, "seed.codechain.io", TYPE_TXT), "The id is different");
+    }
+}
@@ -18,10 +18,12 @@ use std::result;
 use std::sync::Arc;
 
 use cio::IoError;
+use ckeys::Public;
 use rlp::Encodable;
 use time::Duration;
 
-use super::NodeId;
+use super::reputation::PeerBehavior;
+use super::{DisconnectReason, NodeId};
 pub use cio::TimerToken;
 
 #[derive(Debug)]
@@ -43,26 +45,47 @@ pub type Result<T> = result::Result<T, Error>;
 pub trait Api: Send + Sync {
     fn send(&self, node: &NodeId, message: &[u8]);
     fn negotiate(&self, node: &NodeId);
+    /// Closes the connection to the node. The network dials another peer to fill the slot.
+    fn disconnect(&self, node: &NodeId);
 
     fn set_timer(&self, timer: TimerToken, d: Duration) -> Result<()>;
     fn set_timer_once(&self, timer: TimerToken, d: Duration) -> Result<()>;
     fn clear_timer(&self, timer: TimerToken) -> Result<()>;
 
     fn send_local_message(&self, message: &Encodable);
+
+    /// Returns the long-term public key which the node proved in the handshake.
+    fn peer_identity(&self, node: &NodeId) -> Option<Public>;
+
+    /// Returns the envelope version which the node negotiated in the handshake.
+    /// The version 0 means that the messages are exchanged without the envelope.
+    fn envelope_version(&self, node: &NodeId) -> Option<u64>;
+
+    /// Feeds how the node served the extension into its reputation.
+    /// The network evicts the peers with the lowest reputation first when it runs out of slots.
+    fn report(&self, node: &NodeId, behavior: PeerBehavior);
 }
 
 pub trait Extension: Send + Sync {
     fn name(&self) -> String;
     fn need_encryption(&self) -> bool;
+    /// The version of the messages. The negotiation is denied if the peer speaks another one.
+    fn version(&self) -> u64 {
+        0
+    }
 
     fn on_initialize(&self, api: Arc<Api>);
 
     fn on_node_added(&self, _node: &NodeId) {}
     fn on_node_removed(&self, _node: &NodeId) {}
+    /// Called before `on_node_removed` when either side closed the connection for the reason.
+    fn on_node_disconnected(&self, _node: &NodeId, _reason: DisconnectReason) {}
 
     fn on_negotiated(&self, _node: &NodeId) {}
     fn on_negotiation_allowed(&self, _node: &NodeId) {}
     fn on_negotiation_denied(&self, _node: &NodeId) {}
+    /// Called when the node didn't reply to the negotiation even after the retries.
+    fn on_negotiation_failed(&self, _node: &NodeId) {}
 
     fn on_message(&self, _node: &NodeId, _message: &[u8]) {}
 
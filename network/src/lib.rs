@@ -16,9 +16,13 @@
 
 #![allow(deprecated)]
 
+extern crate bytes;
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
 extern crate mio;
+extern crate net2;
 extern crate parking_lot;
 extern crate rand;
 extern crate rlp;
@@ -37,17 +41,29 @@ extern crate time;
 extern crate codechain_logger as clogger;
 
 mod addr;
+mod allowlist;
+mod capture;
 mod client;
 mod config;
 mod discovery;
+mod dns;
+mod dns_seed;
 mod extension;
 mod limited_table;
+mod mdns;
+mod metrics;
+mod node_key;
+mod node_record;
+mod peer_store;
+mod reputation;
 mod routing_table;
 mod service;
 mod session_initiator;
 mod test;
 mod timer;
 mod token_generator;
+mod trace;
+mod workers;
 
 mod p2p;
 pub mod session;
@@ -55,14 +71,32 @@ pub mod session;
 use ctypes::H256;
 
 pub use self::addr::SocketAddr;
+pub use self::allowlist::load as load_allowlist;
+pub use self::capture::{
+    read as read_capture, start as start_capture, Direction as CaptureDirection, Record as CaptureRecord,
+};
+pub use self::client::PeerNotification;
 pub use self::config::Config as NetworkConfig;
 pub use self::discovery::Api as DiscoveryApi;
+pub use self::dns_seed::{sign_record as sign_dns_seed_record, Seed as DnsSeed};
 pub use self::extension::{
     Api, Error as NetworkExtensionError, Extension as NetworkExtension, Result as NetworkExtensionResult, TimerToken,
 };
+pub use self::metrics::{corrupted_frames, expired_sessions, live_sessions, rate_limited_peers, throttled_messages};
+pub use self::node_key::load_or_generate as load_or_generate_node_key;
+pub use self::node_record::NodeRecord;
+pub use self::p2p::{
+    Config as P2pConfig, ConnectionDirection, ConnectionDump, ConnectionInfo, DisconnectReason, DropCounts, DropReason,
+    DropReport, HandlerDump, NodeStatus, PeerInfo, PeerState, RateLimit, SocketOptions,
+};
+pub use self::reputation::PeerBehavior;
 pub use self::service::{Error as NetworkServiceError, Service as NetworkService};
+pub use self::trace::start_exporter as start_trace_exporter;
 pub use self::test::{Call as TestNetworkCall, TestClient as TestNetworkClient};
 
-pub use self::routing_table::RoutingTable;
+pub use self::routing_table::{RoutingTable, RoutingTableSizes};
+
+#[cfg(feature = "fuzzing")]
+pub use self::p2p::fuzz;
 
 pub type NodeId = H256;
@@ -0,0 +1,183 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::{self, Ipv4Addr, SocketAddrV4};
+
+use cio::{IoChannel, IoContext, IoHandler, IoHandlerResult, StreamToken, TimerToken};
+use mio::net::UdpSocket;
+use mio::{Poll, PollOpt, Ready, Token};
+use net2::UdpBuilder;
+use rand;
+
+use super::super::p2p::Message as P2pMessage;
+use super::super::SocketAddr;
+use super::message::Packet;
+
+const MDNS_PORT: u16 = 5353;
+const MAX_PACKET_SIZE: usize = 9000;
+
+const SOCKET_TOKEN: StreamToken = 0;
+
+const QUERY_TOKEN: TimerToken = 0;
+const QUERY_MS: u64 = 10 * 1000;
+
+fn mdns_group() -> Ipv4Addr {
+    Ipv4Addr::new(224, 0, 0, 251)
+}
+
+// SO_REUSEADDR is enough to share the port on Windows
+#[cfg(unix)]
+fn reuse_port(builder: &UdpBuilder) -> io::Result<()> {
+    use net2::unix::UnixUdpBuilderExt;
+
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn reuse_port(_builder: &UdpBuilder) -> io::Result<()> {
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub enum Message {}
+
+pub struct Handler {
+    socket: UdpSocket,
+    // Tells this node from the other nodes on the same host
+    instance: String,
+    port: u16,
+    network_id: u64,
+    p2p_channel: IoChannel<P2pMessage>,
+}
+
+impl Handler {
+    pub fn bind(port: u16, network_id: u64, p2p_channel: IoChannel<P2pMessage>) -> io::Result<Self> {
+        // The other mDNS responders on the host, e.g. avahi, share the port
+        let builder = UdpBuilder::new_v4()?;
+        builder.reuse_address(true)?;
+        reuse_port(&builder)?;
+        let socket = builder.bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), MDNS_PORT))?;
+        socket.join_multicast_v4(&mdns_group(), &Ipv4Addr::new(0, 0, 0, 0))?;
+        // The nodes on the same host find each other through the loopback
+        socket.set_multicast_loop_v4(true)?;
+        Ok(Self {
+            socket: UdpSocket::from_socket(socket)?,
+            instance: format!("{:016x}", rand::random::<u64>()),
+            port,
+            network_id,
+            p2p_channel,
+        })
+    }
+
+    fn send(&self, packet: &Packet) {
+        let target: net::SocketAddr = SocketAddrV4::new(mdns_group(), MDNS_PORT).into();
+        // The packets are sent again at the next query, so a packet which cannot be sent now is dropped
+        if let Err(err) = self.socket.send_to(&packet.encode(), &target) {
+            cdebug!(NET, "Cannot send the mDNS packet: {:?}", err);
+        }
+    }
+
+    fn announcement(&self) -> Packet {
+        Packet::Announcement {
+            instance: self.instance.clone(),
+            port: self.port,
+            network_id: self.network_id,
+        }
+    }
+
+    fn handle(&self, packet: Packet, from: SocketAddr) -> IoHandlerResult<()> {
+        match packet {
+            Packet::Query => self.send(&self.announcement()),
+            Packet::Announcement {
+                instance,
+                port,
+                network_id,
+            } => {
+                if instance == self.instance {
+                    return Ok(())
+                }
+                if network_id != self.network_id {
+                    ctrace!(NET, "Ignoring the node of the network {} at {}", network_id, from);
+                    return Ok(())
+                }
+                let address = SocketAddr::new(from.ip(), port);
+                ctrace!(NET, "Found {} on the local network", address);
+                self.p2p_channel.send(P2pMessage::AddLocalPeer(address))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IoHandler<Message> for Handler {
+    fn initialize(&self, io: &IoContext<Message>) -> IoHandlerResult<()> {
+        io.register_stream(SOCKET_TOKEN)?;
+        io.register_timer(QUERY_TOKEN, QUERY_MS)?;
+        // Lets the running nodes know this node without waiting for their queries
+        self.send(&self.announcement());
+        self.send(&Packet::Query);
+        Ok(())
+    }
+
+    fn timeout(&self, _io: &IoContext<Message>, token: TimerToken) -> IoHandlerResult<()> {
+        match token {
+            QUERY_TOKEN => {
+                self.send(&Packet::Query);
+                Ok(())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn message(&self, _io: &IoContext<Message>, message: &Message) -> IoHandlerResult<()> {
+        match *message {}
+    }
+
+    fn stream_readable(&self, _io: &IoContext<Message>, stream: StreamToken) -> IoHandlerResult<()> {
+        if stream != SOCKET_TOKEN {
+            unreachable!()
+        }
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            };
+            match Packet::decode(&buf[..size]) {
+                Ok(Some(packet)) => self.handle(packet, from.into())?,
+                Ok(None) => {}
+                Err(err) => ctrace!(NET, "Invalid mDNS packet from {}: {:?}", from, err),
+            }
+        }
+        Ok(())
+    }
+
+    fn register_stream(&self, stream: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
+        if stream != SOCKET_TOKEN {
+            unreachable!()
+        }
+        Ok(poll.register(&self.socket, reg, Ready::readable(), PollOpt::edge())?)
+    }
+
+    fn update_stream(&self, stream: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
+        if stream != SOCKET_TOKEN {
+            unreachable!()
+        }
+        Ok(poll.reregister(&self.socket, reg, Ready::readable(), PollOpt::edge())?)
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The subset of the mDNS packets (RFC 6762, RFC 6763) which the nodes need to find each other.
+
+use std::str;
+
+use super::super::dns::{
+    self, Error, Header, Reader, CLASS_IN, FLAG_AUTHORITATIVE, FLAG_RESPONSE, TYPE_ANY, TYPE_PTR, TYPE_SRV, TYPE_TXT,
+};
+
+pub const SERVICE_NAME: &'static str = "_codechain._tcp.local";
+
+// Lets the receivers replace the records of the instance instead of adding them
+const CACHE_FLUSH: u16 = 0x8000;
+const TTL_SECS: u32 = 120;
+
+const NETWORK_ID_KEY: &'static str = "network_id=";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    /// Asks the nodes on the local network to announce themselves.
+    Query,
+    /// Tells that the node named `instance` accepts the connections for the network on `port`.
+    /// The IP is the source of the packet.
+    Announcement {
+        instance: String,
+        port: u16,
+        network_id: u64,
+    },
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Packet::Query => {
+                header(0, 1, 0).write(&mut bytes);
+                dns::write_question(&mut bytes, SERVICE_NAME, TYPE_PTR);
+            }
+            Packet::Announcement {
+                instance,
+                port,
+                network_id,
+            } => {
+                let instance_name = format!("{}.{}", instance, SERVICE_NAME);
+                header(FLAG_RESPONSE | FLAG_AUTHORITATIVE, 0, 3).write(&mut bytes);
+
+                let mut ptr = Vec::new();
+                dns::write_name(&mut ptr, &instance_name);
+                dns::write_record(&mut bytes, SERVICE_NAME, TYPE_PTR, CLASS_IN, TTL_SECS, &ptr);
+
+                let mut srv = Vec::new();
+                dns::write_u16(&mut srv, 0); // priority
+                dns::write_u16(&mut srv, 0); // weight
+                dns::write_u16(&mut srv, *port);
+                dns::write_name(&mut srv, &format!("{}.local", instance));
+                dns::write_record(&mut bytes, &instance_name, TYPE_SRV, CLASS_IN | CACHE_FLUSH, TTL_SECS, &srv);
+
+                let entry = format!("{}{}", NETWORK_ID_KEY, network_id);
+                let mut txt = vec![entry.len() as u8];
+                txt.extend_from_slice(entry.as_bytes());
+                dns::write_record(&mut bytes, &instance_name, TYPE_TXT, CLASS_IN | CACHE_FLUSH, TTL_SECS, &txt);
+            }
+        }
+        bytes
+    }
+
+    /// Returns None if the packet is not about CodeChain, e.g. it's from another service on the network.
+    pub fn decode(bytes: &[u8]) -> Result<Option<Packet>, Error> {
+        let mut reader = Reader::new(bytes);
+        let header = reader.read_header()?;
+
+        if !header.is_response() {
+            for _ in 0..header.questions {
+                let (name, query_type) = reader.read_question()?;
+                if name == SERVICE_NAME && (query_type == TYPE_PTR || query_type == TYPE_ANY) {
+                    return Ok(Some(Packet::Query))
+                }
+            }
+            return Ok(None)
+        }
+
+        for _ in 0..header.questions {
+            reader.read_question()?;
+        }
+        let suffix = format!(".{}", SERVICE_NAME);
+        let mut service = None;
+        let mut network_id = None;
+        for _ in 0..header.records() {
+            let record = reader.read_record()?;
+            if !record.name.ends_with(&suffix) {
+                continue
+            }
+            let instance = record.name[..record.name.len() - suffix.len()].to_string();
+            match record.record_type {
+                // The priority and the weight come before the port
+                TYPE_SRV if record.data.len() >= 6 => {
+                    let port = (u16::from(record.data[4]) << 8) | u16::from(record.data[5]);
+                    service = Some((instance, port));
+                }
+                TYPE_TXT => network_id = network_id.or_else(|| read_network_id(record.data)),
+                _ => {}
+            }
+        }
+        Ok(match (service, network_id) {
+            (Some((instance, port)), Some(network_id)) => Some(Packet::Announcement {
+                instance,
+                port,
+                network_id,
+            }),
+            _ => None,
+        })
+    }
+}
+
+// The id is always 0 in mDNS
+fn header(flags: u16, questions: u16, answers: u16) -> Header {
+    Header {
+        id: 0,
+        flags,
+        questions,
+        answers,
+        authorities: 0,
+        additionals: 0,
+    }
+}
+
+fn read_network_id(txt: &[u8]) -> Option<u64> {
+    dns::txt_strings(txt)
+        .into_iter()
+        .filter_map(|entry| str::from_utf8(entry).ok())
+        .find(|entry| entry.starts_with(NETWORK_ID_KEY))
+        .and_then(|entry| entry[NETWORK_ID_KEY.len()..].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_query() {
+        assert_eq!(Ok(Some(Packet::Query)), Packet::decode(&Packet::Query.encode()));
+    }
+
+    #[test]
+    fn encode_and_decode_announcement() {
+        let announcement = Packet::Announcement {
+            instance: "3f2a".to_string(),
+            port: 3485,
+            network_id: 17,
+        };
+        assert_eq!(Ok(Some(announcement.clone())), Packet::decode(&announcement.encode()));
+    }
+
+    #[test]
+    fn the_query_of_another_service_is_ignored() {
+        let mut bytes = Vec::new();
+        header(0, 1, 0).write(&mut bytes);
+        dns::write_question(&mut bytes, "_http._tcp.local", TYPE_PTR);
+        assert_eq!(Ok(None), Packet::decode(&bytes));
+    }
+
+    #[test]
+    fn compressed_names_are_read() {
+        let mut bytes = Vec::new();
+        header(FLAG_RESPONSE, 0, 2).write(&mut bytes);
+        let txt_name_offset = bytes.len();
+        let entry = b"network_id=3";
+        let mut txt = vec![entry.len() as u8];
+        txt.extend_from_slice(entry);
+        dns::write_record(&mut bytes, &format!("a.{}", SERVICE_NAME), TYPE_TXT, CLASS_IN, TTL_SECS, &txt);
+
+        // The SRV record names the instance with a pointer to the name of the TXT record
+        bytes.extend_from_slice(&[0xC0, txt_name_offset as u8]);
+        dns::write_u16(&mut bytes, TYPE_SRV);
+        dns::write_u16(&mut bytes, CLASS_IN);
+        bytes.extend_from_slice(&[0, 0, 0, 120]);
+        dns::write_u16(&mut bytes, 7);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0x0D, 0x9D, 0]);
+
+        assert_eq!(
+            Ok(Some(Packet::Announcement {
+                instance: "a".to_string(),
+                port: 3485,
+                network_id: 3,
+            })),
+            Packet::decode(&bytes)
+        );
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let bytes = Packet::Query.encode();
+        assert_eq!(Err(Error::Truncated), Packet::decode(&bytes[..bytes.len() - 1]));
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Counts the network events which the operators watch.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+lazy_static! {
+    static ref CORRUPTED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static ref THROTTLED_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+    static ref RATE_LIMITED_PEERS: AtomicUsize = AtomicUsize::new(0);
+    static ref LIVE_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+    static ref EXPIRED_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+}
+
+pub fn count_corrupted_frame() {
+    CORRUPTED_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of the connections which are closed since their frames are corrupted.
+pub fn corrupted_frames() -> usize {
+    CORRUPTED_FRAMES.load(Ordering::Relaxed)
+}
+
+pub fn count_throttled_message() {
+    THROTTLED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of the extension messages which are dropped since their peers exceeded the rate limit.
+pub fn throttled_messages() -> usize {
+    THROTTLED_MESSAGES.load(Ordering::Relaxed)
+}
+
+pub fn count_rate_limited_peer() {
+    RATE_LIMITED_PEERS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of the connections which are closed since they kept flooding after being throttled.
+pub fn rate_limited_peers() -> usize {
+    RATE_LIMITED_PEERS.load(Ordering::Relaxed)
+}
+
+pub fn set_live_sessions(sessions: usize) {
+    LIVE_SESSIONS.store(sessions, Ordering::Relaxed);
+}
+
+/// The number of the sessions which are being made or are not established yet, as of the last sweep.
+pub fn live_sessions() -> usize {
+    LIVE_SESSIONS.load(Ordering::Relaxed)
+}
+
+pub fn count_expired_sessions(sessions: usize) {
+    EXPIRED_SESSIONS.fetch_add(sessions, Ordering::Relaxed);
+}
+
+/// The number of the sessions which are forgotten since they were not established in time.
+pub fn expired_sessions() -> usize {
+    EXPIRED_SESSIONS.load(Ordering::Relaxed)
+}
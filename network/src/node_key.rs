@@ -0,0 +1,90 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use ckeys::hex::ToHex;
+use ckeys::{Generator, KeyPair, Private, Random};
+
+/// Loads the long-term key pair of this node from the file at `path`.
+/// A new key pair is generated and saved if the file doesn't exist.
+pub fn load_or_generate(path: &Path) -> Result<KeyPair, String> {
+    if path.exists() {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read node key {:?}: {}", path, e))?;
+        let private = Private::from_str(contents.trim()).map_err(|e| format!("Invalid node key {:?}: {}", path, e))?;
+        return KeyPair::from_private(private).map_err(|e| format!("Invalid node key {:?}: {}", path, e))
+    }
+
+    let key_pair = Random.generate().map_err(|e| format!("Cannot generate node key: {:?}", e))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Cannot create {:?}: {}", parent, e))?;
+    }
+    let mut file = create_secret_file(path).map_err(|e| format!("Cannot create node key {:?}: {}", path, e))?;
+    file.write_all(key_pair.private().to_hex().as_bytes())
+        .map_err(|e| format!("Cannot write node key {:?}: {}", path, e))?;
+    Ok(key_pair)
+}
+
+// Fails if the file exists, e.g. because another process created it after the check.
+// The mode is set on the creation, so the key is never readable by others.
+#[cfg(unix)]
+fn create_secret_file(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_secret_file(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn generated_key_is_loaded_again() {
+        let path = env::temp_dir().join("codechain-network-node-key-test");
+        let _ = fs::remove_file(&path);
+
+        let generated = load_or_generate(&path).unwrap();
+        let loaded = load_or_generate(&path).unwrap();
+        assert_eq!(generated.public(), loaded.public());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_key_is_readable_only_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join("codechain-network-node-key-mode-test");
+        let _ = fs::remove_file(&path);
+
+        load_or_generate(&path).unwrap();
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
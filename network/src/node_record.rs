@@ -0,0 +1,173 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccrypto::blake256;
+use ckeys::{sign_ecdsa, verify_ecdsa, ECDSASignature, KeyPair, Message, Public};
+use ctypes::H520;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::SocketAddr;
+
+/// The announcement of a node which is signed by its node key.
+/// The discovery passes the records around instead of the bare addresses, so that nobody can advertise the others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeRecord {
+    // The newer record of the same node has the greater sequence
+    sequence: u64,
+    public: Public,
+    addresses: Vec<SocketAddr>,
+    network_id: u64,
+    // The names of the extensions which the node runs
+    capabilities: Vec<String>,
+    signature: ECDSASignature,
+}
+
+impl NodeRecord {
+    pub fn new(
+        key_pair: &KeyPair,
+        sequence: u64,
+        addresses: Vec<SocketAddr>,
+        network_id: u64,
+        capabilities: Vec<String>,
+    ) -> Self {
+        let message = signing_message(sequence, key_pair.public(), &addresses, network_id, &capabilities);
+        let signature = sign_ecdsa(key_pair.private(), &message).expect("The key pair is valid");
+        Self {
+            sequence,
+            public: *key_pair.public(),
+            addresses,
+            network_id,
+            capabilities,
+            signature,
+        }
+    }
+
+    /// Returns true if the node key signed the record.
+    pub fn verify(&self) -> bool {
+        let message =
+            signing_message(self.sequence, &self.public, &self.addresses, self.network_id, &self.capabilities);
+        verify_ecdsa(&self.public, &self.signature, &message).unwrap_or(false)
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn public(&self) -> &Public {
+        &self.public
+    }
+
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    pub fn network_id(&self) -> u64 {
+        self.network_id
+    }
+
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+}
+
+fn signing_message(
+    sequence: u64,
+    public: &Public,
+    addresses: &[SocketAddr],
+    network_id: u64,
+    capabilities: &[String],
+) -> Message {
+    let mut s = RlpStream::new_list(5);
+    s.append(&sequence)
+        .append(public)
+        .append_list(addresses)
+        .append(&network_id)
+        .append_list::<String, _>(capabilities);
+    blake256(s.out())
+}
+
+impl Encodable for NodeRecord {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(6)
+            .append(&self.sequence)
+            .append(&self.public)
+            .append_list(&self.addresses)
+            .append(&self.network_id)
+            .append_list::<String, _>(&self.capabilities)
+            .append(&H520::from(self.signature.clone()));
+    }
+}
+
+impl Decodable for NodeRecord {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 6 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let signature: H520 = rlp.val_at(5)?;
+        Ok(Self {
+            sequence: rlp.val_at(0)?,
+            public: rlp.val_at(1)?,
+            addresses: rlp.list_at(2)?,
+            network_id: rlp.val_at(3)?,
+            capabilities: rlp.list_at(4)?,
+            signature: signature.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ckeys::{Generator, Random};
+    use rlp::{self, Encodable};
+
+    use super::*;
+
+    fn record(key_pair: &KeyPair) -> NodeRecord {
+        let addresses = vec![SocketAddr::v4(127, 0, 0, 1, 3485)];
+        NodeRecord::new(key_pair, 3, addresses, 0x11, vec!["block-propagation".to_string()])
+    }
+
+    #[test]
+    fn signed_record_is_verified() {
+        let key_pair = Random.generate().unwrap();
+        assert!(record(&key_pair).verify());
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let key_pair = Random.generate().unwrap();
+        let record = record(&key_pair);
+        let decoded: NodeRecord = rlp::decode(&record.rlp_bytes());
+        assert_eq!(record, decoded);
+        assert!(decoded.verify());
+    }
+
+    #[test]
+    fn modified_record_is_not_verified() {
+        let key_pair = Random.generate().unwrap();
+        let mut record = record(&key_pair);
+        record.addresses = vec![SocketAddr::v4(10, 0, 0, 1, 3485)];
+        assert!(!record.verify());
+    }
+
+    #[test]
+    fn record_of_another_key_is_not_verified() {
+        let key_pair = Random.generate().unwrap();
+        let mut record = record(&key_pair);
+        record.public = *Random.generate().unwrap().public();
+        assert!(!record.verify());
+    }
+}
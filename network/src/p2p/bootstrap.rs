@@ -0,0 +1,194 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::super::SocketAddr;
+
+/// Dials the bootnodes first, and then the peers which the node was connected to before.
+/// The next stage starts only if the previous ones didn't bring enough peers in their time.
+pub struct Bootstrap {
+    stages: VecDeque<Vec<SocketAddr>>,
+    // The addresses of the stages which are started so far
+    dialing: Vec<SocketAddr>,
+    target_peers: usize,
+    stage_timeout: Duration,
+    stage_started_at: Option<Instant>,
+    done: bool,
+}
+
+impl Bootstrap {
+    pub fn new(
+        bootnodes: Vec<SocketAddr>,
+        stored_peers: Vec<SocketAddr>,
+        target_peers: usize,
+        stage_timeout: Duration,
+    ) -> Self {
+        // The stored bootnodes are already dialed in the first stage
+        let stored_peers = stored_peers.into_iter().filter(|address| !bootnodes.contains(address)).collect();
+        let stages: VecDeque<_> =
+            vec![bootnodes, stored_peers].into_iter().filter(|stage: &Vec<_>| !stage.is_empty()).collect();
+        let done = stages.is_empty();
+        Self {
+            stages,
+            dialing: Vec::new(),
+            target_peers,
+            stage_timeout,
+            stage_started_at: None,
+            done,
+        }
+    }
+
+    /// Returns the addresses of the stage which starts now, if any.
+    pub fn next_stage(&mut self, number_of_peers: usize, now: Instant) -> Option<Vec<SocketAddr>> {
+        if self.done {
+            return None
+        }
+        if self.target_peers <= number_of_peers {
+            self.finish();
+            return None
+        }
+        if let Some(started_at) = self.stage_started_at {
+            if now < started_at + self.stage_timeout {
+                return None
+            }
+        }
+        match self.stages.pop_front() {
+            Some(stage) => {
+                self.dialing.extend(stage.iter().cloned());
+                self.stage_started_at = Some(now);
+                Some(stage)
+            }
+            None => {
+                // The last stage timed out
+                self.finish();
+                None
+            }
+        }
+    }
+
+    /// Adds the stage after the other stages. Return true if the bootstrapping resumes.
+    pub fn add_stage(&mut self, stage: Vec<SocketAddr>) -> bool {
+        let stage: Vec<_> = stage.into_iter().filter(|address| !self.dialing.contains(address)).collect();
+        if stage.is_empty() {
+            return false
+        }
+        self.stages.push_back(stage);
+        let resumes = self.done;
+        self.done = false;
+        resumes
+    }
+
+    /// The addresses of the started stages, which are dialed once their sessions are ready.
+    pub fn dialing(&self) -> &[SocketAddr] {
+        &self.dialing
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn finish(&mut self) {
+        self.stages.clear();
+        self.dialing.clear();
+        self.stage_started_at = None;
+        self.done = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::v4(127, 0, 0, 1, port)
+    }
+
+
+    #[test]
+    fn bootnodes_are_dialed_before_the_stored_peers() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![address(1)], vec![address(2), address(3)], 5, timeout);
+        assert_eq!(Some(vec![address(1)]), bootstrap.next_stage(0, now));
+        assert_eq!(None, bootstrap.next_stage(1, now + Duration::from_secs(1)), "The first stage is not over yet");
+        assert_eq!(Some(vec![address(2), address(3)]), bootstrap.next_stage(1, now + timeout));
+        assert_eq!(&[address(1), address(2), address(3)], bootstrap.dialing());
+        assert!(!bootstrap.is_done());
+
+        assert_eq!(None, bootstrap.next_stage(3, now + timeout * 2));
+        assert!(bootstrap.is_done());
+        assert!(bootstrap.dialing().is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_target_peers() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![address(1)], vec![address(2)], 1, timeout);
+        assert_eq!(Some(vec![address(1)]), bootstrap.next_stage(0, now));
+        assert_eq!(None, bootstrap.next_stage(1, now + timeout));
+        assert!(bootstrap.is_done());
+        assert_eq!(None, bootstrap.next_stage(0, now + timeout * 2));
+    }
+
+    #[test]
+    fn the_stored_bootnodes_are_not_dialed_twice() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![address(1)], vec![address(1), address(2)], 5, timeout);
+        assert_eq!(Some(vec![address(1)]), bootstrap.next_stage(0, now));
+        assert_eq!(Some(vec![address(2)]), bootstrap.next_stage(0, now + timeout));
+    }
+
+    #[test]
+    fn falls_back_to_the_stored_peers_without_bootnodes() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![], vec![address(2)], 5, timeout);
+        assert_eq!(Some(vec![address(2)]), bootstrap.next_stage(0, now));
+    }
+
+    #[test]
+    fn the_added_stage_resumes_the_bootstrapping() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![], vec![], 5, timeout);
+        assert!(bootstrap.add_stage(vec![address(1)]));
+        assert!(!bootstrap.add_stage(vec![address(2)]), "The bootstrapping is not over yet");
+        assert_eq!(Some(vec![address(1)]), bootstrap.next_stage(0, now));
+        assert!(!bootstrap.add_stage(vec![address(1)]), "The address is already dialed");
+        assert_eq!(Some(vec![address(2)]), bootstrap.next_stage(0, now + timeout));
+    }
+
+    #[test]
+    fn the_added_stage_does_not_resume_with_the_target_peers() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut bootstrap = Bootstrap::new(vec![], vec![], 1, timeout);
+        assert!(bootstrap.add_stage(vec![address(1)]));
+        assert_eq!(None, bootstrap.next_stage(1, now));
+        assert!(bootstrap.is_done());
+    }
+
+    #[test]
+    fn nothing_to_bootstrap() {
+        let timeout = Duration::from_secs(5);
+        let bootstrap = Bootstrap::new(vec![], vec![], 5, timeout);
+        assert!(bootstrap.is_done());
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ckeys::{KeyPair, Public};
+
+use super::super::SocketAddr;
+use super::{RateLimit, SocketOptions};
+
+/// The settings of the p2p handler
+pub struct Config {
+    pub address: SocketAddr,
+    /// Accepts the light clients in the browsers
+    pub websocket_address: Option<SocketAddr>,
+    pub key_pair: KeyPair,
+    pub network_id: u64,
+    /// Forwards introductions between the peers if true
+    pub relay: bool,
+    /// The peers which are always kept connected
+    pub static_peers: Vec<SocketAddr>,
+    pub bootnodes: Vec<SocketAddr>,
+    /// Remembers the connected peers to dial them after the restart
+    pub peer_store_path: Option<PathBuf>,
+    pub min_peers: usize,
+    pub max_peers: usize,
+    /// Disconnects the peers which sent no extension message for this long
+    pub idle_timeout: Option<Duration>,
+    /// Overrides the idle timeout for the static peers. They never time out if it's None.
+    pub static_peer_idle_timeout: Option<Duration>,
+    /// The peers which sent no extension message for this long are evicted first when the slots are full
+    pub evictable_idle_timeout: Duration,
+    pub socket_options: SocketOptions,
+    /// Overrides the socket options for the connections from and to the static peers
+    pub static_peer_socket_options: SocketOptions,
+    /// Limits the inbound extension messages of each peer
+    pub rate_limit: RateLimit,
+    /// Sent to the peers in the handshake
+    pub user_agent: String,
+    /// Accepts the older peers which know only the unauthenticated AES-CBC cipher
+    pub allow_legacy_cipher: bool,
+    /// Only these nodes can complete the handshake in a permissioned network
+    pub allowlist: Option<Vec<Public>>,
+}
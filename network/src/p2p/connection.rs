@@ -14,32 +14,90 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::cell::Cell;
+use std::cmp;
 use std::collections::{HashMap, VecDeque};
 use std::error;
 use std::fmt;
 use std::io;
 use std::result;
+use std::time::{Duration, Instant};
 
-use cio::IoManager;
-use mio::deprecated::EventLoop;
+use bytes::Bytes;
+use ckeys::{KeyPair, Public};
 use mio::unix::UnixReady;
-use mio::{PollOpt, Ready, Token};
+use mio::{Poll, PollOpt, Ready, Token};
 use parking_lot::Mutex;
 use rlp::{DecoderError, UntrustedRlp};
 
-use super::super::session::Session;
+use super::super::capture::{self, Direction};
+use super::super::session::{cipher, CipherSuite, Role, Session};
+use super::super::trace::Span;
 use super::super::{NodeId, SocketAddr};
-use super::message::{HandshakeMessage, Message, Seq, SignedMessage, Version};
-use super::stream::{Error as StreamError, SignedStream, Stream};
-use super::{ExtensionMessage, NegotiationMessage};
+use super::drops::DropReason;
+use super::message::{
+    seal_envelope, DisconnectMessage, DisconnectReason, HandshakeMessage, Message, Seq, SignedMessage, Version,
+    ENVELOPE_VERSION,
+};
+use super::stream::{Error as StreamError, SignedStream, Stream, FRAME_VERSION, LEGACY_FRAME_VERSION};
+use super::{ExtensionMessage, NegotiationMessage, RelayMessage};
+
+// Queued messages are written together until the write reaches this size.
+const MAX_COALESCED_WRITE_SIZE: usize = 64 * 1024;
+
+/// Pops the items from the queue and concatenates their encodings until the result reaches `limit` bytes.
+///
+/// The first item is always popped even if it is larger than `limit`.
+fn coalesce<T, E, F>(queue: &mut VecDeque<T>, limit: usize, mut encode: F) -> (Vec<u8>, Vec<T>)
+where
+    E: AsRef<[u8]>,
+    F: FnMut(&T) -> E, {
+    let mut bytes = Vec::new();
+    let mut items = Vec::new();
+    while bytes.len() < limit {
+        match queue.pop_front() {
+            Some(item) => {
+                bytes.extend_from_slice(encode(&item).as_ref());
+                items.push(item);
+            }
+            None => break,
+        }
+    }
+    (bytes, items)
+}
 
 struct EstablishedConnection {
     stream: SignedStream,
-    send_queue: VecDeque<Message>,
+    // The span of a traced message ends when the message is sent
+    send_queue: VecDeque<(Message, Option<Span>)>,
     next_negotiation_seq: Seq,
-    requested_negotiation: HashMap<Seq, String>,
+    requested_negotiation: HashMap<Seq, RequestedNegotiation>,
     remote_node_id: NodeId,
+    remote_public: Public,
+    // The lower of the envelope versions of the both sides
+    envelope_version: Version,
+    remote_user_agent: String,
+    cipher: CipherSuite,
+    // Smoothed from the round trips of the negotiations which this node requested
+    rtt: Option<Duration>,
+    // The sequence numbers of the authenticated extension messages, which increase in each direction
+    next_sent_sequence: u64,
+    next_received_sequence: u64,
+}
+
+struct RequestedNegotiation {
+    name: String,
+    version: Version,
+    requested_at: Instant,
+    retries: usize,
+}
+
+/// The negotiation requests which the peer didn't reply to in time
+#[derive(Debug, Default, PartialEq)]
+pub struct ExpiredNegotiations {
+    /// The number of the requests which were sent again
+    pub retried: usize,
+    /// The extensions which gave up the negotiation after the retries
+    pub failed: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -47,6 +105,11 @@ pub enum Error {
     StreamError(StreamError),
     DecoderError(DecoderError),
     UnreadySession,
+    UnauthenticatedHandshake,
+    UnexpectedHandshake,
+    NoCommonCipherSuite,
+    UnauthenticatedMessage,
+    AlreadyEstablished,
 }
 
 impl fmt::Display for Error {
@@ -55,6 +118,11 @@ impl fmt::Display for Error {
             Error::StreamError(err) => err.fmt(f),
             Error::DecoderError(err) => err.fmt(f),
             Error::UnreadySession => fmt::Debug::fmt(self, f),
+            Error::UnauthenticatedHandshake => fmt::Debug::fmt(self, f),
+            Error::UnexpectedHandshake => fmt::Debug::fmt(self, f),
+            Error::NoCommonCipherSuite => fmt::Debug::fmt(self, f),
+            Error::UnauthenticatedMessage => fmt::Debug::fmt(self, f),
+            Error::AlreadyEstablished => fmt::Debug::fmt(self, f),
         }
     }
 }
@@ -65,6 +133,11 @@ impl error::Error for Error {
             Error::StreamError(err) => err.description(),
             Error::DecoderError(err) => err.description(),
             Error::UnreadySession => "Session is not ready",
+            Error::UnauthenticatedHandshake => "Handshake is not signed by the peer",
+            Error::UnexpectedHandshake => "Handshake is received after the session is established",
+            Error::NoCommonCipherSuite => "The peer supports none of the cipher suites",
+            Error::UnauthenticatedMessage => "Extension message doesn't match its MAC or is replayed",
+            Error::AlreadyEstablished => "Connection is already established",
         }
     }
 
@@ -73,6 +146,11 @@ impl error::Error for Error {
             Error::StreamError(err) => Some(err),
             Error::DecoderError(err) => Some(err),
             Error::UnreadySession => None,
+            Error::UnauthenticatedHandshake => None,
+            Error::UnexpectedHandshake => None,
+            Error::NoCommonCipherSuite => None,
+            Error::UnauthenticatedMessage => None,
+            Error::AlreadyEstablished => None,
         }
     }
 }
@@ -91,108 +169,243 @@ impl From<StreamError> for Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+// The peers which don't know the envelope advertise 0
+fn negotiate_envelope_version(remote_version: Option<Version>) -> Version {
+    cmp::min(ENVELOPE_VERSION, remote_version.unwrap_or(0))
+}
+
+// The handshake is framed in the legacy version, and both sides switch after the ack
+fn negotiate_frame_version(remote_version: Option<Version>) -> Version {
+    cmp::min(FRAME_VERSION, remote_version.unwrap_or(LEGACY_FRAME_VERSION))
+}
+
+// The peers which don't know the cipher suites advertise nothing
+fn negotiate_cipher_suite(local: &[CipherSuite], remote: &Option<Vec<CipherSuite>>) -> Result<CipherSuite> {
+    let remote = remote.as_ref().map(|suites| &suites[..]).unwrap_or(&[]);
+    cipher::negotiate(local, remote).ok_or(Error::NoCommonCipherSuite)
+}
+
 impl EstablishedConnection {
-    fn new(stream: SignedStream, remote_node_id: NodeId) -> Self {
+    fn new(
+        stream: SignedStream,
+        remote_node_id: NodeId,
+        remote_public: Public,
+        envelope_version: Version,
+        remote_user_agent: String,
+        cipher: CipherSuite,
+    ) -> Self {
         Self {
             stream,
             send_queue: VecDeque::new(),
             next_negotiation_seq: 0,
             requested_negotiation: HashMap::new(),
             remote_node_id,
+            remote_public,
+            envelope_version,
+            remote_user_agent,
+            cipher,
+            rtt: None,
+            next_sent_sequence: 0,
+            next_received_sequence: 0,
         }
     }
 
     fn enqueue(&mut self, message: Message) {
-        self.send_queue.push_back(message);
+        self.send_queue.push_back((message, None));
     }
 
     fn enqueue_negotiation_request(&mut self, name: String, version: Version) {
         let seq = self.next_negotiation_seq;
         self.next_negotiation_seq += 1;
-        if let Some(_) = self.requested_negotiation.insert(seq, name.clone()) {
-            unreachable!();
-        }
+        let requested = RequestedNegotiation {
+            name: name.clone(),
+            version,
+            requested_at: Instant::now(),
+            retries: 0,
+        };
+        let t = self.requested_negotiation.insert(seq, requested);
+        debug_assert!(t.is_none(), "The sequence numbers are not reused");
         self.enqueue(Message::Negotiation(NegotiationMessage::request(seq, name, version)));
     }
 
-    fn remove_requested_negotiation(&mut self, seq: &u64) -> Option<String> {
-        self.requested_negotiation.remove(seq)
+    fn remove_requested_negotiation(&mut self, seq: &u64) -> Option<(String, Version)> {
+        let requested = self.requested_negotiation.remove(seq)?;
+        let sample = Instant::now().duration_since(requested.requested_at);
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt * 7 / 8 + sample / 8,
+            None => sample,
+        });
+        Some((requested.name, requested.version))
+    }
+
+    // The retries reuse the sequence number, so a late reply to the earlier request is still accepted.
+    fn expire_negotiations(&mut self, timeout: Duration, max_retries: usize, now: Instant) -> ExpiredNegotiations {
+        let mut expired = ExpiredNegotiations::default();
+        let timed_out: Vec<Seq> = self
+            .requested_negotiation
+            .iter()
+            .filter(|(_, requested)| requested.requested_at + timeout <= now)
+            .map(|(seq, _)| *seq)
+            .collect();
+        for seq in timed_out {
+            let retry = {
+                let requested = self.requested_negotiation.get_mut(&seq).expect("The seq is taken from the map");
+                if requested.retries < max_retries {
+                    requested.retries += 1;
+                    requested.requested_at = now;
+                    Some((requested.name.clone(), requested.version))
+                } else {
+                    None
+                }
+            };
+            match retry {
+                Some((name, version)) => {
+                    ctrace!(NET, "Requesting the negotiation of {} to {:?} again", name, self.remote_node_id);
+                    self.enqueue(Message::Negotiation(NegotiationMessage::request(seq, name, version)));
+                    expired.retried += 1;
+                }
+                None => {
+                    let requested = self.requested_negotiation.remove(&seq).expect("The seq is taken from the map");
+                    expired.failed.push(requested.name);
+                }
+            }
+        }
+        expired
     }
 
     fn enqueue_negotiation_allowed(&mut self, seq: Seq) {
         self.enqueue(Message::Negotiation(NegotiationMessage::allowed(seq)));
     }
 
-    fn enqueue_extension_message(&mut self, extension_name: String, need_encryption: bool, message: &[u8]) {
+    fn enqueue_negotiation_denied(&mut self, seq: Seq) {
+        self.enqueue(Message::Negotiation(NegotiationMessage::denied(seq, vec![])));
+    }
+
+    fn enqueue_extension_message(
+        &mut self,
+        extension_name: String,
+        need_encryption: bool,
+        message: Bytes,
+        span: Option<Span>,
+    ) -> result::Result<(), DropReason> {
         const VERSION: u64 = 0;
+        capture::record(Direction::Outbound, &self.remote_node_id, &extension_name, &message);
+        let message = seal_envelope(self.envelope_version, &extension_name, &message);
         let message = if need_encryption {
             match ExtensionMessage::encrypted_from_unencrypted_data(
                 extension_name,
                 VERSION,
-                message,
+                &message,
                 self.stream.session(),
+                self.cipher,
             ) {
                 Ok(message) => message,
                 Err(err) => {
                     cdebug!(NET, "Cannot encrypt message : {:?}", err);
-                    return
+                    return Err(DropReason::EncryptionFailure)
                 }
             }
+        } else if self.cipher.is_aead() {
+            let sequence = self.next_sent_sequence;
+            self.next_sent_sequence += 1;
+            ExtensionMessage::authenticated(extension_name, VERSION, message, sequence, self.stream.session())
         } else {
-            ExtensionMessage::unencrypted(extension_name, VERSION, &message)
+            ExtensionMessage::unencrypted(extension_name, VERSION, message)
         };
-        self.enqueue(Message::Extension(message));
+        self.send_queue.push_back((Message::Extension(message), span));
+        Ok(())
+    }
+
+    fn enqueue_relay_message(&mut self, message: RelayMessage) {
+        self.enqueue(Message::Relay(message));
+    }
+
+    // Writes the reason right away, since the connection is closed after this
+    fn disconnect(&mut self, reason: DisconnectReason) -> Result<()> {
+        // The queued messages are dropped with the connection anyway
+        self.send_queue.clear();
+        self.enqueue(Message::Disconnect(DisconnectMessage::disconnect(reason)));
+        self.send()?;
+        self.stream.flush()?;
+        Ok(())
     }
 
     fn stream(&self) -> &SignedStream {
         &self.stream
     }
 
+    // The writable event resumes the bytes which the transport holds
     fn interest(&self) -> Ready {
-        if self.send_queue.is_empty() {
+        if self.send_queue.is_empty() && !self.stream.has_pending_write() {
             Ready::readable() | UnixReady::hup()
         } else {
             Ready::writable() | Ready::readable() | UnixReady::hup()
         }
     }
 
+    // Returns false if the queue is empty or the socket doesn't take more bytes now
     fn send(&mut self) -> Result<bool> {
-        if let Some(message) = self.send_queue.pop_front() {
-            self.stream.write(&message)?;
-            Ok(true)
-        } else {
-            Ok(false)
+        // The tail of the last write goes before the next messages
+        if self.stream.has_unsent() {
+            self.stream.flush()?;
+            if self.stream.has_unsent() {
+                return Ok(false)
+            }
+        }
+        let (bytes, sent) = {
+            let stream = &self.stream;
+            coalesce(&mut self.send_queue, MAX_COALESCED_WRITE_SIZE, |(message, _span)| stream.encode(message))
+        };
+        if sent.is_empty() {
+            self.stream.flush()?;
+            return Ok(false)
         }
+        self.stream.write_bytes(&bytes)?;
+        // The spans end here, after the messages are written
+        drop(sent);
+        Ok(!self.stream.has_unsent())
     }
 
     fn receive(&mut self) -> Result<Option<Message>> {
-        Ok(self.stream.read()?)
+        let message = self.stream.read()?;
+        if let Some(Message::Extension(extension_message)) = &message {
+            // The extensions never see the data which is forged on the way
+            if !extension_message.verify(self.stream.session(), self.cipher) {
+                return Err(Error::UnauthenticatedMessage)
+            }
+            // The replayed messages have the old sequence numbers
+            if let Some(sequence) = extension_message.sequence() {
+                if sequence < self.next_received_sequence {
+                    return Err(Error::UnauthenticatedMessage)
+                }
+                self.next_received_sequence = sequence + 1;
+            }
+        }
+        Ok(message)
     }
 
     fn remote_node_id(&self) -> Option<NodeId> {
         Some(self.remote_node_id.clone())
     }
 
+    fn remote_public(&self) -> Option<Public> {
+        Some(self.remote_public)
+    }
+
     fn session(&self) -> Option<Session> {
         Some(self.stream.session().clone())
     }
 
-    fn register<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.register(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.register(self.stream(), reg, self.interest(), PollOpt::edge())
     }
 
-    fn reregister<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
     }
 
-    fn deregister<Message>(&self, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.deregister(self.stream())
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(self.stream())
     }
 }
 
@@ -206,32 +419,65 @@ enum WaitState {
 struct WaitSyncConnection {
     stream: Stream,
     session: Option<Session>,
+    key_pair: KeyPair,
+    user_agent: String,
+    cipher_suites: Vec<CipherSuite>,
     remote_node_id: Option<NodeId>,
+    remote_public: Option<Public>,
+    remote_version: Option<Version>,
+    remote_user_agent: Option<String>,
+    remote_cipher_suites: Option<Vec<CipherSuite>>,
+    remote_frame_version: Option<Version>,
     state: WaitState,
 }
 
 impl WaitSyncConnection {
-    fn new(stream: Stream) -> Self {
+    fn new(stream: Stream, key_pair: KeyPair, user_agent: String, cipher_suites: Vec<CipherSuite>) -> Self {
         Self {
             stream,
             session: None,
+            key_pair,
+            user_agent,
+            cipher_suites,
             remote_node_id: None,
+            remote_public: None,
+            remote_version: None,
+            remote_user_agent: None,
+            remote_cipher_suites: None,
+            remote_frame_version: None,
             state: WaitState::Created,
         }
     }
 
-    fn ready_session(&mut self, remote_node_id: NodeId, session: Session) {
+    fn ready_session(&mut self, remote_node_id: NodeId, remote_public: Public, session: Session) {
         debug_assert_eq!(self.state, WaitState::Created);
         self.remote_node_id = Some(remote_node_id);
-        self.session = Some(session);
+        self.remote_public = Some(remote_public);
+        self.session = Some(session.with_role(Role::Responder));
         self.state = WaitState::Received;
     }
 
-    fn establish(self) -> EstablishedConnection {
-        debug_assert_eq!(self.state, WaitState::Sent);
+    // The ack must be sent
+    fn establish(self) -> result::Result<EstablishedConnection, (Self, Error)> {
+        if self.state != WaitState::Sent {
+            return Err((self, Error::UnreadySession))
+        }
         let session = self.session.as_ref().expect("Session must exist");
         let remote_node_id = self.remote_node_id.expect("Sync message set peer node id");
-        EstablishedConnection::new(SignedStream::new(self.stream, session.clone()), remote_node_id)
+        let remote_public = self.remote_public.expect("Sync message set peer public key");
+        let envelope_version = negotiate_envelope_version(self.remote_version);
+        let cipher = negotiate_cipher_suite(&self.cipher_suites, &self.remote_cipher_suites)
+            .expect("The cipher suites are checked when the sync message is received");
+        let remote_user_agent = self.remote_user_agent.unwrap_or_default();
+        let stream = SignedStream::new(self.stream, session.clone());
+        Ok(EstablishedConnection::new(
+            stream,
+            remote_node_id,
+            remote_public,
+            envelope_version,
+            remote_user_agent,
+            cipher,
+        ))
     }
 
     fn remote_addr(&self) -> Result<SocketAddr> {
@@ -243,41 +489,78 @@ impl WaitSyncConnection {
     }
 
     fn interest(&self) -> Ready {
-        match self.state {
+        let interest = match self.state {
             WaitState::Created => Ready::readable() | UnixReady::hup(),
             WaitState::Received => Ready::writable() | UnixReady::hup(),
             WaitState::Sent => Ready::empty() | UnixReady::hup(),
+        };
+        // The stream can hold the handshake which the socket didn't take
+        if self.stream.has_pending_write() {
+            interest | Ready::writable()
+        } else {
+            interest
         }
     }
 
     fn send(&mut self) -> Result<bool> {
         if self.state != WaitState::Received {
+            self.stream.flush().map_err(StreamError::from)?;
             return Ok(false)
         }
 
         let session = self.session.as_ref().expect("Session must exist");
-        let message = Message::Handshake(HandshakeMessage::ack());
+        let observed_address = self.remote_addr()?;
+        let ack = HandshakeMessage::ack(
+            &self.key_pair,
+            session,
+            observed_address,
+            self.user_agent.clone(),
+            self.cipher_suites.clone(),
+        );
+        let message = Message::Handshake(ack);
         let signed_message = SignedMessage::new(&message, session);
 
         self.stream.write(&signed_message)?;
+        self.stream.set_frame_version(negotiate_frame_version(self.remote_frame_version));
         self.state = WaitState::Sent;
         Ok(false)
     }
 
+    // The peer is waiting for the ack, so it hears the reason instead
+    fn disconnect(&mut self, reason: DisconnectReason) -> Result<bool> {
+        if self.state != WaitState::Received {
+            return Ok(false)
+        }
+        let session = self.session.as_ref().expect("Session must exist");
+        let message = Message::Disconnect(DisconnectMessage::disconnect(reason));
+        self.stream.write(&SignedMessage::new(&message, session))?;
+        self.stream.flush().map_err(StreamError::from)?;
+        Ok(true)
+    }
+
     fn receive(&mut self) -> Result<Option<SignedMessage>> {
         if self.state != WaitState::Created {
             return Ok(None)
         }
-        if let Some(signed_message) = self.stream.read::<SignedMessage>()? {
+        if let Some(signed_message) = self.stream.read_shared::<SignedMessage>()? {
             let message = {
                 let rlp = UntrustedRlp::new(&signed_message.message);
                 rlp.as_val::<Message>()?
             };
 
             match &message {
-                Message::Handshake(HandshakeMessage::Sync {
-                    ..
-                }) => Ok(Some(signed_message)),
+                Message::Handshake(
+                    sync @ HandshakeMessage::Sync {
+                        ..
+                    },
+                ) => {
+                    self.remote_version = Some(*sync.version());
+                    self.remote_user_agent = Some(sync.user_agent().clone());
+                    self.remote_cipher_suites = Some(sync.cipher_suites().to_vec());
+                    self.remote_frame_version = Some(*sync.frame_version());
+                    negotiate_cipher_suite(&self.cipher_suites, &self.remote_cipher_suites)?;
+                    Ok(Some(signed_message))
+                }
                 _ => Err(Error::UnreadySession),
             }
         } else {
@@ -289,48 +572,86 @@ impl WaitSyncConnection {
         self.remote_node_id.clone()
     }
 
-    fn register<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.register(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn remote_public(&self) -> Option<Public> {
+        self.remote_public
+    }
+
+    fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.register(self.stream(), reg, self.interest(), PollOpt::edge())
     }
 
-    fn reregister<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
     }
 
-    fn deregister<Message>(&self, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.deregister(self.stream())
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(self.stream())
     }
 }
 
 struct WaitAckConnection {
     stream: SignedStream,
     port: u16,
+    key_pair: KeyPair,
     local_node_id: NodeId,
+    user_agent: String,
+    cipher_suites: Vec<CipherSuite>,
     remote_node_id: NodeId,
+    remote_public: Option<Public>,
+    remote_version: Option<Version>,
+    remote_user_agent: Option<String>,
+    remote_cipher_suites: Option<Vec<CipherSuite>>,
+    remote_frame_version: Option<Version>,
     state: WaitState,
 }
 
 impl WaitAckConnection {
-    fn new(stream: Stream, session: Session, port: u16, local_node_id: NodeId, remote_node_id: NodeId) -> Self {
+    fn new(
+        stream: Stream,
+        session: Session,
+        port: u16,
+        key_pair: KeyPair,
+        local_node_id: NodeId,
+        remote_node_id: NodeId,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
+    ) -> Self {
         Self {
-            stream: SignedStream::new(stream, session),
+            stream: SignedStream::new(stream, session.with_role(Role::Initiator)),
             port,
+            key_pair,
             local_node_id,
+            user_agent,
+            cipher_suites,
             remote_node_id,
+            remote_public: None,
+            remote_version: None,
+            remote_user_agent: None,
+            remote_cipher_suites: None,
+            remote_frame_version: None,
             state: WaitState::Created,
         }
     }
 
-    fn establish(self) -> EstablishedConnection {
-        debug_assert_eq!(WaitState::Received, self.state);
+    // The ack must be received
+    fn establish(self) -> result::Result<EstablishedConnection, (Self, Error)> {
+        if self.state != WaitState::Received {
+            return Err((self, Error::UnreadySession))
+        }
         let remote_node_id = self.remote_node_id;
-        EstablishedConnection::new(self.stream, remote_node_id)
+        let remote_public = self.remote_public.expect("Ack message set peer public key");
+        let envelope_version = negotiate_envelope_version(self.remote_version);
+        let cipher = negotiate_cipher_suite(&self.cipher_suites, &self.remote_cipher_suites)
+            .expect("The cipher suites are checked when the ack message is received");
+        let remote_user_agent = self.remote_user_agent.unwrap_or_default();
+        Ok(EstablishedConnection::new(
+            self.stream,
+            remote_node_id,
+            remote_public,
+            envelope_version,
+            remote_user_agent,
+            cipher,
+        ))
     }
 
     fn stream(&self) -> &SignedStream {
@@ -338,33 +659,81 @@ impl WaitAckConnection {
     }
 
     fn interest(&self) -> Ready {
-        match self.state {
+        let interest = match self.state {
             WaitState::Created => Ready::writable() | UnixReady::hup(),
             WaitState::Sent => Ready::readable() | UnixReady::hup(),
             WaitState::Received => Ready::empty() | UnixReady::hup(),
+        };
+        if self.stream.has_pending_write() {
+            interest | Ready::writable()
+        } else {
+            interest
         }
     }
 
     fn send(&mut self) -> Result<bool> {
         if self.state != WaitState::Created {
+            self.stream.flush()?;
             return Ok(false)
         }
 
-        self.stream.write(&Message::Handshake(HandshakeMessage::sync(self.port, self.local_node_id.clone())))?;
+        let sync = HandshakeMessage::sync(
+            self.port,
+            self.local_node_id.clone(),
+            &self.key_pair,
+            self.stream.session(),
+            self.user_agent.clone(),
+            self.cipher_suites.clone(),
+        );
+        self.stream.write(&Message::Handshake(sync))?;
         self.state = WaitState::Sent;
         Ok(false)
     }
 
-    fn receive(&mut self) -> Result<Option<HandshakeMessage>> {
+    // The peer established the session when it sent the ack
+    fn disconnect(&mut self, reason: DisconnectReason) -> Result<bool> {
+        if self.state != WaitState::Received {
+            return Ok(false)
+        }
+        self.stream.write(&Message::Disconnect(DisconnectMessage::disconnect(reason)))?;
+        self.stream.flush()?;
+        Ok(true)
+    }
+
+    fn receive(&mut self) -> Result<Option<ReceivedMessage>> {
         if self.state != WaitState::Sent {
             return Ok(None)
         }
         if let Some(message) = self.stream.read()? {
             match message {
-                Message::Handshake(HandshakeMessage::Ack(version)) => {
+                Message::Handshake(ack) => {
+                    let observed_address = match &ack {
+                        HandshakeMessage::Ack {
+                            observed_address,
+                            ..
+                        } => observed_address.clone(),
+                        HandshakeMessage::Sync {
+                            ..
+                        } => return Err(Error::UnreadySession),
+                    };
+                    if !ack.is_authenticated(self.stream.session()) {
+                        return Err(Error::UnauthenticatedHandshake)
+                    }
+                    self.remote_public = Some(*ack.public());
+                    self.remote_version = Some(*ack.version());
+                    self.remote_user_agent = Some(ack.user_agent().clone());
+                    self.remote_cipher_suites = Some(ack.cipher_suites().to_vec());
+                    self.remote_frame_version = Some(*ack.frame_version());
+                    negotiate_cipher_suite(&self.cipher_suites, &self.remote_cipher_suites)?;
+                    self.stream.set_frame_version(negotiate_frame_version(self.remote_frame_version));
                     self.state = WaitState::Received;
-                    Ok(Some(HandshakeMessage::Ack(version)))
+                    Ok(Some(ReceivedMessage::Ack {
+                        version: *ack.version(),
+                        observed_address,
+                    }))
                 }
+                // The peer rejected this node before the ack
+                Message::Disconnect(message) => Ok(Some(ReceivedMessage::Disconnect(message.reason()))),
                 _ => Err(Error::UnreadySession),
             }
         } else {
@@ -376,42 +745,31 @@ impl WaitAckConnection {
         Some(self.remote_node_id.clone())
     }
 
-    fn register<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.register(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn remote_public(&self) -> Option<Public> {
+        self.remote_public
     }
 
-    fn reregister<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
+    fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.register(self.stream(), reg, self.interest(), PollOpt::edge())
     }
 
-    fn deregister<Message>(&self, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        event_loop.deregister(self.stream())
+    fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        poll.reregister(self.stream(), reg, self.interest(), PollOpt::edge())
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub enum ConnectionType {
-    None,
-    AckWaiting,
-    SyncWaiting,
-    Established,
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(self.stream())
+    }
 }
 
 enum State {
     WaitSync(WaitSyncConnection),
     WaitAck(WaitAckConnection),
     Established(EstablishedConnection),
-    Intermediate, // An intermediate state before established
 }
 
 pub struct Connection {
-    state: Mutex<Cell<State>>,
+    state: Mutex<State>,
 }
 
 impl Connection {
@@ -419,237 +777,342 @@ impl Connection {
         stream: Stream,
         session: Session,
         local_port: u16,
+        key_pair: KeyPair,
         local_node_id: NodeId,
         remote_node_id: NodeId,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
     ) -> Self {
-        let connection = WaitAckConnection::new(stream, session, local_port, local_node_id, remote_node_id);
+        let connection = WaitAckConnection::new(
+            stream,
+            session,
+            local_port,
+            key_pair,
+            local_node_id,
+            remote_node_id,
+            user_agent,
+            cipher_suites,
+        );
         Self {
-            state: Mutex::new(Cell::new(State::WaitAck(connection))),
+            state: Mutex::new(State::WaitAck(connection)),
         }
     }
 
-    pub fn accept(stream: Stream) -> Self {
-        let connection = WaitSyncConnection::new(stream);
+    pub fn accept(stream: Stream, key_pair: KeyPair, user_agent: String, cipher_suites: Vec<CipherSuite>) -> Self {
+        let connection = WaitSyncConnection::new(stream, key_pair, user_agent, cipher_suites);
         Self {
-            state: Mutex::new(Cell::new(State::WaitSync(connection))),
+            state: Mutex::new(State::WaitSync(connection)),
         }
     }
 
-    pub fn establish(&self) -> bool {
-        let state = self.state.lock();
-        let old_state = state.replace(State::Intermediate);
-        match old_state {
+    /// Moves to the established state once the handshake is done.
+    /// The connection is given back unchanged along with the error if it cannot be established.
+    pub fn establish(self) -> result::Result<Self, (Self, Error)> {
+        let state = match self.state.into_inner() {
             State::WaitAck(connection) => {
-                state.replace(State::Established(connection.establish()));
-                true
+                connection.establish().map_err(|(connection, err)| (State::WaitAck(connection), err))
             }
             State::WaitSync(connection) => {
-                state.replace(State::Established(connection.establish()));
-                true
+                connection.establish().map_err(|(connection, err)| (State::WaitSync(connection), err))
             }
-            State::Established(_) => false,
-            _ => unreachable!(),
+            State::Established(connection) => Err((State::Established(connection), Error::AlreadyEstablished)),
+        };
+        match state {
+            Ok(connection) => Ok(Self {
+                state: Mutex::new(State::Established(connection)),
+            }),
+            Err((state, err)) => Err((
+                Self {
+                    state: Mutex::new(state),
+                },
+                err,
+            )),
         }
     }
 
-    pub fn register<Message>(
-        &self,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
+    pub fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
         let mut state = self.state.lock();
-        match state.get_mut() {
-            State::WaitAck(connection) => {
-                connection.register(reg, event_loop)?;
-                Ok(ConnectionType::AckWaiting)
-            }
-            State::WaitSync(connection) => {
-                connection.register(reg, event_loop)?;
-                Ok(ConnectionType::SyncWaiting)
-            }
-            State::Established(connection) => {
-                connection.register(reg, event_loop)?;
-                Ok(ConnectionType::Established)
-            }
-            _ => unreachable!(),
+        match &mut *state {
+            State::WaitAck(connection) => connection.register(reg, poll),
+            State::WaitSync(connection) => connection.register(reg, poll),
+            State::Established(connection) => connection.register(reg, poll),
         }
     }
 
-    pub fn reregister<Message>(
-        &self,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
+    pub fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
         let mut state = self.state.lock();
-        match state.get_mut() {
-            State::WaitAck(connection) => {
-                connection.reregister(reg, event_loop)?;
-                Ok(ConnectionType::AckWaiting)
-            }
-            State::WaitSync(connection) => {
-                connection.reregister(reg, event_loop)?;
-                Ok(ConnectionType::SyncWaiting)
-            }
-            State::Established(connection) => {
-                connection.reregister(reg, event_loop)?;
-                Ok(ConnectionType::Established)
-            }
-            _ => unreachable!(),
+        match &mut *state {
+            State::WaitAck(connection) => connection.reregister(reg, poll),
+            State::WaitSync(connection) => connection.reregister(reg, poll),
+            State::Established(connection) => connection.reregister(reg, poll),
         }
     }
 
-    pub fn deregister<Message>(&self, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
+    pub fn deregister(&self, poll: &Poll) -> io::Result<()> {
         let mut state = self.state.lock();
-        match state.get_mut() {
-            State::WaitAck(connection) => {
-                connection.deregister(event_loop)?;
-                Ok(ConnectionType::AckWaiting)
-            }
-            State::WaitSync(connection) => {
-                connection.deregister(event_loop)?;
-                Ok(ConnectionType::SyncWaiting)
-            }
-            State::Established(connection) => {
-                connection.deregister(event_loop)?;
-                Ok(ConnectionType::Established)
-            }
-            _ => unreachable!(),
+        match &mut *state {
+            State::WaitAck(connection) => connection.deregister(poll),
+            State::WaitSync(connection) => connection.deregister(poll),
+            State::Established(connection) => connection.deregister(poll),
         }
     }
 
-    pub fn send(&self) -> Result<(ConnectionType, bool)> {
+    // Return true if the queue is not empty
+    pub fn send(&self) -> Result<bool> {
         let mut state = self.state.lock();
-        match state.get_mut() {
-            State::WaitAck(connection) => {
-                let remain = connection.send()?;
-                Ok((ConnectionType::AckWaiting, remain))
-            }
-            State::WaitSync(connection) => {
-                let remain = connection.send()?;
-                Ok((ConnectionType::SyncWaiting, remain))
-            }
-            State::Established(connection) => {
-                let remain = connection.send()?;
-                Ok((ConnectionType::Established, remain))
-            }
-            _ => unreachable!(),
+        match &mut *state {
+            State::WaitAck(connection) => connection.send(),
+            State::WaitSync(connection) => connection.send(),
+            State::Established(connection) => connection.send(),
         }
     }
 
     pub fn receive(&self) -> Result<Option<ReceivedMessage>> {
         let mut state = self.state.lock();
-        match state.get_mut() {
-            State::WaitAck(connection) => Ok(connection.receive()?.map(|message| match message {
-                HandshakeMessage::Ack(version) => ReceivedMessage::Ack {
-                    version,
-                },
-                _ => unreachable!(),
-            })),
+        match &mut *state {
+            State::WaitAck(connection) => connection.receive(),
             State::WaitSync(connection) => Ok(connection.receive()?.map(ReceivedMessage::Sync)),
-            State::Established(connection) => Ok(connection.receive()?.map(|message| match message {
-                Message::Negotiation(msg) => ReceivedMessage::Negotiation(msg),
-                Message::Extension(msg) => ReceivedMessage::Extension(msg),
-                _ => unreachable!(),
-            })),
-            _ => unreachable!(),
+            State::Established(connection) => match connection.receive()? {
+                Some(Message::Negotiation(msg)) => Ok(Some(ReceivedMessage::Negotiation(msg))),
+                Some(Message::Extension(msg)) => Ok(Some(ReceivedMessage::Extension(msg))),
+                Some(Message::Relay(msg)) => Ok(Some(ReceivedMessage::Relay(msg))),
+                Some(Message::Disconnect(msg)) => Ok(Some(ReceivedMessage::Disconnect(msg.reason()))),
+                // The peer can send anything, even after the handshake is done
+                Some(Message::Handshake(_)) => Err(Error::UnexpectedHandshake),
+                None => Ok(None),
+            },
         }
     }
 
-    pub fn ready_session(&self, remote_node_id: NodeId, session: Session) -> bool {
+    pub fn ready_session(&self, remote_node_id: NodeId, remote_public: Public, session: Session) -> bool {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => false,
             State::WaitSync(connection) => {
-                connection.ready_session(remote_node_id, session);
+                connection.ready_session(remote_node_id, remote_public, session);
                 true
             }
             State::Established(_) => false,
-            _ => unreachable!(),
         }
     }
 
     pub fn enqueue_negotiation_request(&self, name: String, version: u64) -> bool {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => false,
             State::WaitSync(_) => false,
             State::Established(connection) => {
                 connection.enqueue_negotiation_request(name, version);
                 true
             }
-            _ => unreachable!(),
         }
     }
 
     pub fn enqueue_negotiation_allowed(&self, seq: u64) -> bool {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => false,
             State::WaitSync(_) => false,
             State::Established(connection) => {
                 connection.enqueue_negotiation_allowed(seq);
                 true
             }
-            _ => unreachable!(),
         }
     }
 
-    pub fn enqueue_extension_message(&self, extension_name: &String, need_encryption: bool, data: &[u8]) -> bool {
+    pub fn enqueue_negotiation_denied(&self, seq: u64) -> bool {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => false,
+            State::WaitSync(_) => false,
+            State::Established(connection) => {
+                connection.enqueue_negotiation_denied(seq);
+                true
+            }
+        }
+    }
+
+    pub fn enqueue_extension_message(
+        &self,
+        extension_name: &String,
+        need_encryption: bool,
+        data: Bytes,
+        span: Option<Span>,
+    ) -> result::Result<(), DropReason> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => Err(DropReason::NotConnected),
+            State::WaitSync(_) => Err(DropReason::NotConnected),
+            State::Established(connection) => {
+                connection.enqueue_extension_message(extension_name.clone(), need_encryption, data, span)
+            }
+        }
+    }
+
+    pub fn enqueue_relay_message(&self, message: RelayMessage) -> bool {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => false,
             State::WaitSync(_) => false,
             State::Established(connection) => {
-                connection.enqueue_extension_message(extension_name.clone(), need_encryption, &data);
+                connection.enqueue_relay_message(message);
                 true
             }
-            _ => unreachable!(),
         }
     }
 
-    pub fn remove_requested_negotiation(&self, seq: &u64) -> Option<String> {
+    // Return false if the handshake is not done yet
+    pub fn disconnect(&self, reason: DisconnectReason) -> Result<bool> {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
+            State::WaitAck(connection) => connection.disconnect(reason),
+            State::WaitSync(connection) => connection.disconnect(reason),
+            State::Established(connection) => {
+                connection.disconnect(reason)?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn remove_requested_negotiation(&self, seq: &u64) -> Option<(String, Version)> {
+        let mut state = self.state.lock();
+        match &mut *state {
             State::WaitAck(_) => None,
             State::WaitSync(_) => None,
             State::Established(connection) => connection.remove_requested_negotiation(seq),
-            _ => unreachable!(),
+        }
+    }
+
+    pub fn expire_negotiations(&self, timeout: Duration, max_retries: usize, now: Instant) -> ExpiredNegotiations {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => ExpiredNegotiations::default(),
+            State::WaitSync(_) => ExpiredNegotiations::default(),
+            State::Established(connection) => connection.expire_negotiations(timeout, max_retries, now),
+        }
+    }
+
+    pub fn is_ack_sent(&self) -> bool {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitSync(connection) => connection.state == WaitState::Sent,
+            _ => false,
         }
     }
 
     pub fn remote_addr_of_waiting_sync(&self) -> Option<SocketAddr> {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => None,
             State::WaitSync(connection) => connection.remote_addr().ok(),
             State::Established(_) => None,
-            _ => unreachable!(),
         }
     }
 
     pub fn remote_node_id(&self) -> Option<NodeId> {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(connection) => connection.remote_node_id(),
             State::WaitSync(connection) => connection.remote_node_id(),
             State::Established(connection) => connection.remote_node_id(),
-            _ => unreachable!(),
+        }
+    }
+
+    pub fn remote_public(&self) -> Option<Public> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(connection) => connection.remote_public(),
+            State::WaitSync(connection) => connection.remote_public(),
+            State::Established(connection) => connection.remote_public(),
         }
     }
 
     pub fn established_session(&self) -> Option<Session> {
         let mut state = self.state.lock();
-        match state.get_mut() {
+        match &mut *state {
             State::WaitAck(_) => None,
             State::WaitSync(_) => None,
             State::Established(connection) => connection.session(),
-            _ => unreachable!(),
+        }
+    }
+
+    pub fn remote_user_agent(&self) -> Option<String> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => None,
+            State::WaitSync(_) => None,
+            State::Established(connection) => Some(connection.remote_user_agent.clone()),
+        }
+    }
+
+    pub fn envelope_version(&self) -> Option<Version> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => None,
+            State::WaitSync(_) => None,
+            State::Established(connection) => Some(connection.envelope_version),
+        }
+    }
+
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => None,
+            State::WaitSync(_) => None,
+            State::Established(connection) => Some(connection.cipher),
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => None,
+            State::WaitSync(_) => None,
+            State::Established(connection) => connection.rtt,
+        }
+    }
+
+    /// The number of the messages waiting to be sent
+    pub fn send_queue_len(&self) -> usize {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => 0,
+            State::WaitSync(_) => 0,
+            State::Established(connection) => connection.send_queue.len(),
+        }
+    }
+
+    /// The extensions whose negotiation requests are not answered yet
+    pub fn requested_negotiations(&self) -> Vec<String> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(_) => vec![],
+            State::WaitSync(_) => vec![],
+            State::Established(connection) => {
+                let mut names: Vec<_> =
+                    connection.requested_negotiation.values().map(|requested| requested.name.clone()).collect();
+                names.sort();
+                names
+            }
+        }
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(connection) => connection.stream().peer_addr().ok(),
+            State::WaitSync(connection) => connection.stream().peer_addr().ok(),
+            State::Established(connection) => connection.stream().peer_addr().ok(),
+        }
+    }
+
+    /// The bytes received from and sent to the peer since the connection was opened
+    pub fn traffic(&self) -> (u64, u64) {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::WaitAck(connection) => connection.stream().traffic(),
+            State::WaitSync(connection) => connection.stream().traffic(),
+            State::Established(connection) => connection.stream().traffic(),
         }
     }
 }
@@ -657,8 +1120,458 @@ impl Connection {
 pub enum ReceivedMessage {
     Ack {
         version: u64,
+        observed_address: SocketAddr,
     },
     Sync(SignedMessage),
     Extension(ExtensionMessage),
     Negotiation(NegotiationMessage),
+    Relay(RelayMessage),
+    Disconnect(DisconnectReason),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::net;
+
+    use ckeys::{Generator, Random};
+    use ctypes::Secret;
+    use rlp::Encodable;
+
+    use super::super::stream::seal;
+    use super::super::transport::MemoryStream;
+    use super::*;
+
+    // Counts the write calls, each of which is a syscall on a socket
+    struct CountingWriter {
+        writes: usize,
+        written: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_all_coalesced(queue: &mut VecDeque<Vec<u8>>, limit: usize, writer: &mut CountingWriter) {
+        loop {
+            let (bytes, items) = coalesce(queue, limit, |item| item.clone());
+            if items.is_empty() {
+                break
+            }
+            writer.write_all(&bytes).unwrap();
+        }
+    }
+
+    #[test]
+    fn small_messages_are_written_at_once() {
+        let mut queue: VecDeque<_> = (0..100).map(|_| vec![0u8; 100]).collect();
+        let mut writer = CountingWriter {
+            writes: 0,
+            written: 0,
+        };
+        write_all_coalesced(&mut queue, MAX_COALESCED_WRITE_SIZE, &mut writer);
+        assert_eq!(1, writer.writes);
+        assert_eq!(100 * 100, writer.written);
+    }
+
+    #[test]
+    fn writes_are_split_at_the_limit() {
+        let mut queue: VecDeque<_> = (0..10).map(|_| vec![0u8; 100]).collect();
+        let mut writer = CountingWriter {
+            writes: 0,
+            written: 0,
+        };
+        write_all_coalesced(&mut queue, 300, &mut writer);
+        // 3 + 3 + 3 + 1 messages
+        assert_eq!(4, writer.writes);
+        assert_eq!(10 * 100, writer.written);
+    }
+
+    #[test]
+    fn a_message_larger_than_the_limit_is_written_alone() {
+        let mut queue: VecDeque<_> = vec![vec![0u8; 500], vec![0u8; 10]].into_iter().collect();
+        let (bytes, items) = coalesce(&mut queue, 300, |item| item.clone());
+        assert_eq!(500, bytes.len());
+        assert_eq!(1, items.len());
+        assert_eq!(1, queue.len());
+    }
+
+    fn address(port: u16) -> net::SocketAddr {
+        net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    fn established(connection: Connection) -> Connection {
+        match connection.establish() {
+            Ok(connection) => connection,
+            Err((_, err)) => panic!("Cannot establish the connection: {}", err),
+        }
+    }
+
+    #[test]
+    fn only_the_connection_which_finished_the_handshake_is_established() {
+        let (stream, _remote, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(stream), key_pair, String::new(), CipherSuite::supported(true));
+
+        let connection = match connection.establish() {
+            Err((connection, Error::UnreadySession)) => connection,
+            _ => panic!("The ack is not sent yet"),
+        };
+        assert!(connection.ready_session(NodeId::random(), public, session));
+        connection.send().unwrap();
+        let connection = established(connection);
+        // The sync message is skipped, so the peer is taken as an older node which uses the legacy cipher
+        assert_eq!(Some(CipherSuite::LegacyAesCbc), connection.cipher_suite());
+        match connection.establish() {
+            Err((connection, Error::AlreadyEstablished)) => assert!(connection.envelope_version().is_some()),
+            _ => panic!("The connection is already established"),
+        }
+    }
+
+    #[test]
+    fn handshake_and_negotiate_in_memory() {
+        let (stream_a, stream_b, link) = MemoryStream::pair(address(3485), address(3486), 1);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair_a = Random.generate().unwrap();
+        let key_pair_b = Random.generate().unwrap();
+        let public_a = *key_pair_a.public();
+        let public_b = *key_pair_b.public();
+        let node_id_a = NodeId::random();
+        let node_id_b = NodeId::random();
+
+        let a = Connection::connect(
+            Stream::from(stream_a),
+            session.clone(),
+            3485,
+            key_pair_a,
+            node_id_a,
+            node_id_b,
+            "a".to_string(),
+            CipherSuite::supported(false),
+        );
+        let b = Connection::accept(Stream::from(stream_b), key_pair_b, "b".to_string(), CipherSuite::supported(true));
+
+        assert!(!a.send().unwrap());
+        assert!(b.receive().unwrap().is_none(), "The sync message is in flight");
+        link.advance(1);
+        match b.receive().unwrap() {
+            Some(ReceivedMessage::Sync(_)) => {}
+            _ => panic!("Sync expected"),
+        }
+
+        assert!(b.ready_session(node_id_a, public_a, session));
+        assert!(!b.send().unwrap());
+        assert!(b.is_ack_sent());
+        let b = established(b);
+
+        link.advance(1);
+        match a.receive().unwrap() {
+            Some(ReceivedMessage::Ack {
+                ..
+            }) => {}
+            _ => panic!("Ack expected"),
+        }
+        let a = established(a);
+        assert_eq!(Some(public_b), a.remote_public());
+        assert_eq!(Some("b".to_string()), a.remote_user_agent());
+        assert_eq!(Some("a".to_string()), b.remote_user_agent());
+        assert_eq!(Some(CipherSuite::Aes256Gcm), a.cipher_suite());
+        assert_eq!(Some(CipherSuite::Aes256Gcm), b.cipher_suite());
+
+        assert!(a.enqueue_negotiation_request("ext".to_string(), 0));
+        a.send().unwrap();
+        link.advance(1);
+        match b.receive().unwrap() {
+            Some(ReceivedMessage::Negotiation(_)) => {}
+            _ => panic!("Negotiation expected"),
+        }
+    }
+
+    #[test]
+    fn the_disconnect_reason_reaches_the_peer() {
+        let (stream_a, stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair_a = Random.generate().unwrap();
+        let key_pair_b = Random.generate().unwrap();
+        let public_a = *key_pair_a.public();
+        let node_id_a = NodeId::random();
+        let node_id_b = NodeId::random();
+
+        let a = Connection::connect(
+            Stream::from(stream_a),
+            session.clone(),
+            3485,
+            key_pair_a,
+            node_id_a,
+            node_id_b,
+            String::new(),
+            CipherSuite::supported(false),
+        );
+        let b = Connection::accept(Stream::from(stream_b), key_pair_b, String::new(), CipherSuite::supported(false));
+        assert!(!b.disconnect(DisconnectReason::Requested).unwrap(), "The handshake is not done yet");
+
+        a.send().unwrap();
+        assert!(b.receive().unwrap().is_some());
+        assert!(b.ready_session(node_id_a, public_a, session));
+        b.send().unwrap();
+        let b = established(b);
+        assert!(a.receive().unwrap().is_some());
+        let a = established(a);
+
+        assert!(a.disconnect(DisconnectReason::IdleTimeout).unwrap());
+        match b.receive().unwrap() {
+            Some(ReceivedMessage::Disconnect(reason)) => assert_eq!(DisconnectReason::IdleTimeout, reason),
+            _ => panic!("Disconnect expected"),
+        }
+    }
+
+    #[test]
+    fn an_unreplied_negotiation_is_retried_and_then_fails() {
+        let (stream_a, _stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(stream_a), key_pair, String::new(), CipherSuite::supported(true));
+        assert!(connection.ready_session(NodeId::random(), public, session));
+        connection.send().unwrap();
+        let connection = established(connection);
+
+        let timeout = Duration::from_secs(5);
+        assert!(connection.enqueue_negotiation_request("ext".to_string(), 0));
+        let now = Instant::now();
+        assert_eq!(ExpiredNegotiations::default(), connection.expire_negotiations(timeout, 2, now));
+
+        let expired = ExpiredNegotiations {
+            retried: 1,
+            failed: vec![],
+        };
+        assert_eq!(expired, connection.expire_negotiations(timeout, 2, now + timeout));
+        assert_eq!(expired, connection.expire_negotiations(timeout, 2, now + timeout * 2));
+        let expired = ExpiredNegotiations {
+            retried: 0,
+            failed: vec!["ext".to_string()],
+        };
+        assert_eq!(expired, connection.expire_negotiations(timeout, 2, now + timeout * 3));
+        assert_eq!(ExpiredNegotiations::default(), connection.expire_negotiations(timeout, 2, now + timeout * 4));
+    }
+
+    #[test]
+    fn a_replied_negotiation_does_not_expire() {
+        let (stream_a, _stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(stream_a), key_pair, String::new(), CipherSuite::supported(true));
+        assert!(connection.ready_session(NodeId::random(), public, session));
+        connection.send().unwrap();
+        let connection = established(connection);
+
+        let timeout = Duration::from_secs(5);
+        assert!(connection.enqueue_negotiation_request("ext".to_string(), 0));
+        let now = Instant::now();
+        assert_eq!(Some(("ext".to_string(), 0)), connection.remove_requested_negotiation(&0));
+        assert_eq!(ExpiredNegotiations::default(), connection.expire_negotiations(timeout, 2, now + timeout));
+    }
+
+    #[test]
+    fn a_replied_negotiation_measures_the_rtt() {
+        let (stream_a, _stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(stream_a), key_pair, String::new(), CipherSuite::supported(true));
+        assert!(connection.ready_session(NodeId::random(), public, session));
+        connection.send().unwrap();
+        let connection = established(connection);
+        assert_eq!(None, connection.rtt());
+
+        assert!(connection.enqueue_negotiation_request("ext".to_string(), 3));
+        assert_eq!(None, connection.rtt());
+        assert_eq!(Some(("ext".to_string(), 3)), connection.remove_requested_negotiation(&0));
+        assert!(connection.rtt().is_some());
+    }
+
+    #[test]
+    fn a_handshake_after_the_establishment_is_an_error() {
+        let (mut remote, local, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(local), key_pair.clone(), String::new(), CipherSuite::supported(true));
+        assert!(connection.ready_session(NodeId::random(), public, session.clone()));
+        connection.send().unwrap();
+        let connection = established(connection);
+
+        let sync = HandshakeMessage::sync(3485, NodeId::random(), &key_pair, &session, String::new(), vec![]);
+        let frame = SignedMessage::new(&Message::Handshake(sync), &session).rlp_bytes().into_vec();
+        // The connection didn't hear the frame version of the remote
+        remote.write_all(&seal(LEGACY_FRAME_VERSION, frame)).unwrap();
+        match connection.receive() {
+            Err(Error::UnexpectedHandshake) => {}
+            _ => panic!("UnexpectedHandshake expected"),
+        }
+    }
+
+    #[test]
+    fn a_peer_without_the_common_cipher_suite_is_rejected() {
+        let (stream_a, stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let legacy = Connection::connect(
+            Stream::from(stream_a),
+            session,
+            3485,
+            Random.generate().unwrap(),
+            NodeId::random(),
+            NodeId::random(),
+            String::new(),
+            vec![CipherSuite::LegacyAesCbc],
+        );
+        let b = Connection::accept(
+            Stream::from(stream_b),
+            Random.generate().unwrap(),
+            String::new(),
+            CipherSuite::supported(false),
+        );
+
+        legacy.send().unwrap();
+        match b.receive() {
+            Err(Error::NoCommonCipherSuite) => {}
+            _ => panic!("NoCommonCipherSuite expected"),
+        }
+    }
+
+    #[test]
+    fn a_forged_plain_message_is_rejected() {
+        let (mut remote, local, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let connection =
+            Connection::accept(Stream::from(local), key_pair.clone(), String::new(), CipherSuite::supported(false));
+        let write = |remote: &mut MemoryStream, frame_version: Version, message: Message| {
+            let frame = SignedMessage::new(&message, &session).rlp_bytes().into_vec();
+            remote.write_all(&seal(frame_version, frame)).unwrap();
+        };
+
+        let suites = CipherSuite::supported(false);
+        let sync = HandshakeMessage::sync(3485, NodeId::random(), &key_pair, &session, String::new(), suites);
+        write(&mut remote, LEGACY_FRAME_VERSION, Message::Handshake(sync));
+        assert!(connection.receive().unwrap().is_some());
+        assert!(connection.ready_session(NodeId::random(), public, session.clone()));
+        connection.send().unwrap();
+        let connection = established(connection);
+        assert_eq!(Some(CipherSuite::Aes256Gcm), connection.cipher_suite());
+
+        let data = Bytes::from(&b"data"[..]);
+        let authenticated = ExtensionMessage::authenticated("ext".to_string(), 0, data.clone(), 0, &session);
+        write(&mut remote, FRAME_VERSION, Message::Extension(authenticated));
+        match connection.receive() {
+            Ok(Some(ReceivedMessage::Extension(_))) => {}
+            _ => panic!("Extension expected"),
+        }
+
+        let replayed = ExtensionMessage::authenticated("ext".to_string(), 0, data.clone(), 0, &session);
+        write(&mut remote, FRAME_VERSION, Message::Extension(replayed));
+        match connection.receive() {
+            Err(Error::UnauthenticatedMessage) => {}
+            _ => panic!("UnauthenticatedMessage expected"),
+        }
+
+        let another_session = Session::new_with_zero_nonce(Secret::random());
+        let forged = ExtensionMessage::authenticated("ext".to_string(), 0, data.clone(), 1, &another_session);
+        write(&mut remote, FRAME_VERSION, Message::Extension(forged));
+        match connection.receive() {
+            Err(Error::UnauthenticatedMessage) => {}
+            _ => panic!("UnauthenticatedMessage expected"),
+        }
+
+        let unencrypted = ExtensionMessage::unencrypted("ext".to_string(), 0, data);
+        write(&mut remote, FRAME_VERSION, Message::Extension(unencrypted));
+        match connection.receive() {
+            Err(Error::UnauthenticatedMessage) => {}
+            _ => panic!("UnauthenticatedMessage expected"),
+        }
+    }
+
+    #[test]
+    fn the_peer_hears_the_rejection_before_the_ack() {
+        let (stream_a, stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair_a = Random.generate().unwrap();
+        let public_a = *key_pair_a.public();
+        let node_id_a = NodeId::random();
+        let suites = CipherSuite::supported(false);
+
+        let a = Connection::connect(
+            Stream::from(stream_a),
+            session.clone(),
+            3485,
+            key_pair_a,
+            node_id_a,
+            NodeId::random(),
+            String::new(),
+            suites.clone(),
+        );
+        let b = Connection::accept(Stream::from(stream_b), Random.generate().unwrap(), String::new(), suites);
+
+        a.send().unwrap();
+        assert!(b.receive().unwrap().is_some());
+        assert!(b.ready_session(node_id_a, public_a, session));
+        assert!(b.disconnect(DisconnectReason::NotAllowed).unwrap());
+        match a.receive().unwrap() {
+            Some(ReceivedMessage::Disconnect(reason)) => assert_eq!(DisconnectReason::NotAllowed, reason),
+            _ => panic!("Disconnect expected"),
+        }
+    }
+
+    #[test]
+    fn the_peer_hears_the_rejection_after_the_ack() {
+        let (stream_a, stream_b, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let key_pair_a = Random.generate().unwrap();
+        let public_a = *key_pair_a.public();
+        let node_id_a = NodeId::random();
+        let suites = CipherSuite::supported(false);
+
+        let a = Connection::connect(
+            Stream::from(stream_a),
+            session.clone(),
+            3485,
+            key_pair_a,
+            node_id_a,
+            NodeId::random(),
+            String::new(),
+            suites.clone(),
+        );
+        let b = Connection::accept(Stream::from(stream_b), Random.generate().unwrap(), String::new(), suites);
+
+        assert!(!a.disconnect(DisconnectReason::NotAllowed).unwrap(), "The ack is not received yet");
+        a.send().unwrap();
+        assert!(b.receive().unwrap().is_some());
+        assert!(b.ready_session(node_id_a, public_a, session));
+        b.send().unwrap();
+        let b = established(b);
+        assert!(a.receive().unwrap().is_some());
+
+        assert!(a.disconnect(DisconnectReason::NotAllowed).unwrap());
+        match b.receive().unwrap() {
+            Some(ReceivedMessage::Disconnect(reason)) => assert_eq!(DisconnectReason::NotAllowed, reason),
+            _ => panic!("Disconnect expected"),
+        }
+    }
 }
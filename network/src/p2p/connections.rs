@@ -14,42 +14,169 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
+use std::time::{Duration, Instant};
 
-use cio::{IoManager, StreamToken};
-use mio::deprecated::EventLoop;
-use mio::Token;
-use parking_lot::RwLock;
+use bytes::Bytes;
+use cio::StreamToken;
+use ckeys::{KeyPair, Public};
+use mio::{Poll, Token};
+use parking_lot::{Mutex, RwLock};
 
-use super::super::session::Session;
+use super::super::session::{CipherSuite, Session};
+use super::super::trace::Span;
 use super::super::{NodeId, SocketAddr};
 use super::connection::{Connection, Result};
+use super::drops::{DropCounts, DropReason};
+use super::message::{DisconnectReason, Version};
+use super::peer::{PeerEvent, PeerState};
+use super::rate_limit::{Admission, RateLimit, RateLimiter};
 use super::stream::Stream;
+use super::RelayMessage;
 
-pub use super::connection::{ConnectionType, ReceivedMessage};
+pub use super::connection::{ExpiredNegotiations, ReceivedMessage};
+
+/// Which side dialed the connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A connection in the table, which may not be established yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionInfo {
+    pub token: StreamToken,
+    pub node_id: Option<NodeId>,
+    pub remote_address: Option<SocketAddr>,
+    pub direction: Direction,
+    /// The time since the connection was opened
+    pub age: Duration,
+    /// The extensions which either side allowed, with the negotiated versions
+    pub extensions: BTreeMap<String, Version>,
+    /// The software of the peer, which is known after the handshake
+    pub user_agent: Option<String>,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub rtt: Option<Duration>,
+}
+
+/// The internal state of a connection, for the diagnosis
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionDump {
+    pub token: StreamToken,
+    pub node_id: Option<NodeId>,
+    pub state: PeerState,
+    /// The messages waiting to be sent
+    pub send_queue: usize,
+    /// The extensions which are requested to the peer and not answered yet
+    pub requested_negotiations: Vec<String>,
+    pub extensions: BTreeMap<String, Version>,
+    /// The time since the last extension message
+    pub idle: Duration,
+}
+
+struct Peer {
+    state: PeerState,
+    direction: Direction,
+    connected_at: Instant,
+    connection: Connection,
+    // The negotiated extensions and their versions
+    extensions: Mutex<BTreeMap<String, Version>>,
+    // The last time when an extension message was received. Sending doesn't count, since a peer which only
+    // receives the broadcasts would look active.
+    last_traffic: Mutex<Instant>,
+    // Limits the inbound extension messages
+    rate_limiter: Mutex<RateLimiter>,
+    // The messages from and to the peer which are dropped
+    drops: Mutex<DropCounts>,
+}
+
+impl Peer {
+    fn new(state: PeerState, connection: Connection, rate_limit: RateLimit, now: Instant) -> Self {
+        let direction = if state == PeerState::Connecting {
+            Direction::Outbound
+        } else {
+            Direction::Inbound
+        };
+        Self {
+            state,
+            direction,
+            connected_at: now,
+            connection,
+            extensions: Mutex::new(BTreeMap::new()),
+            last_traffic: Mutex::new(now),
+            rate_limiter: Mutex::new(RateLimiter::new(rate_limit, now)),
+            drops: Mutex::new(DropCounts::default()),
+        }
+    }
+
+    fn touch(&self, now: Instant) {
+        *self.last_traffic.lock() = now;
+    }
+
+    fn is_idle(&self, idle_timeout: Duration, now: Instant) -> bool {
+        let last_traffic = *self.last_traffic.lock();
+        last_traffic <= now && now.duration_since(last_traffic) >= idle_timeout
+    }
+
+    fn transit(&mut self, event: PeerEvent) -> bool {
+        match self.state.next(event) {
+            Some(next) => {
+                self.state = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
 
 pub struct Connections {
-    // stream token => established connection
-    connections: RwLock<HashMap<StreamToken, Connection>>,
+    // stream token => peer
+    peers: RwLock<HashMap<StreamToken, Peer>>,
 
+    // The index of the peers whose node ids are known
     connected_nodes: RwLock<HashMap<NodeId, StreamToken>>,
-    reversed_connected_nodes: RwLock<HashMap<StreamToken, NodeId>>,
+
+    rate_limit: RateLimit,
+    // Sent to the peers in the handshake
+    user_agent: String,
+    cipher_suites: Vec<CipherSuite>,
+    // Tells the time of the traffic. The tests replace it to move the time without sleeping.
+    clock: Box<Fn() -> Instant + Send + Sync>,
 }
 
 impl Connections {
-    pub fn new() -> Self {
+    pub fn new(rate_limit: RateLimit, user_agent: String, cipher_suites: Vec<CipherSuite>) -> Self {
         Self {
-            connections: RwLock::new(HashMap::new()),
+            peers: RwLock::new(HashMap::new()),
 
             connected_nodes: RwLock::new(HashMap::new()),
-            reversed_connected_nodes: RwLock::new(HashMap::new()),
+
+            rate_limit,
+            user_agent,
+            cipher_suites,
+            clock: Box::new(Instant::now),
         }
     }
 
-    pub fn accept(&self, token: StreamToken, stream: Stream) {
-        let mut connections = self.connections.write();
-        let t = connections.insert(token, Connection::accept(stream));
+    #[cfg(test)]
+    fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> Instant + Send + Sync + 'static, {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn now(&self) -> Instant {
+        (self.clock)()
+    }
+
+    pub fn accept(&self, token: StreamToken, stream: Stream, key_pair: KeyPair) {
+        let mut peers = self.peers.write();
+        let connection = Connection::accept(stream, key_pair, self.user_agent.clone(), self.cipher_suites.clone());
+        let t = peers.insert(token, Peer::new(PeerState::AwaitingSync, connection, self.rate_limit, self.now()));
         debug_assert!(t.is_none());
     }
 
@@ -57,155 +184,201 @@ impl Connections {
         &self,
         token: StreamToken,
         stream: Stream,
+        key_pair: KeyPair,
         local_node_id: NodeId,
         session: Session,
         socket_address: &SocketAddr,
         local_port: u16,
     ) -> bool {
-        let mut connections = self.connections.write();
-
+        let mut peers = self.peers.write();
         let mut connected_nodes = self.connected_nodes.write();
-        let mut reversed_connected_nodes = self.reversed_connected_nodes.write();
 
         let remote_node_id = socket_address.into();
         if connected_nodes.contains_key(&remote_node_id) {
             return false
         }
 
-        let connection = Connection::connect(stream, session, local_port, local_node_id, remote_node_id.clone());
-        let t = connections.insert(token, connection);
+        let connection = Connection::connect(
+            stream,
+            session,
+            local_port,
+            key_pair,
+            local_node_id,
+            remote_node_id.clone(),
+            self.user_agent.clone(),
+            self.cipher_suites.clone(),
+        );
+        let t = peers.insert(token, Peer::new(PeerState::Connecting, connection, self.rate_limit, self.now()));
         debug_assert!(t.is_none());
         let t = connected_nodes.insert(remote_node_id, token);
         debug_assert!(t.is_none());
-        let t = reversed_connected_nodes.insert(token, remote_node_id);
-        debug_assert!(t.is_none());
         true
     }
 
+    // Return false if the peer is not waiting for an ack or hasn't received it
     pub fn establish_wait_ack_connection(&self, token: &StreamToken) -> bool {
-        let connections = self.connections.read();
-
-        connections
-            .get(token)
-            .map(|connection| {
-                let established = connection.establish();
-                debug_assert!(established);
-            })
-            .is_some()
+        let mut peers = self.peers.write();
+        match peers.get(token) {
+            Some(peer) if peer.state == PeerState::Connecting => {}
+            _ => return false,
+        }
+        if establish_connection(&mut *peers, token).is_err() {
+            return false
+        }
+        let peer = peers.get_mut(token).expect("The peer is put back");
+        peer.touch(self.now());
+        peer.transit(PeerEvent::AckReceived)
     }
 
+    // Return false if the peer is not waiting for its sync, the ack is not sent or the node is already connected
     pub fn establish_wait_sync_connection(&self, token: &StreamToken) -> bool {
-        let connections = self.connections.read();
+        let mut peers = self.peers.write();
         let mut connected_nodes = self.connected_nodes.write();
-        let mut reversed_connected_nodes = self.reversed_connected_nodes.write();
-
-        connections
-            .get(token)
-            .and_then(|connection| {
-                let remote_node_id =
-                    connection.remote_node_id().expect("EstablishedConnection MUST have remote node id");
-                if connected_nodes.contains_key(&remote_node_id) {
-                    return None
-                }
-                let t = connection.establish();
-                debug_assert!(t);
-                let t = connected_nodes.insert(remote_node_id, *token);
-                debug_assert!(t.is_none());
-                let t = reversed_connected_nodes.insert(*token, remote_node_id);
-                debug_assert!(t.is_none());
-                Some(())
-            })
-            .is_some()
+
+        let remote_node_id = match peers.get(token) {
+            Some(peer) if peer.state == PeerState::AwaitingSync => {
+                peer.connection.remote_node_id().expect("EstablishedConnection MUST have remote node id")
+            }
+            _ => return false,
+        };
+        if connected_nodes.contains_key(&remote_node_id) {
+            return false
+        }
+        if establish_connection(&mut *peers, token).is_err() {
+            return false
+        }
+        let t = connected_nodes.insert(remote_node_id, *token);
+        debug_assert!(t.is_none());
+        let peer = peers.get_mut(token).expect("The peer is put back");
+        peer.touch(self.now());
+        peer.transit(PeerEvent::AckSent)
     }
 
-    pub fn register<Message>(
-        &self,
-        token: &StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            let result = connection.register(reg, event_loop)?;
-            debug_assert_ne!(result, ConnectionType::None);
-            Ok(result)
+    /// Marks the peer as closing.
+    /// Returns the state before closing and the node id of the peer, or None if it is already closing.
+    pub fn close(&self, token: &StreamToken) -> Option<(PeerState, Option<NodeId>)> {
+        let mut peers = self.peers.write();
+        let peer = peers.get_mut(token)?;
+        let previous = peer.state;
+        if peer.transit(PeerEvent::Closed) {
+            Some((previous, peer.connection.remote_node_id()))
         } else {
-            Ok(ConnectionType::None)
+            None
         }
     }
 
-    pub fn reregister<Message>(
-        &self,
-        token: &StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            let result = connection.reregister(reg, event_loop)?;
-            debug_assert_ne!(result, ConnectionType::None);
-            Ok(result)
-        } else {
-            Ok(ConnectionType::None)
+    // Return false if the reason is not sent, e.g. the handshake is not done yet
+    pub fn disconnect(&self, token: &StreamToken, reason: DisconnectReason) -> Result<bool> {
+        let peers = self.peers.read();
+        match peers.get(token) {
+            Some(peer) if peer.state == PeerState::Closing => Ok(false),
+            Some(peer) => peer.connection.disconnect(reason),
+            None => Ok(false),
         }
     }
 
-    pub fn deregister<Message>(
-        &self,
-        token: &StreamToken,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> io::Result<ConnectionType>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            let result = connection.deregister(event_loop)?;
-            debug_assert_ne!(result, ConnectionType::None);
-            Ok(result)
-        } else {
-            Ok(ConnectionType::None)
+    pub fn remove(&self, token: &StreamToken) -> Option<Connection> {
+        let mut peers = self.peers.write();
+        let mut connected_nodes = self.connected_nodes.write();
+
+        let peer = peers.remove(token)?;
+        debug_assert_eq!(PeerState::Closing, peer.state);
+        if let Some(remote_node_id) = peer.connection.remote_node_id() {
+            if connected_nodes.get(&remote_node_id) == Some(token) {
+                connected_nodes.remove(&remote_node_id);
+            }
         }
+        Some(peer.connection)
     }
 
-    // Return true if the queue is not empty
-    pub fn send(&self, token: &StreamToken) -> Result<(ConnectionType, bool)> {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            let (result, remain) = connection.send()?;
-            debug_assert_ne!(result, ConnectionType::None);
-            Ok((result, remain))
+    pub fn state(&self, token: &StreamToken) -> Option<PeerState> {
+        let peers = self.peers.read();
+        peers.get(token).map(|peer| peer.state)
+    }
+
+    pub fn register(&self, token: &StreamToken, reg: Token, poll: &Poll) -> io::Result<Option<PeerState>> {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.register(reg, poll)?;
+            Ok(Some(peer.state))
         } else {
-            Ok((ConnectionType::None, false))
+            Ok(None)
         }
     }
 
-    pub fn receive(&self, token: &StreamToken) -> Result<Option<ReceivedMessage>> {
-        let connections = self.connections.read();
+    pub fn reregister(&self, token: &StreamToken, reg: Token, poll: &Poll) -> io::Result<Option<PeerState>> {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.reregister(reg, poll)?;
+            Ok(Some(peer.state))
+        } else {
+            Ok(None)
+        }
+    }
 
-        if let Some(connection) = connections.get(token) {
-            Ok(connection.receive()?)
+    pub fn deregister(&self, token: &StreamToken, poll: &Poll) -> io::Result<Option<PeerState>> {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.deregister(poll)?;
+            Ok(Some(peer.state))
         } else {
             Ok(None)
         }
     }
 
+    // Return true if the queue is not empty
+    pub fn send(&self, token: &StreamToken) -> Result<Option<(PeerState, bool)>> {
+        let peers = self.peers.read();
+        match peers.get(token) {
+            Some(peer) if peer.state == PeerState::Closing => Ok(Some((PeerState::Closing, false))),
+            Some(peer) => Ok(Some((peer.state, peer.connection.send()?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn receive(&self, token: &StreamToken) -> Result<Option<ReceivedMessage>> {
+        let peers = self.peers.read();
+        match peers.get(token) {
+            Some(peer) if peer.state == PeerState::Closing => Ok(None),
+            Some(peer) => {
+                let message = peer.connection.receive()?;
+                if let Some(ReceivedMessage::Extension(_)) = &message {
+                    peer.touch(self.now());
+                }
+                Ok(message)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Charges an inbound extension message of `bytes` to the rate limit of the peer.
+    pub fn admit(&self, token: &StreamToken, bytes: usize, now: Instant) -> Option<Admission> {
+        let peers = self.peers.read();
+        peers.get(token).map(|peer| peer.rate_limiter.lock().admit(bytes, now))
+    }
+
     pub fn enqueue_negotiation_request(&self, token: &StreamToken, name: String, version: u64) -> bool {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            connection.enqueue_negotiation_request(name, version)
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.enqueue_negotiation_request(name, version)
         } else {
             false
         }
     }
 
     pub fn enqueue_negotiation_allowed(&self, token: &StreamToken, seq: u64) -> bool {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            connection.enqueue_negotiation_allowed(seq)
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.enqueue_negotiation_allowed(seq)
+        } else {
+            false
+        }
+    }
+
+    pub fn enqueue_negotiation_denied(&self, token: &StreamToken, seq: u64) -> bool {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.enqueue_negotiation_denied(seq)
         } else {
             false
         }
@@ -216,29 +389,81 @@ impl Connections {
         token: &StreamToken,
         extension_name: &String,
         need_encryption: bool,
-        data: &[u8],
-    ) -> bool {
-        let connections = self.connections.read();
-        if let Some(connection) = connections.get(token) {
-            connection.enqueue_extension_message(extension_name, need_encryption, &data)
+        data: Bytes,
+        span: Option<Span>,
+    ) -> ::std::result::Result<(), DropReason> {
+        let peers = self.peers.read();
+        let peer = peers.get(token).ok_or(DropReason::NotConnected)?;
+        let enqueued = peer.connection.enqueue_extension_message(extension_name, need_encryption, data, span);
+        if let Err(reason) = enqueued {
+            peer.drops.lock().count(reason);
+        }
+        enqueued
+    }
+
+    pub fn count_drop(&self, token: &StreamToken, reason: DropReason) {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.drops.lock().count(reason);
+        }
+    }
+
+    // The dropped messages of the established peers
+    pub fn drops(&self) -> HashMap<NodeId, DropCounts> {
+        let peers = self.peers.read();
+        peers
+            .values()
+            .filter(|peer| peer.state == PeerState::Established)
+            .filter_map(|peer| peer.connection.remote_node_id().map(|node_id| (node_id, peer.drops.lock().clone())))
+            .filter(|(_, drops)| !drops.is_empty())
+            .collect()
+    }
+
+    pub fn enqueue_relay_message(&self, token: &StreamToken, message: RelayMessage) -> bool {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.connection.enqueue_relay_message(message)
         } else {
             false
         }
     }
 
-    pub fn remove_requested_negotiation(&self, token: &StreamToken, seq: &u64) -> Option<String> {
-        let connections = self.connections.read();
-        connections.get(token).and_then(|connection| connection.remove_requested_negotiation(seq))
+    pub fn remove_requested_negotiation(&self, token: &StreamToken, seq: &u64) -> Option<(String, Version)> {
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.remove_requested_negotiation(seq))
+    }
+
+    pub fn add_negotiated_extension(&self, token: &StreamToken, name: String, version: Version) {
+        let peers = self.peers.read();
+        if let Some(peer) = peers.get(token) {
+            peer.extensions.lock().insert(name, version);
+        }
+    }
+
+    pub fn is_ack_sent(&self, token: &StreamToken) -> bool {
+        let peers = self.peers.read();
+        peers.get(token).map(|peer| peer.connection.is_ack_sent()).unwrap_or(false)
     }
 
     pub fn remote_addr_of_waiting_sync(&self, token: &StreamToken) -> Option<SocketAddr> {
-        let connections = self.connections.read();
-        connections.get(token).and_then(|connection| connection.remote_addr_of_waiting_sync())
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.remote_addr_of_waiting_sync())
+    }
+
+    pub fn ready_session(
+        &self,
+        token: &StreamToken,
+        remote_node_id: NodeId,
+        remote_public: Public,
+        session: Session,
+    ) -> bool {
+        let peers = self.peers.read();
+        peers.get(token).map(|peer| peer.connection.ready_session(remote_node_id, remote_public, session)).is_some()
     }
 
-    pub fn ready_session(&self, token: &StreamToken, remote_node_id: NodeId, session: Session) -> bool {
-        let connections = self.connections.read();
-        connections.get(token).map(|connection| connection.ready_session(remote_node_id, session)).is_some()
+    pub fn remote_public(&self, token: &StreamToken) -> Option<Public> {
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.remote_public())
     }
 
     pub fn stream_token(&self, node: &NodeId) -> Option<StreamToken> {
@@ -246,18 +471,312 @@ impl Connections {
         connected_nodes.get(node).cloned()
     }
 
+    // Return the node id of the connecting or established peer
     pub fn node_id(&self, token: &StreamToken) -> Option<NodeId> {
-        let reversed_connected_nodes = self.reversed_connected_nodes.read();
-        reversed_connected_nodes.get(token).cloned()
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| match peer.state {
+            PeerState::Connecting | PeerState::Established => peer.connection.remote_node_id(),
+            PeerState::AwaitingSync | PeerState::Closing => None,
+        })
     }
 
     pub fn established_session(&self, token: &StreamToken) -> Option<Session> {
-        let connections = self.connections.read();
-        connections.get(token).and_then(|con| con.established_session())
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.established_session())
+    }
+
+    pub fn envelope_version(&self, token: &StreamToken) -> Option<Version> {
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.envelope_version())
+    }
+
+    pub fn cipher_suite(&self, token: &StreamToken) -> Option<CipherSuite> {
+        let peers = self.peers.read();
+        peers.get(token).and_then(|peer| peer.connection.cipher_suite())
+    }
+
+    /// Requests again the negotiations which were not replied in `timeout`.
+    /// Returns the peers which have any expired negotiation.
+    pub fn expire_negotiations(
+        &self,
+        timeout: Duration,
+        max_retries: usize,
+        now: Instant,
+    ) -> Vec<(StreamToken, ExpiredNegotiations)> {
+        let peers = self.peers.read();
+        peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerState::Established)
+            .map(|(token, peer)| (*token, peer.connection.expire_negotiations(timeout, max_retries, now)))
+            .filter(|(_, expired)| *expired != ExpiredNegotiations::default())
+            .collect()
+    }
+
+    // The user agent of this node
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    // The user agents of the established peers
+    pub fn user_agents(&self) -> Vec<(NodeId, String)> {
+        let peers = self.peers.read();
+        peers
+            .values()
+            .filter(|peer| peer.state == PeerState::Established)
+            .filter_map(|peer| {
+                let node_id = peer.connection.remote_node_id()?;
+                let user_agent = peer.connection.remote_user_agent()?;
+                Some((node_id, user_agent))
+            })
+            .collect()
+    }
+
+    pub fn established_nodes(&self) -> Vec<NodeId> {
+        let peers = self.peers.read();
+        peers
+            .values()
+            .filter(|peer| peer.state == PeerState::Established)
+            .filter_map(|peer| peer.connection.remote_node_id())
+            .collect()
+    }
+
+    /// Returns the established peers which have sent no extension message for `idle_timeout`.
+    /// The peers which have been idle longer come first.
+    pub fn idle_nodes(&self, idle_timeout: Duration, now: Instant) -> Vec<NodeId> {
+        let peers = self.peers.read();
+        let mut idle: Vec<_> = peers
+            .values()
+            .filter(|peer| peer.state == PeerState::Established && peer.is_idle(idle_timeout, now))
+            .filter_map(|peer| peer.connection.remote_node_id().map(|node_id| (*peer.last_traffic.lock(), node_id)))
+            .collect();
+        idle.sort_by_key(|(last_traffic, _)| *last_traffic);
+        idle.into_iter().map(|(_, node_id)| node_id).collect()
     }
 
+    /// Returns the established peers which have been idle longer than their own timeouts.
+    /// `idle_timeout_of` returns None for the peers which never time out.
+    pub fn timed_out_nodes<F>(&self, idle_timeout_of: F, now: Instant) -> Vec<NodeId>
+    where
+        F: Fn(&NodeId) -> Option<Duration>, {
+        let peers = self.peers.read();
+        peers
+            .values()
+            .filter(|peer| peer.state == PeerState::Established)
+            .filter_map(|peer| peer.connection.remote_node_id().map(|node_id| (peer, node_id)))
+            .filter(|(peer, node_id)| match idle_timeout_of(node_id) {
+                Some(idle_timeout) => peer.is_idle(idle_timeout, now),
+                None => false,
+            })
+            .map(|(_, node_id)| node_id)
+            .collect()
+    }
+
+    pub fn infos(&self, now: Instant) -> Vec<ConnectionInfo> {
+        let peers = self.peers.read();
+        let mut infos: Vec<_> = peers
+            .iter()
+            .filter(|(_, peer)| peer.state != PeerState::Closing)
+            .map(|(token, peer)| {
+                let (bytes_received, bytes_sent) = peer.connection.traffic();
+                ConnectionInfo {
+                    token: *token,
+                    node_id: peer.connection.remote_node_id(),
+                    remote_address: peer.connection.peer_addr(),
+                    direction: peer.direction,
+                    age: if peer.connected_at <= now {
+                        now.duration_since(peer.connected_at)
+                    } else {
+                        Duration::from_secs(0)
+                    },
+                    extensions: peer.extensions.lock().clone(),
+                    user_agent: peer.connection.remote_user_agent(),
+                    bytes_received,
+                    bytes_sent,
+                    rtt: peer.connection.rtt(),
+                }
+            })
+            .collect();
+        infos.sort_by_key(|info| info.token);
+        infos
+    }
+
+    pub fn dumps(&self, now: Instant) -> Vec<ConnectionDump> {
+        let peers = self.peers.read();
+        let mut dumps: Vec<_> = peers
+            .iter()
+            .map(|(token, peer)| {
+                let last_traffic = *peer.last_traffic.lock();
+                ConnectionDump {
+                    token: *token,
+                    node_id: peer.connection.remote_node_id(),
+                    state: peer.state,
+                    send_queue: peer.connection.send_queue_len(),
+                    requested_negotiations: peer.connection.requested_negotiations(),
+                    extensions: peer.extensions.lock().clone(),
+                    idle: if last_traffic <= now {
+                        now.duration_since(last_traffic)
+                    } else {
+                        Duration::from_secs(0)
+                    },
+                }
+            })
+            .collect();
+        dumps.sort_by_key(|dump| dump.token);
+        dumps
+    }
+
+    // The number of the peers which are not closing
     pub fn len(&self) -> usize {
-        let connections = self.connections.read();
-        connections.len()
+        let peers = self.peers.read();
+        peers.values().filter(|peer| peer.state != PeerState::Closing).count()
+    }
+}
+
+// The state of a connection moves by value, so the peer is taken out while its connection is established.
+// The peer is put back even if the connection cannot be established.
+fn establish_connection(peers: &mut HashMap<StreamToken, Peer>, token: &StreamToken) -> Result<()> {
+    let mut peer = peers.remove(token).expect("The peer exists");
+    let (connection, result) = match peer.connection.establish() {
+        Ok(connection) => (connection, Ok(())),
+        Err((connection, err)) => (connection, Err(err)),
+    };
+    peer.connection = connection;
+    peers.insert(*token, peer);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net;
+    use std::sync::Arc;
+
+    use ckeys::{Generator, Random};
+    use ctypes::Secret;
+
+    use super::super::transport::MemoryStream;
+    use super::*;
+
+    const TOKEN: StreamToken = 1;
+
+    fn address(port: u16) -> net::SocketAddr {
+        net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    // Establishes a peer with the token, and returns its node id and stream.
+    // The sync message is skipped, so the peer is taken as an older node which uses the legacy cipher.
+    fn establish(connections: &Connections, token: StreamToken, port: u16) -> (NodeId, MemoryStream) {
+        let (remote, local, _link) = MemoryStream::pair(address(port), address(port + 1), 0);
+        let key_pair = Random.generate().unwrap();
+        let public = *key_pair.public();
+        let node_id = NodeId::random();
+        connections.accept(token, Stream::from(local), key_pair);
+        assert!(connections.ready_session(&token, node_id, public, Session::new_with_zero_nonce(Secret::random())));
+        connections.send(&token).unwrap();
+        assert!(connections.is_ack_sent(&token));
+        assert!(connections.establish_wait_sync_connection(&token));
+        (node_id, remote)
+    }
+
+    // Returns the connections which have an established peer, and the stream of the peer
+    fn established() -> (Connections, NodeId, MemoryStream) {
+        let connections = Connections::new(RateLimit::default(), String::new(), CipherSuite::supported(true));
+        let (node_id, remote) = establish(&connections, TOKEN, 3485);
+        (connections, node_id, remote)
+    }
+
+    #[test]
+    fn established_peer_is_idle_after_the_timeout() {
+        let (connections, node_id, _remote) = established();
+        let idle_timeout = Duration::from_secs(60);
+        assert_eq!(Vec::<NodeId>::new(), connections.idle_nodes(idle_timeout, Instant::now()));
+        assert_eq!(vec![node_id], connections.idle_nodes(idle_timeout, Instant::now() + idle_timeout));
+    }
+
+    #[test]
+    fn outbound_message_does_not_keep_peer_active() {
+        let (connections, node_id, _remote) = established();
+        let idle_timeout = Duration::from_secs(60);
+        assert!(connections.enqueue_extension_message(&TOKEN, &"ext".to_string(), false, Bytes::new(), None).is_ok());
+        assert_eq!(vec![node_id], connections.idle_nodes(idle_timeout, Instant::now() + idle_timeout));
+    }
+
+    #[test]
+    fn longer_idle_peer_comes_first() {
+        let time = Arc::new(Mutex::new(Instant::now()));
+        let clock = Arc::clone(&time);
+        let connections = Connections::new(RateLimit::default(), String::new(), CipherSuite::supported(true))
+            .with_clock(move || *clock.lock());
+        let (first, _first_remote) = establish(&connections, TOKEN, 3485);
+        *time.lock() += Duration::from_secs(1);
+        let (second, _second_remote) = establish(&connections, TOKEN + 1, 3487);
+
+        let later = *time.lock() + Duration::from_secs(60);
+        assert_eq!(vec![first, second], connections.idle_nodes(Duration::from_secs(30), later));
+    }
+
+    #[test]
+    fn peer_without_timeout_is_not_timed_out() {
+        let (connections, node_id, _remote) = established();
+        let idle_timeout = Duration::from_secs(60);
+        let later = Instant::now() + idle_timeout;
+        assert_eq!(Vec::<NodeId>::new(), connections.timed_out_nodes(|_| None, later));
+        assert_eq!(vec![node_id], connections.timed_out_nodes(|_| Some(idle_timeout), later));
+        assert_eq!(Vec::<NodeId>::new(), connections.timed_out_nodes(|_| Some(idle_timeout * 2), later));
+    }
+
+    #[test]
+    fn each_peer_has_its_own_rate_limit() {
+        let rate_limit = RateLimit {
+            messages_per_second: 1,
+            bytes_per_second: 1000,
+        };
+        let connections = Connections::new(rate_limit, String::new(), CipherSuite::supported(true));
+        let (_first, _first_remote) = establish(&connections, TOKEN, 3485);
+        let (_second, _second_remote) = establish(&connections, TOKEN + 1, 3487);
+        let now = Instant::now();
+        assert_eq!(Some(Admission::Accepted), connections.admit(&TOKEN, 10, now));
+        assert_eq!(Some(Admission::Throttled), connections.admit(&TOKEN, 10, now));
+        assert_eq!(Some(Admission::Accepted), connections.admit(&(TOKEN + 1), 10, now));
+        assert_eq!(None, connections.admit(&(TOKEN + 2), 10, now));
+    }
+
+    #[test]
+    fn peer_in_handshake_is_not_idle() {
+        let (_remote, local, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+        let connections = Connections::new(RateLimit::default(), String::new(), CipherSuite::supported(true));
+        connections.accept(TOKEN, Stream::from(local), Random.generate().unwrap());
+        let idle_timeout = Duration::from_secs(60);
+        assert_eq!(Vec::<NodeId>::new(), connections.idle_nodes(idle_timeout, Instant::now() + idle_timeout));
+    }
+
+    #[test]
+    fn info_of_the_established_peer() {
+        let (connections, node_id, _remote) = established();
+        connections.add_negotiated_extension(&TOKEN, "ext".to_string(), 3);
+
+        let infos = connections.infos(Instant::now());
+        assert_eq!(1, infos.len());
+        let info = &infos[0];
+        assert_eq!(TOKEN, info.token);
+        assert_eq!(Some(node_id), info.node_id);
+        assert_eq!(Direction::Inbound, info.direction);
+        assert_eq!(Some(&3), info.extensions.get("ext"));
+        assert_eq!(Some(String::new()), info.user_agent);
+        assert!(info.bytes_sent > 0, "The ack is sent");
+        assert_eq!(None, info.rtt);
+    }
+
+    #[test]
+    fn dump_shows_the_unanswered_negotiation() {
+        let (connections, node_id, _remote) = established();
+        assert!(connections.enqueue_negotiation_request(&TOKEN, "ext".to_string(), 1));
+
+        let dumps = connections.dumps(Instant::now());
+        assert_eq!(1, dumps.len());
+        let dump = &dumps[0];
+        assert_eq!(Some(node_id), dump.node_id);
+        assert_eq!(PeerState::Established, dump.state);
+        assert_eq!(1, dump.send_queue);
+        assert_eq!(vec!["ext".to_string()], dump.requested_negotiations);
     }
 }
@@ -0,0 +1,156 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use super::super::NodeId;
+
+/// Why a message was dropped or rejected
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DropReason {
+    /// The peer exceeded the rate limit
+    Throttled,
+    /// The message couldn't be decrypted or its envelope couldn't be opened
+    Undecodable,
+    /// No extension is registered for the message
+    UnknownExtension,
+    /// The message was sent to a node which is not connected
+    NotConnected,
+    /// The message to be sent couldn't be encrypted
+    EncryptionFailure,
+    /// The worker of the extension was too busy to queue the message
+    QueueFull,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DropCounts(BTreeMap<DropReason, usize>);
+
+impl DropCounts {
+    pub fn count(&mut self, reason: DropReason) {
+        *self.0.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, reason: DropReason) -> usize {
+        self.0.get(&reason).cloned().unwrap_or(0)
+    }
+
+    pub fn merge(&mut self, other: &DropCounts) {
+        for (reason, count) in &other.0 {
+            *self.0.entry(*reason).or_insert(0) += count;
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The dropped messages of the connected peers and the registered extensions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DropReport {
+    pub connections: HashMap<NodeId, DropCounts>,
+    pub extensions: HashMap<String, DropCounts>,
+}
+
+/// Carries the answer of a query back to the thread which sent the query to the handler.
+pub struct Reply<T>(Arc<Mutex<Sender<T>>>);
+
+impl<T> Reply<T> {
+    pub fn new(sender: Sender<T>) -> Self {
+        Reply(Arc::new(Mutex::new(sender)))
+    }
+
+    pub fn send(&self, value: T) {
+        if self.0.lock().send(value).is_err() {
+            cdebug!(NET, "The receiver of the reply is gone");
+        }
+    }
+}
+
+impl<T> Clone for Reply<T> {
+    fn clone(&self) -> Self {
+        Reply(Arc::clone(&self.0))
+    }
+}
+
+impl<T> fmt::Debug for Reply<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Reply")
+    }
+}
+
+// The replies are equal only if they are sent to the same receiver
+impl<T> PartialEq for Reply<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> PartialOrd for Reply<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
+        if self == other {
+            Some(::std::cmp::Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn counts_by_reason() {
+        let mut counts = DropCounts::default();
+        assert!(counts.is_empty());
+        counts.count(DropReason::Throttled);
+        counts.count(DropReason::Throttled);
+        counts.count(DropReason::Undecodable);
+        assert_eq!(2, counts.get(DropReason::Throttled));
+        assert_eq!(1, counts.get(DropReason::Undecodable));
+        assert_eq!(0, counts.get(DropReason::NotConnected));
+        assert_eq!(3, counts.total());
+
+        let mut others = DropCounts::default();
+        others.count(DropReason::Throttled);
+        others.count(DropReason::QueueFull);
+        counts.merge(&others);
+        assert_eq!(3, counts.get(DropReason::Throttled));
+        assert_eq!(1, counts.get(DropReason::QueueFull));
+        assert_eq!(5, counts.total());
+    }
+
+    #[test]
+    fn reply_is_received() {
+        let (sender, receiver) = mpsc::channel();
+        let reply = Reply::new(sender);
+        assert_eq!(reply, reply.clone());
+        reply.clone().send(3);
+        assert_eq!(Ok(3), receiver.try_recv());
+    }
+}
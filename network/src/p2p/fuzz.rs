@@ -0,0 +1,87 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Entry points of the fuzz targets in `network/fuzz`.
+//! Each of them feeds the bytes from the fuzzer to the decoders as if a peer sent them.
+
+use std::io::Write;
+use std::net;
+
+use ckeys::{Generator, KeyPair, Random};
+use ctypes::{Secret, H256};
+use rlp::RlpStream;
+
+use super::super::session::{CipherSuite, Session};
+use super::super::NodeId;
+use super::connection::{Connection, ReceivedMessage};
+use super::stream::{seal, Stream, FRAME_VERSION, LEGACY_FRAME_VERSION};
+use super::transport::MemoryStream;
+use super::SignedMessage;
+
+fn address(port: u16) -> net::SocketAddr {
+    net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), port)
+}
+
+// Returns the stream which reads `data`
+fn stream_of(data: &[u8]) -> Stream {
+    let (mut remote, local, _link) = MemoryStream::pair(address(3485), address(3486), 0);
+    remote.write_all(data).expect("The link is open");
+    Stream::from(local)
+}
+
+fn key_pair() -> KeyPair {
+    Random.generate().expect("Cannot generate a key pair")
+}
+
+/// Splits `data` into frames. Most of them are rejected by the checksum.
+pub fn frame(data: &[u8]) {
+    let mut stream = stream_of(data);
+    stream.set_frame_version(FRAME_VERSION);
+    while let Ok(Some(_)) = stream.read_shared::<SignedMessage>() {}
+}
+
+/// Decodes `data` as the handshake from a peer which is not authenticated yet.
+pub fn handshake(data: &[u8]) {
+    let mut frame = RlpStream::new_list(2);
+    frame.append(&data).append(&H256::zero());
+    let stream = stream_of(&seal(LEGACY_FRAME_VERSION, frame.out()));
+    let connection = Connection::accept(stream, key_pair(), String::new(), CipherSuite::supported(true));
+    while let Ok(Some(_)) = connection.receive() {}
+}
+
+/// Decodes `data` as a message signed by an established peer.
+/// If it is an extension message, it is decrypted with every cipher.
+pub fn message(data: &[u8]) {
+    let session = Session::new_with_zero_nonce(Secret::zero());
+    let mut frame = RlpStream::new_list(2);
+    frame.append(&data).append(&session.sign(data));
+
+    let key_pair = key_pair();
+    let public = *key_pair.public();
+    let stream = stream_of(&seal(LEGACY_FRAME_VERSION, frame.out()));
+    let connection = Connection::accept(stream, key_pair, String::new(), CipherSuite::supported(true));
+    connection.ready_session(NodeId::zero(), public, session.clone());
+    connection.send().expect("The ack is written in memory");
+    let connection = match connection.establish() {
+        Ok(connection) => connection,
+        Err(_) => return,
+    };
+    if let Ok(Some(ReceivedMessage::Extension(message))) = connection.receive() {
+        for cipher in CipherSuite::supported(true) {
+            let _ = message.unencrypted_data(&session, cipher);
+        }
+    }
+}
@@ -14,53 +14,142 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use ccrypto::aes::SymmetricCipherError;
+use bytes::Bytes;
 use cfinally::finally;
-use cio::{IoContext, IoHandler, IoHandlerResult, IoManager, StreamToken, TimerToken};
-use mio::deprecated::EventLoop;
-use mio::{PollOpt, Ready, Token};
+use cio::{IoChannel, IoContext, IoHandler, IoHandlerResult, StreamToken, TimerToken};
+use ckeys::{KeyPair, Public};
+use mio::{Poll, PollOpt, Ready, Token};
 use parking_lot::Mutex;
 use rlp::UntrustedRlp;
 use unexpected::Mismatch;
 
 use super::super::addr::convert_to_node_id;
+use super::super::capture::{self, Direction as CaptureDirection};
 use super::super::client::Client;
+use super::super::metrics;
+use super::super::peer_store;
+use super::super::session::{CipherError, CipherSuite};
+use super::super::session_initiator::Message as SessionInitiatorMessage;
 use super::super::token_generator::TokenGenerator;
-use super::super::RoutingTable;
-use super::super::{NodeId, SocketAddr};
-use super::connections::{ConnectionType, Connections, ReceivedMessage};
+use super::super::trace::{Span, SpanContext};
+use super::super::{RoutingTable, RoutingTableSizes};
+use super::super::{NodeId, NodeRecord, SocketAddr};
+use super::bootstrap::Bootstrap;
+use super::config::Config;
+use super::connection::Error as ConnectionError;
+use super::connections::{ConnectionDump, ConnectionInfo, Connections, ReceivedMessage};
+use super::drops::{DropCounts, DropReason, DropReport, Reply};
 use super::listener::Listener;
-use super::message::{HandshakeMessage, Message as NetworkMessage, Version};
-use super::stream::Stream;
-use super::NegotiationBody;
+use super::message::{open_envelope, DisconnectReason, HandshakeMessage, Message as NetworkMessage, Version};
+use super::observed_addresses::ObservedAddresses;
+use super::peer::PeerState;
+use super::rate_limit::Admission;
+use super::socket_options::SocketOptions;
+use super::stream::{Error as StreamError, Stream};
+use super::{NegotiationBody, RelayMessage};
 
 struct Manager {
     listener: Listener,
+    // Accepts the light clients in the browsers
+    websocket_listener: Option<Listener>,
 
     tokens: TokenGenerator,
 
     routing_table: Arc<RoutingTable>,
     connections: Connections,
 
+    socket_address: SocketAddr,
     port: u16,
+    key_pair: KeyPair,
+    network_id: u64,
+    observed_addresses: ObservedAddresses,
+
+    // The listening addresses of the connected peers
+    peer_addresses: HashMap<NodeId, SocketAddr>,
+    // Forwards introductions between the peers if true
+    relay: bool,
+    session_initiator: IoChannel<SessionInitiatorMessage>,
+    // The targets that this node asked a relay to be introduced to, with the relays
+    requested_introductions: HashMap<SocketAddr, NodeId>,
+    // The punched addresses to be dialed
+    punched_addresses: Vec<SocketAddr>,
+    // The peers which are always kept connected
+    static_peers: HashMap<NodeId, SocketAddr>,
+    // Dials the bootnodes and the stored peers at startup
+    bootstrap: Bootstrap,
+    // Remembers the connected peers to dial them after the restart
+    peer_store_path: Option<PathBuf>,
+
+    socket_options: SocketOptions,
+    // Overrides the socket options for the connections from and to the static peers
+    static_peer_socket_options: SocketOptions,
+
+    // Disconnects the peers which sent no extension message for this long
+    idle_timeout: Option<Duration>,
+    // Overrides the idle timeout for the static peers. They never time out if it's None.
+    static_peer_idle_timeout: Option<Duration>,
+    // The peers which sent no extension message for this long are evicted first when the slots are full
+    evictable_idle_timeout: Duration,
+
+    // The dropped messages of the registered extensions
+    extension_drops: HashMap<String, DropCounts>,
+
+    // Only these nodes can complete the handshake in a permissioned network
+    allowlist: Option<HashSet<Public>>,
 }
 
 pub const MAX_CONNECTIONS: usize = 200;
 
 const ACCEPT_TOKEN: TimerToken = 0;
+const WEBSOCKET_ACCEPT_TOKEN: TimerToken = ACCEPT_TOKEN + 1;
 
-const FIRST_CONNECTION_TOKEN: TimerToken = ACCEPT_TOKEN + 1;
+const FIRST_CONNECTION_TOKEN: TimerToken = WEBSOCKET_ACCEPT_TOKEN + 1;
 const LAST_CONNECTION_TOKEN: TimerToken = FIRST_CONNECTION_TOKEN + MAX_CONNECTIONS;
 
 const CREATE_CONNECTIONS_TOKEN: TimerToken = 0;
 const PULL_CONNECTIONS_MS: u64 = 1 * 1000;
 
+const DIAL_PUNCHED_TOKEN: TimerToken = CREATE_CONNECTIONS_TOKEN + 1;
+// Waits for both nodes to finish creating the session before dialing
+const DIAL_PUNCHED_MS: u64 = 2 * 1000;
+
+const DIAL_STATIC_PEERS_TOKEN: TimerToken = DIAL_PUNCHED_TOKEN + 1;
+const DIAL_STATIC_PEERS_MS: u64 = 1 * 1000;
+
+const SWEEP_IDLE_PEERS_TOKEN: TimerToken = DIAL_STATIC_PEERS_TOKEN + 1;
+const SWEEP_IDLE_PEERS_MS: u64 = 10 * 1000;
+
+const EXPIRE_NEGOTIATIONS_TOKEN: TimerToken = SWEEP_IDLE_PEERS_TOKEN + 1;
+const EXPIRE_NEGOTIATIONS_MS: u64 = 1 * 1000;
+// The negotiation request is sent again if the peer doesn't reply for this long
+const NEGOTIATION_TIMEOUT_MS: u64 = 5 * 1000;
+const MAX_NEGOTIATION_RETRIES: usize = 3;
+
+const BOOTSTRAP_TOKEN: TimerToken = EXPIRE_NEGOTIATIONS_TOKEN + 1;
+const BOOTSTRAP_MS: u64 = 1 * 1000;
+// Waits for the peers of a stage before starting the next stage
+const BOOTSTRAP_STAGE_TIMEOUT_MS: u64 = 10 * 1000;
+
+const SAVE_PEERS_TOKEN: TimerToken = BOOTSTRAP_TOKEN + 1;
+const SAVE_PEERS_MS: u64 = 60 * 1000;
+
+const PUBLISH_STATUS_TOKEN: TimerToken = SAVE_PEERS_TOKEN + 1;
+const PUBLISH_STATUS_MS: u64 = 1 * 1000;
+
 #[derive(Clone, Debug, PartialOrd, PartialEq)]
 pub enum Message {
     RequestConnection(SocketAddr),
+    RequestDisconnection(NodeId),
+    RequestIntroduction {
+        relay: NodeId,
+        target: SocketAddr,
+    },
 
     RequestNegotiation {
         node_id: NodeId,
@@ -71,17 +160,80 @@ pub enum Message {
         node_id: NodeId,
         extension_name: String,
         need_encryption: bool,
-        data: Vec<u8>,
+        data: Bytes,
+        // The span of the received message which caused this message
+        trace: Option<SpanContext>,
     },
+    ReportDrops(Reply<DropReport>),
+    ReportPeers(Reply<Vec<PeerInfo>>),
+    ReportConnections(Reply<Vec<ConnectionInfo>>),
+    ReportDump(Reply<HandlerDump>),
+    // Disconnects the peer, even if it's static, and replies whether it was connected
+    // The static peer is forgotten, so that it's not redialed
+    RemovePeer(RemovedPeer, Reply<bool>),
+    // A node of the same network on the local network, which is found by mDNS
+    AddLocalPeer(SocketAddr),
+    // The peers which the DNS seeds gave
+    AddSeedPeers(Vec<SocketAddr>),
+    // Signs the record of this node again with the registered extensions
+    UpdateLocalRecord,
+}
+
+/// An established peer
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerInfo {
+    pub node_id: NodeId,
+    /// The listening address, which is unknown for the inbound peers
+    pub address: Option<SocketAddr>,
+    /// The name, the version and the OS of the peer's software
+    pub user_agent: String,
+}
+
+/// The peer which the operator removes, given by its node id or by its listening address
+#[derive(Clone, Debug, PartialOrd, PartialEq)]
+pub enum RemovedPeer {
+    NodeId(NodeId),
+    Address(SocketAddr),
+}
+
+/// The status of this node
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeStatus {
+    pub listening_address: SocketAddr,
+    /// The name, the version and the OS of this node's software
+    pub user_agent: String,
+    /// The number of the established peers
+    pub peer_count: usize,
+    /// The number of peers this node tries to keep connected
+    pub min_peers: usize,
+}
+
+/// The internal state of the handler, which is dumped to diagnose a stuck network
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerDump {
+    pub connections: Vec<ConnectionDump>,
+    /// The connections which are not established yet
+    pub pending_handshakes: usize,
+    pub assigned_tokens: usize,
+    pub token_limit: usize,
+    pub routing_table: RoutingTableSizes,
+    pub extensions: Vec<String>,
+    pub static_peers: usize,
+    pub requested_introductions: usize,
+    pub punched_addresses: usize,
 }
 
 #[derive(Debug)]
 enum Error {
     InvalidStream(StreamToken),
+    CorruptedFrame(StreamToken),
+    ForgedMessage(StreamToken),
+    RateLimitExceeded(StreamToken),
     InvalidNode(NodeId),
     InvalidSign,
+    InvalidIdentity,
     UnexpectedNodeId(Mismatch<NodeId>),
-    SymmetricCipherError(SymmetricCipherError),
+    CipherError(CipherError),
     General(&'static str),
 }
 
@@ -89,34 +241,102 @@ impl ::std::fmt::Display for Error {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         match self {
             Error::InvalidStream(_) => ::std::fmt::Debug::fmt(self, f),
+            Error::CorruptedFrame(_) => ::std::fmt::Debug::fmt(self, f),
+            Error::ForgedMessage(_) => ::std::fmt::Debug::fmt(self, f),
+            Error::RateLimitExceeded(_) => ::std::fmt::Debug::fmt(self, f),
             Error::InvalidNode(_) => ::std::fmt::Debug::fmt(self, f),
             Error::InvalidSign => ::std::fmt::Debug::fmt(&self, f),
+            Error::InvalidIdentity => ::std::fmt::Debug::fmt(&self, f),
             Error::UnexpectedNodeId(_) => ::std::fmt::Debug::fmt(&self, f),
-            Error::SymmetricCipherError(err) => ::std::fmt::Debug::fmt(&err, f),
+            Error::CipherError(err) => ::std::fmt::Debug::fmt(&err, f),
             Error::General(_) => ::std::fmt::Debug::fmt(self, f),
         }
     }
 }
 
 impl Manager {
-    pub fn listen(socket_address: &SocketAddr, routing_table: Arc<RoutingTable>) -> io::Result<Self> {
+    pub fn listen(
+        config: Config,
+        routing_table: Arc<RoutingTable>,
+        session_initiator: IoChannel<SessionInitiatorMessage>,
+        bootstrap: Bootstrap,
+    ) -> io::Result<Self> {
+        let cipher_suites = CipherSuite::supported(config.allow_legacy_cipher);
         Ok(Manager {
-            listener: Listener::bind(&socket_address)?,
+            listener: Listener::bind(&config.address)?,
+            websocket_listener: match &config.websocket_address {
+                Some(address) => Some(Listener::bind_websocket(address)?),
+                None => None,
+            },
 
             tokens: TokenGenerator::new(FIRST_CONNECTION_TOKEN, LAST_CONNECTION_TOKEN),
 
             routing_table,
-            connections: Connections::new(),
+            connections: Connections::new(config.rate_limit, config.user_agent, cipher_suites),
 
-            port: socket_address.port(),
+            port: config.address.port(),
+            socket_address: config.address,
+            key_pair: config.key_pair,
+            network_id: config.network_id,
+            observed_addresses: ObservedAddresses::new(),
+
+            peer_addresses: HashMap::new(),
+            relay: config.relay,
+            session_initiator,
+            requested_introductions: HashMap::new(),
+            punched_addresses: Vec::new(),
+            static_peers: config.static_peers.into_iter().map(|address| (address.clone().into(), address)).collect(),
+            bootstrap,
+            peer_store_path: config.peer_store_path,
+
+            socket_options: config.socket_options,
+            static_peer_socket_options: config.static_peer_socket_options,
+
+            idle_timeout: config.idle_timeout,
+            static_peer_idle_timeout: config.static_peer_idle_timeout,
+            evictable_idle_timeout: config.evictable_idle_timeout,
+
+            extension_drops: HashMap::new(),
+
+            allowlist: config.allowlist.map(|keys| keys.into_iter().collect()),
         })
     }
 
+    fn is_allowed(&self, public: &Public) -> bool {
+        match &self.allowlist {
+            Some(allowlist) => allowlist.contains(public),
+            None => true,
+        }
+    }
+
+    // The static peers are recognized by their IP, since the inbound connections come from ephemeral ports
+    fn socket_options_for(&self, address: &SocketAddr) -> SocketOptions {
+        if self.static_peers.values().any(|peer| peer.ip() == address.ip()) {
+            self.static_peer_socket_options
+        } else {
+            self.socket_options
+        }
+    }
+
     pub fn accept(&mut self) -> IoHandlerResult<Option<(StreamToken)>> {
-        match self.listener.accept()? {
+        let accepted = self.listener.accept(|address| self.socket_options_for(address))?;
+        self.add_accepted(accepted)
+    }
+
+    // The WebSocket connections join the same connection table, so the extensions don't tell them apart
+    pub fn accept_websocket(&mut self) -> IoHandlerResult<Option<(StreamToken)>> {
+        let accepted = match &self.websocket_listener {
+            Some(listener) => listener.accept(|address| self.socket_options_for(address))?,
+            None => None,
+        };
+        self.add_accepted(accepted)
+    }
+
+    fn add_accepted(&mut self, accepted: Option<(Stream, SocketAddr)>) -> IoHandlerResult<Option<(StreamToken)>> {
+        match accepted {
             Some((stream, _socket_address)) => {
                 let token = self.tokens.gen().ok_or(Error::General("TooManyConnections"))?;
-                self.connections.accept(token, stream);
+                self.connections.accept(token, stream, self.key_pair.clone());
                 Ok(Some(token))
             }
             None => Ok(None),
@@ -124,7 +344,8 @@ impl Manager {
     }
 
     pub fn connect(&mut self, socket_address: &SocketAddr) -> IoHandlerResult<Option<StreamToken>> {
-        Ok(match Stream::connect(socket_address)? {
+        let options = self.socket_options_for(socket_address);
+        Ok(match Stream::connect(socket_address, &options)? {
             Some(stream) => {
                 let remote_node_id = socket_address.into();
 
@@ -135,12 +356,20 @@ impl Manager {
                     .ok_or(Error::General("Session doesn't exist"))?;
 
                 let token = self.tokens.gen().ok_or(Error::General("TooManyConnections"))?;
-                if self.connections.connect(token, stream, local_node_id, session, socket_address, self.port) {
-                    self.routing_table.establish(socket_address);
+                let key_pair = self.key_pair.clone();
+                let port = self.port;
+                if self.routing_table.establish(socket_address).is_none() {
+                    self.tokens.restore(token);
+                    return Err(Error::General("Session is already in use").into())
+                }
+                if self.connections.connect(token, stream, key_pair, local_node_id, session, socket_address, port) {
+                    self.peer_addresses.insert(socket_address.into(), socket_address.clone());
                     Some(token)
                 } else {
                     cwarn!(NET, "Cannot create connection to {:?}", socket_address);
                     self.tokens.restore(token);
+                    // The session is taken, so the address starts over
+                    self.routing_table.remove_node(socket_address.clone());
                     None
                 }
             }
@@ -148,46 +377,69 @@ impl Manager {
         })
     }
 
-    pub fn register_stream(
-        &self,
-        token: StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
-        self.connections.register(&token, reg, event_loop)?;
+    pub fn register_stream(&self, token: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
+        self.connections.register(&token, reg, poll)?;
         Ok(())
     }
 
-    pub fn reregister_stream(
-        &self,
-        token: StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
-        self.connections.reregister(&token, reg, event_loop)?;
+    pub fn reregister_stream(&self, token: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
+        self.connections.reregister(&token, reg, poll)?;
         Ok(())
     }
 
-    fn deregister_stream(
-        &self,
-        token: StreamToken,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
-        self.connections.deregister(&token, event_loop)?;
+    fn deregister_stream(&mut self, token: StreamToken, poll: &Poll) -> IoHandlerResult<()> {
+        self.connections.deregister(&token, poll)?;
+        if self.connections.remove(&token).is_some() {
+            self.tokens.restore(token);
+        }
         Ok(())
     }
 
     // Return false if there is no message
-    fn receive(&mut self, stream: &StreamToken, client: &Client) -> IoHandlerResult<bool> {
-        Ok(match self.connections.receive(stream)? {
+    fn receive(&mut self, stream: &StreamToken, client: &Client, io: &IoContext<Message>) -> IoHandlerResult<bool> {
+        let read_at = SystemTime::now();
+        let received = match self.connections.receive(stream) {
+            Err(ConnectionError::StreamError(StreamError::CorruptedFrame)) => {
+                metrics::count_corrupted_frame();
+                cwarn!(NET, "Closing {} since it sent a corrupted frame", stream);
+                if self.disconnect(stream, DisconnectReason::ProtocolViolation, client)? {
+                    io.deregister_stream(*stream)?;
+                }
+                return Err(Error::CorruptedFrame(*stream).into())
+            }
+            Err(ConnectionError::UnauthenticatedMessage) => {
+                cwarn!(NET, "Closing {} since it sent a forged message", stream);
+                if self.disconnect(stream, DisconnectReason::ProtocolViolation, client)? {
+                    io.deregister_stream(*stream)?;
+                }
+                return Err(Error::ForgedMessage(*stream).into())
+            }
+            received => received?,
+        };
+        Ok(match received {
             None => false,
             Some(ReceivedMessage::Ack {
+                observed_address,
                 ..
             }) => {
+                let public = self.connections.remote_public(&stream).ok_or(Error::InvalidStream(*stream))?;
+                if !self.is_allowed(&public) {
+                    cwarn!(NET, "Rejecting {} since its key {:?} is not in the allowlist", stream, public);
+                    if self.disconnect(stream, DisconnectReason::NotAllowed, client)? {
+                        io.deregister_stream(*stream)?;
+                    }
+                    return Ok(false)
+                }
                 if !self.connections.establish_wait_ack_connection(stream) {
                     return Err(Error::InvalidStream(*stream).into())
                 }
                 let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                self.observed_addresses.observe(node_id, observed_address.ip());
+                self.update_external_address();
+                let envelope_version =
+                    self.connections.envelope_version(&stream).ok_or(Error::InvalidStream(*stream))?;
+                client.set_peer_identity(&node_id, public);
+                client.set_peer_envelope_version(&node_id, envelope_version);
                 client.on_node_added(&node_id);
                 true
             }
@@ -196,11 +448,15 @@ impl Manager {
                 let message = rlp.as_val::<NetworkMessage>()?;
 
                 match message {
-                    NetworkMessage::Handshake(HandshakeMessage::Sync {
-                        port,
-                        node_id,
-                        ..
-                    }) => {
+                    NetworkMessage::Handshake(sync) => {
+                        let (port, node_id) = match sync {
+                            HandshakeMessage::Sync {
+                                port,
+                                node_id,
+                                ..
+                            } => (port, node_id),
+                            _ => unreachable!(),
+                        };
                         let remote_addr = self.connections
                             .remote_addr_of_waiting_sync(stream)
                             .ok_or(Error::General("Cannot find remote address"))?;
@@ -220,19 +476,95 @@ impl Manager {
                         if !signed_message.is_valid(&session) {
                             return Err(Error::InvalidSign.into())
                         }
+                        if !sync.is_authenticated(&session) {
+                            return Err(Error::InvalidIdentity.into())
+                        }
+                        if !self.is_allowed(sync.public()) {
+                            cwarn!(
+                                NET,
+                                "Rejecting {} from {} since its key {:?} is not in the allowlist",
+                                stream,
+                                remote_addr,
+                                sync.public()
+                            );
+                            // The session is needed to tell the reason
+                            self.connections.ready_session(stream, remote_node_id, *sync.public(), session);
+                            if self.disconnect(stream, DisconnectReason::NotAllowed, client)? {
+                                io.deregister_stream(*stream)?;
+                            }
+                            return Ok(false)
+                        }
 
-                        self.routing_table.establish(&remote_addr);
-                        self.connections.ready_session(stream, remote_node_id, session);
+                        if self.routing_table.establish(&remote_addr).is_none() {
+                            return Err(Error::General("Session is already in use").into())
+                        }
+                        self.peer_addresses.insert(remote_node_id, remote_addr);
+                        self.connections.ready_session(stream, remote_node_id, *sync.public(), session);
                         true
                     }
                     _ => unreachable!(),
                 }
             }
             Some(ReceivedMessage::Extension(msg)) => {
+                let span = Span::root_since("p2p.receive", read_at);
+                // The message is already read from the socket
+                drop(Span::child_of_since(&span.context(), "p2p.read", read_at));
+
+                match self.connections.admit(stream, msg.data().len(), Instant::now()) {
+                    Some(Admission::Accepted) => {}
+                    Some(Admission::Throttled) => {
+                        metrics::count_throttled_message();
+                        self.count_drop(stream, msg.extension_name(), DropReason::Throttled, client);
+                        ctrace!(NET, "Dropping a message from {} which exceeds the rate limit", stream);
+                        return Ok(true)
+                    }
+                    Some(Admission::Exceeded) => {
+                        metrics::count_rate_limited_peer();
+                        self.count_drop(stream, msg.extension_name(), DropReason::Throttled, client);
+                        cwarn!(NET, "Closing {} since it kept exceeding the rate limit", stream);
+                        if self.disconnect(stream, DisconnectReason::RateLimitExceeded, client)? {
+                            io.deregister_stream(*stream)?;
+                        }
+                        return Err(Error::RateLimitExceeded(*stream).into())
+                    }
+                    None => return Err(Error::InvalidStream(*stream).into()),
+                }
+
                 let session = self.connections.established_session(stream).ok_or(Error::General("Invalid stream"))?;
+                let envelope_version =
+                    self.connections.envelope_version(stream).ok_or(Error::InvalidStream(*stream))?;
+                let cipher = self.connections.cipher_suite(stream).ok_or(Error::InvalidStream(*stream))?;
                 // FIXME: check version of extension
-                let message = msg.unencrypted_data(&session).map_err(Error::from)?;
+                let message = {
+                    let _decode = span.child("p2p.decode");
+                    let data = match msg.unencrypted_data(&session, cipher) {
+                        Ok(data) => data,
+                        Err(err) => {
+                            self.count_drop(stream, msg.extension_name(), DropReason::Undecodable, client);
+                            return Err(Error::from(err).into())
+                        }
+                    };
+                    match open_envelope(envelope_version, msg.extension_name(), data) {
+                        Ok(message) => message,
+                        Err(err) => {
+                            self.count_drop(stream, msg.extension_name(), DropReason::Undecodable, client);
+                            return Err(err.into())
+                        }
+                    }
+                };
                 let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                // The message is captured here, since a sealed message is opened only once
+                capture::record(CaptureDirection::Inbound, &node_id, msg.extension_name(), &message);
+                if !client.has_extension(msg.extension_name()) {
+                    self.connections.count_drop(stream, DropReason::UnknownExtension);
+                    ctrace!(NET, "Dropping the message of unknown {} from {}", msg.extension_name(), stream);
+                    return Ok(true)
+                }
+
+                let mut dispatch = span.child("p2p.dispatch");
+                dispatch.set_attribute("extension", msg.extension_name().clone());
+                dispatch.set_attribute("from", format!("{:?}", node_id));
+                let _entered = dispatch.enter();
                 client.on_message(msg.extension_name(), &node_id, &message);
                 true
             }
@@ -240,12 +572,35 @@ impl Manager {
                 match msg.body() {
                     NegotiationBody::Request {
                         ref extension_name,
-                        ..
+                        extension_version,
                     } => {
                         let seq = msg.seq();
-                        // FIXME: version negotiation
-                        if self.connections.enqueue_negotiation_allowed(stream, seq) {
+                        let version = client.extension_version(extension_name);
+                        if version.is_none() {
+                            // The extensions can be deregistered while the node is running
+                            ctrace!(NET, "Denying the negotiation of the unknown extension {}", extension_name);
+                            self.connections.count_drop(stream, DropReason::UnknownExtension);
+                            if !self.connections.enqueue_negotiation_denied(stream, seq) {
+                                cwarn!(NET, "Cannot enqueue negotiation message for {}", stream);
+                            }
+                        } else if version != Some(*extension_version) {
+                            ctrace!(
+                                NET,
+                                "Denying the version {} of {} from {}",
+                                extension_version,
+                                extension_name,
+                                stream
+                            );
+                            if !self.connections.enqueue_negotiation_denied(stream, seq) {
+                                cwarn!(NET, "Cannot enqueue negotiation message for {}", stream);
+                            }
+                        } else if self.connections.enqueue_negotiation_allowed(stream, seq) {
                             let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                            self.connections.add_negotiated_extension(
+                                stream,
+                                extension_name.clone(),
+                                *extension_version,
+                            );
                             client.on_negotiated(extension_name, &node_id);
                         } else {
                             cwarn!(NET, "Cannot enqueue negotiation message for {}", stream);
@@ -253,8 +608,9 @@ impl Manager {
                     }
                     NegotiationBody::Allowed => {
                         let seq = msg.seq();
-                        if let Some(name) = self.connections.remove_requested_negotiation(stream, &seq) {
+                        if let Some((name, version)) = self.connections.remove_requested_negotiation(stream, &seq) {
                             let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                            self.connections.add_negotiated_extension(stream, name.clone(), version);
                             client.on_negotiation_allowed(&name, &node_id);
                         } else {
                             ctrace!(NET, "Negotiation::Allowed message received from non requested seq");
@@ -262,7 +618,7 @@ impl Manager {
                     }
                     NegotiationBody::Denied(_) => {
                         let seq = msg.seq();
-                        if let Some(name) = self.connections.remove_requested_negotiation(stream, &seq) {
+                        if let Some((name, _)) = self.connections.remove_requested_negotiation(stream, &seq) {
                             let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
                             client.on_negotiation_denied(&name, &node_id);
                         } else {
@@ -272,27 +628,359 @@ impl Manager {
                 };
                 true
             }
+            Some(ReceivedMessage::Relay(RelayMessage::Introduce(_, target))) => {
+                let requester = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                self.introduce(&requester, &target, io)?;
+                true
+            }
+            Some(ReceivedMessage::Relay(RelayMessage::Punch(_, peer))) => {
+                // A node punches a hole only to the peer which it asked the relay about,
+                // so both peers ask the relay to be introduced to each other to open their NATs.
+                let relay = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                if self.requested_introductions.get(&peer) != Some(&relay) {
+                    ctrace!(NET, "Punch to {:?} from {} is ignored since it's not requested", peer, relay);
+                    return Ok(true)
+                }
+                self.requested_introductions.remove(&peer);
+                cinfo!(NET, "Punching a hole to {:?}", peer);
+                // Both nodes send the session requests at the same time to open their NATs
+                self.session_initiator
+                    .send(SessionInitiatorMessage::ConnectTo(peer.clone()))
+                    .map_err(|_| Error::General("Cannot request session"))?;
+                self.punched_addresses.push(peer);
+                io.register_timer_once(DIAL_PUNCHED_TOKEN, DIAL_PUNCHED_MS)?;
+                true
+            }
+            Some(ReceivedMessage::Disconnect(reason)) => {
+                cinfo!(NET, "{} closed the connection: {}", stream, reason);
+                if self.close(stream, Some(reason), client)? {
+                    io.deregister_stream(*stream)?;
+                }
+                // Nothing can be read from the closed connection
+                false
+            }
         })
     }
 
+    fn introduce(&self, requester: &NodeId, target: &SocketAddr, io: &IoContext<Message>) -> IoHandlerResult<()> {
+        if !self.relay {
+            ctrace!(NET, "Introduction request from {:?} is ignored since this node is not a relay", requester);
+            return Ok(())
+        }
+        let requester_address = self.peer_addresses.get(requester).ok_or(Error::InvalidNode(*requester))?;
+        let target_node_id = convert_to_node_id(&target.ip(), target.port());
+        let target_token = match self.connections.stream_token(&target_node_id) {
+            Some(token) => token,
+            None => {
+                ctrace!(NET, "Cannot introduce {:?} to {:?} which is not connected", requester, target);
+                return Ok(())
+            }
+        };
+        let requester_token = self.connections.stream_token(requester).ok_or(Error::InvalidNode(*requester))?;
+
+        cinfo!(NET, "Introducing {:?} to {:?}", requester_address, target);
+        if !self.connections.enqueue_relay_message(&target_token, RelayMessage::punch(requester_address.clone())) {
+            return Err(Error::InvalidStream(target_token).into())
+        }
+        io.update_registration(target_token)?;
+        if !self.connections.enqueue_relay_message(&requester_token, RelayMessage::punch(target.clone())) {
+            return Err(Error::InvalidStream(requester_token).into())
+        }
+        Ok(())
+    }
+
+    // The drops of the unknown extensions are only counted for the connections
+    fn count_drop(&mut self, stream: &StreamToken, extension_name: &String, reason: DropReason, client: &Client) {
+        self.connections.count_drop(stream, reason);
+        self.count_extension_drop(extension_name, reason, client);
+    }
+
+    fn count_extension_drop(&mut self, extension_name: &String, reason: DropReason, client: &Client) {
+        if client.has_extension(extension_name) {
+            self.extension_drops.entry(extension_name.clone()).or_insert_with(DropCounts::default).count(reason);
+        }
+    }
+
+    fn drops(&self, client: &Client) -> DropReport {
+        let mut extensions = self.extension_drops.clone();
+        for (extension_name, drops) in client.worker_drops() {
+            extensions.entry(extension_name).or_insert_with(DropCounts::default).merge(&drops);
+        }
+        DropReport {
+            connections: self.connections.drops(),
+            extensions,
+        }
+    }
+
+    fn peers(&self) -> Vec<PeerInfo> {
+        self.connections
+            .user_agents()
+            .into_iter()
+            .map(|(node_id, user_agent)| PeerInfo {
+                node_id,
+                address: self.peer_addresses.get(&node_id).cloned(),
+                user_agent,
+            })
+            .collect()
+    }
+
+    fn node_status(&self, min_peers: usize) -> NodeStatus {
+        NodeStatus {
+            listening_address: self.socket_address.clone(),
+            user_agent: self.connections.user_agent().to_string(),
+            peer_count: self.connections.established_nodes().len(),
+            min_peers,
+        }
+    }
+
+    fn dump(&self, extensions: Vec<String>) -> HandlerDump {
+        let connections = self.connections.dumps(Instant::now());
+        let pending_handshakes = connections
+            .iter()
+            .filter(|dump| dump.state == PeerState::Connecting || dump.state == PeerState::AwaitingSync)
+            .count();
+        HandlerDump {
+            connections,
+            pending_handshakes,
+            assigned_tokens: self.tokens.len(),
+            token_limit: self.tokens.limit(),
+            routing_table: self.routing_table.sizes(),
+            extensions,
+            static_peers: self.static_peers.len(),
+            requested_introductions: self.requested_introductions.len(),
+            punched_addresses: self.punched_addresses.len(),
+        }
+    }
+
+    // The inbound peers are found by their listening addresses, and the outbound peers by the dialed addresses.
+    fn node_id_of(&self, peer: &RemovedPeer) -> NodeId {
+        match peer {
+            RemovedPeer::NodeId(node_id) => *node_id,
+            RemovedPeer::Address(address) => self
+                .peer_addresses
+                .iter()
+                .find(|(_, peer_address)| *peer_address == address)
+                .map(|(node_id, _)| *node_id)
+                .unwrap_or_else(|| address.clone().into()),
+        }
+    }
+
+    fn is_static_peer(&self, node_id: &NodeId) -> bool {
+        self.static_peers.contains_key(node_id)
+    }
+
+    // Returns the peer which gives its slot to a new peer.
+    // The least reputable one among the idle peers goes first, and then the misbehaving peer.
+    fn peer_to_evict(&self, client: &Client) -> Option<NodeId> {
+        let idle = self.connections.idle_nodes(self.evictable_idle_timeout, Instant::now());
+        let idle = idle.into_iter().filter(|node_id| !self.is_static_peer(node_id)).collect();
+        if let Some(node_id) = client.least_reputable_peer(idle) {
+            return Some(node_id)
+        }
+        let candidates =
+            self.connections.established_nodes().into_iter().filter(|node_id| !self.is_static_peer(node_id)).collect();
+        client.worst_peer(candidates)
+    }
+
+    fn idle_timeout_of(&self, node_id: &NodeId) -> Option<Duration> {
+        if self.is_static_peer(node_id) {
+            self.static_peer_idle_timeout
+        } else {
+            self.idle_timeout
+        }
+    }
+
+    fn has_idle_timeout(&self) -> bool {
+        self.idle_timeout.is_some() || self.static_peer_idle_timeout.is_some()
+    }
+
+    // Returns the peers which sent no extension message for their idle timeouts
+    fn idle_peers(&self) -> Vec<NodeId> {
+        self.connections.timed_out_nodes(|node_id| self.idle_timeout_of(node_id), Instant::now())
+    }
+
+    // Returns the static peers which are not connected and have their sessions ready
+    fn dial_static_peers(&self) -> IoHandlerResult<Vec<SocketAddr>> {
+        let mut dialable = Vec::new();
+        for (node_id, address) in self.static_peers.iter() {
+            if self.connections.stream_token(node_id).is_some() {
+                continue
+            }
+            if self.routing_table.unestablished_session(address).is_some() {
+                dialable.push(address.clone());
+            } else if !self.routing_table.contains(address) {
+                self.request_session(address)?;
+            }
+        }
+        Ok(dialable)
+    }
+
+    // Returns the addresses to dial, or None if the bootstrapping is over
+    fn bootstrap(&mut self) -> IoHandlerResult<Option<Vec<SocketAddr>>> {
+        let number_of_peers = self.connections.len();
+        if let Some(addresses) = self.bootstrap.next_stage(number_of_peers, Instant::now()) {
+            cinfo!(NET, "Bootstrapping from {:?}", addresses);
+            for address in addresses.iter().filter(|address| !self.routing_table.contains(address)) {
+                self.request_session(address)?;
+            }
+        }
+        if self.bootstrap.is_done() {
+            cinfo!(NET, "Bootstrapping is done with {} peers", number_of_peers);
+            return Ok(None)
+        }
+        // The sessions are created asynchronously, so the addresses are dialed once they are ready
+        let mut dialable = Vec::new();
+        for address in self.bootstrap.dialing() {
+            if self.connections.stream_token(&address.into()).is_some() {
+                continue
+            }
+            if self.routing_table.unestablished_session(address).is_some() {
+                dialable.push(address.clone());
+            }
+        }
+        Ok(Some(dialable))
+    }
+
+    fn save_peers(&self) {
+        let path = match &self.peer_store_path {
+            Some(path) => path,
+            None => return,
+        };
+        // The peers are remembered by their records, so that only the peers who announced themselves are stored
+        let records: Vec<_> = self
+            .connections
+            .established_nodes()
+            .iter()
+            .filter_map(|node_id| self.connections.stream_token(node_id))
+            .filter_map(|stream| self.connections.remote_public(&stream))
+            .filter_map(|public| self.routing_table.record(&public))
+            .collect();
+        // Keeps the peers of the last run while this node is offline
+        if records.is_empty() {
+            return
+        }
+        if let Err(err) = peer_store::save(path, &records) {
+            cwarn!(NET, "{}", err);
+        }
+    }
+
+    fn redial_static_peer(&self, node_id: &NodeId) -> IoHandlerResult<()> {
+        if let Some(address) = self.static_peers.get(node_id) {
+            cinfo!(NET, "Redialing the static peer {:?}", address);
+            self.routing_table.remove_node(address.clone());
+            self.request_session(address)?;
+        }
+        Ok(())
+    }
+
+    fn request_session(&self, address: &SocketAddr) -> IoHandlerResult<()> {
+        self.session_initiator
+            .send(SessionInitiatorMessage::ConnectTo(address.clone()))
+            .map_err(|_| Error::General("Cannot request session"))?;
+        Ok(())
+    }
+
+    fn update_external_address(&self) {
+        let external_address = self.observed_addresses.consensus().map(|ip| SocketAddr::new(ip, self.port));
+        if self.routing_table.external_address() != external_address {
+            cinfo!(NET, "External address is changed to {:?}", external_address);
+            self.routing_table.set_external_address(external_address);
+            let capabilities =
+                self.routing_table.local_record().map(|record| record.capabilities().to_vec()).unwrap_or_default();
+            self.update_local_record(capabilities);
+        }
+    }
+
+    // The peers learn the external address of this node from the record, if it's known
+    // The sequence is the time in milliseconds, so that it keeps increasing after a restart.
+    fn update_local_record(&self, capabilities: Vec<String>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("The clock is after the epoch");
+        let now = now.as_secs() * 1000 + u64::from(now.subsec_nanos() / 1_000_000);
+        let sequence = match self.routing_table.local_record() {
+            Some(record) if now <= record.sequence() => record.sequence() + 1,
+            _ => now,
+        };
+        let address = self.routing_table.external_address().unwrap_or_else(|| self.socket_address.clone());
+        let record = NodeRecord::new(&self.key_pair, sequence, vec![address], self.network_id, capabilities);
+        self.routing_table.set_local_record(record);
+    }
+
+    // Tells the peer why before closing the connection
+    fn disconnect(&mut self, stream: &StreamToken, reason: DisconnectReason, client: &Client) -> IoHandlerResult<bool> {
+        cinfo!(NET, "Closing the connection to {}: {}", stream, reason);
+        // The connection is closed anyway, even if the peer cannot hear the reason
+        if let Err(err) = self.connections.disconnect(stream, reason) {
+            cdebug!(NET, "Cannot send the disconnect reason to {}: {:?}", stream, err);
+        }
+        self.close(stream, Some(reason), client)
+    }
+
+    // Return false if the stream is already closing
+    // The reason is None if the connection is lost without the reason, e.g. the peer hung up
+    fn close(
+        &mut self,
+        stream: &StreamToken,
+        reason: Option<DisconnectReason>,
+        client: &Client,
+    ) -> IoHandlerResult<bool> {
+        let (previous, node_id) = match self.connections.close(stream) {
+            Some(closed) => closed,
+            None => return Ok(false),
+        };
+        ctrace!(NET, "Closing {} which was {:?}", stream, previous);
+        match (previous, node_id) {
+            (PeerState::Established, Some(node_id)) => {
+                if let Some(reason) = reason {
+                    client.on_node_disconnected(&node_id, reason);
+                }
+                client.on_node_removed(&node_id, reason);
+                client.remove_peer_identity(&node_id);
+                self.forget_peer(&node_id);
+                self.redial_static_peer(&node_id)?;
+            }
+            (PeerState::Connecting, Some(node_id)) => {
+                self.forget_peer(&node_id);
+                self.redial_static_peer(&node_id)?;
+            }
+            // The node id of the accepted peer is not verified yet
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn forget_peer(&mut self, node_id: &NodeId) {
+        self.peer_addresses.remove(node_id);
+        self.observed_addresses.forget(node_id);
+        self.update_external_address();
+    }
+
     fn send(&mut self, stream: &StreamToken, client: &Client) -> IoHandlerResult<bool> {
-        let (connection_type, remain) = self.connections.send(stream)?;
-        Ok(match connection_type {
-            ConnectionType::None => return Err(Error::InvalidStream(stream.clone()).into()),
-            ConnectionType::AckWaiting => {
+        let (state, remain) = self.connections.send(stream)?.ok_or(Error::InvalidStream(*stream))?;
+        Ok(match state {
+            PeerState::Connecting => {
                 debug_assert!(!remain);
                 false
             }
-            ConnectionType::SyncWaiting => {
-                // Ack message was sent
+            PeerState::AwaitingSync => {
                 debug_assert!(!remain);
-                self.connections.establish_wait_sync_connection(stream);
+                if !self.connections.is_ack_sent(stream) {
+                    return Ok(false)
+                }
+                if !self.connections.establish_wait_sync_connection(stream) {
+                    return Err(Error::General("Already connected").into())
+                }
                 let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                let public = self.connections.remote_public(&stream).ok_or(Error::InvalidStream(*stream))?;
+                let envelope_version =
+                    self.connections.envelope_version(&stream).ok_or(Error::InvalidStream(*stream))?;
 
+                client.set_peer_identity(&node_id, public);
+                client.set_peer_envelope_version(&node_id, envelope_version);
                 client.on_node_added(&node_id);
                 false
             }
-            ConnectionType::Established => remain,
+            PeerState::Established => remain,
+            PeerState::Closing => false,
         })
     }
 }
@@ -308,16 +996,27 @@ pub struct Handler {
 
 impl Handler {
     pub fn try_new(
-        socket_address: SocketAddr,
+        config: Config,
         client: Arc<Client>,
         routing_table: Arc<RoutingTable>,
-        min_peers: usize,
-        max_peers: usize,
+        session_initiator: IoChannel<SessionInitiatorMessage>,
     ) -> ::std::result::Result<Self, String> {
+        let socket_address = config.address.clone();
+        let min_peers = config.min_peers;
+        let max_peers = config.max_peers;
         if MAX_CONNECTIONS < max_peers {
             return Err(format!("Max peers must be less than {}", MAX_CONNECTIONS))
         }
-        let manager = Mutex::new(Manager::listen(&socket_address, routing_table).expect("Cannot listen TCP port"));
+        let stored_peers = match &config.peer_store_path {
+            Some(path) => peer_store::load(path)?.iter().flat_map(|record| record.addresses().to_vec()).collect(),
+            None => vec![],
+        };
+        let bootnodes = config.bootnodes.clone();
+        let bootstrap =
+            Bootstrap::new(bootnodes, stored_peers, min_peers, Duration::from_millis(BOOTSTRAP_STAGE_TIMEOUT_MS));
+        let manager = Mutex::new(
+            Manager::listen(config, routing_table, session_initiator, bootstrap).expect("Cannot listen TCP port"),
+        );
         debug_assert!(max_peers < MAX_CONNECTIONS);
         Ok(Self {
             socket_address,
@@ -333,7 +1032,24 @@ impl Handler {
 impl IoHandler<Message> for Handler {
     fn initialize(&self, io: &IoContext<Message>) -> IoHandlerResult<()> {
         io.register_stream(ACCEPT_TOKEN)?;
+        if self.manager.lock().websocket_listener.is_some() {
+            io.register_stream(WEBSOCKET_ACCEPT_TOKEN)?;
+        }
         io.register_timer_once(CREATE_CONNECTIONS_TOKEN, PULL_CONNECTIONS_MS)?;
+        io.register_timer(DIAL_STATIC_PEERS_TOKEN, DIAL_STATIC_PEERS_MS)?;
+        io.register_timer(EXPIRE_NEGOTIATIONS_TOKEN, EXPIRE_NEGOTIATIONS_MS)?;
+        if self.manager.lock().has_idle_timeout() {
+            io.register_timer(SWEEP_IDLE_PEERS_TOKEN, SWEEP_IDLE_PEERS_MS)?;
+        }
+        if !self.manager.lock().bootstrap.is_done() {
+            io.register_timer(BOOTSTRAP_TOKEN, BOOTSTRAP_MS)?;
+        }
+        if self.manager.lock().peer_store_path.is_some() {
+            io.register_timer(SAVE_PEERS_TOKEN, SAVE_PEERS_MS)?;
+        }
+        io.register_timer(PUBLISH_STATUS_TOKEN, PUBLISH_STATUS_MS)?;
+        self.manager.lock().update_local_record(self.client.extension_names());
+        self.client.set_node_status(self.manager.lock().node_status(self.min_peers));
         Ok(())
     }
 
@@ -351,6 +1067,74 @@ impl IoHandler<Message> for Handler {
                 }
                 Ok(())
             }
+            DIAL_PUNCHED_TOKEN => {
+                let mut manager = self.manager.lock();
+                for address in manager.punched_addresses.drain(..) {
+                    io.message(Message::RequestConnection(address))?;
+                }
+                Ok(())
+            }
+            DIAL_STATIC_PEERS_TOKEN => {
+                let manager = self.manager.lock();
+                for address in manager.dial_static_peers()? {
+                    io.message(Message::RequestConnection(address))?;
+                }
+                Ok(())
+            }
+            SWEEP_IDLE_PEERS_TOKEN => {
+                let mut manager = self.manager.lock();
+                for node_id in manager.idle_peers() {
+                    let idle_timeout =
+                        manager.idle_timeout_of(&node_id).expect("Only the peers with timeouts are idle");
+                    cinfo!(NET, "Disconnecting {:?} which has been idle for {:?}", node_id, idle_timeout);
+                    let token = manager.connections.stream_token(&node_id).ok_or(Error::InvalidNode(node_id))?;
+                    if manager.disconnect(&token, DisconnectReason::IdleTimeout, &self.client)? {
+                        io.deregister_stream(token)?;
+                    }
+                }
+                Ok(())
+            }
+            EXPIRE_NEGOTIATIONS_TOKEN => {
+                let manager = self.manager.lock();
+                let timeout = Duration::from_millis(NEGOTIATION_TIMEOUT_MS);
+                let expired = manager.connections.expire_negotiations(timeout, MAX_NEGOTIATION_RETRIES, Instant::now());
+                for (token, expired) in expired {
+                    if expired.retried != 0 {
+                        io.update_registration(token)?;
+                    }
+                    if expired.failed.is_empty() {
+                        continue
+                    }
+                    let node_id = manager.connections.node_id(&token).ok_or(Error::InvalidStream(token))?;
+                    for name in expired.failed {
+                        cwarn!(NET, "{:?} didn't reply to the negotiation of {}", node_id, name);
+                        self.client.on_negotiation_failed(&name, &node_id);
+                    }
+                }
+                Ok(())
+            }
+            BOOTSTRAP_TOKEN => {
+                let mut manager = self.manager.lock();
+                match manager.bootstrap()? {
+                    Some(addresses) => {
+                        for address in addresses {
+                            io.message(Message::RequestConnection(address))?;
+                        }
+                    }
+                    None => io.clear_timer(BOOTSTRAP_TOKEN)?,
+                }
+                Ok(())
+            }
+            SAVE_PEERS_TOKEN => {
+                let manager = self.manager.lock();
+                manager.save_peers();
+                Ok(())
+            }
+            PUBLISH_STATUS_TOKEN => {
+                let status = self.manager.lock().node_status(self.min_peers);
+                self.client.set_node_status(status);
+                Ok(())
+            }
             _ => unreachable!(),
         }
     }
@@ -360,9 +1144,20 @@ impl IoHandler<Message> for Handler {
             Message::RequestConnection(socket_address) => {
                 let mut manager = self.manager.lock();
                 let number_of_connections = manager.connections.len();
-                if self.max_peers <= manager.connections.len() {
-                    ctrace!(NET, "Already has maximum peers({})", number_of_connections);
-                    return Ok(())
+                let is_static_peer = manager.is_static_peer(&socket_address.clone().into());
+                if self.max_peers <= manager.connections.len() && !is_static_peer {
+                    let evicted = match manager.peer_to_evict(&self.client) {
+                        Some(evicted) => evicted,
+                        None => {
+                            ctrace!(NET, "Already has maximum peers({})", number_of_connections);
+                            return Ok(())
+                        }
+                    };
+                    cinfo!(NET, "Evicting {:?} to connect to {:?}", evicted, socket_address);
+                    let token = manager.connections.stream_token(&evicted).ok_or(Error::InvalidNode(evicted))?;
+                    if manager.disconnect(&token, DisconnectReason::TooManyPeers, &self.client)? {
+                        io.deregister_stream(token)?;
+                    }
                 }
 
                 ctrace!(NET, "Connecting to {:?}", socket_address);
@@ -370,6 +1165,36 @@ impl IoHandler<Message> for Handler {
                 io.register_stream(token)?;
                 Ok(())
             }
+            Message::RequestDisconnection(node_id) => {
+                let mut manager = self.manager.lock();
+                if manager.is_static_peer(&node_id) {
+                    ctrace!(NET, "The static peer {:?} is not disconnected", node_id);
+                    return Ok(())
+                }
+                let token = manager.connections.stream_token(&node_id).ok_or(Error::InvalidNode(*node_id))?;
+                ctrace!(NET, "Disconnecting from {:?}", node_id);
+                if manager.disconnect(&token, DisconnectReason::Requested, &self.client)? {
+                    io.deregister_stream(token)?;
+                }
+
+                if let Some(address) = manager.routing_table.unestablished_addresses(1).pop() {
+                    io.message(Message::RequestConnection(address))?;
+                }
+                Ok(())
+            }
+            Message::RequestIntroduction {
+                relay,
+                target,
+            } => {
+                let mut manager = self.manager.lock();
+                let token = manager.connections.stream_token(&relay).ok_or(Error::InvalidNode(*relay))?;
+                if !manager.connections.enqueue_relay_message(&token, RelayMessage::introduce(target.clone())) {
+                    return Err(Error::InvalidStream(token).into())
+                }
+                manager.requested_introductions.insert(target.clone(), *relay);
+                io.update_registration(token)?;
+                Ok(())
+            }
             Message::RequestNegotiation {
                 node_id,
                 extension_name,
@@ -388,26 +1213,129 @@ impl IoHandler<Message> for Handler {
                 extension_name,
                 need_encryption,
                 data,
+                trace,
             } => {
                 let mut manager = self.manager.lock();
-                let token = manager.connections.stream_token(node_id).ok_or(Error::InvalidNode(*node_id))?;
-                if !manager.connections.enqueue_extension_message(&token, extension_name, *need_encryption, data) {
-                    return Err(Error::InvalidStream(token).into())
+                let token = match manager.connections.stream_token(node_id) {
+                    Some(token) => token,
+                    None => {
+                        manager.count_extension_drop(extension_name, DropReason::NotConnected, &self.client);
+                        return Err(Error::InvalidNode(*node_id).into())
+                    }
+                };
+                // The span ends when the message is written to the socket
+                let span = match trace {
+                    Some(parent) if parent.is_sampled() => {
+                        let mut span = Span::child_of(parent, "p2p.write");
+                        span.set_attribute("extension", extension_name.clone());
+                        span.set_attribute("to", format!("{:?}", node_id));
+                        Some(span)
+                    }
+                    _ => None,
+                };
+                let enqueued = manager.connections.enqueue_extension_message(
+                    &token,
+                    extension_name,
+                    *need_encryption,
+                    data.clone(),
+                    span,
+                );
+                match enqueued {
+                    Ok(()) => {}
+                    Err(DropReason::NotConnected) => {
+                        manager.count_extension_drop(extension_name, DropReason::NotConnected, &self.client);
+                        return Err(Error::InvalidStream(token).into())
+                    }
+                    Err(reason) => {
+                        manager.count_extension_drop(extension_name, reason, &self.client);
+                        return Ok(())
+                    }
                 }
                 io.update_registration(token)?;
                 Ok(())
             }
+            Message::ReportDrops(reply) => {
+                let manager = self.manager.lock();
+                reply.send(manager.drops(&self.client));
+                Ok(())
+            }
+            Message::ReportPeers(reply) => {
+                let manager = self.manager.lock();
+                reply.send(manager.peers());
+                Ok(())
+            }
+            Message::ReportConnections(reply) => {
+                let manager = self.manager.lock();
+                reply.send(manager.connections.infos(Instant::now()));
+                Ok(())
+            }
+            Message::ReportDump(reply) => {
+                let manager = self.manager.lock();
+                reply.send(manager.dump(self.client.extension_names()));
+                Ok(())
+            }
+            Message::RemovePeer(peer, reply) => {
+                let mut manager = self.manager.lock();
+                let node_id = manager.node_id_of(peer);
+                if let Some(address) = manager.static_peers.remove(&node_id) {
+                    cinfo!(NET, "{:?} is not a static peer anymore", address);
+                }
+                let token = match manager.connections.stream_token(&node_id) {
+                    Some(token) => token,
+                    None => {
+                        reply.send(false);
+                        return Ok(())
+                    }
+                };
+                cinfo!(NET, "Removing the peer {:?}", node_id);
+                if manager.disconnect(&token, DisconnectReason::Requested, &self.client)? {
+                    io.deregister_stream(token)?;
+                }
+                reply.send(true);
+                Ok(())
+            }
+            Message::AddLocalPeer(address) => {
+                let manager = self.manager.lock();
+                if manager.connections.stream_token(&address.into()).is_some() {
+                    return Ok(())
+                }
+                // The peer is announced again later, so it's dialed once the session is ready
+                if manager.routing_table.unestablished_session(address).is_some() {
+                    if manager.connections.len() < self.max_peers {
+                        io.message(Message::RequestConnection(address.clone()))?;
+                    }
+                } else if !manager.routing_table.contains(address) {
+                    cinfo!(NET, "Found {} on the local network", address);
+                    manager.request_session(address)?;
+                }
+                Ok(())
+            }
+            Message::AddSeedPeers(addresses) => {
+                let mut manager = self.manager.lock();
+                if manager.bootstrap.add_stage(addresses.clone()) {
+                    io.register_timer(BOOTSTRAP_TOKEN, BOOTSTRAP_MS)?;
+                }
+                Ok(())
+            }
+            Message::UpdateLocalRecord => {
+                let manager = self.manager.lock();
+                manager.update_local_record(self.client.extension_names());
+                Ok(())
+            }
         }
     }
 
     fn stream_hup(&self, io: &IoContext<Message>, stream: StreamToken) -> IoHandlerResult<()> {
         match stream {
-            ACCEPT_TOKEN => unreachable!(),
+            ACCEPT_TOKEN | WEBSOCKET_ACCEPT_TOKEN => unreachable!(),
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
-                let manager = self.manager.lock();
-                let node_id = manager.connections.node_id(&stream).ok_or(Error::InvalidStream(stream))?;
-                self.client.on_node_removed(&node_id);
-                io.deregister_stream(stream)?;
+                let mut manager = self.manager.lock();
+                if manager.connections.state(&stream).is_none() {
+                    return Err(Error::InvalidStream(stream).into())
+                }
+                if manager.close(&stream, None, &self.client)? {
+                    io.deregister_stream(stream)?;
+                }
             }
             _ => unreachable!(),
         }
@@ -423,6 +1351,12 @@ impl IoHandler<Message> for Handler {
                 }
                 break
             },
+            WEBSOCKET_ACCEPT_TOKEN => {
+                let mut manager = self.manager.lock();
+                if let Some(token) = manager.accept_websocket()? {
+                    io.register_stream(token)?;
+                }
+            }
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
                 let _f = finally(|| {
                     if let Err(err) = io.update_registration(stream) {
@@ -431,7 +1365,7 @@ impl IoHandler<Message> for Handler {
                 });
                 loop {
                     let mut manager = self.manager.lock();
-                    if !manager.receive(&stream, &self.client)? {
+                    if !manager.receive(&stream, &self.client, io)? {
                         break
                     }
                 }
@@ -443,7 +1377,7 @@ impl IoHandler<Message> for Handler {
 
     fn stream_writable(&self, io: &IoContext<Message>, stream: StreamToken) -> IoHandlerResult<()> {
         match stream {
-            ACCEPT_TOKEN => unreachable!(),
+            ACCEPT_TOKEN | WEBSOCKET_ACCEPT_TOKEN => unreachable!(),
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
                 let _f = finally(|| {
                     if let Err(err) = io.update_registration(stream) {
@@ -462,22 +1396,24 @@ impl IoHandler<Message> for Handler {
         Ok(())
     }
 
-    fn register_stream(
-        &self,
-        stream: StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn register_stream(&self, stream: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
         match stream {
             ACCEPT_TOKEN => {
                 let manager = self.manager.lock();
-                event_loop.register(&manager.listener, reg, Ready::readable(), PollOpt::edge())?;
+                poll.register(&manager.listener, reg, Ready::readable(), PollOpt::edge())?;
                 ctrace!(NET, "TCP connection starts for {:?}", self.socket_address);
                 Ok(())
             }
+            WEBSOCKET_ACCEPT_TOKEN => {
+                let manager = self.manager.lock();
+                let listener = manager.websocket_listener.as_ref().expect("Registered only if it listens WebSocket");
+                poll.register(listener, reg, Ready::readable(), PollOpt::edge())?;
+                ctrace!(NET, "WebSocket connection starts");
+                Ok(())
+            }
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
                 let mut manager = self.manager.lock();
-                manager.register_stream(stream, reg, event_loop)?;
+                manager.register_stream(stream, reg, poll)?;
                 Ok(())
             }
             _ => {
@@ -486,19 +1422,14 @@ impl IoHandler<Message> for Handler {
         }
     }
 
-    fn update_stream(
-        &self,
-        stream: StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn update_stream(&self, stream: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
         match stream {
-            ACCEPT_TOKEN => {
+            ACCEPT_TOKEN | WEBSOCKET_ACCEPT_TOKEN => {
                 unreachable!();
             }
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
                 let mut manager = self.manager.lock();
-                manager.reregister_stream(stream, reg, event_loop)?;
+                manager.reregister_stream(stream, reg, poll)?;
                 Ok(())
             }
             _ => {
@@ -507,16 +1438,12 @@ impl IoHandler<Message> for Handler {
         }
     }
 
-    fn deregister_stream(
-        &self,
-        stream: StreamToken,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn deregister_stream(&self, stream: StreamToken, poll: &Poll) -> IoHandlerResult<()> {
         match stream {
-            ACCEPT_TOKEN => unreachable!(),
+            ACCEPT_TOKEN | WEBSOCKET_ACCEPT_TOKEN => unreachable!(),
             FIRST_CONNECTION_TOKEN...LAST_CONNECTION_TOKEN => {
                 let mut manager = self.manager.lock();
-                manager.deregister_stream(stream, event_loop)?;
+                manager.deregister_stream(stream, poll)?;
             }
             _ => unreachable!(),
         }
@@ -524,8 +1451,8 @@ impl IoHandler<Message> for Handler {
     }
 }
 
-impl From<SymmetricCipherError> for Error {
-    fn from(err: SymmetricCipherError) -> Self {
-        Error::SymmetricCipherError(err)
+impl From<CipherError> for Error {
+    fn from(err: CipherError) -> Self {
+        Error::CipherError(err)
     }
 }
@@ -21,38 +21,112 @@ use mio::net::TcpListener;
 use mio::{Poll, PollOpt, Ready, Token};
 
 use super::super::SocketAddr;
+use super::socket_options::SocketOptions;
 use super::stream::Stream;
+#[cfg(test)]
+use super::transport::MemoryListener;
+use super::transport::WebSocketStream;
+
+enum Inner {
+    Tcp(TcpListener),
+    // Accepts the light clients which speak WebSocket
+    WebSocket(TcpListener),
+    #[cfg(test)]
+    Memory(MemoryListener),
+}
 
 pub struct Listener {
-    listener: TcpListener,
+    listener: Inner,
 }
 
 impl Listener {
     pub fn bind(socket_address: &SocketAddr) -> io::Result<Self> {
         Ok(Self {
-            listener: TcpListener::bind(socket_address.into())?,
+            listener: Inner::Tcp(TcpListener::bind(socket_address.into())?),
+        })
+    }
+
+    pub fn bind_websocket(socket_address: &SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            listener: Inner::WebSocket(TcpListener::bind(socket_address.into())?),
         })
     }
 
-    pub fn accept(&self) -> io::Result<Option<(Stream, SocketAddr)>> {
-        Ok(match self.listener.accept() {
-            Ok((stream, socket_address)) => Some((From::from(stream), From::from(socket_address))),
+    /// The options for the accepted socket are chosen by its remote address.
+    pub fn accept<F>(&self, options_for: F) -> io::Result<Option<(Stream, SocketAddr)>>
+    where
+        F: Fn(&SocketAddr) -> SocketOptions, {
+        let accepted = match &self.listener {
+            Inner::Tcp(listener) => listener.accept().and_then(|(stream, address)| {
+                options_for(&From::from(address)).apply(&stream)?;
+                Ok((Stream::from(stream), address))
+            }),
+            Inner::WebSocket(listener) => listener.accept().and_then(|(stream, address)| {
+                options_for(&From::from(address)).apply(&stream)?;
+                Ok((Stream::from(WebSocketStream::accept(stream)), address))
+            }),
+            #[cfg(test)]
+            Inner::Memory(listener) => listener.accept().map(|(stream, address)| (Stream::from(stream), address)),
+        };
+        Ok(match accepted {
+            Ok((stream, socket_address)) => Some((stream, From::from(socket_address))),
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => None,
             Err(e) => Err(e)?,
         })
     }
 }
 
+#[cfg(test)]
+impl From<MemoryListener> for Listener {
+    fn from(listener: MemoryListener) -> Self {
+        Self {
+            listener: Inner::Memory(listener),
+        }
+    }
+}
+
 impl Evented for Listener {
     fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
-        self.listener.register(poll, token, interest, opts)
+        match &self.listener {
+            Inner::Tcp(listener) | Inner::WebSocket(listener) => listener.register(poll, token, interest, opts),
+            #[cfg(test)]
+            Inner::Memory(listener) => listener.register(poll, token, interest, opts),
+        }
     }
 
     fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
-        self.listener.reregister(poll, token, interest, opts)
+        match &self.listener {
+            Inner::Tcp(listener) | Inner::WebSocket(listener) => listener.reregister(poll, token, interest, opts),
+            #[cfg(test)]
+            Inner::Memory(listener) => listener.reregister(poll, token, interest, opts),
+        }
     }
 
     fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        self.listener.deregister(poll)
+        match &self.listener {
+            Inner::Tcp(listener) | Inner::WebSocket(listener) => listener.deregister(poll),
+            #[cfg(test)]
+            Inner::Memory(listener) => listener.deregister(poll),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net;
+
+    use super::*;
+
+    #[test]
+    fn accept_a_memory_stream() {
+        let address = net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        let from = net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 3486);
+        let (listener, connector) = MemoryListener::bind(address);
+        let listener = Listener::from(listener);
+        assert!(listener.accept(|_| SocketOptions::default()).unwrap().is_none());
+
+        let (_stream, _link) = connector.connect(from, 0);
+        let (_, remote) = listener.accept(|_| SocketOptions::default()).unwrap().expect("A stream is pending");
+        assert_eq!(SocketAddr::from(from), remote);
     }
 }
@@ -0,0 +1,191 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::ProtocolId;
+use super::Version;
+
+use super::DISCONNECT_ID;
+
+/// Why a node closes the connection.
+/// The codes are a part of the protocol, so a new reason must take a new code.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Reason {
+    /// An extension or the operator asked to disconnect the node.
+    Requested,
+    /// The node is evicted to make room for another peer.
+    TooManyPeers,
+    /// The node has not sent any extension message for the idle timeout.
+    IdleTimeout,
+    /// The node kept exceeding the rate limit.
+    RateLimitExceeded,
+    /// The node sent a frame which cannot be decoded.
+    ProtocolViolation,
+    /// The public key of the node is not in the allowlist of the permissioned network.
+    NotAllowed,
+    /// The reason which this node doesn't know. The peer may run a newer version.
+    Unknown(u8),
+}
+
+impl Reason {
+    fn code(self) -> u8 {
+        match self {
+            Reason::Requested => 0,
+            Reason::TooManyPeers => 1,
+            Reason::IdleTimeout => 2,
+            Reason::RateLimitExceeded => 3,
+            Reason::ProtocolViolation => 4,
+            Reason::NotAllowed => 5,
+            Reason::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            0 => Reason::Requested,
+            1 => Reason::TooManyPeers,
+            2 => Reason::IdleTimeout,
+            3 => Reason::RateLimitExceeded,
+            4 => Reason::ProtocolViolation,
+            5 => Reason::NotAllowed,
+            code => Reason::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Requested => write!(f, "requested"),
+            Reason::TooManyPeers => write!(f, "too many peers"),
+            Reason::IdleTimeout => write!(f, "idle timeout"),
+            Reason::RateLimitExceeded => write!(f, "rate limit exceeded"),
+            Reason::ProtocolViolation => write!(f, "protocol violation"),
+            Reason::NotAllowed => write!(f, "not allowed"),
+            Reason::Unknown(code) => write!(f, "unknown reason({})", code),
+        }
+    }
+}
+
+impl Encodable for Reason {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append(&self.code());
+    }
+}
+
+impl Decodable for Reason {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(Reason::from_code(rlp.as_val()?))
+    }
+}
+
+/// The last message before a node closes the connection.
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Message {
+    Disconnect(Version, Reason),
+}
+
+impl Message {
+    pub fn disconnect(reason: Reason) -> Self {
+        Message::Disconnect(0, reason)
+    }
+
+    pub fn reason(&self) -> Reason {
+        match self {
+            Message::Disconnect(_, reason) => *reason,
+        }
+    }
+
+    fn protocol_id(&self) -> ProtocolId {
+        match self {
+            Message::Disconnect(..) => DISCONNECT_ID,
+        }
+    }
+}
+
+impl Encodable for Message {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            Message::Disconnect(version, reason) => {
+                s.begin_list(3).append(version).append(&self.protocol_id()).append(reason);
+            }
+        }
+    }
+}
+
+impl Decodable for Message {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let version: Version = rlp.val_at(0)?;
+        let protocol_id: ProtocolId = rlp.val_at(1)?;
+        let reason: Reason = rlp.val_at(2)?;
+        match protocol_id {
+            DISCONNECT_ID => Ok(Message::Disconnect(version, reason)),
+            _ => Err(DecoderError::Custom("invalid protocol id")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::{Decodable, Encodable, UntrustedRlp};
+
+    use super::*;
+
+    #[test]
+    fn protocol_id_of_disconnect_is_9() {
+        assert_eq!(0x09, Message::disconnect(Reason::Requested).protocol_id());
+    }
+
+    #[test]
+    fn encode_and_decode_disconnect() {
+        let reasons = vec![
+            Reason::Requested,
+            Reason::TooManyPeers,
+            Reason::IdleTimeout,
+            Reason::RateLimitExceeded,
+            Reason::ProtocolViolation,
+            Reason::NotAllowed,
+        ];
+        for reason in reasons {
+            let disconnect = Message::disconnect(reason);
+            let bytes = disconnect.rlp_bytes();
+
+            let rlp = UntrustedRlp::new(&bytes);
+
+            match Decodable::decode(&rlp) {
+                Ok(message) => assert_eq!(disconnect, message),
+                Err(err) => assert!(false, "{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_reason_is_decoded() {
+        let disconnect = Message::disconnect(Reason::Unknown(200));
+        let bytes = disconnect.rlp_bytes();
+
+        let rlp = UntrustedRlp::new(&bytes);
+
+        let message: Message = Decodable::decode(&rlp).unwrap();
+        assert_eq!(Reason::Unknown(200), message.reason());
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The envelope around the payloads of the extension messages, so that the payload formats can be upgraded.
+//!
+//! An envelope is `[version, extension id, flags, payload]`. The peers advertise the newest envelope version they
+//! know in the handshake and use the lower one. Version 0 means that the payload is sent without an envelope.
+
+use bytes::Bytes;
+use ccrypto::blake256;
+use rlp::{DecoderError, RlpStream, UntrustedRlp};
+
+use super::frame::shared_data;
+use super::Version;
+
+pub const ENVELOPE_VERSION: Version = 1;
+
+// No flag is defined yet. The receivers ignore the flags they don't know.
+const NO_FLAGS: u8 = 0;
+
+/// The first four bytes of the hash of the extension name
+pub fn extension_id(extension_name: &str) -> u32 {
+    let hash = blake256(extension_name);
+    hash[..4].iter().fold(0, |id, byte| (id << 8) | u32::from(*byte))
+}
+
+pub fn seal_envelope(version: Version, extension_name: &str, payload: &[u8]) -> Bytes {
+    if version == 0 {
+        return Bytes::from(payload)
+    }
+    let mut s = RlpStream::new_list(4);
+    s.append(&version).append(&extension_id(extension_name)).append(&NO_FLAGS).append(&payload);
+    Bytes::from(s.out())
+}
+
+/// Returns the payload if the envelope is of the negotiated version and for the extension.
+pub fn open_envelope(version: Version, extension_name: &str, data: Bytes) -> Result<Bytes, DecoderError> {
+    if version == 0 {
+        return Ok(data)
+    }
+    let rlp = UntrustedRlp::new(&data);
+    if rlp.item_count()? != 4 {
+        return Err(DecoderError::RlpIncorrectListLen)
+    }
+    let envelope_version: Version = rlp.val_at(0)?;
+    if envelope_version != version {
+        return Err(DecoderError::Custom("Unexpected envelope version"))
+    }
+    let id: u32 = rlp.val_at(1)?;
+    if id != extension_id(extension_name) {
+        return Err(DecoderError::Custom("Envelope of another extension"))
+    }
+    let _flags: u8 = rlp.val_at(2)?;
+    shared_data(&data, &rlp.at(3)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_is_opened_as_sealed() {
+        let sealed = seal_envelope(ENVELOPE_VERSION, "block-propagation", b"payload");
+        assert_eq!(Bytes::from(&b"payload"[..]), open_envelope(ENVELOPE_VERSION, "block-propagation", sealed).unwrap());
+    }
+
+    #[test]
+    fn version_0_has_no_envelope() {
+        let sealed = seal_envelope(0, "block-propagation", b"payload");
+        assert_eq!(Bytes::from(&b"payload"[..]), sealed);
+        assert_eq!(sealed.clone(), open_envelope(0, "block-propagation", sealed).unwrap());
+    }
+
+    #[test]
+    fn envelope_of_another_extension_is_rejected() {
+        let sealed = seal_envelope(ENVELOPE_VERSION, "block-propagation", b"payload");
+        assert_eq!(
+            Err(DecoderError::Custom("Envelope of another extension")),
+            open_envelope(ENVELOPE_VERSION, "discovery", sealed)
+        );
+    }
+
+    #[test]
+    fn envelope_of_another_version_is_rejected() {
+        let sealed = seal_envelope(ENVELOPE_VERSION + 1, "block-propagation", b"payload");
+        assert_eq!(
+            Err(DecoderError::Custom("Unexpected envelope version")),
+            open_envelope(ENVELOPE_VERSION, "block-propagation", sealed)
+        );
+    }
+
+    #[test]
+    fn unknown_flags_are_ignored() {
+        let mut s = RlpStream::new_list(4);
+        s.append(&ENVELOPE_VERSION).append(&extension_id("discovery")).append(&0x80u8).append(&&b"payload"[..]);
+        let opened = open_envelope(ENVELOPE_VERSION, "discovery", Bytes::from(s.out())).unwrap();
+        assert_eq!(Bytes::from(&b"payload"[..]), opened);
+    }
+
+    #[test]
+    fn opened_payload_shares_the_data() {
+        let sealed = seal_envelope(ENVELOPE_VERSION, "discovery", b"payload");
+        let begin = sealed.as_ptr() as usize;
+        let opened = open_envelope(ENVELOPE_VERSION, "discovery", sealed.clone()).unwrap();
+        let data_begin = opened.as_ptr() as usize;
+        assert!(begin <= data_begin && data_begin < begin + sealed.len());
+    }
+}
@@ -14,17 +14,21 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccrypto::aes::{self, SymmetricCipherError};
+use bytes::Bytes;
+use ccrypto::is_equal;
+use ctypes::H256;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
-use super::super::super::session::Session;
-use super::ProtocolId;
+use super::super::super::session::{CipherError, CipherSuite, Session};
+use super::frame::shared_data;
+use super::{FrameDecodable, ProtocolId};
 use super::Version;
 
+use super::AUTHENTICATED_ID;
 use super::ENCRYPTED_ID;
+use super::SEALED_ID;
 use super::UNENCRYPTED_ID;
 
-
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Message {
     version: Version,
@@ -35,8 +39,13 @@ pub struct Message {
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 enum Data {
-    Encrypted(Vec<u8>),
-    Unencrypted(Vec<u8>),
+    // Encrypted with the legacy cipher
+    Encrypted(Bytes),
+    Unencrypted(Bytes),
+    // Encrypted with an AEAD cipher, which authenticates the extension name too
+    Sealed(Bytes),
+    // Not encrypted, but it has the sequence number and the MAC of the session
+    Authenticated(Bytes, u64, H256),
 }
 
 impl Message {
@@ -46,7 +55,7 @@ impl Message {
             version: 0,
             extension_name,
             extension_version,
-            data: Data::Encrypted(data.to_vec()),
+            data: Data::Encrypted(Bytes::from(data)),
         }
     }
 
@@ -55,8 +64,14 @@ impl Message {
         extension_version: Version,
         unencrypted_data: &[u8],
         session: &Session,
-    ) -> Result<Self, SymmetricCipherError> {
-        let data = Data::Encrypted(aes::encrypt(unencrypted_data, session.secret(), &session.id().clone().into())?);
+        cipher: CipherSuite,
+    ) -> Result<Self, CipherError> {
+        let encrypted = Bytes::from(session.seal(cipher, extension_name.as_bytes(), unencrypted_data)?);
+        let data = if cipher.is_aead() {
+            Data::Sealed(encrypted)
+        } else {
+            Data::Encrypted(encrypted)
+        };
         Ok(Self {
             version: 0,
             extension_name,
@@ -64,12 +79,57 @@ impl Message {
             data,
         })
     }
-    pub fn unencrypted(extension_name: String, extension_version: Version, data: &[u8]) -> Self {
+
+    pub fn unencrypted(extension_name: String, extension_version: Version, data: Bytes) -> Self {
+        Self {
+            version: 0,
+            extension_name,
+            extension_version,
+            data: Data::Unencrypted(data),
+        }
+    }
+
+    /// Sends the data without the encryption.
+    /// The peers which negotiated an AEAD cipher can verify it with the MAC, and the older peers cannot.
+    ///
+    /// The sequence number increases with each message of the connection, so that the messages cannot be replayed.
+    pub fn authenticated(
+        extension_name: String,
+        extension_version: Version,
+        data: Bytes,
+        sequence: u64,
+        session: &Session,
+    ) -> Self {
+        let mac = mac(session, &extension_name, extension_version, sequence, &data);
         Self {
             version: 0,
             extension_name,
             extension_version,
-            data: Data::Unencrypted(data.to_vec()),
+            data: Data::Authenticated(data, sequence, mac),
+        }
+    }
+
+    /// Returns false if the data is not what the peer sent.
+    ///
+    /// In the sessions of an AEAD cipher, the data without the MAC is rejected too.
+    /// The encrypted data is verified when it's decrypted.
+    /// The caller checks that the sequence numbers increase.
+    pub fn verify(&self, session: &Session, cipher: CipherSuite) -> bool {
+        match self.data {
+            Data::Authenticated(ref data, sequence, ref expected) => {
+                let mac = mac(session, &self.extension_name, self.extension_version, sequence, &data);
+                is_equal(&mac, expected)
+            }
+            Data::Unencrypted(_) => !cipher.is_aead(),
+            Data::Encrypted(_) | Data::Sealed(_) => true,
+        }
+    }
+
+    /// The sequence number of the authenticated message
+    pub fn sequence(&self) -> Option<u64> {
+        match self.data {
+            Data::Authenticated(_, sequence, _) => Some(sequence),
+            _ => None,
         }
     }
 
@@ -77,13 +137,26 @@ impl Message {
         match self.data {
             Data::Encrypted(ref data) => &data,
             Data::Unencrypted(ref data) => &data,
+            Data::Sealed(ref data) => &data,
+            Data::Authenticated(ref data, ..) => &data,
         }
     }
 
-    pub fn unencrypted_data(&self, session: &Session) -> Result<Vec<u8>, SymmetricCipherError> {
+    /// Decrypts the data with the cipher negotiated for the session.
+    ///
+    /// The encrypted messages of the other kind are rejected, so a peer cannot fall back to the legacy cipher.
+    /// The MAC of the data which is not encrypted should be checked with `verify` beforehand.
+    pub fn unencrypted_data(&self, session: &Session, cipher: CipherSuite) -> Result<Bytes, CipherError> {
         match self.data {
-            Data::Encrypted(ref data) => aes::decrypt(&data, session.secret(), &session.id().clone().into()),
-            Data::Unencrypted(ref data) => Ok(data.clone()),
+            Data::Encrypted(ref data) if !cipher.is_aead() => {
+                Ok(Bytes::from(session.open(cipher, self.extension_name.as_bytes(), &data)?))
+            }
+            Data::Sealed(ref data) if cipher.is_aead() => {
+                Ok(Bytes::from(session.open(cipher, self.extension_name.as_bytes(), &data)?))
+            }
+            Data::Encrypted(_) | Data::Sealed(_) => Err(CipherError::UnexpectedCipher),
+            Data::Unencrypted(_) if cipher.is_aead() => Err(CipherError::UnexpectedCipher),
+            Data::Unencrypted(ref data) | Data::Authenticated(ref data, ..) => Ok(data.clone()),
         }
     }
 
@@ -95,6 +168,8 @@ impl Message {
         match self.data {
             Data::Encrypted(_) => ENCRYPTED_ID,
             Data::Unencrypted(_) => UNENCRYPTED_ID,
+            Data::Sealed(_) => SEALED_ID,
+            Data::Authenticated(..) => AUTHENTICATED_ID,
         }
     }
 
@@ -109,41 +184,70 @@ impl Message {
 
 impl Encodable for Message {
     fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_list(5)
-            .append(&self.version())
+        // The sequence number and the MAC follow the data
+        match self.data {
+            Data::Authenticated(..) => s.begin_list(7),
+            _ => s.begin_list(5),
+        };
+        s.append(&self.version())
             .append(&self.protocol_id())
             .append(self.extension_name())
             .append(&self.extension_version())
             .append(&self.data());
+        if let Data::Authenticated(_, sequence, ref mac) = self.data {
+            s.append(&sequence).append(mac);
+        }
     }
 }
 
 impl Decodable for Message {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        let version: Version = rlp.val_at(0)?;
-        let protocol_id: ProtocolId = rlp.val_at(1)?;
-        let extension_name: String = rlp.val_at(2)?;
-        let extension_version: Version = rlp.val_at(3)?;
         let data: Vec<u8> = rlp.val_at(4)?;
-        let data = match protocol_id {
-            ENCRYPTED_ID => Data::Encrypted(data),
-            UNENCRYPTED_ID => Data::Unencrypted(data),
-            _ => return Err(DecoderError::Custom("invalid protocol id")),
-        };
-        Ok(Self {
-            version,
-            extension_name,
-            extension_version,
-            data,
-        })
+        decode_with_data(rlp, Bytes::from(data))
     }
 }
 
+impl FrameDecodable for Message {
+    fn decode_frame(frame: &Bytes, rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let data = shared_data(frame, &rlp.at(4)?)?;
+        decode_with_data(rlp, data)
+    }
+}
+
+fn decode_with_data(rlp: &UntrustedRlp, data: Bytes) -> Result<Message, DecoderError> {
+    let version: Version = rlp.val_at(0)?;
+    let protocol_id: ProtocolId = rlp.val_at(1)?;
+    let extension_name: String = rlp.val_at(2)?;
+    let extension_version: Version = rlp.val_at(3)?;
+    let data = match protocol_id {
+        ENCRYPTED_ID => Data::Encrypted(data),
+        UNENCRYPTED_ID => Data::Unencrypted(data),
+        SEALED_ID => Data::Sealed(data),
+        AUTHENTICATED_ID => Data::Authenticated(data, rlp.val_at(5)?, rlp.val_at(6)?),
+        _ => return Err(DecoderError::Custom("invalid protocol id")),
+    };
+    Ok(Message {
+        version,
+        extension_name,
+        extension_version,
+        data,
+    })
+}
+
+// The MAC covers the version and the sequence number, so they cannot be changed on the way
+fn mac(session: &Session, extension_name: &str, extension_version: Version, sequence: u64, data: &[u8]) -> H256 {
+    let mut s = RlpStream::new_list(3);
+    s.append(&extension_version).append(&sequence).append(&data);
+    session.mac(extension_name.as_bytes(), &s.out())
+}
+
 #[cfg(test)]
 mod tests {
     use ctypes::Secret;
     use rand::{OsRng, Rng};
+    use rlp::{Encodable, UntrustedRlp};
 
+    use super::super::super::super::session::Role;
     use super::super::super::message::Nonce;
     use super::*;
 
@@ -168,10 +272,116 @@ mod tests {
         let nonce: Nonce = rng.gen();
 
         let session = Session::new(shared_secret, nonce);
-        let encrypted =
-            Message::encrypted_from_unencrypted_data(extension_name, extension_version, &unencrypted_data, &session)
+        let remote = session.with_role(Role::Responder);
+        for cipher in CipherSuite::supported(true) {
+            let encrypted = Message::encrypted_from_unencrypted_data(
+                extension_name.clone(),
+                extension_version,
+                &unencrypted_data,
+                &session,
+                cipher,
+            ).unwrap();
+            assert_ne!(unencrypted_data, encrypted.data());
+            assert_eq!(unencrypted_data, encrypted.unencrypted_data(&remote, cipher).unwrap().as_ref());
+        }
+    }
+
+    #[test]
+    fn sealed_id_is_10() {
+        assert_eq!(10, super::SEALED_ID)
+    }
+
+    #[test]
+    fn legacy_message_is_rejected_in_aead_session() {
+        let session = Session::new(Secret::random(), 1000.into());
+        let data = "the peer tries to downgrade".as_bytes();
+        let legacy =
+            Message::encrypted_from_unencrypted_data("name".to_string(), 0, data, &session, CipherSuite::LegacyAesCbc)
+                .unwrap();
+        assert_eq!(ENCRYPTED_ID, legacy.protocol_id());
+        assert_eq!(Err(CipherError::UnexpectedCipher), legacy.unencrypted_data(&session, CipherSuite::Aes256Gcm));
+
+        let sealed =
+            Message::encrypted_from_unencrypted_data("name".to_string(), 0, data, &session, CipherSuite::Aes256Gcm)
                 .unwrap();
-        assert_ne!(unencrypted_data, encrypted.data());
-        assert_eq!(unencrypted_data, encrypted.unencrypted_data(&session).unwrap().as_slice());
+        assert_eq!(SEALED_ID, sealed.protocol_id());
+        assert_eq!(Err(CipherError::UnexpectedCipher), sealed.unencrypted_data(&session, CipherSuite::LegacyAesCbc));
+    }
+
+    #[test]
+    fn authenticated_id_is_11() {
+        assert_eq!(11, super::AUTHENTICATED_ID)
+    }
+
+    #[test]
+    fn authenticated_message_is_verified_with_the_mac() {
+        let session = Session::new(Secret::random(), 1000.into());
+        let message = Message::authenticated("name".to_string(), 0, Bytes::from(&b"data"[..]), 3, &session);
+        let decoded = UntrustedRlp::new(&message.rlp_bytes()).as_val::<Message>().unwrap();
+        assert_eq!(message, decoded);
+        assert_eq!(Some(3), decoded.sequence());
+        assert!(decoded.verify(&session, CipherSuite::Aes256Gcm));
+        assert!(!decoded.verify(&Session::new(Secret::random(), 1000.into()), CipherSuite::Aes256Gcm));
+
+        let mac = mac(&session, "name", 0, 3, b"data");
+        let tampered = Message {
+            data: Data::Authenticated(Bytes::from(&b"tampered"[..]), 3, mac),
+            ..decoded
+        };
+        assert!(!tampered.verify(&session, CipherSuite::Aes256Gcm));
+    }
+
+    #[test]
+    fn mac_covers_the_version_and_the_sequence() {
+        let session = Session::new(Secret::random(), 1000.into());
+        let message = Message::authenticated("name".to_string(), 0, Bytes::from(&b"data"[..]), 3, &session);
+        let mac = mac(&session, "name", 0, 3, b"data");
+        let resequenced = Message {
+            data: Data::Authenticated(Bytes::from(&b"data"[..]), 4, mac),
+            ..message
+        };
+        assert!(!resequenced.verify(&session, CipherSuite::Aes256Gcm));
+        let reversioned = Message {
+            extension_version: 1,
+            data: Data::Authenticated(Bytes::from(&b"data"[..]), 3, mac),
+            ..resequenced
+        };
+        assert!(!reversioned.verify(&session, CipherSuite::Aes256Gcm));
+    }
+
+    #[test]
+    fn mac_is_required_in_aead_session() {
+        let session = Session::new(Secret::random(), 1000.into());
+        let message = Message::unencrypted("name".to_string(), 0, Bytes::from(&b"data"[..]));
+        assert!(message.verify(&session, CipherSuite::LegacyAesCbc));
+        assert!(!message.verify(&session, CipherSuite::ChaCha20Poly1305));
+        assert_eq!(Err(CipherError::UnexpectedCipher), message.unencrypted_data(&session, CipherSuite::Aes256Gcm));
+    }
+
+    #[test]
+    fn sealed_message_is_bound_to_the_extension_name() {
+        let session = Session::new(Secret::random(), 1000.into());
+        let sealed =
+            Message::encrypted_from_unencrypted_data("name".to_string(), 0, b"data", &session, CipherSuite::Aes256Gcm)
+                .unwrap();
+        let renamed = Message {
+            extension_name: "another".to_string(),
+            ..sealed
+        };
+        let remote = session.with_role(Role::Responder);
+        assert_eq!(Err(CipherError::Forged), renamed.unencrypted_data(&remote, CipherSuite::Aes256Gcm));
+    }
+
+    #[test]
+    fn decode_frame_shares_the_unencrypted_data() {
+        let data = Bytes::from("this data is not copied".as_bytes());
+        let message = Message::unencrypted("shared".to_string(), 1, data.clone());
+        let frame = Bytes::from(message.rlp_bytes().into_vec());
+
+        let decoded = Message::decode_frame(&frame, &UntrustedRlp::new(&frame)).unwrap();
+        assert_eq!(message, decoded);
+        let frame_begin = frame.as_ptr() as usize;
+        let data_begin = decoded.data().as_ptr() as usize;
+        assert!(frame_begin <= data_begin && data_begin < frame_begin + frame.len());
     }
 }
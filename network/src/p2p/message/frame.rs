@@ -0,0 +1,63 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use bytes::Bytes;
+use rlp::{DecoderError, UntrustedRlp};
+
+/// A message which is decoded from a frame without copying its payload.
+pub trait FrameDecodable: Sized {
+    /// `rlp` must be a view of `frame`.
+    fn decode_frame(frame: &Bytes, rlp: &UntrustedRlp) -> Result<Self, DecoderError>;
+}
+
+/// Returns the payload of `rlp` as a slice sharing the memory of `frame`.
+pub fn shared_data(frame: &Bytes, rlp: &UntrustedRlp) -> Result<Bytes, DecoderError> {
+    let data = rlp.data()?;
+    let frame_begin = frame.as_ptr() as usize;
+    let data_begin = data.as_ptr() as usize;
+    if data_begin < frame_begin || frame_begin + frame.len() < data_begin + data.len() {
+        // The rlp doesn't point into the frame
+        return Ok(Bytes::from(data))
+    }
+    let begin = data_begin - frame_begin;
+    Ok(frame.slice(begin, begin + data.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::{Encodable, UntrustedRlp};
+
+    use super::*;
+
+    #[test]
+    fn shared_data_shares_the_frame() {
+        let frame = Bytes::from(vec![1u8, 2, 3, 4, 5].rlp_bytes().into_vec());
+        let rlp = UntrustedRlp::new(&frame);
+        let data = shared_data(&frame, &rlp).unwrap();
+        assert_eq!(&[1u8, 2, 3, 4, 5], &data[..]);
+        assert_eq!(frame[1..].as_ptr(), data.as_ptr());
+    }
+
+    #[test]
+    fn shared_data_copies_the_data_out_of_the_frame() {
+        let frame = Bytes::from(vec![1u8, 2, 3].rlp_bytes().into_vec());
+        let other = vec![4u8, 5].rlp_bytes().into_vec();
+        let rlp = UntrustedRlp::new(&other);
+        let data = shared_data(&frame, &rlp).unwrap();
+        assert_eq!(&[4u8, 5], &data[..]);
+    }
+}
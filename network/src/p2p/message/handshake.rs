@@ -14,15 +14,21 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use ccrypto::blake256;
+use ckeys::{sign_ecdsa, verify_ecdsa, ECDSASignature, KeyPair, Public};
+use ctypes::{H256, H520};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use super::ProtocolId;
 use super::Version;
+use super::ENVELOPE_VERSION;
 
 use super::ACK_ID;
 use super::SYNC_ID;
 
-use super::super::super::NodeId;
+use super::super::super::session::{cipher, CipherSuite, Session};
+use super::super::super::{NodeId, SocketAddr};
+use super::super::stream::{FRAME_VERSION, LEGACY_FRAME_VERSION};
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum Message {
@@ -30,44 +36,201 @@ pub enum Message {
         version: Version,
         port: u16,
         node_id: NodeId,
+        public: Public,
+        signature: H520,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
+        frame_version: Version,
+    },
+    Ack {
+        version: Version,
+        public: Public,
+        signature: H520,
+        // The address of the receiver which the sender sees
+        observed_address: SocketAddr,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
+        frame_version: Version,
     },
-    Ack(Version),
 }
 
 impl Message {
-    pub fn sync(port: u16, node_id: NodeId) -> Self {
+    pub fn sync(
+        port: u16,
+        node_id: NodeId,
+        key_pair: &KeyPair,
+        session: &Session,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
+    ) -> Self {
         Message::Sync {
-            version: 0,
+            version: ENVELOPE_VERSION,
             port,
             node_id,
+            public: *key_pair.public(),
+            signature: sign(SYNC_ID, key_pair, session, &cipher_suites, FRAME_VERSION),
+            user_agent,
+            cipher_suites,
+            frame_version: FRAME_VERSION,
         }
     }
 
-    pub fn ack() -> Self {
-        Message::Ack(0)
+    pub fn ack(
+        key_pair: &KeyPair,
+        session: &Session,
+        observed_address: SocketAddr,
+        user_agent: String,
+        cipher_suites: Vec<CipherSuite>,
+    ) -> Self {
+        Message::Ack {
+            version: ENVELOPE_VERSION,
+            public: *key_pair.public(),
+            signature: sign(ACK_ID, key_pair, session, &cipher_suites, FRAME_VERSION),
+            observed_address,
+            user_agent,
+            cipher_suites,
+            frame_version: FRAME_VERSION,
+        }
     }
 
-    #[allow(dead_code)]
-    fn version(&self) -> &Version {
+    /// The newest envelope version which the sender knows
+    pub fn version(&self) -> &Version {
         match self {
             Message::Sync {
                 version,
                 ..
             } => version,
-            Message::Ack(version) => version,
+            Message::Ack {
+                version,
+                ..
+            } => version,
+        }
+    }
+
+    /// The name, the version and the OS of the sender's software.
+    /// It's empty if the sender is older than the user agent.
+    pub fn user_agent(&self) -> &String {
+        match self {
+            Message::Sync {
+                user_agent,
+                ..
+            } => user_agent,
+            Message::Ack {
+                user_agent,
+                ..
+            } => user_agent,
+        }
+    }
+
+    /// The cipher suites which the sender supports.
+    /// It's empty if the sender is older than the cipher suites, and knows only the legacy cipher.
+    pub fn cipher_suites(&self) -> &[CipherSuite] {
+        match self {
+            Message::Sync {
+                cipher_suites,
+                ..
+            } => cipher_suites,
+            Message::Ack {
+                cipher_suites,
+                ..
+            } => cipher_suites,
+        }
+    }
+
+    /// The newest frame version which the sender knows. The older senders know only the legacy frames.
+    pub fn frame_version(&self) -> &Version {
+        match self {
+            Message::Sync {
+                frame_version,
+                ..
+            } => frame_version,
+            Message::Ack {
+                frame_version,
+                ..
+            } => frame_version,
+        }
+    }
+
+    /// The long-term public key of the sender
+    pub fn public(&self) -> &Public {
+        match self {
+            Message::Sync {
+                public,
+                ..
+            } => public,
+            Message::Ack {
+                public,
+                ..
+            } => public,
         }
     }
 
+    /// Returns true if the message is signed by the owner of its public key for the given session.
+    pub fn is_authenticated(&self, session: &Session) -> bool {
+        let (public, signature) = match self {
+            Message::Sync {
+                public,
+                signature,
+                ..
+            } => (public, signature),
+            Message::Ack {
+                public,
+                signature,
+                ..
+            } => (public, signature),
+        };
+        let hash = signing_hash(self.protocol_id(), session, self.cipher_suites(), *self.frame_version());
+        verify_ecdsa(public, &ECDSASignature::from(*signature), &hash).unwrap_or(false)
+    }
+
     fn protocol_id(&self) -> ProtocolId {
         match self {
             Message::Sync {
                 ..
             } => SYNC_ID,
-            Message::Ack(_) => ACK_ID,
+            Message::Ack {
+                ..
+            } => ACK_ID,
         }
     }
 }
 
+// The signature covers the shared secret of the session, so it cannot be replayed on another session.
+// It covers the cipher suites and the frame version too, so they cannot be stripped to downgrade the session.
+fn signing_hash(
+    protocol_id: ProtocolId,
+    session: &Session,
+    cipher_suites: &[CipherSuite],
+    frame_version: Version,
+) -> H256 {
+    if frame_version == LEGACY_FRAME_VERSION && cipher_suites.is_empty() {
+        let mut s = RlpStream::new_list(3);
+        s.append(&protocol_id).append(session.secret()).append(session.id());
+        return blake256(s.out())
+    }
+    if frame_version == LEGACY_FRAME_VERSION {
+        let mut s = RlpStream::new_list(4);
+        s.append(&protocol_id).append(session.secret()).append(session.id());
+        cipher::append_suites(&mut s, cipher_suites);
+        return blake256(s.out())
+    }
+    let mut s = RlpStream::new_list(5);
+    s.append(&protocol_id).append(session.secret()).append(session.id());
+    cipher::append_suites(&mut s, cipher_suites);
+    s.append(&frame_version);
+    blake256(s.out())
+}
+
+fn sign(
+    protocol_id: ProtocolId,
+    key_pair: &KeyPair,
+    session: &Session,
+    cipher_suites: &[CipherSuite],
+    frame_version: Version,
+) -> H520 {
+    let hash = signing_hash(protocol_id, session, cipher_suites, frame_version);
+    sign_ecdsa(key_pair.private(), &hash).expect("The key pair of the node is valid").into()
+}
 
 impl Encodable for Message {
     fn rlp_append(&self, s: &mut RlpStream) {
@@ -76,11 +239,41 @@ impl Encodable for Message {
                 version,
                 port,
                 node_id,
+                public,
+                signature,
+                user_agent,
+                cipher_suites,
+                frame_version,
             } => {
-                s.begin_list(4).append(version).append(&self.protocol_id()).append(port).append(node_id);
+                s.begin_list(9)
+                    .append(version)
+                    .append(&self.protocol_id())
+                    .append(port)
+                    .append(node_id)
+                    .append(public)
+                    .append(signature)
+                    .append(user_agent);
+                cipher::append_suites(s, cipher_suites);
+                s.append(frame_version);
             }
-            Message::Ack(version) => {
-                s.begin_list(2).append(version).append(&self.protocol_id());
+            Message::Ack {
+                version,
+                public,
+                signature,
+                observed_address,
+                user_agent,
+                cipher_suites,
+                frame_version,
+            } => {
+                s.begin_list(8)
+                    .append(version)
+                    .append(&self.protocol_id())
+                    .append(public)
+                    .append(signature)
+                    .append(observed_address)
+                    .append(user_agent);
+                cipher::append_suites(s, cipher_suites);
+                s.append(frame_version);
             }
         }
     }
@@ -91,21 +284,43 @@ impl Decodable for Message {
         let version: Version = rlp.val_at(0)?;
         let protocol_id: ProtocolId = rlp.val_at(1)?;
         match protocol_id {
+            // The older nodes don't send the user agent, the cipher suites and the frame version
             SYNC_ID => {
-                if rlp.item_count()? != 4 {
-                    return Err(DecoderError::RlpIncorrectListLen)
-                }
+                let (user_agent, cipher_suites, frame_version) = match rlp.item_count()? {
+                    6 => (String::new(), vec![], LEGACY_FRAME_VERSION),
+                    7 => (rlp.val_at(6)?, vec![], LEGACY_FRAME_VERSION),
+                    8 => (rlp.val_at(6)?, cipher::decode_suites(&rlp.at(7)?)?, LEGACY_FRAME_VERSION),
+                    9 => (rlp.val_at(6)?, cipher::decode_suites(&rlp.at(7)?)?, rlp.val_at(8)?),
+                    _ => return Err(DecoderError::RlpIncorrectListLen),
+                };
                 Ok(Message::Sync {
                     version,
                     port: rlp.val_at(2)?,
                     node_id: rlp.val_at(3)?,
+                    public: rlp.val_at(4)?,
+                    signature: rlp.val_at(5)?,
+                    user_agent,
+                    cipher_suites,
+                    frame_version,
                 })
             }
             ACK_ID => {
-                if rlp.item_count()? != 2 {
-                    return Err(DecoderError::RlpIncorrectListLen)
-                }
-                Ok(Message::Ack(version))
+                let (user_agent, cipher_suites, frame_version) = match rlp.item_count()? {
+                    5 => (String::new(), vec![], LEGACY_FRAME_VERSION),
+                    6 => (rlp.val_at(5)?, vec![], LEGACY_FRAME_VERSION),
+                    7 => (rlp.val_at(5)?, cipher::decode_suites(&rlp.at(6)?)?, LEGACY_FRAME_VERSION),
+                    8 => (rlp.val_at(5)?, cipher::decode_suites(&rlp.at(6)?)?, rlp.val_at(7)?),
+                    _ => return Err(DecoderError::RlpIncorrectListLen),
+                };
+                Ok(Message::Ack {
+                    version,
+                    public: rlp.val_at(2)?,
+                    signature: rlp.val_at(3)?,
+                    observed_address: rlp.val_at(4)?,
+                    user_agent,
+                    cipher_suites,
+                    frame_version,
+                })
             }
             _ => Err(DecoderError::Custom("invalid protocol id")),
         }
@@ -114,27 +329,46 @@ impl Decodable for Message {
 
 #[cfg(test)]
 mod tests {
-    use rlp::{Decodable, Encodable, UntrustedRlp};
+    use ckeys::{Generator, Random};
+    use ctypes::Secret;
+    use rlp::{Decodable, Encodable, RlpStream, UntrustedRlp};
 
+    use super::super::super::super::session::Nonce;
     use super::*;
 
+    const USER_AGENT: &'static str = "CodeChain/v0.1.0/linux";
+
+    fn suites() -> Vec<CipherSuite> {
+        CipherSuite::supported(false)
+    }
+
+    fn session() -> Session {
+        Session::new(Secret::random(), Nonce::from(1000))
+    }
+
     #[test]
     fn protocol_id_of_sync_is_0() {
         const PORT: u16 = 1234;
         let node_id = 1000.into();
-        assert_eq!(0x00, Message::sync(PORT, node_id).protocol_id());
+        let key_pair = Random.generate().unwrap();
+        let sync = Message::sync(PORT, node_id, &key_pair, &session(), USER_AGENT.to_string(), suites());
+        assert_eq!(0x00, sync.protocol_id());
     }
 
     #[test]
     fn protocol_id_of_ack_is_1() {
-        assert_eq!(0x01, Message::ack().protocol_id());
+        let key_pair = Random.generate().unwrap();
+        let observed_address = SocketAddr::v4(1, 2, 3, 4, 5678);
+        let ack = Message::ack(&key_pair, &session(), observed_address, USER_AGENT.to_string(), suites());
+        assert_eq!(0x01, ack.protocol_id());
     }
 
     #[test]
     fn encode_and_decode_sync() {
         const PORT: u16 = 1234;
         let node_id = 1000.into();
-        let sync = Message::sync(PORT, node_id);
+        let key_pair = Random.generate().unwrap();
+        let sync = Message::sync(PORT, node_id, &key_pair, &session(), USER_AGENT.to_string(), suites());
         let bytes = sync.rlp_bytes();
 
         let rlp = UntrustedRlp::new(&bytes);
@@ -147,7 +381,9 @@ mod tests {
 
     #[test]
     fn encode_and_decode_ack() {
-        let ack = Message::ack();
+        let key_pair = Random.generate().unwrap();
+        let observed_address = SocketAddr::v4(1, 2, 3, 4, 5678);
+        let ack = Message::ack(&key_pair, &session(), observed_address, USER_AGENT.to_string(), suites());
         let bytes = ack.rlp_bytes();
 
         let rlp = UntrustedRlp::new(&bytes);
@@ -157,4 +393,125 @@ mod tests {
             Err(err) => assert!(false, "{:?}", err),
         }
     }
+
+    #[test]
+    fn sync_of_the_older_node_has_no_user_agent() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let mut s = RlpStream::new_list(6);
+        s.append(&0u64).append(&SYNC_ID).append(&1234u16).append(&NodeId::from(1000)).append(key_pair.public());
+        s.append(&sign(SYNC_ID, &key_pair, &session, &[], LEGACY_FRAME_VERSION));
+
+        let sync: Message = UntrustedRlp::new(&s.out()).as_val().unwrap();
+        assert_eq!("", sync.user_agent());
+        assert!(sync.cipher_suites().is_empty());
+        assert_eq!(LEGACY_FRAME_VERSION, *sync.frame_version());
+        assert!(sync.is_authenticated(&session));
+    }
+
+    #[test]
+    fn ack_of_the_older_node_has_no_user_agent() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let mut s = RlpStream::new_list(5);
+        s.append(&0u64).append(&ACK_ID).append(key_pair.public());
+        s.append(&sign(ACK_ID, &key_pair, &session, &[], LEGACY_FRAME_VERSION));
+        s.append(&SocketAddr::v4(1, 2, 3, 4, 5678));
+
+        let ack: Message = UntrustedRlp::new(&s.out()).as_val().unwrap();
+        assert_eq!("", ack.user_agent());
+        assert!(ack.cipher_suites().is_empty());
+        assert_eq!(LEGACY_FRAME_VERSION, *ack.frame_version());
+        assert!(ack.is_authenticated(&session));
+    }
+
+    #[test]
+    fn handshake_is_authenticated_only_in_its_session() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let sync = Message::sync(1234, 1000.into(), &key_pair, &session, USER_AGENT.to_string(), suites());
+        assert!(sync.is_authenticated(&session));
+        assert!(!sync.is_authenticated(&self::session()));
+    }
+
+    #[test]
+    fn sync_signature_is_not_valid_for_ack() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let signature = match Message::sync(1234, 1000.into(), &key_pair, &session, USER_AGENT.to_string(), suites()) {
+            Message::Sync {
+                signature,
+                ..
+            } => signature,
+            _ => unreachable!(),
+        };
+        let ack = Message::Ack {
+            version: 0,
+            public: *key_pair.public(),
+            signature,
+            observed_address: SocketAddr::v4(1, 2, 3, 4, 5678),
+            user_agent: USER_AGENT.to_string(),
+            cipher_suites: suites(),
+            frame_version: FRAME_VERSION,
+        };
+        assert!(!ack.is_authenticated(&session));
+    }
+
+    #[test]
+    fn stripping_the_cipher_suites_breaks_the_signature() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let stripped = match Message::sync(1234, 1000.into(), &key_pair, &session, USER_AGENT.to_string(), suites()) {
+            Message::Sync {
+                version,
+                port,
+                node_id,
+                public,
+                signature,
+                user_agent,
+                frame_version,
+                ..
+            } => Message::Sync {
+                version,
+                port,
+                node_id,
+                public,
+                signature,
+                user_agent,
+                cipher_suites: vec![],
+                frame_version,
+            },
+            _ => unreachable!(),
+        };
+        assert!(!stripped.is_authenticated(&session));
+    }
+
+    #[test]
+    fn lowering_the_frame_version_breaks_the_signature() {
+        let key_pair = Random.generate().unwrap();
+        let session = session();
+        let lowered = match Message::sync(1234, 1000.into(), &key_pair, &session, USER_AGENT.to_string(), suites()) {
+            Message::Sync {
+                version,
+                port,
+                node_id,
+                public,
+                signature,
+                user_agent,
+                cipher_suites,
+                ..
+            } => Message::Sync {
+                version,
+                port,
+                node_id,
+                public,
+                signature,
+                user_agent,
+                cipher_suites,
+                frame_version: LEGACY_FRAME_VERSION,
+            },
+            _ => unreachable!(),
+        };
+        assert!(!lowered.is_authenticated(&session));
+    }
 }
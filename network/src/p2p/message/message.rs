@@ -14,17 +14,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use bytes::Bytes;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::DisconnectMessage;
 use super::ExtensionMessage;
+use super::FrameDecodable;
 use super::HandshakeMessage;
 use super::NegotiationMessage;
+use super::RelayMessage;
 
 #[derive(Debug)]
 pub enum Message {
+    Disconnect(DisconnectMessage),
     Extension(ExtensionMessage),
     Handshake(HandshakeMessage),
     Negotiation(NegotiationMessage),
+    Relay(RelayMessage),
 }
 
 impl Message {
@@ -42,7 +48,10 @@ impl Message {
 use super::ACK_ID;
 use super::ALLOWED_ID;
 use super::DENIED_ID;
+use super::DISCONNECT_ID;
 use super::ENCRYPTED_ID;
+use super::INTRODUCE_ID;
+use super::PUNCH_ID;
 use super::REQUEST_ID;
 use super::SYNC_ID;
 use super::UNENCRYPTED_ID;
@@ -50,9 +59,11 @@ use super::UNENCRYPTED_ID;
 impl Encodable for Message {
     fn rlp_append(&self, s: &mut RlpStream) {
         match self {
+            Message::Disconnect(message) => message.rlp_append(s),
             Message::Extension(message) => message.rlp_append(s),
             Message::Handshake(message) => message.rlp_append(s),
             Message::Negotiation(message) => message.rlp_append(s),
+            Message::Relay(message) => message.rlp_append(s),
         }
     }
 }
@@ -68,7 +79,21 @@ impl Decodable for Message {
             DENIED_ID => Ok(Message::Negotiation(NegotiationMessage::decode(rlp)?)),
             ENCRYPTED_ID => Ok(Message::Extension(ExtensionMessage::decode(rlp)?)),
             UNENCRYPTED_ID => Ok(Message::Extension(ExtensionMessage::decode(rlp)?)),
+            INTRODUCE_ID => Ok(Message::Relay(RelayMessage::decode(rlp)?)),
+            PUNCH_ID => Ok(Message::Relay(RelayMessage::decode(rlp)?)),
+            DISCONNECT_ID => Ok(Message::Disconnect(DisconnectMessage::decode(rlp)?)),
             _ => Err(DecoderError::Custom("unexpected protocol id")),
         }
     }
 }
+
+impl FrameDecodable for Message {
+    fn decode_frame(frame: &Bytes, rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let protocol_id = rlp.val_at(1)?;
+        match protocol_id {
+            ENCRYPTED_ID => Ok(Message::Extension(ExtensionMessage::decode_frame(frame, rlp)?)),
+            UNENCRYPTED_ID => Ok(Message::Extension(ExtensionMessage::decode_frame(frame, rlp)?)),
+            _ => Message::decode(rlp),
+        }
+    }
+}
@@ -14,18 +14,26 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod disconnect;
+mod envelope;
 mod extension;
+mod frame;
 mod handshake;
 mod message;
 mod negotiation;
+mod relay;
 mod signed_message;
 
 use ctypes::H256;
 
+pub use self::disconnect::{Message as DisconnectMessage, Reason as DisconnectReason};
+pub use self::envelope::{open_envelope, seal_envelope, ENVELOPE_VERSION};
 pub use self::extension::Message as ExtensionMessage;
+pub use self::frame::FrameDecodable;
 pub use self::handshake::Message as HandshakeMessage;
 pub use self::message::Message;
 pub use self::negotiation::{Body as NegotiationBody, Message as NegotiationMessage};
+pub use self::relay::Message as RelayMessage;
 pub use self::signed_message::SignedMessage;
 pub use super::super::session::Nonce;
 
@@ -41,14 +49,24 @@ pub const ALLOWED_ID: ProtocolId = 0x03;
 pub const DENIED_ID: ProtocolId = 0x04;
 pub const ENCRYPTED_ID: ProtocolId = 0x05;
 pub const UNENCRYPTED_ID: ProtocolId = 0x06;
+pub const INTRODUCE_ID: ProtocolId = 0x07;
+pub const PUNCH_ID: ProtocolId = 0x08;
+pub const DISCONNECT_ID: ProtocolId = 0x09;
+pub const SEALED_ID: ProtocolId = 0x0a;
+pub const AUTHENTICATED_ID: ProtocolId = 0x0b;
 
 #[cfg(test)]
 mod tests {
     use super::ACK_ID;
     use super::ALLOWED_ID;
+    use super::AUTHENTICATED_ID;
     use super::DENIED_ID;
+    use super::DISCONNECT_ID;
     use super::ENCRYPTED_ID;
+    use super::INTRODUCE_ID;
+    use super::PUNCH_ID;
     use super::REQUEST_ID;
+    use super::SEALED_ID;
     use super::SYNC_ID;
     use super::UNENCRYPTED_ID;
 
@@ -60,6 +78,9 @@ mod tests {
         assert_ne!(SYNC_ID, DENIED_ID);
         assert_ne!(SYNC_ID, ENCRYPTED_ID);
         assert_ne!(SYNC_ID, UNENCRYPTED_ID);
+        assert_ne!(SYNC_ID, INTRODUCE_ID);
+        assert_ne!(SYNC_ID, PUNCH_ID);
+        assert_ne!(SYNC_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -70,6 +91,9 @@ mod tests {
         assert_ne!(ACK_ID, DENIED_ID);
         assert_ne!(ACK_ID, ENCRYPTED_ID);
         assert_ne!(ACK_ID, UNENCRYPTED_ID);
+        assert_ne!(ACK_ID, INTRODUCE_ID);
+        assert_ne!(ACK_ID, PUNCH_ID);
+        assert_ne!(ACK_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -80,6 +104,9 @@ mod tests {
         assert_ne!(REQUEST_ID, DENIED_ID);
         assert_ne!(REQUEST_ID, ENCRYPTED_ID);
         assert_ne!(REQUEST_ID, UNENCRYPTED_ID);
+        assert_ne!(REQUEST_ID, INTRODUCE_ID);
+        assert_ne!(REQUEST_ID, PUNCH_ID);
+        assert_ne!(REQUEST_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -90,6 +117,9 @@ mod tests {
         assert_ne!(ALLOWED_ID, DENIED_ID);
         assert_ne!(ALLOWED_ID, ENCRYPTED_ID);
         assert_ne!(ALLOWED_ID, UNENCRYPTED_ID);
+        assert_ne!(ALLOWED_ID, INTRODUCE_ID);
+        assert_ne!(ALLOWED_ID, PUNCH_ID);
+        assert_ne!(ALLOWED_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -100,6 +130,9 @@ mod tests {
         assert_ne!(DENIED_ID, ALLOWED_ID);
         assert_ne!(DENIED_ID, ENCRYPTED_ID);
         assert_ne!(DENIED_ID, UNENCRYPTED_ID);
+        assert_ne!(DENIED_ID, INTRODUCE_ID);
+        assert_ne!(DENIED_ID, PUNCH_ID);
+        assert_ne!(DENIED_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -110,6 +143,9 @@ mod tests {
         assert_ne!(ENCRYPTED_ID, ALLOWED_ID);
         assert_ne!(ENCRYPTED_ID, DENIED_ID);
         assert_ne!(ENCRYPTED_ID, UNENCRYPTED_ID);
+        assert_ne!(ENCRYPTED_ID, INTRODUCE_ID);
+        assert_ne!(ENCRYPTED_ID, PUNCH_ID);
+        assert_ne!(ENCRYPTED_ID, DISCONNECT_ID);
     }
 
     #[test]
@@ -120,5 +156,77 @@ mod tests {
         assert_ne!(UNENCRYPTED_ID, ALLOWED_ID);
         assert_ne!(UNENCRYPTED_ID, DENIED_ID);
         assert_ne!(UNENCRYPTED_ID, ENCRYPTED_ID);
+        assert_ne!(UNENCRYPTED_ID, INTRODUCE_ID);
+        assert_ne!(UNENCRYPTED_ID, PUNCH_ID);
+        assert_ne!(UNENCRYPTED_ID, DISCONNECT_ID);
+    }
+
+    #[test]
+    fn introduce_id_is_a_unique() {
+        assert_ne!(INTRODUCE_ID, SYNC_ID);
+        assert_ne!(INTRODUCE_ID, ACK_ID);
+        assert_ne!(INTRODUCE_ID, REQUEST_ID);
+        assert_ne!(INTRODUCE_ID, ALLOWED_ID);
+        assert_ne!(INTRODUCE_ID, DENIED_ID);
+        assert_ne!(INTRODUCE_ID, ENCRYPTED_ID);
+        assert_ne!(INTRODUCE_ID, UNENCRYPTED_ID);
+        assert_ne!(INTRODUCE_ID, PUNCH_ID);
+        assert_ne!(INTRODUCE_ID, DISCONNECT_ID);
+    }
+
+    #[test]
+    fn punch_id_is_a_unique() {
+        assert_ne!(PUNCH_ID, SYNC_ID);
+        assert_ne!(PUNCH_ID, ACK_ID);
+        assert_ne!(PUNCH_ID, REQUEST_ID);
+        assert_ne!(PUNCH_ID, ALLOWED_ID);
+        assert_ne!(PUNCH_ID, DENIED_ID);
+        assert_ne!(PUNCH_ID, ENCRYPTED_ID);
+        assert_ne!(PUNCH_ID, UNENCRYPTED_ID);
+        assert_ne!(PUNCH_ID, INTRODUCE_ID);
+        assert_ne!(PUNCH_ID, DISCONNECT_ID);
+    }
+
+    #[test]
+    fn disconnect_id_is_a_unique() {
+        assert_ne!(DISCONNECT_ID, SYNC_ID);
+        assert_ne!(DISCONNECT_ID, ACK_ID);
+        assert_ne!(DISCONNECT_ID, REQUEST_ID);
+        assert_ne!(DISCONNECT_ID, ALLOWED_ID);
+        assert_ne!(DISCONNECT_ID, DENIED_ID);
+        assert_ne!(DISCONNECT_ID, ENCRYPTED_ID);
+        assert_ne!(DISCONNECT_ID, UNENCRYPTED_ID);
+        assert_ne!(DISCONNECT_ID, INTRODUCE_ID);
+        assert_ne!(DISCONNECT_ID, PUNCH_ID);
+    }
+
+    #[test]
+    fn sealed_id_is_a_unique() {
+        assert_ne!(SEALED_ID, SYNC_ID);
+        assert_ne!(SEALED_ID, ACK_ID);
+        assert_ne!(SEALED_ID, REQUEST_ID);
+        assert_ne!(SEALED_ID, ALLOWED_ID);
+        assert_ne!(SEALED_ID, DENIED_ID);
+        assert_ne!(SEALED_ID, ENCRYPTED_ID);
+        assert_ne!(SEALED_ID, UNENCRYPTED_ID);
+        assert_ne!(SEALED_ID, INTRODUCE_ID);
+        assert_ne!(SEALED_ID, PUNCH_ID);
+        assert_ne!(SEALED_ID, DISCONNECT_ID);
+        assert_ne!(SEALED_ID, AUTHENTICATED_ID);
+    }
+
+    #[test]
+    fn authenticated_id_is_a_unique() {
+        assert_ne!(AUTHENTICATED_ID, SYNC_ID);
+        assert_ne!(AUTHENTICATED_ID, ACK_ID);
+        assert_ne!(AUTHENTICATED_ID, REQUEST_ID);
+        assert_ne!(AUTHENTICATED_ID, ALLOWED_ID);
+        assert_ne!(AUTHENTICATED_ID, DENIED_ID);
+        assert_ne!(AUTHENTICATED_ID, ENCRYPTED_ID);
+        assert_ne!(AUTHENTICATED_ID, UNENCRYPTED_ID);
+        assert_ne!(AUTHENTICATED_ID, INTRODUCE_ID);
+        assert_ne!(AUTHENTICATED_ID, PUNCH_ID);
+        assert_ne!(AUTHENTICATED_ID, DISCONNECT_ID);
+        assert_ne!(AUTHENTICATED_ID, SEALED_ID);
     }
 }
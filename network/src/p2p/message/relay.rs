@@ -0,0 +1,123 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::ProtocolId;
+use super::Version;
+
+use super::INTRODUCE_ID;
+use super::PUNCH_ID;
+
+use super::super::super::SocketAddr;
+
+/// Messages to let two nodes behind NATs connect to each other through a publicly reachable relay.
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Message {
+    /// Asks the relay to introduce the sender to the node at the address.
+    Introduce(Version, SocketAddr),
+    /// Tells that the node at the address starts to connect to the receiver at the same time.
+    Punch(Version, SocketAddr),
+}
+
+impl Message {
+    pub fn introduce(target: SocketAddr) -> Self {
+        Message::Introduce(0, target)
+    }
+
+    pub fn punch(peer: SocketAddr) -> Self {
+        Message::Punch(0, peer)
+    }
+
+    fn protocol_id(&self) -> ProtocolId {
+        match self {
+            Message::Introduce(..) => INTRODUCE_ID,
+            Message::Punch(..) => PUNCH_ID,
+        }
+    }
+}
+
+impl Encodable for Message {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            Message::Introduce(version, address) => {
+                s.begin_list(3).append(version).append(&self.protocol_id()).append(address);
+            }
+            Message::Punch(version, address) => {
+                s.begin_list(3).append(version).append(&self.protocol_id()).append(address);
+            }
+        }
+    }
+}
+
+impl Decodable for Message {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let version: Version = rlp.val_at(0)?;
+        let protocol_id: ProtocolId = rlp.val_at(1)?;
+        let address: SocketAddr = rlp.val_at(2)?;
+        match protocol_id {
+            INTRODUCE_ID => Ok(Message::Introduce(version, address)),
+            PUNCH_ID => Ok(Message::Punch(version, address)),
+            _ => Err(DecoderError::Custom("invalid protocol id")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::{Decodable, Encodable, UntrustedRlp};
+
+    use super::*;
+
+    #[test]
+    fn protocol_id_of_introduce_is_7() {
+        assert_eq!(0x07, Message::introduce(SocketAddr::v4(1, 2, 3, 4, 3485)).protocol_id());
+    }
+
+    #[test]
+    fn protocol_id_of_punch_is_8() {
+        assert_eq!(0x08, Message::punch(SocketAddr::v4(1, 2, 3, 4, 3485)).protocol_id());
+    }
+
+    #[test]
+    fn encode_and_decode_introduce() {
+        let introduce = Message::introduce(SocketAddr::v4(1, 2, 3, 4, 3485));
+        let bytes = introduce.rlp_bytes();
+
+        let rlp = UntrustedRlp::new(&bytes);
+
+        match Decodable::decode(&rlp) {
+            Ok(message) => assert_eq!(introduce, message),
+            Err(err) => assert!(false, "{:?}", err),
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_punch() {
+        let punch = Message::punch(SocketAddr::v6(1, 2, 3, 4, 5, 6, 7, 8, 3485));
+        let bytes = punch.rlp_bytes();
+
+        let rlp = UntrustedRlp::new(&bytes);
+
+        match Decodable::decode(&rlp) {
+            Ok(message) => assert_eq!(punch, message),
+            Err(err) => assert!(false, "{:?}", err),
+        }
+    }
+}
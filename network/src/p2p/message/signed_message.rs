@@ -14,14 +14,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use bytes::Bytes;
+use rlp::{DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use super::super::super::session::Session;
-use super::Signature;
+use super::frame::shared_data;
+use super::{FrameDecodable, Signature};
 
 #[derive(Debug)]
 pub struct SignedMessage {
-    pub message: Vec<u8>,
+    pub message: Bytes,
     signature: Signature,
 }
 
@@ -29,7 +31,7 @@ impl SignedMessage {
     pub fn new<M>(message: &M, session: &Session) -> Self
     where
         M: Encodable, {
-        let message = message.rlp_bytes().into_vec();
+        let message = Bytes::from(message.rlp_bytes().into_vec());
         let signature = session.sign(&message);
         Self {
             message,
@@ -44,16 +46,16 @@ impl SignedMessage {
 
 impl Encodable for SignedMessage {
     fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_list(2).append(&self.message).append(&self.signature);
+        s.begin_list(2).append(&self.message.as_ref()).append(&self.signature);
     }
 }
 
-impl Decodable for SignedMessage {
-    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+impl FrameDecodable for SignedMessage {
+    fn decode_frame(frame: &Bytes, rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
         if rlp.item_count()? != 2 {
             return Err(DecoderError::Custom("invalid message"))
         }
-        let message: Vec<u8> = rlp.val_at(0)?;
+        let message = shared_data(frame, &rlp.at(0)?)?;
         let signature: Signature = rlp.val_at(1)?;
         Ok(Self {
             message,
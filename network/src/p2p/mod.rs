@@ -14,15 +14,34 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod bootstrap;
+mod config;
 mod connection;
 mod connections;
+mod crc32;
+mod drops;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 mod handler;
 mod listener;
 mod message;
+mod observed_addresses;
+mod peer;
+mod rate_limit;
+mod socket_options;
 mod stream;
+mod transport;
 
-pub use self::handler::{Handler, Message};
+pub use self::config::Config;
+pub use self::connections::{ConnectionDump, ConnectionInfo, Direction as ConnectionDirection};
+pub use self::drops::{DropCounts, DropReason, DropReport, Reply};
+pub use self::message::DisconnectReason;
+pub use self::handler::{Handler, HandlerDump, Message, NodeStatus, PeerInfo, RemovedPeer};
+pub use self::peer::PeerState;
+pub use self::rate_limit::RateLimit;
+pub use self::socket_options::SocketOptions;
 use self::message::ExtensionMessage;
 use self::message::NegotiationBody;
 use self::message::NegotiationMessage;
+use self::message::RelayMessage;
 use self::message::SignedMessage;
@@ -0,0 +1,118 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::super::NodeId;
+
+// The number of peers that must agree on an address before it is trusted
+const MIN_AGREEMENTS: usize = 2;
+
+/// Collects the addresses of this node which the peers observed during the handshakes.
+pub struct ObservedAddresses {
+    observations: HashMap<NodeId, IpAddr>,
+}
+
+impl ObservedAddresses {
+    pub fn new() -> Self {
+        Self {
+            observations: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, reporter: NodeId, ip: IpAddr) {
+        self.observations.insert(reporter, ip);
+    }
+
+    pub fn forget(&mut self, reporter: &NodeId) {
+        self.observations.remove(reporter);
+    }
+
+    /// Returns the address which the most peers reported, if it is not contested by another address.
+    pub fn consensus(&self) -> Option<IpAddr> {
+        let mut votes = HashMap::new();
+        for ip in self.observations.values() {
+            *votes.entry(ip).or_insert(0usize) += 1;
+        }
+
+        let mut votes: Vec<_> = votes.into_iter().collect();
+        votes.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        let runner_up = votes.get(1).map(|(_, count)| *count).unwrap_or(0);
+        votes.first().and_then(|(ip, count)| {
+            if *count >= MIN_AGREEMENTS && *count > runner_up {
+                Some(**ip)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn single_observation_is_not_trusted() {
+        let mut addresses = ObservedAddresses::new();
+        addresses.observe(1.into(), ip(1, 2, 3, 4));
+        assert_eq!(None, addresses.consensus());
+    }
+
+    #[test]
+    fn agreed_observation_is_trusted() {
+        let mut addresses = ObservedAddresses::new();
+        addresses.observe(1.into(), ip(1, 2, 3, 4));
+        addresses.observe(2.into(), ip(1, 2, 3, 4));
+        addresses.observe(3.into(), ip(5, 6, 7, 8));
+        assert_eq!(Some(ip(1, 2, 3, 4)), addresses.consensus());
+    }
+
+    #[test]
+    fn tie_is_not_trusted() {
+        let mut addresses = ObservedAddresses::new();
+        addresses.observe(1.into(), ip(1, 2, 3, 4));
+        addresses.observe(2.into(), ip(1, 2, 3, 4));
+        addresses.observe(3.into(), ip(5, 6, 7, 8));
+        addresses.observe(4.into(), ip(5, 6, 7, 8));
+        assert_eq!(None, addresses.consensus());
+    }
+
+    #[test]
+    fn forgotten_observation_is_not_counted() {
+        let mut addresses = ObservedAddresses::new();
+        addresses.observe(1.into(), ip(1, 2, 3, 4));
+        addresses.observe(2.into(), ip(1, 2, 3, 4));
+        addresses.forget(&2.into());
+        assert_eq!(None, addresses.consensus());
+    }
+
+    #[test]
+    fn later_observation_replaces_the_previous_one() {
+        let mut addresses = ObservedAddresses::new();
+        addresses.observe(1.into(), ip(1, 2, 3, 4));
+        addresses.observe(2.into(), ip(5, 6, 7, 8));
+        addresses.observe(2.into(), ip(1, 2, 3, 4));
+        assert_eq!(Some(ip(1, 2, 3, 4)), addresses.consensus());
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+/// The lifecycle of a connection to a peer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerState {
+    /// This node dialed the peer and waits for the ack of its sync message
+    Connecting,
+    /// The peer dialed this node and the handshake is not finished
+    AwaitingSync,
+    /// The handshake is finished and the extensions know the peer
+    Established,
+    /// The stream is being deregistered
+    Closing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerEvent {
+    /// The ack for the sync message is received
+    AckReceived,
+    /// The ack for the peer's sync message is sent
+    AckSent,
+    /// The stream is hung up or this node disconnects the peer
+    Closed,
+}
+
+impl PeerState {
+    /// Returns the state after the event, or None if the event cannot happen in this state.
+    pub fn next(&self, event: PeerEvent) -> Option<PeerState> {
+        match (self, event) {
+            (PeerState::Connecting, PeerEvent::AckReceived) => Some(PeerState::Established),
+            (PeerState::AwaitingSync, PeerEvent::AckSent) => Some(PeerState::Established),
+            (PeerState::Closing, PeerEvent::Closed) => None,
+            (_, PeerEvent::Closed) => Some(PeerState::Closing),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATES: [PeerState; 4] =
+        [PeerState::Connecting, PeerState::AwaitingSync, PeerState::Established, PeerState::Closing];
+
+    #[test]
+    fn connecting_peer_is_established_by_ack() {
+        assert_eq!(Some(PeerState::Established), PeerState::Connecting.next(PeerEvent::AckReceived));
+        assert_eq!(None, PeerState::Connecting.next(PeerEvent::AckSent));
+    }
+
+    #[test]
+    fn accepted_peer_is_established_after_sending_ack() {
+        assert_eq!(Some(PeerState::Established), PeerState::AwaitingSync.next(PeerEvent::AckSent));
+        assert_eq!(None, PeerState::AwaitingSync.next(PeerEvent::AckReceived));
+    }
+
+    #[test]
+    fn established_peer_is_not_established_again() {
+        assert_eq!(None, PeerState::Established.next(PeerEvent::AckReceived));
+        assert_eq!(None, PeerState::Established.next(PeerEvent::AckSent));
+    }
+
+    #[test]
+    fn every_open_peer_can_be_closed() {
+        for state in STATES.iter().filter(|state| **state != PeerState::Closing) {
+            assert_eq!(Some(PeerState::Closing), state.next(PeerEvent::Closed), "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn closing_peer_accepts_no_event() {
+        assert_eq!(None, PeerState::Closing.next(PeerEvent::AckReceived));
+        assert_eq!(None, PeerState::Closing.next(PeerEvent::AckSent));
+        assert_eq!(None, PeerState::Closing.next(PeerEvent::Closed));
+    }
+}
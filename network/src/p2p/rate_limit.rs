@@ -0,0 +1,177 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Instant;
+
+/// The inbound extension traffic which a peer is allowed per second.
+/// A peer may burst up to a second's worth of traffic at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub messages_per_second: u32,
+    pub bytes_per_second: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 1000,
+            bytes_per_second: 16 * 1024 * 1024,
+        }
+    }
+}
+
+// The peer is disconnected when this many messages are throttled before it pauses
+const MAX_VIOLATIONS: usize = 100;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Admission {
+    Accepted,
+    /// The message is dropped
+    Throttled,
+    /// The peer kept sending after being throttled
+    Exceeded,
+}
+
+/// A pair of token buckets, one for the messages and one for the bytes.
+pub struct RateLimiter {
+    limit: RateLimit,
+    messages: f64,
+    bytes: f64,
+    refilled_at: Instant,
+    violations: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            messages: limit.messages_per_second as f64,
+            bytes: limit.bytes_per_second as f64,
+            refilled_at: now,
+            violations: 0,
+        }
+    }
+
+    pub fn admit(&mut self, bytes: usize, now: Instant) -> Admission {
+        self.refill(now);
+        // A message larger than the burst is accepted when the bucket is full
+        let required_bytes = (bytes as f64).min(self.limit.bytes_per_second as f64);
+        if self.messages >= 1.0 && self.bytes >= required_bytes {
+            self.messages -= 1.0;
+            self.bytes -= bytes as f64;
+            return Admission::Accepted
+        }
+        self.violations += 1;
+        if self.violations >= MAX_VIOLATIONS {
+            Admission::Exceeded
+        } else {
+            Admission::Throttled
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.refilled_at {
+            return
+        }
+        let elapsed = now.duration_since(self.refilled_at);
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+        self.refilled_at = now;
+
+        let max_messages = self.limit.messages_per_second as f64;
+        let max_bytes = self.limit.bytes_per_second as f64;
+        self.messages = max_messages.min(self.messages + seconds * max_messages);
+        self.bytes = max_bytes.min(self.bytes + seconds * max_bytes);
+        // The peer which paused until the buckets are full is forgiven
+        if self.messages >= max_messages && self.bytes >= max_bytes {
+            self.violations = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limit(messages_per_second: u32, bytes_per_second: u64) -> RateLimit {
+        RateLimit {
+            messages_per_second,
+            bytes_per_second,
+        }
+    }
+
+    #[test]
+    fn messages_over_the_rate_are_throttled() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(2, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(10, now));
+        assert_eq!(Admission::Accepted, limiter.admit(10, now));
+        assert_eq!(Admission::Throttled, limiter.admit(10, now));
+    }
+
+    #[test]
+    fn bytes_over_the_rate_are_throttled() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(100, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(600, now));
+        assert_eq!(Admission::Throttled, limiter.admit(600, now));
+        assert_eq!(Admission::Accepted, limiter.admit(400, now));
+    }
+
+    #[test]
+    fn buckets_are_refilled_over_time() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(2, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(10, now));
+        assert_eq!(Admission::Accepted, limiter.admit(10, now));
+        assert_eq!(Admission::Throttled, limiter.admit(10, now));
+        assert_eq!(Admission::Accepted, limiter.admit(10, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn large_message_is_accepted_when_the_bucket_is_full() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(100, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(3000, now));
+        assert_eq!(Admission::Throttled, limiter.admit(1, now + Duration::from_secs(1)));
+        assert_eq!(Admission::Accepted, limiter.admit(1, now + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn persistent_flood_exceeds_the_limit() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(1, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(1, now));
+        for _ in 1..MAX_VIOLATIONS {
+            assert_eq!(Admission::Throttled, limiter.admit(1, now));
+        }
+        assert_eq!(Admission::Exceeded, limiter.admit(1, now));
+    }
+
+    #[test]
+    fn paused_peer_is_forgiven() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(limit(1, 1000), now);
+        assert_eq!(Admission::Accepted, limiter.admit(1, now));
+        for _ in 1..MAX_VIOLATIONS {
+            assert_eq!(Admission::Throttled, limiter.admit(1, now));
+        }
+        let later = now + Duration::from_secs(1);
+        assert_eq!(Admission::Accepted, limiter.admit(1, later));
+        assert_eq!(Admission::Throttled, limiter.admit(1, later));
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+use std::time::Duration;
+
+use mio::net::TcpStream;
+
+/// The options of the TCP sockets under the p2p connections.
+/// The fields which are None are left to the OS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SocketOptions {
+    /// Sends the small messages, like the consensus votes, without waiting to coalesce them.
+    pub nodelay: bool,
+    /// The size of the receive buffer in bytes. Larger buffers speed up the block sync.
+    pub recv_buffer_size: Option<usize>,
+    /// The size of the send buffer in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// How long closing a socket waits for the unsent data.
+    pub linger: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            linger: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(size) = self.recv_buffer_size {
+            stream.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            stream.set_send_buffer_size(size)?;
+        }
+        if self.linger.is_some() {
+            stream.set_linger(self.linger)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net;
+
+    use super::*;
+
+    fn connected() -> (TcpStream, net::TcpListener) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(&listener.local_addr().unwrap()).unwrap();
+        (stream, listener)
+    }
+
+    #[test]
+    fn nodelay_is_the_default() {
+        let (stream, _listener) = connected();
+        SocketOptions::default().apply(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+
+    #[test]
+    fn options_are_applied() {
+        let (stream, _listener) = connected();
+        let options = SocketOptions {
+            nodelay: false,
+            recv_buffer_size: Some(128 * 1024),
+            send_buffer_size: Some(128 * 1024),
+            linger: Some(Duration::from_secs(1)),
+        };
+        options.apply(&stream).unwrap();
+        assert!(!stream.nodelay().unwrap());
+        // The OS may round the buffer sizes up
+        assert!(stream.recv_buffer_size().unwrap() >= 128 * 1024);
+        assert!(stream.send_buffer_size().unwrap() >= 128 * 1024);
+        assert_eq!(Some(Duration::from_secs(1)), stream.linger().unwrap());
+    }
+}
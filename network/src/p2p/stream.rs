@@ -14,11 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cmp;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io::{self, Write};
 use std::net;
 
+use bytes::{BufMut, Bytes, BytesMut};
 use mio::deprecated::TryRead;
 use mio::event::Evented;
 use mio::net::TcpStream;
@@ -27,13 +29,20 @@ use rlp::{Decodable, DecoderError, Encodable, UntrustedRlp};
 
 use super::super::session::Session;
 use super::super::SocketAddr;
-use super::SignedMessage;
+use super::crc32::crc32;
+use super::message::Version;
+use super::socket_options::SocketOptions;
+#[cfg(any(test, feature = "fuzzing"))]
+use super::transport::MemoryStream;
+use super::transport::{Transport, WebSocketStream};
+use super::{FrameDecodable, SignedMessage};
 
 #[derive(Debug)]
 pub enum Error {
     IoError(io::Error),
     DecoderError(DecoderError),
     InvalidSign,
+    CorruptedFrame,
 }
 
 impl fmt::Display for Error {
@@ -42,6 +51,7 @@ impl fmt::Display for Error {
             Error::IoError(err) => err.fmt(f),
             Error::DecoderError(err) => err.fmt(f),
             Error::InvalidSign => fmt::Debug::fmt(&self, f),
+            Error::CorruptedFrame => fmt::Debug::fmt(&self, f),
         }
     }
 }
@@ -52,6 +62,7 @@ impl StdError for Error {
             Error::IoError(err) => err.description(),
             Error::DecoderError(err) => err.description(),
             Error::InvalidSign => "invalid sign",
+            Error::CorruptedFrame => "corrupted frame",
         }
     }
     fn cause(&self) -> Option<&StdError> {
@@ -59,6 +70,7 @@ impl StdError for Error {
             Error::IoError(err) => Some(err),
             Error::DecoderError(err) => Some(err),
             Error::InvalidSign => None,
+            Error::CorruptedFrame => None,
         }
     }
 }
@@ -77,14 +89,89 @@ impl From<DecoderError> for Error {
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+// A peer cannot make the node allocate more than this for a frame.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// The frames of this version are the bare RLP lists. The handshake is always in this version, since the peer's
+// version is not known yet.
+pub const LEGACY_FRAME_VERSION: Version = 0;
+// The RLP list of a frame of this version is followed by the checksum of the list
+pub const FRAME_VERSION: Version = 1;
+
+const CHECKSUM_SIZE: usize = 4;
+
+fn checksum_size(frame_version: Version) -> usize {
+    if frame_version >= FRAME_VERSION {
+        CHECKSUM_SIZE
+    } else {
+        0
+    }
+}
+
+fn frame_checksum(frame: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let checksum = crc32(frame);
+    [(checksum >> 24) as u8, (checksum >> 16) as u8, (checksum >> 8) as u8, checksum as u8]
+}
+
+/// Appends the checksum to the encoded RLP list if the frame version has it.
+pub fn seal(frame_version: Version, mut frame: Vec<u8>) -> Vec<u8> {
+    if checksum_size(frame_version) != 0 {
+        let checksum = frame_checksum(&frame);
+        frame.extend_from_slice(&checksum);
+    }
+    frame
+}
+
+// Returns the length of the list after the header, or an error if the header is not canonical
+fn body_length(header: &[u8]) -> Result<usize> {
+    let prefix = header[0];
+    if prefix <= 0xf7 {
+        return Ok((prefix - 0xc0) as usize)
+    }
+    let len_of_len = &header[1..];
+    if len_of_len[0] == 0 {
+        return Err(Error::CorruptedFrame)
+    }
+    let mut length: u64 = 0;
+    for i in len_of_len {
+        length <<= 8;
+        length |= u64::from(*i);
+    }
+    // The short lists must use the short header
+    if length <= 55 || length > MAX_FRAME_SIZE as u64 {
+        return Err(Error::CorruptedFrame)
+    }
+    Ok(length as usize)
+}
+
+// Frames are split off from a chunk of this size, so small messages share an allocation.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+// The longest RLP list header is 9 bytes. The read buffer never grows beyond a frame of the maximum size.
+const MAX_READ_BUFFER_SIZE: usize = 9 + MAX_FRAME_SIZE + CHECKSUM_SIZE;
+
+// The socket reads into these zeros, so that it never sees the uninitialized memory of the read buffer.
+static ZEROS: [u8; READ_BUFFER_SIZE] = [0; READ_BUFFER_SIZE];
+
 pub struct Stream {
-    stream: TcpStream,
+    stream: Transport,
+    read_buffer: BytesMut,
+    // The tail of the frames which the non-blocking socket didn't take yet
+    unsent: Vec<u8>,
+    frame_version: Version,
+    bytes_read: u64,
+    bytes_written: u64,
 }
 
 impl Stream {
-    pub fn connect<'a, S: Into<&'a net::SocketAddr>>(socket_address: S) -> Result<Option<Self>> {
+    pub fn connect<'a, S>(socket_address: S, options: &SocketOptions) -> Result<Option<Self>>
+    where
+        S: Into<&'a net::SocketAddr>, {
         Ok(match TcpStream::connect(socket_address.into()) {
-            Ok(stream) => Some(Self::from(stream)),
+            Ok(stream) => {
+                options.apply(&stream)?;
+                Some(Self::from(stream))
+            }
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => None,
             Err(e) => Err(e)?,
         })
@@ -103,79 +190,171 @@ impl Stream {
         Ok(Some(rlp.as_val::<M>()?))
     }
 
+    /// Unlike `read`, the decoded message refers to the received frame instead of copying it.
+    pub fn read_shared<M>(&mut self) -> Result<Option<M>>
+    where
+        M: FrameDecodable, {
+        let bytes = self.read_bytes()?;
+
+        if bytes.is_empty() {
+            return Ok(None)
+        }
+
+        let rlp = UntrustedRlp::new(&bytes);
+        Ok(Some(M::decode_frame(&bytes, &rlp)?))
+    }
+
     pub fn write<M>(&mut self, message: &M) -> Result<()>
     where
         M: Encodable, {
-        let bytes = message.rlp_bytes();
+        let bytes = seal(self.frame_version, message.rlp_bytes().into_vec());
         Ok(self.write_bytes(&bytes)?)
     }
 
-    fn read_len_of_len(&mut self, mut bytes: Vec<u8>) -> io::Result<(usize, Vec<u8>)> {
-        debug_assert_eq!(1, bytes.len());
-        debug_assert!(bytes[0] >= 0xf7);
-        let len_of_len = (bytes[0] - 0xf7) as usize;
-        debug_assert!(len_of_len <= 8);
-        bytes.resize(1 + len_of_len, 0);
+    /// The frames after the handshake are in the version which the both sides know.
+    /// The frames which are already written are not affected.
+    pub fn set_frame_version(&mut self, frame_version: Version) {
+        self.frame_version = frame_version;
+    }
 
-        if let Some(read_size) = self.stream.try_read(&mut bytes[1..(1 + len_of_len)])? {
-            debug_assert_eq!(len_of_len, read_size);
-            let mut total_length: usize = 0;
-            for i in &bytes[1..(1 + len_of_len)] {
-                total_length <<= 8;
-                total_length |= *i as usize;
+    // Appends at most `len` bytes to the read buffer and returns the number of bytes read.
+    fn fill_read_buffer(&mut self, len: usize) -> io::Result<usize> {
+        let buffered = self.read_buffer.len();
+        debug_assert!(buffered + len <= MAX_READ_BUFFER_SIZE);
+        if self.read_buffer.remaining_mut() < len {
+            // The memory is reused when none of the frames split off from it is alive
+            self.read_buffer.reserve(cmp::min(cmp::max(len, READ_BUFFER_SIZE), MAX_READ_BUFFER_SIZE - buffered));
+        }
+
+        let mut total_read_size = 0;
+        let result = loop {
+            if total_read_size == len {
+                break Ok(())
+            }
+            let offset = self.read_buffer.len();
+            let chunk_size = cmp::min(len - total_read_size, ZEROS.len());
+            self.read_buffer.extend_from_slice(&ZEROS[..chunk_size]);
+            match self.stream.try_read(&mut self.read_buffer[offset..]) {
+                Ok(Some(0)) | Ok(None) => {
+                    self.read_buffer.truncate(offset);
+                    break Ok(())
+                }
+                Ok(Some(read_size)) => {
+                    self.read_buffer.truncate(offset + read_size);
+                    total_read_size += read_size;
+                }
+                Err(err) => {
+                    self.read_buffer.truncate(offset);
+                    break Err(err)
+                }
             }
-            return Ok((total_length, bytes))
+        };
+        self.bytes_read += total_read_size as u64;
+        result.map(|()| total_read_size)
+    }
+
+    fn read_bytes(&mut self) -> Result<Bytes> {
+        let result = self.read_frame();
+        if result.is_err() {
+            // Drop the broken frame, so that the next read starts with an empty buffer
+            self.read_buffer.clear();
         }
-        Ok((0, bytes))
+        result
     }
 
-    fn read_len(&mut self) -> io::Result<(usize, Vec<u8>)> {
-        let mut bytes: Vec<u8> = vec![0];
+    // Returns an empty frame until the whole frame arrives
+    fn read_frame(&mut self) -> Result<Bytes> {
+        if self.read_buffer.is_empty() && self.fill_read_buffer(1)? == 0 {
+            return Ok(Bytes::new())
+        }
 
-        if let Some(read_size) = self.stream.try_read(&mut bytes)? {
-            debug_assert_eq!(1, read_size);
-            if bytes[0] >= 0xf7 {
-                return self.read_len_of_len(bytes)
-            }
-            if bytes[0] >= 0xc0 {
-                return Ok(((bytes[0] - 0xc0) as usize, bytes))
+        let header_length = match self.read_buffer[0] {
+            0xc0...0xf7 => 1,
+            prefix @ 0xf8...0xff => 1 + (prefix - 0xf7) as usize,
+            _ => return Err(Error::CorruptedFrame),
+        };
+        if !self.fill_up_to(header_length)? {
+            return Ok(Bytes::new())
+        }
+
+        let checksum_size = checksum_size(self.frame_version);
+        let frame_length = header_length + body_length(&self.read_buffer[..header_length])? + checksum_size;
+        if !self.fill_up_to(frame_length)? {
+            return Ok(Bytes::new())
+        }
+
+        let mut frame = self.read_buffer.split_to(frame_length);
+        if checksum_size != 0 {
+            let checksum = frame.split_off(frame_length - checksum_size);
+            if checksum[..] != frame_checksum(&frame)[..] {
+                return Err(Error::CorruptedFrame)
             }
-            return Ok((0, vec![]))
         }
-        return Ok((0, vec![]))
+        Ok(frame.freeze())
     }
 
-    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
-        let (mut total_length, mut result) = self.read_len()?;
-        let mut bytes: [u8; 1024] = [0; 1024];
+    // Returns false if the read buffer is still shorter than `len`
+    fn fill_up_to(&mut self, len: usize) -> io::Result<bool> {
+        let buffered = self.read_buffer.len();
+        if buffered < len {
+            let missing = len - buffered;
+            return Ok(self.fill_read_buffer(missing)? == missing)
+        }
+        Ok(true)
+    }
 
-        loop {
-            if total_length == 0 {
-                break
+    /// Writes as many bytes as the socket takes and keeps the rest, which `flush` writes when it's writable.
+    pub fn write_bytes(&mut self, bytes_to_send: &[u8]) -> io::Result<()> {
+        self.unsent.extend_from_slice(bytes_to_send);
+        self.write_unsent()
+    }
+
+    fn write_unsent(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.unsent.len() {
+                break Ok(())
             }
-            let to_be_read = ::std::cmp::min(total_length, 1024);
-            if let Some(read_size) = self.stream.try_read(&mut bytes[0..to_be_read])? {
-                result.extend_from_slice(&bytes[..read_size]);
-                debug_assert!(total_length >= read_size);
-                total_length -= read_size;
-            } else {
-                break
+            match self.stream.write(&self.unsent[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => written += size,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
             }
-        }
-        Ok(result)
+        };
+        self.unsent.drain(..written);
+        self.bytes_written += written as u64;
+        result
+    }
+
+    /// Returns true if the frames are not written completely.
+    pub fn has_unsent(&self) -> bool {
+        !self.unsent.is_empty()
     }
 
-    fn write_bytes(&mut self, bytes_to_send: &[u8]) -> io::Result<()> {
-        self.stream.write_all(&bytes_to_send)
+    // Writes the bytes which this stream and the transport buffered, e.g. the WebSocket frames
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_unsent()?;
+        self.stream.flush()
     }
 
-    pub fn stream(&self) -> &TcpStream {
+    pub fn stream(&self) -> &Transport {
         &self.stream
     }
 
+    pub fn has_pending_write(&self) -> bool {
+        self.has_unsent() || self.stream.has_pending_write()
+    }
+
     pub fn peer_addr(&self) -> Result<SocketAddr> {
         Ok(self.stream.peer_addr()?.into())
     }
+
+    /// The number of the bytes read from and written to the transport, including the frame headers.
+    pub fn traffic(&self) -> (u64, u64) {
+        (self.bytes_read, self.bytes_written)
+    }
 }
 
 pub struct SignedStream {
@@ -193,13 +372,13 @@ impl SignedStream {
 
     pub fn read<M>(&mut self) -> Result<Option<M>>
     where
-        M: ?Sized + Decodable, {
-        if let Some(signed) = self.stream.read::<SignedMessage>()? {
+        M: FrameDecodable, {
+        if let Some(signed) = self.stream.read_shared::<SignedMessage>()? {
             if !signed.is_valid(&self.session) {
                 return Err(Error::InvalidSign)
             }
             let rlp = UntrustedRlp::new(&signed.message);
-            Ok(Some(rlp.as_val::<M>()?))
+            Ok(Some(M::decode_frame(&signed.message, &rlp)?))
         } else {
             Ok(None)
         }
@@ -212,28 +391,75 @@ impl SignedStream {
         self.stream.write(&signed_message)
     }
 
+    /// Encodes the message into a frame which can be written with `write_bytes`.
+    pub fn encode<M>(&self, message: &M) -> Vec<u8>
+    where
+        M: Encodable, {
+        seal(self.stream.frame_version, SignedMessage::new(message, &self.session).rlp_bytes().into_vec())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        Ok(self.stream.write_bytes(bytes)?)
+    }
+
+    pub fn set_frame_version(&mut self, frame_version: Version) {
+        self.stream.set_frame_version(frame_version)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.stream.flush()?)
+    }
+
     pub fn session(&self) -> &Session {
         &self.session
     }
+
+    pub fn has_pending_write(&self) -> bool {
+        self.stream.has_pending_write()
+    }
+
+    pub fn has_unsent(&self) -> bool {
+        self.stream.has_unsent()
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn traffic(&self) -> (u64, u64) {
+        self.stream.traffic()
+    }
 }
 
-impl From<TcpStream> for Stream {
-    fn from(stream: TcpStream) -> Self {
+impl From<Transport> for Stream {
+    fn from(stream: Transport) -> Self {
         Self {
             stream,
+            read_buffer: BytesMut::new(),
+            unsent: Vec::new(),
+            frame_version: LEGACY_FRAME_VERSION,
+            bytes_read: 0,
+            bytes_written: 0,
         }
     }
 }
 
-impl Into<TcpStream> for Stream {
-    fn into(self) -> TcpStream {
-        self.stream
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Self::from(Transport::Tcp(stream))
+    }
+}
+
+impl From<WebSocketStream<TcpStream>> for Stream {
+    fn from(stream: WebSocketStream<TcpStream>) -> Self {
+        Self::from(Transport::WebSocket(stream))
     }
 }
 
-impl<'a> Into<&'a TcpStream> for &'a Stream {
-    fn into(self) -> &'a TcpStream {
-        &self.stream
+#[cfg(any(test, feature = "fuzzing"))]
+impl From<MemoryStream> for Stream {
+    fn from(stream: MemoryStream) -> Self {
+        Self::from(Transport::Memory(stream))
     }
 }
 
@@ -264,3 +490,148 @@ impl Evented for SignedStream {
         self.stream.deregister(poll)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use ctypes::Secret;
+
+    use super::*;
+
+    fn streams() -> (MemoryStream, Stream) {
+        let address = net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        let (remote, local, _link) = MemoryStream::pair(address, address, 0);
+        let mut stream = Stream::from(local);
+        stream.set_frame_version(FRAME_VERSION);
+        (remote, stream)
+    }
+
+    fn frame() -> Vec<u8> {
+        let session = Session::new_with_zero_nonce(Secret::random());
+        seal(FRAME_VERSION, SignedMessage::new(&0xdead_beefu64, &session).rlp_bytes().into_vec())
+    }
+
+    fn is_corrupted<T>(result: Result<T>) -> bool {
+        match result {
+            Err(Error::CorruptedFrame) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn sealed_frame_is_read() {
+        let (mut remote, mut stream) = streams();
+        remote.write_all(&frame()).unwrap();
+        remote.write_all(&frame()).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_some());
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_some());
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_none());
+    }
+
+    #[test]
+    fn legacy_frame_has_no_checksum() {
+        let (mut remote, mut stream) = streams();
+        stream.set_frame_version(LEGACY_FRAME_VERSION);
+        let session = Session::new_with_zero_nonce(Secret::random());
+        let frame = SignedMessage::new(&0xdead_beefu64, &session).rlp_bytes().into_vec();
+        assert_eq!(frame, seal(LEGACY_FRAME_VERSION, frame.clone()));
+        remote.write_all(&frame).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_some());
+
+        stream.write(&0xdead_beefu64).unwrap();
+        let mut written = vec![0u8; 5];
+        assert_eq!(5, remote.read(&mut written).unwrap());
+        assert_eq!(0xdead_beefu64.rlp_bytes().into_vec(), written);
+    }
+
+    #[test]
+    fn traffic_counts_the_whole_frames() {
+        let (mut remote, mut stream) = streams();
+        remote.write_all(&frame()).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_some());
+        stream.write_bytes(&frame()).unwrap();
+        let length = frame().len() as u64;
+        assert_eq!((length, length), stream.traffic());
+    }
+
+    #[test]
+    fn unsent_tail_is_written_when_the_socket_takes_it() {
+        let address = net::SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        let (mut remote, local, link) = MemoryStream::pair(address, address, 0);
+        let mut stream = Stream::from(local);
+        let frame = frame();
+        link.set_capacity(frame.len() / 2);
+        stream.write_bytes(&frame).unwrap();
+        assert!(stream.has_unsent());
+
+        let mut received = vec![0u8; frame.len()];
+        let head = remote.read(&mut received).unwrap();
+        assert_eq!(frame.len() / 2, head);
+        link.set_capacity(usize::max_value());
+        stream.flush().unwrap();
+        assert!(!stream.has_unsent());
+        let tail = remote.read(&mut received[head..]).unwrap();
+        assert_eq!(frame, received[..(head + tail)].to_vec());
+        assert_eq!(frame.len() as u64, stream.traffic().1);
+    }
+
+    #[test]
+    fn partial_frame_waits_for_the_rest() {
+        let (mut remote, mut stream) = streams();
+        let frame = frame();
+        let (head, tail) = frame.split_at(frame.len() / 2);
+        remote.write_all(head).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_none());
+        remote.write_all(tail).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_some());
+    }
+
+    #[test]
+    fn corrupted_byte_is_detected() {
+        let (mut remote, mut stream) = streams();
+        let mut frame = frame();
+        frame[5] ^= 0x01;
+        remote.write_all(&frame).unwrap();
+        assert!(is_corrupted(stream.read_shared::<SignedMessage>()));
+        assert!(stream.read_buffer.is_empty());
+    }
+
+    #[test]
+    fn frame_larger_than_the_limit_is_corrupted() {
+        let (mut remote, mut stream) = streams();
+        remote.write_all(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap();
+        assert!(is_corrupted(stream.read_shared::<SignedMessage>()));
+        assert!(stream.read_buffer.is_empty());
+    }
+
+    #[test]
+    fn read_buffer_does_not_grow_beyond_the_largest_frame() {
+        let (mut remote, mut stream) = streams();
+        // The header of the largest frame, whose body doesn't arrive yet
+        remote.write_all(&[0xfb, 0x01, 0x00, 0x00, 0x00]).unwrap();
+        assert!(stream.read_shared::<SignedMessage>().unwrap().is_none());
+        assert_eq!(5, stream.read_buffer.len());
+        assert!(stream.read_buffer.capacity() <= MAX_READ_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn non_canonical_length_is_corrupted() {
+        let (mut remote, mut stream) = streams();
+        // The short list uses the long header
+        remote.write_all(&[0xf8, 0x01, 0x00]).unwrap();
+        assert!(is_corrupted(stream.read_shared::<SignedMessage>()));
+
+        let (mut remote, mut stream) = streams();
+        // The length has a leading zero
+        remote.write_all(&[0xf9, 0x00, 0x40]).unwrap();
+        assert!(is_corrupted(stream.read_shared::<SignedMessage>()));
+    }
+
+    #[test]
+    fn frame_which_is_not_a_list_is_corrupted() {
+        let (mut remote, mut stream) = streams();
+        remote.write_all(&[0x80]).unwrap();
+        assert!(is_corrupted(stream.read_shared::<SignedMessage>()));
+    }
+}
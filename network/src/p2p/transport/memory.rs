@@ -0,0 +1,362 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Connects the nodes in memory, so that the tests drive the handshake and the negotiation without sockets.
+//! The in-memory streams have no wall clock: the latency is counted in ticks, which the test advances with
+//! `MemoryLink::advance`.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net;
+use std::sync::Arc;
+
+#[cfg(test)]
+use mio::event::Evented;
+use mio::unix::UnixReady;
+#[cfg(test)]
+use mio::{Poll, PollOpt, Token};
+use mio::{Ready, Registration, SetReadiness};
+use parking_lot::Mutex;
+
+// The bytes flowing into one end of a link
+struct Wire {
+    // The chunks and the tick when they arrive
+    chunks: VecDeque<(u64, Vec<u8>)>,
+    closed: bool,
+    readiness: SetReadiness,
+}
+
+impl Wire {
+    fn new(readiness: SetReadiness) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            closed: false,
+            readiness,
+        }
+    }
+
+    fn has_arrived(&self, now: u64) -> bool {
+        self.chunks.front().map_or(false, |(arrival, _)| *arrival <= now)
+    }
+
+    fn notify(&self, now: u64) {
+        let mut readiness = Ready::writable();
+        if self.has_arrived(now) {
+            readiness |= Ready::readable();
+        }
+        if self.closed {
+            readiness |= Ready::readable() | UnixReady::hup();
+        }
+        // It fails only if the registration is already dropped
+        let _ = self.readiness.set_readiness(readiness);
+    }
+}
+
+struct LinkState {
+    now: u64,
+    latency: u64,
+    // The bytes which each wire holds at most, like the buffer of a socket
+    capacity: usize,
+    wires: [Wire; 2],
+}
+
+/// Controls the time of the link between two `MemoryStream`s.
+#[derive(Clone)]
+pub struct MemoryLink {
+    state: Arc<Mutex<LinkState>>,
+}
+
+impl MemoryLink {
+    /// Delivers the bytes which were written `ticks` or more ticks ago.
+    pub fn advance(&self, ticks: u64) {
+        let mut state = self.state.lock();
+        state.now += ticks;
+        let now = state.now;
+        for wire in state.wires.iter() {
+            wire.notify(now);
+        }
+    }
+
+    /// Limits the bytes which are written but not read yet. The writes beyond it would block.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.state.lock().capacity = capacity;
+    }
+}
+
+pub struct MemoryStream {
+    state: Arc<Mutex<LinkState>>,
+    // The index of the wire which this end reads
+    side: usize,
+    peer_addr: net::SocketAddr,
+    registration: Registration,
+}
+
+impl MemoryStream {
+    /// Connects `a` and `b`. The bytes written to one end arrive at the other after `latency` ticks.
+    pub fn pair(a: net::SocketAddr, b: net::SocketAddr, latency: u64) -> (MemoryStream, MemoryStream, MemoryLink) {
+        let (registration_a, readiness_a) = Registration::new2();
+        let (registration_b, readiness_b) = Registration::new2();
+        let state = Arc::new(Mutex::new(LinkState {
+            now: 0,
+            latency,
+            capacity: usize::max_value(),
+            wires: [Wire::new(readiness_a), Wire::new(readiness_b)],
+        }));
+        {
+            let state = state.lock();
+            for wire in state.wires.iter() {
+                wire.notify(0);
+            }
+        }
+        let stream_a = MemoryStream {
+            state: Arc::clone(&state),
+            side: 0,
+            peer_addr: b,
+            registration: registration_a,
+        };
+        let stream_b = MemoryStream {
+            state: Arc::clone(&state),
+            side: 1,
+            peer_addr: a,
+            registration: registration_b,
+        };
+        (
+            stream_a,
+            stream_b,
+            MemoryLink {
+                state,
+            },
+        )
+    }
+
+    pub fn peer_addr(&self) -> net::SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn registration(&self) -> &Registration {
+        &self.registration
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock();
+        let now = state.now;
+        let wire = &mut state.wires[self.side];
+        let mut read = 0;
+        while read < buf.len() && wire.has_arrived(now) {
+            let (arrival, mut chunk) = wire.chunks.pop_front().expect("The chunk has arrived");
+            let size = cmp::min(buf.len() - read, chunk.len());
+            buf[read..(read + size)].copy_from_slice(&chunk[..size]);
+            read += size;
+            if size < chunk.len() {
+                let rest = chunk.split_off(size);
+                wire.chunks.push_front((arrival, rest));
+            }
+        }
+        wire.notify(now);
+        if read == 0 && !wire.closed {
+            return Err(io::ErrorKind::WouldBlock.into())
+        }
+        Ok(read)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock();
+        let arrival = state.now + state.latency;
+        let now = state.now;
+        let capacity = state.capacity;
+        let wire = &mut state.wires[1 - self.side];
+        if wire.closed {
+            return Err(io::ErrorKind::BrokenPipe.into())
+        }
+        let buffered: usize = wire.chunks.iter().map(|(_, chunk)| chunk.len()).sum();
+        let size = cmp::min(buf.len(), capacity.saturating_sub(buffered));
+        if size == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into())
+        }
+        wire.chunks.push_back((arrival, buf[..size].to_vec()));
+        wire.notify(now);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let now = state.now;
+        let wire = &mut state.wires[1 - self.side];
+        wire.closed = true;
+        wire.notify(now);
+    }
+}
+
+#[cfg(test)]
+/// Accepts the `MemoryStream`s which a `MemoryConnector` dials.
+pub struct MemoryListener {
+    pending: Arc<Mutex<VecDeque<MemoryStream>>>,
+    registration: Registration,
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MemoryConnector {
+    address: net::SocketAddr,
+    pending: Arc<Mutex<VecDeque<MemoryStream>>>,
+    readiness: SetReadiness,
+}
+
+#[cfg(test)]
+impl MemoryListener {
+    /// The listener pretends to listen on `address`.
+    pub fn bind(address: net::SocketAddr) -> (MemoryListener, MemoryConnector) {
+        let (registration, readiness) = Registration::new2();
+        let pending = Arc::new(Mutex::new(VecDeque::new()));
+        (
+            MemoryListener {
+                pending: Arc::clone(&pending),
+                registration,
+            },
+            MemoryConnector {
+                address,
+                pending,
+                readiness,
+            },
+        )
+    }
+
+    pub fn accept(&self) -> io::Result<(MemoryStream, net::SocketAddr)> {
+        match self.pending.lock().pop_front() {
+            Some(stream) => {
+                let peer_addr = stream.peer_addr;
+                Ok((stream, peer_addr))
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Evented for MemoryListener {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.registration.register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.registration.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(&self.registration)
+    }
+}
+
+#[cfg(test)]
+impl MemoryConnector {
+    /// Dials the listener from `from`, and returns this end of the connection.
+    pub fn connect(&self, from: net::SocketAddr, latency: u64) -> (MemoryStream, MemoryLink) {
+        let (local, remote, link) = MemoryStream::pair(from, self.address, latency);
+        self.pending.lock().push_back(remote);
+        // It fails only if the listener is already dropped
+        let _ = self.readiness.set_readiness(Ready::readable());
+        (local, link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    fn address(port: u16) -> net::SocketAddr {
+        net::SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    fn is_would_block<T>(result: io::Result<T>) -> bool {
+        match result {
+            Err(ref err) => err.kind() == io::ErrorKind::WouldBlock,
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn bytes_arrive_at_the_other_end() {
+        let (mut a, mut b, _link) = MemoryStream::pair(address(1), address(2), 0);
+        a.write_all(b"hello").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(5, b.read(&mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+        assert!(is_would_block(b.read(&mut buf)));
+    }
+
+    #[test]
+    fn bytes_arrive_after_the_latency() {
+        let (mut a, mut b, link) = MemoryStream::pair(address(1), address(2), 2);
+        a.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0; 3];
+        assert!(is_would_block(b.read(&mut buf)));
+        link.advance(1);
+        assert!(is_would_block(b.read(&mut buf)));
+        link.advance(1);
+        assert_eq!(3, b.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn chunk_is_read_in_pieces() {
+        let (mut a, mut b, _link) = MemoryStream::pair(address(1), address(2), 0);
+        a.write_all(&[1, 2, 3, 4, 5]).unwrap();
+        let mut buf = [0; 2];
+        assert_eq!(2, b.read(&mut buf).unwrap());
+        assert_eq!([1, 2], buf);
+        assert_eq!(2, b.read(&mut buf).unwrap());
+        assert_eq!([3, 4], buf);
+        assert_eq!(1, b.read(&mut buf).unwrap());
+        assert_eq!(5, buf[0]);
+    }
+
+    #[test]
+    fn dropped_end_closes_the_link() {
+        let (a, mut b, _link) = MemoryStream::pair(address(1), address(2), 0);
+        drop(a);
+        let mut buf = [0; 1];
+        assert_eq!(0, b.read(&mut buf).unwrap());
+        assert_eq!(io::ErrorKind::BrokenPipe, b.write(&[1]).unwrap_err().kind());
+    }
+
+    #[test]
+    fn listener_accepts_the_dialed_stream() {
+        let (listener, connector) = MemoryListener::bind(address(3485));
+        assert!(is_would_block(listener.accept()));
+
+        let (mut local, _link) = connector.connect(address(3000), 0);
+        let (mut remote, peer_addr) = listener.accept().unwrap();
+        assert_eq!(address(3000), peer_addr);
+        assert_eq!(address(3485), local.peer_addr());
+
+        local.write_all(&[7]).unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(1, remote.read(&mut buf).unwrap());
+        assert_eq!([7], buf);
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! The byte streams under `Stream` and `Listener`.
+//!
+//! Besides TCP, the light clients in the browsers connect over WebSocket, see `websocket`.
+//! The tests can connect the nodes in memory, see `memory`.
+
+use std::io::{self, Read, Write};
+use std::net;
+
+use mio::event::Evented;
+use mio::net::TcpStream;
+use mio::{Poll, PollOpt, Ready, Token};
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub use self::memory::{MemoryLink, MemoryStream};
+#[cfg(test)]
+pub use self::memory::{MemoryConnector, MemoryListener};
+pub use self::websocket::WebSocketStream;
+
+#[cfg(any(test, feature = "fuzzing"))]
+mod memory;
+mod websocket;
+
+pub enum Transport {
+    Tcp(TcpStream),
+    WebSocket(WebSocketStream<TcpStream>),
+    #[cfg(any(test, feature = "fuzzing"))]
+    Memory(MemoryStream),
+}
+
+impl Transport {
+    pub fn peer_addr(&self) -> io::Result<net::SocketAddr> {
+        match self {
+            Transport::Tcp(stream) => stream.peer_addr(),
+            Transport::WebSocket(stream) => stream.get_ref().peer_addr(),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => Ok(stream.peer_addr()),
+        }
+    }
+
+    /// Returns true if the transport holds the bytes which the socket didn't take yet.
+    pub fn has_pending_write(&self) -> bool {
+        match self {
+            Transport::Tcp(_) => false,
+            Transport::WebSocket(stream) => stream.has_outgoing(),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(_) => false,
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.read(buf),
+            Transport::WebSocket(stream) => stream.read(buf),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.write(buf),
+            Transport::WebSocket(stream) => stream.write(buf),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.flush(),
+            Transport::WebSocket(stream) => stream.flush(),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Evented for Transport {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.register(poll, token, interest, opts),
+            Transport::WebSocket(stream) => stream.get_ref().register(poll, token, interest, opts),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => stream.registration().register(poll, token, interest, opts),
+        }
+    }
+
+    fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.reregister(poll, token, interest, opts),
+            Transport::WebSocket(stream) => stream.get_ref().reregister(poll, token, interest, opts),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => stream.registration().reregister(poll, token, interest, opts),
+        }
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.deregister(poll),
+            Transport::WebSocket(stream) => stream.get_ref().deregister(poll),
+            #[cfg(any(test, feature = "fuzzing"))]
+            Transport::Memory(stream) => poll.deregister(stream.registration()),
+        }
+    }
+}
@@ -0,0 +1,510 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The server side of the WebSocket protocol(RFC 6455) for the light clients in the browsers.
+//!
+//! The p2p frames are carried in the binary messages. A message may hold several frames, and a frame may be split
+//! across messages, since the frames are read as a byte stream.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use ccrypto::sha1;
+
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// The handshake request larger than this is rejected
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+// A little more than the largest p2p frame
+const MAX_PAYLOAD_SIZE: u64 = 17 * 1024 * 1024;
+// The client which doesn't read the messages is disconnected when this many bytes are waiting for it
+const MAX_OUTGOING_SIZE: usize = 2 * MAX_PAYLOAD_SIZE as usize;
+
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Handshaking,
+    Open,
+    Closed,
+}
+
+pub struct WebSocketStream<S> {
+    stream: S,
+    state: State,
+    // The bytes from the socket which are not decoded yet
+    incoming: Vec<u8>,
+    // The payloads of the binary messages which are not read yet
+    payload: Vec<u8>,
+    // The bytes which the socket didn't take yet
+    outgoing: Vec<u8>,
+}
+
+impl<S: Read + Write> WebSocketStream<S> {
+    /// Wraps an accepted stream. The handshake is done while reading.
+    pub fn accept(stream: S) -> Self {
+        Self {
+            stream,
+            state: State::Handshaking,
+            incoming: Vec::new(),
+            payload: Vec::new(),
+            outgoing: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns true if the socket didn't take all the messages yet.
+    /// The owner should wait for the socket to be writable and flush the stream again.
+    pub fn has_outgoing(&self) -> bool {
+        !self.outgoing.is_empty()
+    }
+
+    fn queue(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.outgoing.len() + bytes.len() > MAX_OUTGOING_SIZE {
+            self.state = State::Closed;
+            return Err(io::Error::new(io::ErrorKind::Other, "The client doesn't read the messages"))
+        }
+        self.outgoing.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn flush_outgoing(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // Returns false if more bytes are needed
+    fn decode(&mut self) -> io::Result<bool> {
+        match self.state {
+            State::Handshaking => self.decode_handshake(),
+            State::Open => self.decode_frame(),
+            State::Closed => Ok(false),
+        }
+    }
+
+    fn decode_handshake(&mut self) -> io::Result<bool> {
+        let end = match self.incoming.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(position) => position + 4,
+            None if self.incoming.len() > MAX_HANDSHAKE_SIZE => return Err(invalid_data("Too large handshake")),
+            None => return Ok(false),
+        };
+        let response = {
+            let request = ::std::str::from_utf8(&self.incoming[..end]).map_err(|_| invalid_data("Invalid handshake"))?;
+            handshake_response(request)?
+        };
+        self.incoming.drain(..end);
+        self.queue(response.as_bytes())?;
+        self.state = State::Open;
+        Ok(true)
+    }
+
+    fn decode_frame(&mut self) -> io::Result<bool> {
+        let (header_length, payload_length) = match frame_length(&self.incoming)? {
+            Some(length) => length,
+            None => return Ok(false),
+        };
+        if (self.incoming.len() as u64) < header_length as u64 + payload_length {
+            return Ok(false)
+        }
+        let frame_end = header_length + payload_length as usize;
+        let opcode = self.incoming[0] & 0x0f;
+        let mut payload: Vec<u8> = self.incoming[header_length..frame_end].to_vec();
+        {
+            let mask = &self.incoming[(header_length - 4)..header_length];
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        self.incoming.drain(..frame_end);
+
+        match opcode {
+            OPCODE_CONTINUATION | OPCODE_BINARY => self.payload.extend_from_slice(&payload),
+            OPCODE_CLOSE => {
+                // Echoes the status code
+                let status_code = &payload[..cmp::min(2, payload.len())];
+                self.queue(&encode_frame(OPCODE_CLOSE, status_code))?;
+                self.state = State::Closed;
+            }
+            OPCODE_PING => self.queue(&encode_frame(OPCODE_PONG, &payload))?,
+            OPCODE_PONG => {}
+            _ => return Err(invalid_data("Unsupported opcode")),
+        }
+        Ok(true)
+    }
+}
+
+impl<S: Read + Write> Read for WebSocketStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_outgoing()?;
+        loop {
+            if !self.payload.is_empty() {
+                let len = cmp::min(buf.len(), self.payload.len());
+                buf[..len].copy_from_slice(&self.payload[..len]);
+                self.payload.drain(..len);
+                return Ok(len)
+            }
+            if self.decode()? {
+                self.flush_outgoing()?;
+                continue
+            }
+            if self.state == State::Closed {
+                return Ok(0)
+            }
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match self.stream.read(&mut chunk)? {
+                0 => {
+                    self.state = State::Closed;
+                    return Ok(0)
+                }
+                read => self.incoming.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for WebSocketStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.state {
+            State::Handshaking => return Err(io::ErrorKind::WouldBlock.into()),
+            State::Closed => return Err(io::ErrorKind::BrokenPipe.into()),
+            State::Open => {}
+        }
+        // The whole buffer becomes a message, and the socket takes it when it's writable
+        self.queue(&encode_frame(OPCODE_BINARY, buf))?;
+        self.flush_outgoing()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_outgoing()?;
+        self.stream.flush()
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn handshake_response(request: &str) -> io::Result<String> {
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    if !request_line.starts_with("GET ") {
+        return Err(invalid_data("Not a GET request"))
+    }
+    let mut upgrade = false;
+    let mut version = false;
+    let mut key = None;
+    for line in lines {
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim().to_lowercase();
+        let value = header.next().unwrap_or("").trim();
+        match name.as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-version" => version = value == "13",
+            "sec-websocket-key" => key = Some(value),
+            _ => {}
+        }
+    }
+    match key {
+        Some(key) if upgrade && version => Ok(format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        )),
+        _ => Err(invalid_data("Not a WebSocket handshake")),
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = sha1(format!("{}{}", key, GUID));
+    base64(&digest)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let indices = [b[0] >> 2, ((b[0] & 0x03) << 4) | (b[1] >> 4), ((b[1] & 0x0f) << 2) | (b[2] >> 6), b[2] & 0x3f];
+        for (i, index) in indices.iter().enumerate() {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[*index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Returns the length of the header, including the mask, and the length of the payload.
+// The frames from the clients must be masked.
+fn frame_length(bytes: &[u8]) -> io::Result<Option<(usize, u64)>> {
+    if bytes.len() < 2 {
+        return Ok(None)
+    }
+    if bytes[1] & 0x80 == 0 {
+        return Err(invalid_data("Unmasked frame"))
+    }
+    let (length_size, payload_length) = match bytes[1] & 0x7f {
+        126 if bytes.len() < 4 => return Ok(None),
+        126 => (2, (u64::from(bytes[2]) << 8) | u64::from(bytes[3])),
+        127 if bytes.len() < 10 => return Ok(None),
+        127 => (8, bytes[2..10].iter().fold(0, |length, byte| (length << 8) | u64::from(*byte))),
+        length => (0, u64::from(length)),
+    };
+    if payload_length > MAX_PAYLOAD_SIZE {
+        return Err(invalid_data("Too large frame"))
+    }
+    Ok(Some((2 + length_size + 4, payload_length)))
+}
+
+// The frames from the server are not masked
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0...125 => frame.push(len as u8),
+        len @ 126...0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&[(len >> 8) as u8, len as u8]);
+        }
+        len => {
+            frame.push(127);
+            let len = len as u64;
+            for shift in (0..8).rev() {
+                frame.push((len >> (shift * 8)) as u8);
+            }
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A socket which has the bytes from the client, and would block when they are all read
+    struct FakeSocket {
+        input: Vec<u8>,
+        output: Vec<u8>,
+        // The socket doesn't take any byte while it's full
+        is_full: bool,
+    }
+
+    impl Read for FakeSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into())
+            }
+            let len = cmp::min(buf.len(), self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.is_full {
+                return Err(io::ErrorKind::WouldBlock.into())
+            }
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const REQUEST: &'static str = "GET /p2p HTTP/1.1\r\n\
+                                   Host: localhost\r\n\
+                                   Upgrade: websocket\r\n\
+                                   Connection: Upgrade\r\n\
+                                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                                   Sec-WebSocket-Version: 13\r\n\r\n";
+
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = encode_frame(opcode, payload);
+        let header_length = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        let masked: Vec<u8> = payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+        frame.truncate(header_length);
+        frame.extend_from_slice(&mask);
+        frame.extend_from_slice(&masked);
+        frame
+    }
+
+    fn opened(input: Vec<u8>) -> WebSocketStream<FakeSocket> {
+        let mut socket_input = REQUEST.as_bytes().to_vec();
+        socket_input.extend_from_slice(&input);
+        WebSocketStream::accept(FakeSocket {
+            input: socket_input,
+            output: Vec::new(),
+            is_full: false,
+        })
+    }
+
+    fn read_all(stream: &mut WebSocketStream<FakeSocket>) -> Vec<u8> {
+        let mut read = Vec::new();
+        let mut buf = [0u8; 4];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => return read,
+                Ok(len) => read.extend_from_slice(&buf[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return read,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn accept_key_of_the_rfc_example() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn base64_pads_the_last_chunk() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYg==", base64(b"foob"));
+    }
+
+    #[test]
+    fn handshake_is_answered() {
+        let mut stream = opened(Vec::new());
+        assert_eq!(Vec::<u8>::new(), read_all(&mut stream));
+        let response = String::from_utf8(stream.get_ref().output.clone()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn non_websocket_request_is_rejected() {
+        let mut stream = WebSocketStream::accept(FakeSocket {
+            input: b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+            output: Vec::new(),
+            is_full: false,
+        });
+        let err = stream.read(&mut [0u8; 4]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn binary_messages_are_read_as_a_stream() {
+        let mut input = client_frame(OPCODE_BINARY, b"hello ");
+        input.extend_from_slice(&client_frame(OPCODE_BINARY, b"world"));
+        let mut stream = opened(input);
+        assert_eq!(b"hello world".to_vec(), read_all(&mut stream));
+    }
+
+    #[test]
+    fn large_message_is_unmasked() {
+        let payload: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+        let mut stream = opened(client_frame(OPCODE_BINARY, &payload));
+        assert_eq!(payload, read_all(&mut stream));
+    }
+
+    #[test]
+    fn unmasked_frame_is_rejected() {
+        let mut stream = opened(encode_frame(OPCODE_BINARY, b"hello"));
+        let err = stream.read(&mut [0u8; 4]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn ping_is_answered_with_pong() {
+        let mut stream = opened(client_frame(OPCODE_PING, b"ping"));
+        read_all(&mut stream);
+        let output = &stream.get_ref().output;
+        assert!(output.ends_with(&encode_frame(OPCODE_PONG, b"ping")));
+    }
+
+    #[test]
+    fn close_ends_the_stream() {
+        let mut input = client_frame(OPCODE_CLOSE, &[0x03, 0xe8]);
+        input.extend_from_slice(&client_frame(OPCODE_BINARY, b"ignored"));
+        let mut stream = opened(input);
+        assert_eq!(0, stream.read(&mut [0u8; 4]).unwrap());
+        assert!(stream.get_ref().output.ends_with(&encode_frame(OPCODE_CLOSE, &[0x03, 0xe8])));
+        assert_eq!(io::ErrorKind::BrokenPipe, stream.write(b"data").unwrap_err().kind());
+    }
+
+    #[test]
+    fn written_bytes_become_a_binary_message() {
+        let mut stream = opened(Vec::new());
+        read_all(&mut stream);
+        assert_eq!(4, stream.write(b"data").unwrap());
+        assert!(stream.get_ref().output.ends_with(&[0x82, 4, b'd', b'a', b't', b'a']));
+    }
+
+    #[test]
+    fn message_is_kept_until_the_socket_takes_it() {
+        let mut stream = opened(Vec::new());
+        read_all(&mut stream);
+        stream.stream.is_full = true;
+        assert_eq!(4, stream.write(b"data").unwrap());
+        assert!(stream.has_outgoing());
+
+        stream.stream.is_full = false;
+        stream.flush().unwrap();
+        assert!(!stream.has_outgoing());
+        assert!(stream.get_ref().output.ends_with(&[0x82, 4, b'd', b'a', b't', b'a']));
+    }
+
+    #[test]
+    fn client_which_does_not_read_is_disconnected() {
+        let mut stream = opened(Vec::new());
+        read_all(&mut stream);
+        stream.stream.is_full = true;
+        let message = vec![0u8; 1024 * 1024];
+        let mut written = 0;
+        let err = loop {
+            match stream.write(&message) {
+                Ok(len) => written += len,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(io::ErrorKind::Other, err.kind());
+        assert!(written <= MAX_OUTGOING_SIZE);
+        assert_eq!(io::ErrorKind::BrokenPipe, stream.write(b"data").unwrap_err().kind());
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use ckeys::hex::{FromHex, ToHex};
+use rlp::{Encodable, UntrustedRlp};
+
+use super::NodeRecord;
+
+/// Loads the records of the peers which this node was connected to, from the file at `path`.
+/// The store is empty if the file doesn't exist.
+pub fn load(path: &Path) -> Result<Vec<NodeRecord>, String> {
+    if !path.exists() {
+        return Ok(vec![])
+    }
+    let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read peer store {:?}: {}", path, e))?;
+    let mut records = Vec::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match decode(line) {
+            Some(record) => records.push(record),
+            // The store is only a hint, so a broken line doesn't stop the node
+            None => cwarn!(NET, "Ignoring the invalid record {} in the peer store {:?}", line, path),
+        }
+    }
+    Ok(records)
+}
+
+fn decode(line: &str) -> Option<NodeRecord> {
+    let bytes = line.from_hex().ok()?;
+    let record: NodeRecord = UntrustedRlp::new(&bytes).as_val().ok()?;
+    if !record.verify() {
+        return None
+    }
+    Some(record)
+}
+
+/// Replaces the records in the file at `path`, one hex-encoded record per line.
+pub fn save(path: &Path, records: &[NodeRecord]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Cannot create {:?}: {}", parent, e))?;
+    }
+    let contents: String = records.iter().map(|record| format!("{}\n", record.rlp_bytes().to_hex())).collect();
+    let mut file = fs::File::create(path).map_err(|e| format!("Cannot create peer store {:?}: {}", path, e))?;
+    file.write_all(contents.as_bytes()).map_err(|e| format!("Cannot write peer store {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use ckeys::{Generator, Random};
+
+    use super::super::SocketAddr;
+    use super::*;
+
+    fn record(address: SocketAddr) -> NodeRecord {
+        NodeRecord::new(&Random.generate().unwrap(), 0, vec![address], 0x11, vec![])
+    }
+
+    #[test]
+    fn saved_peers_are_loaded_again() {
+        let path = env::temp_dir().join("codechain-network-peer-store-test");
+        let _ = fs::remove_file(&path);
+
+        let records = vec![
+            record(SocketAddr::v4(127, 0, 0, 1, 3485)),
+            record(SocketAddr::v6(0, 0, 0, 0, 0, 0, 0, 1, 3486)),
+        ];
+        save(&path, &records).unwrap();
+        assert_eq!(records, load(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_store_is_empty_without_the_file() {
+        let path = env::temp_dir().join("codechain-network-peer-store-missing-test");
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(Vec::<NodeRecord>::new(), load(&path).unwrap());
+    }
+
+    #[test]
+    fn invalid_lines_are_ignored() {
+        let path = env::temp_dir().join("codechain-network-peer-store-invalid-test");
+        let valid = record(SocketAddr::v4(127, 0, 0, 1, 3485));
+        let contents = format!("{}\n127.0.0.1:3486\nnot a record\n\n", valid.rlp_bytes().to_hex());
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(vec![valid], load(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forged_records_are_ignored() {
+        let path = env::temp_dir().join("codechain-network-peer-store-forged-test");
+        let mut bytes = record(SocketAddr::v4(127, 0, 0, 1, 3485)).rlp_bytes().to_vec();
+        // Corrupts the signature
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, format!("{}\n", bytes.to_hex())).unwrap();
+
+        assert_eq!(Vec::<NodeRecord>::new(), load(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::cmp;
+use std::collections::HashMap;
+
+use super::NodeId;
+
+const MIN_SCORE: i64 = -100;
+const MAX_SCORE: i64 = 100;
+
+/// How a peer served an extension.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum PeerBehavior {
+    /// The peer answered with valid data, e.g. the requested blocks
+    ServedValidData,
+    /// The peer sent data which failed the verification
+    SentInvalidData,
+    /// The peer didn't answer in time
+    TimedOut,
+}
+
+impl PeerBehavior {
+    fn weight(&self) -> i64 {
+        match self {
+            PeerBehavior::ServedValidData => 1,
+            PeerBehavior::SentInvalidData => -20,
+            PeerBehavior::TimedOut => -5,
+        }
+    }
+}
+
+/// The aggregate of the behaviors reported by the extensions.
+/// A peer which nobody reported has the neutral score, zero.
+pub struct Reputations {
+    scores: HashMap<NodeId, i64>,
+}
+
+impl Reputations {
+    pub fn new() -> Self {
+        Self {
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn report(&mut self, node: &NodeId, behavior: PeerBehavior) {
+        let score = self.scores.entry(*node).or_insert(0);
+        *score = cmp::max(MIN_SCORE, cmp::min(MAX_SCORE, *score + behavior.weight()));
+    }
+
+    pub fn score(&self, node: &NodeId) -> i64 {
+        self.scores.get(node).cloned().unwrap_or(0)
+    }
+
+    pub fn forget(&mut self, node: &NodeId) {
+        self.scores.remove(node);
+    }
+
+    /// The least reputable peer among the candidates, whatever its score is.
+    /// The earlier candidate wins the tie.
+    pub fn lowest<I>(&self, candidates: I) -> Option<NodeId>
+    where
+        I: IntoIterator<Item = NodeId>, {
+        candidates
+            .into_iter()
+            .map(|node| (self.score(&node), node))
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, node)| node)
+    }
+
+    /// The peer to be evicted first among the candidates, if any has a negative score.
+    pub fn worst<I>(&self, candidates: I) -> Option<NodeId>
+    where
+        I: IntoIterator<Item = NodeId>, {
+        candidates
+            .into_iter()
+            .map(|node| (self.score(&node), node))
+            .filter(|(score, _)| *score < 0)
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, node)| node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_peer_is_neutral() {
+        let reputations = Reputations::new();
+        assert_eq!(0, reputations.score(&1.into()));
+    }
+
+    #[test]
+    fn reports_are_aggregated() {
+        let mut reputations = Reputations::new();
+        let node = 1.into();
+        reputations.report(&node, PeerBehavior::ServedValidData);
+        reputations.report(&node, PeerBehavior::ServedValidData);
+        reputations.report(&node, PeerBehavior::TimedOut);
+        assert_eq!(-3, reputations.score(&node));
+    }
+
+    #[test]
+    fn score_is_bounded() {
+        let mut reputations = Reputations::new();
+        let node = 1.into();
+        for _ in 0..100 {
+            reputations.report(&node, PeerBehavior::SentInvalidData);
+        }
+        assert_eq!(MIN_SCORE, reputations.score(&node));
+        for _ in 0..1000 {
+            reputations.report(&node, PeerBehavior::ServedValidData);
+        }
+        assert_eq!(MAX_SCORE, reputations.score(&node));
+    }
+
+    #[test]
+    fn the_lowest_scored_peer_is_the_worst() {
+        let mut reputations = Reputations::new();
+        reputations.report(&1.into(), PeerBehavior::TimedOut);
+        reputations.report(&2.into(), PeerBehavior::SentInvalidData);
+        reputations.report(&3.into(), PeerBehavior::ServedValidData);
+        let candidates = vec![1.into(), 2.into(), 3.into(), 4.into()];
+        assert_eq!(Some(NodeId::from(2)), reputations.worst(candidates));
+    }
+
+    #[test]
+    fn well_behaving_peers_are_not_evicted() {
+        let mut reputations = Reputations::new();
+        reputations.report(&1.into(), PeerBehavior::ServedValidData);
+        assert_eq!(None, reputations.worst(vec![1.into(), 2.into()]));
+    }
+
+    #[test]
+    fn the_lowest_peer_may_be_well_behaving() {
+        let mut reputations = Reputations::new();
+        reputations.report(&1.into(), PeerBehavior::ServedValidData);
+        reputations.report(&2.into(), PeerBehavior::ServedValidData);
+        reputations.report(&2.into(), PeerBehavior::ServedValidData);
+        assert_eq!(Some(NodeId::from(1)), reputations.lowest(vec![2.into(), 1.into()]));
+        assert_eq!(None, reputations.lowest(vec![]));
+    }
+
+    #[test]
+    fn the_earlier_candidate_is_the_lowest_in_a_tie() {
+        let reputations = Reputations::new();
+        assert_eq!(Some(NodeId::from(3)), reputations.lowest(vec![3.into(), 1.into(), 2.into()]));
+    }
+
+    #[test]
+    fn forgotten_peer_is_neutral_again() {
+        let mut reputations = Reputations::new();
+        let node = 1.into();
+        reputations.report(&node, PeerBehavior::SentInvalidData);
+        reputations.forget(&node);
+        assert_eq!(0, reputations.score(&node));
+    }
+}
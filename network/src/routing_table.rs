@@ -14,17 +14,34 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use ckeys::{exchange, Generator, KeyPair, Public, Random};
-use ctypes::Secret;
+use ckeys::{exchange, Generator, KeyPair, Public, Random, SharedSecret};
 use parking_lot::{Mutex, RwLock};
 use rand::{OsRng, Rng};
 use rlp::{Decodable, Encodable, UntrustedRlp};
 
 use super::session::{Nonce, Session};
-use super::{NodeId, SocketAddr};
+use super::{NodeId, NodeRecord, SocketAddr};
+
+// The oldest records are forgotten beyond this, so that the records of generated keys cannot exhaust the memory.
+const MAX_RECORDS: usize = 1_000;
+
+/// The number of the addresses in each step of making a session
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoutingTableSizes {
+    pub candidates: usize,
+    pub uninitialized: usize,
+    pub key_pairs: usize,
+    pub shared_secrets: usize,
+    pub temporary_nonces: usize,
+    pub unestablished_sessions: usize,
+    pub established: usize,
+    /// The addresses which will be forgotten if their sessions are not established in time
+    pub pending: usize,
+}
 
 pub struct RoutingTable {
     // Only the addresses are known
@@ -37,7 +54,7 @@ pub struct RoutingTable {
     key_pairs: RwLock<HashMap<SocketAddr, KeyPair>>,
 
     // remote node id -> shared secret
-    shared_secrets: RwLock<HashMap<SocketAddr, Secret>>,
+    shared_secrets: RwLock<HashMap<SocketAddr, SharedSecret>>,
 
     // remote node id -> temporary nonce
     temporary_nonces: RwLock<HashMap<SocketAddr, Nonce>>,
@@ -47,6 +64,9 @@ pub struct RoutingTable {
 
     established: RwLock<HashSet<SocketAddr>>,
 
+    // When the session of the address began to be made. The address is forgotten if it's not established in time.
+    pending_since: RwLock<HashMap<SocketAddr, Instant>>,
+
     // remote node id => local node id
     // One node can have multiple node ids because the machine can has a multiple ip addresses
     // This field represents the local node id that remote node thinks.
@@ -54,6 +74,14 @@ pub struct RoutingTable {
 
     id_to_addresses: RwLock<HashMap<NodeId, SocketAddr>>,
 
+    // The address of this node which the peers agree on
+    external_address: RwLock<Option<SocketAddr>>,
+
+    // The verified records of the other nodes by their node keys
+    records: RwLock<Records>,
+    // The record of this node which is passed to the peers
+    local_record: RwLock<Option<NodeRecord>>,
+
     rng: Mutex<OsRng>,
 }
 
@@ -69,9 +97,16 @@ impl RoutingTable {
             unestablished_sessions: RwLock::new(HashMap::new()),
             established: RwLock::new(HashSet::new()),
 
+            pending_since: RwLock::new(HashMap::new()),
+
             remote_to_local_node_ids: RwLock::new(HashMap::new()),
             id_to_addresses: RwLock::new(HashMap::new()),
 
+            external_address: RwLock::new(None),
+
+            records: RwLock::new(Records::new()),
+            local_record: RwLock::new(None),
+
             rng: Mutex::new(OsRng::new().unwrap()),
         })
     }
@@ -93,6 +128,10 @@ impl RoutingTable {
             .collect()
     }
 
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.candidates.read().contains(addr) || self.all_addresses().contains(addr)
+    }
+
     pub fn add_candidate(&self, addr: SocketAddr) -> bool {
         let mut candidates = self.candidates.write();
         let uninitialized = self.uninitializeds.read();
@@ -140,6 +179,7 @@ impl RoutingTable {
         let mut established = self.established.write();
         let mut remote_to_local_node_ids = self.remote_to_local_node_ids.write();
         let mut id_to_addresses = self.id_to_addresses.write();
+        self.pending_since.write().remove(&addr);
 
         if candidates.remove(&addr) {
             return true
@@ -197,6 +237,7 @@ impl RoutingTable {
 
         let remote_node_id: NodeId = addr.into();
         id_to_addresses.insert(remote_node_id, addr.clone());
+        self.pending_since.write().insert(addr.clone(), Instant::now());
 
         match remote_to_local_node_ids.insert(remote_node_id, local_node_id) {
             None => cinfo!(NET, "{:?} thinks my node id is {}", addr, local_node_id),
@@ -230,13 +271,14 @@ impl RoutingTable {
         if let None = key_pairs.remove(remote_address) {
             return false
         }
+        self.pending_since.write().remove(remote_address);
 
         let t = candidates.insert(remote_address.clone());
         debug_assert!(t);
         true
     }
 
-    pub fn share_secret(&self, remote_address: &SocketAddr, remote_public: &Public) -> Option<Secret> {
+    pub fn share_secret(&self, remote_address: &SocketAddr, remote_public: &Public) -> Option<SharedSecret> {
         let mut key_pairs = self.key_pairs.write();
         let mut shared_secrets = self.shared_secrets.write();
 
@@ -327,19 +369,53 @@ impl RoutingTable {
         true
     }
 
-    pub fn establish(&self, remote_address: &SocketAddr) -> bool {
+    /// Takes the session out of the unestablished ones, so that it backs only one connection.
+    /// The counters of the AEAD nonces start from zero on each connection, which must not share the session.
+    /// Returns `None` if the session is not ready or is already in use.
+    pub fn establish(&self, remote_address: &SocketAddr) -> Option<Session> {
         let mut unestablished_sessions = self.unestablished_sessions.write();
         let mut established = self.established.write();
 
-        if !unestablished_sessions.contains_key(remote_address) {
-            return false
-        }
+        let session = unestablished_sessions.remove(remote_address)?;
         debug_assert!(!established.contains(remote_address));
-
-        let t = unestablished_sessions.remove(remote_address);
-        debug_assert!(t.is_some());
         established.insert(remote_address.clone());
-        true
+        self.pending_since.write().remove(remote_address);
+        Some(session)
+    }
+
+    /// Forgets the addresses whose sessions are not established in `ttl`, so that they can be tried again later.
+    /// Returns the number of the expired sessions.
+    pub fn expire_sessions(&self, ttl: Duration, now: Instant) -> usize {
+        let expired: Vec<_> = self
+            .pending_since
+            .read()
+            .iter()
+            .filter(|(_, since)| **since + ttl <= now)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in &expired {
+            cdebug!(NET, "The session with {} is expired", address);
+            self.remove_node(address.clone());
+        }
+        expired.len()
+    }
+
+    /// The number of the sessions which are being made or are not established yet.
+    pub fn pending_sessions(&self) -> usize {
+        self.pending_since.read().len()
+    }
+
+    pub fn sizes(&self) -> RoutingTableSizes {
+        RoutingTableSizes {
+            candidates: self.candidates.read().len(),
+            uninitialized: self.uninitializeds.read().len(),
+            key_pairs: self.key_pairs.read().len(),
+            shared_secrets: self.shared_secrets.read().len(),
+            temporary_nonces: self.temporary_nonces.read().len(),
+            unestablished_sessions: self.unestablished_sessions.read().len(),
+            established: self.established.read().len(),
+            pending: self.pending_since.read().len(),
+        }
     }
 
     pub fn unestablished_session(&self, remote_address: &SocketAddr) -> Option<Session> {
@@ -364,6 +440,56 @@ impl RoutingTable {
         id_to_addresses.get(remote_node_id).cloned()
     }
 
+    pub fn external_address(&self) -> Option<SocketAddr> {
+        self.external_address.read().clone()
+    }
+
+    pub fn set_external_address(&self, address: Option<SocketAddr>) {
+        *self.external_address.write() = address;
+    }
+
+    /// Stores the record if it's signed and newer than the known one, and adds its addresses to the candidates.
+    /// The records of the other networks are rejected.
+    pub fn add_record(&self, record: NodeRecord) -> bool {
+        if !record.verify() {
+            return false
+        }
+        if let Some(local_record) = &*self.local_record.read() {
+            if local_record.public() == record.public() || local_record.network_id() != record.network_id() {
+                return false
+            }
+        }
+        let mut records = self.records.write();
+        if let Some(known) = records.by_public.get(record.public()) {
+            if record.sequence() <= known.sequence() {
+                return false
+            }
+        }
+        for address in record.addresses() {
+            self.add_candidate(address.clone());
+        }
+        records.insert(record);
+        true
+    }
+
+    /// The record of the node whose key is verified in the handshake.
+    /// The addresses cannot identify the node, because anyone can sign a record with the address of another one.
+    pub fn record(&self, public: &Public) -> Option<NodeRecord> {
+        self.records.read().by_public.get(public).cloned()
+    }
+
+    pub fn records(&self) -> Vec<NodeRecord> {
+        self.records.read().by_public.values().cloned().collect()
+    }
+
+    pub fn local_record(&self) -> Option<NodeRecord> {
+        self.local_record.read().clone()
+    }
+
+    pub fn set_local_record(&self, record: NodeRecord) {
+        *self.local_record.write() = Some(record);
+    }
+
     pub fn candidates(&self, len: &usize) -> Vec<SocketAddr> {
         let candidates = self.candidates.read();
         let mut rng = self.rng.lock();
@@ -385,3 +511,132 @@ fn encode_and_encrypt_nonce(session: &Session, nonce: &Nonce) -> Option<Vec<u8>>
     let encoded_nonce = nonce.rlp_bytes();
     session.encrypt(&encoded_nonce).ok()
 }
+
+struct Records {
+    by_public: HashMap<Public, NodeRecord>,
+    // The node keys in the order of their first records
+    order: VecDeque<Public>,
+}
+
+impl Records {
+    fn new() -> Self {
+        Self {
+            by_public: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, record: NodeRecord) {
+        let public = *record.public();
+        if self.by_public.insert(public, record).is_some() {
+            return
+        }
+        self.order.push_back(public);
+        if self.order.len() > MAX_RECORDS {
+            let oldest = self.order.pop_front().expect("The order is not empty");
+            self.by_public.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key_pair: &KeyPair, sequence: u64, network_id: u64) -> NodeRecord {
+        NodeRecord::new(key_pair, sequence, vec![SocketAddr::v4(127, 0, 0, 1, 3485)], network_id, vec![])
+    }
+
+    #[test]
+    fn the_added_record_becomes_a_candidate() {
+        let routing_table = RoutingTable::new();
+        let key_pair = Random.generate().unwrap();
+        assert!(routing_table.add_record(record(&key_pair, 0, 0x11)));
+        assert_eq!(Some(record(&key_pair, 0, 0x11)), routing_table.record(key_pair.public()));
+        assert!(routing_table.contains(&SocketAddr::v4(127, 0, 0, 1, 3485)));
+    }
+
+    #[test]
+    fn only_the_newer_record_replaces_the_known_one() {
+        let routing_table = RoutingTable::new();
+        let key_pair = Random.generate().unwrap();
+        assert!(routing_table.add_record(record(&key_pair, 1, 0x11)));
+        assert!(!routing_table.add_record(record(&key_pair, 1, 0x11)));
+        assert!(!routing_table.add_record(record(&key_pair, 0, 0x11)));
+        assert!(routing_table.add_record(record(&key_pair, 2, 0x11)));
+        assert_eq!(1, routing_table.records().len());
+    }
+
+    #[test]
+    fn the_session_which_is_not_established_in_time_is_expired() {
+        let routing_table = RoutingTable::new();
+        let address = SocketAddr::v4(127, 0, 0, 1, 3485);
+        routing_table.add_node(&address, SocketAddr::v4(127, 0, 0, 1, 3486).into());
+        assert_eq!(1, routing_table.pending_sessions());
+
+        let ttl = Duration::from_secs(60);
+        assert_eq!(0, routing_table.expire_sessions(ttl, Instant::now()));
+        assert_eq!(1, routing_table.expire_sessions(ttl, Instant::now() + ttl));
+        assert_eq!(0, routing_table.pending_sessions());
+        assert!(!routing_table.contains(&address));
+    }
+
+    #[test]
+    fn the_session_is_established_only_once() {
+        let a = RoutingTable::new();
+        let b = RoutingTable::new();
+        let address_a = SocketAddr::v4(127, 0, 0, 1, 3485);
+        let address_b = SocketAddr::v4(127, 0, 0, 1, 3486);
+        assert!(a.add_node(&address_b, address_a.into()));
+        assert!(b.add_node(&address_a, address_b.into()));
+        let public_a = a.register_key_pair_for_secret(&address_b).unwrap();
+        let public_b = b.register_key_pair_for_secret(&address_a).unwrap();
+        assert!(a.share_secret(&address_b, &public_b).is_some());
+        assert!(b.share_secret(&address_a, &public_a).is_some());
+
+        let temporary_nonce = a.request_session(&address_b).unwrap();
+        let nonce = b.create_requested_session(&address_a, &temporary_nonce).unwrap();
+        assert!(a.create_allowed_session(&address_b, &nonce));
+
+        let session = a.unestablished_session(&address_b).unwrap();
+        assert_eq!(Some(session), a.establish(&address_b));
+        // The inbound and the outbound connections cannot share the session
+        assert_eq!(None, a.establish(&address_b));
+        assert_eq!(None, a.unestablished_session(&address_b));
+        assert!(b.establish(&address_a).is_some());
+    }
+
+    #[test]
+    fn sizes_follow_the_steps_of_the_session() {
+        let routing_table = RoutingTable::new();
+        let candidate = SocketAddr::v4(127, 0, 0, 1, 3485);
+        let node = SocketAddr::v4(127, 0, 0, 1, 3487);
+        assert!(routing_table.add_candidate(candidate));
+        assert!(routing_table.add_node(&node, SocketAddr::v4(127, 0, 0, 1, 3486).into()));
+        let sizes = routing_table.sizes();
+        assert_eq!(1, sizes.candidates);
+        assert_eq!(1, sizes.uninitialized);
+        assert_eq!(1, sizes.pending);
+        assert_eq!(0, sizes.established);
+    }
+
+    #[test]
+    fn the_records_of_other_networks_are_rejected() {
+        let routing_table = RoutingTable::new();
+        routing_table.set_local_record(record(&Random.generate().unwrap(), 0, 0x11));
+        assert!(!routing_table.add_record(record(&Random.generate().unwrap(), 0, 0x12)));
+        assert!(routing_table.records().is_empty());
+    }
+
+    #[test]
+    fn the_oldest_record_is_forgotten() {
+        let routing_table = RoutingTable::new();
+        let first = Random.generate().unwrap();
+        assert!(routing_table.add_record(record(&first, 0, 0x11)));
+        for _ in 0..MAX_RECORDS {
+            assert!(routing_table.add_record(record(&Random.generate().unwrap(), 0, 0x11)));
+        }
+        assert_eq!(MAX_RECORDS, routing_table.records().len());
+        assert_eq!(None, routing_table.record(first.public()));
+    }
+}
@@ -15,54 +15,90 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use cio::{IoError, IoService};
 
-use super::client::Client;
+use super::client::{Client, PeerNotification};
+use super::dns_seed;
+use super::mdns;
 use super::p2p;
 use super::routing_table::RoutingTable;
 use super::session_initiator;
 use super::timer;
 use super::DiscoveryApi;
-use super::{NetworkExtension, SocketAddr};
+use super::{
+    ConnectionInfo, DnsSeed, DropReport, HandlerDump, NetworkExtension, NodeId, NodeStatus, PeerInfo, SocketAddr,
+};
+
+const REPORT_TIMEOUT_SECS: u64 = 5;
+// The p2p handler publishes the status every second
+const MAX_NODE_STATUS_AGE_SECS: u64 = 5;
 
 pub struct Service {
     session_initiator: IoService<session_initiator::Message>,
-    _p2p: IoService<p2p::Message>,
+    p2p: IoService<p2p::Message>,
     timer: IoService<timer::Message>,
+    // Advertises this node on the local network. It's only kept to run.
+    #[allow(dead_code)]
+    mdns: Option<IoService<mdns::Message>>,
     client: Arc<Client>,
     routing_table: Arc<RoutingTable>,
 }
 
 impl Service {
-    pub fn start(address: SocketAddr, min_peers: usize, max_peers: usize) -> Result<Self, Error> {
+    pub fn start(
+        p2p_config: p2p::Config,
+        dns_seeds: Vec<DnsSeed>,
+        extension_workers: usize,
+        mdns: bool,
+    ) -> Result<Self, Error> {
+        let address = p2p_config.address.clone();
+        let network_id = p2p_config.network_id;
+
         let p2p = IoService::start()?;
         let timer = IoService::start()?;
         let session_initiator = IoService::start()?;
 
         let routing_table = RoutingTable::new();
 
-        let client = Client::new(p2p.channel(), timer.channel());
+        let client = Client::new(p2p.channel(), timer.channel(), extension_workers);
 
         let p2p_handler = Arc::new(p2p::Handler::try_new(
-            address.clone(),
+            p2p_config,
             Arc::clone(&client),
             Arc::clone(&routing_table),
-            min_peers,
-            max_peers,
+            session_initiator.channel(),
         )?);
         p2p.register_handler(p2p_handler)?;
 
         timer.register_handler(Arc::new(timer::Handler::new(Arc::clone(&client))))?;
 
+        let port = address.port();
         let session_initiator_handler = Arc::new(session_initiator::Handler::new(address, Arc::clone(&routing_table)));
         session_initiator.register_handler(session_initiator_handler)?;
 
+        let mdns = if mdns {
+            let mdns = IoService::start()?;
+            let mdns_handler = mdns::Handler::bind(port, network_id, p2p.channel())
+                .map_err(|err| format!("Cannot start mDNS: {}", err))?;
+            mdns.register_handler(Arc::new(mdns_handler))?;
+            Some(mdns)
+        } else {
+            None
+        };
+
+        if !dns_seeds.is_empty() {
+            dns_seed::start(dns_seeds, p2p.channel())?;
+        }
+
         Ok(Self {
             session_initiator,
-            _p2p: p2p,
+            p2p,
             timer,
+            mdns,
             client,
             routing_table,
         })
@@ -71,6 +107,7 @@ impl Service {
     pub fn register_extension(&self, extension: Arc<NetworkExtension>) -> Result<(), String> {
         let extension_name = extension.name();
         self.client.register_extension(extension);
+        self.p2p.send_message(p2p::Message::UpdateLocalRecord).map_err(|err| format!("{:?}", err))?;
         if let Err(err) = self.timer.send_message(timer::Message::InitializeExtension {
             extension_name,
         }) {
@@ -80,6 +117,19 @@ impl Service {
         }
     }
 
+    /// Removes the extension from the running node and clears its timers.
+    pub fn deregister_extension(&self, extension_name: &String) -> Result<(), String> {
+        if self.client.deregister_extension(extension_name).is_none() {
+            return Err(format!("{} is not registered", extension_name))
+        }
+        self.p2p.send_message(p2p::Message::UpdateLocalRecord).map_err(|err| format!("{:?}", err))?;
+        self.timer
+            .send_message(timer::Message::DeinitializeExtension {
+                extension_name: extension_name.clone(),
+            })
+            .map_err(|err| format!("{:?}", err))
+    }
+
     pub fn connect_to(&self, address: SocketAddr) -> Result<(), String> {
         if let Err(err) = self.session_initiator.send_message(session_initiator::Message::ConnectTo(address)) {
             return Err(format!("{:?}", err))
@@ -88,6 +138,87 @@ impl Service {
         }
     }
 
+    /// Disconnects the peer and returns whether it was connected.
+    /// A static peer is not dialed again until the node restarts.
+    pub fn remove_peer(&self, node_id: NodeId) -> Result<bool, String> {
+        self.request_removal(p2p::RemovedPeer::NodeId(node_id))
+    }
+
+    /// Disconnects the peer which listens on the address, and returns whether it was connected.
+    pub fn remove_peer_at(&self, address: SocketAddr) -> Result<bool, String> {
+        self.request_removal(p2p::RemovedPeer::Address(address))
+    }
+
+    fn request_removal(&self, peer: p2p::RemovedPeer) -> Result<bool, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.p2p
+            .send_message(p2p::Message::RemovePeer(peer, p2p::Reply::new(sender)))
+            .map_err(|err| format!("{:?}", err))?;
+        receiver.recv_timeout(Duration::from_secs(REPORT_TIMEOUT_SECS)).map_err(|err| format!("{:?}", err))
+    }
+
+    /// Asks the relay to let this node and the target behind NATs connect to each other.
+    pub fn request_introduction(&self, relay: NodeId, target: SocketAddr) -> Result<(), String> {
+        self.p2p
+            .send_message(p2p::Message::RequestIntroduction {
+                relay,
+                target,
+            })
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    /// The messages which are dropped or rejected, by the connected peers and by the extensions.
+    pub fn drops(&self) -> Result<DropReport, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.p2p.send_message(p2p::Message::ReportDrops(p2p::Reply::new(sender))).map_err(|err| format!("{:?}", err))?;
+        receiver.recv_timeout(Duration::from_secs(REPORT_TIMEOUT_SECS)).map_err(|err| format!("{:?}", err))
+    }
+
+    /// The established peers with the software which they run.
+    pub fn peers(&self) -> Result<Vec<PeerInfo>, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.p2p.send_message(p2p::Message::ReportPeers(p2p::Reply::new(sender))).map_err(|err| format!("{:?}", err))?;
+        receiver.recv_timeout(Duration::from_secs(REPORT_TIMEOUT_SECS)).map_err(|err| format!("{:?}", err))
+    }
+
+    /// Every connection in the table of the p2p handler, including the ones in the handshake.
+    pub fn connections(&self) -> Result<Vec<ConnectionInfo>, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.p2p
+            .send_message(p2p::Message::ReportConnections(p2p::Reply::new(sender)))
+            .map_err(|err| format!("{:?}", err))?;
+        receiver.recv_timeout(Duration::from_secs(REPORT_TIMEOUT_SECS)).map_err(|err| format!("{:?}", err))
+    }
+
+    /// The receiver gets the peers which are connected or disconnected from now on.
+    pub fn subscribe_peers(&self) -> mpsc::Receiver<PeerNotification> {
+        self.client.subscribe_peers()
+    }
+
+    /// The internal state of the p2p handler, which is meant to diagnose a stuck network.
+    pub fn dump(&self) -> Result<HandlerDump, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.p2p
+            .send_message(p2p::Message::ReportDump(p2p::Reply::new(sender)))
+            .map_err(|err| format!("{:?}", err))?;
+        receiver.recv_timeout(Duration::from_secs(REPORT_TIMEOUT_SECS)).map_err(|err| format!("{:?}", err))
+    }
+
+    /// The listening address, the software and the number of the peers of this node.
+    ///
+    /// It's the status which the network published within a few seconds, so it doesn't wait for the busy io threads.
+    /// It fails if the network stopped publishing it.
+    pub fn node_status(&self) -> Result<NodeStatus, String> {
+        self.client
+            .node_status(Duration::from_secs(MAX_NODE_STATUS_AGE_SECS))
+            .ok_or_else(|| "The network doesn't report its status".to_string())
+    }
+
+    /// The public address of this node which the connected peers observed.
+    pub fn external_address(&self) -> Option<SocketAddr> {
+        self.routing_table.external_address()
+    }
+
     pub fn set_routing_table(&self, disc: &DiscoveryApi) {
         disc.set_routing_table(Arc::clone(&self.routing_table));
     }
@@ -0,0 +1,140 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccrypto::aes::SymmetricCipherError;
+use rlp::{DecoderError, RlpStream, UntrustedRlp};
+
+/// The cipher which encrypts the extension messages of a session
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CipherSuite {
+    /// AES-256/CBC with the session id as the IV, which the older nodes use.
+    /// It's not authenticated, so it's allowed only for the compatibility.
+    LegacyAesCbc,
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+// Both sides pick the first common suite in this order, so they agree without another round trip.
+const PREFERENCE: [CipherSuite; 3] = [CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305, CipherSuite::LegacyAesCbc];
+
+impl CipherSuite {
+    /// The suites which this node advertises in the handshake, in the order of preference
+    pub fn supported(allow_legacy: bool) -> Vec<CipherSuite> {
+        PREFERENCE.iter().cloned().filter(|suite| allow_legacy || *suite != CipherSuite::LegacyAesCbc).collect()
+    }
+
+    pub fn is_aead(self) -> bool {
+        self != CipherSuite::LegacyAesCbc
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            CipherSuite::LegacyAesCbc => 0,
+            CipherSuite::Aes256Gcm => 1,
+            CipherSuite::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(CipherSuite::LegacyAesCbc),
+            1 => Some(CipherSuite::Aes256Gcm),
+            2 => Some(CipherSuite::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the suite of the session.
+///
+/// The peers older than the cipher suites advertise nothing and use only the legacy cipher.
+pub fn negotiate(local: &[CipherSuite], remote: &[CipherSuite]) -> Option<CipherSuite> {
+    if remote.is_empty() {
+        return local.iter().find(|suite| !suite.is_aead()).cloned()
+    }
+    PREFERENCE.iter().find(|suite| local.contains(suite) && remote.contains(suite)).cloned()
+}
+
+/// Encodes the suites as a list of their codes
+pub fn append_suites(s: &mut RlpStream, suites: &[CipherSuite]) {
+    let codes: Vec<u8> = suites.iter().map(|suite| suite.code()).collect();
+    s.append_list::<u8, _>(&codes);
+}
+
+/// Decodes the list of the suites. The unknown suites, which the newer nodes may advertise, are ignored.
+pub fn decode_suites(rlp: &UntrustedRlp) -> Result<Vec<CipherSuite>, DecoderError> {
+    let codes: Vec<u8> = rlp.as_list()?;
+    Ok(codes.into_iter().filter_map(CipherSuite::from_code).collect())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Symmetric(SymmetricCipherError),
+    /// The data or the associated data doesn't match the authentication tag
+    Forged,
+    /// The message is encrypted with a cipher other than the one negotiated for the session
+    UnexpectedCipher,
+    /// The counter of the message is not greater than the one of the last opened message
+    Replayed,
+}
+
+impl From<SymmetricCipherError> for Error {
+    fn from(err: SymmetricCipherError) -> Self {
+        Error::Symmetric(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::RlpStream;
+
+    use super::*;
+
+    #[test]
+    fn legacy_is_supported_only_when_allowed() {
+        assert_eq!(vec![CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305], CipherSuite::supported(false));
+        assert!(CipherSuite::supported(true).contains(&CipherSuite::LegacyAesCbc));
+    }
+
+    #[test]
+    fn both_sides_negotiate_the_same_suite() {
+        let a = [CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm];
+        let b = [CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305, CipherSuite::LegacyAesCbc];
+        assert_eq!(Some(CipherSuite::Aes256Gcm), negotiate(&a, &b));
+        assert_eq!(Some(CipherSuite::Aes256Gcm), negotiate(&b, &a));
+        assert_eq!(Some(CipherSuite::ChaCha20Poly1305), negotiate(&[CipherSuite::ChaCha20Poly1305], &b));
+    }
+
+    #[test]
+    fn older_peer_negotiates_legacy_only_when_allowed() {
+        assert_eq!(Some(CipherSuite::LegacyAesCbc), negotiate(&CipherSuite::supported(true), &[]));
+        assert_eq!(None, negotiate(&CipherSuite::supported(false), &[]));
+    }
+
+    #[test]
+    fn no_common_suite() {
+        assert_eq!(None, negotiate(&[CipherSuite::Aes256Gcm], &[CipherSuite::ChaCha20Poly1305]));
+        assert_eq!(None, negotiate(&CipherSuite::supported(false), &[CipherSuite::LegacyAesCbc]));
+    }
+
+    #[test]
+    fn unknown_suites_are_ignored() {
+        let mut s = RlpStream::new();
+        s.append_list::<u8, _>(&[2, 200, 1]);
+        let suites = decode_suites(&UntrustedRlp::new(&s.out())).unwrap();
+        assert_eq!(vec![CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm], suites);
+    }
+}
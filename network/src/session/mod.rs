@@ -14,8 +14,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cipher;
 mod nonce;
 mod session;
 
+pub use self::cipher::{CipherSuite, Error as CipherError};
 pub use self::nonce::Nonce;
-pub use self::session::Session;
+pub use self::session::{Role, Session};
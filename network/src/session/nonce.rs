@@ -16,6 +16,7 @@
 
 use std::hash::{Hash, Hasher};
 
+use ckeys::zeroize;
 use ctypes::H128;
 use rand::{Rand, Rng};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
@@ -23,6 +24,12 @@ use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Nonce(H128);
 
+impl Drop for Nonce {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
 impl Nonce {
     pub fn zero() -> Self {
         From::from(H128::zero())
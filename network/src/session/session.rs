@@ -14,30 +14,92 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::sync::Arc;
+
+use ccrypto::aead::{self, AES_256_GCM_NONCE_LENGTH, CHACHA20_POLY1305_NONCE_LENGTH};
 use ccrypto::aes::{self, SymmetricCipherError};
-use ccrypto::Blake;
-use ctypes::Secret;
-use ctypes::{H128, H256};
+use ccrypto::{blake256_with_key, Blake};
+use ckeys::{zeroize, SharedSecret};
+use ctypes::{H128, H256, Secret};
+use parking_lot::Mutex;
+
+use super::{CipherError, CipherSuite, Nonce};
+
+/// The side of a connection. Each side seals the messages with its own key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    /// The node which dialed the connection
+    Initiator,
+    Responder,
+}
+
+impl Role {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Role::Initiator => b"initiator",
+            Role::Responder => b"responder",
+        }
+    }
 
-use super::Nonce;
+    fn remote(self) -> Self {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+}
 
-#[derive(Clone, Debug, Hash, Eq, PartialOrd, PartialEq)]
+// The AEAD nonces are the counters of the messages, which precede the sealed data.
+// ChaCha20-Poly1305 uses the counter itself, and AES-256-GCM pads it with zeros.
+const COUNTER_LENGTH: usize = CHACHA20_POLY1305_NONCE_LENGTH;
+
+#[derive(Debug, Default)]
+struct Counters {
+    next_sealed: u64,
+    last_opened: Option<u64>,
+}
+
+/// The clones of a session share the memory of the secret, which is zeroed when the last of them is dropped.
+/// They share the counters of the AEAD nonces too.
+#[derive(Clone, Debug)]
 pub struct Session {
-    secret: Secret,
+    secret: SharedSecret,
     id: Nonce,
+    role: Role,
+    counters: Arc<Mutex<Counters>>,
 }
 
+impl PartialEq for Session {
+    fn eq(&self, other: &Self) -> bool {
+        self.secret == other.secret && self.id == other.id && self.role == other.role
+    }
+}
+
+impl Eq for Session {}
+
 type Error = SymmetricCipherError;
 
 impl Session {
-    pub fn new_with_zero_nonce(secret: Secret) -> Self {
+    pub fn new_with_zero_nonce<S: Into<SharedSecret>>(secret: S) -> Self {
         Self::new(secret, Nonce::zero())
     }
 
-    pub fn new(secret: Secret, nonce: Nonce) -> Self {
+    pub fn new<S: Into<SharedSecret>>(secret: S, nonce: Nonce) -> Self {
         Session {
-            secret,
+            secret: secret.into(),
             id: nonce,
+            role: Role::Initiator,
+            counters: Default::default(),
+        }
+    }
+
+    /// The session of a side of the connection, whose counters start from zero.
+    pub fn with_role(&self, role: Role) -> Self {
+        Session {
+            secret: self.secret.clone(),
+            id: self.id.clone(),
+            role,
+            counters: Default::default(),
         }
     }
 
@@ -63,12 +125,100 @@ impl Session {
         Ok(aes::decrypt(&data, &self.secret, &iv)?)
     }
 
+    /// Encrypts the data with the cipher of the session.
+    ///
+    /// The AEAD ciphers seal with the key of this side and prepend the counter of the message as the nonce.
+    /// They authenticate `aad` along with the data.
+    pub fn seal(&self, cipher: CipherSuite, aad: &[u8], data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if !cipher.is_aead() {
+            return Ok(self.encrypt(data)?)
+        }
+        let counter = {
+            let mut counters = self.counters.lock();
+            let counter = counters.next_sealed;
+            counters.next_sealed += 1;
+            encode_counter(counter)
+        };
+        let mut key = self.key(self.role);
+        let mut sealed = counter.to_vec();
+        match cipher {
+            CipherSuite::Aes256Gcm => sealed.extend(aead::aes_256_gcm_seal(&key, &gcm_nonce(&counter), aad, data)),
+            CipherSuite::ChaCha20Poly1305 => sealed.extend(aead::chacha20_poly1305_seal(&key, &counter, aad, data)),
+            CipherSuite::LegacyAesCbc => unreachable!(),
+        }
+        zeroize(&mut key);
+        Ok(sealed)
+    }
+
+    /// Decrypts the data which the other side sealed.
+    ///
+    /// The AEAD ciphers reject the data whose counter is not greater than the one of the last opened data,
+    /// so the messages cannot be replayed or reordered.
+    pub fn open(&self, cipher: CipherSuite, aad: &[u8], data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if !cipher.is_aead() {
+            return Ok(self.decrypt(data)?)
+        }
+        if data.len() < COUNTER_LENGTH {
+            return Err(CipherError::Forged)
+        }
+        let (encoded_counter, sealed) = data.split_at(COUNTER_LENGTH);
+        let mut counter = [0u8; COUNTER_LENGTH];
+        counter.copy_from_slice(encoded_counter);
+
+        let mut counters = self.counters.lock();
+        let received = decode_counter(&counter);
+        if counters.last_opened.map_or(false, |last_opened| received <= last_opened) {
+            return Err(CipherError::Replayed)
+        }
+        let mut key = self.key(self.role.remote());
+        let opened = match cipher {
+            CipherSuite::Aes256Gcm => aead::aes_256_gcm_open(&key, &gcm_nonce(&counter), aad, sealed),
+            CipherSuite::ChaCha20Poly1305 => aead::chacha20_poly1305_open(&key, &counter, aad, sealed),
+            CipherSuite::LegacyAesCbc => unreachable!(),
+        };
+        zeroize(&mut key);
+        let opened = opened.ok_or(CipherError::Forged)?;
+        counters.last_opened = Some(received);
+        Ok(opened)
+    }
+
+    // The key which the side seals with
+    fn key(&self, sender: Role) -> H256 {
+        blake256_with_key(sender.label(), &self.secret)
+    }
+
+    /// The MAC of the data which is sent without the encryption.
+    ///
+    /// The key is derived from the secret for each `aad`, so a MAC of one extension is not valid for another.
+    pub fn mac(&self, aad: &[u8], data: &[u8]) -> H256 {
+        let key = blake256_with_key(aad, &self.secret);
+        blake256_with_key(data, &key)
+    }
+
     pub fn sign(&self, data: &[u8]) -> H256 {
         let iv: &H128 = self.id().into();
         Blake::blake_with_key(data, iv)
     }
 }
 
+fn encode_counter(counter: u64) -> [u8; COUNTER_LENGTH] {
+    let mut encoded = [0u8; COUNTER_LENGTH];
+    for (i, byte) in encoded.iter_mut().enumerate() {
+        *byte = (counter >> (8 * (COUNTER_LENGTH - 1 - i))) as u8;
+    }
+    encoded
+}
+
+fn decode_counter(encoded: &[u8; COUNTER_LENGTH]) -> u64 {
+    encoded.iter().fold(0, |counter, byte| (counter << 8) | u64::from(*byte))
+}
+
+fn gcm_nonce(counter: &[u8; COUNTER_LENGTH]) -> [u8; AES_256_GCM_NONCE_LENGTH] {
+    let mut nonce = [0u8; AES_256_GCM_NONCE_LENGTH];
+    nonce[AES_256_GCM_NONCE_LENGTH - COUNTER_LENGTH..].copy_from_slice(counter);
+    nonce
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -138,4 +288,101 @@ mod tests {
 
         assert_ne!(encrypted1, encrypted2);
     }
+
+    #[test]
+    fn mac_depends_on_the_secret_and_the_aad() {
+        let id = Nonce::from(1000);
+        let session = Session::new(Secret::random(), id.clone());
+        let data = "some short data".as_bytes();
+        assert_eq!(session.mac(b"name", data), Session::new(*session.secret(), id.clone()).mac(b"name", data));
+        assert_ne!(session.mac(b"name", data), session.mac(b"another name", data));
+        assert_ne!(session.mac(b"name", data), Session::new(Secret::random(), id).mac(b"name", data));
+    }
+
+    fn sides() -> (Session, Session) {
+        let session = Session::new(Secret::random(), Nonce::from(1000));
+        (session.with_role(Role::Initiator), session.with_role(Role::Responder))
+    }
+
+    #[test]
+    fn seal_and_open_with_every_cipher() {
+        let (initiator, responder) = sides();
+        let data = "some short data".as_bytes();
+        for cipher in CipherSuite::supported(true) {
+            let sealed = initiator.seal(cipher, b"name", data).unwrap();
+            assert_ne!(data, &sealed[..]);
+            assert_eq!(data, &responder.open(cipher, b"name", &sealed).unwrap()[..]);
+
+            let sealed = responder.seal(cipher, b"name", data).unwrap();
+            assert_eq!(data, &initiator.open(cipher, b"name", &sealed).unwrap()[..]);
+        }
+    }
+
+    #[test]
+    fn aead_cipher_seals_with_the_key_of_each_side() {
+        let (initiator, _responder) = sides();
+        let data = "some short data".as_bytes();
+        for cipher in CipherSuite::supported(false) {
+            let sealed = initiator.seal(cipher, b"name", data).unwrap();
+            assert_eq!(Err(CipherError::Forged), initiator.open(cipher, b"name", &sealed));
+        }
+    }
+
+    #[test]
+    fn aead_cipher_rejects_the_replayed_and_the_reordered_data() {
+        let data = "some short data".as_bytes();
+        for cipher in CipherSuite::supported(false) {
+            let (initiator, responder) = sides();
+            let first = initiator.seal(cipher, b"name", data).unwrap();
+            let second = initiator.seal(cipher, b"name", data).unwrap();
+            let third = initiator.seal(cipher, b"name", data).unwrap();
+
+            assert!(responder.open(cipher, b"name", &second).is_ok());
+            assert_eq!(Err(CipherError::Replayed), responder.open(cipher, b"name", &second));
+            assert_eq!(Err(CipherError::Replayed), responder.open(cipher, b"name", &first));
+            assert!(responder.open(cipher, b"name", &third).is_ok());
+        }
+    }
+
+    #[test]
+    fn forged_counter_does_not_advance_the_counter() {
+        let (initiator, responder) = sides();
+        let data = "some short data".as_bytes();
+        let sealed = initiator.seal(CipherSuite::Aes256Gcm, b"name", data).unwrap();
+        let mut forged = sealed.clone();
+        forged[0] = 0xff;
+
+        assert_eq!(Err(CipherError::Forged), responder.open(CipherSuite::Aes256Gcm, b"name", &forged));
+        assert!(responder.open(CipherSuite::Aes256Gcm, b"name", &sealed).is_ok());
+    }
+
+    #[test]
+    fn aead_cipher_doesnt_reuse_the_nonce() {
+        let session = Session::new(Secret::random(), Nonce::from(1000));
+        let data = "some short data".as_bytes();
+        let sealed1 = session.seal(CipherSuite::Aes256Gcm, b"name", data).unwrap();
+        let sealed2 = session.seal(CipherSuite::Aes256Gcm, b"name", data).unwrap();
+        assert_ne!(sealed1, sealed2);
+    }
+
+    #[test]
+    fn aead_cipher_rejects_the_forged_data() {
+        let (initiator, responder) = sides();
+        let data = "some short data".as_bytes();
+        for cipher in CipherSuite::supported(false) {
+            let mut sealed = initiator.seal(cipher, b"name", data).unwrap();
+            assert_eq!(Err(CipherError::Forged), responder.open(cipher, b"another name", &sealed));
+            let last = sealed.len() - 1;
+            sealed[last] ^= 1;
+            assert_eq!(Err(CipherError::Forged), responder.open(cipher, b"name", &sealed));
+            assert_eq!(Err(CipherError::Forged), responder.open(cipher, b"name", &[0u8; 4]));
+        }
+    }
+
+    #[test]
+    fn cloned_session_shares_the_secret() {
+        let session = Session::new(Secret::random(), Nonce::from(1000));
+        let cloned = session.clone();
+        assert_eq!(session.secret() as *const Secret, cloned.secret() as *const Secret);
+    }
 }
@@ -0,0 +1,112 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The stateless cookies, which make a remote prove that it receives the packets sent to its address before the node
+//! allocates anything for it.
+//!
+//! A cookie is the keyed hash of the address, the node id and the epoch, so the node doesn't remember the cookies it
+//! issued.
+
+use ccrypto::blake256_with_key;
+use ctypes::H256;
+use rlp::RlpStream;
+
+use super::super::{NodeId, SocketAddr};
+
+pub type Cookie = H256;
+
+const EPOCH_SECS: u64 = 60;
+
+pub struct Cookies {
+    secret: H256,
+}
+
+impl Cookies {
+    pub fn new() -> Self {
+        Self {
+            secret: H256::random(),
+        }
+    }
+
+    pub fn issue(&self, address: &SocketAddr, node_id: &NodeId, now: u64) -> Cookie {
+        self.compute(address, node_id, now / EPOCH_SECS)
+    }
+
+    /// The cookies issued in the current and the previous epochs are valid.
+    pub fn verify(&self, cookie: &Cookie, address: &SocketAddr, node_id: &NodeId, now: u64) -> bool {
+        let epoch = now / EPOCH_SECS;
+        if constant_time_eq(cookie, &self.compute(address, node_id, epoch)) {
+            return true
+        }
+        epoch > 0 && constant_time_eq(cookie, &self.compute(address, node_id, epoch - 1))
+    }
+
+    fn compute(&self, address: &SocketAddr, node_id: &NodeId, epoch: u64) -> Cookie {
+        let mut s = RlpStream::new_list(3);
+        s.append(address).append(node_id).append(&epoch);
+        blake256_with_key(s.out(), &self.secret)
+    }
+}
+
+// Doesn't leak how many leading bytes of a forged cookie are right
+fn constant_time_eq(a: &Cookie, b: &Cookie) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::v4(127, 0, 0, 1, port)
+    }
+
+    const NOW: u64 = 1_530_000_000;
+
+    #[test]
+    fn issued_cookie_is_valid() {
+        let cookies = Cookies::new();
+        let node_id = NodeId::random();
+        let cookie = cookies.issue(&address(3485), &node_id, NOW);
+        assert!(cookies.verify(&cookie, &address(3485), &node_id, NOW));
+    }
+
+    #[test]
+    fn cookie_is_bound_to_the_address_and_the_node_id() {
+        let cookies = Cookies::new();
+        let node_id = NodeId::random();
+        let cookie = cookies.issue(&address(3485), &node_id, NOW);
+        assert!(!cookies.verify(&cookie, &address(3486), &node_id, NOW));
+        assert!(!cookies.verify(&cookie, &address(3485), &NodeId::random(), NOW));
+    }
+
+    #[test]
+    fn cookie_of_another_node_is_invalid() {
+        let node_id = NodeId::random();
+        let cookie = Cookies::new().issue(&address(3485), &node_id, NOW);
+        assert!(!Cookies::new().verify(&cookie, &address(3485), &node_id, NOW));
+    }
+
+    #[test]
+    fn cookie_expires_after_the_next_epoch() {
+        let cookies = Cookies::new();
+        let node_id = NodeId::random();
+        let issued_at = NOW - NOW % EPOCH_SECS;
+        let cookie = cookies.issue(&address(3485), &node_id, issued_at);
+        assert!(cookies.verify(&cookie, &address(3485), &node_id, issued_at + EPOCH_SECS));
+        assert!(!cookies.verify(&cookie, &address(3485), &node_id, issued_at + 2 * EPOCH_SECS));
+    }
+}
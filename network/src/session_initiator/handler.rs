@@ -19,19 +19,21 @@ use std::error;
 use std::fmt;
 use std::io;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use ccrypto::aes::SymmetricCipherError;
 use cfinally::finally;
-use cio::{IoContext, IoError as CIoError, IoHandler, IoHandlerResult, IoManager, StreamToken, TimerToken};
+use cio::{IoContext, IoError as CIoError, IoHandler, IoHandlerResult, StreamToken, TimerToken};
 use ckeys::Error as KeysError;
-use mio::deprecated::EventLoop;
-use mio::Token;
+use mio::{Poll, Token};
 use parking_lot::Mutex;
 use rlp::DecoderError;
 
+use super::super::metrics;
 use super::super::token_generator::TokenGenerator;
 use super::super::RoutingTable;
 use super::super::SocketAddr;
+use super::cookie::Cookies;
 use super::message;
 use super::server::{Error as ServerError, Server};
 
@@ -39,6 +41,10 @@ const REFRESH_TIMER_TOKEN: TimerToken = 0;
 const BEGIN_OF_REQUEST_TOKEN: TimerToken = 1;
 const NUMBER_OF_REQUESTS: usize = 100;
 const END_OF_REQUEST_TOKEN: TimerToken = BEGIN_OF_REQUEST_TOKEN + NUMBER_OF_REQUESTS;
+const EXPIRE_SESSIONS_TOKEN: TimerToken = END_OF_REQUEST_TOKEN + 1;
+const EXPIRE_SESSIONS_MS: u64 = 30_000;
+// The sessions which are not established in this time are forgotten
+const SESSION_TTL_SECS: u64 = 5 * 60;
 
 struct Requests {
     request_tokens: TokenGenerator,
@@ -87,6 +93,8 @@ struct SessionInitiator {
 
     routing_table: Arc<RoutingTable>,
     requests: Requests,
+    // Nothing is allocated for a remote until it echoes a cookie
+    cookies: Cookies,
 }
 
 #[derive(Debug)]
@@ -185,6 +193,10 @@ pub enum Message {
 
 const MESSAGE_TIMEOUT_MS: u64 = 10_000;
 
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("The system time is after the epoch").as_secs()
+}
+
 impl SessionInitiator {
     fn bind(socket_address: &SocketAddr, routing_table: Arc<RoutingTable>) -> Result<Self> {
         let server = Server::bind(socket_address)?;
@@ -192,6 +204,7 @@ impl SessionInitiator {
             server,
             routing_table,
             requests: Requests::new(),
+            cookies: Cookies::new(),
         })
     }
 
@@ -227,16 +240,14 @@ impl SessionInitiator {
     fn on_packet(&mut self, message: &message::Message, from: &SocketAddr, io: &IoContext<Message>) -> Result<()> {
         match message.body() {
             message::Body::NodeIdRequest(responder_node_id) => {
-                if !self.routing_table.add_node(from, *responder_node_id) {
-                    ctrace!(NET, "{:?} is not a new candidate", from);
-                }
-
+                // The source address may be spoofed, so the requester is added after it echoes the cookie
                 let requester_node_id = from.into();
-                let message = message::Message::node_id_response(message.seq(), requester_node_id);
+                let cookie = self.cookies.issue(from, responder_node_id, now());
+                let message = message::Message::node_id_response(message.seq(), requester_node_id, cookie);
                 self.server.enqueue(message, from.clone())?;
                 Ok(())
             }
-            message::Body::NodeIdResponse(requester_node_id) => {
+            message::Body::NodeIdResponse(requester_node_id, cookie) => {
                 if self.requests.restore(message.seq() as usize, Some(from.clone())).is_err() {
                     ctrace!(NET, "Invalid message({:?}) from {:?}", message, from);
                     return Ok(())
@@ -253,12 +264,22 @@ impl SessionInitiator {
                 let seq = self.requests.gen(from.clone())?;
                 io.register_timer_once(seq, MESSAGE_TIMEOUT_MS)?;
 
-                let message = message::Message::secret_request(seq as u64, requester_pub_key);
+                let responder_node_id = from.into();
+                let message =
+                    message::Message::secret_request(seq as u64, requester_pub_key, responder_node_id, *cookie);
                 self.server.enqueue(message, from.clone())?;
 
                 Ok(())
             }
-            message::Body::SecretRequest(requester_pub_key) => {
+            message::Body::SecretRequest(requester_pub_key, responder_node_id, cookie) => {
+                if !self.cookies.verify(cookie, from, responder_node_id, now()) {
+                    ctrace!(NET, "Invalid cookie from {:?}", from);
+                    return Ok(())
+                }
+                if !self.routing_table.add_node(from, *responder_node_id) {
+                    ctrace!(NET, "{:?} is not a new candidate", from);
+                }
+
                 if let Some(responder_pub_key) = self.routing_table.register_key_pair_for_secret(from) {
                     if let Some(_secret) = self.routing_table.share_secret(from, requester_pub_key) {
                         let message = message::Message::secret_allowed(message.seq(), responder_pub_key.clone());
@@ -344,12 +365,12 @@ impl SessionInitiator {
         }
     }
 
-    fn register(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()> {
-        Ok(self.server.register(reg, event_loop)?)
+    fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        Ok(self.server.register(reg, poll)?)
     }
 
-    fn reregister(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()> {
-        Ok(self.server.reregister(reg, event_loop)?)
+    fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        Ok(self.server.reregister(reg, poll)?)
     }
 }
 
@@ -373,6 +394,7 @@ impl IoHandler<Message> for Handler {
     fn initialize(&self, io: &IoContext<Message>) -> IoHandlerResult<()> {
         io.register_stream(RECEIVE_TOKEN)?;
         io.register_timer(REFRESH_TIMER_TOKEN, 10_000)?;
+        io.register_timer(EXPIRE_SESSIONS_TOKEN, EXPIRE_SESSIONS_MS)?;
         Ok(())
     }
 
@@ -396,6 +418,14 @@ impl IoHandler<Message> for Handler {
                 }
                 Ok(())
             }
+            EXPIRE_SESSIONS_TOKEN => {
+                let session_initiator = self.session_initiator.lock();
+                let routing_table = &session_initiator.routing_table;
+                let expired = routing_table.expire_sessions(Duration::from_secs(SESSION_TTL_SECS), Instant::now());
+                metrics::count_expired_sessions(expired);
+                metrics::set_live_sessions(routing_table.pending_sessions());
+                Ok(())
+            }
             _ => unreachable!(),
         }
     }
@@ -467,37 +497,23 @@ impl IoHandler<Message> for Handler {
         Ok(())
     }
 
-    fn register_stream(
-        &self,
-        stream: StreamToken,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn register_stream(&self, stream: StreamToken, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
         if stream != RECEIVE_TOKEN {
             unreachable!()
         }
         let session_initiator = self.session_initiator.lock();
-        Ok(session_initiator.register(reg, event_loop)?)
+        Ok(session_initiator.register(reg, poll)?)
     }
 
-    fn update_stream(
-        &self,
-        stream: usize,
-        reg: Token,
-        event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn update_stream(&self, stream: usize, reg: Token, poll: &Poll) -> IoHandlerResult<()> {
         if stream != RECEIVE_TOKEN {
             unreachable!()
         }
         let session_initiator = self.session_initiator.lock();
-        Ok(session_initiator.reregister(reg, event_loop)?)
+        Ok(session_initiator.reregister(reg, poll)?)
     }
 
-    fn deregister_stream(
-        &self,
-        _stream: usize,
-        _event_loop: &mut EventLoop<IoManager<Message>>,
-    ) -> IoHandlerResult<()> {
+    fn deregister_stream(&self, _stream: usize, _poll: &Poll) -> IoHandlerResult<()> {
         unreachable!()
     }
 }
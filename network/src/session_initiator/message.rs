@@ -18,6 +18,7 @@ use ctypes::Public;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use super::super::NodeId;
+use super::cookie::Cookie;
 
 type Version = u32;
 type Raw = Vec<u8>;
@@ -33,8 +34,10 @@ pub struct Message {
 #[derive(Clone, Debug, PartialOrd, PartialEq)]
 pub enum Body {
     NodeIdRequest(NodeId),
-    NodeIdResponse(NodeId),
-    SecretRequest(Public),
+    /// The node id of the requester, and the cookie to be echoed in the secret request
+    NodeIdResponse(NodeId, Cookie),
+    /// The key of the requester, the node id of the responder as the requester sees, and the cookie
+    SecretRequest(Public, NodeId, Cookie),
     SecretAllowed(Public),
     SecretDenied(String),
     NonceRequest(Raw),
@@ -62,19 +65,19 @@ impl Message {
         }
     }
 
-    pub fn node_id_response(seq: Seq, id: NodeId) -> Self {
+    pub fn node_id_response(seq: Seq, id: NodeId, cookie: Cookie) -> Self {
         Self {
             version: 0,
             seq,
-            body: Body::NodeIdResponse(id),
+            body: Body::NodeIdResponse(id, cookie),
         }
     }
 
-    pub fn secret_request(seq: Seq, key: Public) -> Self {
+    pub fn secret_request(seq: Seq, key: Public, responder_id: NodeId, cookie: Cookie) -> Self {
         Self {
             version: 0,
             seq,
-            body: Body::SecretRequest(key),
+            body: Body::SecretRequest(key, responder_id, cookie),
         }
     }
 
@@ -121,8 +124,8 @@ impl Message {
     pub fn protocol_id(&self) -> u8 {
         match self.body {
             Body::NodeIdRequest(_) => NODE_ID_REQUEST,
-            Body::NodeIdResponse(_) => NODE_ID_RESPONSE,
-            Body::SecretRequest(_) => SECRET_REQUEST,
+            Body::NodeIdResponse(..) => NODE_ID_RESPONSE,
+            Body::SecretRequest(..) => SECRET_REQUEST,
             Body::SecretAllowed(_) => SECRET_ALLOWED,
             Body::SecretDenied(_) => SECRET_DENIED,
             Body::NonceRequest(_) => NONCE_REQUEST,
@@ -145,7 +148,11 @@ impl Message {
     }
 
     fn item_count(&self) -> usize {
-        4
+        match self.body {
+            Body::NodeIdResponse(..) => 5,
+            Body::SecretRequest(..) => 6,
+            _ => 4,
+        }
     }
 }
 
@@ -158,11 +165,11 @@ impl Encodable for Message {
             Body::NodeIdRequest(id) => {
                 s.append(id);
             }
-            Body::NodeIdResponse(id) => {
-                s.append(id);
+            Body::NodeIdResponse(id, cookie) => {
+                s.append(id).append(cookie);
             }
-            Body::SecretRequest(key) => {
-                s.append(key);
+            Body::SecretRequest(key, responder_id, cookie) => {
+                s.append(key).append(responder_id).append(cookie);
             }
             Body::SecretAllowed(key) => {
                 s.append(key);
@@ -193,11 +200,14 @@ impl Decodable for Message {
             NODE_ID_REQUEST => Message::node_id_request(seq, rlp.val_at(3)?),
             NODE_ID_RESPONSE => {
                 let node_id = rlp.val_at(3)?;
-                Message::node_id_response(seq, node_id)
+                let cookie = rlp.val_at(4)?;
+                Message::node_id_response(seq, node_id, cookie)
             }
             SECRET_REQUEST => {
                 let key: Public = rlp.val_at(3)?;
-                Message::secret_request(seq, key)
+                let responder_id = rlp.val_at(4)?;
+                let cookie = rlp.val_at(5)?;
+                Message::secret_request(seq, key, responder_id, cookie)
             }
             SECRET_ALLOWED => {
                 let key: Public = rlp.val_at(3)?;
@@ -251,7 +261,7 @@ mod tests {
     #[test]
     fn encode_and_decode_node_id_response() {
         let id = NodeId::random();
-        let response = Message::node_id_response(0x9a, id);
+        let response = Message::node_id_response(0x9a, id, Cookie::random());
 
         let encoded = response.rlp_bytes();
         let rlp = UntrustedRlp::new(&encoded);
@@ -261,6 +271,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn encode_and_decode_secret_request() {
+        let request = Message::secret_request(0x3c, Public::random(), NodeId::random(), Cookie::random());
+
+        let encoded = request.rlp_bytes();
+        let rlp = UntrustedRlp::new(&encoded);
+        match Decodable::decode(&rlp) {
+            Ok(decoded) => assert_eq!(request, decoded),
+            Err(err) => assert!(false, "{:?}", err),
+        }
+    }
+
+    #[test]
+    fn secret_request_without_cookie_is_invalid() {
+        let mut s = RlpStream::new_list(4);
+        s.append(&(0 as Version)).append(&(0 as Seq)).append(&SECRET_REQUEST).append(&Public::random());
+        let encoded = s.out();
+        let rlp = UntrustedRlp::new(&encoded);
+        assert!(Message::decode(&rlp).is_err());
+    }
+
     #[test]
     fn encode_and_decode_nonce_request() {
         const SEQ: Seq = 0;
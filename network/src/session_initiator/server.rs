@@ -19,9 +19,7 @@ use std::error::Error as StdError;
 use std::fmt;
 use std::io;
 
-use cio::IoManager;
-use mio::deprecated::EventLoop;
-use mio::{PollOpt, Ready, Token};
+use mio::{Poll, PollOpt, Ready, Token};
 
 use super::super::SocketAddr;
 use super::message::Message;
@@ -123,15 +121,11 @@ impl Server {
         }
     }
 
-    pub fn register<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        Ok(event_loop.register(&self.socket, reg, self.interest(), PollOpt::edge())?)
+    pub fn register(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        Ok(poll.register(&self.socket, reg, self.interest(), PollOpt::edge())?)
     }
 
-    pub fn reregister<Message>(&self, reg: Token, event_loop: &mut EventLoop<IoManager<Message>>) -> io::Result<()>
-    where
-        Message: Send + Sync + Clone + 'static, {
-        Ok(event_loop.reregister(&self.socket, reg, self.interest(), PollOpt::edge())?)
+    pub fn reregister(&self, reg: Token, poll: &Poll) -> io::Result<()> {
+        Ok(poll.reregister(&self.socket, reg, self.interest(), PollOpt::edge())?)
     }
 }
@@ -18,17 +18,21 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::ops::Deref;
 use std::sync::{Arc, Weak};
 
+use ckeys::Public;
 use parking_lot::Mutex;
 use rlp::Encodable;
 use time::Duration;
 
+use super::super::capture::{Direction, Record};
 use super::super::extension::{Api, Extension, Result, TimerToken};
+use super::super::reputation::PeerBehavior;
 use super::super::NodeId;
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Call {
     Send(NodeId, Vec<u8>),
     Negotiate(NodeId),
+    Disconnect(NodeId),
     SetTimer {
         token: TimerToken,
         duration: Duration,
@@ -39,6 +43,7 @@ pub enum Call {
     },
     ClearTimer(TimerToken),
     SendLocalMessage(Vec<u8>),
+    Report(NodeId, PeerBehavior),
 }
 
 struct TestApi {
@@ -79,6 +84,10 @@ impl Api for TestApi {
         self.calls.lock().push_back(Call::Negotiate(*node));
     }
 
+    fn disconnect(&self, node: &NodeId) {
+        self.calls.lock().push_back(Call::Disconnect(*node));
+    }
+
     fn set_timer(&self, token: TimerToken, duration: Duration) -> Result<()> {
         let mut timers = self.timers.lock();
         if timers.contains_key(&token) {
@@ -118,6 +127,18 @@ impl Api for TestApi {
         let message = message.rlp_bytes().into_vec();
         self.calls.lock().push_back(Call::SendLocalMessage(message));
     }
+
+    fn peer_identity(&self, _node: &NodeId) -> Option<Public> {
+        None
+    }
+
+    fn envelope_version(&self, _node: &NodeId) -> Option<u64> {
+        None
+    }
+
+    fn report(&self, node: &NodeId, behavior: PeerBehavior) {
+        self.calls.lock().push_back(Call::Report(*node, behavior));
+    }
 }
 
 impl TestApi {
@@ -270,4 +291,22 @@ impl TestClient {
     pub fn pop_call(&self, name: &str) -> Option<Call> {
         self.get_api(name).calls.lock().pop_front()
     }
+
+    /// Feeds the captured inbound messages to the registered extensions, connecting the senders on the way.
+    /// Returns the number of the replayed messages.
+    pub fn replay(&self, records: &[Record]) -> usize {
+        let mut replayed = 0;
+        for record in records.iter().filter(|record| record.direction == Direction::Inbound) {
+            if !self.extensions.contains_key(&record.extension_name) {
+                continue
+            }
+            let api = self.get_api(&record.extension_name);
+            if !api.connections.lock().contains(&record.node_id) {
+                api.connected(record.node_id);
+            }
+            api.send_message(record.node_id, &record.data);
+            replayed += 1;
+        }
+        replayed
+    }
 }
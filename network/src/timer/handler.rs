@@ -49,6 +49,9 @@ pub enum Message {
     InitializeExtension {
         extension_name: String,
     },
+    DeinitializeExtension {
+        extension_name: String,
+    },
 }
 
 #[derive(Debug)]
@@ -146,6 +149,15 @@ impl IoHandler<Message> for Handler {
                 self.client.initialize_extension(extension_name);
                 Ok(())
             }
+            Message::DeinitializeExtension {
+                extension_name,
+            } => {
+                let mut timer = self.timer.lock();
+                for token in timer.remove_by_name(extension_name) {
+                    io.clear_timer(token)?;
+                }
+                Ok(())
+            }
         }
     }
 }
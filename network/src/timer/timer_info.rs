@@ -98,6 +98,15 @@ impl TimerInfo {
             token
         })
     }
+
+    // Removes all the timers of the extension
+    pub fn remove_by_name(&mut self, name: &String) -> Vec<TimerToken> {
+        let timer_ids: Vec<TimerId> = match self.reversed.row(name) {
+            Some(row) => row.keys().cloned().collect(),
+            None => return vec![],
+        };
+        timer_ids.into_iter().filter_map(|timer_id| self.remove_by_info(name.clone(), timer_id)).collect()
+    }
 }
 
 #[cfg(test)]
@@ -125,4 +134,21 @@ mod tests {
         assert_eq!(Ok(0), timer.insert("a".to_string(), 1, false));
         assert_eq!(Ok(1), timer.insert("b".to_string(), 1, false));
     }
+
+    #[test]
+    fn remove_all_timers_of_the_extension() {
+        let mut timer = TimerInfo::new(0, 4);
+        assert_eq!(Ok(0), timer.insert("a".to_string(), 1, false));
+        assert_eq!(Ok(1), timer.insert("b".to_string(), 1, false));
+        assert_eq!(Ok(2), timer.insert("a".to_string(), 2, true));
+
+        let mut removed = timer.remove_by_name(&"a".to_string());
+        removed.sort();
+        assert_eq!(vec![0, 2], removed);
+        assert!(timer.get_info(0).is_none());
+        assert!(timer.get_info(1).is_some());
+        assert!(timer.get_info(2).is_none());
+        assert_eq!(Vec::<usize>::new(), timer.remove_by_name(&"a".to_string()));
+        assert!(timer.insert("a".to_string(), 1, false).is_ok());
+    }
 }
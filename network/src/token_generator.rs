@@ -31,12 +31,10 @@ impl TokenGenerator {
         }
     }
 
-    #[allow(dead_code)]
     pub fn limit(&self) -> usize {
         self.limited.limit()
     }
 
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.limited.len()
     }
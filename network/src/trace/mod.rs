@@ -0,0 +1,249 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+mod otlp;
+
+use std::cell::Cell;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rand;
+
+pub use self::otlp::start as start_exporter;
+
+lazy_static! {
+    static ref EXPORTER: Mutex<Option<Exporter>> = Mutex::new(None);
+}
+
+struct Exporter {
+    sender: SyncSender<FinishedSpan>,
+    // The fraction of the traces which are exported
+    sample_rate: f64,
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<SpanContext>> = Cell::new(None);
+}
+
+/// Identifies a span and the trace it belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
+pub struct SpanContext {
+    trace_id: (u64, u64),
+    span_id: u64,
+    // The whole trace is either exported or not, so the children follow the root
+    sampled: bool,
+}
+
+impl SpanContext {
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+}
+
+/// A timed operation. The span is exported when it is dropped.
+pub struct Span {
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    name: &'static str,
+    start: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    pub fn root(name: &'static str) -> Self {
+        Self::root_since(name, SystemTime::now())
+    }
+
+    /// Starts a new trace from the moment which is already passed.
+    /// Only the sampled traces are exported, and none is sampled until the exporter starts.
+    pub fn root_since(name: &'static str, start: SystemTime) -> Self {
+        let sampled = match EXPORTER.lock().as_ref() {
+            Some(exporter) => rand::random::<f64>() < exporter.sample_rate,
+            None => false,
+        };
+        Self {
+            context: SpanContext {
+                trace_id: (rand::random(), rand::random()),
+                span_id: rand::random(),
+                sampled,
+            },
+            parent_span_id: None,
+            name,
+            start,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn child_of(parent: &SpanContext, name: &'static str) -> Self {
+        Self::child_of_since(parent, name, SystemTime::now())
+    }
+
+    pub fn child_of_since(parent: &SpanContext, name: &'static str, start: SystemTime) -> Self {
+        Self {
+            context: SpanContext {
+                trace_id: parent.trace_id,
+                span_id: rand::random(),
+                sampled: parent.sampled,
+            },
+            parent_span_id: Some(parent.span_id),
+            name,
+            start,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn child(&self, name: &'static str) -> Self {
+        Self::child_of(&self.context, name)
+    }
+
+    pub fn context(&self) -> SpanContext {
+        self.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: String) {
+        if self.context.sampled {
+            self.attributes.push((key, value));
+        }
+    }
+
+    /// Makes the span the parent of the spans created by `current` until the guard is dropped.
+    pub fn enter(&self) -> Entered {
+        let previous = CURRENT.with(|current| current.replace(Some(self.context)));
+        Entered {
+            previous,
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if !self.context.sampled {
+            return
+        }
+        let exporter = EXPORTER.lock();
+        if let Some(exporter) = exporter.as_ref() {
+            let span = FinishedSpan {
+                context: self.context,
+                parent_span_id: self.parent_span_id,
+                name: self.name,
+                start: unix_nanos(self.start),
+                end: unix_nanos(SystemTime::now()),
+                attributes: ::std::mem::replace(&mut self.attributes, Vec::new()),
+            };
+            // The spans are dropped rather than blocking the network threads when the collector is slow
+            if let Err(TrySendError::Full(_)) = exporter.sender.try_send(span) {
+                ctrace!(NET, "Dropping the span {} since the export queue is full", self.name);
+            }
+        }
+    }
+}
+
+pub struct Entered {
+    previous: Option<SpanContext>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// The context of the span which the current thread is working in.
+pub fn current() -> Option<SpanContext> {
+    CURRENT.with(|current| current.get())
+}
+
+struct FinishedSpan {
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    name: &'static str,
+    start: u64,
+    end: u64,
+    attributes: Vec<(&'static str, String)>,
+}
+
+fn set_exporter(sender: SyncSender<FinishedSpan>, sample_rate: f64) {
+    *EXPORTER.lock() = Some(Exporter {
+        sender,
+        sample_rate,
+    });
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_belongs_to_the_same_trace() {
+        let root = Span::root("root");
+        let child = root.child("child");
+        assert_eq!(root.context().trace_id, child.context().trace_id);
+        assert_ne!(root.context().span_id, child.context().span_id);
+        assert_eq!(Some(root.context().span_id), child.parent_span_id);
+        assert_eq!(None, root.parent_span_id);
+    }
+
+    #[test]
+    fn child_follows_the_sampling_of_the_root() {
+        let unsampled = Span::root("unsampled");
+        assert!(!unsampled.context().is_sampled());
+        assert!(!unsampled.child("child").context().is_sampled());
+
+        let mut context = unsampled.context();
+        context.sampled = true;
+        let mut child = Span::child_of(&context, "child");
+        assert!(child.context().is_sampled());
+        child.set_attribute("key", "value".to_string());
+        assert_eq!(1, child.attributes.len());
+    }
+
+    #[test]
+    fn unsampled_span_keeps_no_attribute() {
+        let mut span = Span::root("root");
+        span.set_attribute("key", "value".to_string());
+        assert!(span.attributes.is_empty());
+    }
+
+    #[test]
+    fn roots_start_different_traces() {
+        let a = Span::root("a");
+        let b = Span::root("b");
+        assert_ne!(a.context().trace_id, b.context().trace_id);
+    }
+
+    #[test]
+    fn entered_span_is_current_until_the_guard_is_dropped() {
+        assert_eq!(None, current());
+        let outer = Span::root("outer");
+        {
+            let _outer = outer.enter();
+            assert_eq!(Some(outer.context()), current());
+            let inner = outer.child("inner");
+            {
+                let _inner = inner.enter();
+                assert_eq!(Some(inner.context()), current());
+            }
+            assert_eq!(Some(outer.context()), current());
+        }
+        assert_eq!(None, current());
+    }
+}
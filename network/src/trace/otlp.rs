@@ -0,0 +1,201 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! Exports the spans to an OpenTelemetry collector with OTLP/HTTP in the JSON encoding.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use super::{set_exporter, FinishedSpan};
+
+const MAX_BATCH: usize = 512;
+// The finished spans which wait for the exporter. The later spans are dropped when it's full.
+const MAX_QUEUED_SPANS: usize = 8 * MAX_BATCH;
+const TIMEOUT_SECS: u64 = 5;
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// Starts exporting the finished spans to the collector at `endpoint`, which is given as host:port.
+/// Only `sample_rate` of the traces, which is between 0 and 1, are exported.
+pub fn start(endpoint: &str, sample_rate: f64) -> Result<(), String> {
+    if sample_rate < 0.0 || sample_rate > 1.0 {
+        return Err(format!("Invalid trace sample rate {}", sample_rate))
+    }
+    let address = endpoint
+        .to_socket_addrs()
+        .map_err(|err| format!("Invalid OTLP endpoint {}: {}", endpoint, err))?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve OTLP endpoint {}", endpoint))?;
+    let host = endpoint.to_string();
+    let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_SPANS);
+    thread::Builder::new()
+        .name("otlp".to_string())
+        .spawn(move || run(address, host, receiver))
+        .map_err(|err| format!("Cannot start OTLP exporter: {}", err))?;
+    set_exporter(sender, sample_rate);
+    Ok(())
+}
+
+fn run(address: SocketAddr, host: String, receiver: Receiver<FinishedSpan>) {
+    while let Ok(span) = receiver.recv() {
+        let mut batch = vec![span];
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = post(&address, &host, &encode(&batch)) {
+            cwarn!(NET, "Cannot export {} spans to {}: {}", batch.len(), host, err);
+        }
+    }
+}
+
+fn post(address: &SocketAddr, host: &str, body: &str) -> io::Result<()> {
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let mut stream = TcpStream::connect_timeout(address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(
+        stream,
+        concat!(
+            "POST /v1/traces HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n",
+            "Content-Length: {}\r\nConnection: close\r\n\r\n{}"
+        ),
+        host,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, format!("Unexpected response status {:?}", status)))
+    }
+}
+
+fn encode(spans: &[FinishedSpan]) -> String {
+    let spans: Vec<_> = spans.iter().map(encode_span).collect();
+    format!(
+        concat!(
+            r#"{{"resourceSpans":[{{"resource":{{"attributes":[{}]}},"#,
+            r#""scopeSpans":[{{"scope":{{"name":"codechain-network"}},"spans":[{}]}}]}}]}}"#
+        ),
+        encode_attribute("service.name", "codechain"),
+        spans.join(",")
+    )
+}
+
+fn encode_span(span: &FinishedSpan) -> String {
+    let (high, low) = span.context.trace_id;
+    let parent = match span.parent_span_id {
+        Some(parent) => format!(r#""parentSpanId":"{:016x}","#, parent),
+        None => String::new(),
+    };
+    let attributes: Vec<_> = span.attributes.iter().map(|(key, value)| encode_attribute(key, value)).collect();
+    format!(
+        concat!(
+            r#"{{"traceId":"{:016x}{:016x}","spanId":"{:016x}",{}"name":"{}","kind":{},"#,
+            r#""startTimeUnixNano":"{}","endTimeUnixNano":"{}","attributes":[{}]}}"#
+        ),
+        high,
+        low,
+        span.context.span_id,
+        parent,
+        escape(span.name),
+        SPAN_KIND_INTERNAL,
+        span.start,
+        span.end,
+        attributes.join(",")
+    )
+}
+
+fn encode_attribute(key: &str, value: &str) -> String {
+    format!(r#"{{"key":"{}","value":{{"stringValue":"{}"}}}}"#, escape(key), escape(value))
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SpanContext;
+    use super::*;
+
+    fn span(parent_span_id: Option<u64>) -> FinishedSpan {
+        FinishedSpan {
+            context: SpanContext {
+                trace_id: (1, 2),
+                span_id: 3,
+            },
+            parent_span_id,
+            name: "p2p.receive",
+            start: 10,
+            end: 20,
+            attributes: vec![("extension", "block-propagation".to_string())],
+        }
+    }
+
+    #[test]
+    fn encode_root_span() {
+        assert_eq!(
+            concat!(
+                r#"{"traceId":"00000000000000010000000000000002","spanId":"0000000000000003","#,
+                r#""name":"p2p.receive","kind":1,"startTimeUnixNano":"10","endTimeUnixNano":"20","#,
+                r#""attributes":[{"key":"extension","value":{"stringValue":"block-propagation"}}]}"#
+            ),
+            encode_span(&span(None))
+        );
+    }
+
+    #[test]
+    fn encode_child_span() {
+        let encoded = encode_span(&span(Some(0xff)));
+        assert!(encoded.contains(r#""parentSpanId":"00000000000000ff","#));
+    }
+
+    #[test]
+    fn encode_batch() {
+        let encoded = encode(&[span(None), span(Some(3))]);
+        assert!(encoded.starts_with(concat!(
+            r#"{"resourceSpans":[{"resource":{"attributes":"#,
+            r#"[{"key":"service.name","value":{"stringValue":"codechain"}}]},"#
+        )));
+        assert!(encoded.ends_with("]}]}]}"));
+        assert_eq!(2, encoded.matches(r#""spanId""#).count());
+    }
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(r#"a\"b\\c\u000a"#, escape("a\"b\\c\n"));
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs the extension callbacks off the io threads, so a slow extension doesn't stall the connections.
+//! The callbacks of an extension always run on the same worker in the order they are dispatched.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+use parking_lot::Mutex;
+
+use super::trace::{self, Span, SpanContext};
+use super::{DisconnectReason, DropCounts, DropReason, NetworkExtension, NodeId, TimerToken};
+
+const QUEUE_SIZE: usize = 1024;
+
+pub enum Callback {
+    NodeAdded(NodeId),
+    NodeRemoved(NodeId),
+    NodeDisconnected(NodeId, DisconnectReason),
+    Negotiated(NodeId),
+    NegotiationAllowed(NodeId),
+    NegotiationDenied(NodeId),
+    NegotiationFailed(NodeId),
+    Message(NodeId, Vec<u8>),
+    Timeout(TimerToken),
+    LocalMessage(Vec<u8>),
+}
+
+impl Callback {
+    fn call(self, extension: &NetworkExtension) {
+        match self {
+            Callback::NodeAdded(node_id) => extension.on_node_added(&node_id),
+            Callback::NodeRemoved(node_id) => extension.on_node_removed(&node_id),
+            Callback::NodeDisconnected(node_id, reason) => extension.on_node_disconnected(&node_id, reason),
+            Callback::Negotiated(node_id) => extension.on_negotiated(&node_id),
+            Callback::NegotiationAllowed(node_id) => extension.on_negotiation_allowed(&node_id),
+            Callback::NegotiationDenied(node_id) => extension.on_negotiation_denied(&node_id),
+            Callback::NegotiationFailed(node_id) => extension.on_negotiation_failed(&node_id),
+            Callback::Message(node_id, message) => extension.on_message(&node_id, &message),
+            Callback::Timeout(timer_id) => extension.on_timeout(timer_id),
+            Callback::LocalMessage(message) => extension.on_local_message(&message),
+        }
+    }
+}
+
+// The span which dispatched the callback is carried to the worker
+type Job = (Arc<NetworkExtension>, Callback, Option<SpanContext>);
+
+pub struct Workers {
+    senders: Vec<Mutex<SyncSender<Job>>>,
+    drops: Mutex<HashMap<String, DropCounts>>,
+}
+
+impl Workers {
+    /// The callbacks run on the calling thread if `count` is 0.
+    pub fn start(count: usize) -> Self {
+        Self::with_queue_size(count, QUEUE_SIZE)
+    }
+
+    fn with_queue_size(count: usize, queue_size: usize) -> Self {
+        let senders = (0..count)
+            .map(|index| {
+                let (sender, receiver) = mpsc::sync_channel(queue_size);
+                thread::Builder::new()
+                    .name(format!("extension #{}", index))
+                    .spawn(move || run(receiver))
+                    .expect("Failed to create extension worker thread");
+                Mutex::new(sender)
+            })
+            .collect();
+        Self {
+            senders,
+            drops: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dispatch(&self, extension: Arc<NetworkExtension>, callback: Callback) {
+        if self.senders.is_empty() {
+            callback.call(&*extension);
+            return
+        }
+        let index = worker_of(&extension.name(), self.senders.len());
+        let is_message = match callback {
+            Callback::Message(..) => true,
+            _ => false,
+        };
+        let job = (extension, callback, trace::current());
+        let sender = self.senders[index].lock();
+        // The messages are driven by the peers, so they are dropped rather than queued without limit.
+        // The other callbacks wait for the worker not to lose the state changes of the extension.
+        let result = if is_message {
+            sender.try_send(job)
+        } else {
+            sender.send(job).map_err(|mpsc::SendError(job)| TrySendError::Disconnected(job))
+        };
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full((extension, ..))) => {
+                cdebug!(NETAPI, "The worker #{} of {} is full. A message is dropped", index, extension.name());
+                let mut drops = self.drops.lock();
+                drops.entry(extension.name()).or_insert_with(DropCounts::default).count(DropReason::QueueFull);
+            }
+            Err(TrySendError::Disconnected((extension, ..))) => {
+                cwarn!(NETAPI, "The worker #{} of {} is stopped", index, extension.name());
+            }
+        }
+    }
+
+    /// The messages dropped because the workers of the extensions were full
+    pub fn drops(&self) -> HashMap<String, DropCounts> {
+        self.drops.lock().clone()
+    }
+}
+
+fn worker_of(extension_name: &str, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    extension_name.hash(&mut hasher);
+    (hasher.finish() % count as u64) as usize
+}
+
+// The worker stops when the workers are dropped
+fn run(receiver: Receiver<Job>) {
+    while let Ok((extension, callback, parent)) = receiver.recv() {
+        let span = parent.map(|parent| Span::child_of(&parent, "p2p.extension"));
+        let _entered = span.as_ref().map(Span::enter);
+        callback.call(&*extension);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::super::Extension;
+    use super::*;
+
+    struct TestExtension {
+        name: String,
+        messages: Mutex<mpsc::Sender<(String, Vec<u8>)>>,
+    }
+
+    impl Extension for TestExtension {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn need_encryption(&self) -> bool {
+            false
+        }
+
+        fn on_message(&self, _node: &NodeId, message: &[u8]) {
+            self.messages.lock().send((self.name.clone(), message.to_vec())).unwrap();
+        }
+
+        fn on_timeout(&self, timer: TimerToken) {
+            self.messages.lock().send(("timeout".to_string(), vec![timer as u8])).unwrap();
+        }
+    }
+
+    #[test]
+    fn the_messages_of_an_extension_are_processed_in_order() {
+        let (sender, receiver) = mpsc::channel();
+        let a: Arc<NetworkExtension> = Arc::new(TestExtension {
+            name: "a".to_string(),
+            messages: Mutex::new(sender.clone()),
+        });
+        let b: Arc<NetworkExtension> = Arc::new(TestExtension {
+            name: "b".to_string(),
+            messages: Mutex::new(sender),
+        });
+
+        let workers = Workers::start(4);
+        for i in 0..100u8 {
+            workers.dispatch(Arc::clone(&a), Callback::Message(NodeId::random(), vec![i]));
+            workers.dispatch(Arc::clone(&b), Callback::Message(NodeId::random(), vec![i]));
+        }
+
+        let mut received_a = vec![];
+        let mut received_b = vec![];
+        for _ in 0..200 {
+            let (name, message) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            if name == "a" {
+                received_a.push(message[0]);
+            } else {
+                received_b.push(message[0]);
+            }
+        }
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(expected, received_a);
+        assert_eq!(expected, received_b);
+    }
+
+    #[test]
+    fn no_worker_runs_the_callback_at_once() {
+        let (sender, receiver) = mpsc::channel();
+        let a: Arc<NetworkExtension> = Arc::new(TestExtension {
+            name: "a".to_string(),
+            messages: Mutex::new(sender),
+        });
+
+        let workers = Workers::start(0);
+        workers.dispatch(a, Callback::Message(NodeId::random(), vec![1]));
+        assert_eq!(Ok(("a".to_string(), vec![1])), receiver.try_recv());
+    }
+
+    struct StalledExtension {
+        gate: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Extension for StalledExtension {
+        fn name(&self) -> String {
+            "stalled".to_string()
+        }
+
+        fn need_encryption(&self) -> bool {
+            false
+        }
+
+        fn on_message(&self, _node: &NodeId, _message: &[u8]) {
+            let _ = self.gate.lock().recv();
+        }
+    }
+
+    #[test]
+    fn the_messages_are_dropped_when_the_queue_is_full() {
+        let (gate, receiver) = mpsc::channel();
+        let stalled: Arc<NetworkExtension> = Arc::new(StalledExtension {
+            gate: Mutex::new(receiver),
+        });
+
+        let workers = Workers::with_queue_size(1, 2);
+        for i in 0..10u8 {
+            workers.dispatch(Arc::clone(&stalled), Callback::Message(NodeId::random(), vec![i]));
+        }
+        // The worker holds at most one message and the queue holds two
+        let dropped = workers.drops()["stalled"].get(DropReason::QueueFull);
+        assert!(7 <= dropped && dropped <= 8, "{} messages are dropped", dropped);
+        drop(gate);
+    }
+
+    #[test]
+    fn the_timeouts_run_on_the_worker_after_the_earlier_messages() {
+        let (sender, receiver) = mpsc::channel();
+        let a: Arc<NetworkExtension> = Arc::new(TestExtension {
+            name: "a".to_string(),
+            messages: Mutex::new(sender),
+        });
+
+        let workers = Workers::start(2);
+        workers.dispatch(Arc::clone(&a), Callback::Message(NodeId::random(), vec![1]));
+        workers.dispatch(a, Callback::Timeout(7));
+
+        assert_eq!(Ok(("a".to_string(), vec![1])), receiver.recv_timeout(Duration::from_secs(5)));
+        assert_eq!(Ok(("timeout".to_string(), vec![7])), receiver.recv_timeout(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use jsonrpc_core;
+use jsonrpc_pubsub::{PubSubMetadata, Session};
+use rand::{OsRng, Rng};
+use rustc_hex::ToHex;
+
+use super::rate_limit::Client;
+
+// The number of the random bytes in a generated token
+const TOKEN_SIZE: usize = 32;
+
+/// The transport which a call comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    /// The local tools, which the permission of the socket file authenticates
+    Ipc,
+    /// The bearer token given in the Authorization header, if any
+    Http(Option<String>),
+    /// The token given in the Sec-WebSocket-Protocol header of the handshake, if any
+    Ws(Option<String>),
+}
+
+#[derive(Clone)]
+pub struct Metadata {
+    pub origin: Origin,
+    /// The client which the rate limits count the calls by
+    pub client: Option<Client>,
+    /// The connection which the notifications of the subscriptions are sent over
+    pub session: Option<Arc<Session>>,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            origin: Origin::Http(None),
+            client: None,
+            session: None,
+        }
+    }
+}
+
+impl jsonrpc_core::Metadata for Metadata {}
+
+impl PubSubMetadata for Metadata {
+    fn session(&self) -> Option<Arc<Session>> {
+        self.session.clone()
+    }
+}
+
+/// The methods which a token can call.
+///
+/// A scope is `*` for every method, a namespace such as `chain_*`, or the name of a method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scopes(Vec<String>);
+
+impl Scopes {
+    pub fn new(scopes: Vec<String>) -> Self {
+        Scopes(scopes)
+    }
+
+    pub fn allows(&self, method: &str) -> bool {
+        self.0.iter().any(|scope| {
+            if scope == "*" {
+                true
+            } else if scope.ends_with("_*") {
+                method.starts_with(&scope[..scope.len() - 1])
+            } else {
+                scope == method
+            }
+        })
+    }
+}
+
+/// The API tokens and their scopes.
+///
+/// Each line of the secrets file is a token followed by its scopes, separated by whitespace.
+/// The empty lines and the lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Secrets {
+    tokens: HashMap<String, Scopes>,
+}
+
+impl Secrets {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut tokens = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut words = line.split_whitespace();
+            let token = words.next().expect("The line is not empty").to_string();
+            let scopes: Vec<String> = words.map(|scope| scope.to_string()).collect();
+            if scopes.is_empty() {
+                return Err(format!("The token at line {} has no scope", number + 1))
+            }
+            if tokens.insert(token, Scopes(scopes)).is_some() {
+                return Err(format!("The token at line {} is duplicated", number + 1))
+            }
+        }
+        Ok(Self {
+            tokens,
+        })
+    }
+
+    /// Loads the secrets file at `path`.
+    /// A new file with a token which can call every method is created if it doesn't exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read RPC secrets {:?}: {}", path, e))?;
+            return Self::parse(&contents).map_err(|e| format!("Invalid RPC secrets {:?}: {}", path, e))
+        }
+
+        let mut rng = OsRng::new().map_err(|e| format!("Cannot generate RPC token: {}", e))?;
+        let token = rng.gen_iter::<u8>().take(TOKEN_SIZE).collect::<Vec<_>>().to_hex();
+        let contents = format!("{} *\n", token);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Cannot create {:?}: {}", parent, e))?;
+        }
+        let mut file = create_private_file(path).map_err(|e| format!("Cannot create RPC secrets {:?}: {}", path, e))?;
+        file.write_all(contents.as_bytes()).map_err(|e| format!("Cannot write RPC secrets {:?}: {}", path, e))?;
+        Self::parse(&contents)
+    }
+
+    pub fn allows(&self, token: &str, method: &str) -> bool {
+        self.tokens.get(token).map(|scopes| scopes.allows(method)).unwrap_or(false)
+    }
+}
+
+// Only the owner can read the tokens
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> ::std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> ::std::io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Decides whether the token of a call over HTTP or WebSocket can make it.
+///
+/// Every call is allowed if there are no secrets, which the node allows only if the authorization is turned off
+/// explicitly. The calls over IPC are always allowed.
+pub struct Authorization {
+    secrets: Option<Secrets>,
+}
+
+impl Authorization {
+    pub fn new(secrets: Option<Secrets>) -> Self {
+        Self {
+            secrets,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.secrets.is_some()
+    }
+
+    pub fn allows(&self, origin: &Origin, method: &str) -> bool {
+        match (&self.secrets, origin) {
+            (None, _) => true,
+            (Some(_), Origin::Ipc) => true,
+            (Some(secrets), Origin::Http(Some(token))) | (Some(secrets), Origin::Ws(Some(token))) => {
+                secrets.allows(token, method)
+            }
+            (Some(_), Origin::Http(None)) | (Some(_), Origin::Ws(None)) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn scopes_allow_the_namespace_and_the_method() {
+        let secrets = Secrets::parse("# comment\n\nalice chain_* net_getPeers\nbob *\n").unwrap();
+        assert!(secrets.allows("alice", "chain_getBlockNumber"));
+        assert!(secrets.allows("alice", "net_getPeers"));
+        assert!(!secrets.allows("alice", "net_removePeer"));
+        assert!(!secrets.allows("alice", "chainx_get"));
+        assert!(secrets.allows("bob", "net_removePeer"));
+        assert!(!secrets.allows("carol", "chain_getBlockNumber"));
+    }
+
+    #[test]
+    fn token_without_scope_is_invalid() {
+        assert!(Secrets::parse("alice\n").is_err());
+        assert!(Secrets::parse("alice *\nalice chain_*\n").is_err());
+    }
+
+    #[test]
+    fn ipc_is_always_allowed() {
+        let authorization = Authorization::new(Some(Secrets::default()));
+        assert!(authorization.allows(&Origin::Ipc, "net_removePeer"));
+        assert!(!authorization.allows(&Origin::Http(None), "chain_getBlockNumber"));
+        assert!(!authorization.allows(&Origin::Http(Some("alice".to_string())), "chain_getBlockNumber"));
+        assert!(Authorization::new(None).allows(&Origin::Http(None), "net_removePeer"));
+    }
+
+    #[test]
+    fn websocket_needs_the_token_too() {
+        let authorization = Authorization::new(Some(Secrets::parse("alice chain_*\n").unwrap()));
+        assert!(!authorization.allows(&Origin::Ws(None), "chain_getBlockNumber"));
+        assert!(authorization.allows(&Origin::Ws(Some("alice".to_string())), "chain_getBlockNumber"));
+        assert!(!authorization.allows(&Origin::Ws(Some("alice".to_string())), "net_removePeer"));
+    }
+
+    #[test]
+    fn generated_secrets_are_loaded_again() {
+        let path = env::temp_dir().join("codechain-rpc-secrets-test");
+        let _ = fs::remove_file(&path);
+
+        let generated = Secrets::load_or_generate(&path).unwrap();
+        let loaded = Secrets::load_or_generate(&path).unwrap();
+        assert_eq!(generated, loaded);
+        let token = generated.tokens.keys().next().unwrap();
+        assert!(loaded.allows(token, "net_removePeer"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use ccore::{database_version, BlockChainClient, Client};
+use cnetwork::NetworkService;
+use csync::BlockSyncExtension;
+use jsonrpc_core::futures::future;
+use jsonrpc_http_server::hyper::header::ContentType;
+use jsonrpc_http_server::hyper::{self, Method, StatusCode};
+use jsonrpc_http_server::RequestMiddlewareAction;
+use serde_json;
+
+/// The path of the health endpoint, which is served next to JSON-RPC over HTTP
+pub const HEALTH_PATH: &str = "/health";
+
+// The node which is behind the best peer by more blocks than this cannot serve the recent state
+const MAX_HEALTHY_LAG: u64 = 10;
+
+/// The status reported by the health endpoint
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    /// Whether this node can serve the requests
+    pub healthy: bool,
+    /// Null if the network is disabled
+    pub listening_address: Option<String>,
+    pub peer_count: Option<usize>,
+    /// The number of peers the network tries to keep connected
+    pub min_peers: Option<usize>,
+    /// Null if the block sync is disabled
+    pub syncing: Option<bool>,
+    pub best_block_number: u64,
+    /// The highest block which the peers announced. Null if the block sync is disabled.
+    pub highest_block_number: Option<u64>,
+    /// Whether the database can be read
+    pub database: bool,
+}
+
+impl Health {
+    fn is_healthy(&self) -> bool {
+        let has_enough_peers = match (self.peer_count, self.min_peers) {
+            (Some(0), Some(min_peers)) => min_peers == 0,
+            _ => true,
+        };
+        // A node which follows the chain is always a few blocks behind the peers
+        let is_caught_up = match self.highest_block_number {
+            Some(highest) => highest.saturating_sub(self.best_block_number) <= MAX_HEALTHY_LAG,
+            None => true,
+        };
+        self.database && has_enough_peers && is_caught_up
+    }
+}
+
+/// Collects the health of the node from the client, the network and the block sync.
+#[derive(Clone)]
+pub struct HealthCheck {
+    client: Arc<Client>,
+    network_service: Option<Arc<NetworkService>>,
+    block_sync: Option<Arc<BlockSyncExtension>>,
+}
+
+impl HealthCheck {
+    pub fn new(
+        client: Arc<Client>,
+        network_service: Option<Arc<NetworkService>>,
+        block_sync: Option<Arc<BlockSyncExtension>>,
+    ) -> Self {
+        Self {
+            client,
+            network_service,
+            block_sync,
+        }
+    }
+
+    pub fn check(&self) -> Health {
+        let status = self.network_service.as_ref().map(|service| service.node_status());
+        let (listening, status) = match status {
+            Some(Ok(status)) => (true, Some(status)),
+            // The network stopped publishing its status
+            Some(Err(_)) => (false, None),
+            None => (true, None),
+        };
+        let sync_status = self.block_sync.as_ref().map(|sync| sync.status());
+        let mut health = Health {
+            healthy: false,
+            listening_address: status.as_ref().map(|status| status.listening_address.to_string()),
+            peer_count: status.as_ref().map(|status| status.peer_count),
+            min_peers: status.as_ref().map(|status| status.min_peers),
+            syncing: sync_status.as_ref().map(|status| status.is_syncing),
+            best_block_number: self.client.chain_info().best_block_number,
+            highest_block_number: sync_status.as_ref().map(|status| status.highest_block),
+            database: database_version(&*self.client.database()).is_ok(),
+        };
+        health.healthy = listening && health.is_healthy();
+        health
+    }
+
+    /// Answers `GET /health` with 200 if the node is healthy and 503 if not, and passes the others through.
+    pub fn on_request(&self, request: hyper::Request) -> RequestMiddlewareAction {
+        if request.method() != &Method::Get || request.path() != HEALTH_PATH {
+            return RequestMiddlewareAction::Proceed {
+                should_continue_on_invalid_cors: false,
+                request,
+            }
+        }
+        let health = self.check();
+        let status = if health.healthy {
+            StatusCode::Ok
+        } else {
+            StatusCode::ServiceUnavailable
+        };
+        let body = serde_json::to_string(&health).expect("Health is always serializable");
+        let response = hyper::Response::new().with_status(status).with_header(ContentType::json()).with_body(body);
+        // The probes of load balancers and Kubernetes send the address of the pod as the host
+        RequestMiddlewareAction::Respond {
+            should_validate_hosts: false,
+            response: Box::new(future::ok(response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health() -> Health {
+        Health {
+            healthy: false,
+            listening_address: Some("127.0.0.1:3485".to_string()),
+            peer_count: Some(3),
+            min_peers: Some(10),
+            syncing: Some(false),
+            best_block_number: 10,
+            highest_block_number: Some(10),
+            database: true,
+        }
+    }
+
+    #[test]
+    fn healthy_with_fewer_peers_than_target() {
+        assert!(health().is_healthy());
+    }
+
+    #[test]
+    fn unhealthy_without_peers_or_while_far_behind() {
+        let mut without_peers = health();
+        without_peers.peer_count = Some(0);
+        assert!(!without_peers.is_healthy());
+
+        let mut far_behind = health();
+        far_behind.syncing = Some(true);
+        far_behind.highest_block_number = Some(10 + MAX_HEALTHY_LAG + 1);
+        assert!(!far_behind.is_healthy());
+
+        let mut broken_database = health();
+        broken_database.database = false;
+        assert!(!broken_database.is_healthy());
+    }
+
+    #[test]
+    fn healthy_without_network() {
+        let mut health = health();
+        health.listening_address = None;
+        health.peer_count = None;
+        health.min_peers = None;
+        health.syncing = None;
+        health.highest_block_number = None;
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn healthy_while_following_the_peers() {
+        let mut following = health();
+        following.syncing = Some(true);
+        following.highest_block_number = Some(10 + MAX_HEALTHY_LAG);
+        assert!(following.is_healthy());
+    }
+}
@@ -15,25 +15,42 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 extern crate codechain_core as ccore;
+extern crate codechain_discovery as cdiscovery;
+extern crate codechain_network as cnetwork;
+extern crate codechain_sync as csync;
 extern crate codechain_types as ctypes;
 extern crate jsonrpc_core;
 extern crate jsonrpc_http_server;
+extern crate jsonrpc_ipc_server;
+extern crate jsonrpc_pubsub;
+extern crate jsonrpc_ws_server;
 extern crate kvdb;
-extern crate kvdb_rocksdb as rocksdb;
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
 extern crate log;
+extern crate parking_lot;
+extern crate rand;
 extern crate rlp;
 extern crate rustc_hex;
 extern crate rustc_serialize;
+extern crate rustls;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg_attr(test, macro_use)]
 extern crate serde_json;
 extern crate tokio_core;
 
 #[macro_use]
 extern crate jsonrpc_macros;
 
+pub mod auth;
+pub mod health;
+pub mod middleware;
+pub mod rate_limit;
 pub mod rpc_server;
+pub mod tls;
 pub mod v1;
 
 pub use rustc_serialize::hex;
@@ -41,5 +58,12 @@ pub use rustc_serialize::hex;
 pub use jsonrpc_core::{Compatibility, Error, MetaIoHandler, Params, Value};
 pub use jsonrpc_http_server::tokio_core::reactor::Remote;
 
+pub use auth::{Authorization, Metadata, Origin, Scopes, Secrets};
+pub use health::{Health, HealthCheck};
+pub use middleware::{DEFAULT_MAX_BATCH_SIZE, RequestMiddleware};
+pub use rate_limit::{RateLimit, RateLimits};
 pub use jsonrpc_http_server::Server;
-pub use rpc_server::start_http;
+pub use jsonrpc_ipc_server::Server as IpcServer;
+pub use jsonrpc_ws_server::Server as WsServer;
+pub use rpc_server::{start_http, start_ipc, start_ws};
+pub use tls::{start_tls, TlsCertificates, TlsServer};
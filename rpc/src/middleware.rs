@@ -0,0 +1,283 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use jsonrpc_core::futures::future::{self, Either, Future};
+use jsonrpc_core::{self, Call, Error, Failure, FutureResponse, Id, Output, Request, Response, Version};
+
+use super::auth::{Authorization, Metadata, Scopes};
+use super::rate_limit::{RateLimiter, RateLimits};
+use super::v1::errors;
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+/// Checks the requests before the handler calls the methods.
+///
+/// The calls of a batch are answered one by one: the calls which are not allowed or exceed the rate limits fail,
+/// and the others are made.
+/// The methods out of the allowlist of the transport don't exist for its clients.
+/// The handler runs the calls of a batch as the joined futures, so a slow call doesn't hold the rest.
+pub struct RequestMiddleware {
+    authorization: Authorization,
+    rate_limiter: RateLimiter,
+    max_batch_size: usize,
+    allowed_methods: Option<Scopes>,
+}
+
+impl RequestMiddleware {
+    pub fn new(authorization: Authorization, rate_limits: RateLimits, max_batch_size: usize) -> Self {
+        Self {
+            authorization,
+            rate_limiter: RateLimiter::new(rate_limits),
+            max_batch_size,
+            allowed_methods: None,
+        }
+    }
+
+    /// Serves only the methods in the scopes, such as `chain_*` or `net_getPeers`.
+    pub fn with_allowed_methods(mut self, allowed_methods: Scopes) -> Self {
+        self.allowed_methods = Some(allowed_methods);
+        self
+    }
+
+    /// Whether the calls over HTTP need the API tokens
+    pub fn authorizes(&self) -> bool {
+        self.authorization.is_enabled()
+    }
+
+    /// The number of the calls of each method which are rejected by the rate limits
+    pub fn rate_limited_calls(&self) -> HashMap<String, u64> {
+        self.rate_limiter.rejected_calls()
+    }
+
+    // Returns the failure if the call cannot be made. It's None for the notifications, which have no response.
+    fn check(&self, call: &Call, meta: &Metadata, now: Instant) -> Result<(), Option<Output>> {
+        let method = match call {
+            Call::MethodCall(method_call) => &method_call.method,
+            Call::Notification(notification) => &notification.method,
+            // The handler answers the invalid calls with the errors
+            Call::Invalid(_) => return Ok(()),
+        };
+        if let Some(ref allowed_methods) = self.allowed_methods {
+            if !allowed_methods.allows(method) {
+                return Err(failure(call, Error::method_not_found()))
+            }
+        }
+        if !self.authorization.allows(&meta.origin, method) {
+            return Err(failure(call, errors::unauthorized(method)))
+        }
+        if let Some(client) = meta.client {
+            if !self.rate_limiter.admit(client, method, now) {
+                return Err(failure(call, errors::rate_limited(method)))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn failure(call: &Call, error: Error) -> Option<Output> {
+    match call {
+        Call::MethodCall(method_call) => Some(Output::Failure(Failure {
+            jsonrpc: method_call.jsonrpc.clone(),
+            error,
+            id: method_call.id.clone(),
+        })),
+        _ => None,
+    }
+}
+
+fn respond(response: Option<Response>) -> FutureResponse {
+    Box::new(future::ok(response))
+}
+
+impl jsonrpc_core::Middleware<Metadata> for RequestMiddleware {
+    type Future = FutureResponse;
+
+    fn on_request<F, X>(&self, request: Request, meta: Metadata, next: F) -> Either<Self::Future, X>
+    where
+        F: FnOnce(Request, Metadata) -> X,
+        X: Future<Item = Option<Response>, Error = ()> + Send + 'static, {
+        let now = Instant::now();
+        let calls = match request {
+            Request::Single(call) => {
+                return match self.check(&call, &meta, now) {
+                    Ok(()) => Either::B(next(Request::Single(call), meta)),
+                    Err(failure) => Either::A(respond(failure.map(Response::Single))),
+                }
+            }
+            Request::Batch(calls) => calls,
+        };
+
+        if calls.len() > self.max_batch_size {
+            return Either::A(respond(Some(Response::Single(Output::Failure(Failure {
+                jsonrpc: Some(Version::V2),
+                error: errors::batch_too_large(self.max_batch_size),
+                id: Id::Null,
+            })))))
+        }
+
+        let mut allowed = Vec::with_capacity(calls.len());
+        let mut failures = Vec::new();
+        let mut is_denied = false;
+        for call in calls {
+            match self.check(&call, &meta, now) {
+                Ok(()) => allowed.push(call),
+                Err(failure) => {
+                    is_denied = true;
+                    failures.extend(failure);
+                }
+            }
+        }
+        if !is_denied {
+            return Either::B(next(Request::Batch(allowed), meta))
+        }
+        if allowed.is_empty() {
+            return Either::A(respond(if failures.is_empty() {
+                None
+            } else {
+                Some(Response::Batch(failures))
+            }))
+        }
+        Either::A(Box::new(next(Request::Batch(allowed), meta).map(move |response| {
+            let mut outputs = match response {
+                Some(Response::Batch(outputs)) => outputs,
+                Some(Response::Single(output)) => vec![output],
+                None => vec![],
+            };
+            outputs.extend(failures);
+            if outputs.is_empty() {
+                None
+            } else {
+                Some(Response::Batch(outputs))
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::{Compatibility, MetaIoHandler, Params, Value};
+    use serde_json;
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::super::auth::{Origin, Secrets};
+    use super::super::rate_limit::{Client, RateLimit};
+    use super::*;
+
+    fn handler_with_rate_limits(
+        secrets: &str,
+        rate_limits: RateLimits,
+        max_batch_size: usize,
+    ) -> MetaIoHandler<Metadata, RequestMiddleware> {
+        let authorization = Authorization::new(Some(Secrets::parse(secrets).unwrap()));
+        let middleware = RequestMiddleware::new(authorization, rate_limits, max_batch_size);
+        let mut handler = MetaIoHandler::new(Compatibility::V2, middleware);
+        handler.add_method("chain_ping", |_params: Params| Ok(Value::String("pong".to_string())));
+        handler.add_method("net_ping", |_params: Params| Ok(Value::String("pong".to_string())));
+        handler
+    }
+
+    fn handler(secrets: &str, max_batch_size: usize) -> MetaIoHandler<Metadata, RequestMiddleware> {
+        handler_with_rate_limits(secrets, RateLimits::default(), max_batch_size)
+    }
+
+    fn call(handler: &MetaIoHandler<Metadata, RequestMiddleware>, request: &str) -> Value {
+        let meta = Metadata {
+            origin: Origin::Http(Some("alice".to_string())),
+            client: Some(Client::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))),
+            session: None,
+        };
+        let response = handler.handle_request_sync(request, meta).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn batch_reports_the_errors_per_call() {
+        let handler = handler("alice chain_*", 10);
+        let response = call(
+            &handler,
+            r#"[{"jsonrpc":"2.0","method":"chain_ping","id":1},{"jsonrpc":"2.0","method":"net_ping","id":2}]"#,
+        );
+        let outputs = response.as_array().unwrap();
+        assert_eq!(2, outputs.len());
+        let succeeded = outputs.iter().find(|output| output["id"] == 1).unwrap();
+        assert_eq!("pong", succeeded["result"]);
+        let failed = outputs.iter().find(|output| output["id"] == 2).unwrap();
+        assert!(failed["error"].is_object());
+    }
+
+    #[test]
+    fn batch_larger_than_the_limit_is_rejected() {
+        let handler = handler("alice *", 1);
+        let response = call(
+            &handler,
+            r#"[{"jsonrpc":"2.0","method":"chain_ping","id":1},{"jsonrpc":"2.0","method":"net_ping","id":2}]"#,
+        );
+        assert!(response["error"].is_object());
+        assert_eq!(Value::Null, response["id"]);
+    }
+
+    #[test]
+    fn denied_single_call_fails() {
+        let handler = handler("alice chain_*", 10);
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"net_ping","id":1}"#);
+        assert!(response["error"].is_object());
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"chain_ping","id":1}"#);
+        assert_eq!("pong", response["result"]);
+    }
+
+    #[test]
+    fn call_over_the_rate_limit_fails() {
+        let mut rate_limits = RateLimits::default();
+        rate_limits.per_method.insert(
+            "net_ping".to_string(),
+            RateLimit {
+                calls_per_second: 1,
+            },
+        );
+        let handler = handler_with_rate_limits("alice *", rate_limits, 10);
+        let response = call(
+            &handler,
+            r#"[{"jsonrpc":"2.0","method":"net_ping","id":1},{"jsonrpc":"2.0","method":"net_ping","id":2}]"#,
+        );
+        let outputs = response.as_array().unwrap();
+        assert_eq!(2, outputs.len());
+        assert!(outputs.iter().any(|output| output["result"] == "pong"));
+        let failed = outputs.iter().find(|output| output["error"].is_object()).unwrap();
+        assert_eq!(-32015, failed["error"]["code"]);
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"chain_ping","id":3}"#);
+        assert_eq!("pong", response["result"]);
+    }
+
+    #[test]
+    fn method_out_of_the_allowlist_is_not_found() {
+        let authorization = Authorization::new(None);
+        let middleware = RequestMiddleware::new(authorization, RateLimits::default(), 10)
+            .with_allowed_methods(Scopes::new(vec!["chain_*".to_string()]));
+        let mut handler = MetaIoHandler::new(Compatibility::V2, middleware);
+        handler.add_method("chain_ping", |_params: Params| Ok(Value::String("pong".to_string())));
+        handler.add_method("net_ping", |_params: Params| Ok(Value::String("pong".to_string())));
+
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"net_ping","id":1}"#);
+        assert_eq!(-32601, response["error"]["code"]);
+        let response = call(&handler, r#"{"jsonrpc":"2.0","method":"chain_ping","id":2}"#);
+        assert_eq!("pong", response["result"]);
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+// The full buckets are forgotten when there are more clients than this
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// The calls per second which a client can make. A client which was silent can make as many calls at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub calls_per_second: u32,
+}
+
+impl RateLimit {
+    fn capacity(&self) -> f64 {
+        f64::from(self.calls_per_second.max(1))
+    }
+}
+
+/// The client which the rate limits count the calls by
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Client {
+    /// The address of an HTTP client
+    Ip(IpAddr),
+    /// A WebSocket connection, since the server doesn't tell the address of its peer
+    Connection(u64),
+}
+
+/// The limits of the calls over HTTP and WebSocket, which are counted by the clients.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    /// Every call of a client
+    pub per_client: Option<RateLimit>,
+    /// The calls of a client to each method
+    pub per_method: HashMap<String, RateLimit>,
+}
+
+impl RateLimits {
+    pub fn is_empty(&self) -> bool {
+        self.per_client.is_none() && self.per_method.is_empty()
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity(),
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        if self.updated_at < now {
+            let elapsed = now.duration_since(self.updated_at);
+            let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+            self.tokens = (self.tokens + elapsed * f64::from(limit.calls_per_second)).min(limit.capacity());
+            self.updated_at = now;
+        }
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        debug_assert!(self.has_token());
+        self.tokens -= 1.0;
+    }
+
+    fn is_full(&self, limit: &RateLimit) -> bool {
+        self.tokens >= limit.capacity()
+    }
+}
+
+/// Counts the calls of the clients in the token buckets, and the calls which are rejected.
+pub struct RateLimiter {
+    limits: RateLimits,
+    client_buckets: Mutex<HashMap<Client, TokenBucket>>,
+    method_buckets: Mutex<HashMap<(Client, String), TokenBucket>>,
+    // method => the number of the rejected calls
+    rejected: Mutex<HashMap<String, u64>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            client_buckets: Mutex::new(HashMap::new()),
+            method_buckets: Mutex::new(HashMap::new()),
+            rejected: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from each bucket of the call, or returns false without taking any if one of them is empty.
+    pub fn admit(&self, client: Client, method: &str, now: Instant) -> bool {
+        let mut client_buckets = self.client_buckets.lock();
+        let mut method_buckets = self.method_buckets.lock();
+        prune(&mut client_buckets, |_| self.limits.per_client.as_ref(), now);
+        prune(&mut method_buckets, |(_, method)| self.limits.per_method.get(method), now);
+
+        let mut client_bucket = match &self.limits.per_client {
+            Some(limit) => {
+                let bucket = client_buckets.entry(client).or_insert_with(|| TokenBucket::new(limit, now));
+                bucket.refill(limit, now);
+                Some(bucket)
+            }
+            None => None,
+        };
+        let mut method_bucket = match self.limits.per_method.get(method) {
+            Some(limit) => {
+                let bucket =
+                    method_buckets.entry((client, method.to_string())).or_insert_with(|| TokenBucket::new(limit, now));
+                bucket.refill(limit, now);
+                Some(bucket)
+            }
+            None => None,
+        };
+
+        let admitted = client_bucket.as_ref().map(|bucket| bucket.has_token()).unwrap_or(true)
+            && method_bucket.as_ref().map(|bucket| bucket.has_token()).unwrap_or(true);
+        if !admitted {
+            *self.rejected.lock().entry(method.to_string()).or_insert(0) += 1;
+            return false
+        }
+        if let Some(bucket) = client_bucket.as_mut() {
+            bucket.take();
+        }
+        if let Some(bucket) = method_bucket.as_mut() {
+            bucket.take();
+        }
+        true
+    }
+
+    /// The number of the rejected calls of each method
+    pub fn rejected_calls(&self) -> HashMap<String, u64> {
+        self.rejected.lock().clone()
+    }
+}
+
+// Forgets the clients whose buckets are refilled, which would be created again with the same tokens.
+fn prune<K, F>(buckets: &mut HashMap<K, TokenBucket>, limit_of: F, now: Instant)
+where
+    K: ::std::hash::Hash + Eq,
+    F: Fn(&K) -> Option<&RateLimit>, {
+    if buckets.len() < MAX_TRACKED_CLIENTS {
+        return
+    }
+    buckets.retain(|key, bucket| match limit_of(key) {
+        Some(limit) => {
+            bucket.refill(limit, now);
+            !bucket.is_full(limit)
+        }
+        None => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn ip(last: u8) -> Client {
+        Client::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+    }
+
+    fn per_client(calls_per_second: u32) -> RateLimits {
+        RateLimits {
+            per_client: Some(RateLimit {
+                calls_per_second,
+            }),
+            per_method: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn bucket_is_refilled_over_time() {
+        let limiter = RateLimiter::new(per_client(2));
+        let now = Instant::now();
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(!limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now + Duration::from_millis(500)));
+        assert!(!limiter.admit(ip(1), "chain_getBlockNumber", now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn each_ip_has_its_own_bucket() {
+        let limiter = RateLimiter::new(per_client(1));
+        let now = Instant::now();
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(!limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(limiter.admit(ip(2), "chain_getBlockNumber", now));
+    }
+
+    #[test]
+    fn each_connection_has_its_own_bucket() {
+        let limiter = RateLimiter::new(per_client(1));
+        let now = Instant::now();
+        assert!(limiter.admit(Client::Connection(1), "chain_getBlockNumber", now));
+        assert!(!limiter.admit(Client::Connection(1), "chain_getBlockNumber", now));
+        assert!(limiter.admit(Client::Connection(2), "chain_getBlockNumber", now));
+    }
+
+    #[test]
+    fn method_limit_applies_only_to_the_method() {
+        let mut limits = RateLimits::default();
+        limits.per_method.insert(
+            "chain_sendSignedParcel".to_string(),
+            RateLimit {
+                calls_per_second: 1,
+            },
+        );
+        let limiter = RateLimiter::new(limits);
+        let now = Instant::now();
+        assert!(limiter.admit(ip(1), "chain_sendSignedParcel", now));
+        assert!(!limiter.admit(ip(1), "chain_sendSignedParcel", now));
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert_eq!(Some(&1), limiter.rejected_calls().get("chain_sendSignedParcel"));
+    }
+
+    #[test]
+    fn rejected_call_takes_no_token() {
+        let mut limits = per_client(2);
+        limits.per_method.insert(
+            "chain_sendSignedParcel".to_string(),
+            RateLimit {
+                calls_per_second: 1,
+            },
+        );
+        let limiter = RateLimiter::new(limits);
+        let now = Instant::now();
+        assert!(limiter.admit(ip(1), "chain_sendSignedParcel", now));
+        assert!(!limiter.admit(ip(1), "chain_sendSignedParcel", now));
+        assert!(limiter.admit(ip(1), "chain_getBlockNumber", now));
+        assert!(!limiter.admit(ip(1), "chain_getBlockNumber", now));
+    }
+}
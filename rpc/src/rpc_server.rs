@@ -15,21 +15,68 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 // TODO: panic handler
-use jsonrpc_core;
+use jsonrpc_core::MetaIoHandler;
+use jsonrpc_http_server::hyper;
 use jsonrpc_http_server::{self, Host, Server, ServerBuilder};
-use std::default::Default;
+use jsonrpc_ipc_server::{self, RequestContext};
+use jsonrpc_pubsub::Session;
+use jsonrpc_ws_server;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use auth::{Metadata, Origin};
+use health::HealthCheck;
+use middleware::RequestMiddleware;
+use rate_limit::Client;
+
+// The WebSocket clients, which cannot set the Authorization header in the browsers, give the token as a protocol
+const WS_TOKEN_PROTOCOL_PREFIX: &str = "bearer.";
+
+// The TLS server on the loopback tells the address of its client
+fn client_ip(request: &hyper::Request) -> Option<IpAddr> {
+    let remote_ip = request.remote_addr()?.ip();
+    if !remote_ip.is_loopback() {
+        return Some(remote_ip)
+    }
+    let forwarded_for = request
+        .headers()
+        .get_raw("X-Forwarded-For")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(|value| value.trim().parse().ok());
+    Some(forwarded_for.unwrap_or(remote_ip))
+}
+
+// Reads the token from the "Authorization: Bearer <token>" header
+fn http_metadata(request: &hyper::Request) -> Metadata {
+    let token = request
+        .headers()
+        .get_raw("Authorization")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(|value| {
+            let mut words = value.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("Bearer"), Some(token)) => Some(token.to_string()),
+                _ => None,
+            }
+        });
+    Metadata {
+        origin: Origin::Http(token),
+        client: client_ip(request).map(Client::Ip),
+        session: None,
+    }
+}
 
 /// Start http server asynchronously and returns result with `Server` handle on success or an error.
-pub fn start_http<M: jsonrpc_core::Metadata>(
+pub fn start_http(
     addr: &SocketAddr,
     cors_domains: Option<Vec<String>>,
     allowed_hosts: Option<Vec<String>>,
-    handler: jsonrpc_core::MetaIoHandler<M>,
-) -> Result<Server, io::Error>
-where
-    M: Default, {
+    health: HealthCheck,
+    handler: MetaIoHandler<Metadata, RequestMiddleware>,
+) -> Result<Server, io::Error> {
     let cors_domains = cors_domains.map(|domains| {
         domains
             .into_iter()
@@ -42,7 +89,59 @@ where
     });
 
     ServerBuilder::new(handler)
+        .meta_extractor(http_metadata)
+        .request_middleware(move |request: hyper::Request| health.on_request(request))
         .cors(cors_domains.into())
         .allowed_hosts(allowed_hosts.map(|hosts| hosts.into_iter().map(Host::from).collect()).into())
         .start_http(addr)
 }
+
+/// Start ipc server asynchronously and returns result with `Server` handle on success or an error.
+///
+/// The path is a Unix domain socket, or a named pipe on Windows.
+pub fn start_ipc(
+    path: &str,
+    handler: MetaIoHandler<Metadata, RequestMiddleware>,
+) -> Result<jsonrpc_ipc_server::Server, io::Error> {
+    jsonrpc_ipc_server::ServerBuilder::new(handler)
+        .session_meta_extractor(|_: &RequestContext| Metadata {
+            origin: Origin::Ipc,
+            client: None,
+            session: None,
+        })
+        .start(path)
+}
+
+fn ws_metadata(context: &jsonrpc_ws_server::RequestContext) -> Metadata {
+    let token = context
+        .protocols
+        .iter()
+        .find(|protocol| protocol.starts_with(WS_TOKEN_PROTOCOL_PREFIX))
+        .map(|protocol| protocol[WS_TOKEN_PROTOCOL_PREFIX.len()..].to_string());
+    Metadata {
+        origin: Origin::Ws(token),
+        client: Some(Client::Connection(context.session_id)),
+        session: Some(Arc::new(Session::new(context.sender()))),
+    }
+}
+
+/// Start WebSocket server asynchronously and returns result with `Server` handle on success or an error.
+///
+/// Each connection has its own session, so it can subscribe to the notifications.
+/// The handshakes whose Origin or Host header is out of the allowlists are refused.
+pub fn start_ws(
+    addr: &SocketAddr,
+    allowed_origins: Option<Vec<String>>,
+    allowed_hosts: Option<Vec<String>>,
+    max_connections: usize,
+    handler: MetaIoHandler<Metadata, RequestMiddleware>,
+) -> Result<jsonrpc_ws_server::Server, jsonrpc_ws_server::Error> {
+    let allowed_origins =
+        allowed_origins.map(|origins| origins.into_iter().map(jsonrpc_ws_server::Origin::from).collect());
+    jsonrpc_ws_server::ServerBuilder::new(handler)
+        .session_meta_extractor(ws_metadata)
+        .allowed_origins(allowed_origins.into())
+        .allowed_hosts(allowed_hosts.map(|hosts| hosts.into_iter().map(jsonrpc_ws_server::Host::from).collect()).into())
+        .max_connections(max_connections)
+        .start(addr)
+}
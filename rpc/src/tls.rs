@@ -0,0 +1,494 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use parking_lot::{Mutex, RwLock};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig, ServerSession, Session};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+// The client which doesn't finish the handshake in time is dropped
+const HANDSHAKE_TIMEOUT_SECONDS: u64 = 10;
+// The connections beyond it are closed as soon as they are accepted
+const MAX_CONNECTIONS: usize = 256;
+// The head of a request longer than it is forwarded as it is
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const FORWARDED_FOR: &str = "x-forwarded-for:";
+
+/// The certificate chain and the private key of the TLS connections, which can be replaced while running.
+pub struct TlsCertificates {
+    certificate_path: String,
+    key_path: String,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsCertificates {
+    /// Loads the PEM encoded certificate chain and private key.
+    pub fn load(certificate_path: &str, key_path: &str) -> Result<Self, String> {
+        let config = load_config(certificate_path, key_path)?;
+        Ok(Self {
+            certificate_path: certificate_path.to_string(),
+            key_path: key_path.to_string(),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Reads the files again. The established connections keep the old certificates.
+    pub fn reload(&self) -> Result<(), String> {
+        let config = load_config(&self.certificate_path, &self.key_path)?;
+        *self.config.write() = Arc::new(config);
+        Ok(())
+    }
+
+    /// Reloads the certificates whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(certificates: Arc<TlsCertificates>) -> Result<(), String> {
+        sighup::watch(move || match certificates.reload() {
+            Ok(()) => info!("Reloaded the TLS certificate {}", certificates.certificate_path),
+            Err(err) => warn!("Cannot reload the TLS certificate, keep the old one: {}", err),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn reload_on_sighup(_certificates: Arc<TlsCertificates>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn config(&self) -> Arc<ServerConfig> {
+        self.config.read().clone()
+    }
+}
+
+fn load_config(certificate_path: &str, key_path: &str) -> Result<ServerConfig, String> {
+    let open = |path: &str| {
+        File::open(path).map(BufReader::new).map_err(|err| format!("Cannot open {}: {}", path, err))
+    };
+    let certificates = pemfile::certs(&mut open(certificate_path)?)
+        .map_err(|_| format!("Invalid certificate chain in {}", certificate_path))?;
+    if certificates.is_empty() {
+        return Err(format!("No certificate in {}", certificate_path))
+    }
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+        .map_err(|_| format!("Invalid private key in {}", key_path))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?)
+            .map_err(|_| format!("Invalid private key in {}", key_path))?;
+    }
+    let key = keys.into_iter().next().ok_or_else(|| format!("No private key in {}", key_path))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certificates, key);
+    Ok(config)
+}
+
+/// Terminates the TLS connections and forwards the plaintext to the rpc server.
+///
+/// The address of the client is sent in the X-Forwarded-For header of each HTTP request,
+/// which the rpc server trusts only from the loopback.
+pub struct TlsServer {
+    address: SocketAddr,
+    is_stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TlsServer {
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+}
+
+impl Drop for TlsServer {
+    fn drop(&mut self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        // Wake up the thread blocked on accept
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Listens on `addr` and forwards the decrypted connections to the rpc server listening on `backend`.
+pub fn start_tls(
+    addr: &SocketAddr,
+    backend: SocketAddr,
+    certificates: Arc<TlsCertificates>,
+) -> Result<TlsServer, io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    let address = listener.local_addr()?;
+    let is_stopped = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&is_stopped);
+    let connections = Arc::new(AtomicUsize::new(0));
+    let thread = thread::Builder::new().name("rpc.tls".to_string()).spawn(move || {
+        for client in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                break
+            }
+            let client = match client {
+                Ok(client) => client,
+                Err(err) => {
+                    warn!("Cannot accept a TLS connection: {}", err);
+                    continue
+                }
+            };
+            let connection = match Connection::count(&connections) {
+                Some(connection) => connection,
+                None => {
+                    debug!("Too many TLS connections, closing a new one");
+                    continue
+                }
+            };
+            let config = certificates.config();
+            let spawned = thread::Builder::new().name("rpc.tls.connection".to_string()).spawn(move || {
+                if let Err(err) = forward(client, backend, config) {
+                    debug!("The TLS connection is closed: {}", err);
+                }
+                drop(connection);
+            });
+            if let Err(err) = spawned {
+                warn!("Cannot serve a TLS connection: {}", err);
+            }
+        }
+    })?;
+    Ok(TlsServer {
+        address,
+        is_stopped,
+        thread: Some(thread),
+    })
+}
+
+// Counts a connection until it's dropped
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn count(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            return None
+        }
+        Some(Connection(Arc::clone(connections)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Relays the client to the backend on this thread, and the backend to the client on another.
+fn forward(client: TcpStream, backend: SocketAddr, config: Arc<ServerConfig>) -> io::Result<()> {
+    let forwarded_for = ForwardedFor::new(client.peer_addr()?.ip());
+    client.set_read_timeout(Some(Duration::from_secs(HANDSHAKE_TIMEOUT_SECONDS)))?;
+    let backend = TcpStream::connect(backend)?;
+    let session = Arc::new(Mutex::new(ServerSession::new(&config)));
+
+    let responses = {
+        let session = Arc::clone(&session);
+        let mut client = client.try_clone()?;
+        let mut backend = backend.try_clone()?;
+        thread::Builder::new().name("rpc.tls.response".to_string()).spawn(move || {
+            let result = forward_responses(&session, &mut backend, &mut client);
+            let _ = client.shutdown(Shutdown::Both);
+            result
+        })?
+    };
+
+    let result = forward_requests(&session, forwarded_for, &mut &client, &mut &backend);
+    let _ = backend.shutdown(Shutdown::Both);
+    let responses = responses.join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "relay panicked")));
+    result.and(responses)
+}
+
+// The session is not locked while writing to the backend, so that a slow backend doesn't hold the responses.
+fn forward_requests(
+    session: &Mutex<ServerSession>,
+    mut forwarded_for: ForwardedFor,
+    client: &mut &TcpStream,
+    backend: &mut &TcpStream,
+) -> io::Result<()> {
+    let mut encrypted = [0u8; BUFFER_SIZE];
+    let mut plaintext = [0u8; BUFFER_SIZE];
+    let mut is_handshaking = true;
+    loop {
+        let len = client.read(&mut encrypted)?;
+        if len == 0 {
+            return Ok(())
+        }
+        let mut requests = Vec::new();
+        {
+            let mut session = session.lock();
+            let mut input = &encrypted[..len];
+            while !input.is_empty() {
+                session.read_tls(&mut input)?;
+                if let Err(err) = session.process_new_packets() {
+                    // Send the alert before closing
+                    let _ = write_tls(&mut *session, client);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))
+                }
+                loop {
+                    let len = session.read(&mut plaintext)?;
+                    if len == 0 {
+                        break
+                    }
+                    requests.extend_from_slice(&plaintext[..len]);
+                }
+            }
+            // The handshake messages
+            write_tls(&mut *session, client)?;
+            if is_handshaking && !session.is_handshaking() {
+                is_handshaking = false;
+                client.set_read_timeout(None)?;
+            }
+        }
+        backend.write_all(&forwarded_for.rewrite(&requests))?;
+    }
+}
+
+// Adds the address of the client to each HTTP request, replacing the X-Forwarded-For headers which the client sent.
+//
+// The requests are followed by their bodies of Content-Length bytes.
+// The rest of the connection is forwarded as it is after a request which upgrades the connection or is chunked,
+// since it's not a sequence of the requests anymore.
+struct ForwardedFor {
+    header: Vec<u8>,
+    state: RequestState,
+}
+
+enum RequestState {
+    Head(Vec<u8>),
+    Body(usize),
+    Raw,
+}
+
+impl ForwardedFor {
+    fn new(ip: IpAddr) -> Self {
+        Self {
+            header: format!("X-Forwarded-For: {}\r\n", ip).into_bytes(),
+            state: RequestState::Head(Vec::new()),
+        }
+    }
+
+    fn rewrite(&mut self, mut input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() + self.header.len());
+        while !input.is_empty() {
+            let state = ::std::mem::replace(&mut self.state, RequestState::Raw);
+            self.state = match state {
+                RequestState::Raw => {
+                    output.extend_from_slice(input);
+                    input = &[];
+                    RequestState::Raw
+                }
+                RequestState::Body(remaining) => {
+                    let len = remaining.min(input.len());
+                    output.extend_from_slice(&input[..len]);
+                    input = &input[len..];
+                    if len == remaining {
+                        RequestState::Head(Vec::new())
+                    } else {
+                        RequestState::Body(remaining - len)
+                    }
+                }
+                RequestState::Head(mut head) => {
+                    let start = head.len().saturating_sub(3);
+                    head.extend_from_slice(input);
+                    match find(&head[start..], b"\r\n\r\n") {
+                        Some(position) => {
+                            let end = start + position + 4;
+                            input = &input[input.len() - (head.len() - end)..];
+                            head.truncate(end);
+                            self.rewrite_head(&head, &mut output)
+                        }
+                        None if head.len() > MAX_HEAD_SIZE => {
+                            output.extend_from_slice(&head);
+                            input = &[];
+                            RequestState::Raw
+                        }
+                        None => {
+                            input = &[];
+                            RequestState::Head(head)
+                        }
+                    }
+                }
+            };
+        }
+        output
+    }
+
+    // Writes the head with the header and returns the state after it
+    fn rewrite_head(&self, head: &[u8], output: &mut Vec<u8>) -> RequestState {
+        let head = match ::std::str::from_utf8(head) {
+            Ok(head) => head,
+            Err(_) => {
+                output.extend_from_slice(head);
+                return RequestState::Raw
+            }
+        };
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().expect("split returns at least one item");
+        output.extend_from_slice(request_line.as_bytes());
+        output.extend_from_slice(b"\r\n");
+        output.extend_from_slice(&self.header);
+
+        let mut content_length = 0;
+        let mut is_raw = false;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let lowercase = line.to_ascii_lowercase();
+            if lowercase.starts_with(FORWARDED_FOR) {
+                continue
+            }
+            if lowercase.starts_with("content-length:") {
+                content_length = lowercase["content-length:".len()..].trim().parse().unwrap_or(0);
+            } else if lowercase.starts_with("transfer-encoding:") || lowercase.starts_with("upgrade:") {
+                is_raw = true;
+            }
+            output.extend_from_slice(line.as_bytes());
+            output.extend_from_slice(b"\r\n");
+        }
+        output.extend_from_slice(b"\r\n");
+
+        if is_raw {
+            RequestState::Raw
+        } else if content_length == 0 {
+            RequestState::Head(Vec::new())
+        } else {
+            RequestState::Body(content_length)
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn forward_responses(
+    session: &Mutex<ServerSession>,
+    backend: &mut TcpStream,
+    client: &mut TcpStream,
+) -> io::Result<()> {
+    let mut plaintext = [0u8; BUFFER_SIZE];
+    loop {
+        let len = backend.read(&mut plaintext)?;
+        let mut session = session.lock();
+        if len == 0 {
+            session.send_close_notify();
+            return write_tls(&mut *session, client)
+        }
+        session.write_all(&plaintext[..len])?;
+        write_tls(&mut *session, client)?;
+    }
+}
+
+fn write_tls<W: Write>(session: &mut ServerSession, client: &mut W) -> io::Result<()> {
+    while session.wants_write() {
+        session.write_tls(client)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod sighup {
+    use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+    use std::thread;
+    use std::time::Duration;
+
+    use libc;
+
+    const POLL_INTERVAL_MS: u64 = 500;
+
+    static RECEIVED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    extern "C" fn on_sighup(_: libc::c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    // Only sets a flag in the signal handler, and calls the callback on a thread.
+    pub fn watch<F>(callback: F) -> Result<(), String>
+    where
+        F: Fn() + Send + 'static, {
+        let previous = unsafe { libc::signal(libc::SIGHUP, on_sighup as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err("Cannot handle SIGHUP".to_string())
+        }
+        thread::Builder::new()
+            .name("rpc.sighup".to_string())
+            .spawn(move || loop {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                if RECEIVED.swap(false, Ordering::SeqCst) {
+                    callback();
+                }
+            })
+            .map(|_| ())
+            .map_err(|err| format!("Cannot watch SIGHUP: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loading_missing_files_fails() {
+        assert!(TlsCertificates::load("/nonexistent/cert.pem", "/nonexistent/key.pem").is_err());
+    }
+
+    fn forwarded_for() -> ForwardedFor {
+        ForwardedFor::new("10.0.0.1".parse().unwrap())
+    }
+
+    #[test]
+    fn each_request_has_the_address_of_the_client() {
+        let mut forwarded_for = forwarded_for();
+        let requests = b"POST / HTTP/1.1\r\nX-Forwarded-For: 1.2.3.4\r\nContent-Length: 2\r\n\r\n{}\
+                         POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let mut output = Vec::new();
+        // The requests are split anywhere
+        for chunk in requests.chunks(5) {
+            output.extend(forwarded_for.rewrite(chunk));
+        }
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\nContent-Length: 2\r\n\r\n{}\
+               POST / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\nContent-Length: 0\r\n\r\n"[..],
+            &output[..]
+        );
+    }
+
+    #[test]
+    fn upgraded_connection_is_forwarded_as_it_is() {
+        let mut forwarded_for = forwarded_for();
+        let output = forwarded_for.rewrite(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            &b"GET / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\nUpgrade: websocket\r\n\r\nGET / HTTP/1.1\r\n\r\n"[..],
+            &output[..]
+        );
+    }
+
+    #[test]
+    fn connections_are_capped() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counted: Vec<_> = (0..MAX_CONNECTIONS).map(|_| Connection::count(&connections).unwrap()).collect();
+        assert!(Connection::count(&connections).is_none());
+        drop(counted);
+        assert!(Connection::count(&connections).is_some());
+    }
+}
@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::Error as CoreError;
+use ccore::{Error as CoreError, SignError};
 use kvdb::Error as KVDBError;
 use rlp::DecoderError;
 
@@ -24,6 +24,12 @@ mod codes {
     pub const UNKNOWN_ERROR: i64 = -32009;
     pub const PARCEL_ERROR: i64 = -32010;
     pub const KVDB_ERROR: i64 = -32011;
+    pub const INVOICE_PRUNED: i64 = -32012;
+    pub const NETWORK_ERROR: i64 = -32013;
+    pub const UNAUTHORIZED: i64 = -32014;
+    pub const RATE_LIMITED: i64 = -32015;
+    pub const ACCOUNT_ERROR: i64 = -32016;
+    pub const LIGHT_NODE: i64 = -32017;
 }
 
 pub fn parcel<T: Into<CoreError>>(error: T) -> Error {
@@ -32,7 +38,7 @@ pub fn parcel<T: Into<CoreError>>(error: T) -> Error {
         Error {
             code: ErrorCode::ServerError(codes::PARCEL_ERROR),
             message: ::ccore::parcel_error_message(&e),
-            data: None,
+            data: Some(::serde_json::to_value(&e).expect("A parcel error is always serializable")),
         }
     } else {
         Error {
@@ -51,6 +57,70 @@ pub fn kvdb(error: KVDBError) -> Error {
     }
 }
 
+pub fn invoice_pruned() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::INVOICE_PRUNED),
+        message: "The invoice is pruned by the retention policy of this node.".into(),
+        data: None,
+    }
+}
+
+pub fn network(error: String) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::NETWORK_ERROR),
+        message: "Network error.".into(),
+        data: Some(Value::String(error)),
+    }
+}
+
+pub fn unauthorized(method: &str) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::UNAUTHORIZED),
+        message: format!("The token cannot call {}.", method),
+        data: None,
+    }
+}
+
+pub fn rate_limited(method: &str) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::RATE_LIMITED),
+        message: format!("Too many calls to {}. Try again later.", method),
+        data: None,
+    }
+}
+
+pub fn batch_too_large(max_batch_size: usize) -> Error {
+    Error {
+        code: ErrorCode::InvalidRequest,
+        message: format!("A batch cannot have more than {} calls.", max_batch_size),
+        data: None,
+    }
+}
+
+pub fn account(error: SignError) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::ACCOUNT_ERROR),
+        message: error.to_string(),
+        data: None,
+    }
+}
+
+pub fn not_on_light_node(method: &str) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::LIGHT_NODE),
+        message: format!("A light node cannot serve {}.", method),
+        data: None,
+    }
+}
+
+pub fn not_fetched_from_peers() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::LIGHT_NODE),
+        message: "No peer answered the query of the light node. Try again later.".into(),
+        data: None,
+    }
+}
+
 pub fn rlp(error: DecoderError) -> Error {
     Error {
         code: ErrorCode::ServerError(codes::UNKNOWN_ERROR),
@@ -58,3 +128,30 @@ pub fn rlp(error: DecoderError) -> Error {
         data: Some(Value::String(format!("{:?}", error))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ccore::ParcelError;
+    use ctypes::U256;
+
+    use super::*;
+
+    #[test]
+    fn parcel_error_carries_the_reason() {
+        let error = parcel(ParcelError::InvalidNonce {
+            expected: U256::from(3),
+            got: U256::from(1),
+        });
+        assert_eq!(ErrorCode::ServerError(codes::PARCEL_ERROR), error.code);
+        let data = error.data.unwrap();
+        assert_eq!(Some(&Value::String("InvalidNonce".to_string())), data.get("type"));
+        assert!(data["content"].get("expected").is_some());
+    }
+
+    #[test]
+    fn unit_parcel_error_has_only_the_type() {
+        let data = parcel(ParcelError::AlreadyImported).data.unwrap();
+        assert_eq!(Some(&Value::String("AlreadyImported".to_string())), data.get("type"));
+        assert_eq!(None, data.get("content"));
+    }
+}
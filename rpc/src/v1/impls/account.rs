@@ -0,0 +1,122 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ccore::AccountProvider;
+use ctypes::{H160, H256, H520};
+use jsonrpc_core::Result;
+
+use super::super::errors;
+use super::super::traits::Account;
+
+pub struct AccountClient {
+    account_provider: Arc<AccountProvider>,
+}
+
+impl AccountClient {
+    pub fn new(account_provider: &Arc<AccountProvider>) -> Self {
+        Self {
+            account_provider: account_provider.clone(),
+        }
+    }
+}
+
+impl Account for AccountClient {
+    fn list(&self) -> Result<Vec<H160>> {
+        self.account_provider.get_list().map_err(errors::account)
+    }
+
+    fn create(&self, password: String) -> Result<H160> {
+        self.account_provider.new_account(&password).map_err(errors::account)
+    }
+
+    fn import_raw(&self, secret: H256, password: String) -> Result<H160> {
+        self.account_provider.insert_account_with_password(secret.into(), &password).map_err(errors::account)
+    }
+
+    fn unlock(&self, address: H160, password: String, duration: Option<u64>) -> Result<()> {
+        self.account_provider.unlock(address, &password, duration.map(Duration::from_secs)).map_err(errors::account)
+    }
+
+    fn sign(&self, message: H256, address: H160, password: Option<String>) -> Result<H520> {
+        let password = password.as_ref().map(String::as_str);
+        let signature =
+            self.account_provider.sign_user_message(address, password, &message).map_err(errors::account)?;
+        Ok(signature.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::IoHandler;
+    use serde_json::{self, Value};
+
+    use super::*;
+
+    fn handler(account_provider: &Arc<AccountProvider>) -> IoHandler {
+        let mut handler = IoHandler::new();
+        handler.extend_with(AccountClient::new(account_provider).to_delegate());
+        handler
+    }
+
+    fn call(handler: &IoHandler, method: &str, params: Value) -> Value {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        });
+        let response = handler.handle_request_sync(&request.to_string()).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn create_requires_a_password() {
+        let handler = handler(&AccountProvider::transient_provider());
+
+        let response = call(&handler, "account_create", json!([""]));
+        assert!(response.get("error").is_some());
+        let response = call(&handler, "account_create", json!(["password"]));
+        assert!(response.get("error").is_some());
+        let response = call(&handler, "account_create", json!(["secret"]));
+        assert!(response.get("result").is_some());
+    }
+
+    #[test]
+    fn node_account_does_not_sign() {
+        let account_provider = AccountProvider::transient_provider();
+        let (address, _) = account_provider.new_account_and_public().unwrap();
+        let handler = handler(&account_provider);
+
+        let params = json!([H256::random(), address, "password"]);
+        let response = call(&handler, "account_sign", params);
+        assert_eq!(Some(&Value::String("Account is used by the node".to_string())), response["error"].get("message"));
+    }
+
+    #[test]
+    fn user_account_signs_with_the_password() {
+        let handler = handler(&AccountProvider::transient_provider());
+        let address = call(&handler, "account_create", json!(["secret"]))["result"].clone();
+
+        let response = call(&handler, "account_sign", json!([H256::random(), address, null]));
+        assert!(response.get("error").is_some());
+        let response = call(&handler, "account_sign", json!([H256::random(), address, "secret"]));
+        assert!(response.get("result").is_some());
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::sync::Arc;
+
+use csync::BlockSyncExtension;
+use jsonrpc_core::Result;
+
+use super::super::traits::BlockSync;
+use super::super::types::SyncStatus;
+
+pub struct BlockSyncClient {
+    block_sync: Arc<BlockSyncExtension>,
+}
+
+impl BlockSyncClient {
+    pub fn new(block_sync: &Arc<BlockSyncExtension>) -> Self {
+        Self {
+            block_sync: block_sync.clone(),
+        }
+    }
+}
+
+impl BlockSync for BlockSyncClient {
+    fn get_status(&self) -> Result<SyncStatus> {
+        Ok(self.block_sync.status().into())
+    }
+}
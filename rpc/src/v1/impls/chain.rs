@@ -14,33 +14,94 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::sync::mpsc::{Receiver, TrySendError};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 use ccore::{
-    Asset, AssetAddress, AssetScheme, AssetSchemeAddress, Balance, BlockChainClient, BlockId, BlockInfo, ChainInfo,
-    Client, Invoice, Miner, MinerService, Nonce, RegularKey, SignedParcel,
+    Account, Asset, AssetAddress, AssetScheme, AssetSchemeAddress, Balance, Block as CoreBlock, BlockChainClient,
+    BlockId, BlockInfo, BlockNumber, ChainInfo, ChainNotify, Client, Invoice, LocalizedLogEntry, MessageAddress, Nonce,
+    RegularKey, ShardId, SignedParcel, StateClient, Transaction, TransactionQueueClient,
 };
+use csync::LightSyncExtension;
 use ctypes::{H160, H256, Public, U256};
+use jsonrpc_core::{Error, Result};
+use jsonrpc_macros::pubsub::Subscriber;
+use jsonrpc_pubsub::SubscriptionId;
+use parking_lot::Mutex;
 use rlp::UntrustedRlp;
 
-use jsonrpc_core::Result;
-
+use super::super::super::auth::Metadata;
 use super::super::errors;
-use super::super::traits::Chain;
-use super::super::types::{Block, Bytes, Parcel};
+use super::super::traits::{Chain, ChainPubSub};
+use super::super::types::{Block, Bytes, Filter, FinalizedBlock, Parcel, Reorg};
+use super::subscribers::{spawn_notifier, Subscribers};
+
+// The light extension tries the other peers for a while before giving up.
+const LIGHT_FETCH_TIMEOUT_SECONDS: u64 = 30;
+// The events beyond it are dropped rather than holding the importer.
+const MAX_QUEUED_CHAIN_EVENTS: usize = 1024;
 
 pub struct ChainClient {
-    client: Arc<Client>,
-    miner: Arc<Miner>,
+    client: Arc<BlockChainClient>,
+    state: Arc<StateClient>,
+    queue: Arc<TransactionQueueClient>,
+    // The light node has only the headers, so the state and the bodies are fetched from the peers.
+    light: Option<Arc<LightSyncExtension>>,
 }
 
 impl ChainClient {
-    pub fn new(client: &Arc<Client>, miner: &Arc<Miner>) -> Self {
+    pub fn new(
+        client: Arc<BlockChainClient>,
+        state: Arc<StateClient>,
+        queue: Arc<TransactionQueueClient>,
+        light: Option<Arc<LightSyncExtension>>,
+    ) -> Self {
         ChainClient {
-            client: client.clone(),
-            miner: miner.clone(),
+            client,
+            state,
+            queue,
+            light,
+        }
+    }
+
+    fn refuse_on_light_node(&self, method: &str) -> Result<()> {
+        if self.light.is_some() {
+            return Err(errors::not_on_light_node(method))
+        }
+        Ok(())
+    }
+
+    fn account(&self, light: &LightSyncExtension, address: H160, block_id: BlockId) -> Result<Option<Account>> {
+        match self.client.block_hash(block_id) {
+            Some(block_hash) => wait_for_peers(light.fetch_account(block_hash, address.into())),
+            None => Ok(None),
         }
     }
+
+    fn block(&self, block_id: BlockId) -> Result<Option<Block>> {
+        let light = match &self.light {
+            Some(light) => light,
+            None => return Ok(self.client.block(block_id).map(|block| block.decode().into())),
+        };
+        let header = match self.client.block_header(block_id) {
+            Some(header) => header.decode(),
+            None => return Ok(None),
+        };
+        let parcels = wait_for_peers(light.fetch_body(header.hash()))?;
+        let block = CoreBlock {
+            header,
+            parcels,
+        };
+        Ok(Some(block.into()))
+    }
+}
+
+fn wait_for_peers<T>(receiver: Receiver<T>) -> Result<T> {
+    // The sender is dropped when every peer failed to answer
+    receiver
+        .recv_timeout(Duration::from_secs(LIGHT_FETCH_TIMEOUT_SECONDS))
+        .map_err(|_| errors::not_fetched_from_peers())
 }
 
 impl Chain for ChainClient {
@@ -51,57 +112,106 @@ impl Chain for ChainClient {
             .and_then(|parcel| SignedParcel::new(parcel).map_err(errors::parcel))
             .and_then(|signed| {
                 let hash = signed.hash();
-                self.miner.import_own_parcel(&*self.client, signed).map_err(errors::parcel).map(|_| hash)
+                self.queue.queue_own_parcel(signed).map_err(errors::parcel).map(|_| hash)
             })
             .map(Into::into)
     }
 
     fn get_parcel(&self, parcel_hash: H256) -> Result<Option<Parcel>> {
+        self.refuse_on_light_node("chain_getParcel")?;
         match self.client.parcel(parcel_hash.into()) {
             Some(parcel) => Ok(Some(parcel.into())),
             None => Ok(None),
         }
     }
 
+    fn get_transaction(&self, transaction_hash: H256) -> Result<Option<Transaction>> {
+        self.refuse_on_light_node("chain_getTransaction")?;
+        Ok(self.client.transaction(transaction_hash.into()))
+    }
+
     fn get_parcel_invoices(&self, parcel_hash: H256) -> Result<Option<Vec<Invoice>>> {
-        Ok(self.client.parcel_invoices(parcel_hash.into()).map(|parcel_invoices| parcel_invoices.invoices))
+        self.refuse_on_light_node("chain_getParcelInvoices")?;
+        match self.client.parcel_invoices(parcel_hash.into()) {
+            Some(parcel_invoices) => Ok(Some(parcel_invoices.invoices)),
+            None => match self.client.parcel(parcel_hash.into()) {
+                Some(parcel) if !self.client.is_invoice_retained(BlockId::Hash(parcel.block_hash)) => {
+                    Err(errors::invoice_pruned())
+                }
+                _ => Ok(None),
+            },
+        }
     }
 
     fn get_transaction_invoice(&self, transaction_hash: H256) -> Result<Option<Invoice>> {
-        Ok(self.client.transaction_invoice(transaction_hash.into()))
+        self.refuse_on_light_node("chain_getTransactionInvoice")?;
+        match self.client.transaction_invoice(transaction_hash.into()) {
+            Some(invoice) => Ok(Some(invoice)),
+            None if !self.client.is_transaction_invoice_retained(transaction_hash.into()) => {
+                Err(errors::invoice_pruned())
+            }
+            None => Ok(None),
+        }
     }
 
-    fn get_asset_scheme(&self, transaction_hash: H256) -> Result<Option<AssetScheme>> {
-        if let Some(state) = self.client.state_at(BlockId::Latest) {
-            let address = AssetSchemeAddress::new(transaction_hash);
+    fn get_logs(&self, filter: Filter) -> Result<Vec<LocalizedLogEntry>> {
+        self.refuse_on_light_node("chain_getLogs")?;
+        Ok(self.client.logs(filter.into()))
+    }
+
+    fn get_asset_scheme(&self, transaction_hash: H256, shard_id: Option<ShardId>) -> Result<Option<AssetScheme>> {
+        self.refuse_on_light_node("chain_getAssetScheme")?;
+        if let Some(state) = self.state.state_info(BlockId::Latest) {
+            let address = AssetSchemeAddress::new(transaction_hash, shard_id.unwrap_or(0));
             Ok(state.asset_scheme(&address).map_err(errors::parcel)?)
         } else {
             Ok(None)
         }
     }
 
-    fn get_asset(&self, transaction_hash: H256, index: usize) -> Result<Option<Asset>> {
-        if let Some(state) = self.client.state_at(BlockId::Latest) {
-            let address = AssetAddress::new(transaction_hash, index);
+    fn get_asset(&self, transaction_hash: H256, index: usize, shard_id: Option<ShardId>) -> Result<Option<Asset>> {
+        self.refuse_on_light_node("chain_getAsset")?;
+        if let Some(state) = self.state.state_info(BlockId::Latest) {
+            let address = AssetAddress::new(transaction_hash, index, shard_id.unwrap_or(0));
             Ok(state.asset(&address).map_err(errors::parcel)?)
         } else {
             Ok(None)
         }
     }
 
+    fn get_message_proof(&self, transaction_hash: H256, index: usize, shard_id: ShardId) -> Result<Option<Vec<Bytes>>> {
+        self.refuse_on_light_node("chain_getMessageProof")?;
+        if let Some(state) = self.state.state_info(BlockId::Latest) {
+            let address = MessageAddress::new(transaction_hash, index, shard_id);
+            let proof = state.message_proof(&address).map_err(errors::parcel)?;
+            Ok(proof.map(|proof| proof.into_iter().map(Bytes::new).collect()))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn get_nonce(&self, address: H160, block_number: Option<u64>) -> Result<Option<U256>> {
         let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.nonce(&address.into(), block_id))
+        if let Some(light) = &self.light {
+            return Ok(self.account(light, address, block_id)?.map(|account| *account.nonce()))
+        }
+        Ok(self.state.nonce(&address.into(), block_id))
     }
 
     fn get_balance(&self, address: H160, block_number: Option<u64>) -> Result<Option<U256>> {
         let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.balance(&address.into(), block_id.into()))
+        if let Some(light) = &self.light {
+            return Ok(self.account(light, address, block_id)?.map(|account| *account.balance()))
+        }
+        Ok(self.state.balance(&address.into(), block_id.into()))
     }
 
     fn get_regular_key(&self, address: H160, block_number: Option<u64>) -> Result<Option<Public>> {
         let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.regular_key(&address.into(), block_id.into()))
+        if let Some(light) = &self.light {
+            return Ok(self.account(light, address, block_id)?.and_then(|account| account.regular_key()))
+        }
+        Ok(self.state.regular_key(&address.into(), block_id.into()))
     }
 
     fn get_block_number(&self) -> Result<u64> {
@@ -113,10 +223,133 @@ impl Chain for ChainClient {
     }
 
     fn get_block_by_hash(&self, block_hash: H256) -> Result<Option<Block>> {
-        Ok(self.client.block(BlockId::Hash(block_hash)).map(|block| block.decode().into()))
+        self.block(BlockId::Hash(block_hash))
+    }
+
+    fn get_block_by_number(&self, block_number: u64) -> Result<Option<Block>> {
+        self.block(BlockId::Number(block_number))
+    }
+
+    fn get_best_block(&self) -> Result<Block> {
+        Ok(self.block(BlockId::Latest)?.expect("The best block always exists"))
+    }
+
+    fn get_finalized_block(&self) -> Result<Block> {
+        let hash = self.client.chain_info().finalized_block_hash;
+        Ok(self.block(BlockId::Hash(hash))?.expect("The finalized block always exists"))
+    }
+
+    fn get_raw_block_by_hash(&self, block_hash: H256) -> Result<Option<Bytes>> {
+        self.refuse_on_light_node("chain_getRawBlockByHash")?;
+        Ok(self.client.block(BlockId::Hash(block_hash)).map(|block| block.into_inner().into()))
+    }
+
+    fn get_raw_block_by_number(&self, block_number: u64) -> Result<Option<Bytes>> {
+        self.refuse_on_light_node("chain_getRawBlockByNumber")?;
+        Ok(self.client.block(BlockId::Number(block_number)).map(|block| block.into_inner().into()))
     }
 
     fn get_pending_parcels(&self) -> Result<Vec<Parcel>> {
-        Ok(self.client.ready_parcels().into_iter().map(|signed| signed.into()).collect())
+        Ok(self.queue.ready_parcels().into_iter().map(|signed| signed.into()).collect())
+    }
+}
+
+enum ChainEvent {
+    Reorg(Reorg),
+    Finalized(FinalizedBlock),
+}
+
+// Passes the events to another thread not to block the importer on the sessions
+struct ChainNotifier {
+    sender: Mutex<mpsc::SyncSender<ChainEvent>>,
+}
+
+impl ChainNotifier {
+    fn send(&self, event: ChainEvent) {
+        if let Err(TrySendError::Full(_)) = self.sender.lock().try_send(event) {
+            warn!("The chain subscriptions are behind, dropping an event");
+        }
+    }
+}
+
+impl ChainNotify for ChainNotifier {
+    fn new_blocks(
+        &self,
+        _imported: Vec<H256>,
+        _invalid: Vec<H256>,
+        enacted: Vec<H256>,
+        retracted: Vec<H256>,
+        _sealed: Vec<H256>,
+        _duration: u64,
+    ) {
+        if retracted.is_empty() {
+            return
+        }
+        self.send(ChainEvent::Reorg(Reorg {
+            enacted,
+            retracted,
+        }));
+    }
+
+    fn finalized(&self, hash: H256, number: BlockNumber) {
+        self.send(ChainEvent::Finalized(FinalizedBlock {
+            hash,
+            number,
+        }));
+    }
+}
+
+pub struct ChainPubSubClient {
+    reorg_subscribers: Subscribers<Reorg>,
+    finality_subscribers: Subscribers<FinalizedBlock>,
+    // The client only keeps a weak reference to the notifier
+    _notifier: Arc<ChainNotifier>,
+}
+
+impl ChainPubSubClient {
+    /// Spawns the thread which sends the reorgs and the finalized blocks to the subscribers.
+    pub fn new(client: &Arc<Client>) -> Self {
+        let reorg_subscribers = Subscribers::new();
+        let finality_subscribers = Subscribers::new();
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_CHAIN_EVENTS);
+        let notifier = Arc::new(ChainNotifier {
+            sender: Mutex::new(sender),
+        });
+        client.add_notify(notifier.clone());
+        let reorgs = reorg_subscribers.clone();
+        let finalities = finality_subscribers.clone();
+        spawn_notifier("rpc.chain", receiver, move |event| match event {
+            ChainEvent::Reorg(reorg) => reorgs.notify(&reorg),
+            ChainEvent::Finalized(block) => finalities.notify(&block),
+        });
+        Self {
+            reorg_subscribers,
+            finality_subscribers,
+            _notifier: notifier,
+        }
+    }
+}
+
+impl ChainPubSub for ChainPubSubClient {
+    type Metadata = Metadata;
+
+    fn subscribe(&self, meta: Metadata, subscriber: Subscriber<Reorg>, kind: String) {
+        if kind != "reorgs" {
+            let _ = subscriber.reject(Error::invalid_params(format!("Cannot subscribe to {}", kind)));
+            return
+        }
+        self.reorg_subscribers.add(&meta, subscriber);
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        Ok(self.reorg_subscribers.remove(&id))
+    }
+
+    fn subscribe_finality(&self, meta: Metadata, subscriber: Subscriber<FinalizedBlock>) {
+        self.finality_subscribers.add(&meta, subscriber);
+    }
+
+    fn unsubscribe_finality(&self, id: SubscriptionId) -> Result<bool> {
+        Ok(self.finality_subscribers.remove(&id))
     }
 }
@@ -0,0 +1,58 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use cnetwork::NetworkService;
+use jsonrpc_core::Result;
+
+use super::super::errors;
+use super::super::traits::Debug;
+use super::super::types::{ConnectionState, NetworkDump, SessionTableSizes, TokenOccupancy};
+
+pub struct DebugClient {
+    network_service: Arc<NetworkService>,
+}
+
+impl DebugClient {
+    pub fn new(network_service: &Arc<NetworkService>) -> Self {
+        Self {
+            network_service: network_service.clone(),
+        }
+    }
+}
+
+impl Debug for DebugClient {
+    fn dump_network(&self) -> Result<NetworkDump> {
+        let dump = self.network_service.dump().map_err(errors::network)?;
+        Ok(dump.into())
+    }
+
+    fn get_connection_table(&self) -> Result<Vec<ConnectionState>> {
+        let dump = self.network_service.dump().map_err(errors::network)?;
+        Ok(dump.connections.into_iter().map(ConnectionState::from).collect())
+    }
+
+    fn get_token_occupancy(&self) -> Result<TokenOccupancy> {
+        let dump = self.network_service.dump().map_err(errors::network)?;
+        Ok(TokenOccupancy::from(&dump))
+    }
+
+    fn get_session_table_sizes(&self) -> Result<SessionTableSizes> {
+        let dump = self.network_service.dump().map_err(errors::network)?;
+        Ok(dump.routing_table.into())
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use cdiscovery::KademliaExtension;
+use jsonrpc_core::Result;
+
+use super::super::traits::Discovery;
+use super::super::types::{BucketOccupancy, KademliaLookup, KademliaTable};
+
+pub struct DiscoveryClient {
+    kademlia: Arc<KademliaExtension>,
+}
+
+impl DiscoveryClient {
+    pub fn new(kademlia: &Arc<KademliaExtension>) -> Self {
+        Self {
+            kademlia: kademlia.clone(),
+        }
+    }
+}
+
+impl Discovery for DiscoveryClient {
+    fn get_routing_tables(&self) -> Result<Vec<KademliaTable>> {
+        Ok(self.kademlia.routing_tables().into_iter().map(KademliaTable::from).collect())
+    }
+
+    fn get_bucket_occupancy(&self) -> Result<Vec<BucketOccupancy>> {
+        Ok(self.kademlia.routing_tables().iter().flat_map(BucketOccupancy::of_table).collect())
+    }
+
+    fn get_recent_lookups(&self) -> Result<Vec<KademliaLookup>> {
+        Ok(self.kademlia.recent_lookups().into_iter().map(KademliaLookup::from).collect())
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use ccore::{Client, EngineClient, Miner as CoreMiner, MinerService};
+use ctypes::H160;
+use jsonrpc_core::Result;
+
+use super::super::traits::Miner;
+
+pub struct MinerClient {
+    client: Arc<Client>,
+    miner: Arc<CoreMiner>,
+}
+
+impl MinerClient {
+    pub fn new(client: &Arc<Client>, miner: &Arc<CoreMiner>) -> Self {
+        Self {
+            client: client.clone(),
+            miner: miner.clone(),
+        }
+    }
+}
+
+impl Miner for MinerClient {
+    fn start_authoring(&self) -> Result<()> {
+        self.miner.set_authoring(true);
+        self.client.update_sealing();
+        Ok(())
+    }
+
+    fn stop_authoring(&self) -> Result<()> {
+        self.miner.set_authoring(false);
+        Ok(())
+    }
+
+    fn is_authoring(&self) -> Result<bool> {
+        Ok(self.miner.is_authoring())
+    }
+
+    fn get_author(&self) -> Result<H160> {
+        Ok(self.miner.author())
+    }
+
+    fn set_author(&self, author: H160) -> Result<()> {
+        self.miner.set_author(author);
+        Ok(())
+    }
+}
@@ -14,8 +14,21 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod account;
+mod block_sync;
 mod chain;
+mod debug;
 mod devel;
+mod discovery;
+mod miner;
+mod net;
+mod subscribers;
 
-pub use self::chain::ChainClient;
+pub use self::account::AccountClient;
+pub use self::block_sync::BlockSyncClient;
+pub use self::chain::{ChainClient, ChainPubSubClient};
+pub use self::debug::DebugClient;
 pub use self::devel::DevelClient;
+pub use self::discovery::DiscoveryClient;
+pub use self::miner::MinerClient;
+pub use self::net::{NetClient, NetPubSubClient};
@@ -0,0 +1,135 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use cnetwork::{NetworkService, NodeId, PeerNotification, SocketAddr};
+use csync::BlockSyncExtension;
+use jsonrpc_core::{Error, Result};
+use jsonrpc_macros::pubsub::Subscriber;
+use jsonrpc_pubsub::SubscriptionId;
+
+use super::super::super::auth::Metadata;
+use super::super::errors;
+use super::super::traits::{Net, NetAdmin, NetPubSub};
+use super::super::types::{Peer, PeerConnection, PeerEvent};
+use super::subscribers::{spawn_notifier, Subscribers};
+
+pub struct NetClient {
+    network_service: Arc<NetworkService>,
+    block_sync: Option<Arc<BlockSyncExtension>>,
+}
+
+impl NetClient {
+    pub fn new(network_service: &Arc<NetworkService>, block_sync: &Option<Arc<BlockSyncExtension>>) -> Self {
+        Self {
+            network_service: network_service.clone(),
+            block_sync: block_sync.clone(),
+        }
+    }
+}
+
+impl Net for NetClient {
+    fn get_external_address(&self) -> Result<Option<String>> {
+        Ok(self.network_service.external_address().map(|address| address.to_string()))
+    }
+
+    fn get_peers(&self) -> Result<Vec<Peer>> {
+        let peers = self.network_service.peers().map_err(errors::network)?;
+        let heads = self.block_sync.as_ref().map(|block_sync| block_sync.peer_heads()).unwrap_or_default();
+        Ok(peers
+            .into_iter()
+            .map(|peer| {
+                let head = heads.get(&peer.node_id).cloned();
+                Peer::new(peer, head)
+            })
+            .collect())
+    }
+
+    fn peers(&self) -> Result<Vec<PeerConnection>> {
+        let connections = self.network_service.connections().map_err(errors::network)?;
+        Ok(connections.into_iter().map(PeerConnection::from).collect())
+    }
+
+    fn get_peer_count(&self) -> Result<usize> {
+        let status = self.network_service.node_status().map_err(errors::network)?;
+        Ok(status.peer_count)
+    }
+
+    fn get_listening_address(&self) -> Result<String> {
+        let status = self.network_service.node_status().map_err(errors::network)?;
+        Ok(status.listening_address.to_string())
+    }
+
+    fn get_version(&self) -> Result<String> {
+        let status = self.network_service.node_status().map_err(errors::network)?;
+        Ok(status.user_agent)
+    }
+}
+
+impl NetAdmin for NetClient {
+    fn add_peer(&self, address: String) -> Result<()> {
+        let address = SocketAddr::from_str(&address).map_err(|_| Error::invalid_params("Invalid address"))?;
+        self.network_service.connect_to(address).map_err(errors::network)
+    }
+
+    fn remove_peer(&self, peer: String) -> Result<bool> {
+        let removed = if let Ok(address) = SocketAddr::from_str(&peer) {
+            self.network_service.remove_peer_at(address)
+        } else if let Ok(node_id) = NodeId::from_str(peer.trim_left_matches("0x")) {
+            self.network_service.remove_peer(node_id)
+        } else {
+            return Err(Error::invalid_params("Invalid address or node id"))
+        };
+        removed.map_err(errors::network)
+    }
+}
+
+pub struct NetPubSubClient {
+    peer_subscribers: Subscribers<PeerEvent>,
+}
+
+impl NetPubSubClient {
+    /// Spawns the thread which sends the changes of the peers to the subscribers.
+    pub fn new(network_service: &Arc<NetworkService>) -> Self {
+        let peer_subscribers = Subscribers::new();
+        let receiver = network_service.subscribe_peers();
+        let subscribers = peer_subscribers.clone();
+        spawn_notifier("rpc.peers", receiver, move |notification: PeerNotification| {
+            subscribers.notify(&PeerEvent::from(notification))
+        });
+        Self {
+            peer_subscribers,
+        }
+    }
+}
+
+impl NetPubSub for NetPubSubClient {
+    type Metadata = Metadata;
+
+    fn subscribe(&self, meta: Metadata, subscriber: Subscriber<PeerEvent>, kind: String) {
+        if kind != "peers" {
+            let _ = subscriber.reject(Error::invalid_params(format!("Cannot subscribe to {}", kind)));
+            return
+        }
+        self.peer_subscribers.add(&meta, subscriber);
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        Ok(self.peer_subscribers.remove(&id))
+    }
+}
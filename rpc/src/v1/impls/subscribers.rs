@@ -0,0 +1,126 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+
+use jsonrpc_core::futures::{executor, Async};
+use jsonrpc_core::Error;
+use jsonrpc_macros::pubsub::{Sink, Subscriber};
+use jsonrpc_pubsub::SubscriptionId;
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+
+use super::super::super::auth::Metadata;
+
+/// The subscriptions to one kind of the notifications.
+///
+/// The ids are random, so that a session cannot guess the subscriptions of the others,
+/// and the subscriptions are dropped with their sessions.
+pub struct Subscribers<T> {
+    sinks: Arc<Mutex<HashMap<SubscriptionId, Sink<T>>>>,
+}
+
+impl<T> Clone for Subscribers<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sinks: Arc::clone(&self.sinks),
+        }
+    }
+}
+
+impl<T: Serialize + Clone + Send + 'static> Subscribers<T> {
+    pub fn new() -> Self {
+        Self {
+            sinks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn add(&self, meta: &Metadata, subscriber: Subscriber<T>) {
+        let session = match &meta.session {
+            Some(session) => session,
+            None => {
+                let _ = subscriber.reject(Error::invalid_request());
+                return
+            }
+        };
+        let id = SubscriptionId::String(format!("0x{:016x}", thread_rng().gen::<u64>()));
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.sinks.lock().insert(id.clone(), sink);
+            let sinks = Arc::clone(&self.sinks);
+            session.on_drop(move || {
+                sinks.lock().remove(&id);
+            });
+        }
+    }
+
+    pub fn remove(&self, id: &SubscriptionId) -> bool {
+        self.sinks.lock().remove(id).is_some()
+    }
+
+    /// Sends the item without holding the lock or waiting for the clients.
+    /// The clients whose queues are full miss the item.
+    pub fn notify(&self, item: &T) {
+        let sinks: Vec<(SubscriptionId, Sink<T>)> =
+            self.sinks.lock().iter().map(|(id, sink)| (id.clone(), sink.clone())).collect();
+        let closed: Vec<SubscriptionId> =
+            sinks.into_iter().filter(|(_, sink)| !send(sink, item)).map(|(id, _)| id).collect();
+        if closed.is_empty() {
+            return
+        }
+        let mut sinks = self.sinks.lock();
+        for id in closed {
+            sinks.remove(&id);
+        }
+    }
+}
+
+struct NoopNotify;
+
+impl executor::Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+// Returns false if the session is closed
+fn send<T: Serialize + Clone>(sink: &Sink<T>, item: &T) -> bool {
+    let notify = Arc::new(NoopNotify);
+    match executor::spawn(sink.notify(Ok(item.clone()))).poll_future_notify(&notify, 0) {
+        Ok(Async::Ready(_)) => true,
+        Ok(Async::NotReady) => {
+            debug!("Dropping a notification to a slow client");
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Spawns the thread which passes the events from the receiver to `handle`, until the senders are dropped.
+pub fn spawn_notifier<E, F>(name: &str, receiver: Receiver<E>, mut handle: F)
+where
+    E: Send + 'static,
+    F: FnMut(E) + Send + 'static, {
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            for event in receiver {
+                handle(event);
+            }
+        })
+        .expect("Cannot spawn the thread for the subscriptions");
+}
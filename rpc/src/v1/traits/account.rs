@@ -0,0 +1,45 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use ctypes::{H160, H256, H520};
+
+use jsonrpc_core::Result;
+
+build_rpc_trait! {
+    pub trait Account {
+        /// Gets the addresses of the accounts in the keystore.
+        # [rpc(name = "account_list")]
+        fn list(&self) -> Result<Vec<H160>>;
+
+        /// Creates an account encrypted with the password, which must not be empty.
+        # [rpc(name = "account_create")]
+        fn create(&self, String) -> Result<H160>;
+
+        /// Imports the raw private key and encrypts it with the password.
+        # [rpc(name = "account_importRaw")]
+        fn import_raw(&self, H256, String) -> Result<H160>;
+
+        /// Lets the account sign without the password for the seconds, or until the node exits if they're null.
+        # [rpc(name = "account_unlock")]
+        fn unlock(&self, H160, String, Option<u64>) -> Result<()>;
+
+        /// Signs the message hash prefixed with "\x19CodeChain Signed Message:\n", so the signature is never the one of
+        /// a parcel. The password can be null if the account is unlocked. The accounts of the node don't sign.
+        # [rpc(name = "account_sign")]
+        fn sign(&self, H256, H160, Option<String>) -> Result<H520>;
+    }
+}
@@ -14,12 +14,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::{Asset, AssetScheme, Invoice};
+use ccore::{Asset, AssetScheme, Invoice, LocalizedLogEntry, ShardId, Transaction};
 use ctypes::{H160, H256, Public, U256};
 
 use jsonrpc_core::Result;
+use jsonrpc_macros::pubsub::Subscriber;
+use jsonrpc_pubsub::SubscriptionId;
 
-use super::super::types::{Block, Bytes, Parcel};
+use super::super::types::{Block, Bytes, Filter, FinalizedBlock, Parcel, Reorg};
 
 build_rpc_trait! {
     pub trait Chain {
@@ -31,6 +33,10 @@ build_rpc_trait! {
         # [rpc(name = "chain_getParcel")]
         fn get_parcel(&self, H256) -> Result<Option<Parcel>>;
 
+        /// Gets transaction with given hash.
+        # [rpc(name = "chain_getTransaction")]
+        fn get_transaction(&self, H256) -> Result<Option<Transaction>>;
+
         /// Gets parcel invoices with given hash.
         # [rpc(name = "chain_getParcelInvoices")]
         fn get_parcel_invoices(&self, H256) -> Result<Option<Vec<Invoice>>>;
@@ -39,13 +45,22 @@ build_rpc_trait! {
         # [rpc(name = "chain_getTransactionInvoice")]
         fn get_transaction_invoice(&self, H256) -> Result<Option<Invoice>>;
 
-        /// Gets asset scheme with given asset type.
+        /// Gets the logs which the transactions in the range of the blocks emitted. The blocks whose invoices are
+        /// pruned are skipped.
+        # [rpc(name = "chain_getLogs")]
+        fn get_logs(&self, Filter) -> Result<Vec<LocalizedLogEntry>>;
+
+        /// Gets asset scheme with given asset type. The shard is 0 if not given.
         # [rpc(name = "chain_getAssetScheme")]
-        fn get_asset_scheme(&self, H256) -> Result<Option<AssetScheme>>;
+        fn get_asset_scheme(&self, H256, Option<ShardId>) -> Result<Option<AssetScheme>>;
 
-        /// Gets asset with given asset type.
+        /// Gets asset with given asset type. The shard is 0 if not given.
         # [rpc(name = "chain_getAsset")]
-        fn get_asset(&self, H256, usize) -> Result<Option<Asset>>;
+        fn get_asset(&self, H256, usize, Option<ShardId>) -> Result<Option<Asset>>;
+
+        /// Gets the proof of the cross-shard message which the output of the transfer in the shard sent.
+        # [rpc(name = "chain_getMessageProof")]
+        fn get_message_proof(&self, H256, usize, ShardId) -> Result<Option<Vec<Bytes>>>;
 
         /// Gets nonce with given account.
         # [rpc(name = "chain_getNonce")]
@@ -71,8 +86,57 @@ build_rpc_trait! {
         # [rpc(name = "chain_getBlockByHash")]
         fn get_block_by_hash(&self, H256) -> Result<Option<Block>>;
 
+        /// Gets block with given number.
+        # [rpc(name = "chain_getBlockByNumber")]
+        fn get_block_by_number(&self, u64) -> Result<Option<Block>>;
+
+        /// Gets the best block.
+        # [rpc(name = "chain_getBestBlock")]
+        fn get_best_block(&self) -> Result<Block>;
+
+        /// Gets the latest block which can never be retracted. It's the genesis block unless the engine finalizes
+        /// blocks.
+        # [rpc(name = "chain_getFinalizedBlock")]
+        fn get_finalized_block(&self) -> Result<Block>;
+
+        /// Gets the RLP encoded block with given hash.
+        # [rpc(name = "chain_getRawBlockByHash")]
+        fn get_raw_block_by_hash(&self, H256) -> Result<Option<Bytes>>;
+
+        /// Gets the RLP encoded block with given number.
+        # [rpc(name = "chain_getRawBlockByNumber")]
+        fn get_raw_block_by_number(&self, u64) -> Result<Option<Bytes>>;
+
         /// Gets parcels in the current parcel queue.
         # [rpc(name = "chain_getPendingParcels")]
         fn get_pending_parcels(&self) -> Result<Vec<Parcel>>;
     }
 }
+
+build_rpc_trait! {
+    /// Notifies the subscribers of the changes of the canonical chain. It's served on the WebSocket.
+    pub trait ChainPubSub {
+        type Metadata;
+
+        # [pubsub(name = "chain_subscription")] {
+            /// Subscribes to the kind of the events. "reorgs" notifies the blocks which are retracted and enacted
+            /// when the best block moves to another branch.
+            # [rpc(name = "chain_subscribe")]
+            fn subscribe(&self, Self::Metadata, Subscriber<Reorg>, String);
+
+            /// Cancels the subscription, and returns whether it existed.
+            # [rpc(name = "chain_unsubscribe")]
+            fn unsubscribe(&self, SubscriptionId) -> Result<bool>;
+        }
+
+        # [pubsub(name = "chain_finality")] {
+            /// Subscribes to the advancement of the finalized block.
+            # [rpc(name = "chain_subscribeFinality")]
+            fn subscribe_finality(&self, Self::Metadata, Subscriber<FinalizedBlock>);
+
+            /// Cancels the subscription to the finality, and returns whether it existed.
+            # [rpc(name = "chain_unsubscribeFinality")]
+            fn unsubscribe_finality(&self, SubscriptionId) -> Result<bool>;
+        }
+    }
+}
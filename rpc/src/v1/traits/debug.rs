@@ -0,0 +1,40 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use jsonrpc_core::Result;
+
+use super::super::types::{ConnectionState, NetworkDump, SessionTableSizes, TokenOccupancy};
+
+build_rpc_trait! {
+    /// Dumps the internal state of the network to diagnose a stuck node. It's only served to the local tools.
+    pub trait Debug {
+        /// Dumps everything below, with the registered extensions and the peers being introduced.
+        # [rpc(name = "debug_dumpNetwork")]
+        fn dump_network(&self) -> Result<NetworkDump>;
+
+        /// Gets every connection with its handshake state, its send queue and its unanswered negotiations.
+        # [rpc(name = "debug_getConnectionTable")]
+        fn get_connection_table(&self) -> Result<Vec<ConnectionState>>;
+
+        /// Gets the number of the connection tokens in use and the limit.
+        # [rpc(name = "debug_getTokenOccupancy")]
+        fn get_token_occupancy(&self) -> Result<TokenOccupancy>;
+
+        /// Gets the number of the addresses in each step of making a session.
+        # [rpc(name = "debug_getSessionTableSizes")]
+        fn get_session_table_sizes(&self) -> Result<SessionTableSizes>;
+    }
+}
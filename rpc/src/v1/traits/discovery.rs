@@ -0,0 +1,35 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use jsonrpc_core::Result;
+
+use super::super::types::{BucketOccupancy, KademliaLookup, KademliaTable};
+
+build_rpc_trait! {
+    pub trait Discovery {
+        /// Gets the contacts in the kademlia routing tables.
+        # [rpc(name = "discovery_getRoutingTables")]
+        fn get_routing_tables(&self) -> Result<Vec<KademliaTable>>;
+
+        /// Gets how full the buckets of the kademlia routing tables are.
+        # [rpc(name = "discovery_getBucketOccupancy")]
+        fn get_bucket_occupancy(&self) -> Result<Vec<BucketOccupancy>>;
+
+        /// Gets the recent answers of the peers which were asked for the nodes.
+        # [rpc(name = "discovery_getRecentLookups")]
+        fn get_recent_lookups(&self) -> Result<Vec<KademliaLookup>>;
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ctypes::H160;
+
+use jsonrpc_core::Result;
+
+build_rpc_trait! {
+    pub trait Miner {
+        /// Starts authoring the blocks, and seals a block if there are pending parcels.
+        # [rpc(name = "miner_startAuthoring")]
+        fn start_authoring(&self) -> Result<()>;
+
+        /// Stops authoring the blocks. The blocks from the network are still imported.
+        # [rpc(name = "miner_stopAuthoring")]
+        fn stop_authoring(&self) -> Result<()>;
+
+        /// Gets whether the blocks are authored.
+        # [rpc(name = "miner_isAuthoring")]
+        fn is_authoring(&self) -> Result<bool>;
+
+        /// Gets the address which the authored blocks are rewarded to.
+        # [rpc(name = "miner_getAuthor")]
+        fn get_author(&self) -> Result<H160>;
+
+        /// Sets the address which the authored blocks are rewarded to.
+        # [rpc(name = "miner_setAuthor")]
+        fn set_author(&self, H160) -> Result<()>;
+    }
+}
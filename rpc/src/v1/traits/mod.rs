@@ -14,8 +14,20 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod account;
+mod block_sync;
 mod chain;
+mod debug;
 mod devel;
+mod discovery;
+mod miner;
+mod net;
 
-pub use self::chain::Chain;
+pub use self::account::Account;
+pub use self::block_sync::BlockSync;
+pub use self::chain::{Chain, ChainPubSub};
+pub use self::debug::Debug;
 pub use self::devel::Devel;
+pub use self::discovery::Discovery;
+pub use self::miner::Miner;
+pub use self::net::{Net, NetAdmin, NetPubSub};
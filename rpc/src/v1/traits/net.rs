@@ -0,0 +1,79 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use jsonrpc_core::Result;
+use jsonrpc_macros::pubsub::Subscriber;
+use jsonrpc_pubsub::SubscriptionId;
+
+use super::super::types::{Peer, PeerConnection, PeerEvent};
+
+build_rpc_trait! {
+    pub trait Net {
+        /// Gets the public address of this node which the connected peers agree on.
+        # [rpc(name = "net_getExternalAddress")]
+        fn get_external_address(&self) -> Result<Option<String>>;
+
+        /// Gets the connected peers with the software versions which they run and their advertised chain heads.
+        # [rpc(name = "net_getPeers")]
+        fn get_peers(&self) -> Result<Vec<Peer>>;
+
+        /// Gets every connection with its traffic, its age and the negotiated extensions.
+        # [rpc(name = "net_peers")]
+        fn peers(&self) -> Result<Vec<PeerConnection>>;
+
+        /// Gets the number of the established peers.
+        # [rpc(name = "net_getPeerCount")]
+        fn get_peer_count(&self) -> Result<usize>;
+
+        /// Gets the address on which this node accepts the peers.
+        # [rpc(name = "net_getListeningAddress")]
+        fn get_listening_address(&self) -> Result<String>;
+
+        /// Gets the name, the version and the OS of the software which this node runs.
+        # [rpc(name = "net_getVersion")]
+        fn get_version(&self) -> Result<String>;
+    }
+}
+
+build_rpc_trait! {
+    /// Changes the connectivity of this node. It's only served to the local tools.
+    pub trait NetAdmin {
+        /// Connects to the address and establishes the session.
+        # [rpc(name = "net_addPeer")]
+        fn add_peer(&self, String) -> Result<()>;
+
+        /// Disconnects the peer given by its listening address or its node id, and returns whether it was connected.
+        # [rpc(name = "net_removePeer")]
+        fn remove_peer(&self, String) -> Result<bool>;
+    }
+}
+
+build_rpc_trait! {
+    /// Notifies the subscribers of the changes of this node's network. It's served on the WebSocket.
+    pub trait NetPubSub {
+        type Metadata;
+
+        # [pubsub(name = "net_subscription")] {
+            /// Subscribes to the kind of the events. "peers" notifies the peers which are connected or disconnected.
+            # [rpc(name = "net_subscribe")]
+            fn subscribe(&self, Self::Metadata, Subscriber<PeerEvent>, String);
+
+            /// Cancels the subscription, and returns whether it existed.
+            # [rpc(name = "net_unsubscribe")]
+            fn unsubscribe(&self, SubscriptionId) -> Result<bool>;
+        }
+    }
+}
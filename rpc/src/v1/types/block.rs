@@ -15,9 +15,9 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use ccore::Block as CoreBlock;
-use ctypes::{H160, H256, U256};
+use ctypes::{Bloom, H160, H256, U256};
 
-use super::Parcel;
+use super::{Bytes, Parcel};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -27,14 +27,16 @@ pub struct Block {
     number: u64,
     author: H160,
 
-    extra_data: Vec<u8>,
+    extra_data: Bytes,
 
     parcels_root: H256,
     state_root: H256,
     invoices_root: H256,
+    shard_roots: Vec<H256>,
+    log_bloom: Bloom,
 
     score: U256,
-    seal: Vec<Vec<u8>>,
+    seal: Vec<Bytes>,
 
     hash: H256,
     parcels: Vec<Parcel>,
@@ -50,14 +52,16 @@ impl From<CoreBlock> for Block {
             number: block.header.number(),
             author: block.header.author().clone(),
 
-            extra_data: block.header.extra_data().clone(),
+            extra_data: block.header.extra_data().clone().into(),
 
             parcels_root: block.header.parcels_root().clone(),
             state_root: block.header.state_root().clone(),
             invoices_root: block.header.invoices_root().clone(),
+            shard_roots: block.header.shard_roots().to_vec(),
+            log_bloom: *block.header.log_bloom(),
 
             score: block.header.score().clone(),
-            seal: block.header.seal().clone().to_vec(),
+            seal: block.header.seal().iter().cloned().map(Into::into).collect(),
 
             hash: block.header.hash(),
             parcels: block
@@ -0,0 +1,133 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use cnetwork::{ConnectionDump, HandlerDump, PeerState, RoutingTableSizes};
+use ctypes::H256;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionState {
+    token: usize,
+    /// Null until the session is ready
+    node_id: Option<H256>,
+    /// "connecting", "awaitingSync", "established" or "closing"
+    state: &'static str,
+    /// The messages waiting to be sent
+    send_queue: usize,
+    /// The extensions which this node asked the peer to negotiate and which are not answered yet
+    requested_negotiations: Vec<String>,
+    /// The negotiated extensions and their versions
+    extensions: BTreeMap<String, u64>,
+    /// Seconds since the last extension message
+    idle: u64,
+}
+
+impl From<ConnectionDump> for ConnectionState {
+    fn from(dump: ConnectionDump) -> Self {
+        ConnectionState {
+            token: dump.token,
+            node_id: dump.node_id,
+            state: match dump.state {
+                PeerState::Connecting => "connecting",
+                PeerState::AwaitingSync => "awaitingSync",
+                PeerState::Established => "established",
+                PeerState::Closing => "closing",
+            },
+            send_queue: dump.send_queue,
+            requested_negotiations: dump.requested_negotiations,
+            extensions: dump.extensions,
+            idle: dump.idle.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenOccupancy {
+    assigned: usize,
+    limit: usize,
+}
+
+/// The number of the addresses in each step of making a session
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTableSizes {
+    candidates: usize,
+    uninitialized: usize,
+    key_pairs: usize,
+    shared_secrets: usize,
+    temporary_nonces: usize,
+    unestablished_sessions: usize,
+    established: usize,
+    /// The addresses which are forgotten if their sessions are not established in time
+    pending: usize,
+}
+
+impl From<RoutingTableSizes> for SessionTableSizes {
+    fn from(sizes: RoutingTableSizes) -> Self {
+        SessionTableSizes {
+            candidates: sizes.candidates,
+            uninitialized: sizes.uninitialized,
+            key_pairs: sizes.key_pairs,
+            shared_secrets: sizes.shared_secrets,
+            temporary_nonces: sizes.temporary_nonces,
+            unestablished_sessions: sizes.unestablished_sessions,
+            established: sizes.established,
+            pending: sizes.pending,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkDump {
+    connections: Vec<ConnectionState>,
+    /// The connections which are not established yet
+    pending_handshakes: usize,
+    tokens: TokenOccupancy,
+    sessions: SessionTableSizes,
+    /// The registered extensions
+    extensions: Vec<String>,
+    static_peers: usize,
+    requested_introductions: usize,
+    punched_addresses: usize,
+}
+
+impl From<HandlerDump> for NetworkDump {
+    fn from(dump: HandlerDump) -> Self {
+        NetworkDump {
+            connections: dump.connections.into_iter().map(ConnectionState::from).collect(),
+            pending_handshakes: dump.pending_handshakes,
+            tokens: TokenOccupancy::from(&dump),
+            sessions: dump.routing_table.into(),
+            extensions: dump.extensions,
+            static_peers: dump.static_peers,
+            requested_introductions: dump.requested_introductions,
+            punched_addresses: dump.punched_addresses,
+        }
+    }
+}
+
+impl<'a> From<&'a HandlerDump> for TokenOccupancy {
+    fn from(dump: &'a HandlerDump) -> Self {
+        TokenOccupancy {
+            assigned: dump.assigned_tokens,
+            limit: dump.token_limit,
+        }
+    }
+}
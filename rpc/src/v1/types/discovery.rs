@@ -0,0 +1,119 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use cdiscovery::{
+    KademliaBucket as CoreKademliaBucket, KademliaLookup as CoreKademliaLookup, KademliaTable as CoreKademliaTable,
+};
+use ctypes::H256;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KademliaContact {
+    node_id: H256,
+    address: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KademliaBucket {
+    /// The log2 distance of the contacts from the local node id
+    distance: usize,
+    capacity: u8,
+    contacts: Vec<KademliaContact>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KademliaTable {
+    /// The node id which the peers know this node by
+    local_id: H256,
+    buckets: Vec<KademliaBucket>,
+}
+
+impl From<CoreKademliaBucket> for KademliaBucket {
+    fn from(bucket: CoreKademliaBucket) -> Self {
+        KademliaBucket {
+            distance: bucket.distance,
+            capacity: bucket.capacity,
+            contacts: bucket
+                .contacts
+                .into_iter()
+                .map(|(node_id, address)| KademliaContact {
+                    node_id,
+                    address: address.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<CoreKademliaTable> for KademliaTable {
+    fn from(table: CoreKademliaTable) -> Self {
+        KademliaTable {
+            local_id: table.local_id,
+            buckets: table.buckets.into_iter().map(KademliaBucket::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketOccupancy {
+    local_id: H256,
+    distance: usize,
+    /// The number of the contacts, which can exceed the capacity while the oldest one is being verified
+    size: usize,
+    capacity: u8,
+}
+
+impl BucketOccupancy {
+    pub fn of_table(table: &CoreKademliaTable) -> Vec<Self> {
+        table
+            .buckets
+            .iter()
+            .map(|bucket| BucketOccupancy {
+                local_id: table.local_id,
+                distance: bucket.distance,
+                size: bucket.contacts.len(),
+                capacity: bucket.capacity,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KademliaLookup {
+    local_id: H256,
+    peer: String,
+    received: usize,
+    /// The received contacts which were not in the routing table
+    new_contacts: usize,
+    /// Seconds since the UNIX epoch
+    timestamp: u64,
+}
+
+impl From<CoreKademliaLookup> for KademliaLookup {
+    fn from(lookup: CoreKademliaLookup) -> Self {
+        KademliaLookup {
+            local_id: lookup.local_id,
+            peer: lookup.peer.to_string(),
+            received: lookup.received,
+            new_contacts: lookup.new_contacts,
+            timestamp: lookup.timestamp,
+        }
+    }
+}
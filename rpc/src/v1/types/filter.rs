@@ -0,0 +1,42 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::{BlockId, Filter as CoreFilter};
+use ctypes::H256;
+
+/// Selects the logs by their addresses and topics. The range is the latest block if it's not given.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Filter {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    #[serde(default)]
+    addresses: Vec<H256>,
+    /// `null` matches any topic at the position.
+    #[serde(default)]
+    topics: Vec<Option<H256>>,
+}
+
+impl From<Filter> for CoreFilter {
+    fn from(filter: Filter) -> Self {
+        CoreFilter {
+            from_block: filter.from_block.map(BlockId::Number).unwrap_or(BlockId::Latest),
+            to_block: filter.to_block.map(BlockId::Number).unwrap_or(BlockId::Latest),
+            addresses: filter.addresses,
+            topics: filter.topics,
+        }
+    }
+}
@@ -16,8 +16,22 @@
 
 mod block;
 mod bytes;
+mod debug;
+mod discovery;
+mod filter;
+mod finalized_block;
 mod parcel;
+mod peer;
+mod reorg;
+mod sync_status;
 
 pub use self::block::Block;
 pub use self::bytes::Bytes;
+pub use self::debug::{ConnectionState, NetworkDump, SessionTableSizes, TokenOccupancy};
+pub use self::discovery::{BucketOccupancy, KademliaLookup, KademliaTable};
+pub use self::filter::Filter;
+pub use self::finalized_block::FinalizedBlock;
 pub use self::parcel::Parcel;
+pub use self::peer::{Peer, PeerConnection, PeerEvent};
+pub use self::reorg::Reorg;
+pub use self::sync_status::SyncStatus;
@@ -0,0 +1,117 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use cnetwork::{ConnectionDirection, ConnectionInfo, PeerInfo, PeerNotification};
+use csync::PeerHead;
+use ctypes::{H256, U256};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    node_id: H256,
+    /// The listening address, null for the inbound peers
+    address: Option<String>,
+    /// The name, the version and the OS of the software, empty for the older peers
+    user_agent: String,
+    /// The best block which the peer advertised, null until the block sync receives its status
+    best_hash: Option<H256>,
+    total_score: Option<U256>,
+    /// Whether the best block of the peer is off the best chain of this node with a lower score
+    is_on_minority_fork: Option<bool>,
+}
+
+impl Peer {
+    pub fn new(peer: PeerInfo, head: Option<PeerHead>) -> Self {
+        Peer {
+            node_id: peer.node_id,
+            address: peer.address.map(|address| address.to_string()),
+            user_agent: peer.user_agent,
+            best_hash: head.map(|head| head.best_hash),
+            total_score: head.map(|head| head.total_score),
+            is_on_minority_fork: head.map(|head| head.is_on_minority_fork),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerConnection {
+    token: usize,
+    /// Null until the session is ready
+    node_id: Option<H256>,
+    address: Option<String>,
+    /// "inbound" or "outbound"
+    direction: &'static str,
+    /// Seconds since the connection was opened
+    age: u64,
+    /// The negotiated extensions and their versions
+    extensions: BTreeMap<String, u64>,
+    /// Null until the handshake is finished
+    user_agent: Option<String>,
+    bytes_received: u64,
+    bytes_sent: u64,
+    /// The round trip time in milliseconds, null if it's not measured yet
+    rtt: Option<u64>,
+}
+
+impl From<ConnectionInfo> for PeerConnection {
+    fn from(connection: ConnectionInfo) -> Self {
+        PeerConnection {
+            token: connection.token,
+            node_id: connection.node_id,
+            address: connection.remote_address.map(|address| address.to_string()),
+            direction: match connection.direction {
+                ConnectionDirection::Inbound => "inbound",
+                ConnectionDirection::Outbound => "outbound",
+            },
+            age: connection.age.as_secs(),
+            extensions: connection.extensions,
+            user_agent: connection.user_agent,
+            bytes_received: connection.bytes_received,
+            bytes_sent: connection.bytes_sent,
+            rtt: connection.rtt.map(|rtt| rtt.as_secs() * 1000 + u64::from(rtt.subsec_nanos() / 1_000_000)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerEvent {
+    /// "connected" or "disconnected"
+    event: &'static str,
+    node_id: H256,
+    /// Why the peer is disconnected, null if the connection is lost without the reason
+    reason: Option<String>,
+}
+
+impl From<PeerNotification> for PeerEvent {
+    fn from(notification: PeerNotification) -> Self {
+        match notification {
+            PeerNotification::Connected(node_id) => PeerEvent {
+                event: "connected",
+                node_id,
+                reason: None,
+            },
+            PeerNotification::Disconnected(node_id, reason) => PeerEvent {
+                event: "disconnected",
+                node_id,
+                reason: reason.map(|reason| reason.to_string()),
+            },
+        }
+    }
+}
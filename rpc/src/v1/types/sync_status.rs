@@ -0,0 +1,51 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use csync::SyncStatus as CoreSyncStatus;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// Whether a peer has a better chain
+    is_syncing: bool,
+    /// The best block when the current sync started
+    starting_block: u64,
+    /// The best block
+    current_block: u64,
+    /// The highest header imported from the peers
+    highest_block: u64,
+    /// The number of peers which have a better chain
+    peers: usize,
+    /// Bytes downloaded per second
+    bandwidth: u64,
+    /// Estimated seconds to catch up with the best peer, null if it cannot be estimated yet
+    eta: Option<u64>,
+}
+
+impl From<CoreSyncStatus> for SyncStatus {
+    fn from(status: CoreSyncStatus) -> Self {
+        SyncStatus {
+            is_syncing: status.is_syncing,
+            starting_block: status.starting_block,
+            current_block: status.current_block,
+            highest_block: status.highest_block,
+            peers: status.peers,
+            bandwidth: status.bandwidth,
+            eta: status.eta,
+        }
+    }
+}
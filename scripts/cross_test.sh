@@ -0,0 +1,16 @@
+#!/bin/bash
+
+# Runs the conformance tests on the targets whose word size or byte order differs from x86_64.
+# The digests are pinned in the tests, so a target which serializes differently fails.
+# Requires cross: cargo install cross
+
+TARGETS=${@:-"i686-unknown-linux-gnu armv7-unknown-linux-gnueabihf mips-unknown-linux-gnu powerpc64-unknown-linux-gnu"}
+
+BASE_DIR=$(cd "$(dirname "$0")"/.. && pwd)
+cd ${BASE_DIR}
+
+for TARGET in ${TARGETS}; do
+    echo "Testing on ${TARGET}..."
+    cross test --target ${TARGET} -p rlp || exit 1
+    cross test --target ${TARGET} -p codechain-core conformance || exit 1
+done
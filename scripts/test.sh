@@ -23,13 +23,14 @@ run_server() {
     if [ $1 -eq 0 ]; then
         BOOTSTRAP=""
     else
-        BOOTSTRAP="--bootstrap-addresses 127.0.0.1:${CODECHAIN_PORT_START}"
+        BOOTSTRAP="--bootnodes 127.0.0.1:${CODECHAIN_PORT_START}"
     fi
     cd ${BASE_DIR}
     cargo run -- \
         --db-path ${DB_DIR}/db$1 \
         --port $((${CODECHAIN_PORT_START} + $1)) \
         --jsonrpc-port $((${RPC_PORT_START} + $1)) \
+        --jsonrpc-no-auth \
         --secret-key "`printf "%064x" $(($1 + 1))`" \
         ${BOOTSTRAP} \
         ${OTHER_OPTIONS} \
@@ -0,0 +1,101 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+// A response slower than this shrinks the batch.
+const TARGET_LATENCY_MS: u64 = 2_000;
+
+/// The number of items requested at once from a peer.
+///
+/// It grows by `increment` after each timely response and is halved after a slow or failed one.
+#[derive(Clone, Debug)]
+pub struct BatchSize {
+    current: u64,
+    min: u64,
+    max: u64,
+    increment: u64,
+}
+
+impl BatchSize {
+    pub fn new(initial: u64, min: u64, max: u64) -> Self {
+        debug_assert!(min <= initial && initial <= max);
+        Self {
+            current: initial,
+            min,
+            max,
+            increment: ::std::cmp::max(1, min / 2),
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.current
+    }
+
+    pub fn on_success(&mut self, latency_ms: u64) {
+        if latency_ms > TARGET_LATENCY_MS {
+            self.decrease();
+        } else {
+            self.current = ::std::cmp::min(self.max, self.current + self.increment);
+        }
+    }
+
+    pub fn on_failure(&mut self) {
+        self.decrease();
+    }
+
+    fn decrease(&mut self) {
+        self.current = ::std::cmp::max(self.min, self.current / 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_additively_on_fast_responses() {
+        let mut batch = BatchSize::new(32, 16, 64);
+        batch.on_success(100);
+        assert_eq!(40, batch.get());
+        batch.on_success(100);
+        assert_eq!(48, batch.get());
+    }
+
+    #[test]
+    fn does_not_grow_over_max() {
+        let mut batch = BatchSize::new(60, 16, 64);
+        batch.on_success(100);
+        assert_eq!(64, batch.get());
+    }
+
+    #[test]
+    fn halves_on_slow_responses() {
+        let mut batch = BatchSize::new(64, 16, 128);
+        batch.on_success(TARGET_LATENCY_MS + 1);
+        assert_eq!(32, batch.get());
+    }
+
+    #[test]
+    fn halves_on_failures_down_to_min() {
+        let mut batch = BatchSize::new(64, 16, 128);
+        batch.on_failure();
+        assert_eq!(32, batch.get());
+        batch.on_failure();
+        assert_eq!(16, batch.get());
+        batch.on_failure();
+        assert_eq!(16, batch.get());
+    }
+}
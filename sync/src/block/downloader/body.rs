@@ -21,10 +21,14 @@ use ctypes::H256;
 
 use super::super::message::RequestMessage;
 
+// The body of a block is downloaded at most this many times more before the block is given up.
+const MAX_RETRIES: usize = 3;
+
 pub struct BodyDownloader {
     targets: Vec<(H256, H256, H256)>,
     downloading: HashSet<H256>,
     downloaded: HashMap<H256, Vec<UnverifiedParcel>>,
+    retries: HashMap<H256, usize>,
 }
 
 impl BodyDownloader {
@@ -33,12 +37,16 @@ impl BodyDownloader {
             targets,
             downloading: HashSet::new(),
             downloaded: HashMap::new(),
+            retries: HashMap::new(),
         }
     }
 
-    pub fn create_request(&mut self) -> Option<RequestMessage> {
+    pub fn create_request(&mut self, max_count: u64) -> Option<RequestMessage> {
         let mut hashes = Vec::new();
         for (hash, ..) in &self.targets {
+            if hashes.len() as u64 >= max_count {
+                break
+            }
             if !self.downloading.contains(hash) && !self.downloaded.contains_key(hash) {
                 hashes.push(*hash);
             }
@@ -51,19 +59,33 @@ impl BodyDownloader {
         }
     }
 
+    /// The bodies which the peer didn't send can be requested again.
     pub fn import_bodies(&mut self, hashes: Vec<H256>, bodies: Vec<Vec<UnverifiedParcel>>) {
-        for (hash, body) in hashes.into_iter().zip(bodies) {
-            if self.downloading.contains(&hash) {
-                if body.len() == 0 {
-                    let (_, prev_root, parcels_root) =
-                        self.targets.iter().find(|(h, ..)| *h == hash).expect("Downloading target must exist");
-                    if prev_root != parcels_root {
-                        continue
-                    }
+        let mut bodies = bodies.into_iter();
+        for hash in hashes {
+            let body = bodies.next();
+            if !self.downloading.remove(&hash) {
+                continue
+            }
+            let body = match body {
+                Some(body) => body,
+                None => continue,
+            };
+            if body.len() == 0 {
+                let (_, prev_root, parcels_root) =
+                    self.targets.iter().find(|(h, ..)| *h == hash).expect("Downloading target must exist");
+                if prev_root != parcels_root {
+                    continue
                 }
-                self.downloading.remove(&hash);
-                self.downloaded.insert(hash, body);
             }
+            self.downloaded.insert(hash, body);
+        }
+    }
+
+    /// Makes the bodies of the failed request available to the next requests.
+    pub fn release(&mut self, hashes: &[H256]) {
+        for hash in hashes {
+            self.downloading.remove(hash);
         }
     }
 
@@ -71,6 +93,33 @@ impl BodyDownloader {
         self.targets.extend(targets);
     }
 
+    /// Puts the drained targets whose bodies were rejected back in front of the others,
+    /// so that they are downloaded again, possibly from other peers.
+    /// The targets which were retried too many times are dropped.
+    pub fn retry(&mut self, targets: Vec<(H256, H256, H256)>) {
+        let mut kept = Vec::with_capacity(targets.len());
+        for target in targets {
+            let is_exhausted = {
+                let retries = self.retries.entry(target.0).or_insert(0);
+                *retries += 1;
+                *retries > MAX_RETRIES
+            };
+            if is_exhausted {
+                self.retries.remove(&target.0);
+            } else {
+                kept.push(target);
+            }
+        }
+        self.targets.splice(0..0, kept);
+    }
+
+    /// Forgets the retries of the drained targets which were imported.
+    pub fn mark_as_imported(&mut self, hashes: &[H256]) {
+        for hash in hashes {
+            self.retries.remove(hash);
+        }
+    }
+
     pub fn remove_target(&mut self, targets: Vec<H256>) {
         for hash in targets {
             if let Some(index) = self.targets.iter().position(|(h, ..)| *h == hash) {
@@ -78,17 +127,101 @@ impl BodyDownloader {
             }
             self.downloading.remove(&hash);
             self.downloaded.remove(&hash);
+            self.retries.remove(&hash);
+        }
+    }
+
+    /// Drops the targets for which `keep` returns false.
+    pub fn retain_target<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&H256) -> bool, {
+        let (kept, dropped): (Vec<_>, Vec<_>) = self.targets.drain(..).partition(|(hash, ..)| keep(hash));
+        self.targets = kept;
+        for (hash, ..) in dropped {
+            self.downloading.remove(&hash);
+            self.downloaded.remove(&hash);
+            self.retries.remove(&hash);
         }
     }
 
+    /// Takes the downloaded bodies in the order of the targets, up to the first one not downloaded yet.
     pub fn drain(&mut self) -> Vec<(H256, Vec<UnverifiedParcel>)> {
         let mut result = Vec::new();
         for (target, ..) in &self.targets {
-            if let Some(body) = self.downloaded.remove(target) {
-                result.push((*target, body));
+            match self.downloaded.remove(target) {
+                Some(body) => result.push((*target, body)),
+                None => break,
             }
         }
         self.targets.drain(0..result.len());
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downloader() -> BodyDownloader {
+        // The parcels root of the first block differs from its parent's, so its body can't be empty.
+        BodyDownloader::new(vec![
+            (H256::from(1), H256::from(10), H256::from(11)),
+            (H256::from(2), H256::from(11), H256::from(11)),
+        ])
+    }
+
+    #[test]
+    fn released_bodies_are_requested_again() {
+        let mut downloader = downloader();
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(1), H256::from(2)])), downloader.create_request(8));
+        assert_eq!(None, downloader.create_request(8));
+
+        downloader.release(&[H256::from(2)]);
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(2)])), downloader.create_request(8));
+    }
+
+    #[test]
+    fn missing_bodies_are_requested_again() {
+        let mut downloader = downloader();
+        downloader.create_request(8);
+        downloader.import_bodies(vec![H256::from(1), H256::from(2)], vec![Vec::new()]);
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(1), H256::from(2)])), downloader.create_request(8));
+
+        downloader.import_bodies(vec![H256::from(1), H256::from(2)], vec![Vec::new(), Vec::new()]);
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(1)])), downloader.create_request(8));
+        assert_eq!(Vec::<H256>::new(), downloader.drain().into_iter().map(|(hash, _)| hash).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejected_bodies_are_requested_again() {
+        let target = |hash: u64| (H256::from(hash), H256::from(11), H256::from(11));
+        let mut downloader = BodyDownloader::new(vec![target(1), target(2), target(3)]);
+        downloader.create_request(8);
+        downloader.import_bodies(vec![H256::from(1), H256::from(2)], vec![Vec::new(), Vec::new()]);
+        assert_eq!(2, downloader.drain().len());
+
+        downloader.retry(vec![target(1)]);
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(1)])), downloader.create_request(8));
+        downloader.import_bodies(vec![H256::from(1)], vec![Vec::new()]);
+        assert_eq!(vec![H256::from(1)], downloader.drain().into_iter().map(|(hash, _)| hash).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn body_rejected_too_many_times_is_dropped() {
+        let target = (H256::from(1), H256::from(11), H256::from(11));
+        let mut downloader = BodyDownloader::new(vec![target]);
+        for _ in 0..MAX_RETRIES {
+            downloader.create_request(8);
+            downloader.import_bodies(vec![H256::from(1)], vec![Vec::new()]);
+            assert_eq!(1, downloader.drain().len());
+            downloader.retry(vec![target]);
+        }
+        downloader.create_request(8);
+        downloader.import_bodies(vec![H256::from(1)], vec![Vec::new()]);
+        assert_eq!(1, downloader.drain().len());
+
+        downloader.retry(vec![target]);
+        assert_eq!(None, downloader.create_request(8));
+        assert!(downloader.retries.is_empty());
+    }
+}
@@ -23,8 +23,11 @@ use ccore::{BlockChainClient, BlockId};
 use ctypes::{H256, U256};
 
 use super::super::message::RequestMessage;
+use super::BatchSize;
 
-const MAX_HEADER_REQUEST_LENGTH: u64 = 128;
+const INITIAL_HEADER_REQUEST_LENGTH: u64 = 128;
+const MIN_HEADER_REQUEST_LENGTH: u64 = 16;
+const MAX_HEADER_REQUEST_LENGTH: u64 = 512;
 const MAX_RETRY: usize = 3;
 const MAX_WAIT: u64 = 15;
 
@@ -46,6 +49,7 @@ pub struct HeaderDownloader {
     request_time: Option<Instant>,
     downloaded: HashMap<H256, Header>,
     trial: usize,
+    batch_size: BatchSize,
 }
 
 impl HeaderDownloader {
@@ -53,6 +57,10 @@ impl HeaderDownloader {
         self.total_score
     }
 
+    pub fn best_hash(&self) -> H256 {
+        self.best_hash
+    }
+
     pub fn new(client: Arc<BlockChainClient>, total_score: U256, best_hash: H256) -> Self {
         let best_header_hash = client.best_block_header().hash();
         let best_score = client.block_total_score(BlockId::Latest).expect("Best block always exist");
@@ -70,6 +78,11 @@ impl HeaderDownloader {
             request_time: None,
             downloaded: HashMap::new(),
             trial: 0,
+            batch_size: BatchSize::new(
+                INITIAL_HEADER_REQUEST_LENGTH,
+                MIN_HEADER_REQUEST_LENGTH,
+                MAX_HEADER_REQUEST_LENGTH,
+            ),
         }
     }
 
@@ -82,7 +95,7 @@ impl HeaderDownloader {
         self.trial < MAX_RETRY
     }
 
-    fn is_expired(&self) -> bool {
+    pub fn is_expired(&self) -> bool {
         self.request_time.map_or(false, |time| (Instant::now() - time).as_secs() > MAX_WAIT)
     }
 
@@ -106,19 +119,27 @@ impl HeaderDownloader {
             return None
         }
 
+        if self.is_expired() {
+            self.batch_size.on_failure();
+        }
+
         let pivot_number = self.pivot_header().number();
 
         self.request_time = Some(Instant::now());
 
         Some(RequestMessage::Headers {
             start_number: pivot_number,
-            max_count: MAX_HEADER_REQUEST_LENGTH,
+            max_count: self.batch_size.get(),
         })
     }
 
     /// Imports headers and mark success
     /// Expects importing headers matches requested header
     pub fn import_headers(&mut self, headers: Vec<Header>) {
+        if let Some(request_time) = self.request_time {
+            let elapsed = Instant::now() - request_time;
+            self.batch_size.on_success(elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64);
+        }
         let first_header_hash = headers.first().expect("First header must exist").hash();
         if first_header_hash == self.pivot.hash {
             for header in headers.iter() {
@@ -143,6 +164,11 @@ impl HeaderDownloader {
         self.trial = 0;
     }
 
+    /// The peer sent a response which doesn't match the request.
+    pub fn mark_as_failed(&mut self) {
+        self.batch_size.on_failure();
+    }
+
     pub fn downloaded(&self) -> Vec<Header> {
         self.downloaded.values().cloned().collect()
     }
@@ -14,8 +14,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod batch;
 mod body;
 mod header;
 
+pub use self::batch::BatchSize;
 pub use self::body::BodyDownloader;
 pub use self::header::HeaderDownloader;
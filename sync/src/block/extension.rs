@@ -16,46 +16,96 @@
 
 use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use ccore::encoded::Header as EncodedHeader;
 use ccore::{
-    Block, BlockChainClient, BlockId, BlockImportError, BlockNumber, ChainNotify, Header, ImportError, Seal,
-    UnverifiedParcel,
+    Block, BlockChainClient, BlockError, BlockId, BlockImportError, BlockNumber, ChainNotify, Header, ImportError,
+    Seal, UnverifiedParcel,
 };
-use cnetwork::{Api, NetworkExtension, NodeId, TimerToken};
+use cnetwork::{Api, NetworkExtension, NodeId, PeerBehavior, TimerToken};
 use ctypes::{H256, U256};
 use rlp::{Encodable, UntrustedRlp};
 use time::Duration;
 
-use super::downloader::{BodyDownloader, HeaderDownloader};
+use super::downloader::{BatchSize, BodyDownloader, HeaderDownloader};
+use super::history::HistoryPolicy;
 use super::message::{Message, RequestMessage, ResponseMessage};
+use super::progress::{Progress, SyncStatus};
 
 const EXTENSION_NAME: &'static str = "block-propagation";
 const SYNC_TIMER_TOKEN: usize = 0;
 const SYNC_TIMER_INTERVAL: i64 = 1000;
+const ROTATION_TIMER_TOKEN: usize = 1;
+const ROTATION_TIMER_INTERVAL: i64 = 60 * 1000;
+const INFORMANT_TIMER_TOKEN: usize = 2;
+const INFORMANT_TIMER_INTERVAL: i64 = 10 * 1000;
+
+// The node is considered to be in the initial sync while its best block is older than this.
+const INITIAL_SYNC_THRESHOLD_SECONDS: u64 = 10 * 60;
+// Peers are rotated only if there are at least this many peers.
+const MIN_PEERS_TO_ROTATE: usize = 4;
+// 1/ROTATION_DIVISOR of the peers are replaced at each rotation.
+const ROTATION_DIVISOR: usize = 8;
 
 const SNAPSHOT_PERIOD: u64 = (1 << 14);
 
+// The body requests which are not answered in this time are sent to other peers.
+const MAX_BODY_REQUEST_WAIT_MS: u64 = 15 * 1000;
+
+const INITIAL_BODY_REQUEST_LENGTH: u64 = 32;
+const MIN_BODY_REQUEST_LENGTH: u64 = 8;
+const MAX_BODY_REQUEST_LENGTH: u64 = 256;
+
+/// The best block which a peer advertised in its latest status.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerHead {
+    pub best_hash: H256,
+    pub total_score: U256,
+    /// The peer's best block is known to this node, but it's off the best chain and has a lower score.
+    pub is_on_minority_fork: bool,
+}
+
 pub struct Extension {
-    requests: RwLock<HashMap<NodeId, Vec<(u64, RequestMessage)>>>,
+    /// The requests which are not answered yet, with the times when they were sent.
+    requests: RwLock<HashMap<NodeId, Vec<(u64, RequestMessage, Instant)>>>,
     header_downloaders: RwLock<HashMap<NodeId, HeaderDownloader>>,
     body_downloader: Mutex<BodyDownloader>,
+    body_batch_sizes: RwLock<HashMap<NodeId, BatchSize>>,
     client: Arc<BlockChainClient>,
     api: Mutex<Option<Arc<Api>>>,
     last_request: AtomicUsize,
+    latencies: RwLock<HashMap<NodeId, u64>>,
+    progress: Mutex<Progress>,
+    last_tick: Mutex<Instant>,
+    starting_block: Mutex<Option<BlockNumber>>,
+    highest_block: Mutex<BlockNumber>,
+    history_policy: HistoryPolicy,
+    is_body_download_paused: AtomicBool,
+    is_headers_only: AtomicBool,
 }
 
 impl Extension {
-    pub fn new(client: Arc<BlockChainClient>) -> Arc<Self> {
+    pub fn new(client: Arc<BlockChainClient>, history_policy: HistoryPolicy) -> Arc<Self> {
+        let chain_info = client.chain_info();
         Arc::new(Self {
             requests: RwLock::new(HashMap::new()),
             header_downloaders: RwLock::new(HashMap::new()),
             body_downloader: Mutex::new(BodyDownloader::new(Vec::new())),
+            body_batch_sizes: RwLock::new(HashMap::new()),
             client,
             api: Mutex::new(None),
             last_request: AtomicUsize::new(0),
+            latencies: RwLock::new(HashMap::new()),
+            progress: Mutex::new(Progress::new(chain_info.total_score)),
+            last_tick: Mutex::new(Instant::now()),
+            starting_block: Mutex::new(None),
+            highest_block: Mutex::new(chain_info.best_block_number),
+            history_policy,
+            is_body_download_paused: AtomicBool::new(false),
+            is_headers_only: AtomicBool::new(false),
         })
     }
 
@@ -65,23 +115,250 @@ impl Extension {
         });
     }
 
-    fn dismiss_request(&self, token: &NodeId, id: u64) {
-        if let Some(requests) = self.requests.write().get_mut(token) {
-            requests.retain(|(i, _)| *i == id);
+    /// Forgets the request. Returns when it was sent.
+    fn forget_request(&self, token: &NodeId, id: u64) -> Option<Instant> {
+        let mut requests = self.requests.write();
+        let requests = requests.get_mut(token)?;
+        let index = requests.iter().position(|(i, ..)| *i == id)?;
+        let (_, _, sent_at) = requests.remove(index);
+        Some(sent_at)
+    }
+
+    /// Returns how long the request took in milliseconds.
+    fn dismiss_request(&self, token: &NodeId, id: u64) -> Option<u64> {
+        let sample = self.forget_request(token, id).map(elapsed_ms);
+        if let Some(sample) = sample {
+            self.update_latency(token, sample);
         }
+        sample
     }
 
     fn send_request(&self, token: &NodeId, request: RequestMessage) {
         if let Some(requests) = self.requests.write().get_mut(token) {
             let id = self.last_request.fetch_add(1, Ordering::Relaxed) as u64;
-            requests.push((id, request.clone()));
+            requests.push((id, request.clone(), Instant::now()));
             self.send_message(token, Message::Request(id, request));
         }
     }
 
+    /// Drops the body requests which the peer didn't answer in time. Returns whether there was any.
+    fn expire_body_requests(&self, token: &NodeId) -> bool {
+        let expired: Vec<Vec<H256>> = match self.requests.write().get_mut(token) {
+            Some(requests) => {
+                let (expired, pending): (Vec<_>, Vec<_>) = requests.drain(..).partition(|(_, request, sent_at)| {
+                    match request {
+                        RequestMessage::Bodies(_) => elapsed_ms(*sent_at) > MAX_BODY_REQUEST_WAIT_MS,
+                        _ => false,
+                    }
+                });
+                *requests = pending;
+                expired
+                    .into_iter()
+                    .filter_map(|(_, request, _)| match request {
+                        RequestMessage::Bodies(hashes) => Some(hashes),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if expired.is_empty() {
+            return false
+        }
+
+        let mut body_downloader = self.body_downloader.lock();
+        for hashes in expired {
+            body_downloader.release(&hashes);
+        }
+        drop(body_downloader);
+        if let Some(batch_size) = self.body_batch_sizes.write().get_mut(token) {
+            batch_size.on_failure();
+        }
+        cinfo!(SYNC, "The body request to peer #{} timed out", token);
+        true
+    }
+
+    /// Forgets the requests to the peer, and the bodies it was asked for can be requested to the others.
+    fn drop_requests(&self, token: &NodeId) {
+        let requests = self.requests.write().remove(token).unwrap_or_default();
+        let mut body_downloader = self.body_downloader.lock();
+        for (_, request, _) in requests {
+            if let RequestMessage::Bodies(hashes) = request {
+                body_downloader.release(&hashes);
+            }
+        }
+    }
+
+    fn update_latency(&self, token: &NodeId, sample: u64) {
+        let mut latencies = self.latencies.write();
+        let latency = latencies.entry(*token).or_insert(sample);
+        *latency = (*latency * 3 + sample) / 4;
+    }
+
+    /// The latency of the peer in milliseconds. A request which is not answered yet counts as a sample.
+    fn latency(&self, token: &NodeId) -> u64 {
+        let measured = self.latencies.read().get(token).cloned().unwrap_or(0);
+        let pending = self.requests
+            .read()
+            .get(token)
+            .into_iter()
+            .flat_map(|requests| requests.iter())
+            .map(|(_, _, sent_at)| elapsed_ms(*sent_at))
+            .max()
+            .unwrap_or(0);
+        ::std::cmp::max(measured, pending)
+    }
+
+    fn is_initial_sync(&self) -> bool {
+        let best_block_timestamp = self.client.best_block_header().timestamp();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        best_block_timestamp + INITIAL_SYNC_THRESHOLD_SECONDS < now
+    }
+
+    fn rotate_peers(&self) {
+        if !self.is_initial_sync() {
+            return
+        }
+        let peers: Vec<_> = self.header_downloaders.read().keys().map(|id| (*id, self.latency(id))).collect();
+        for id in slowest_peers(peers) {
+            cinfo!(SYNC, "Replacing slow peer #{} during the initial sync", id);
+            self.api.lock().as_ref().map(|api| api.disconnect(&id));
+        }
+    }
+
+    /// The blocks downloaded so far, the download bandwidth and the estimated time to catch up with the best peer.
+    pub fn status(&self) -> SyncStatus {
+        let chain_info = self.client.chain_info();
+        let own_score = chain_info.total_score;
+        let scores: Vec<U256> = self.header_downloaders.read().values().map(|peer| peer.total_score()).collect();
+        let target_score = scores.iter().cloned().fold(own_score, ::std::cmp::max);
+        let peers = scores.iter().filter(|score| **score > own_score).count();
+        let estimate = self.progress.lock().estimate(target_score);
+        let current_block = chain_info.best_block_number;
+        SyncStatus {
+            is_syncing: peers > 0,
+            starting_block: self.starting_block.lock().unwrap_or(current_block),
+            current_block,
+            highest_block: ::std::cmp::max(*self.highest_block.lock(), current_block),
+            peers,
+            bandwidth: estimate.bandwidth,
+            eta: estimate.eta,
+        }
+    }
+
+    /// The advertised chain heads of the connected peers.
+    pub fn peer_heads(&self) -> HashMap<NodeId, PeerHead> {
+        let peers: Vec<_> = self
+            .header_downloaders
+            .read()
+            .iter()
+            .map(|(id, peer)| (*id, peer.best_hash(), peer.total_score()))
+            .collect();
+        peers
+            .into_iter()
+            .map(|(id, best_hash, total_score)| {
+                let head = PeerHead {
+                    best_hash,
+                    total_score,
+                    is_on_minority_fork: self.is_on_minority_fork(&best_hash, total_score),
+                };
+                (id, head)
+            })
+            .collect()
+    }
+
+    fn is_on_minority_fork(&self, best_hash: &H256, total_score: U256) -> bool {
+        if total_score >= self.client.chain_info().total_score {
+            return false
+        }
+        match self.client.block_number(BlockId::Hash(*best_hash)) {
+            Some(number) => self.client.block_hash(BlockId::Number(number)) != Some(*best_hash),
+            None => false,
+        }
+    }
+
+    /// Whether a connected peer has a chain with a higher score than this node's.
+    pub fn is_syncing(&self) -> bool {
+        let own_score = self.client.chain_info().total_score;
+        self.header_downloaders.read().values().any(|peer| peer.total_score() > own_score)
+    }
+
+    fn update_progress(&self) {
+        let mut last_tick = self.last_tick.lock();
+        let elapsed = elapsed_ms(*last_tick);
+        *last_tick = Instant::now();
+        let chain_info = self.client.chain_info();
+        self.progress.lock().tick(chain_info.total_score, elapsed);
+
+        let is_syncing = self.is_syncing();
+        let mut starting_block = self.starting_block.lock();
+        if !is_syncing {
+            *starting_block = None;
+        } else if starting_block.is_none() {
+            *starting_block = Some(chain_info.best_block_number);
+        }
+    }
+
+    fn inform(&self) {
+        let status = self.status();
+        let connected = self.header_downloaders.read().len();
+        if !status.is_syncing {
+            cdebug!(SYNC, "Idle at #{} with {} peers", status.current_block, connected);
+            return
+        }
+        let eta = match status.eta {
+            Some(eta) => format!("{}s", eta),
+            None => String::from("unknown"),
+        };
+        cinfo!(
+            SYNC,
+            "Syncing #{} of #{} from {}/{} peers at {} B/s, started at #{}, eta {}",
+            status.current_block,
+            status.highest_block,
+            status.peers,
+            connected,
+            status.bandwidth,
+            status.starting_block,
+            eta
+        );
+    }
+
+    // The bodies are requested again on the next tick after the verifiers catch up.
+    fn is_block_queue_full(&self) -> bool {
+        let is_full = self.client.queue_info().is_full();
+        if is_full {
+            cdebug!(SYNC, "The block queue is full, stop downloading the bodies");
+        }
+        is_full
+    }
+
+    /// The bodies are not downloaded while the state is restored from a snapshot.
+    pub fn pause_body_download(&self, paused: bool) {
+        self.is_body_download_paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// A light client syncs only the headers, and fetches the bodies and the state on demand.
+    pub fn set_headers_only(&self, headers_only: bool) {
+        self.is_headers_only.store(headers_only, Ordering::SeqCst);
+    }
+
+    fn can_download_bodies(&self) -> bool {
+        !self.is_headers_only.load(Ordering::SeqCst)
+            && !self.is_body_download_paused.load(Ordering::SeqCst)
+            && !self.is_block_queue_full()
+    }
+
+    fn body_batch_size(&self, token: &NodeId) -> u64 {
+        self.body_batch_sizes.read().get(token).map_or(INITIAL_BODY_REQUEST_LENGTH, BatchSize::get)
+    }
+
     fn send_response(&self, token: &NodeId, id: u64, response: ResponseMessage) {
         self.send_message(token, Message::Response(id, response));
     }
+
+    fn report(&self, token: &NodeId, behavior: PeerBehavior) {
+        self.api.lock().as_ref().map(|api| api.report(token, behavior));
+    }
 }
 
 impl NetworkExtension for Extension {
@@ -94,6 +371,10 @@ impl NetworkExtension for Extension {
 
     fn on_initialize(&self, api: Arc<Api>) {
         api.set_timer(SYNC_TIMER_TOKEN, Duration::milliseconds(SYNC_TIMER_INTERVAL)).expect("Timer set succeeds");
+        api.set_timer(ROTATION_TIMER_TOKEN, Duration::milliseconds(ROTATION_TIMER_INTERVAL))
+            .expect("Timer set succeeds");
+        api.set_timer(INFORMANT_TIMER_TOKEN, Duration::milliseconds(INFORMANT_TIMER_INTERVAL))
+            .expect("Timer set succeeds");
         *self.api.lock() = Some(api);
         cinfo!(SYNC, "Sync extension initialized");
     }
@@ -103,7 +384,10 @@ impl NetworkExtension for Extension {
         self.api.lock().as_ref().map(|api| api.negotiate(token));
     }
     fn on_node_removed(&self, token: &NodeId) {
+        self.drop_requests(token);
         self.header_downloaders.write().remove(token);
+        self.body_batch_sizes.write().remove(token);
+        self.latencies.write().remove(token);
         cinfo!(SYNC, "Peer removed #{}", token);
     }
 
@@ -124,6 +408,7 @@ impl NetworkExtension for Extension {
     }
 
     fn on_message(&self, token: &NodeId, data: &[u8]) {
+        self.progress.lock().on_received(data.len());
         if let Ok(received_message) = UntrustedRlp::new(data).as_val() {
             match received_message {
                 Message::Status {
@@ -142,18 +427,37 @@ impl NetworkExtension for Extension {
     }
 
     fn on_timeout(&self, timer: TimerToken) {
-        debug_assert_eq!(timer, SYNC_TIMER_TOKEN);
+        match timer {
+            SYNC_TIMER_TOKEN => {
+                self.update_progress();
+                self.sync()
+            }
+            ROTATION_TIMER_TOKEN => self.rotate_peers(),
+            INFORMANT_TIMER_TOKEN => self.inform(),
+            _ => unreachable!(),
+        }
+    }
+}
 
+impl Extension {
+    fn sync(&self) {
         let total_score = self.client.chain_info().total_score;
-        let peer_ids: Vec<_> = self.header_downloaders.read().keys().cloned().collect();
-        for id in peer_ids {
+        let can_download_bodies = self.can_download_bodies();
+        let peers: Vec<_> =
+            self.header_downloaders.read().iter().map(|(id, peer)| (*id, peer.total_score())).collect();
+        for id in highest_score_first(peers) {
+            let mut timed_out = false;
             if let Some(peer) = self.header_downloaders.write().get_mut(&id) {
+                timed_out = peer.is_expired();
                 if let Some(request) = peer.create_request() {
                     self.send_request(&id, request);
                 }
             }
+            let body_timed_out = self.expire_body_requests(&id);
+            if timed_out || body_timed_out {
+                self.report(&id, PeerBehavior::TimedOut);
+            }
 
-            // FIXME: invalidate expired body requests
             let peer_score = if let Some(peer) = self.header_downloaders.read().get(&id) {
                 peer.total_score()
             } else {
@@ -162,15 +466,16 @@ impl NetworkExtension for Extension {
             let have_body_request = {
                 if let Some(request_list) = self.requests.read().get(&id) {
                     request_list.iter().any(|r| match r {
-                        (_, RequestMessage::Bodies(..)) => true,
+                        (_, RequestMessage::Bodies(..), _) => true,
                         _ => false,
                     })
                 } else {
                     false
                 }
             };
-            if !have_body_request && peer_score > total_score {
-                if let Some(request) = self.body_downloader.lock().create_request() {
+            if !have_body_request && peer_score > total_score && can_download_bodies {
+                let max_count = self.body_batch_size(&id);
+                if let Some(request) = self.body_downloader.lock().create_request(max_count) {
                     self.send_request(&id, request);
                 }
             }
@@ -188,6 +493,20 @@ impl ChainNotify for Extension {
         _sealed: Vec<H256>,
         _duration: u64,
     ) {
+        // The ancestors of a block restored from a snapshot don't have bodies, and they are not downloaded.
+        let restored = imported
+            .iter()
+            .filter_map(|hash| self.client.block_header(BlockId::Hash(*hash)))
+            .filter(|header| header.number() != 0)
+            .filter(|header| self.client.block_body(BlockId::Hash(header.parent_hash())).is_none())
+            .map(|header| header.number())
+            .max();
+        if let Some(restored) = restored {
+            let client = &self.client;
+            self.body_downloader.lock().retain_target(|hash| {
+                client.block_number(BlockId::Hash(*hash)).map_or(true, |number| number > restored)
+            });
+        }
         self.body_downloader.lock().remove_target(imported);
         self.body_downloader.lock().remove_target(invalid);
 
@@ -226,18 +545,23 @@ impl ChainNotify for Extension {
             .map(|hash| self.client.block_header(BlockId::Hash(hash)).expect("Enacted header must exist"))
             .collect();
         enacted_headers.sort_unstable_by_key(|header| header.number());
+        if let Some(last) = enacted_headers.last() {
+            let mut highest_block = self.highest_block.lock();
+            // The blocks of the retracted branch are no longer on the chain.
+            *highest_block = if retracted.is_empty() {
+                ::std::cmp::max(*highest_block, last.number())
+            } else {
+                last.number()
+            };
+        }
+        if self.is_headers_only.load(Ordering::SeqCst) {
+            return
+        }
 
         let body_targets = enacted_headers
             .into_iter()
             .filter(|header| self.client.block_body(BlockId::Hash(header.hash())).is_none())
-            .map(|header| {
-                let prev_root = if let Some(parent) = self.client.block_header(BlockId::Hash(header.parent_hash())) {
-                    parent.parcels_root()
-                } else {
-                    H256::zero()
-                };
-                (header.hash(), prev_root, header.parcels_root())
-            })
+            .map(|header| self.body_target(&header))
             .collect();
         self.body_downloader.lock().add_target(body_targets);
         self.body_downloader.lock().remove_target(retracted);
@@ -253,6 +577,11 @@ impl Extension {
         }
 
         ctrace!(SYNC, "Peer #{} status update: total_score: {}, best_hash: {}", from, total_score, best_hash);
+        // The peer repeats its status while its head stays, so the fork is reported once per head.
+        let is_new_head = self.header_downloaders.read().get(from).map_or(true, |peer| peer.best_hash() != best_hash);
+        if is_new_head && self.is_on_minority_fork(&best_hash, total_score) {
+            cinfo!(SYNC, "Peer #{} is on a minority fork at {}", from, best_hash);
+        }
 
         let mut requests = self.requests.write();
         let mut peers = self.header_downloaders.write();
@@ -261,6 +590,10 @@ impl Extension {
         } else {
             requests.insert(*from, Vec::new());
             peers.insert(*from, HeaderDownloader::new(self.client.clone(), total_score, best_hash));
+            self.body_batch_sizes.write().insert(
+                *from,
+                BatchSize::new(INITIAL_BODY_REQUEST_LENGTH, MIN_BODY_REQUEST_LENGTH, MAX_BODY_REQUEST_LENGTH),
+            );
         }
     }
 }
@@ -277,6 +610,13 @@ impl Extension {
             return
         }
 
+        if !self.is_allowed_request(from, &request) {
+            cinfo!(SYNC, "Refused the history request from peer #{}", from);
+            // The peer asks another peer instead of waiting for the request to expire
+            self.send_response(from, id, ResponseMessage::Refused);
+            return
+        }
+
         let response = match request {
             RequestMessage::Headers {
                 start_number,
@@ -317,6 +657,31 @@ impl Extension {
         }
     }
 
+    fn is_allowed_request(&self, from: &NodeId, request: &RequestMessage) -> bool {
+        let oldest = match request {
+            RequestMessage::Headers {
+                start_number,
+                ..
+            } => Some(*start_number),
+            RequestMessage::Bodies(hashes) => {
+                hashes.iter().filter_map(|hash| self.client.block_number(BlockId::Hash(*hash))).min()
+            }
+            RequestMessage::StateHead(hash) => self.client.block_number(BlockId::Hash(*hash)),
+            RequestMessage::StateChunk {
+                block_hash,
+                ..
+            } => self.client.block_number(BlockId::Hash(*block_hash)),
+        };
+        let oldest = match oldest {
+            Some(oldest) => oldest,
+            // Nothing is served for the unknown blocks
+            None => return true,
+        };
+        let best = self.client.chain_info().best_block_number;
+        let peer = self.api.lock().as_ref().and_then(|api| api.peer_identity(from));
+        self.history_policy.allows(peer.as_ref(), oldest, best)
+    }
+
     fn create_headers_response(&self, start_number: BlockNumber, max_count: u64) -> ResponseMessage {
         let headers = (0..max_count)
             .map(|number| self.client.block(BlockId::Number(start_number + number)))
@@ -349,8 +714,10 @@ impl Extension {
 
 impl Extension {
     fn on_peer_response(&self, from: &NodeId, id: u64, mut response: ResponseMessage) {
-        let last_request = self.requests.read()[from].iter().find(|(i, _)| *i == id).cloned();
-        if let Some((_, request)) = last_request {
+        // The response to an expired request is ignored.
+        let last_request =
+            self.requests.read().get(from).and_then(|requests| requests.iter().find(|(i, ..)| *i == id).cloned());
+        if let Some((_, request, _)) = last_request {
             match &mut response {
                 ResponseMessage::Headers(headers) => {
                     headers.sort_unstable_by_key(|h| h.number());
@@ -358,14 +725,23 @@ impl Extension {
                 _ => {}
             }
 
+            if response == ResponseMessage::Refused {
+                self.on_refused_response(from, id, &request);
+                return
+            }
             if !self.is_valid_response(&request, &response) {
+                self.on_invalid_response(from, id, &request);
                 return
             }
-            self.dismiss_request(from, id);
+            let latency = self.dismiss_request(from, id);
+            self.report(from, PeerBehavior::ServedValidData);
 
             match response {
                 ResponseMessage::Headers(headers) => self.on_header_response(from, headers),
                 ResponseMessage::Bodies(bodies) => {
+                    if let (Some(batch_size), Some(latency)) = (self.body_batch_sizes.write().get_mut(from), latency) {
+                        batch_size.on_success(latency);
+                    }
                     let hashes = match request {
                         RequestMessage::Bodies(hashes) => hashes,
                         _ => unreachable!(),
@@ -377,6 +753,62 @@ impl Extension {
         }
     }
 
+    fn on_invalid_response(&self, from: &NodeId, id: u64, request: &RequestMessage) {
+        self.report(from, PeerBehavior::SentInvalidData);
+        // Other peers can be asked without waiting for the request to expire.
+        self.forget_request(from, id);
+        match request {
+            RequestMessage::Headers {
+                ..
+            } => {
+                if let Some(peer) = self.header_downloaders.write().get_mut(from) {
+                    peer.mark_as_failed();
+                }
+            }
+            RequestMessage::Bodies(hashes) => {
+                if let Some(batch_size) = self.body_batch_sizes.write().get_mut(from) {
+                    batch_size.on_failure();
+                }
+                self.body_downloader.lock().release(hashes);
+            }
+            _ => {}
+        }
+    }
+
+    // Refusing the history is not a misbehavior, so the peer is not reported.
+    fn on_refused_response(&self, from: &NodeId, id: u64, request: &RequestMessage) {
+        cdebug!(SYNC, "Peer #{} refused the request {}", from, id);
+        self.forget_request(from, id);
+        if let RequestMessage::Bodies(hashes) = request {
+            self.body_downloader.lock().release(hashes);
+        }
+    }
+
+    // The queues verify the header and the block against the consensus engine before queueing them.
+    fn is_invalid_import(&self, from: &NodeId, hash: &H256, err: &BlockImportError) -> bool {
+        match err {
+            BlockImportError::Import(ImportError::AlreadyQueued) => false,
+            BlockImportError::Import(ImportError::AlreadyInChain) => false,
+            // The timestamp is valid later, or the clock of this node is behind
+            BlockImportError::Block(BlockError::TemporarilyInvalid(_)) => false,
+            BlockImportError::Import(ImportError::KnownBad) | BlockImportError::Block(_) => {
+                cinfo!(SYNC, "Peer #{} sent the invalid block {}: {:?}", from, hash, err);
+                true
+            }
+            // Not the fault of the peer
+            BlockImportError::Other(_) => false,
+        }
+    }
+
+    fn body_target(&self, header: &EncodedHeader) -> (H256, H256, H256) {
+        let prev_root = if let Some(parent) = self.client.block_header(BlockId::Hash(header.parent_hash())) {
+            parent.parcels_root()
+        } else {
+            H256::zero()
+        };
+        (header.hash(), prev_root, header.parcels_root())
+    }
+
     fn is_valid_response(&self, request: &RequestMessage, response: &ResponseMessage) -> bool {
         match (request, response) {
             (
@@ -420,14 +852,18 @@ impl Extension {
         completed.sort_unstable_by_key(|header| header.number());
 
         let mut exists = Vec::new();
+        let mut is_invalid = false;
         for header in completed {
             let hash = header.hash();
-            // FIXME: handle import errors
             match self.client.import_header(header.into_inner()) {
                 Err(BlockImportError::Import(ImportError::AlreadyInChain)) => exists.push(hash),
-                _ => {}
+                Err(err) => is_invalid |= self.is_invalid_import(from, &hash, &err),
+                Ok(_) => {}
             }
         }
+        if is_invalid {
+            self.report(from, PeerBehavior::SentInvalidData);
+        }
 
         if let Some(peer) = self.header_downloaders.write().get_mut(from) {
             peer.mark_as_imported(exists);
@@ -441,19 +877,40 @@ impl Extension {
         self.body_downloader.lock().import_bodies(hashes, bodies);
         let completed = self.body_downloader.lock().drain();
         let mut exists = Vec::new();
+        let mut imported = Vec::new();
+        let mut rejected = Vec::new();
+        let mut is_invalid = false;
         for (hash, body) in completed {
+            // The engine doesn't import the block again while it is marked, e.g. as a proposal block.
+            self.client.mark_block_seen(hash);
             let header = self.client.block_header(BlockId::Hash(hash)).expect("Downloaded body's header must exist");
             let block = Block {
                 header: header.decode(),
                 parcels: body,
             };
-            // FIXME: handle import errors
-            match self.client.import_block(block.rlp_bytes(Seal::With)) {
+            let result = self.client.import_block(block.rlp_bytes(Seal::With));
+            if result.is_err() {
+                self.client.unmark_block_seen(&hash);
+            }
+            match result {
                 Err(BlockImportError::Import(ImportError::AlreadyInChain)) => exists.push(hash),
-                _ => {}
+                Err(err) => {
+                    // The body doesn't match the valid header, so other peers can have the right one.
+                    // The other errors condemn the block itself.
+                    if let BlockImportError::Block(BlockError::InvalidParcelsRoot(_)) = err {
+                        rejected.push(self.body_target(&header));
+                    }
+                    is_invalid |= self.is_invalid_import(from, &hash, &err);
+                }
+                Ok(_) => imported.push(hash),
             }
         }
         self.body_downloader.lock().remove_target(exists);
+        self.body_downloader.lock().mark_as_imported(&imported);
+        self.body_downloader.lock().retry(rejected);
+        if is_invalid {
+            self.report(from, PeerBehavior::SentInvalidData);
+        }
 
         let total_score = self.client.chain_info().total_score;
         let peer_score = if let Some(peer) = self.header_downloaders.read().get(from) {
@@ -462,10 +919,61 @@ impl Extension {
             U256::zero()
         };
 
-        if peer_score > total_score {
-            if let Some(request) = self.body_downloader.lock().create_request() {
+        if peer_score > total_score && self.can_download_bodies() {
+            let max_count = self.body_batch_size(from);
+            if let Some(request) = self.body_downloader.lock().create_request(max_count) {
                 self.send_request(from, request);
             }
         }
     }
 }
+
+fn elapsed_ms(since: Instant) -> u64 {
+    let elapsed = since.elapsed();
+    elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Picks the peers to be replaced, from the slowest one.
+fn slowest_peers(mut peers: Vec<(NodeId, u64)>) -> Vec<NodeId> {
+    if peers.len() < MIN_PEERS_TO_ROTATE {
+        return Vec::new()
+    }
+    let count = ::std::cmp::max(1, peers.len() / ROTATION_DIVISOR);
+    peers.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+    peers.into_iter().take(count).map(|(id, _)| id).collect()
+}
+
+/// Orders the peers from the highest score, so the best chains get the body requests first.
+fn highest_score_first(mut peers: Vec<(NodeId, U256)>) -> Vec<NodeId> {
+    peers.sort_by(|(_, a), (_, b)| b.cmp(a));
+    peers.into_iter().map(|(id, _)| id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn too_few_peers_are_not_rotated() {
+        let peers = vec![(1.into(), 100), (2.into(), 200), (3.into(), 300)];
+        assert_eq!(Vec::<NodeId>::new(), slowest_peers(peers));
+    }
+
+    #[test]
+    fn the_slowest_peer_is_rotated() {
+        let peers = vec![(1.into(), 100), (2.into(), 400), (3.into(), 300), (4.into(), 200)];
+        assert_eq!(vec![NodeId::from(2)], slowest_peers(peers));
+    }
+
+    #[test]
+    fn a_fraction_of_peers_is_rotated() {
+        let peers = (0..16).map(|i| (NodeId::from(i), i)).collect();
+        assert_eq!(vec![NodeId::from(15), NodeId::from(14)], slowest_peers(peers));
+    }
+
+    #[test]
+    fn peers_are_ordered_by_score() {
+        let peers = vec![(1.into(), 100.into()), (2.into(), 300.into()), (3.into(), 200.into())];
+        assert_eq!(vec![NodeId::from(2), NodeId::from(3), NodeId::from(1)], highest_score_first(peers));
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use ccore::BlockNumber;
+use ckeys::Public;
+
+/// Decides which peers are served the blocks deeper than `max_depth` from the best block.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryPolicy {
+    max_depth: Option<u64>,
+    allowed_peers: HashSet<Public>,
+}
+
+impl HistoryPolicy {
+    /// The whole history is served to every peer if `max_depth` is None.
+    pub fn new(max_depth: Option<u64>, allowed_peers: Vec<Public>) -> Self {
+        Self {
+            max_depth,
+            allowed_peers: allowed_peers.into_iter().collect(),
+        }
+    }
+
+    pub fn allows(&self, peer: Option<&Public>, oldest: BlockNumber, best: BlockNumber) -> bool {
+        match self.max_depth {
+            None => true,
+            Some(max_depth) if oldest.saturating_add(max_depth) >= best => true,
+            Some(_) => peer.map_or(false, |peer| self.allowed_peers.contains(peer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrestricted_policy_serves_everyone() {
+        let policy = HistoryPolicy::default();
+        assert!(policy.allows(None, 0, 1_000_000));
+    }
+
+    #[test]
+    fn recent_blocks_are_served_to_unknown_peers() {
+        let policy = HistoryPolicy::new(Some(100), vec![]);
+        assert!(policy.allows(None, 900, 1000));
+        assert!(policy.allows(Some(&Public::random()), 950, 1000));
+    }
+
+    #[test]
+    fn deep_history_is_refused_to_unknown_peers() {
+        let policy = HistoryPolicy::new(Some(100), vec![]);
+        assert!(!policy.allows(None, 899, 1000));
+        assert!(!policy.allows(Some(&Public::random()), 0, 1000));
+    }
+
+    #[test]
+    fn deep_history_is_served_to_allowed_peers() {
+        let allowed = Public::random();
+        let policy = HistoryPolicy::new(Some(100), vec![allowed]);
+        assert!(policy.allows(Some(&allowed), 0, 1000));
+    }
+}
@@ -32,6 +32,7 @@ const MESSAGE_ID_GET_STATE_HEAD: u8 = 0x06;
 const MESSAGE_ID_STATE_HEAD: u8 = 0x07;
 const MESSAGE_ID_GET_STATE_CHUNK: u8 = 0x08;
 const MESSAGE_ID_STATE_CHUNK: u8 = 0x09;
+const MESSAGE_ID_REFUSED: u8 = 0x0a;
 
 #[derive(Debug, PartialEq)]
 pub enum Message {
@@ -105,7 +106,11 @@ impl Decodable for Message {
                 | MESSAGE_ID_GET_BODIES
                 | MESSAGE_ID_GET_STATE_HEAD
                 | MESSAGE_ID_GET_STATE_CHUNK => Ok(Message::Request(request_id, RequestMessage::decode(id, &message)?)),
-                MESSAGE_ID_HEADERS | MESSAGE_ID_BODIES | MESSAGE_ID_STATE_HEAD | MESSAGE_ID_STATE_CHUNK => {
+                MESSAGE_ID_HEADERS
+                | MESSAGE_ID_BODIES
+                | MESSAGE_ID_STATE_HEAD
+                | MESSAGE_ID_STATE_CHUNK
+                | MESSAGE_ID_REFUSED => {
                     Ok(Message::Response(request_id, ResponseMessage::decode(id, &message)?))
                 }
                 _ => Err(DecoderError::Custom("Unknown message id detected")),
@@ -23,6 +23,8 @@ pub enum ResponseMessage {
     Bodies(Vec<Vec<UnverifiedParcel>>),
     StateHead(Vec<u8>),
     StateChunk(Vec<u8>),
+    /// The peer doesn't serve the requested history to this node
+    Refused,
 }
 
 impl Encodable for ResponseMessage {
@@ -45,6 +47,9 @@ impl Encodable for ResponseMessage {
                 s.begin_list(1);
                 s.append(bytes);
             }
+            ResponseMessage::Refused => {
+                s.begin_list(0);
+            }
         };
     }
 }
@@ -60,6 +65,7 @@ impl ResponseMessage {
             ResponseMessage::StateChunk {
                 ..
             } => super::MESSAGE_ID_STATE_CHUNK,
+            ResponseMessage::Refused => super::MESSAGE_ID_REFUSED,
         }
     }
 
@@ -85,6 +91,12 @@ impl ResponseMessage {
                 }
                 ResponseMessage::StateChunk(rlp.val_at(0)?)
             }
+            super::MESSAGE_ID_REFUSED => {
+                if rlp.item_count()? != 0 {
+                    return Err(DecoderError::RlpIncorrectListLen)
+                }
+                ResponseMessage::Refused
+            }
             _ => return Err(DecoderError::Custom("Unknown message id detected")),
         };
 
@@ -132,4 +144,10 @@ mod tests {
         let message = ResponseMessage::StateChunk(vec![]);
         assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
     }
+
+    #[test]
+    fn test_refused_message_rlp() {
+        let message = ResponseMessage::Refused;
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+    }
 }
@@ -16,6 +16,10 @@
 
 mod downloader;
 mod extension;
+mod history;
 mod message;
+mod progress;
 
-pub use self::extension::Extension as BlockSyncExtension;
+pub use self::extension::{Extension as BlockSyncExtension, PeerHead};
+pub use self::history::HistoryPolicy;
+pub use self::progress::SyncStatus;
@@ -0,0 +1,174 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use ccore::BlockNumber;
+use ctypes::U256;
+
+// The weight of the previous average is (SMOOTHING - 1) / SMOOTHING.
+const SMOOTHING: u64 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyncStatus {
+    /// Whether a peer has a better chain than this node
+    pub is_syncing: bool,
+    /// The best block when the current sync started, or the best block if the node is idle
+    pub starting_block: BlockNumber,
+    /// The best block
+    pub current_block: BlockNumber,
+    /// The highest header imported from the peers
+    pub highest_block: BlockNumber,
+    /// The number of peers which have a better chain than this node
+    pub peers: usize,
+    /// Bytes downloaded from the peers per second
+    pub bandwidth: u64,
+    /// Seconds left until the node catches up with the best peer
+    pub eta: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub bandwidth: u64,
+    pub eta: Option<u64>,
+}
+
+/// Tracks the moving averages of the download speed and the score growth.
+pub struct Progress {
+    received_bytes: u64,
+    last_score: U256,
+    bandwidth: Option<u64>,
+    score_rate: Option<U256>,
+}
+
+impl Progress {
+    pub fn new(total_score: U256) -> Self {
+        Self {
+            received_bytes: 0,
+            last_score: total_score,
+            bandwidth: None,
+            score_rate: None,
+        }
+    }
+
+    pub fn on_received(&mut self, bytes: usize) {
+        self.received_bytes += bytes as u64;
+    }
+
+    /// Takes the samples gathered since the last tick.
+    pub fn tick(&mut self, total_score: U256, elapsed_ms: u64) {
+        if elapsed_ms == 0 {
+            return
+        }
+        let bandwidth_sample = self.received_bytes * 1000 / elapsed_ms;
+        self.bandwidth = Some(match self.bandwidth {
+            Some(average) => (average * (SMOOTHING - 1) + bandwidth_sample) / SMOOTHING,
+            None => bandwidth_sample,
+        });
+        self.received_bytes = 0;
+
+        let gained = if total_score > self.last_score {
+            total_score - self.last_score
+        } else {
+            U256::zero()
+        };
+        let score_sample = gained * U256::from(1000) / U256::from(elapsed_ms);
+        self.score_rate = Some(match self.score_rate {
+            Some(average) => (average * U256::from(SMOOTHING - 1) + score_sample) / U256::from(SMOOTHING),
+            None => score_sample,
+        });
+        self.last_score = total_score;
+    }
+
+    pub fn estimate(&self, target_score: U256) -> Estimate {
+        Estimate {
+            bandwidth: self.bandwidth.unwrap_or(0),
+            eta: self.eta(target_score),
+        }
+    }
+
+    fn eta(&self, target_score: U256) -> Option<u64> {
+        if target_score <= self.last_score {
+            return Some(0)
+        }
+        let rate = self.score_rate?;
+        if rate.is_zero() {
+            return None
+        }
+        let seconds = (target_score - self.last_score + rate - U256::one()) / rate;
+        if seconds > U256::from(u64::max_value()) {
+            return None
+        }
+        Some(seconds.low_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_is_estimated_before_the_first_tick() {
+        let progress = Progress::new(100.into());
+        assert_eq!(
+            Estimate {
+                bandwidth: 0,
+                eta: None,
+            },
+            progress.estimate(200.into())
+        );
+    }
+
+    #[test]
+    fn synced_node_has_no_remaining_time() {
+        let progress = Progress::new(200.into());
+        assert_eq!(Some(0), progress.estimate(200.into()).eta);
+        assert_eq!(Some(0), progress.estimate(100.into()).eta);
+    }
+
+    #[test]
+    fn bandwidth_is_bytes_per_second() {
+        let mut progress = Progress::new(0.into());
+        progress.on_received(300);
+        progress.on_received(700);
+        progress.tick(0.into(), 500);
+        assert_eq!(2000, progress.estimate(0.into()).bandwidth);
+    }
+
+    #[test]
+    fn bandwidth_is_smoothed() {
+        let mut progress = Progress::new(0.into());
+        progress.on_received(1000);
+        progress.tick(0.into(), 1000);
+        progress.tick(0.into(), 1000);
+        assert_eq!(750, progress.estimate(0.into()).bandwidth);
+    }
+
+    #[test]
+    fn eta_follows_the_score_growth() {
+        let mut progress = Progress::new(0.into());
+        progress.tick(10.into(), 1000);
+        assert_eq!(Some(9), progress.estimate(100.into()).eta);
+        // Rounded up
+        assert_eq!(Some(10), progress.estimate(101.into()).eta);
+    }
+
+    #[test]
+    fn stalled_sync_has_no_eta() {
+        let mut progress = Progress::new(10.into());
+        progress.tick(10.into(), 1000);
+        assert_eq!(None, progress.estimate(100.into()).eta);
+    }
+}
@@ -17,6 +17,8 @@
 extern crate parking_lot;
 
 extern crate codechain_core as ccore;
+extern crate codechain_crypto as ccrypto;
+extern crate codechain_keys as ckeys;
 extern crate codechain_merkle as cmerkle;
 #[macro_use]
 extern crate codechain_logger as clogger;
@@ -31,10 +33,11 @@ extern crate time;
 extern crate triehash;
 
 mod block;
+mod light;
 mod parcel;
+mod snapshot;
 
-pub use self::block::BlockSyncExtension;
+pub use self::block::{BlockSyncExtension, HistoryPolicy, PeerHead, SyncStatus};
+pub use self::light::LightSyncExtension;
 pub use self::parcel::ParcelSyncExtension;
-
-#[cfg(test)]
-extern crate codechain_keys as ckeys;
+pub use self::snapshot::SnapshotSyncExtension;
@@ -0,0 +1,93 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+const MAX_BUDGET: u64 = 64;
+const REFILL_PER_SECOND: u64 = 8;
+
+/// The cost of the requests that a light client can still make.
+/// It refills over time, so that the clients can't make the node serve them faster than the refill rate.
+pub struct Budget {
+    remaining: u64,
+    refilled_at: Instant,
+}
+
+impl Budget {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            remaining: MAX_BUDGET,
+            refilled_at: now,
+        }
+    }
+
+    /// Returns false, spending nothing, if the remaining budget is less than the cost.
+    pub fn spend(&mut self, cost: u64, now: Instant) -> bool {
+        self.refill(now);
+        if self.remaining < cost {
+            return false
+        }
+        self.remaining -= cost;
+        true
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.refilled_at {
+            return
+        }
+        let seconds = (now - self.refilled_at).as_secs();
+        if seconds == 0 {
+            return
+        }
+        self.remaining = cmp::min(MAX_BUDGET, self.remaining.saturating_add(seconds.saturating_mul(REFILL_PER_SECOND)));
+        self.refilled_at += Duration::from_secs(seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expensive_request_is_refused() {
+        let now = Instant::now();
+        let mut budget = Budget::new(now);
+        assert!(budget.spend(MAX_BUDGET - 1, now));
+        assert!(!budget.spend(2, now));
+        assert!(budget.spend(1, now));
+        assert!(!budget.spend(1, now));
+    }
+
+    #[test]
+    fn budget_is_refilled_every_second() {
+        let now = Instant::now();
+        let mut budget = Budget::new(now);
+        assert!(budget.spend(MAX_BUDGET, now));
+        assert!(!budget.spend(1, now + Duration::from_millis(999)));
+        assert!(budget.spend(REFILL_PER_SECOND, now + Duration::from_secs(1)));
+        assert!(!budget.spend(1, now + Duration::from_millis(1999)));
+    }
+
+    #[test]
+    fn budget_is_not_refilled_over_the_maximum() {
+        let now = Instant::now();
+        let mut budget = Budget::new(now);
+        assert!(!budget.spend(MAX_BUDGET + 1, now + Duration::from_secs(60)));
+        assert!(budget.spend(MAX_BUDGET, now + Duration::from_secs(60)));
+    }
+}
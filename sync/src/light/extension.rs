@@ -0,0 +1,569 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
+
+use ccore::encoded::Header;
+use ccore::{
+    verify_account_proof, Account, BlockChainClient, BlockId, Invoice, ParcelId, StateClient, UnverifiedParcel,
+};
+use cmerkle::skewed_merkle_root;
+use cnetwork::{Api, NetworkExtension, NodeId, PeerBehavior, TimerToken};
+use ctypes::{Address, Bytes, H256};
+use rand::{thread_rng, Rng};
+use rlp::{Encodable, UntrustedRlp};
+use time::Duration;
+
+use super::budget::Budget;
+use super::message::Message;
+
+const EXTENSION_NAME: &'static str = "light-client";
+const EXPIRATION_TIMER_TOKEN: TimerToken = 0;
+const EXPIRATION_TIMER_INTERVAL: i64 = 1000;
+// The requests which are not answered in this time are sent to other peers.
+const MAX_REQUEST_WAIT_SECONDS: u64 = 10;
+
+enum Query {
+    Account {
+        block_hash: H256,
+        address: Address,
+        state_root: H256,
+        sender: Sender<Option<Account>>,
+    },
+    Body {
+        block_hash: H256,
+        parent_parcels_root: H256,
+        parcels_root: H256,
+        sender: Sender<Vec<UnverifiedParcel>>,
+    },
+    Parcel {
+        parcel_hash: H256,
+        sender: Sender<(H256, usize)>,
+    },
+    Invoices {
+        block_hash: H256,
+        parent_invoices_root: H256,
+        invoices_root: H256,
+        sender: Sender<Vec<Invoice>>,
+    },
+}
+
+impl Query {
+    fn message(&self, id: u64) -> Message {
+        match self {
+            Query::Account {
+                block_hash,
+                address,
+                ..
+            } => Message::GetAccount {
+                id,
+                block_hash: *block_hash,
+                address: *address,
+            },
+            Query::Body {
+                block_hash,
+                ..
+            } => Message::GetBody {
+                id,
+                block_hash: *block_hash,
+            },
+            Query::Parcel {
+                parcel_hash,
+                ..
+            } => Message::GetParcel {
+                id,
+                parcel_hash: *parcel_hash,
+            },
+            Query::Invoices {
+                block_hash,
+                ..
+            } => Message::GetInvoices {
+                id,
+                block_hash: *block_hash,
+            },
+        }
+    }
+}
+
+// The budget spent by the requests, roughly in proportion to the work and the size of the answers.
+fn cost(request: &Message) -> u64 {
+    match request {
+        Message::GetAccount {
+            ..
+        } => 1,
+        Message::GetInvoices {
+            ..
+        } => 2,
+        _ => 4,
+    }
+}
+
+struct Request {
+    query: Query,
+    peer: NodeId,
+    // The peers which failed to answer. The query is dropped when every peer failed.
+    tried: HashSet<NodeId>,
+    sent_at: Instant,
+}
+
+/// Fetches the state and the bodies of the blocks from the full nodes on demand,
+/// and serves them to the light clients.
+///
+/// The answers are verified against the headers, so the headers have to be synchronized first.
+/// Every peer has a budget for its requests, which refills over time.
+pub struct Extension {
+    peers: RwLock<HashSet<NodeId>>,
+    budgets: Mutex<HashMap<NodeId, Budget>>,
+    requests: Mutex<HashMap<u64, Request>>,
+    last_request: AtomicUsize,
+    client: Arc<BlockChainClient>,
+    state: Arc<StateClient>,
+    api: Mutex<Option<Arc<Api>>>,
+}
+
+impl Extension {
+    pub fn new(client: Arc<BlockChainClient>, state: Arc<StateClient>) -> Arc<Self> {
+        Arc::new(Self {
+            peers: RwLock::new(HashSet::new()),
+            budgets: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+            last_request: AtomicUsize::new(0),
+            client,
+            state,
+            api: Mutex::new(None),
+        })
+    }
+
+    /// Fetches the account at the block with the proof from a peer.
+    /// `None` is received if the account doesn't exist, and the sender is dropped if no peer can prove the account.
+    pub fn fetch_account(&self, block_hash: H256, address: Address) -> Receiver<Option<Account>> {
+        let (sender, receiver) = channel();
+        if let Some(header) = self.client.block_header(BlockId::Hash(block_hash)) {
+            self.send_query(Query::Account {
+                block_hash,
+                address,
+                state_root: header.state_root(),
+                sender,
+            });
+        }
+        receiver
+    }
+
+    /// Fetches the parcels of the block from a peer.
+    /// The sender is dropped if no peer has the body.
+    pub fn fetch_body(&self, block_hash: H256) -> Receiver<Vec<UnverifiedParcel>> {
+        let (sender, receiver) = channel();
+        if let Some(header) = self.client.block_header(BlockId::Hash(block_hash)) {
+            self.send_query(Query::Body {
+                block_hash,
+                parent_parcels_root: self.parent(&header).map_or(H256::zero(), |parent| parent.parcels_root()),
+                parcels_root: header.parcels_root(),
+                sender,
+            });
+        }
+        receiver
+    }
+
+    /// Fetches the hash of the block which has the parcel and the index of the parcel in it.
+    /// The sender is dropped if no peer has a block with the parcel.
+    pub fn fetch_parcel(&self, parcel_hash: H256) -> Receiver<(H256, usize)> {
+        let (sender, receiver) = channel();
+        self.send_query(Query::Parcel {
+            parcel_hash,
+            sender,
+        });
+        receiver
+    }
+
+    /// Fetches the invoices of all the transactions in the block.
+    /// The sender is dropped if no peer has the invoices, e.g. because they are pruned.
+    pub fn fetch_invoices(&self, block_hash: H256) -> Receiver<Vec<Invoice>> {
+        let (sender, receiver) = channel();
+        if let Some(header) = self.client.block_header(BlockId::Hash(block_hash)) {
+            self.send_query(Query::Invoices {
+                block_hash,
+                parent_invoices_root: self.parent(&header).map_or(H256::zero(), |parent| parent.invoices_root()),
+                invoices_root: header.invoices_root(),
+                sender,
+            });
+        }
+        receiver
+    }
+
+    fn parent(&self, header: &Header) -> Option<Header> {
+        self.client.block_header(BlockId::Hash(header.parent_hash()))
+    }
+}
+
+impl NetworkExtension for Extension {
+    fn name(&self) -> String {
+        String::from(EXTENSION_NAME)
+    }
+    fn need_encryption(&self) -> bool {
+        false
+    }
+
+    fn on_initialize(&self, api: Arc<Api>) {
+        api.set_timer(EXPIRATION_TIMER_TOKEN, Duration::milliseconds(EXPIRATION_TIMER_INTERVAL))
+            .expect("Timer set succeeds");
+        *self.api.lock() = Some(api);
+    }
+
+    fn on_node_added(&self, token: &NodeId) {
+        self.api.lock().as_ref().map(|api| api.negotiate(token));
+    }
+    fn on_node_removed(&self, token: &NodeId) {
+        self.peers.write().remove(token);
+        self.budgets.lock().remove(token);
+        let ids: Vec<u64> =
+            self.requests.lock().iter().filter(|(_, request)| request.peer == *token).map(|(id, _)| *id).collect();
+        for id in ids {
+            self.retry(id);
+        }
+    }
+
+    fn on_negotiated(&self, token: &NodeId) {
+        self.peers.write().insert(*token);
+        self.budgets.lock().insert(*token, Budget::new(Instant::now()));
+    }
+    fn on_negotiation_allowed(&self, token: &NodeId) {
+        self.on_negotiated(token);
+    }
+
+    fn on_message(&self, token: &NodeId, data: &[u8]) {
+        let message = match UntrustedRlp::new(data).as_val::<Message>() {
+            Ok(message) => message,
+            Err(_) => {
+                cinfo!(SYNC, "Invalid message from peer {}", token);
+                self.report(token, PeerBehavior::SentInvalidData);
+                return
+            }
+        };
+        if message.is_request() {
+            self.on_request(token, message);
+            return
+        }
+        match message {
+            Message::Account {
+                id,
+                proof,
+            } => self.on_account(token, id, proof),
+            Message::Body {
+                id,
+                parcels,
+            } => self.on_body(token, id, parcels),
+            Message::Parcel {
+                id,
+                block_hash,
+                parcels,
+            } => self.on_parcel(token, id, block_hash, parcels),
+            Message::Invoices {
+                id,
+                invoices,
+            } => self.on_invoices(token, id, invoices),
+            Message::Unavailable {
+                id,
+            } => {
+                if self.is_requested_from(token, id) {
+                    self.retry(id);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn on_timeout(&self, timer: TimerToken) {
+        match timer {
+            EXPIRATION_TIMER_TOKEN => self.expire_requests(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Extension {
+    fn send_message(&self, token: &NodeId, message: Message) {
+        self.api.lock().as_ref().map(|api| {
+            api.send(token, &message.rlp_bytes().to_vec());
+        });
+    }
+
+    fn report(&self, token: &NodeId, behavior: PeerBehavior) {
+        self.api.lock().as_ref().map(|api| api.report(token, behavior));
+    }
+
+    fn send_query(&self, query: Query) {
+        let id = self.last_request.fetch_add(1, Ordering::SeqCst) as u64;
+        self.send_request(id, query, HashSet::new());
+    }
+
+    /// Sends the query to a random peer which didn't fail to answer it yet.
+    /// The query is dropped, and so is its sender, if there is no such peer.
+    fn send_request(&self, id: u64, query: Query, tried: HashSet<NodeId>) {
+        let candidates: Vec<NodeId> = self.peers.read().iter().filter(|peer| !tried.contains(*peer)).cloned().collect();
+        let peer = match thread_rng().choose(&candidates) {
+            Some(peer) => *peer,
+            None => return,
+        };
+        let message = query.message(id);
+        self.requests.lock().insert(
+            id,
+            Request {
+                query,
+                peer,
+                tried,
+                sent_at: Instant::now(),
+            },
+        );
+        self.send_message(&peer, message);
+    }
+
+    fn is_requested_from(&self, token: &NodeId, id: u64) -> bool {
+        self.requests.lock().get(&id).map_or(false, |request| request.peer == *token)
+    }
+
+    fn retry(&self, id: u64) {
+        let request = self.requests.lock().remove(&id);
+        if let Some(mut request) = request {
+            request.tried.insert(request.peer);
+            self.send_request(id, request.query, request.tried);
+        }
+    }
+
+    fn expire_requests(&self) {
+        let max_wait = StdDuration::from_secs(MAX_REQUEST_WAIT_SECONDS);
+        let expired: Vec<(u64, NodeId)> = self
+            .requests
+            .lock()
+            .iter()
+            .filter(|(_, request)| request.sent_at.elapsed() > max_wait)
+            .map(|(id, request)| (*id, request.peer))
+            .collect();
+        for (id, peer) in expired {
+            self.report(&peer, PeerBehavior::TimedOut);
+            self.retry(id);
+        }
+    }
+
+    /// Takes the request if the peer was asked for it.
+    fn take_request(&self, token: &NodeId, id: u64) -> Option<Request> {
+        let mut requests = self.requests.lock();
+        if requests.get(&id).map_or(true, |request| request.peer != *token) {
+            return None
+        }
+        requests.remove(&id)
+    }
+
+    fn on_request(&self, token: &NodeId, request: Message) {
+        let id = request.id();
+        let is_allowed =
+            self.budgets.lock().get_mut(token).map_or(false, |budget| budget.spend(cost(&request), Instant::now()));
+        if !is_allowed {
+            cinfo!(SYNC, "Refused the request from peer #{} which ran out of its budget", token);
+            self.send_message(
+                token,
+                Message::Unavailable {
+                    id,
+                },
+            );
+            return
+        }
+        let response = match request {
+            Message::GetAccount {
+                block_hash,
+                address,
+                ..
+            } => self
+                .state
+                .state_info(BlockId::Hash(block_hash))
+                .and_then(|state| state.account_proof(&address).ok())
+                .map(|proof| Message::Account {
+                    id,
+                    proof,
+                }),
+            Message::GetBody {
+                block_hash,
+                ..
+            } => self.client.block_body(BlockId::Hash(block_hash)).map(|body| Message::Body {
+                id,
+                parcels: body.parcels(),
+            }),
+            Message::GetParcel {
+                parcel_hash,
+                ..
+            } => self.client.parcel_block(ParcelId::Hash(parcel_hash)).and_then(|block_hash| {
+                self.client.block_body(BlockId::Hash(block_hash)).map(|body| Message::Parcel {
+                    id,
+                    block_hash,
+                    parcels: body.parcels(),
+                })
+            }),
+            Message::GetInvoices {
+                block_hash,
+                ..
+            } => self.client.block_invoices(BlockId::Hash(block_hash)).map(|invoices| Message::Invoices {
+                id,
+                invoices,
+            }),
+            _ => unreachable!(),
+        };
+        self.send_message(
+            token,
+            response.unwrap_or(Message::Unavailable {
+                id,
+            }),
+        );
+    }
+
+    fn on_account(&self, token: &NodeId, id: u64, proof: Vec<Bytes>) {
+        let request = match self.take_request(token, id) {
+            Some(request) => request,
+            None => return,
+        };
+        let verified = match &request.query {
+            Query::Account {
+                address,
+                state_root,
+                sender,
+                ..
+            } => verify_account_proof(state_root, address, &proof).map(|account| {
+                let _ = sender.send(account);
+            }),
+            _ => None,
+        };
+        self.on_answer(token, id, request, verified.is_some());
+    }
+
+    fn on_body(&self, token: &NodeId, id: u64, parcels: Vec<UnverifiedParcel>) {
+        let request = match self.take_request(token, id) {
+            Some(request) => request,
+            None => return,
+        };
+        let is_valid = match &request.query {
+            Query::Body {
+                parent_parcels_root,
+                parcels_root,
+                sender,
+                ..
+            } => {
+                if parcels_root_of(*parent_parcels_root, &parcels) == *parcels_root {
+                    let _ = sender.send(parcels);
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        };
+        self.on_answer(token, id, request, is_valid);
+    }
+
+    fn on_parcel(&self, token: &NodeId, id: u64, block_hash: H256, parcels: Vec<UnverifiedParcel>) {
+        let request = match self.take_request(token, id) {
+            Some(request) => request,
+            None => return,
+        };
+        let is_valid = match &request.query {
+            Query::Parcel {
+                parcel_hash,
+                sender,
+            } => {
+                // The block must be in the synchronized headers.
+                let index = self.client.block_header(BlockId::Hash(block_hash)).and_then(|header| {
+                    let parent_parcels_root = self.parent(&header).map_or(H256::zero(), |parent| parent.parcels_root());
+                    if parcels_root_of(parent_parcels_root, &parcels) != header.parcels_root() {
+                        return None
+                    }
+                    parcels.iter().position(|parcel| parcel.hash() == *parcel_hash)
+                });
+                index.map(|index| {
+                    let _ = sender.send((block_hash, index));
+                })
+            }
+            _ => None,
+        };
+        self.on_answer(token, id, request, is_valid.is_some());
+    }
+
+    fn on_invoices(&self, token: &NodeId, id: u64, invoices: Vec<Invoice>) {
+        let request = match self.take_request(token, id) {
+            Some(request) => request,
+            None => return,
+        };
+        let is_valid = match &request.query {
+            Query::Invoices {
+                parent_invoices_root,
+                invoices_root,
+                sender,
+                ..
+            } => {
+                let root =
+                    skewed_merkle_root(*parent_invoices_root, invoices.iter().map(|invoice| invoice.rlp_bytes()));
+                if root == *invoices_root {
+                    let _ = sender.send(invoices);
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        };
+        self.on_answer(token, id, request, is_valid);
+    }
+
+    fn on_answer(&self, token: &NodeId, id: u64, mut request: Request, is_valid: bool) {
+        if is_valid {
+            self.report(token, PeerBehavior::ServedValidData);
+            return
+        }
+        cinfo!(SYNC, "Peer #{} sent an answer which doesn't match the header", token);
+        self.report(token, PeerBehavior::SentInvalidData);
+        request.tried.insert(request.peer);
+        self.send_request(id, request.query, request.tried);
+    }
+}
+
+fn parcels_root_of(parent_parcels_root: H256, parcels: &[UnverifiedParcel]) -> H256 {
+    skewed_merkle_root(parent_parcels_root, parcels.iter().map(|parcel| parcel.rlp_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_is_sent_as_its_request_message() {
+        let (sender, _) = channel();
+        let block_hash = H256::random();
+        let query = Query::Body {
+            block_hash,
+            parent_parcels_root: H256::zero(),
+            parcels_root: H256::zero(),
+            sender,
+        };
+        assert_eq!(
+            Message::GetBody {
+                id: 7,
+                block_hash,
+            },
+            query.message(7)
+        );
+    }
+}
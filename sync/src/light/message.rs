@@ -0,0 +1,320 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use ccore::{Invoice, UnverifiedParcel};
+use ctypes::{Address, Bytes, H256};
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+const MESSAGE_ID_GET_ACCOUNT: u8 = 0x01;
+const MESSAGE_ID_ACCOUNT: u8 = 0x02;
+const MESSAGE_ID_GET_BODY: u8 = 0x03;
+const MESSAGE_ID_BODY: u8 = 0x04;
+const MESSAGE_ID_UNAVAILABLE: u8 = 0x05;
+const MESSAGE_ID_GET_PARCEL: u8 = 0x06;
+const MESSAGE_ID_PARCEL: u8 = 0x07;
+const MESSAGE_ID_GET_INVOICES: u8 = 0x08;
+const MESSAGE_ID_INVOICES: u8 = 0x09;
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    GetAccount {
+        id: u64,
+        block_hash: H256,
+        address: Address,
+    },
+    /// The nodes of the world trie on the path to the account
+    Account {
+        id: u64,
+        proof: Vec<Bytes>,
+    },
+    GetBody {
+        id: u64,
+        block_hash: H256,
+    },
+    Body {
+        id: u64,
+        parcels: Vec<UnverifiedParcel>,
+    },
+    /// The peer doesn't have the block or its state.
+    Unavailable {
+        id: u64,
+    },
+    GetParcel {
+        id: u64,
+        parcel_hash: H256,
+    },
+    /// The body of the block which has the parcel
+    Parcel {
+        id: u64,
+        block_hash: H256,
+        parcels: Vec<UnverifiedParcel>,
+    },
+    GetInvoices {
+        id: u64,
+        block_hash: H256,
+    },
+    /// The invoices of all the transactions in the block
+    Invoices {
+        id: u64,
+        invoices: Vec<Invoice>,
+    },
+}
+
+impl Message {
+    pub fn id(&self) -> u64 {
+        match self {
+            Message::GetAccount {
+                id,
+                ..
+            }
+            | Message::Account {
+                id,
+                ..
+            }
+            | Message::GetBody {
+                id,
+                ..
+            }
+            | Message::Body {
+                id,
+                ..
+            }
+            | Message::Unavailable {
+                id,
+            }
+            | Message::GetParcel {
+                id,
+                ..
+            }
+            | Message::Parcel {
+                id,
+                ..
+            }
+            | Message::GetInvoices {
+                id,
+                ..
+            }
+            | Message::Invoices {
+                id,
+                ..
+            } => *id,
+        }
+    }
+
+    pub fn is_request(&self) -> bool {
+        match self {
+            Message::GetAccount {
+                ..
+            }
+            | Message::GetBody {
+                ..
+            }
+            | Message::GetParcel {
+                ..
+            }
+            | Message::GetInvoices {
+                ..
+            } => true,
+            _ => false,
+        }
+    }
+}
+
+impl Encodable for Message {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            Message::GetAccount {
+                id,
+                block_hash,
+                address,
+            } => {
+                s.begin_list(4).append(&MESSAGE_ID_GET_ACCOUNT).append(id).append(block_hash).append(address);
+            }
+            Message::Account {
+                id,
+                proof,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_ACCOUNT).append(id);
+                s.begin_list(proof.len());
+                for node in proof {
+                    s.append(node);
+                }
+            }
+            Message::GetBody {
+                id,
+                block_hash,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_GET_BODY).append(id).append(block_hash);
+            }
+            Message::Body {
+                id,
+                parcels,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_BODY).append(id).append_list(parcels);
+            }
+            Message::Unavailable {
+                id,
+            } => {
+                s.begin_list(2).append(&MESSAGE_ID_UNAVAILABLE).append(id);
+            }
+            Message::GetParcel {
+                id,
+                parcel_hash,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_GET_PARCEL).append(id).append(parcel_hash);
+            }
+            Message::Parcel {
+                id,
+                block_hash,
+                parcels,
+            } => {
+                s.begin_list(4).append(&MESSAGE_ID_PARCEL).append(id).append(block_hash).append_list(parcels);
+            }
+            Message::GetInvoices {
+                id,
+                block_hash,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_GET_INVOICES).append(id).append(block_hash);
+            }
+            Message::Invoices {
+                id,
+                invoices,
+            } => {
+                s.begin_list(3).append(&MESSAGE_ID_INVOICES).append(id).append_list(invoices);
+            }
+        };
+    }
+}
+
+impl Decodable for Message {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let message_id: u8 = rlp.val_at(0)?;
+        let expected_len = match message_id {
+            MESSAGE_ID_GET_ACCOUNT | MESSAGE_ID_PARCEL => 4,
+            MESSAGE_ID_ACCOUNT | MESSAGE_ID_GET_BODY | MESSAGE_ID_BODY => 3,
+            MESSAGE_ID_GET_PARCEL | MESSAGE_ID_GET_INVOICES | MESSAGE_ID_INVOICES => 3,
+            MESSAGE_ID_UNAVAILABLE => 2,
+            _ => return Err(DecoderError::Custom("Unknown message id detected")),
+        };
+        if rlp.item_count()? != expected_len {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let id = rlp.val_at(1)?;
+        Ok(match message_id {
+            MESSAGE_ID_GET_ACCOUNT => Message::GetAccount {
+                id,
+                block_hash: rlp.val_at(2)?,
+                address: rlp.val_at(3)?,
+            },
+            MESSAGE_ID_ACCOUNT => Message::Account {
+                id,
+                proof: rlp.at(2)?.iter().map(|node| node.as_val()).collect::<Result<_, _>>()?,
+            },
+            MESSAGE_ID_GET_BODY => Message::GetBody {
+                id,
+                block_hash: rlp.val_at(2)?,
+            },
+            MESSAGE_ID_BODY => Message::Body {
+                id,
+                parcels: rlp.list_at(2)?,
+            },
+            MESSAGE_ID_UNAVAILABLE => Message::Unavailable {
+                id,
+            },
+            MESSAGE_ID_GET_PARCEL => Message::GetParcel {
+                id,
+                parcel_hash: rlp.val_at(2)?,
+            },
+            MESSAGE_ID_PARCEL => Message::Parcel {
+                id,
+                block_hash: rlp.val_at(2)?,
+                parcels: rlp.list_at(3)?,
+            },
+            MESSAGE_ID_GET_INVOICES => Message::GetInvoices {
+                id,
+                block_hash: rlp.val_at(2)?,
+            },
+            MESSAGE_ID_INVOICES => Message::Invoices {
+                id,
+                invoices: rlp.list_at(2)?,
+            },
+            _ => unreachable!(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::Encodable;
+
+    use super::*;
+
+    #[test]
+    fn test_get_account_message_rlp() {
+        let message = Message::GetAccount {
+            id: 3,
+            block_hash: H256::random(),
+            address: Address::random(),
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_account_message_rlp() {
+        let message = Message::Account {
+            id: 3,
+            proof: vec![vec![1, 2, 3], vec![], vec![4; 40]],
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_body_message_rlp() {
+        let message = Message::Body {
+            id: 3,
+            parcels: Vec::new(),
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_parcel_message_rlp() {
+        let message = Message::Parcel {
+            id: 3,
+            block_hash: H256::random(),
+            parcels: Vec::new(),
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_get_invoices_message_rlp() {
+        let message = Message::GetInvoices {
+            id: 3,
+            block_hash: H256::random(),
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_unavailable_message_rlp() {
+        let message = Message::Unavailable {
+            id: 3,
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+}
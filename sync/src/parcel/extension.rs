@@ -17,23 +17,36 @@
 use parking_lot::{Mutex, RwLock};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
 
-use ccore::BlockChainClient;
-use cnetwork::{Api, NetworkExtension, NodeId, TimerToken};
+use ccore::{BlockChainClient, ParcelId, TransactionQueueClient};
+use cnetwork::{Api, NetworkExtension, NodeId, PeerBehavior, TimerToken};
 use ctypes::H256;
+use rand::{thread_rng, Rng};
 use rlp::{Encodable, UntrustedRlp};
 use time::Duration;
 
 use super::message::Message;
 
 const EXTENSION_NAME: &'static str = "parcel-propagation";
+// The version 1 tags the messages with their ids to announce the hashes before sending the parcels.
+const EXTENSION_VERSION: u64 = 1;
 const BROADCAST_TIMER_TOKEN: TimerToken = 0;
 const BROADCAST_TIMER_INTERVAL: i64 = 1000;
 const MAX_HISTORY_SIZE: usize = 100;
+// Locally submitted parcels are held back for a random number of broadcast ticks, chosen independently for every
+// peer, so that the first peer to hear about a parcel is not necessarily a neighbour of its origin.
+const MAX_DIFFUSION_TICKS: usize = 5;
+const MAX_RELAYED_SIZE: usize = 10_000;
+const MAX_REQUESTED_SIZE: usize = 10_000;
+const MAX_REQUESTED_PER_PEER: usize = 1_000;
+// The parcels which are not received in this time can be requested from other peers.
+const MAX_REQUEST_WAIT_SECONDS: u64 = 10;
 
 struct Peer {
     history_set: HashSet<H256>,
     history_queue: VecDeque<H256>,
+    delayed: HashMap<H256, usize>,
 }
 
 impl Peer {
@@ -41,6 +54,7 @@ impl Peer {
         Self {
             history_set: HashSet::new(),
             history_queue: VecDeque::new(),
+            delayed: HashMap::new(),
         }
     }
 
@@ -57,20 +71,134 @@ impl Peer {
     fn contains(&mut self, hash: &H256) -> bool {
         self.history_set.contains(hash)
     }
+
+    /// Returns true once the parcel has waited out its delay for this peer.
+    /// The delay is drawn from `new_delay` when the parcel is seen for the first time.
+    fn is_released<F>(&mut self, hash: &H256, new_delay: F) -> bool
+    where
+        F: FnOnce() -> usize, {
+        let remaining = self.delayed.entry(*hash).or_insert_with(new_delay);
+        if *remaining == 0 {
+            self.delayed.remove(hash);
+            return true
+        }
+        *remaining -= 1;
+        false
+    }
+
+    fn retain_delayed(&mut self, pending: &HashSet<H256>) {
+        self.delayed.retain(|hash, _| pending.contains(hash));
+    }
+}
+
+/// Hashes of the parcels that arrived from peers.
+/// Everything else in the queue is treated as submitted locally.
+struct Relayed {
+    set: HashSet<H256>,
+    queue: VecDeque<H256>,
+}
+
+impl Relayed {
+    fn new() -> Self {
+        Self {
+            set: HashSet::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, hash: H256) {
+        if self.set.insert(hash) {
+            self.queue.push_back(hash);
+            if self.queue.len() > MAX_RELAYED_SIZE {
+                let oldest = self.queue.pop_front().expect("The queue is not empty");
+                self.set.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, hash: &H256) -> bool {
+        self.set.contains(hash)
+    }
+}
+
+/// The announced parcels that were requested and not received yet, with the peers they were requested from.
+/// A parcel is requested from one peer at a time.
+struct Requested {
+    peers: HashMap<H256, (NodeId, Instant)>,
+    counts: HashMap<NodeId, usize>,
+}
+
+impl Requested {
+    fn new() -> Self {
+        Self {
+            peers: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Returns false if the parcel is requested already or too many parcels are waiting, in total or from the peer.
+    fn insert(&mut self, hash: H256, token: NodeId, now: Instant) -> bool {
+        if self.peers.len() >= MAX_REQUESTED_SIZE || self.peers.contains_key(&hash) {
+            return false
+        }
+        let count = self.counts.entry(token).or_insert(0);
+        if *count >= MAX_REQUESTED_PER_PEER {
+            return false
+        }
+        *count += 1;
+        self.peers.insert(hash, (token, now));
+        true
+    }
+
+    /// Forgets every parcel requested from the peer, so that others can be asked for it.
+    /// A response doesn't need to contain all the requested parcels, the missing ones are abandoned.
+    fn release(&mut self, token: &NodeId) {
+        self.peers.retain(|_, (requested_from, _)| *requested_from != *token);
+        self.counts.remove(token);
+    }
+
+    /// Forgets the parcels which the peers didn't send in time.
+    fn expire(&mut self, now: Instant) {
+        let counts = &mut self.counts;
+        self.peers.retain(|_, (requested_from, requested_at)| {
+            if now.duration_since(*requested_at) < StdDuration::from_secs(MAX_REQUEST_WAIT_SECONDS) {
+                return true
+            }
+            let is_empty = {
+                let count = counts.get_mut(requested_from).expect("Every requested parcel is counted");
+                *count -= 1;
+                *count == 0
+            };
+            if is_empty {
+                counts.remove(requested_from);
+            }
+            false
+        });
+    }
 }
 
 pub struct Extension {
     peers: RwLock<HashMap<NodeId, Peer>>,
+    relayed: Mutex<Relayed>,
+    requested: Mutex<Requested>,
     client: Arc<BlockChainClient>,
+    queue: Arc<TransactionQueueClient>,
     api: Mutex<Option<Arc<Api>>>,
+    diffusion: bool,
 }
 
 impl Extension {
-    pub fn new(client: Arc<BlockChainClient>) -> Arc<Self> {
+    /// When `diffusion` is false, local parcels are sent to every peer on the next broadcast.
+    /// That is fine for consortium chains where the origin of a parcel is not a secret.
+    pub fn new(client: Arc<BlockChainClient>, queue: Arc<TransactionQueueClient>, diffusion: bool) -> Arc<Self> {
         Arc::new(Self {
             peers: RwLock::new(HashMap::new()),
+            relayed: Mutex::new(Relayed::new()),
+            requested: Mutex::new(Requested::new()),
             client,
+            queue,
             api: Mutex::new(None),
+            diffusion,
         })
     }
 }
@@ -82,6 +210,9 @@ impl NetworkExtension for Extension {
     fn need_encryption(&self) -> bool {
         false
     }
+    fn version(&self) -> u64 {
+        EXTENSION_VERSION
+    }
 
     fn on_initialize(&self, api: Arc<Api>) {
         api.set_timer(BROADCAST_TIMER_TOKEN, Duration::milliseconds(BROADCAST_TIMER_INTERVAL))
@@ -94,6 +225,7 @@ impl NetworkExtension for Extension {
     }
     fn on_node_removed(&self, token: &NodeId) {
         self.peers.write().remove(token);
+        self.requested.lock().release(token);
     }
 
     fn on_negotiated(&self, token: &NodeId) {
@@ -106,8 +238,15 @@ impl NetworkExtension for Extension {
     fn on_message(&self, token: &NodeId, data: &[u8]) {
         if let Ok(received_message) = UntrustedRlp::new(data).as_val() {
             match received_message {
+                Message::Hashes(hashes) => self.on_hashes(token, hashes),
+                Message::GetParcels(hashes) => self.on_get_parcels(token, hashes),
                 Message::Parcels(parcels) => {
-                    self.client.queue_parcels(
+                    {
+                        let mut relayed = self.relayed.lock();
+                        parcels.iter().for_each(|unverified| relayed.insert(unverified.hash()));
+                    }
+                    self.requested.lock().release(token);
+                    self.queue.queue_parcels(
                         parcels.iter().map(|unverified| unverified.rlp_bytes().to_vec()).collect(),
                         *token,
                     );
@@ -120,12 +259,16 @@ impl NetworkExtension for Extension {
             }
         } else {
             cinfo!(SYNC, "invalid message from peer {}", token);
+            self.api.lock().as_ref().map(|api| api.report(token, PeerBehavior::SentInvalidData));
         }
     }
 
     fn on_timeout(&self, timer: TimerToken) {
         match timer {
-            BROADCAST_TIMER_TOKEN => self.random_broadcast(),
+            BROADCAST_TIMER_TOKEN => {
+                self.requested.lock().expire(Instant::now());
+                self.random_broadcast();
+            }
             _ => debug_assert!(false),
         }
     }
@@ -138,18 +281,181 @@ impl Extension {
         });
     }
 
+    fn on_hashes(&self, token: &NodeId, hashes: Vec<H256>) {
+        if let Some(peer) = self.peers.write().get_mut(token) {
+            hashes.iter().for_each(|hash| peer.push(hash));
+        } else {
+            return
+        }
+        let pending: HashSet<H256> = self.queue.ready_parcels().iter().map(|parcel| parcel.hash()).collect();
+        let now = Instant::now();
+        let unknown: Vec<H256> = {
+            let relayed = self.relayed.lock();
+            let mut requested = self.requested.lock();
+            hashes
+                .into_iter()
+                .filter(|hash| !pending.contains(hash) && !relayed.contains(hash))
+                .filter(|hash| self.client.parcel_block(ParcelId::Hash(*hash)).is_none())
+                .filter(|hash| requested.insert(*hash, *token, now))
+                .collect()
+        };
+        if !unknown.is_empty() {
+            self.send_message(token, Message::GetParcels(unknown));
+        }
+    }
+
+    fn on_get_parcels(&self, token: &NodeId, hashes: Vec<H256>) {
+        let requested: HashSet<H256> = hashes.into_iter().collect();
+        let parcels: Vec<_> = self
+            .queue
+            .ready_parcels()
+            .into_iter()
+            .filter(|parcel| requested.contains(&parcel.hash()))
+            .map(|signed| signed.deconstruct().0)
+            .collect();
+        if let Some(peer) = self.peers.write().get_mut(token) {
+            parcels.iter().for_each(|unverified| peer.push(&unverified.hash()));
+        }
+        // The empty response is sent too, so that the peer can ask someone else for the missing parcels.
+        self.send_message(token, Message::Parcels(parcels));
+    }
+
     fn random_broadcast(&self) {
-        let parcels = self.client.ready_parcels();
+        let parcels = self.queue.ready_parcels();
+        let local: HashSet<H256> = if self.diffusion {
+            let relayed = self.relayed.lock();
+            parcels.iter().map(|parcel| parcel.hash()).filter(|hash| !relayed.contains(hash)).collect()
+        } else {
+            HashSet::new()
+        };
+        let mut rng = thread_rng();
         for (token, peer) in self.peers.write().iter_mut() {
-            let unsent: Vec<_> = parcels
+            peer.retain_delayed(&local);
+            let unsent: Vec<H256> = parcels
                 .iter()
-                .filter(|parcel| !peer.contains(&parcel.hash()))
-                .map(|signed| signed.clone().deconstruct().0)
+                .map(|parcel| parcel.hash())
+                .filter(|hash| {
+                    if peer.contains(hash) {
+                        return false
+                    }
+                    !local.contains(hash) || peer.is_released(hash, || rng.gen_range(0, MAX_DIFFUSION_TICKS + 1))
+                })
                 .collect();
-            for unverified in unsent.iter() {
-                peer.push(&unverified.hash());
+            if unsent.is_empty() {
+                continue
+            }
+            for hash in unsent.iter() {
+                peer.push(hash);
             }
-            self.send_message(token, Message::Parcels(unsent));
+            self.send_message(token, Message::Hashes(unsent));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parcel_is_released_after_its_delay() {
+        let mut peer = Peer::new();
+        let hash = H256::random();
+        assert!(!peer.is_released(&hash, || 2));
+        assert!(!peer.is_released(&hash, || unreachable!()));
+        assert!(peer.is_released(&hash, || unreachable!()));
+        assert!(peer.delayed.is_empty());
+    }
+
+    #[test]
+    fn parcel_without_delay_is_released_immediately() {
+        let mut peer = Peer::new();
+        assert!(peer.is_released(&H256::random(), || 0));
+    }
+
+    #[test]
+    fn delays_of_parcels_that_left_the_queue_are_dropped() {
+        let mut peer = Peer::new();
+        let kept = H256::random();
+        let dropped = H256::random();
+        assert!(!peer.is_released(&kept, || 3));
+        assert!(!peer.is_released(&dropped, || 3));
+
+        let mut pending = HashSet::new();
+        pending.insert(kept);
+        peer.retain_delayed(&pending);
+
+        assert!(peer.delayed.contains_key(&kept));
+        assert!(!peer.delayed.contains_key(&dropped));
+    }
+
+    #[test]
+    fn relayed_forgets_the_oldest_hash() {
+        let mut relayed = Relayed::new();
+        let first = H256::random();
+        relayed.insert(first);
+        for _ in 0..MAX_RELAYED_SIZE {
+            relayed.insert(H256::random());
+        }
+        assert!(!relayed.contains(&first));
+        assert_eq!(MAX_RELAYED_SIZE, relayed.set.len());
+    }
+
+    #[test]
+    fn parcel_is_requested_from_one_peer_at_a_time() {
+        let mut requested = Requested::new();
+        let now = Instant::now();
+        let hash = H256::random();
+        let first = H256::random();
+        let second = H256::random();
+        assert!(requested.insert(hash, first, now));
+        assert!(!requested.insert(hash, second, now));
+
+        requested.release(&second);
+        assert!(!requested.insert(hash, second, now));
+
+        requested.release(&first);
+        assert!(requested.insert(hash, second, now));
+    }
+
+    #[test]
+    fn requested_is_bounded() {
+        let mut requested = Requested::new();
+        let now = Instant::now();
+        for _ in 0..MAX_REQUESTED_SIZE {
+            assert!(requested.insert(H256::random(), H256::random(), now));
+        }
+        assert!(!requested.insert(H256::random(), H256::random(), now));
+    }
+
+    #[test]
+    fn requested_from_a_peer_is_bounded() {
+        let mut requested = Requested::new();
+        let now = Instant::now();
+        let peer = H256::random();
+        for _ in 0..MAX_REQUESTED_PER_PEER {
+            assert!(requested.insert(H256::random(), peer, now));
         }
+        assert!(!requested.insert(H256::random(), peer, now));
+        assert!(requested.insert(H256::random(), H256::random(), now));
+
+        requested.release(&peer);
+        assert!(requested.insert(H256::random(), peer, now));
+    }
+
+    #[test]
+    fn unanswered_request_expires() {
+        let mut requested = Requested::new();
+        let requested_at = Instant::now();
+        let hash = H256::random();
+        let peer = H256::random();
+        let other = H256::random();
+        assert!(requested.insert(hash, peer, requested_at));
+
+        requested.expire(requested_at + StdDuration::from_secs(MAX_REQUEST_WAIT_SECONDS - 1));
+        assert!(!requested.insert(hash, other, requested_at));
+
+        requested.expire(requested_at + StdDuration::from_secs(MAX_REQUEST_WAIT_SECONDS));
+        assert!(requested.counts.is_empty());
+        assert!(requested.insert(hash, other, requested_at));
     }
 }
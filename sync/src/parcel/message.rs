@@ -15,29 +15,61 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use ccore::UnverifiedParcel;
+use ctypes::H256;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+const MESSAGE_ID_HASHES: u8 = 0x01;
+const MESSAGE_ID_GET_PARCELS: u8 = 0x02;
+const MESSAGE_ID_PARCELS: u8 = 0x03;
+
 #[derive(Debug, PartialEq)]
 pub enum Message {
+    /// Announces the parcels that the sender has.
+    Hashes(Vec<H256>),
+    /// Requests the announced parcels that the sender doesn't know yet.
+    GetParcels(Vec<H256>),
     Parcels(Vec<UnverifiedParcel>),
 }
 
 impl Encodable for Message {
     fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
         match self {
-            &Message::Parcels(ref parcels) => s.append_list(parcels),
+            Message::Hashes(hashes) => {
+                s.append(&MESSAGE_ID_HASHES);
+                s.append_list(hashes);
+            }
+            Message::GetParcels(hashes) => {
+                s.append(&MESSAGE_ID_GET_PARCELS);
+                s.append_list(hashes);
+            }
+            Message::Parcels(parcels) => {
+                s.append(&MESSAGE_ID_PARCELS);
+                s.append_list(parcels);
+            }
         };
     }
 }
 
 impl Decodable for Message {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        Ok(Message::Parcels(rlp.as_list()?))
+        if rlp.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        let id: u8 = rlp.val_at(0)?;
+        let message = rlp.at(1)?;
+        match id {
+            MESSAGE_ID_HASHES => Ok(Message::Hashes(message.as_list()?)),
+            MESSAGE_ID_GET_PARCELS => Ok(Message::GetParcels(message.as_list()?)),
+            MESSAGE_ID_PARCELS => Ok(Message::Parcels(message.as_list()?)),
+            _ => Err(DecoderError::Custom("Unknown message id detected")),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ctypes::H256;
     use rlp::Encodable;
 
     use super::Message;
@@ -47,4 +79,24 @@ mod tests {
         let message = Message::Parcels(Vec::new());
         assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
     }
+
+    #[test]
+    fn test_hashes_message_rlp() {
+        let message = Message::Hashes(vec![H256::random(), H256::random()]);
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_get_parcels_message_rlp() {
+        let message = Message::GetParcels(vec![H256::random()]);
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn message_with_unknown_id_is_rejected() {
+        let mut s = ::rlp::RlpStream::new_list(2);
+        s.append(&0xffu8);
+        s.begin_list(0);
+        assert!(::rlp::UntrustedRlp::new(&s.out()).as_val::<Message>().is_err());
+    }
 }
@@ -0,0 +1,369 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use ccore::{BlockId, ManifestData, SignedManifest, SnapshotClient, SnapshotService, StateRebuilder, SNAPSHOT_VERSION};
+use ccrypto::blake256;
+use cnetwork::{Api, NetworkExtension, NodeId, PeerBehavior, TimerToken};
+use ctypes::{Address, Bytes, H256};
+use rlp::{Encodable, UntrustedRlp};
+use time::Duration;
+
+use super::super::BlockSyncExtension;
+use super::message::Message;
+
+const EXTENSION_NAME: &'static str = "snapshot";
+const SYNC_TIMER_TOKEN: TimerToken = 0;
+const SYNC_TIMER_INTERVAL: i64 = 1000;
+// The node falls back to the full sync if no peer offers a trusted snapshot within this many ticks.
+const MAX_SEARCH_TICKS: usize = 60;
+// The node falls back to the full sync if no chunk arrives within this many ticks during the restoration.
+const MAX_RESTORE_IDLE_TICKS: usize = 60;
+
+struct Restoration {
+    manifest: ManifestData,
+    rebuilder: StateRebuilder,
+    pending: VecDeque<H256>,
+    downloading: HashMap<NodeId, H256>,
+    /// The peers which offered the same snapshot.
+    sources: HashSet<NodeId>,
+    block: Option<Bytes>,
+    /// The ticks since the last chunk arrived.
+    idle_ticks: usize,
+}
+
+impl Restoration {
+    fn new(manifest: ManifestData, rebuilder: StateRebuilder, source: NodeId) -> Self {
+        let mut sources = HashSet::new();
+        sources.insert(source);
+        Self {
+            pending: manifest.chunks().into_iter().collect(),
+            manifest,
+            rebuilder,
+            downloading: HashMap::new(),
+            sources,
+            block: None,
+            idle_ticks: 0,
+        }
+    }
+
+    fn is_downloaded(&self) -> bool {
+        self.pending.is_empty() && self.downloading.is_empty()
+    }
+
+    fn remove_source(&mut self, token: &NodeId) {
+        self.sources.remove(token);
+        if let Some(hash) = self.downloading.remove(token) {
+            self.pending.push_front(hash);
+        }
+    }
+}
+
+enum Warp {
+    /// Looking for a snapshot signed by one of the trusted signers.
+    Searching(usize),
+    Restoring(Restoration),
+    Finished,
+}
+
+/// Serves the snapshots taken by this node, and restores a snapshot on a node which starts from the genesis block.
+/// The block sync doesn't download the bodies until the restoration is finished.
+pub struct Extension {
+    client: Arc<SnapshotClient>,
+    snapshot: Option<Arc<SnapshotService>>,
+    block_sync: Option<Arc<BlockSyncExtension>>,
+    warp_signers: Vec<Address>,
+    warp: Mutex<Warp>,
+    peers: RwLock<HashSet<NodeId>>,
+    api: Mutex<Option<Arc<Api>>>,
+}
+
+impl Extension {
+    /// The node restores a snapshot only if `warp_signers` is not empty and it has only the genesis block.
+    pub fn new(
+        client: Arc<SnapshotClient>,
+        snapshot: Option<Arc<SnapshotService>>,
+        block_sync: Option<Arc<BlockSyncExtension>>,
+        warp_signers: Vec<Address>,
+    ) -> Arc<Self> {
+        let warp = if !warp_signers.is_empty() && client.chain_info().best_block_number == 0 {
+            block_sync.as_ref().map(|sync| sync.pause_body_download(true));
+            Warp::Searching(0)
+        } else {
+            Warp::Finished
+        };
+        Arc::new(Self {
+            client,
+            snapshot,
+            block_sync,
+            warp_signers,
+            warp: Mutex::new(warp),
+            peers: RwLock::new(HashSet::new()),
+            api: Mutex::new(None),
+        })
+    }
+
+    fn send_message(&self, token: &NodeId, message: Message) {
+        self.api.lock().as_ref().map(|api| {
+            api.send(token, &message.rlp_bytes().to_vec());
+        });
+    }
+
+    fn report(&self, token: &NodeId, behavior: PeerBehavior) {
+        self.api.lock().as_ref().map(|api| api.report(token, behavior));
+    }
+
+    fn finish(&self, warp: &mut Warp) {
+        *warp = Warp::Finished;
+        self.block_sync.as_ref().map(|sync| sync.pause_body_download(false));
+    }
+}
+
+impl NetworkExtension for Extension {
+    fn name(&self) -> String {
+        String::from(EXTENSION_NAME)
+    }
+    fn need_encryption(&self) -> bool {
+        false
+    }
+
+    fn on_initialize(&self, api: Arc<Api>) {
+        api.set_timer(SYNC_TIMER_TOKEN, Duration::milliseconds(SYNC_TIMER_INTERVAL)).expect("Timer set succeeds");
+        *self.api.lock() = Some(api);
+    }
+
+    fn on_node_added(&self, token: &NodeId) {
+        self.api.lock().as_ref().map(|api| api.negotiate(token));
+    }
+    fn on_node_removed(&self, token: &NodeId) {
+        self.peers.write().remove(token);
+        if let Warp::Restoring(restoration) = &mut *self.warp.lock() {
+            restoration.remove_source(token);
+        }
+    }
+
+    fn on_negotiated(&self, token: &NodeId) {
+        self.peers.write().insert(*token);
+        if let Warp::Searching(_) = &*self.warp.lock() {
+            self.send_message(token, Message::RequestManifest);
+        }
+    }
+    fn on_negotiation_allowed(&self, token: &NodeId) {
+        self.on_negotiated(token);
+    }
+
+    fn on_message(&self, token: &NodeId, data: &[u8]) {
+        if let Ok(message) = UntrustedRlp::new(data).as_val() {
+            match message {
+                Message::RequestManifest => {
+                    let manifest = self.snapshot.as_ref().and_then(|snapshot| {
+                        snapshot.with_latest(|reader| reader.manifest().clone())
+                    });
+                    self.send_message(token, Message::Manifest(manifest));
+                }
+                Message::RequestChunk(hash) => {
+                    let chunk = self.snapshot.as_ref().and_then(|snapshot| {
+                        snapshot.with_latest(|reader| reader.chunk(&hash).ok().and_then(|chunk| chunk))
+                    });
+                    self.send_message(token, Message::Chunk(hash, chunk.and_then(|chunk| chunk)));
+                }
+                Message::Manifest(Some(manifest)) => self.on_manifest(token, manifest),
+                Message::Manifest(None) => {}
+                Message::Chunk(hash, chunk) => self.on_chunk(token, hash, chunk),
+            }
+        } else {
+            cinfo!(SYNC, "invalid message from peer {}", token);
+            self.report(token, PeerBehavior::SentInvalidData);
+        }
+    }
+
+    fn on_timeout(&self, timer: TimerToken) {
+        match timer {
+            SYNC_TIMER_TOKEN => self.sync(),
+            _ => debug_assert!(false),
+        }
+    }
+}
+
+impl Extension {
+    fn sync(&self) {
+        let mut warp = self.warp.lock();
+        let is_timed_out = match &mut *warp {
+            Warp::Searching(ticks) => {
+                *ticks += 1;
+                if *ticks < MAX_SEARCH_TICKS {
+                    self.request_manifests();
+                    return
+                }
+                cinfo!(SYNC, "No peer offers a trusted snapshot, falls back to the full sync");
+                true
+            }
+            Warp::Restoring(restoration) => {
+                restoration.idle_ticks += 1;
+                if restoration.idle_ticks < MAX_RESTORE_IDLE_TICKS {
+                    if restoration.sources.is_empty() {
+                        // The peers which offer the same snapshot become the sources again.
+                        self.request_manifests();
+                    }
+                    self.request_chunks(restoration);
+                    false
+                } else {
+                    cinfo!(SYNC, "No chunk of the snapshot arrives, falls back to the full sync");
+                    true
+                }
+            }
+            Warp::Finished => return,
+        };
+        if is_timed_out {
+            self.finish(&mut warp);
+        } else {
+            self.try_restore(&mut warp);
+        }
+    }
+
+    fn request_manifests(&self) {
+        for token in self.peers.read().iter() {
+            self.send_message(token, Message::RequestManifest);
+        }
+    }
+
+    fn on_manifest(&self, from: &NodeId, signed: SignedManifest) {
+        match signed.signer() {
+            Ok(signer) if self.warp_signers.contains(&signer) => {}
+            _ => {
+                cdebug!(SYNC, "Peer #{} offers a snapshot from an untrusted signer", from);
+                return
+            }
+        }
+        let manifest = signed.manifest;
+        if manifest.version != SNAPSHOT_VERSION {
+            cdebug!(SYNC, "Peer #{} offers a snapshot of unsupported version {}", from, manifest.version);
+            return
+        }
+
+        let mut warp = self.warp.lock();
+        match &mut *warp {
+            Warp::Searching(_) => {}
+            Warp::Restoring(restoration) => {
+                if restoration.manifest == manifest {
+                    restoration.sources.insert(*from);
+                    self.request_chunks(restoration);
+                }
+                return
+            }
+            Warp::Finished => return,
+        }
+        cinfo!(SYNC, "Start restoring the snapshot at #{} from peer #{}", manifest.block_number, from);
+        let mut restoration = Restoration::new(manifest, self.client.state_rebuilder(), *from);
+        self.request_chunks(&mut restoration);
+        *warp = Warp::Restoring(restoration);
+    }
+
+    fn on_chunk(&self, from: &NodeId, hash: H256, chunk: Option<Bytes>) {
+        let mut warp = self.warp.lock();
+        let failed = match &mut *warp {
+            Warp::Restoring(restoration) => {
+                if restoration.downloading.get(from) != Some(&hash) {
+                    return
+                }
+                restoration.downloading.remove(from);
+                match chunk {
+                    Some(ref chunk) if blake256(chunk) == hash => {}
+                    Some(_) => {
+                        cinfo!(SYNC, "Peer #{} sent an invalid chunk {}", from, hash);
+                        self.report(from, PeerBehavior::SentInvalidData);
+                        restoration.pending.push_front(hash);
+                        restoration.sources.remove(from);
+                        return
+                    }
+                    None => {
+                        restoration.pending.push_front(hash);
+                        restoration.sources.remove(from);
+                        return
+                    }
+                }
+                let chunk = chunk.expect("The chunk is checked above");
+                let fed = if hash == restoration.manifest.block_chunk {
+                    restoration.block = Some(chunk);
+                    Ok(())
+                } else {
+                    restoration.rebuilder.feed(&chunk)
+                };
+                match fed {
+                    Ok(()) => {
+                        restoration.idle_ticks = 0;
+                        self.request_chunks(restoration);
+                        false
+                    }
+                    Err(err) => {
+                        cwarn!(SYNC, "Cannot restore the snapshot: {}, falls back to the full sync", err);
+                        true
+                    }
+                }
+            }
+            _ => return,
+        };
+        if failed {
+            self.finish(&mut warp);
+        } else {
+            self.try_restore(&mut warp);
+        }
+    }
+
+    // Every source downloads one chunk at a time.
+    fn request_chunks(&self, restoration: &mut Restoration) {
+        let idle: Vec<_> =
+            restoration.sources.iter().filter(|token| !restoration.downloading.contains_key(*token)).cloned().collect();
+        for token in idle {
+            let hash = match restoration.pending.pop_front() {
+                Some(hash) => hash,
+                None => break,
+            };
+            restoration.downloading.insert(token, hash);
+            self.send_message(&token, Message::RequestChunk(hash));
+        }
+    }
+
+    // The snapshot is restored once every chunk is downloaded and the header of its block is imported.
+    fn try_restore(&self, warp: &mut Warp) {
+        match &*warp {
+            Warp::Restoring(restoration) => {
+                if !restoration.is_downloaded() {
+                    return
+                }
+                if self.client.block_header(BlockId::Hash(restoration.manifest.block_hash)).is_none() {
+                    cdebug!(SYNC, "Waiting for the header of the snapshot block");
+                    return
+                }
+            }
+            _ => return,
+        }
+        let restoration = match mem::replace(warp, Warp::Finished) {
+            Warp::Restoring(restoration) => restoration,
+            _ => unreachable!(),
+        };
+        let block = restoration.block.expect("The block chunk is downloaded");
+        match self.client.restore_snapshot(&restoration.manifest, restoration.rebuilder, &block) {
+            Ok(()) => cinfo!(SYNC, "Restored the snapshot at #{}", restoration.manifest.block_number),
+            Err(err) => cwarn!(SYNC, "Cannot restore the snapshot: {}, falls back to the full sync", err),
+        }
+        self.finish(warp);
+    }
+}
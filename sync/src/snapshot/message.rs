@@ -0,0 +1,124 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use ccore::SignedManifest;
+use ctypes::{Bytes, H256};
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+const MESSAGE_ID_GET_MANIFEST: u8 = 0x01;
+const MESSAGE_ID_MANIFEST: u8 = 0x02;
+const MESSAGE_ID_GET_CHUNK: u8 = 0x03;
+const MESSAGE_ID_CHUNK: u8 = 0x04;
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    RequestManifest,
+    /// `None` if the peer has no snapshot.
+    Manifest(Option<SignedManifest>),
+    RequestChunk(H256),
+    /// `None` if the peer doesn't have the chunk.
+    Chunk(H256, Option<Bytes>),
+}
+
+impl Encodable for Message {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            Message::RequestManifest => {
+                s.begin_list(1);
+                s.append(&MESSAGE_ID_GET_MANIFEST);
+            }
+            Message::Manifest(None) => {
+                s.begin_list(1);
+                s.append(&MESSAGE_ID_MANIFEST);
+            }
+            Message::Manifest(Some(manifest)) => {
+                s.begin_list(2);
+                s.append(&MESSAGE_ID_MANIFEST);
+                s.append(manifest);
+            }
+            Message::RequestChunk(hash) => {
+                s.begin_list(2);
+                s.append(&MESSAGE_ID_GET_CHUNK);
+                s.append(hash);
+            }
+            Message::Chunk(hash, None) => {
+                s.begin_list(2);
+                s.append(&MESSAGE_ID_CHUNK);
+                s.append(hash);
+            }
+            Message::Chunk(hash, Some(chunk)) => {
+                s.begin_list(3);
+                s.append(&MESSAGE_ID_CHUNK);
+                s.append(hash);
+                s.append(chunk);
+            }
+        };
+    }
+}
+
+impl Decodable for Message {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let item_count = rlp.item_count()?;
+        let id: u8 = rlp.val_at(0)?;
+        let message = match id {
+            MESSAGE_ID_GET_MANIFEST if item_count == 1 => Message::RequestManifest,
+            MESSAGE_ID_MANIFEST if item_count == 1 => Message::Manifest(None),
+            MESSAGE_ID_MANIFEST if item_count == 2 => Message::Manifest(Some(rlp.val_at(1)?)),
+            MESSAGE_ID_GET_CHUNK if item_count == 2 => Message::RequestChunk(rlp.val_at(1)?),
+            MESSAGE_ID_CHUNK if item_count == 2 => Message::Chunk(rlp.val_at(1)?, None),
+            MESSAGE_ID_CHUNK if item_count == 3 => Message::Chunk(rlp.val_at(1)?, Some(rlp.val_at(2)?)),
+            MESSAGE_ID_GET_MANIFEST | MESSAGE_ID_MANIFEST | MESSAGE_ID_GET_CHUNK | MESSAGE_ID_CHUNK => {
+                return Err(DecoderError::RlpIncorrectListLen)
+            }
+            _ => return Err(DecoderError::Custom("Unknown message id detected")),
+        };
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes::H256;
+    use rlp::{self, Encodable};
+
+    use super::Message;
+
+    fn check_round_trip(message: Message) {
+        assert_eq!(message, rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn request_manifest_message_rlp() {
+        check_round_trip(Message::RequestManifest);
+    }
+
+    #[test]
+    fn empty_manifest_message_rlp() {
+        check_round_trip(Message::Manifest(None));
+    }
+
+    #[test]
+    fn request_chunk_message_rlp() {
+        check_round_trip(Message::RequestChunk(H256::random()));
+    }
+
+    #[test]
+    fn chunk_message_rlp() {
+        check_round_trip(Message::Chunk(H256::random(), Some(vec![0xc0, 0x01, 0x02])));
+        check_round_trip(Message::Chunk(H256::random(), None));
+    }
+}
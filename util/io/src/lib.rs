@@ -57,10 +57,8 @@
 //! }
 //! ```
 
-//TODO: use Poll from mio
-#![allow(deprecated)]
-
 extern crate mio;
+extern crate mio_extras;
 #[macro_use]
 extern crate log as rlog;
 extern crate crossbeam;
@@ -70,8 +68,8 @@ extern crate slab;
 mod service;
 mod worker;
 
-use mio::deprecated::{EventLoop, NotifyError};
-use mio::Token;
+use mio::{Poll, Token};
+use mio_extras::channel::SendError;
 use std::{error, fmt};
 
 pub use worker::LOCAL_STACK_SIZE;
@@ -108,11 +106,11 @@ impl From<::std::io::Error> for IoError {
     }
 }
 
-impl<Message> From<NotifyError<service::IoMessage<Message>>> for IoError
+impl<Message> From<SendError<service::IoMessage<Message>>> for IoError
 where
     Message: Send + Clone,
 {
-    fn from(_err: NotifyError<service::IoMessage<Message>>) -> IoError {
+    fn from(_err: SendError<service::IoMessage<Message>>) -> IoError {
         IoError::Mio(::std::io::Error::new(::std::io::ErrorKind::ConnectionAborted, "Network IO notification error"))
     }
 }
@@ -158,29 +156,29 @@ where
     fn stream_writable(&self, _io: &IoContext<Message>, _stream: StreamToken) -> IoHandlerResult<()> {
         Ok(())
     }
-    /// Register a new stream with the event loop
+    /// Register a new stream with the poll
     fn register_stream(
         &self,
         _stream: StreamToken,
         _reg: Token,
-        _event_loop: &mut EventLoop<IoManager<Message>>,
+        _poll: &Poll,
     ) -> IoHandlerResult<()> {
         Ok(())
     }
-    /// Re-register a stream with the event loop
+    /// Re-register a stream with the poll
     fn update_stream(
         &self,
         _stream: StreamToken,
         _reg: Token,
-        _event_loop: &mut EventLoop<IoManager<Message>>,
+        _poll: &Poll,
     ) -> IoHandlerResult<()> {
         Ok(())
     }
-    /// Deregister a stream. Called whenstream is removed from event loop
+    /// Deregister a stream. Called when the stream is removed from the poll
     fn deregister_stream(
         &self,
         _stream: StreamToken,
-        _event_loop: &mut EventLoop<IoManager<Message>>,
+        _poll: &Poll,
     ) -> IoHandlerResult<()> {
         Ok(())
     }
@@ -15,12 +15,13 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use crossbeam::sync::chase_lev;
-use mio::deprecated::{EventLoop, EventLoopBuilder, Handler, Sender};
-use mio::timer::Timeout;
 use mio::*;
+use mio_extras::channel::{self, Receiver, Sender};
+use mio_extras::timer::{self, Timeout, Timer};
 use parking_lot::{Mutex, RwLock};
 use slab::Slab;
 use std::collections::HashMap;
+use std::sync::mpsc::TryRecvError;
 use std::sync::{Arc, Weak};
 use std::sync::{Condvar as SCondvar, Mutex as SMutex};
 use std::thread::{self, JoinHandle};
@@ -39,6 +40,10 @@ pub type HandlerId = usize;
 pub const TOKENS_PER_HANDLER: usize = 16384;
 const MAX_HANDLERS: usize = 8;
 
+// mio reserves usize::MAX, and the handler tokens are far below these.
+const CHANNEL_TOKEN: Token = Token(::std::usize::MAX - 1);
+const TIMER_TOKEN: Token = Token(::std::usize::MAX - 2);
+
 /// Messages used to communicate with the event loop from other threads.
 #[derive(Clone)]
 pub enum IoMessage<Message>
@@ -188,6 +193,10 @@ struct UserTimer {
 pub struct IoManager<Message>
 where
     Message: Send + Sync, {
+    poll: Poll,
+    channel: Receiver<IoMessage<Message>>,
+    host_channel: Sender<IoMessage<Message>>,
+    timer: Timer<Token>,
     timers: Arc<RwLock<HashMap<HandlerId, UserTimer>>>,
     handlers: Arc<RwLock<Slab<Arc<IoHandler<Message>>, HandlerId>>>,
     workers: Vec<Worker>,
@@ -199,11 +208,17 @@ impl<Message> IoManager<Message>
 where
     Message: Send + Sync + Clone + 'static,
 {
-    /// Creates a new instance and registers it with the event loop.
+    /// Creates a new instance and runs the event loop until it is shut down.
     pub fn start(
-        event_loop: &mut EventLoop<IoManager<Message>>,
+        channel: Receiver<IoMessage<Message>>,
+        host_channel: Sender<IoMessage<Message>>,
         handlers: Arc<RwLock<Slab<Arc<IoHandler<Message>>, HandlerId>>>,
     ) -> Result<(), IoError> {
+        let poll = Poll::new()?;
+        let timer = timer::Builder::default().build();
+        poll.register(&channel, CHANNEL_TOKEN, Ready::readable(), PollOpt::edge())?;
+        poll.register(&timer, TIMER_TOKEN, Ready::readable(), PollOpt::edge())?;
+
         let (worker, stealer) = chase_lev::deque();
         let num_workers = 4;
         let work_ready_mutex = Arc::new(SMutex::new(()));
@@ -213,7 +228,7 @@ where
                 Worker::new(
                     i,
                     stealer.clone(),
-                    IoChannel::new(event_loop.channel(), Arc::downgrade(&handlers)),
+                    IoChannel::new(host_channel.clone(), Arc::downgrade(&handlers)),
                     work_ready.clone(),
                     work_ready_mutex.clone(),
                 )
@@ -221,29 +236,56 @@ where
             .collect();
 
         let mut io = IoManager {
+            poll,
+            channel,
+            host_channel,
+            timer,
             timers: Arc::new(RwLock::new(HashMap::new())),
             handlers,
             worker_channel: worker,
             workers,
             work_ready,
         };
-        event_loop.run(&mut io)?;
-        Ok(())
+        io.run()
     }
-}
 
-impl<Message> Handler for IoManager<Message>
-where
-    Message: Send + Clone + Sync + 'static,
-{
-    type Timeout = Token;
-    type Message = IoMessage<Message>;
+    fn run(&mut self) -> Result<(), IoError> {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            self.poll.poll(&mut events, None)?;
+            for event in events.iter() {
+                match event.token() {
+                    CHANNEL_TOKEN => loop {
+                        match self.channel.try_recv() {
+                            Ok(IoMessage::Shutdown) => {
+                                self.workers.clear();
+                                return Ok(())
+                            }
+                            Ok(message) => self.notify(message),
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => return Ok(()),
+                        }
+                    },
+                    TIMER_TOKEN => {
+                        while let Some(token) = self.timer.poll() {
+                            self.timeout(token);
+                        }
+                    }
+                    token => self.ready(token, event.readiness()),
+                }
+            }
+        }
+    }
+
+    fn channel(&self) -> IoChannel<Message> {
+        IoChannel::new(self.host_channel.clone(), Arc::downgrade(&self.handlers))
+    }
 
-    fn ready(&mut self, _event_loop: &mut EventLoop<Self>, token: Token, events: Ready) {
+    fn ready(&mut self, token: Token, events: Ready) {
         let handler_index = token.0 / TOKENS_PER_HANDLER;
         let token_id = token.0 % TOKENS_PER_HANDLER;
         if let Some(handler) = self.handlers.read().get(handler_index) {
-            if events.is_hup() {
+            if is_hup(events) {
                 self.worker_channel.push(Work {
                     work_type: WorkType::Hup,
                     token: token_id,
@@ -272,19 +314,23 @@ where
         }
     }
 
-    fn timeout(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+    fn timeout(&mut self, token: Token) {
         let handler_index = token.0 / TOKENS_PER_HANDLER;
         let token_id = token.0 % TOKENS_PER_HANDLER;
         if let Some(handler) = self.handlers.read().get(handler_index) {
             let maybe_timer = self.timers.read().get(&token.0).cloned();
             if let Some(timer) = maybe_timer {
                 if timer.once {
-                    self.timers.write().remove(&token_id);
-                    event_loop.clear_timeout(&timer.timeout);
+                    self.timers.write().remove(&token.0);
                 } else {
-                    event_loop
-                        .timeout(token, Duration::from_millis(timer.delay))
-                        .expect("Error re-registering user timer");
+                    let timeout = self.timer.set_timeout(Duration::from_millis(timer.delay), token);
+                    self.timers.write().insert(
+                        token.0,
+                        UserTimer {
+                            timeout,
+                            ..timer
+                        },
+                    );
                 }
                 self.worker_channel.push(Work {
                     work_type: WorkType::Timeout,
@@ -297,12 +343,9 @@ where
         }
     }
 
-    fn notify(&mut self, event_loop: &mut EventLoop<Self>, msg: Self::Message) {
+    fn notify(&mut self, msg: IoMessage<Message>) {
         match msg {
-            IoMessage::Shutdown => {
-                self.workers.clear();
-                event_loop.shutdown();
-            }
+            IoMessage::Shutdown => unreachable!("The event loop handles the shutdown"),
             IoMessage::AddHandler {
                 handler,
             } => {
@@ -310,10 +353,7 @@ where
                     .write()
                     .insert(handler.clone())
                     .unwrap_or_else(|_| panic!("Too many handlers registered"));
-                if let Err(err) = handler.initialize(&IoContext::new(
-                    IoChannel::new(event_loop.channel(), Arc::downgrade(&self.handlers)),
-                    handler_id,
-                )) {
+                if let Err(err) = handler.initialize(&IoContext::new(self.channel(), handler_id)) {
                     error!(target: "io", "Error in initialize {:?}", err);
                 }
             }
@@ -328,7 +368,7 @@ where
                     timers.keys().cloned().filter(|timer_id| timer_id / TOKENS_PER_HANDLER == handler_id).collect();
                 for timer_id in to_remove {
                     let timer = timers.remove(&timer_id).expect("to_remove only contains keys from timers; qed");
-                    event_loop.clear_timeout(&timer.timeout);
+                    self.timer.cancel_timeout(&timer.timeout);
                 }
             }
             IoMessage::AddTimer {
@@ -338,9 +378,7 @@ where
                 once,
             } => {
                 let timer_id = token + handler_id * TOKENS_PER_HANDLER;
-                let timeout = event_loop
-                    .timeout(Token(timer_id), Duration::from_millis(delay))
-                    .expect("Error registering user timer");
+                let timeout = self.timer.set_timeout(Duration::from_millis(delay), Token(timer_id));
                 self.timers.write().insert(
                     timer_id,
                     UserTimer {
@@ -356,7 +394,7 @@ where
             } => {
                 let timer_id = token + handler_id * TOKENS_PER_HANDLER;
                 if let Some(timer) = self.timers.write().remove(&timer_id) {
-                    event_loop.clear_timeout(&timer.timeout);
+                    self.timer.cancel_timeout(&timer.timeout);
                 }
             }
             IoMessage::RegisterStream {
@@ -365,7 +403,7 @@ where
             } => {
                 if let Some(handler) = self.handlers.read().get(handler_id) {
                     if let Err(err) =
-                        handler.register_stream(token, Token(token + handler_id * TOKENS_PER_HANDLER), event_loop)
+                        handler.register_stream(token, Token(token + handler_id * TOKENS_PER_HANDLER), &self.poll)
                     {
                         warn!(target: "io", "Error in register_stream {:?}", err);
                     }
@@ -376,13 +414,13 @@ where
                 token,
             } => {
                 if let Some(handler) = self.handlers.read().get(handler_id) {
-                    if let Err(err) = handler.deregister_stream(token, event_loop) {
+                    if let Err(err) = handler.deregister_stream(token, &self.poll) {
                         warn!(target: "io", "Error in deregister_stream {:?}", err);
                     }
                     // unregister a timer associated with the token (if any)
                     let timer_id = token + handler_id * TOKENS_PER_HANDLER;
                     if let Some(timer) = self.timers.write().remove(&timer_id) {
-                        event_loop.clear_timeout(&timer.timeout);
+                        self.timer.cancel_timeout(&timer.timeout);
                     }
                 }
             }
@@ -392,7 +430,7 @@ where
             } => {
                 if let Some(handler) = self.handlers.read().get(handler_id) {
                     if let Err(err) =
-                        handler.update_stream(token, Token(token + handler_id * TOKENS_PER_HANDLER), event_loop)
+                        handler.update_stream(token, Token(token + handler_id * TOKENS_PER_HANDLER), &self.poll)
                     {
                         warn!(target: "io", "Error in update_stream {:?}", err);
                     }
@@ -417,6 +455,16 @@ where
     }
 }
 
+#[cfg(unix)]
+fn is_hup(events: Ready) -> bool {
+    ::mio::unix::UnixReady::from(events).is_hup()
+}
+
+#[cfg(not(unix))]
+fn is_hup(_events: Ready) -> bool {
+    false
+}
+
 #[derive(Clone)]
 enum Handlers<Message>
 where
@@ -534,14 +582,12 @@ where
 {
     /// Starts IO event loop
     pub fn start() -> Result<IoService<Message>, IoError> {
-        let mut config = EventLoopBuilder::new();
-        config.messages_per_tick(1024);
-        let mut event_loop = config.build().expect("Error creating event loop");
-        let channel = event_loop.channel();
+        let (channel, receiver) = channel::channel();
         let handlers = Arc::new(RwLock::new(Slab::new(MAX_HANDLERS)));
         let h = handlers.clone();
+        let host_channel = channel.clone();
         let thread = thread::spawn(move || {
-            IoManager::<Message>::start(&mut event_loop, h).expect("Error starting IO service");
+            IoManager::<Message>::start(receiver, host_channel, h).expect("Error starting IO service");
         });
         Ok(IoService {
             thread: Mutex::new(Some(thread)),
@@ -20,6 +20,7 @@ extern crate codechain_types;
 extern crate hashdb;
 extern crate kvdb;
 extern crate memorydb;
+extern crate parking_lot;
 extern crate rlp;
 extern crate util_error as error;
 
@@ -32,6 +33,7 @@ use std::sync::Arc;
 use std::{fmt, str};
 
 mod archivedb;
+mod overlayrecentdb;
 /// Export the journaldb module.
 mod traits;
 
@@ -43,6 +45,13 @@ pub use self::traits::JournalDB;
 pub enum Algorithm {
     /// Keep all keys forever.
     Archive,
+
+    /// Ancient and recent history maintained separately; recent history lasts for particular
+    /// number of blocks.
+    ///
+    /// Inserts go into backing database, journal retains knowledge of whether backing DB key is
+    /// ancient or recent. Non-canon inserts get explicitly reverted and removed from backing DB.
+    OverlayRecent,
 }
 
 impl Default for Algorithm {
@@ -57,6 +66,7 @@ impl str::FromStr for Algorithm {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "archive" => Ok(Algorithm::Archive),
+            "fast" => Ok(Algorithm::OverlayRecent),
             e => Err(format!("Invalid algorithm: {}", e)),
         }
     }
@@ -67,6 +77,7 @@ impl Algorithm {
     pub fn as_str(&self) -> &'static str {
         match *self {
             Algorithm::Archive => "archive",
+            Algorithm::OverlayRecent => "fast",
         }
     }
 
@@ -74,19 +85,20 @@ impl Algorithm {
     pub fn as_internal_name_str(&self) -> &'static str {
         match *self {
             Algorithm::Archive => "archive",
+            Algorithm::OverlayRecent => "overlayrecent",
         }
     }
 
     /// Returns true if pruning strategy is stable
     pub fn is_stable(&self) -> bool {
         match *self {
-            Algorithm::Archive => true,
+            Algorithm::Archive | Algorithm::OverlayRecent => true,
         }
     }
 
     /// Returns all algorithm types.
     pub fn all_types() -> Vec<Algorithm> {
-        vec![Algorithm::Archive]
+        vec![Algorithm::Archive, Algorithm::OverlayRecent]
     }
 }
 
@@ -100,6 +112,7 @@ impl fmt::Display for Algorithm {
 pub fn new(backing: Arc<::kvdb::KeyValueDB>, algorithm: Algorithm, col: Option<u32>) -> Box<JournalDB> {
     match algorithm {
         Algorithm::Archive => Box::new(archivedb::ArchiveDB::new(backing, col)),
+        Algorithm::OverlayRecent => Box::new(overlayrecentdb::OverlayRecentDB::new(backing, col)),
     }
 }
 
@@ -114,16 +127,19 @@ mod tests {
     #[test]
     fn test_journal_algorithm_parsing() {
         assert_eq!(Algorithm::Archive, "archive".parse().unwrap());
+        assert_eq!(Algorithm::OverlayRecent, "fast".parse().unwrap());
     }
 
     #[test]
     fn test_journal_algorithm_printing() {
         assert_eq!(Algorithm::Archive.to_string(), "archive".to_owned());
+        assert_eq!(Algorithm::OverlayRecent.to_string(), "fast".to_owned());
     }
 
     #[test]
     fn test_journal_algorithm_is_stable() {
         assert!(Algorithm::Archive.is_stable());
+        assert!(Algorithm::OverlayRecent.is_stable());
     }
 
     #[test]
@@ -135,13 +151,16 @@ mod tests {
     fn test_journal_algorithm_all_types() {
         // compiling should fail if some cases are not covered
         let mut archive = 0;
+        let mut overlayrecent = 0;
 
         for a in &Algorithm::all_types() {
             match *a {
                 Algorithm::Archive => archive += 1,
+                Algorithm::OverlayRecent => overlayrecent += 1,
             }
         }
 
         assert_eq!(archive, 1);
+        assert_eq!(overlayrecent, 1);
     }
 }
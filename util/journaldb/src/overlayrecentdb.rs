@@ -0,0 +1,691 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `JournalDB` over in-memory overlay
+
+use super::memorydb::*;
+use super::{DB_PREFIX_LEN, LATEST_ERA_KEY};
+use codechain_types::{Bytes, H256};
+use error::{BaseDataError, UtilError};
+use hashdb::*;
+use kvdb::{DBTransaction, KeyValueDB};
+use parking_lot::RwLock;
+use rlp::{decode, encode, Rlp, RlpStream};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use traits::JournalDB;
+
+/// Implementation of the `JournalDB` trait for a disk-backed database with a memory overlay
+/// and, possibly, latent-removal semantics.
+///
+/// Like `OverlayDB`, there is a memory overlay; `commit()` must be called in order to
+/// write operations out to disk. Unlike `OverlayDB`, `remove()` operations do not take effect
+/// immediately. Rather some age (based on a linear but arbitrary metric) must pass before
+/// the removals actually take effect.
+///
+/// There are two memory overlays:
+/// - Transaction overlay contains current transaction data. It is merged with with history
+/// overlay on each `commit()`
+/// - History overlay contains all data inserted during the history period. When the node
+/// in the overlay becomes ancient it is written to disk on `commit()`
+///
+/// There is also a journal maintained in memory and on the disk as well which lists insertions
+/// and removals for each commit during the history period. This is used to track
+/// data nodes that go out of history scope and must be written to disk.
+///
+/// Commit workflow:
+/// 1. Create a new journal record from the transaction overlay.
+/// 2. Insert each node from the transaction overlay into the History overlay increasing reference
+/// count if it is already there. Note that the reference counting is managed by `MemoryDB`
+/// 3. Clear the transaction overlay.
+/// 4. For a canonical journal record that becomes ancient inserts its insertions into the disk DB
+/// 5. For each journal record that goes out of the history scope (becomes ancient) remove its
+/// insertions from the history overlay, decreasing the reference counter and removing entry if
+/// if reaches zero.
+/// 6. For a canonical journal record that becomes ancient delete its removals from the disk only if
+/// the removed key is not present in the history overlay.
+/// 7. Delete ancient record from memory and disk.
+pub struct OverlayRecentDB {
+    transaction_overlay: MemoryDB,
+    backing: Arc<KeyValueDB>,
+    journal_overlay: Arc<RwLock<JournalOverlay>>,
+    column: Option<u32>,
+}
+
+#[derive(PartialEq)]
+struct JournalOverlay {
+    /// Nodes added in the history period
+    backing_overlay: MemoryDB,
+    /// Nodes being transferred from the backing overlay to the backing db
+    pending_overlay: HashMap<H256, DBValue>,
+    journal: HashMap<u64, Vec<JournalEntry>>,
+    latest_era: Option<u64>,
+    earliest_era: Option<u64>,
+    /// Cumulative size of all entries
+    cumulative_size: usize,
+}
+
+#[derive(PartialEq)]
+struct JournalEntry {
+    id: H256,
+    insertions: Vec<H256>,
+    deletions: Vec<H256>,
+}
+
+impl JournalEntry {
+    fn mem_used(&self) -> usize {
+        (self.insertions.len() + self.deletions.len()) * mem::size_of::<H256>()
+    }
+}
+
+impl Clone for OverlayRecentDB {
+    fn clone(&self) -> OverlayRecentDB {
+        OverlayRecentDB {
+            transaction_overlay: self.transaction_overlay.clone(),
+            backing: self.backing.clone(),
+            journal_overlay: self.journal_overlay.clone(),
+            column: self.column,
+        }
+    }
+}
+
+const PADDING: [u8; 10] = [0u8; 10];
+
+impl OverlayRecentDB {
+    /// Create a new instance.
+    pub fn new(backing: Arc<KeyValueDB>, col: Option<u32>) -> OverlayRecentDB {
+        let journal_overlay = Arc::new(RwLock::new(OverlayRecentDB::read_overlay(&*backing, col)));
+        OverlayRecentDB {
+            transaction_overlay: MemoryDB::new(),
+            backing,
+            journal_overlay,
+            column: col,
+        }
+    }
+
+    #[cfg(test)]
+    fn can_reconstruct_refs(&self) -> bool {
+        let reconstructed = Self::read_overlay(&*self.backing, self.column);
+        let journal_overlay = self.journal_overlay.read();
+        journal_overlay.backing_overlay == reconstructed.backing_overlay
+            && journal_overlay.pending_overlay == reconstructed.pending_overlay
+            && journal_overlay.journal == reconstructed.journal
+            && journal_overlay.latest_era == reconstructed.latest_era
+            && journal_overlay.cumulative_size == reconstructed.cumulative_size
+    }
+
+    fn payload(&self, key: &H256) -> Option<DBValue> {
+        self.backing.get(self.column, key).expect("Low-level database error. Some issue with your hard disk?")
+    }
+
+    fn read_overlay(db: &KeyValueDB, col: Option<u32>) -> JournalOverlay {
+        let mut journal = HashMap::new();
+        let mut overlay = MemoryDB::new();
+        let mut latest_era = None;
+        let mut earliest_era = None;
+        let mut cumulative_size = 0;
+        if let Some(val) = db.get(col, &LATEST_ERA_KEY).expect("Low-level database error.") {
+            let mut era = decode::<u64>(&val);
+            latest_era = Some(era);
+            loop {
+                let mut index = 0usize;
+                while let Some(rlp_data) = db.get(col, &journal_key(era, index)).expect("Low-level database error.") {
+                    let rlp = Rlp::new(&rlp_data);
+                    let id: H256 = rlp.val_at(0);
+                    let insertions = rlp.at(1);
+                    let deletions: Vec<H256> = rlp.list_at(2);
+                    let mut inserted_keys = Vec::new();
+                    for r in insertions.iter() {
+                        let k: H256 = r.val_at(0);
+                        let v = r.at(1).data();
+
+                        if !overlay.contains(&k) {
+                            cumulative_size += v.len();
+                        }
+
+                        overlay.emplace(k, DBValue::from_slice(v));
+                        inserted_keys.push(k);
+                    }
+                    journal.entry(era).or_insert_with(Vec::new).push(JournalEntry {
+                        id,
+                        insertions: inserted_keys,
+                        deletions,
+                    });
+                    index += 1;
+                    earliest_era = Some(era);
+                }
+                if index == 0 || era == 0 {
+                    break
+                }
+                era -= 1;
+            }
+        }
+        JournalOverlay {
+            backing_overlay: overlay,
+            pending_overlay: HashMap::new(),
+            journal,
+            latest_era,
+            earliest_era,
+            cumulative_size,
+        }
+    }
+}
+
+fn journal_key(era: u64, index: usize) -> Vec<u8> {
+    let mut r = RlpStream::new_list(3);
+    r.append(&era);
+    r.append(&index);
+    r.append(&&PADDING[..]);
+    r.out()
+}
+
+impl JournalDB for OverlayRecentDB {
+    fn boxed_clone(&self) -> Box<JournalDB> {
+        Box::new(self.clone())
+    }
+
+    fn mem_used(&self) -> usize {
+        let mut mem = self.transaction_overlay.mem_used();
+        let overlay = self.journal_overlay.read();
+
+        mem += overlay.backing_overlay.mem_used();
+        mem += overlay.pending_overlay.values().map(|v| mem::size_of::<H256>() + v.len()).sum::<usize>();
+        mem += overlay.journal.values().flat_map(|entries| entries.iter()).map(JournalEntry::mem_used).sum::<usize>();
+
+        mem
+    }
+
+    fn journal_size(&self) -> usize {
+        self.journal_overlay.read().cumulative_size
+    }
+
+    fn is_empty(&self) -> bool {
+        self.backing.get(self.column, &LATEST_ERA_KEY).expect("Low level database error").is_none()
+    }
+
+    fn backing(&self) -> &Arc<KeyValueDB> {
+        &self.backing
+    }
+
+    fn latest_era(&self) -> Option<u64> {
+        self.journal_overlay.read().latest_era
+    }
+
+    fn earliest_era(&self) -> Option<u64> {
+        self.journal_overlay.read().earliest_era
+    }
+
+    fn state(&self, key: &H256) -> Option<Bytes> {
+        let journal_overlay = self.journal_overlay.read();
+        journal_overlay
+            .backing_overlay
+            .get(key)
+            .map(|v| v.into_vec())
+            .or_else(|| journal_overlay.pending_overlay.get(key).map(|d| d.clone().into_vec()))
+            .or_else(|| self.backing.get_by_prefix(self.column, &key[0..DB_PREFIX_LEN]).map(|b| b.into_vec()))
+    }
+
+    fn journal_under(&mut self, batch: &mut DBTransaction, now: u64, id: &H256) -> Result<u32, UtilError> {
+        let mut journal_overlay = self.journal_overlay.write();
+
+        // flush previous changes
+        journal_overlay.pending_overlay.clear();
+
+        let mut r = RlpStream::new_list(3);
+        let mut tx = self.transaction_overlay.drain();
+        let inserted_keys: Vec<_> = tx.iter().filter(|&(_, &(_, c))| c > 0).map(|(k, _)| *k).collect();
+        let removed_keys: Vec<_> = tx.iter().filter(|&(_, &(_, c))| c < 0).map(|(k, _)| *k).collect();
+        let ops = inserted_keys.len() + removed_keys.len();
+
+        // Increase counter for each inserted key no matter if the block is canonical or not.
+        let insertions = tx.drain().filter(|&(_, (_, c))| c > 0).map(|(k, (v, _))| (k, v));
+
+        r.append(id);
+        r.begin_list(inserted_keys.len());
+        for (k, v) in insertions {
+            r.begin_list(2);
+            r.append(&k);
+            r.append(&&*v);
+
+            if !journal_overlay.backing_overlay.contains(&k) {
+                journal_overlay.cumulative_size += v.len();
+            }
+
+            journal_overlay.backing_overlay.emplace(k, v);
+        }
+        r.append_list(&removed_keys);
+
+        let index = journal_overlay.journal.get(&now).map_or(0, |j| j.len());
+        batch.put_vec(self.column, &journal_key(now, index), r.out());
+        if journal_overlay.latest_era.map_or(true, |e| now > e) {
+            batch.put_vec(self.column, &LATEST_ERA_KEY, encode(&now).into_vec());
+            journal_overlay.latest_era = Some(now);
+        }
+
+        if journal_overlay.earliest_era.map_or(true, |e| e > now) {
+            journal_overlay.earliest_era = Some(now);
+        }
+
+        journal_overlay.journal.entry(now).or_insert_with(Vec::new).push(JournalEntry {
+            id: *id,
+            insertions: inserted_keys,
+            deletions: removed_keys,
+        });
+        Ok(ops as u32)
+    }
+
+    fn mark_canonical(&mut self, batch: &mut DBTransaction, end_era: u64, canon_id: &H256) -> Result<u32, UtilError> {
+        let mut journal_overlay = self.journal_overlay.write();
+        let journal_overlay = &mut *journal_overlay;
+
+        let mut ops = 0;
+        // apply old commits' details
+        if let Some(records) = journal_overlay.journal.remove(&end_era) {
+            let mut canon_insertions: Vec<(H256, DBValue)> = Vec::new();
+            let mut canon_deletions: Vec<H256> = Vec::new();
+            let mut overlay_deletions: Vec<H256> = Vec::new();
+            for (index, mut journal) in records.into_iter().enumerate() {
+                // delete the record from the db
+                batch.delete(self.column, &journal_key(end_era, index));
+                if *canon_id == journal.id {
+                    for h in &journal.insertions {
+                        if let Some((d, rc)) = journal_overlay.backing_overlay.raw(h) {
+                            if rc > 0 {
+                                canon_insertions.push((*h, d));
+                            }
+                        }
+                    }
+                    canon_deletions = mem::replace(&mut journal.deletions, Vec::new());
+                }
+                overlay_deletions.append(&mut journal.insertions);
+            }
+
+            ops += canon_insertions.len();
+            ops += canon_deletions.len();
+
+            // apply canon inserts first
+            for (k, v) in canon_insertions {
+                batch.put(self.column, &k, &v);
+                journal_overlay.pending_overlay.insert(k, v);
+            }
+            // update the overlay
+            for k in overlay_deletions {
+                if let Some(val) = journal_overlay.backing_overlay.remove_and_purge(&k) {
+                    journal_overlay.cumulative_size -= val.len();
+                }
+            }
+            // apply canon deletions
+            for k in canon_deletions {
+                if !journal_overlay.backing_overlay.contains(&k) {
+                    batch.delete(self.column, &k);
+                }
+            }
+        }
+
+        if !journal_overlay.journal.is_empty() {
+            journal_overlay.earliest_era = Some(end_era + 1);
+        }
+
+        Ok(ops as u32)
+    }
+
+    fn flush(&self) {
+        self.journal_overlay.write().pending_overlay.clear();
+    }
+
+    fn inject(&mut self, batch: &mut DBTransaction) -> Result<u32, UtilError> {
+        let mut ops = 0;
+        for (key, (value, rc)) in self.transaction_overlay.drain() {
+            if rc != 0 {
+                ops += 1
+            }
+
+            match rc {
+                0 => {}
+                _ if rc > 0 => batch.put(self.column, &key, &value),
+                -1 => {
+                    if self.backing.get(self.column, &key)?.is_none() {
+                        return Err(BaseDataError::NegativelyReferencedHash(key).into())
+                    }
+                    batch.delete(self.column, &key)
+                }
+                _ => panic!("Attempted to inject invalid state."),
+            }
+        }
+
+        Ok(ops)
+    }
+
+    fn consolidate(&mut self, with: MemoryDB) {
+        self.transaction_overlay.consolidate(with);
+    }
+}
+
+impl HashDB for OverlayRecentDB {
+    fn keys(&self) -> HashMap<H256, i32> {
+        let mut ret: HashMap<H256, i32> =
+            self.backing.iter(self.column).map(|(key, _)| (H256::from_slice(&*key), 1)).collect();
+
+        for (key, refs) in self.transaction_overlay.keys() {
+            match ret.entry(key) {
+                Entry::Occupied(mut entry) => {
+                    *entry.get_mut() += refs;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(refs);
+                }
+            }
+        }
+        ret
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        if let Some((d, rc)) = self.transaction_overlay.raw(key) {
+            if rc > 0 {
+                return Some(d)
+            }
+        }
+        let v = {
+            let journal_overlay = self.journal_overlay.read();
+            journal_overlay.backing_overlay.get(key).or_else(|| journal_overlay.pending_overlay.get(key).cloned())
+        };
+        v.or_else(|| self.payload(key))
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, value: &[u8]) -> H256 {
+        self.transaction_overlay.insert(value)
+    }
+
+    fn emplace(&mut self, key: H256, value: DBValue) {
+        self.transaction_overlay.emplace(key, value);
+    }
+
+    fn remove(&mut self, key: &H256) {
+        self.transaction_overlay.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::blake256;
+    use hashdb::{DBValue, HashDB};
+    use {kvdb_memorydb, JournalDB};
+
+    fn new_db() -> OverlayRecentDB {
+        let backing = Arc::new(kvdb_memorydb::create(0));
+        OverlayRecentDB::new(backing, None)
+    }
+
+    #[test]
+    fn insert_same_in_fork() {
+        // history is 1
+        let mut jdb = new_db();
+
+        let x = jdb.insert(b"X");
+        jdb.commit_batch(1, &blake256(b"1"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        jdb.commit_batch(2, &blake256(b"2"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        jdb.commit_batch(3, &blake256(b"1002a"), Some((1, blake256(b"1")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        jdb.commit_batch(4, &blake256(b"1003a"), Some((2, blake256(b"2")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+
+        jdb.remove(&x);
+        jdb.commit_batch(3, &blake256(b"1002b"), Some((1, blake256(b"1")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        let x = jdb.insert(b"X");
+        jdb.commit_batch(4, &blake256(b"1003b"), Some((2, blake256(b"2")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+
+        jdb.commit_batch(5, &blake256(b"1004a"), Some((3, blake256(b"1002a")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        jdb.commit_batch(6, &blake256(b"1005a"), Some((4, blake256(b"1003a")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+
+        assert!(jdb.contains(&x));
+    }
+
+    #[test]
+    fn long_history() {
+        // history is 3
+        let mut jdb = new_db();
+        let h = jdb.insert(b"foo");
+        jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&h));
+        jdb.remove(&h);
+        jdb.commit_batch(1, &blake256(b"1"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&h));
+        jdb.commit_batch(2, &blake256(b"2"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&h));
+        jdb.commit_batch(3, &blake256(b"3"), Some((0, blake256(b"0")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&h));
+        jdb.commit_batch(4, &blake256(b"4"), Some((1, blake256(b"1")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(!jdb.contains(&h));
+    }
+
+    #[test]
+    fn complex() {
+        // history is 1
+        let mut jdb = new_db();
+
+        let foo = jdb.insert(b"foo");
+        let bar = jdb.insert(b"bar");
+        jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(jdb.contains(&bar));
+
+        jdb.remove(&foo);
+        jdb.remove(&bar);
+        let baz = jdb.insert(b"baz");
+        jdb.commit_batch(1, &blake256(b"1"), Some((0, blake256(b"0")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(jdb.contains(&bar));
+        assert!(jdb.contains(&baz));
+
+        let foo = jdb.insert(b"foo");
+        jdb.remove(&baz);
+        jdb.commit_batch(2, &blake256(b"2"), Some((1, blake256(b"1")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(!jdb.contains(&bar));
+        assert!(jdb.contains(&baz));
+
+        jdb.remove(&foo);
+        jdb.commit_batch(3, &blake256(b"3"), Some((2, blake256(b"2")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(!jdb.contains(&bar));
+        assert!(!jdb.contains(&baz));
+
+        jdb.commit_batch(4, &blake256(b"4"), Some((3, blake256(b"3")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(!jdb.contains(&foo));
+        assert!(!jdb.contains(&bar));
+        assert!(!jdb.contains(&baz));
+    }
+
+    #[test]
+    fn fork() {
+        // history is 1
+        let mut jdb = new_db();
+
+        let foo = jdb.insert(b"foo");
+        let bar = jdb.insert(b"bar");
+        jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(jdb.contains(&bar));
+
+        jdb.remove(&foo);
+        let baz = jdb.insert(b"baz");
+        jdb.commit_batch(1, &blake256(b"1a"), Some((0, blake256(b"0")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+
+        jdb.remove(&bar);
+        jdb.commit_batch(1, &blake256(b"1b"), Some((0, blake256(b"0")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+
+        assert!(jdb.contains(&foo));
+        assert!(jdb.contains(&bar));
+        assert!(jdb.contains(&baz));
+
+        jdb.commit_batch(2, &blake256(b"2b"), Some((1, blake256(b"1b")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        assert!(!jdb.contains(&baz));
+        assert!(!jdb.contains(&bar));
+    }
+
+    #[test]
+    fn overwrite() {
+        // history is 1
+        let mut jdb = new_db();
+
+        let foo = jdb.insert(b"foo");
+        jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+
+        jdb.remove(&foo);
+        jdb.commit_batch(1, &blake256(b"1"), Some((0, blake256(b"0")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        jdb.insert(b"foo");
+        assert!(jdb.contains(&foo));
+        jdb.commit_batch(2, &blake256(b"2"), Some((1, blake256(b"1")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+        jdb.commit_batch(3, &blake256(b"2"), Some((0, blake256(b"2")))).unwrap();
+        assert!(jdb.can_reconstruct_refs());
+        assert!(jdb.contains(&foo));
+    }
+
+    #[test]
+    fn reopen() {
+        let shared_db = Arc::new(kvdb_memorydb::create(0));
+        let bar = H256::random();
+
+        let foo = {
+            let mut jdb = OverlayRecentDB::new(shared_db.clone(), None);
+            // history is 1
+            let foo = jdb.insert(b"foo");
+            jdb.emplace(bar.clone(), DBValue::from_slice(b"bar"));
+            jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+            foo
+        };
+
+        {
+            let mut jdb = OverlayRecentDB::new(shared_db.clone(), None);
+            jdb.remove(&foo);
+            jdb.commit_batch(1, &blake256(b"1"), Some((0, blake256(b"0")))).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+        }
+
+        {
+            let mut jdb = OverlayRecentDB::new(shared_db, None);
+            assert!(jdb.contains(&foo));
+            assert!(jdb.contains(&bar));
+            jdb.commit_batch(2, &blake256(b"2"), Some((1, blake256(b"1")))).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+            assert!(!jdb.contains(&foo));
+        }
+    }
+
+    #[test]
+    fn reopen_fork() {
+        let shared_db = Arc::new(kvdb_memorydb::create(0));
+
+        let (foo, bar, baz) = {
+            let mut jdb = OverlayRecentDB::new(shared_db.clone(), None);
+            // history is 1
+            let foo = jdb.insert(b"foo");
+            let bar = jdb.insert(b"bar");
+            jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+            jdb.remove(&foo);
+            let baz = jdb.insert(b"baz");
+            jdb.commit_batch(1, &blake256(b"1a"), Some((0, blake256(b"0")))).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+
+            jdb.remove(&bar);
+            jdb.commit_batch(1, &blake256(b"1b"), Some((0, blake256(b"0")))).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+            (foo, bar, baz)
+        };
+
+        {
+            let mut jdb = OverlayRecentDB::new(shared_db, None);
+            jdb.commit_batch(2, &blake256(b"2b"), Some((1, blake256(b"1b")))).unwrap();
+            assert!(jdb.can_reconstruct_refs());
+            assert!(jdb.contains(&foo));
+            assert!(!jdb.contains(&baz));
+            assert!(!jdb.contains(&bar));
+        }
+    }
+
+    #[test]
+    fn earliest_era() {
+        let mut jdb = new_db();
+
+        // empty DB
+        assert!(jdb.earliest_era().is_none());
+
+        // single journalled era.
+        let _key = jdb.insert(b"hello!");
+        jdb.commit_batch(0, &blake256(b"0"), None).unwrap();
+        assert_eq!(jdb.earliest_era(), Some(0));
+
+        // second journalled era.
+        jdb.commit_batch(1, &blake256(b"1"), None).unwrap();
+        assert_eq!(jdb.earliest_era(), Some(0));
+
+        // single journalled era.
+        jdb.commit_batch(2, &blake256(b"2"), Some((0, blake256(b"0")))).unwrap();
+        assert_eq!(jdb.earliest_era(), Some(1));
+
+        // no journalled eras.
+        jdb.commit_batch(3, &blake256(b"3"), Some((1, blake256(b"1")))).unwrap();
+        assert_eq!(jdb.earliest_era(), Some(2));
+    }
+
+    #[test]
+    fn inject() {
+        let mut jdb = new_db();
+        let key = jdb.insert(b"dog");
+        jdb.inject_batch().unwrap();
+
+        assert_eq!(jdb.get(&key).unwrap(), DBValue::from_slice(b"dog"));
+        jdb.remove(&key);
+        jdb.inject_batch().unwrap();
+
+        assert!(jdb.get(&key).is_none());
+    }
+}
@@ -6,7 +6,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use bigint::{H128, H160, H256, H512, H520, H64, U128, U256};
+use bigint::{Bloom, H128, H160, H256, H512, H520, H64, U128, U256};
 use byteorder::{BigEndian, ByteOrder};
 use std::{cmp, mem, str};
 use stream::RlpStream;
@@ -224,7 +224,12 @@ impl Encodable for usize {
 
 impl Decodable for usize {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        u64::decode(rlp).map(|value| value as usize)
+        // Truncating the value makes the 32-bit nodes decode a different number from the 64-bit nodes.
+        let value = u64::decode(rlp)?;
+        if value > ::std::usize::MAX as u64 {
+            return Err(DecoderError::RlpIsTooBig)
+        }
+        Ok(value as usize)
     }
 }
 
@@ -262,6 +267,7 @@ impl_encodable_for_hash!(H160);
 impl_encodable_for_hash!(H256);
 impl_encodable_for_hash!(H512);
 impl_encodable_for_hash!(H520);
+impl_encodable_for_hash!(Bloom);
 
 impl_decodable_for_hash!(H64, 8);
 impl_decodable_for_hash!(H128, 16);
@@ -269,6 +275,7 @@ impl_decodable_for_hash!(H160, 20);
 impl_decodable_for_hash!(H256, 32);
 impl_decodable_for_hash!(H512, 64);
 impl_decodable_for_hash!(H520, 65);
+impl_decodable_for_hash!(Bloom, 256);
 
 macro_rules! impl_encodable_for_uint {
     ($name:ident, $size:expr) => {
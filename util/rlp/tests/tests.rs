@@ -323,6 +323,29 @@ fn decode_untrusted_u64() {
     run_decode_tests(tests);
 }
 
+#[test]
+fn decode_untrusted_usize() {
+    let tests = vec![
+        DTestPair(0usize, vec![0x80]),
+        DTestPair(0x7fusize, vec![0x7f]),
+        DTestPair(0xFFFFFFFFusize, vec![0x84, 0xff, 0xff, 0xff, 0xff]),
+    ];
+    run_decode_tests(tests);
+}
+
+#[test]
+fn usize_is_encoded_as_u64() {
+    assert_eq!(rlp::encode(&0xFFFFFFFFusize).into_vec(), rlp::encode(&0xFFFFFFFFu64).into_vec());
+}
+
+#[cfg(target_pointer_width = "32")]
+#[test]
+fn decode_untrusted_usize_larger_than_the_pointer_width() {
+    let data = vec![0x85, 0x01, 0x00, 0x00, 0x00, 0x00];
+    let rlp = UntrustedRlp::new(&data);
+    assert_eq!(rlp.as_val::<usize>(), Err(DecoderError::RlpIsTooBig));
+}
+
 #[test]
 fn decode_untrusted_u256() {
     let tests = vec![
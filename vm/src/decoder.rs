@@ -44,6 +44,16 @@ pub fn decode(bytes: &[u8]) -> Result<Vec<Instruction>, DecoderError> {
             }
             opcode::POP => result.push(Instruction::Pop),
             opcode::CHKSIG => result.push(Instruction::ChkSig),
+            opcode::FAIL => result.push(Instruction::Fail),
+            opcode::DUP => result.push(Instruction::Dup),
+            opcode::SWAP => result.push(Instruction::Swap),
+            opcode::NOT => result.push(Instruction::Not),
+            opcode::EQ => result.push(Instruction::Eq),
+            opcode::JMP => result.push(Instruction::Jmp(*iter.next().ok_or(DecoderError::ScriptTooShort)?)),
+            opcode::JNZ => result.push(Instruction::Jnz(*iter.next().ok_or(DecoderError::ScriptTooShort)?)),
+            opcode::JZ => result.push(Instruction::Jz(*iter.next().ok_or(DecoderError::ScriptTooShort)?)),
+            opcode::CHKMULTISIG => result.push(Instruction::ChkMultiSig),
+            opcode::BLAKE256 => result.push(Instruction::Blake256),
             invalid_opcode => return Err(DecoderError::InvalidOpCode(invalid_opcode)),
         }
     }
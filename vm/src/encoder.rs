@@ -0,0 +1,52 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use instruction::Instruction;
+use opcode;
+
+/// Encodes the instructions into the bytes that `decode` accepts.
+/// It panics if a blob is longer than 255 bytes.
+pub fn encode(script: &[Instruction]) -> Vec<u8> {
+    let mut result = Vec::new();
+    for instruction in script {
+        match instruction {
+            Instruction::Nop => result.push(opcode::NOP),
+            Instruction::PushB(blob) => {
+                assert!(blob.len() <= 0xff, "A blob must not be longer than 255 bytes");
+                result.push(opcode::PUSHB);
+                result.push(blob.len() as u8);
+                result.extend_from_slice(blob);
+            }
+            Instruction::PushI(val) => {
+                result.push(opcode::PUSHI);
+                result.push(*val as u8);
+            }
+            Instruction::Pop => result.push(opcode::POP),
+            Instruction::ChkSig => result.push(opcode::CHKSIG),
+            Instruction::Fail => result.push(opcode::FAIL),
+            Instruction::Dup => result.push(opcode::DUP),
+            Instruction::Swap => result.push(opcode::SWAP),
+            Instruction::Not => result.push(opcode::NOT),
+            Instruction::Eq => result.push(opcode::EQ),
+            Instruction::Jmp(offset) => result.extend_from_slice(&[opcode::JMP, *offset]),
+            Instruction::Jnz(offset) => result.extend_from_slice(&[opcode::JNZ, *offset]),
+            Instruction::Jz(offset) => result.extend_from_slice(&[opcode::JZ, *offset]),
+            Instruction::ChkMultiSig => result.push(opcode::CHKMULTISIG),
+            Instruction::Blake256 => result.push(opcode::BLAKE256),
+        }
+    }
+    result
+}
@@ -14,21 +14,27 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use ccrypto::blake256;
 use ckeys::{verify_ecdsa, ECDSASignature};
 use ctypes::{H256, H520, Public};
 
 use instruction::Instruction;
 
 const DEFAULT_MAX_MEMORY: usize = 1024;
+const DEFAULT_MAX_STEPS: usize = 1000;
 
 pub struct Config {
     pub max_memory: usize,
+    /// The maximum number of the instructions executed by a pair of the unlock and the lock scripts.
+    /// Each signature verification of ChkMultiSig counts as an instruction too.
+    pub max_steps: usize,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             max_memory: DEFAULT_MAX_MEMORY,
+            max_steps: DEFAULT_MAX_STEPS,
         }
     }
 }
@@ -46,8 +52,12 @@ pub enum RuntimeError {
     StackUnderflow,
     TypeMismatch,
     InvalidResult,
+    OutOfSteps,
+    /// The unlock script has an instruction other than the pushes.
+    NotPushOnly,
 }
 
+#[derive(Clone, PartialEq)]
 enum Item {
     Integer(i8),
     Blob(Vec<u8>),
@@ -104,15 +114,97 @@ impl Stack {
         Err(RuntimeError::TypeMismatch)
     }
 
+    fn pop_integer(&mut self) -> Result<i8, RuntimeError> {
+        match self.pop()? {
+            Item::Integer(val) => Ok(val),
+            Item::Blob(..) => Err(RuntimeError::TypeMismatch),
+        }
+    }
+
+    // A negative count is a type mismatch
+    fn pop_count(&mut self) -> Result<usize, RuntimeError> {
+        match self.pop_integer()? {
+            val if val < 0 => Err(RuntimeError::TypeMismatch),
+            val => Ok(val as usize),
+        }
+    }
+
     fn len(&self) -> usize {
         self.stack.len()
     }
 }
 
-pub fn execute(script: &[Instruction], tx_hash: H256, config: Config) -> Result<ScriptResult, RuntimeError> {
+fn bool_to_item(val: bool) -> Item {
+    Item::Integer(val as i8)
+}
+
+fn is_valid_signature(pubkey: &[u8], signature: &[u8], tx_hash: &H256) -> bool {
+    let pubkey = Public::from_slice(pubkey);
+    let signature = ECDSASignature::from(H520::from(signature));
+    match verify_ecdsa(&pubkey, &signature, tx_hash) {
+        Ok(true) => true,
+        _ => false,
+    }
+}
+
+fn spend_step(steps: &mut usize) -> Result<(), RuntimeError> {
+    if *steps == 0 {
+        return Err(RuntimeError::OutOfSteps)
+    }
+    *steps -= 1;
+    Ok(())
+}
+
+fn is_push(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::PushB(..) | Instruction::PushI(..) => true,
+        _ => false,
+    }
+}
+
+/// Runs the unlock script and then the lock script on the same stack.
+/// The unlock script may only push the items, so it can't skip or tamper with the lock script.
+pub fn execute(
+    unlock: &[Instruction],
+    lock: &[Instruction],
+    tx_hash: H256,
+    config: Config,
+) -> Result<ScriptResult, RuntimeError> {
+    if !unlock.iter().all(is_push) {
+        return Err(RuntimeError::NotPushOnly)
+    }
+
+    let mut steps = config.max_steps;
     let mut stack = Stack::new(config);
+    for script in &[unlock, lock] {
+        if run(script, &tx_hash, &mut stack, &mut steps)? == Flow::Fail {
+            return Ok(ScriptResult::Fail)
+        }
+    }
+
+    match stack.pop() {
+        Ok(Item::Integer(result)) if stack.len() == 0 => {
+            if result == 0 {
+                Ok(ScriptResult::Fail)
+            } else {
+                Ok(ScriptResult::Unlocked)
+            }
+        }
+        _ => Err(RuntimeError::InvalidResult),
+    }
+}
+
+#[derive(PartialEq)]
+enum Flow {
+    Continue,
+    Fail,
+}
+
+fn run(script: &[Instruction], tx_hash: &H256, stack: &mut Stack, steps: &mut usize) -> Result<Flow, RuntimeError> {
     let mut pc = 0;
     while pc < script.len() {
+        spend_step(steps)?;
+
         match &script[pc] {
             Instruction::Nop => {}
             Instruction::PushB(blob) => stack.push(Item::Blob(blob.clone()))?,
@@ -121,26 +213,90 @@ pub fn execute(script: &[Instruction], tx_hash: H256, config: Config) -> Result<
                 stack.pop()?;
             }
             Instruction::ChkSig => {
-                let pubkey = Public::from_slice(stack.pop_blob(64)?.as_slice());
-                let signature = ECDSASignature::from(H520::from(stack.pop_blob(65)?.as_slice()));
-                let result = match verify_ecdsa(&pubkey, &signature, &tx_hash) {
-                    Ok(true) => 1,
-                    _ => 0,
-                };
-                stack.push(Item::Integer(result))?;
+                let pubkey = stack.pop_blob(64)?;
+                let signature = stack.pop_blob(65)?;
+                let result = is_valid_signature(&pubkey, &signature, tx_hash);
+                stack.push(bool_to_item(result))?;
             }
-        }
-        pc += 1;
-    }
+            Instruction::Fail => return Ok(Flow::Fail),
+            Instruction::Dup => {
+                let item = stack.pop()?;
+                stack.push(item.clone())?;
+                stack.push(item)?;
+            }
+            Instruction::Swap => {
+                let first = stack.pop()?;
+                let second = stack.pop()?;
+                stack.push(first)?;
+                stack.push(second)?;
+            }
+            Instruction::Not => {
+                let val = stack.pop_integer()?;
+                stack.push(bool_to_item(val == 0))?;
+            }
+            Instruction::Eq => {
+                let first = stack.pop()?;
+                let second = stack.pop()?;
+                stack.push(bool_to_item(first == second))?;
+            }
+            Instruction::Jmp(offset) => pc += *offset as usize,
+            Instruction::Jnz(offset) => {
+                if stack.pop_integer()? != 0 {
+                    pc += *offset as usize;
+                }
+            }
+            Instruction::Jz(offset) => {
+                if stack.pop_integer()? == 0 {
+                    pc += *offset as usize;
+                }
+            }
+            Instruction::ChkMultiSig => {
+                // The stack has the signatures, the required number of them, the public keys and the number of them.
+                let pubkey_count = stack.pop_count()?;
+                let mut pubkeys = Vec::with_capacity(pubkey_count);
+                for _ in 0..pubkey_count {
+                    pubkeys.push(stack.pop_blob(64)?);
+                }
+                pubkeys.reverse();
+                let signature_count = stack.pop_count()?;
+                if signature_count > pubkey_count {
+                    return Err(RuntimeError::TypeMismatch)
+                }
+                let mut signatures = Vec::with_capacity(signature_count);
+                for _ in 0..signature_count {
+                    signatures.push(stack.pop_blob(65)?);
+                }
+                signatures.reverse();
 
-    match stack.pop() {
-        Ok(Item::Integer(result)) if stack.len() == 0 => {
-            if result == 0 {
-                Ok(ScriptResult::Fail)
-            } else {
-                Ok(ScriptResult::Unlocked)
+                // The signatures must be in the same order as the public keys.
+                // Each verification costs a step, so the number of the keys can't blow up the cost of the script.
+                let mut pubkeys = pubkeys.iter();
+                let mut result = true;
+                for signature in &signatures {
+                    let mut found = false;
+                    for pubkey in &mut pubkeys {
+                        spend_step(steps)?;
+                        if is_valid_signature(pubkey, signature, tx_hash) {
+                            found = true;
+                            break
+                        }
+                    }
+                    if !found {
+                        result = false;
+                        break
+                    }
+                }
+                stack.push(bool_to_item(result))?;
+            }
+            Instruction::Blake256 => {
+                let blob = match stack.pop()? {
+                    Item::Blob(blob) => blob,
+                    Item::Integer(..) => return Err(RuntimeError::TypeMismatch),
+                };
+                stack.push(Item::Blob(blake256(&blob).to_vec()))?;
             }
         }
-        _ => Err(RuntimeError::InvalidResult),
+        pc += 1;
     }
+    Ok(Flow::Continue)
 }
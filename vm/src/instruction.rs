@@ -21,4 +21,15 @@ pub enum Instruction {
     PushI(i8),
     Pop,
     ChkSig,
+    Fail,
+    Dup,
+    Swap,
+    Not,
+    Eq,
+    /// Skips the next instructions of the number.
+    Jmp(u8),
+    Jnz(u8),
+    Jz(u8),
+    ChkMultiSig,
+    Blake256,
 }
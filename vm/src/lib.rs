@@ -22,12 +22,16 @@ extern crate codechain_types as ctypes;
 extern crate secp256k1;
 
 mod decoder;
+mod encoder;
 mod executor;
 mod instruction;
 mod opcode;
+pub mod template;
 
 #[cfg(test)]
 mod tests;
 
 pub use decoder::{decode, DecoderError};
+pub use encoder::encode;
 pub use executor::{execute, Config as VMConfig, RuntimeError, ScriptResult};
+pub use instruction::Instruction;
@@ -19,3 +19,13 @@ pub const PUSHB: u8 = 0x01;
 pub const PUSHI: u8 = 0x02;
 pub const POP: u8 = 0x03;
 pub const CHKSIG: u8 = 0x04;
+pub const FAIL: u8 = 0x05;
+pub const DUP: u8 = 0x06;
+pub const SWAP: u8 = 0x07;
+pub const NOT: u8 = 0x08;
+pub const EQ: u8 = 0x09;
+pub const JMP: u8 = 0x0a;
+pub const JNZ: u8 = 0x0b;
+pub const JZ: u8 = 0x0c;
+pub const CHKMULTISIG: u8 = 0x0d;
+pub const BLAKE256: u8 = 0x0e;
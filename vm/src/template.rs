@@ -0,0 +1,75 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+//! The lock and the unlock scripts of the standard forms.
+//! The hash of a lock script is what an asset output has as its `lock_script_hash`.
+
+use ccrypto::blake256;
+use ckeys::ECDSASignature;
+use ctypes::{H256, Public};
+
+use encoder::encode;
+use instruction::Instruction;
+
+/// Locks an asset to the public key.
+pub fn p2pk_lock(public: &Public) -> Vec<u8> {
+    encode(&[Instruction::PushB(public.to_vec()), Instruction::ChkSig])
+}
+
+pub fn p2pk_unlock(signature: &ECDSASignature) -> Vec<u8> {
+    encode(&[Instruction::PushB(signature.to_vec())])
+}
+
+/// Locks an asset to the blake256 hash of the public key.
+/// The public key is revealed only when the asset is spent.
+pub fn p2pkh_lock(public_hash: &H256) -> Vec<u8> {
+    encode(&[
+        Instruction::Dup,
+        Instruction::Blake256,
+        Instruction::PushB(public_hash.to_vec()),
+        Instruction::Eq,
+        Instruction::Jnz(1),
+        Instruction::Fail,
+        Instruction::ChkSig,
+    ])
+}
+
+pub fn p2pkh_unlock(signature: &ECDSASignature, public: &Public) -> Vec<u8> {
+    encode(&[Instruction::PushB(signature.to_vec()), Instruction::PushB(public.to_vec())])
+}
+
+/// The hash that `p2pkh_lock` takes.
+pub fn public_hash(public: &Public) -> H256 {
+    blake256(&public[..])
+}
+
+/// Locks an asset to `required` signatures of the public keys.
+pub fn multisig_lock(required: u8, publics: &[Public]) -> Vec<u8> {
+    assert!(required as usize <= publics.len(), "The required signatures must not be more than the public keys");
+    assert!(publics.len() <= 127, "The number of the public keys must fit in a PushI");
+    let mut script = vec![Instruction::PushI(required as i8)];
+    script.extend(publics.iter().map(|public| Instruction::PushB(public.to_vec())));
+    script.push(Instruction::PushI(publics.len() as i8));
+    script.push(Instruction::ChkMultiSig);
+    encode(&script)
+}
+
+/// The signatures must be in the same order as the public keys in the lock script.
+pub fn multisig_unlock(signatures: &[ECDSASignature]) -> Vec<u8> {
+    let script: Vec<_> = signatures.iter().map(|signature| Instruction::PushB(signature.to_vec())).collect();
+    encode(&script)
+}
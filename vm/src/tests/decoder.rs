@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use decoder::{decode, DecoderError};
+use encoder::encode;
 use instruction::Instruction;
 use opcode;
 
@@ -43,3 +44,34 @@ fn pushb() {
     );
     assert_eq!(decode([&[opcode::PUSHB, 4], &blobs[0][..]].concat().as_slice()), Err(DecoderError::ScriptTooShort));
 }
+
+#[test]
+fn jumps() {
+    assert_eq!(
+        decode(&[opcode::JMP, 1, opcode::JNZ, 2, opcode::JZ, 3]),
+        Ok(vec![Instruction::Jmp(1), Instruction::Jnz(2), Instruction::Jz(3)])
+    );
+    assert_eq!(decode(&[opcode::JMP]), Err(DecoderError::ScriptTooShort));
+}
+
+#[test]
+fn decode_encoded_script() {
+    let script = vec![
+        Instruction::Nop,
+        Instruction::PushB(vec![0xed, 0x11]),
+        Instruction::PushI(-1),
+        Instruction::Pop,
+        Instruction::ChkSig,
+        Instruction::Fail,
+        Instruction::Dup,
+        Instruction::Swap,
+        Instruction::Not,
+        Instruction::Eq,
+        Instruction::Jmp(1),
+        Instruction::Jnz(2),
+        Instruction::Jz(3),
+        Instruction::ChkMultiSig,
+        Instruction::Blake256,
+    ];
+    assert_eq!(decode(&encode(&script)), Ok(script));
+}
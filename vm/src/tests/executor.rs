@@ -25,26 +25,30 @@ use instruction::Instruction;
 
 #[test]
 fn simple_success() {
-    assert_eq!(execute(&[Instruction::PushI(1)], H256::default(), Config::default()), Ok(ScriptResult::Unlocked));
+    assert_eq!(execute(&[], &[Instruction::PushI(1)], H256::default(), Config::default()), Ok(ScriptResult::Unlocked));
 }
 
 #[test]
 fn simple_failure() {
-    assert_eq!(execute(&[Instruction::PushI(0)], H256::default(), Config::default()), Ok(ScriptResult::Fail));
+    assert_eq!(execute(&[], &[Instruction::PushI(0)], H256::default(), Config::default()), Ok(ScriptResult::Fail));
 }
 
 #[test]
 fn underflow() {
-    assert_eq!(execute(&[Instruction::Pop], H256::default(), Config::default()), Err(RuntimeError::StackUnderflow));
+    assert_eq!(
+        execute(&[], &[Instruction::Pop], H256::default(), Config::default()),
+        Err(RuntimeError::StackUnderflow)
+    );
 }
 
 #[test]
 fn out_of_memory() {
     let config = Config {
         max_memory: 2,
+        ..Config::default()
     };
     assert_eq!(
-        execute(&[Instruction::PushI(0), Instruction::PushI(1), Instruction::PushI(2)], H256::default(), config),
+        execute(&[], &[Instruction::PushI(0), Instruction::PushI(1), Instruction::PushI(2)], H256::default(), config),
         Err(RuntimeError::OutOfMemory)
     );
 }
@@ -59,7 +63,7 @@ fn valid_pay_to_public_key() {
     let lock_script = vec![Instruction::PushB(pubkey), Instruction::ChkSig];
 
     assert_eq!(
-        execute(&[&unlock_script[..], &lock_script[..]].concat(), message, Config::default()),
+        execute(&unlock_script, &lock_script, message, Config::default()),
         Ok(ScriptResult::Unlocked)
     );
 }
@@ -76,7 +80,107 @@ fn invalid_pay_to_public_key() {
     let unlock_script = vec![Instruction::PushB(invalid_signature)];
 
     assert_eq!(
-        execute(&[&unlock_script[..], &lock_script[..]].concat(), message, Config::default()),
+        execute(&unlock_script, &lock_script, message, Config::default()),
+        Ok(ScriptResult::Fail)
+    );
+}
+
+#[test]
+fn out_of_steps() {
+    let config = Config {
+        max_steps: 2,
+        ..Config::default()
+    };
+    assert_eq!(
+        execute(&[Instruction::PushI(1)], &[Instruction::Pop, Instruction::PushI(1)], H256::default(), config),
+        Err(RuntimeError::OutOfSteps)
+    );
+}
+
+#[test]
+fn fail_stops_the_script() {
+    assert_eq!(
+        execute(&[], &[Instruction::Fail, Instruction::PushI(1)], H256::default(), Config::default()),
+        Ok(ScriptResult::Fail)
+    );
+}
+
+#[test]
+fn unlock_script_only_pushes() {
+    let unlock_script = vec![Instruction::PushI(1), Instruction::Jmp(10)];
+    let lock_script = vec![Instruction::Fail];
+    assert_eq!(
+        execute(&unlock_script, &lock_script, H256::default(), Config::default()),
+        Err(RuntimeError::NotPushOnly)
+    );
+}
+
+#[test]
+fn multisig_spends_a_step_per_verification() {
+    let keypair = KeyPair::from_private(Private::from(SecretKey::from(ONE_KEY))).unwrap();
+    let other_keypair = KeyPair::from_private(Private::from(SecretKey::from(MINUS_ONE_KEY))).unwrap();
+    let message = blake256("asdf");
+    let signature = H520::from(sign_ecdsa(keypair.private(), &message).unwrap()).to_vec();
+    let unlock_script = vec![Instruction::PushB(signature)];
+    // The signature is checked against the other key first and then against its own key.
+    let lock_script = vec![
+        Instruction::PushI(1),
+        Instruction::PushB(<&[u8]>::from(other_keypair.public()).to_vec()),
+        Instruction::PushB(<&[u8]>::from(keypair.public()).to_vec()),
+        Instruction::PushI(2),
+        Instruction::ChkMultiSig,
+    ];
+    let config = |max_steps| Config {
+        max_steps,
+        ..Config::default()
+    };
+
+    // 6 instructions and 2 verifications
+    assert_eq!(execute(&unlock_script, &lock_script, message, config(8)), Ok(ScriptResult::Unlocked));
+    assert_eq!(execute(&unlock_script, &lock_script, message, config(7)), Err(RuntimeError::OutOfSteps));
+}
+
+#[test]
+fn conditional_jumps() {
+    let script = |condition: i8, jump: Instruction| {
+        vec![Instruction::PushI(condition), jump, Instruction::Fail, Instruction::PushI(1)]
+    };
+    let result = |script: Vec<Instruction>| execute(&[], &script, H256::default(), Config::default());
+
+    assert_eq!(result(script(1, Instruction::Jnz(1))), Ok(ScriptResult::Unlocked));
+    assert_eq!(result(script(0, Instruction::Jnz(1))), Ok(ScriptResult::Fail));
+    assert_eq!(result(script(0, Instruction::Jz(1))), Ok(ScriptResult::Unlocked));
+    assert_eq!(result(script(1, Instruction::Jz(1))), Ok(ScriptResult::Fail));
+}
+
+#[test]
+fn eq_dup_swap_and_not() {
+    let result = |script: &[Instruction]| execute(&[], script, H256::default(), Config::default());
+
+    assert_eq!(
+        result(&[Instruction::PushB(vec![1, 2]), Instruction::Dup, Instruction::Eq]),
+        Ok(ScriptResult::Unlocked)
+    );
+    assert_eq!(
+        result(&[Instruction::PushB(vec![1]), Instruction::PushI(1), Instruction::Eq]),
         Ok(ScriptResult::Fail)
     );
+    assert_eq!(
+        result(&[Instruction::PushB(vec![]), Instruction::PushI(1), Instruction::Swap, Instruction::Pop]),
+        Ok(ScriptResult::Unlocked)
+    );
+    assert_eq!(result(&[Instruction::PushI(0), Instruction::Not]), Ok(ScriptResult::Unlocked));
+    assert_eq!(result(&[Instruction::PushB(vec![]), Instruction::Not]), Err(RuntimeError::TypeMismatch));
+}
+
+#[test]
+fn blake256_of_blob() {
+    let blob = vec![0x01, 0x02, 0x03];
+    let script = vec![
+        Instruction::PushB(blob.clone()),
+        Instruction::Blake256,
+        Instruction::PushB(blake256(&blob).to_vec()),
+        Instruction::Eq,
+    ];
+    assert_eq!(execute(&[], &script, H256::default(), Config::default()), Ok(ScriptResult::Unlocked));
 }
@@ -16,3 +16,4 @@
 
 mod decoder;
 mod executor;
+mod template;
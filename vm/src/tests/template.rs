@@ -0,0 +1,75 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use ccrypto::blake256;
+use ckeys::{sign_ecdsa, Generator, KeyPair, Random};
+use ctypes::H256;
+
+use decoder::decode;
+use executor::{execute, Config, ScriptResult};
+use template::*;
+
+fn run(unlock_script: &[u8], lock_script: &[u8], message: H256) -> ScriptResult {
+    execute(&decode(unlock_script).unwrap(), &decode(lock_script).unwrap(), message, Config::default()).unwrap()
+}
+
+#[test]
+fn pay_to_public_key() {
+    let keypair = Random.generate().unwrap();
+    let message = blake256("asdf");
+    let lock_script = p2pk_lock(keypair.public());
+
+    let signature = sign_ecdsa(keypair.private(), &message).unwrap();
+    assert_eq!(ScriptResult::Unlocked, run(&p2pk_unlock(&signature), &lock_script, message));
+
+    let invalid_signature = sign_ecdsa(Random.generate().unwrap().private(), &message).unwrap();
+    assert_eq!(ScriptResult::Fail, run(&p2pk_unlock(&invalid_signature), &lock_script, message));
+}
+
+#[test]
+fn pay_to_public_key_hash() {
+    let keypair = Random.generate().unwrap();
+    let message = blake256("asdf");
+    let lock_script = p2pkh_lock(&public_hash(keypair.public()));
+    let signature = sign_ecdsa(keypair.private(), &message).unwrap();
+    assert_eq!(ScriptResult::Unlocked, run(&p2pkh_unlock(&signature, keypair.public()), &lock_script, message));
+
+    // The signature of the other key pair doesn't match the hash
+    let other = Random.generate().unwrap();
+    let other_signature = sign_ecdsa(other.private(), &message).unwrap();
+    assert_eq!(ScriptResult::Fail, run(&p2pkh_unlock(&other_signature, other.public()), &lock_script, message));
+}
+
+#[test]
+fn multisig() {
+    let keypairs: Vec<KeyPair> = (0..3).map(|_| Random.generate().unwrap()).collect();
+    let publics: Vec<_> = keypairs.iter().map(|keypair| *keypair.public()).collect();
+    let message = blake256("asdf");
+    let signatures: Vec<_> = keypairs.iter().map(|keypair| sign_ecdsa(keypair.private(), &message).unwrap()).collect();
+    let lock_script = multisig_lock(2, &publics);
+
+    let unlock = |indices: &[usize]| {
+        let signatures: Vec<_> = indices.iter().map(|i| signatures[*i].clone()).collect();
+        multisig_unlock(&signatures)
+    };
+    assert_eq!(ScriptResult::Unlocked, run(&unlock(&[0, 1]), &lock_script, message));
+    assert_eq!(ScriptResult::Unlocked, run(&unlock(&[0, 2]), &lock_script, message));
+    assert_eq!(ScriptResult::Unlocked, run(&unlock(&[1, 2]), &lock_script, message));
+    // The signatures must be in the order of the public keys
+    assert_eq!(ScriptResult::Fail, run(&unlock(&[1, 0]), &lock_script, message));
+    assert_eq!(ScriptResult::Fail, run(&unlock(&[0, 0]), &lock_script, message));
+}